@@ -0,0 +1,20 @@
+//! Embeds the digest `verify-install` checks the binary against: the
+//! SHA-256 in `GUARDIAN_EXPECTED_DIGEST` at build time, or zeros for none.
+//! It's always 64 characters, so that a binary built with one is laid out
+//! as one built without it, and differs only in those bytes.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=GUARDIAN_EXPECTED_DIGEST");
+    let digest = std::env::var("GUARDIAN_EXPECTED_DIGEST").unwrap_or_default();
+    let digest = digest.trim();
+    let digest = digest.strip_prefix("sha256:").unwrap_or(digest);
+    let digest = if digest.is_empty() {
+        "0".repeat(64)
+    } else if digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        digest.to_ascii_lowercase()
+    } else {
+        panic!("GUARDIAN_EXPECTED_DIGEST must be a SHA-256 digest, 64 hex digits");
+    };
+    println!("cargo:rustc-env=GUARDIAN_EMBEDDED_DIGEST={}", digest);
+}
@@ -0,0 +1,362 @@
+//! `accept`: take the files' current state into the snapshot, for changes
+//! that were meant.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+
+use crate::{
+    Drift, FileHash, GuardianError, Hashes, Snapshot, base_of, chunks_of, config, content,
+    hash_file_as, history, identity, invoker, is_pattern, matches_pattern, redact, utils,
+};
+
+/// A change `accept` makes to the snapshot: the entry for `path` replaced
+/// by what the file holds now, or removed for a file that is gone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Acceptance {
+    pub path: String,
+    pub old: Option<FileHash>,
+    pub new: Option<FileHash>,
+}
+
+impl std::fmt::Display for Acceptance {
+    /// What accepting does, as `--dry-run` prints it after "Would".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.new {
+            Some(_) => write!(f, "accept {} into the snapshot", self.path),
+            None => write!(f, "accept the deletion of {}", self.path),
+        }
+    }
+}
+
+/// Why `path` can't be taken into `snapshot`, if it can't: it leads out of
+/// its directory through `..`, it isn't a file of a directory or pattern
+/// the snapshot was taken of, or a symlinked directory on the way to it
+/// leads out of theirs. So whatever a reviewed report or the command line
+/// names, and whatever its entries say, nothing from outside the tree is
+/// hashed into the snapshot.
+fn escapes(snapshot: &Snapshot, path: &str) -> Option<String> {
+    let file = Path::new(path);
+    if file.components().any(|c| c == Component::ParentDir) {
+        return Some("the path leads out of its directory".to_string());
+    }
+    let roots = &snapshot.metadata.directories;
+    // Snapshots saved as a bare list don't say what they were taken of.
+    if roots.is_empty() {
+        return None;
+    }
+    let Some(root) = roots.iter().find(|root| match is_pattern(root) {
+        true => matches_pattern(root, path),
+        false => file.parent() == Some(Path::new(root.as_str())),
+    }) else {
+        return Some(format!(
+            "it isn't in {}, which the snapshot was taken of",
+            roots.join(", ")
+        ));
+    };
+    let dir = |path: &Path| match path.as_os_str().is_empty() {
+        true => Path::new(".").to_path_buf(),
+        false => path.to_path_buf(),
+    };
+    // The deletion of a directory that's gone reads nothing.
+    let (Ok(resolved), Ok(base)) = (
+        std::fs::canonicalize(dir(file.parent()?)),
+        std::fs::canonicalize(dir(&base_of(root))),
+    ) else {
+        return None;
+    };
+    let inside = match is_pattern(root) {
+        true => resolved.starts_with(&base),
+        false => resolved == base,
+    };
+    (!inside).then(|| {
+        let resolved = resolved.join(file.file_name().unwrap_or_default());
+        format!(
+            "it resolves to {}, outside {}",
+            utils::path_text(&resolved),
+            root
+        )
+    })
+}
+
+/// The changes accepting `paths` would make to `snapshot`. Files that
+/// already match it need none, and are listed apart. A path that leads
+/// out of the directories the snapshot was taken of is refused.
+pub fn plan(
+    snapshot: &Snapshot,
+    paths: &[String],
+) -> Result<(Vec<Acceptance>, Vec<String>), GuardianError> {
+    let mut accepted = Vec::new();
+    let mut matching = Vec::new();
+    for path in paths {
+        if let Some(why) = escapes(snapshot, path) {
+            return Err(GuardianError::Config(format!(
+                "refusing to accept {}: {}",
+                redact::shown(path),
+                why
+            )));
+        }
+        let old = snapshot.get(path).cloned();
+        // Hashed the way the entry was, or as a snapshot would hash it.
+        let sampled = match &old {
+            Some(old) => old.sampled,
+            None => std::fs::metadata(path)
+                .ok()
+                .and_then(|m| config::settings().sample_span(m.len())),
+        };
+        let new = match hash_file_as(Path::new(path), sampled)
+            .and_then(|hash| Ok((hash, chunks_of(Path::new(path), sampled)?)))
+        {
+            Ok((hash, chunks)) => Some(FileHash {
+                sampled,
+                chunks,
+                ..FileHash::new(path.clone(), hash, Path::new(path))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(GuardianError::io(path)(e)),
+        };
+        if old == new {
+            matching.push(path.clone());
+        } else {
+            accepted.push(Acceptance {
+                path: path.clone(),
+                old,
+                new,
+            });
+        }
+    }
+    Ok((accepted, matching))
+}
+
+/// Update the snapshot entries for `paths` to match the files on disk: the
+/// current hash for files that exist, removal for files that don't, with
+/// `reason` recorded in the history. With `dry_run`, print what would
+/// change and change nothing. Returns the number of files accepted, or that
+/// would be.
+pub fn accept(
+    paths: &[String],
+    tags: &[(String, String)],
+    reason: Option<&str>,
+    dry_run: bool,
+) -> Result<usize, GuardianError> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let (accepted, matching) = plan(&snapshot, paths)?;
+    for path in &matching {
+        println!("{} already matches the snapshot.", path);
+    }
+    apply(snapshot, &accepted, tags, dry_run, reason)
+}
+
+/// Make the changes `accepted` to `snapshot` and save it, recording each
+/// in the history with `reason`, or print them with `dry_run`.
+fn apply(
+    mut snapshot: Snapshot,
+    accepted: &[Acceptance],
+    tags: &[(String, String)],
+    dry_run: bool,
+    reason: Option<&str>,
+) -> Result<usize, GuardianError> {
+    if dry_run {
+        for acceptance in accepted {
+            println!("Would {}", acceptance);
+        }
+        for (key, value) in tags {
+            println!("Would tag the snapshot {}={}", key, value);
+        }
+        return Ok(accepted.len());
+    }
+    for acceptance in accepted {
+        snapshot.entries.retain(|f| f.path != acceptance.path);
+        snapshot.entries.extend(acceptance.new.clone());
+    }
+    snapshot.metadata.tags.extend(tags.iter().cloned());
+    snapshot.metadata.invoker = Some(invoker::current().clone());
+    snapshot.metadata.instance_id = Some(identity::current().to_string());
+    snapshot.save(&config::settings().snapshot_file)?;
+    let taken: Vec<FileHash> = accepted.iter().filter_map(|a| a.new.clone()).collect();
+    content::keep(&taken);
+
+    for Acceptance { path, old, new } in accepted {
+        match new {
+            Some(_) => println!("Accepted {}", path),
+            None => println!("Accepted deletion of {}", path),
+        }
+        log::info!(event = "accept", path = path.as_str(); "Accepted {} into the snapshot", path);
+        let dir = match history::directory_of(path) {
+            Ok(Some(dir)) => dir,
+            _ => Path::new(path)
+                .parent()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        let hash = |entry: &Option<FileHash>| entry.as_ref().map(|f| f.hash.clone());
+        let summary = old
+            .as_ref()
+            .zip(new.as_ref())
+            .and_then(|(old, new)| content::summary(old, new));
+        if let Some(summary) = &summary {
+            println!("  {}", summary);
+        }
+        let recorded = history::record_accept(&dir, path, hash(old), hash(new), reason, summary);
+        if let Err(e) = recorded {
+            eprintln!("Warning: could not record drift history: {}", e);
+        }
+    }
+    for (key, value) in tags {
+        println!("Tagged the snapshot {}={}", key, value);
+    }
+    Ok(accepted.len())
+}
+
+/// One drift entry of a reviewed report, with the reviewer's verdict.
+#[derive(Deserialize)]
+struct ReviewedDrift {
+    #[serde(flatten)]
+    drift: Drift,
+    #[serde(default)]
+    approved: Option<bool>,
+}
+
+/// A drift report as compare hands it to hooks and the collector, read
+/// back after review.
+#[derive(Deserialize)]
+struct ReviewedReport {
+    drifts: Vec<ReviewedDrift>,
+    #[serde(default)]
+    hashes: BTreeMap<String, Hashes>,
+}
+
+/// What came of accepting a reviewed report.
+#[derive(Debug, Default, PartialEq)]
+pub struct Review {
+    /// The files accepted, or that would be.
+    pub applied: usize,
+    /// Approved files that already match the snapshot.
+    pub matching: usize,
+    /// The files the reviewer didn't approve.
+    pub skipped: Vec<String>,
+    /// Approved files refused, with why: most often, they changed again
+    /// since the report was made.
+    pub stale: Vec<(String, String)>,
+}
+
+impl std::fmt::Display for Review {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} applied, {} skipped, {} stale",
+            self.applied,
+            self.skipped.len(),
+            self.stale.len()
+        )?;
+        if self.matching > 0 {
+            write!(f, ", {} already matching", self.matching)?;
+        }
+        Ok(())
+    }
+}
+
+/// Accept what a reviewer approved in the JSON drift report at `report`:
+/// every entry marked `"approved": true`, or every entry when none is
+/// marked either way. An approved file that no longer hashes as the report
+/// recorded changed after the review, and is refused as stale. The history
+/// records each as approved in the report, and why when `reason` says.
+pub fn accept_reviewed(
+    report: &Path,
+    tags: &[(String, String)],
+    reason: Option<&str>,
+    dry_run: bool,
+) -> Result<Review, GuardianError> {
+    let data = std::fs::read_to_string(report).map_err(GuardianError::io(report))?;
+    let reviewed: ReviewedReport = serde_json::from_str(&data).map_err(|e| {
+        GuardianError::Config(format!("{} is not a drift report: {}", report.display(), e))
+    })?;
+    let marked = reviewed.drifts.iter().any(|d| d.approved.is_some());
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let mut review = Review::default();
+    let mut accepted: Vec<Acceptance> = Vec::new();
+    for ReviewedDrift { drift, approved } in &reviewed.drifts {
+        let path = &drift.path;
+        if accepted.iter().any(|a| a.path == *path) {
+            continue;
+        }
+        if marked && *approved != Some(true) {
+            review.skipped.push(path.clone());
+            continue;
+        }
+        if path.contains("[redacted:") {
+            review.stale.push((
+                path.clone(),
+                "redacted in the report; accept it by its path".to_string(),
+            ));
+            continue;
+        }
+        if let Some(why) = escapes(&snapshot, path) {
+            review.stale.push((path.clone(), why));
+            continue;
+        }
+        let Some(recorded) = reviewed.hashes.get(path) else {
+            review.stale.push((
+                path.clone(),
+                "the report records no hash for it".to_string(),
+            ));
+            continue;
+        };
+        let (planned, matching) = match plan(&snapshot, std::slice::from_ref(path)) {
+            Ok(planned) => planned,
+            Err(e) => {
+                review.stale.push((path.clone(), e.to_string()));
+                continue;
+            }
+        };
+        let now = planned
+            .first()
+            .map_or_else(|| snapshot.get(path).cloned(), |a| a.new.clone());
+        if now.as_ref().map(|f| &f.hash) != recorded.new.as_ref() {
+            review
+                .stale
+                .push((path.clone(), "changed since the review".to_string()));
+            continue;
+        }
+        if matching.is_empty() {
+            accepted.extend(planned);
+        } else {
+            println!("{} already matches the snapshot.", path);
+            review.matching += 1;
+        }
+    }
+    for path in &review.skipped {
+        println!("Skipped {}: not approved", path);
+    }
+    for (path, why) in &review.stale {
+        eprintln!("Warning: refusing {}: {}", redact::shown(path), why);
+    }
+    let mut approved = format!("approved in {}", utils::path_text(report));
+    if let Some(reason) = reason {
+        approved = format!("{}; {}", approved, reason);
+    }
+    review.applied = apply(snapshot, &accepted, tags, dry_run, Some(&approved))?;
+    if !dry_run {
+        let mut summary = review.to_string();
+        if !review.stale.is_empty() {
+            let stale: Vec<String> = review
+                .stale
+                .iter()
+                .map(|(path, why)| format!("{}: {}", redact::path(path), why))
+                .collect();
+            summary = format!("{} ({})", summary, stale.join("; "));
+        }
+        if let Err(e) = history::record_review(&utils::path_text(report), &summary) {
+            eprintln!("Warning: could not record drift history: {}", e);
+        }
+        log::info!(
+            event = "review_applied",
+            report = utils::path_text(report).as_str(),
+            applied = review.applied,
+            skipped = review.skipped.len(),
+            stale = review.stale.len();
+            "Applied reviewed report {}: {}", report.display(), summary
+        );
+    }
+    Ok(review)
+}
@@ -0,0 +1,739 @@
+//! Drift alerting: building alerts and gating them through a cooldown.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::annotation::Annotation;
+use crate::config;
+use crate::desktop;
+use crate::digest;
+use crate::email::{self, EmailConfig};
+use crate::hooks::DriftHook;
+use crate::http;
+use crate::pagerduty::{self, PagerDutyConfig};
+use crate::redact;
+use crate::slack::{self, SlackConfig};
+use crate::teams::{self, TeamsConfig};
+use crate::telegram::{self, TelegramConfig};
+use crate::webhook::{self, WebhookConfig};
+use crate::{Drift, DriftKind, DriftReport};
+
+/// How urgent an alert is, taken from its most serious drift.
+#[derive(
+    Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+/// A drift's severity: the first matching config rule's, otherwise its
+/// kind's.
+pub fn severity_of(drift: &Drift) -> Severity {
+    config::settings().severity_of(drift)
+}
+
+/// The score of a comparison that found `drifts`: the sum of the weight of
+/// each drift's severity, one number an alert route can key off, such as
+/// paging at 100 and opening a ticket below.
+///
+/// ```
+/// use config_guardian::{Drift, DriftKind, alert::score, config::Settings};
+///
+/// let drift = |kind, path: &str| Drift { kind, path: path.into(), reason: None };
+/// let drifts = [drift(DriftKind::Deleted, "a"), drift(DriftKind::Changed, "b")];
+/// assert_eq!(score(&drifts, &Settings::default()), 55);
+/// ```
+pub fn score(drifts: &[Drift], settings: &config::Settings) -> u64 {
+    drifts
+        .iter()
+        .map(|drift| settings.score.of(settings.severity_of(drift)))
+        .sum()
+}
+
+impl Severity {
+    pub fn of(kind: DriftKind) -> Self {
+        match kind {
+            DriftKind::New => Severity::Info,
+            DriftKind::Changed
+            | DriftKind::Populated
+            | DriftKind::Unreadable
+            | DriftKind::Locked
+            | DriftKind::AttributesChanged
+            | DriftKind::AclChanged
+            | DriftKind::InodeReplaced
+            | DriftKind::LinkCountChanged
+            | DriftKind::PermissionsChanged
+            | DriftKind::TypeChanged
+            | DriftKind::SymlinkRetargeted => Severity::Warning,
+            DriftKind::Truncated
+            | DriftKind::NewExecutable
+            | DriftKind::NewSetuid
+            | DriftKind::Deleted
+            | DriftKind::MountChanged
+            | DriftKind::SymlinkEscaped
+            | DriftKind::BaselineModified => Severity::Critical,
+        }
+    }
+
+    /// Hex colour shown by chat channels that colour-code messages.
+    pub fn color(self) -> &'static str {
+        match self {
+            Severity::Info => "#2eb67d",
+            Severity::Warning => "#ecb22e",
+            Severity::Critical => "#e01e5a",
+        }
+    }
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A message to deliver through the alert channels.
+pub struct Alert {
+    pub dir: String,
+    pub summary: String,
+    pub drifts: Vec<Drift>,
+    /// The baseline snapshot's tags, for channels whose payloads carry them.
+    pub tags: BTreeMap<String, String>,
+    /// The baseline's age in days, for the same channels.
+    pub baseline_age_days: Option<u64>,
+    /// The annotations of the files that drifted, by path, for routing to
+    /// their owners.
+    pub annotations: BTreeMap<String, Annotation>,
+    /// The severity of an alert about something other than drift, such as
+    /// an event flood; otherwise it's that of the drift.
+    pub severity: Option<Severity>,
+    /// The report the drift was found in, which the `--on-drift` hook is
+    /// given whole.
+    pub report: Option<DriftReport>,
+}
+
+impl Alert {
+    pub fn drift(dir: &str, drifts: &[Drift]) -> Self {
+        Alert {
+            dir: dir.to_string(),
+            summary: format!("{} drifted files", drifts.len()),
+            drifts: redact::drifts(drifts),
+            tags: BTreeMap::new(),
+            baseline_age_days: None,
+            annotations: BTreeMap::new(),
+            severity: None,
+            report: None,
+        }
+    }
+
+    pub fn tagged(mut self, tags: &BTreeMap<String, String>) -> Self {
+        self.tags = tags.clone();
+        self
+    }
+
+    pub fn aged(mut self, baseline_age_days: Option<u64>) -> Self {
+        self.baseline_age_days = baseline_age_days;
+        self
+    }
+
+    pub fn annotated(mut self, annotations: &BTreeMap<String, Annotation>) -> Self {
+        self.annotations = redact::annotations(annotations);
+        self
+    }
+
+    pub fn reported(mut self, report: &DriftReport) -> Self {
+        self.report = Some(report.clone());
+        self
+    }
+
+    /// Summary-only alerts (no entries) are informational, unless they say
+    /// otherwise.
+    pub fn severity(&self) -> Severity {
+        self.drifts
+            .iter()
+            .map(severity_of)
+            .max()
+            .or(self.severity)
+            .unwrap_or(Severity::Info)
+    }
+
+    /// The [`score`] of the alert's drift.
+    pub fn score(&self) -> u64 {
+        score(&self.drifts, config::settings())
+    }
+
+    pub fn count(&self, kind: DriftKind) -> usize {
+        self.drifts.iter().filter(|d| d.kind == kind).count()
+    }
+
+    /// The entries a channel listing by `listing` shows, the most severe
+    /// first and by kind within a severity, and how many it leaves out.
+    pub fn listed(&self, listing: &Listing) -> (Vec<&Drift>, usize) {
+        let mut listed: Vec<&Drift> = self.drifts.iter().collect();
+        listed.sort_by_key(|drift| {
+            let kind = DriftKind::ALL.iter().position(|k| *k == drift.kind);
+            (std::cmp::Reverse(severity_of(drift)), kind)
+        });
+        let omitted = listed.len().saturating_sub(listing.max_entries);
+        listed.truncate(listing.max_entries);
+        (listed, omitted)
+    }
+}
+
+/// Entries an alert lists by default; see [`Listing`].
+pub const DEFAULT_MAX_ENTRIES: usize = 50;
+
+/// How many of an alert's entries one channel lists, and where the full
+/// report can be found, as channels whose payloads a long list would
+/// overflow take them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listing {
+    pub max_entries: usize,
+    /// A link or path to the full report, such as a CI artifact.
+    pub report: Option<String>,
+}
+
+impl Default for Listing {
+    fn default() -> Self {
+        Listing {
+            max_entries: DEFAULT_MAX_ENTRIES,
+            report: None,
+        }
+    }
+}
+
+impl Listing {
+    /// The line under a list that left `omitted` entries out, saying where
+    /// they are: in a report `attached` to the message, or the linked one.
+    pub fn trailer(&self, omitted: usize, attached: bool) -> String {
+        if attached {
+            format!("…and {} more (see the attached report)", omitted)
+        } else if let Some(report) = &self.report {
+            format!("…and {} more (see {})", omitted, report)
+        } else {
+            format!("…and {} more", omitted)
+        }
+    }
+}
+
+/// The [`Listing`] of each channel: `alerts.max_entries`, or the channel's
+/// own under `[alerts.max_entries_per_channel]`.
+#[derive(Clone, Debug, Default)]
+pub struct AlertLimits {
+    pub max_entries: Option<usize>,
+    pub per_channel: BTreeMap<String, usize>,
+    pub report: Option<String>,
+}
+
+/// The channels `[alerts.max_entries_per_channel]` can name.
+pub const LISTING_CHANNELS: [&str; 5] = ["webhook", "slack", "teams", "email", "telegram"];
+
+impl AlertLimits {
+    pub fn listing(&self, channel: &str) -> Listing {
+        Listing {
+            max_entries: self
+                .per_channel
+                .get(channel)
+                .copied()
+                .or(self.max_entries)
+                .unwrap_or(DEFAULT_MAX_ENTRIES),
+            report: self.report.clone(),
+        }
+    }
+}
+
+/// The alert channels configured for this run. The log always receives
+/// alerts; everything else is opt-in.
+#[derive(Clone, Default)]
+pub struct AlertChannels {
+    pub webhook: Option<WebhookConfig>,
+    pub slack: Option<SlackConfig>,
+    pub teams: Option<TeamsConfig>,
+    pub email: Option<EmailConfig>,
+    pub telegram: Option<TelegramConfig>,
+    pub pagerduty: Option<PagerDutyConfig>,
+    /// Show a desktop notification (monitor mode only).
+    pub desktop: bool,
+    /// Run the `--on-drift` command.
+    pub on_drift: Option<DriftHook>,
+    /// How many entries each channel lists.
+    pub limits: AlertLimits,
+}
+
+impl AlertChannels {
+    pub fn is_empty(&self) -> bool {
+        self.webhook.is_none()
+            && self.slack.is_none()
+            && self.teams.is_none()
+            && self.email.is_none()
+            && self.telegram.is_none()
+            && self.pagerduty.is_none()
+            && !self.desktop
+            && self.on_drift.is_none()
+    }
+
+    /// Each configured channel's name and the `host:port` it delivers to,
+    /// or `None` when its URL can't be parsed.
+    pub fn endpoints(&self) -> Vec<(&'static str, Option<String>)> {
+        let mut endpoints = Vec::new();
+        if let Some(webhook) = &self.webhook {
+            endpoints.push(("webhook", http::address(&webhook.url)));
+        }
+        if let Some(slack) = &self.slack {
+            endpoints.push(("slack", http::address(&slack.url)));
+        }
+        if let Some(teams) = &self.teams {
+            endpoints.push(("teams", http::address(&teams.url)));
+        }
+        if let Some(email) = &self.email {
+            let port = email.port.unwrap_or(email.tls.default_port());
+            endpoints.push(("email", Some(format!("{}:{}", email.host, port))));
+        }
+        if self.telegram.is_some() {
+            endpoints.push(("telegram", http::address(telegram::API_ORIGIN)));
+        }
+        if self.pagerduty.is_some() {
+            endpoints.push(("pagerduty", http::address(pagerduty::EVENTS_URL)));
+        }
+        endpoints
+    }
+}
+
+/// A channel alerts are delivered through.
+pub trait Notifier {
+    /// The channel's name, as warnings about it and
+    /// `[alerts.max_entries_per_channel]` give it.
+    fn name(&self) -> &'static str;
+
+    /// Deliver `alert`, listing as many of its entries as `listing` says.
+    fn notify(&self, alert: &Alert, listing: &Listing) -> Result<(), String>;
+
+    /// Tell the channel that `dir` no longer drifts, for channels that track
+    /// incidents.
+    fn resolve(&self, _dir: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The log, which receives every alert whatever else is configured.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn notify(&self, alert: &Alert, _listing: &Listing) -> Result<(), String> {
+        // The drifted paths themselves are in the preceding `drift` records.
+        log::warn!(
+            event = "alert",
+            dir = alert.dir.as_str(),
+            severity = alert.severity().as_str(),
+            score = alert.score(),
+            changed = alert.count(DriftKind::Changed),
+            truncated = alert.count(DriftKind::Truncated),
+            populated = alert.count(DriftKind::Populated),
+            new = alert.count(DriftKind::New),
+            new_executable = alert.count(DriftKind::NewExecutable),
+            new_setuid = alert.count(DriftKind::NewSetuid),
+            deleted = alert.count(DriftKind::Deleted),
+            unreadable = alert.count(DriftKind::Unreadable),
+            locked = alert.count(DriftKind::Locked),
+            mount_changed = alert.count(DriftKind::MountChanged),
+            attributes_changed = alert.count(DriftKind::AttributesChanged),
+            acl_changed = alert.count(DriftKind::AclChanged),
+            inode_replaced = alert.count(DriftKind::InodeReplaced),
+            link_count_changed = alert.count(DriftKind::LinkCountChanged),
+            permissions_changed = alert.count(DriftKind::PermissionsChanged),
+            type_changed = alert.count(DriftKind::TypeChanged),
+            symlink_retargeted = alert.count(DriftKind::SymlinkRetargeted),
+            symlink_escaped = alert.count(DriftKind::SymlinkEscaped),
+            baseline_modified = alert.count(DriftKind::BaselineModified);
+            "ALERT for {}: {}", alert.dir, alert.summary
+        );
+        Ok(())
+    }
+}
+
+/// A desktop notification, in monitor mode.
+pub struct DesktopNotifier;
+
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn notify(&self, alert: &Alert, _listing: &Listing) -> Result<(), String> {
+        desktop::notify(alert);
+        Ok(())
+    }
+}
+
+impl Notifier for WebhookConfig {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn notify(&self, alert: &Alert, listing: &Listing) -> Result<(), String> {
+        webhook::send(self, alert, listing)
+    }
+}
+
+impl Notifier for SlackConfig {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    fn notify(&self, alert: &Alert, listing: &Listing) -> Result<(), String> {
+        slack::send(self, alert, listing)
+    }
+}
+
+impl Notifier for TeamsConfig {
+    fn name(&self) -> &'static str {
+        "teams"
+    }
+
+    fn notify(&self, alert: &Alert, listing: &Listing) -> Result<(), String> {
+        teams::send(self, alert, listing)
+    }
+}
+
+impl Notifier for TelegramConfig {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn notify(&self, alert: &Alert, listing: &Listing) -> Result<(), String> {
+        telegram::send(self, alert, listing)
+    }
+}
+
+impl Notifier for PagerDutyConfig {
+    fn name(&self) -> &'static str {
+        "pagerduty"
+    }
+
+    fn notify(&self, alert: &Alert, _listing: &Listing) -> Result<(), String> {
+        pagerduty::send(self, alert)
+    }
+
+    fn resolve(&self, dir: &str) -> Result<(), String> {
+        pagerduty::resolve(self, dir)
+    }
+}
+
+impl Notifier for EmailConfig {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn notify(&self, alert: &Alert, listing: &Listing) -> Result<(), String> {
+        if self.waits_for_digest(alert) {
+            digest::queue(alert).map_err(|e| format!("could not queue it for the digest: {}", e))
+        } else {
+            email::send_email_alert(self, alert, listing)
+        }
+    }
+}
+
+impl EmailConfig {
+    /// Alerts that aren't urgent wait for the digest, where there is one;
+    /// critical ones and urgent drift are emailed at once.
+    fn waits_for_digest(&self, alert: &Alert) -> bool {
+        let urgent = alert.severity() == Severity::Critical
+            || alert.drifts.iter().any(|d| d.kind.is_urgent());
+        self.digest.is_some() && !urgent
+    }
+}
+
+impl AlertChannels {
+    /// The log, then each configured channel.
+    pub fn notifiers(&self) -> Vec<&dyn Notifier> {
+        let mut notifiers: Vec<&dyn Notifier> = vec![&LogNotifier];
+        if self.desktop {
+            notifiers.push(&DesktopNotifier);
+        }
+        let configured: [Option<&dyn Notifier>; 7] = [
+            self.webhook.as_ref().map(|c| c as &dyn Notifier),
+            self.slack.as_ref().map(|c| c as &dyn Notifier),
+            self.teams.as_ref().map(|c| c as &dyn Notifier),
+            self.telegram.as_ref().map(|c| c as &dyn Notifier),
+            self.pagerduty.as_ref().map(|c| c as &dyn Notifier),
+            self.email.as_ref().map(|c| c as &dyn Notifier),
+            self.on_drift.as_ref().map(|c| c as &dyn Notifier),
+        ];
+        notifiers.extend(configured.into_iter().flatten());
+        notifiers
+    }
+}
+
+/// Deliver an alert to every configured channel. Delivery failures are
+/// reported but never propagated: alerting must not abort a comparison, and
+/// one channel failing, or panicking, doesn't keep the alert from the rest.
+pub fn send(alert: &Alert, channels: &AlertChannels) {
+    for notifier in channels.notifiers() {
+        let listing = channels.limits.listing(notifier.name());
+        isolated(notifier, |n| n.notify(alert, &listing));
+    }
+}
+
+/// Tell channels that track incidents that `dir` no longer drifts.
+pub fn resolve(dir: &str, channels: &AlertChannels) {
+    for notifier in channels.notifiers() {
+        isolated(notifier, |n| n.resolve(dir));
+    }
+}
+
+/// Run `deliver` through `notifier`, reporting its failure.
+fn isolated(notifier: &dyn Notifier, deliver: impl FnOnce(&dyn Notifier) -> Result<(), String>) {
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| deliver(notifier)))
+        .unwrap_or_else(|_| Err("it panicked".to_string()));
+    if let Err(e) = result {
+        eprintln!("Warning: {} alert failed: {}", notifier.name(), e);
+        log::error!(
+            event = "alert_failed",
+            channel = notifier.name();
+            "{} alert failed: {}", notifier.name(), e
+        );
+    }
+}
+
+/// Warn, at startup, that `--alert` has nowhere to send alerts but the log.
+pub fn warn_if_log_only(channels: Option<&AlertChannels>) {
+    if channels.is_some_and(AlertChannels::is_empty) {
+        eprintln!(
+            "Warning: alerting is on but no alert channel is configured, so alerts only go to the log. \
+             Configure one with --webhook-url, --slack-webhook, --teams-webhook, --telegram-token, \
+             --pagerduty-routing-key, --smtp-host or --on-drift, or under [alerts] in the config file."
+        );
+        log::warn!(
+            event = "alert_channels_missing";
+            "Alerting is on but no alert channel is configured; alerts only go to the log"
+        );
+    }
+}
+
+/// Suppresses repeat alerts for the same paths within a cooldown window.
+///
+/// Paths not yet alerted on in the current window break through immediately;
+/// everything else is counted and reported in one summary when the window
+/// closes.
+pub struct Cooldown {
+    window: Duration,
+    window_end: Option<Instant>,
+    alerted_paths: HashSet<String>,
+    suppressed_changes: u64,
+    suppressed_paths: BTreeSet<String>,
+}
+
+impl Cooldown {
+    pub fn new(window: Duration) -> Self {
+        Cooldown {
+            window,
+            window_end: None,
+            alerted_paths: HashSet::new(),
+            suppressed_changes: 0,
+            suppressed_paths: BTreeSet::new(),
+        }
+    }
+
+    /// Hold back repeats for `window` from the next window on; the one open
+    /// now runs its course.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Decide what to send for newly detected drift. Returns the alert to
+    /// deliver now, if any.
+    pub fn filter(&mut self, dir: &str, drifts: &[Drift]) -> Option<Alert> {
+        if self.window_end.is_none() {
+            self.window_end = Some(Instant::now() + self.window);
+            self.alerted_paths = drifts.iter().map(|d| d.path.clone()).collect();
+            return Some(Alert::drift(dir, drifts));
+        }
+
+        // A new executable, or a link out of the tree, is alerted on however
+        // recently its path was.
+        let (fresh, repeat): (Vec<Drift>, Vec<Drift>) = drifts
+            .iter()
+            .cloned()
+            .partition(|d| d.kind.is_urgent() || !self.alerted_paths.contains(&d.path));
+        if !repeat.is_empty() {
+            self.suppressed_changes += repeat.len() as u64;
+            self.suppressed_paths
+                .extend(repeat.iter().map(|d| d.path.clone()));
+            let paths: Vec<&str> = repeat.iter().map(|d| d.path.as_str()).collect();
+            log::info!(
+                event = "alert_suppressed",
+                dir = dir,
+                paths:serde = paths;
+                "Alert suppressed during cooldown: {}", paths.join(", ")
+            );
+        }
+        if fresh.is_empty() {
+            return None;
+        }
+        self.alerted_paths
+            .extend(fresh.iter().map(|d| d.path.clone()));
+        Some(Alert::drift(dir, &fresh))
+    }
+
+    /// Close the window once it has elapsed, returning a summary of anything
+    /// that was suppressed during it.
+    pub fn expire(&mut self, dir: &str) -> Option<Alert> {
+        if self.window_end.is_none_or(|end| Instant::now() < end) {
+            return None;
+        }
+        self.window_end = None;
+        self.alerted_paths.clear();
+        if self.suppressed_changes == 0 {
+            return None;
+        }
+        let alert = Alert {
+            dir: dir.to_string(),
+            summary: format!(
+                "{} further changes to {} files suppressed during cooldown",
+                self.suppressed_changes,
+                self.suppressed_paths.len()
+            ),
+            drifts: Vec::new(),
+            tags: BTreeMap::new(),
+            baseline_age_days: None,
+            annotations: BTreeMap::new(),
+            severity: None,
+            report: None,
+        };
+        self.suppressed_changes = 0;
+        self.suppressed_paths.clear();
+        Some(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ScoreWeights, SeverityRule};
+
+    #[test]
+    fn scores_weigh_each_drift_by_its_severity() {
+        let drift = |kind, path: &str| Drift {
+            kind,
+            path: path.into(),
+            reason: None,
+        };
+        let drifts = [
+            drift(DriftKind::Deleted, "/etc/app/app.conf"),
+            drift(DriftKind::Changed, "/etc/app/motd"),
+            drift(DriftKind::New, "/etc/app/extra.conf"),
+        ];
+        let defaults = config::Settings::default();
+        assert_eq!(score(&drifts, &defaults), 50 + 5 + 1);
+        assert_eq!(score(&[], &defaults), 0);
+
+        let config = Config {
+            severity: vec![SeverityRule {
+                path: "*/motd".into(),
+                kind: None,
+                severity: Severity::Info,
+            }],
+            score: ScoreWeights {
+                critical: 100,
+                warning: 10,
+                info: 0,
+            },
+            ..Config::default()
+        };
+        let settings = config.settings().unwrap();
+        assert_eq!(score(&drifts, &settings), 100, "rules decide the severity");
+    }
+
+    #[test]
+    fn alerts_list_the_most_severe_entries_first_up_to_the_limit() {
+        let drift = |kind, path: &str| Drift {
+            kind,
+            path: path.into(),
+            reason: None,
+        };
+        let alert = Alert {
+            dir: "/etc/app".into(),
+            summary: String::new(),
+            drifts: vec![
+                drift(DriftKind::New, "/etc/app/extra.conf"),
+                drift(DriftKind::Changed, "/etc/app/motd"),
+                drift(DriftKind::Deleted, "/etc/app/app.conf"),
+                drift(DriftKind::Changed, "/etc/app/hosts"),
+            ],
+            tags: BTreeMap::new(),
+            baseline_age_days: None,
+            annotations: BTreeMap::new(),
+            severity: None,
+            report: None,
+        };
+        let limits = AlertLimits {
+            max_entries: Some(2),
+            per_channel: BTreeMap::from([("email".to_string(), 10)]),
+            report: Some("drift.json".into()),
+        };
+        let (listed, omitted) = alert.listed(&limits.listing("slack"));
+        let paths: Vec<&str> = listed.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, ["/etc/app/app.conf", "/etc/app/motd"]);
+        assert_eq!(omitted, 2);
+        assert_eq!(alert.listed(&limits.listing("email")).1, 0);
+        assert_eq!(alert.listed(&Listing::default()).0.len(), 4);
+
+        let listing = limits.listing("slack");
+        assert_eq!(listing.trailer(2, false), "…and 2 more (see drift.json)");
+        assert_eq!(
+            listing.trailer(2, true),
+            "…and 2 more (see the attached report)"
+        );
+        assert_eq!(Listing::default().trailer(2, false), "…and 2 more");
+    }
+
+    #[test]
+    fn only_alerts_that_are_not_urgent_wait_for_the_digest() {
+        let mailbox = |address: &str| address.parse().unwrap();
+        let mut config = EmailConfig {
+            host: "smtp.example.com".into(),
+            port: None,
+            tls: email::SmtpTls::Starttls,
+            credentials: None,
+            from: mailbox("guardian@example.com"),
+            to: vec![mailbox("ops@example.com")],
+            subject: email::DEFAULT_SUBJECT.into(),
+            attach_report: false,
+            digest: chrono::NaiveTime::from_hms_opt(8, 0, 0),
+        };
+        let drift = |kind| Drift {
+            kind,
+            path: "/etc/app/app.conf".into(),
+            reason: None,
+        };
+        let changed = Alert::drift("/etc/app", &[drift(DriftKind::Changed)]);
+        let deleted = Alert::drift("/etc/app", &[drift(DriftKind::Deleted)]);
+        let setuid = Alert::drift("/etc/app", &[drift(DriftKind::NewSetuid)]);
+        let mut flood = Alert::drift("/etc/app", &[]);
+        flood.severity = Some(Severity::Critical);
+
+        assert!(config.waits_for_digest(&changed));
+        assert!(!config.waits_for_digest(&deleted), "critical");
+        assert!(!config.waits_for_digest(&setuid), "urgent");
+        assert!(!config.waits_for_digest(&flood), "critical summary");
+
+        config.digest = None;
+        assert!(!config.waits_for_digest(&changed), "no digest");
+    }
+}
@@ -0,0 +1,161 @@
+//! `--all-profiles`: run `snapshot`, `compare` or `monitor` once for each
+//! `[profile.NAME]` of the config file. Settings are fixed for a whole run,
+//! so each profile runs in a process of its own, given the same arguments
+//! and `--profile NAME`.
+
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{GuardianError, config, monitor};
+
+/// The runs' processes, and whether a signal asked them to stop.
+#[derive(Clone, Default)]
+struct Runs {
+    pids: Arc<Mutex<Vec<u32>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Runs {
+    /// Pass Ctrl+C, SIGTERM or SIGHUP on to the runs, rather than leaving
+    /// them without the process that sums them up. A terminal's Ctrl+C
+    /// reaches them itself; on Unix they are sent SIGTERM too, for a stop
+    /// meant for this process alone, such as a service manager's.
+    fn forward_signals(&self) {
+        let runs = self.clone();
+        thread::spawn(move || {
+            let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            else {
+                return;
+            };
+            runtime.block_on(monitor::cancel_on_signal(CancellationToken::new()));
+            runs.stopped.store(true, Ordering::SeqCst);
+            #[cfg(unix)]
+            for pid in runs.pids.lock().unwrap().iter() {
+                unsafe { libc::kill(*pid as libc::pid_t, libc::SIGTERM) };
+            }
+        });
+    }
+
+    fn start(&self, command: &mut Command) -> Result<Child, GuardianError> {
+        let child = command
+            .spawn()
+            .map_err(|e| GuardianError::Other(format!("Could not start a profile's run: {}", e)))?;
+        self.pids.lock().unwrap().push(child.id());
+        Ok(child)
+    }
+}
+
+/// The command for `name`'s run: this executable, with this run's arguments
+/// less `--all-profiles`.
+fn command_for(name: &str) -> Result<Command, GuardianError> {
+    let exe = std::env::current_exe().map_err(GuardianError::io("the current executable"))?;
+    let args: Vec<OsString> = std::env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--all-profiles")
+        .collect();
+    let mut command = Command::new(exe);
+    command.args(args).arg("--profile").arg(name);
+    Ok(command)
+}
+
+/// A signal ended the run.
+const INTERRUPTED: i32 = 130;
+
+fn code_of(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(INTERRUPTED)
+}
+
+/// Copy `from` to stdout, or stderr, a line at a time, each one starting
+/// `[NAME] `.
+fn relay(name: &str, from: impl Read + Send + 'static, to_stderr: bool) -> JoinHandle<()> {
+    let prefix = format!("[{}]", name);
+    thread::spawn(move || {
+        let mut from = BufReader::new(from);
+        let mut line = Vec::new();
+        while from.read_until(b'\n', &mut line).is_ok_and(|n| n > 0) {
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\n', '\r']);
+            if to_stderr {
+                eprintln!("{} {}", prefix, text);
+            } else {
+                println!("{} {}", prefix, text);
+            }
+            line.clear();
+        }
+    })
+}
+
+/// Run every profile of `config`, one after the other, or with `together`
+/// all at once and their output tagged with the profile's name, as
+/// `monitor` needs. Prints how each run ended and returns the exit status
+/// for all of them: the highest of theirs.
+pub fn run(config: &config::Config, together: bool) -> Result<i32, GuardianError> {
+    if config.profiles.is_empty() {
+        return Err(GuardianError::Config(
+            "--all-profiles needs [profile.NAME] sections in the config file".into(),
+        ));
+    }
+    let runs = Runs::default();
+    runs.forward_signals();
+    let mut codes: Vec<(&str, i32)> = Vec::new();
+    if together {
+        let mut started = Vec::new();
+        for name in config.profiles.keys() {
+            let mut child = runs.start(
+                command_for(name)?
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped()),
+            )?;
+            let relays = [
+                relay(name, child.stdout.take().expect("piped"), false),
+                relay(name, child.stderr.take().expect("piped"), true),
+            ];
+            started.push((name.as_str(), child, relays));
+        }
+        for (name, mut child, relays) in started {
+            let status = child
+                .wait()
+                .map_err(|e| GuardianError::Other(e.to_string()))?;
+            for relay in relays {
+                let _ = relay.join();
+            }
+            codes.push((name, code_of(status)));
+        }
+    } else {
+        for name in config.profiles.keys() {
+            if runs.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            println!("==> Profile {}", name);
+            let status = runs
+                .start(&mut command_for(name)?)?
+                .wait()
+                .map_err(|e| GuardianError::Other(e.to_string()))?;
+            codes.push((name, code_of(status)));
+        }
+    }
+
+    let width = codes.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    println!("Profiles:");
+    for (name, code) in &codes {
+        let outcome = match code {
+            0 => "ok".to_string(),
+            &INTERRUPTED => "interrupted".to_string(),
+            code => format!("exit {}", code),
+        };
+        println!("  {:<width$}  {}", name, outcome);
+    }
+    let worst = codes.iter().map(|(_, code)| *code).max().unwrap_or(0);
+    if runs.stopped.load(Ordering::SeqCst) && codes.len() < config.profiles.len() {
+        return Ok(worst.max(INTERRUPTED));
+    }
+    Ok(worst)
+}
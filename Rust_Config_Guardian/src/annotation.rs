@@ -0,0 +1,199 @@
+//! Annotations: what a team records against a baseline entry, such as who
+//! owns the file and what to expect of it. `annotate` keeps them in
+//! `annotations.json` rather than in the snapshot, so that `accept` and a
+//! new snapshot of the same paths leave them as they are. Drift to an
+//! annotated file is reported, and alerted on, with its annotation, so that
+//! it can be routed to its owner; `show --annotations` lists them, flagging
+//! those whose file the snapshot no longer has as stale.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::history::OutputFormat;
+use crate::{DriftReport, GuardianError, Snapshot, config, redact};
+
+pub const ANNOTATIONS_FILE: &str = "annotations.json";
+
+/// Who owns a file and what to expect of it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Annotation {
+    fn is_empty(&self) -> bool {
+        self.owner.is_none() && self.note.is_none()
+    }
+}
+
+/// As drift lines carry it: `owner: db-team — note: rotated monthly`.
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [("owner", &self.owner), ("note", &self.note)]
+            .into_iter()
+            .filter_map(|(name, value)| value.as_ref().map(|v| format!("{}: {}", name, v)))
+            .collect();
+        f.write_str(&parts.join(" — "))
+    }
+}
+
+/// The annotations, by the path the snapshot records each file under.
+pub fn load() -> Result<BTreeMap<String, Annotation>, String> {
+    match std::fs::read_to_string(ANNOTATIONS_FILE) {
+        Ok(data) => serde_json::from_str(&data)
+            .map_err(|e| format!("could not parse {}: {}", ANNOTATIONS_FILE, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("could not read {}: {}", ANNOTATIONS_FILE, e)),
+    }
+}
+
+fn save(annotations: &BTreeMap<String, Annotation>) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(annotations).map_err(|e| e.to_string())?;
+    let tmp = format!("{}.tmp", ANNOTATIONS_FILE);
+    std::fs::write(&tmp, data)
+        .and_then(|_| std::fs::rename(&tmp, ANNOTATIONS_FILE))
+        .map_err(|e| format!("could not write {}: {}", ANNOTATIONS_FILE, e))
+}
+
+/// Set the owner and note given of the snapshot's entry for `path`, as
+/// recorded or relative to a snapshot directory, keeping what isn't given;
+/// with `clear`, drop what was there first. Returns the path as recorded and
+/// its annotation, which is removed when left empty.
+pub fn annotate(
+    path: &str,
+    owner: Option<&str>,
+    note: Option<&str>,
+    clear: bool,
+) -> Result<(String, Annotation), GuardianError> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let Some(entry) = snapshot.resolve(path) else {
+        return Err(GuardianError::Config(format!(
+            "{} is not in the snapshot; annotations are kept for its entries",
+            path
+        )));
+    };
+    let mut annotations = load().map_err(GuardianError::Config)?;
+    let annotation = annotations.entry(entry.path.clone()).or_default();
+    if clear {
+        *annotation = Annotation::default();
+    }
+    if let Some(owner) = owner {
+        annotation.owner = Some(owner.to_string());
+    }
+    if let Some(note) = note {
+        annotation.note = Some(note.to_string());
+    }
+    let annotation = annotation.clone();
+    if annotation.is_empty() {
+        annotations.remove(&entry.path);
+    }
+    save(&annotations).map_err(GuardianError::Config)?;
+    let shown = redact::path(&entry.path);
+    log::info!(
+        event = "annotated",
+        path = shown.as_ref(),
+        owner = annotation.owner.as_deref().unwrap_or_default(),
+        note = annotation.note.as_deref().unwrap_or_default();
+        "Annotation of {} set to '{}'", shown, annotation
+    );
+    Ok((entry.path.clone(), annotation))
+}
+
+/// Print the annotations, by path, those whose file the snapshot no longer
+/// has marked stale.
+pub fn list(format: OutputFormat) -> Result<(), GuardianError> {
+    let annotations = load().map_err(GuardianError::Config)?;
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let index = snapshot.index();
+    let stale = |path: &str| !index.contains_key(path);
+    match format {
+        OutputFormat::Json => {
+            let listed: Vec<serde_json::Value> = annotations
+                .iter()
+                .map(|(path, annotation)| {
+                    serde_json::json!({
+                        "path": redact::path(path),
+                        "owner": annotation.owner,
+                        "note": annotation.note,
+                        "stale": stale(path),
+                    })
+                })
+                .collect();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&listed).expect("annotations serialize to JSON")
+            );
+        }
+        OutputFormat::Text if annotations.is_empty() => println!("No annotations."),
+        OutputFormat::Text => {
+            println!("{:<5}  {:<30}  {:<15}  NOTE", "STATE", "PATH", "OWNER");
+            for (path, annotation) in &annotations {
+                println!(
+                    "{:<5}  {:<30}  {:<15}  {}",
+                    if stale(path) { "stale" } else { "ok" },
+                    redact::shown(path),
+                    annotation.owner.as_deref().unwrap_or("-"),
+                    annotation.note.as_deref().unwrap_or("-")
+                );
+            }
+            let stale = annotations.keys().filter(|path| stale(path)).count();
+            if stale > 0 {
+                eprintln!(
+                    "Warning: {} annotation{} for files the snapshot no longer has; clear {} with 'annotate PATH --clear'.",
+                    stale,
+                    if stale == 1 { " is" } else { "s are" },
+                    if stale == 1 { "it" } else { "them" }
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Give `report` the annotations of the files that drifted.
+pub(crate) fn attach(report: &mut DriftReport) {
+    let annotations = match load() {
+        Ok(annotations) => annotations,
+        Err(e) => {
+            eprintln!("Warning: annotations not shown: {}", e);
+            log::error!("Could not load the annotations: {}", e);
+            return;
+        }
+    };
+    report.annotations = report
+        .drifts
+        .iter()
+        .chain(report.excepted.iter().map(|excepted| &excepted.drift))
+        .filter_map(|drift| {
+            let annotation = annotations.get(&drift.path)?;
+            Some((drift.path.clone(), annotation.clone()))
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotations_read_as_owner_then_note() {
+        let annotation = Annotation {
+            owner: Some("db-team".into()),
+            note: Some("rotated monthly".into()),
+        };
+        assert_eq!(
+            annotation.to_string(),
+            "owner: db-team — note: rotated monthly"
+        );
+        let owner_only = Annotation {
+            note: None,
+            ..annotation
+        };
+        assert_eq!(owner_only.to_string(), "owner: db-team");
+        assert!(Annotation::default().is_empty());
+    }
+}
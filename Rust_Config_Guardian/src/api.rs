@@ -0,0 +1,184 @@
+//! Read-only HTTP status API for monitor mode.
+//!
+//! The monitor loop writes the state after every comparison and watch
+//! change; request handlers only take the read lock.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::drift::Observed;
+use crate::listener::{Reply, Response};
+use crate::{Drift, config, progress, redact};
+
+/// `/healthz` fails if the monitor loop hasn't ticked for this long.
+const STALL_THRESHOLD: Duration = Duration::from_secs(30);
+
+pub struct ApiState {
+    directory: String,
+    baseline_files: usize,
+    started: DateTime<Utc>,
+    watching: bool,
+    last_tick: Instant,
+    last_comparison: Option<DateTime<Utc>>,
+    /// `Ok(drift count)` or the error from the last comparison.
+    last_result: Option<Result<usize, String>>,
+    drifts: Vec<Observed>,
+    /// The config file in effect: where it is, when it was taken up, and
+    /// the checksum of what was.
+    config: Option<(PathBuf, DateTime<Utc>, String)>,
+}
+
+pub type SharedApiState = Arc<RwLock<ApiState>>;
+
+impl ApiState {
+    pub fn new(directory: &str, baseline_files: usize) -> Self {
+        ApiState {
+            directory: directory.to_string(),
+            baseline_files,
+            started: Utc::now(),
+            watching: false,
+            last_tick: Instant::now(),
+            last_comparison: None,
+            last_result: None,
+            drifts: Vec::new(),
+            config: None,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.last_tick = Instant::now();
+    }
+
+    /// Whether the monitor is currently receiving changes (watching or
+    /// polling), as opposed to re-establishing a lost watch.
+    pub fn set_watching(&mut self, watching: bool) {
+        self.watching = watching;
+    }
+
+    pub(crate) fn record(&mut self, result: Result<Vec<Observed>, String>) {
+        self.last_comparison = Some(Utc::now());
+        match result {
+            Ok(drifts) => {
+                self.last_result = Some(Ok(drifts.len()));
+                self.drifts = drifts.into_iter().map(redacted).collect();
+            }
+            Err(e) => self.last_result = Some(Err(e)),
+        }
+    }
+
+    /// The config file at `path`, with `checksum`, was taken up just now.
+    pub(crate) fn config_loaded(&mut self, path: PathBuf, checksum: String) {
+        self.config = Some((path, Utc::now(), checksum));
+    }
+
+    fn healthy(&self) -> bool {
+        self.watching && self.last_tick.elapsed() < STALL_THRESHOLD
+    }
+
+    pub(crate) fn status(&self) -> serde_json::Value {
+        let modified = std::fs::metadata(&config::settings().snapshot_file)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(|t| DateTime::<Utc>::from(t).to_rfc3339());
+        let result = match &self.last_result {
+            None => json!(null),
+            Some(Ok(0)) => json!({ "status": "clean" }),
+            Some(Ok(n)) => json!({ "status": "drift", "drifted_files": n }),
+            Some(Err(e)) => json!({ "status": "error", "error": e }),
+        };
+        // The scan in flight, or else the last one's figures.
+        let scan = match progress::current(&self.directory) {
+            Some(progress) => Some((true, progress)),
+            None => progress::last(&self.directory).map(|progress| (false, progress)),
+        }
+        .map(|(in_progress, progress)| {
+            let mut scan = json!(progress);
+            scan["in_progress"] = json!(in_progress);
+            scan
+        });
+        json!({
+            "pid": std::process::id(),
+            "directories": [self.directory],
+            "started": self.started.to_rfc3339(),
+            "healthy": self.healthy(),
+            "baseline": {
+                "path": config::settings().snapshot_file,
+                "files": self.baseline_files,
+                "modified": modified,
+            },
+            "last_comparison": self.last_comparison.map(|t| t.to_rfc3339()),
+            "last_result": result,
+            "scan": scan,
+            "drift": self.drifts,
+            "config": self.config.as_ref().map(|(path, loaded, checksum)| json!({
+                "path": path,
+                "loaded": loaded.to_rfc3339(),
+                "hash": checksum,
+            })),
+        })
+    }
+}
+
+/// `observed` with its path redacted.
+fn redacted(observed: Observed) -> Observed {
+    Observed {
+        drift: Drift {
+            path: redact::path(&observed.drift.path).into_owned(),
+            ..observed.drift
+        },
+        ..observed
+    }
+}
+
+/// Fan-out of drift events to `/events` subscribers.
+#[derive(Default)]
+pub struct EventHub {
+    subscribers: Mutex<Vec<Sender<String>>>,
+}
+
+impl EventHub {
+    fn subscribe(&self) -> Receiver<String> {
+        let (tx, rx) = channel();
+        self.lock().push(tx);
+        rx
+    }
+
+    /// Send one SSE `drift` event per entry, with when the session first
+    /// and last saw it. Subscribers whose stream has ended are dropped here.
+    pub(crate) fn publish(&self, dir: &str, drifts: &[Observed]) {
+        let mut subscribers = self.lock();
+        for observed in drifts {
+            let observed = redacted(observed.clone());
+            let mut event = observed.drift.event(dir);
+            if let Some(seen) = observed.seen {
+                event["first_seen"] = json!(seen.first_seen);
+                event["last_seen"] = json!(seen.last_seen);
+            }
+            let event = format!("event: drift\ndata: {}\n\n", event);
+            subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Sender<String>>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub fn handle(state: &SharedApiState, events: &EventHub, path: &str) -> Reply {
+    if path == "/events" {
+        return Reply::Stream(events.subscribe());
+    }
+    let state = state.read().unwrap_or_else(|e| e.into_inner());
+    match path {
+        "/status" => Response::json(200, &state.status()),
+        "/drift" => Response::json(200, &json!(state.drifts)),
+        "/healthz" if state.healthy() => Response::json(200, &json!({ "status": "ok" })),
+        "/healthz" => Response::json(503, &json!({ "status": "unhealthy" })),
+        _ => Response::not_found(),
+    }
+    .into()
+}
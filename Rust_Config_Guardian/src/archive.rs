@@ -0,0 +1,234 @@
+//! Earlier snapshots. Each time `snapshot` replaces the snapshot file, the
+//! file it replaces is kept here under the time it was taken, so a
+//! comparison can go back to it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{GuardianError, Snapshot};
+
+/// Number of archived snapshots kept; older ones are removed.
+pub const ARCHIVE_KEEP: usize = 20;
+
+/// A change to the archive, as [`Archive::plan`] lists them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveAction {
+    /// Copy the snapshot file in, as this file.
+    Copy(PathBuf),
+    /// Remove an archived snapshot beyond [`ARCHIVE_KEEP`].
+    Remove(PathBuf),
+}
+
+impl std::fmt::Display for ArchiveAction {
+    /// What the action does, as `--dry-run` prints it after "Would".
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveAction::Copy(copy) => {
+                write!(f, "archive the previous snapshot as {}", copy.display())
+            }
+            ArchiveAction::Remove(old) => write!(f, "remove archived snapshot {}", old.display()),
+        }
+    }
+}
+
+/// The archive of a snapshot file: `snapshot.json` is archived in
+/// `snapshot.archive/` as `snapshot-<time>.json`.
+pub struct Archive {
+    dir: PathBuf,
+    stem: String,
+}
+
+impl Archive {
+    pub fn of(snapshot_file: &Path) -> Self {
+        let stem = snapshot_file
+            .file_stem()
+            .map_or_else(|| "snapshot".into(), |s| s.to_string_lossy().into_owned());
+        Archive {
+            dir: snapshot_file.with_file_name(format!("{}.archive", stem)),
+            stem,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// What [`Archive::add`] would do for `snapshot_file`: copy it in, if
+    /// there is one yet, and drop the oldest archives beyond
+    /// [`ARCHIVE_KEEP`].
+    pub fn plan(&self, snapshot_file: &Path) -> Vec<ArchiveAction> {
+        if !snapshot_file.is_file() {
+            return Vec::new();
+        }
+        // Named by when the snapshot was taken, falling back to when the
+        // file was written for snapshots without metadata.
+        let taken = Snapshot::load(snapshot_file)
+            .ok()
+            .and_then(|s| s.metadata.created)
+            .or_else(|| {
+                let modified = fs::metadata(snapshot_file).and_then(|m| m.modified());
+                modified.ok().map(Into::into)
+            })
+            .unwrap_or_else(chrono::Utc::now);
+        let copy = self.dir.join(format!(
+            "{}-{}.json",
+            self.stem,
+            taken.format("%Y%m%dT%H%M%S%.3fZ")
+        ));
+        let mut kept = self.list();
+        if !kept.contains(&copy) {
+            kept.push(copy.clone());
+            kept.sort_by(|a, b| b.cmp(a));
+        }
+        let mut actions = vec![ArchiveAction::Copy(copy)];
+        actions.extend(
+            kept.into_iter()
+                .skip(ARCHIVE_KEEP)
+                .map(ArchiveAction::Remove),
+        );
+        actions
+    }
+
+    /// Carry out [`Archive::plan`]. Returns the copy.
+    pub fn add(&self, snapshot_file: &Path) -> Result<Option<PathBuf>, GuardianError> {
+        let mut copied = None;
+        for action in self.plan(snapshot_file) {
+            match action {
+                ArchiveAction::Copy(copy) => {
+                    fs::create_dir_all(&self.dir).map_err(GuardianError::io(&self.dir))?;
+                    fs::copy(snapshot_file, &copy).map_err(GuardianError::io(&copy))?;
+                    copied = Some(copy);
+                }
+                ArchiveAction::Remove(old) => {
+                    if let Err(e) = fs::remove_file(&old) {
+                        log::warn!(
+                            "Could not remove archived snapshot {}: {}",
+                            old.display(),
+                            e
+                        );
+                    }
+                }
+            }
+        }
+        Ok(copied)
+    }
+
+    /// The archived snapshots, newest first.
+    pub fn list(&self) -> Vec<PathBuf> {
+        let prefix = format!("{}-", self.stem);
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with(&prefix) && name.ends_with(".json")
+            })
+            .map(|entry| entry.path())
+            .collect();
+        // The times in the names sort as text.
+        files.sort_by(|a, b| b.cmp(a));
+        files
+    }
+
+    /// The snapshot `back` replacements ago: 1 is the one the current
+    /// snapshot replaced.
+    pub fn back(&self, back: usize) -> Result<PathBuf, GuardianError> {
+        let files = self.list();
+        back.checked_sub(1)
+            .and_then(|i| files.get(i))
+            .cloned()
+            .ok_or_else(|| GuardianError::ArchiveMissing {
+                dir: self.dir.clone(),
+                wanted: back,
+                available: files.len(),
+            })
+    }
+}
+
+/// Parse `--against`: `previous` or `-1` for the snapshot the current one
+/// replaced, `-2` for the one before that, and so on.
+pub fn parse_against(value: &str) -> Result<usize, String> {
+    let back = match value {
+        "previous" => Some(1),
+        _ => value
+            .strip_prefix('-')
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|&n| n > 0),
+    };
+    back.ok_or_else(|| {
+        format!(
+            "invalid snapshot '{}' (expected previous, -1, -2, ...)",
+            value
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_are_looked_up_newest_first() {
+        let dir = std::env::temp_dir().join(format!("guardian-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("snapshot.json");
+        let archive = Archive::of(&file);
+        assert_eq!(archive.dir(), dir.join("snapshot.archive"));
+        assert_eq!(archive.add(&file).unwrap(), None);
+
+        for created in ["2024-05-01T10:00:00Z", "2024-05-02T10:00:00Z"] {
+            let json = format!(
+                r#"{{"metadata": {{"created": "{}"}}, "entries": []}}"#,
+                created
+            );
+            fs::write(&file, json).unwrap();
+            archive.add(&file).unwrap();
+        }
+        let name = |path: PathBuf| path.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            name(archive.back(1).unwrap()),
+            "snapshot-20240502T100000.000Z.json"
+        );
+        assert_eq!(
+            name(archive.back(2).unwrap()),
+            "snapshot-20240501T100000.000Z.json"
+        );
+        assert!(matches!(
+            archive.back(3),
+            Err(GuardianError::ArchiveMissing {
+                wanted: 3,
+                available: 2,
+                ..
+            })
+        ));
+
+        // With the archive full, adding one more drops the oldest.
+        for second in 0..ARCHIVE_KEEP - 2 {
+            let old = format!("snapshot-20240101T0000{:02}.000Z.json", second);
+            fs::write(archive.dir().join(old), "{}").unwrap();
+        }
+        fs::write(
+            &file,
+            r#"{"metadata": {"created": "2024-05-03T10:00:00Z"}, "entries": []}"#,
+        )
+        .unwrap();
+        let plan = archive.plan(&file);
+        assert_eq!(
+            plan,
+            [
+                ArchiveAction::Copy(archive.dir().join("snapshot-20240503T100000.000Z.json")),
+                ArchiveAction::Remove(archive.dir().join("snapshot-20240101T000000.000Z.json")),
+            ]
+        );
+        assert!(plan[1].to_string().starts_with("remove archived snapshot "));
+        assert_eq!(archive.list().len(), ARCHIVE_KEEP);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(parse_against("previous"), Ok(1));
+        assert_eq!(parse_against("-3"), Ok(3));
+        assert!(parse_against("-0").is_err());
+        assert!(parse_against("2").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! The hash cache: what each file hashed to when a scan last read it, by
+//! where its content lives (device and inode) and what writing to it would
+//! change (size and ctime). A scan takes a file's hash from the cache
+//! rather than read it when all four still match, so a file whose
+//! modification time no longer matches the snapshot's, as backup tools and
+//! some editors leave it with its content unchanged, is read once rather
+//! than on every comparison. A random sample of the hits is read anyway;
+//! one that hashes differently clears the cache. It is kept in
+//! `hash-cache.json`; `--no-cache` and `paranoid` leave it unused, and
+//! `cache clear` removes it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+
+use crate::config::{self, HashAlgorithm};
+use crate::normalize;
+
+pub const CACHE_FILE: &str = "hash-cache.json";
+
+/// What identifies a file's content to the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Identity {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    /// Seconds and nanoseconds.
+    ctime: (i64, i64),
+}
+
+impl Identity {
+    #[cfg(unix)]
+    pub(crate) fn of(metadata: &fs::Metadata) -> Option<Identity> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Identity {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.len(),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+        })
+    }
+
+    /// Without inodes there is nothing to key the cache by.
+    #[cfg(not(unix))]
+    pub(crate) fn of(_metadata: &fs::Metadata) -> Option<Identity> {
+        None
+    }
+
+    pub(crate) fn inode(&self) -> (u64, u64) {
+        (self.dev, self.ino)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    ctime: i64,
+    ctime_nsec: i64,
+    hash: String,
+    /// The span of a sampled hash, as for [`crate::FileHash::sampled`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sampled: Option<u64>,
+    /// The scan target that read it, whose scans drop it once the file is
+    /// gone.
+    dir: String,
+}
+
+impl Entry {
+    fn identity(&self) -> Identity {
+        Identity {
+            dev: self.dev,
+            ino: self.ino,
+            size: self.size,
+            ctime: (self.ctime, self.ctime_nsec),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    hash: HashAlgorithm,
+    /// The normalizers' identities, as [`crate::ScanOptions`] records them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    normalize: BTreeMap<String, String>,
+    entries: Vec<Entry>,
+}
+
+/// How long before the cache was opened a file's ctime must be for its
+/// hash to be kept: a write in the same clock tick as the read leaves the
+/// ctime as it was, and some filesystems keep times to the second.
+const SETTLE_SECS: i64 = 2;
+
+pub(crate) struct HashCache {
+    entries: HashMap<(u64, u64), Entry>,
+    /// When it was opened, in seconds since the epoch.
+    opened: i64,
+}
+
+impl HashCache {
+    /// The cache as saved, or `None` when scans aren't to use it. One that
+    /// can't be read, or was kept for another hash algorithm or other
+    /// normalizers, starts empty.
+    pub(crate) fn open() -> Option<HashCache> {
+        let settings = config::settings();
+        if !settings.hash_cache || settings.paranoid || !cfg!(unix) {
+            return None;
+        }
+        let entries = match fs::read_to_string(CACHE_FILE) {
+            Ok(data) => match serde_json::from_str::<CacheFile>(&data) {
+                Ok(file)
+                    if file.hash == settings.hash && file.normalize == normalize::identities() =>
+                {
+                    file.entries
+                }
+                Ok(_) => Vec::new(),
+                Err(e) => {
+                    log::warn!("Could not parse {}, starting it afresh: {}", CACHE_FILE, e);
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                log::warn!("Could not read {}: {}", CACHE_FILE, e);
+                Vec::new()
+            }
+        };
+        Some(HashCache {
+            entries: entries
+                .into_iter()
+                .map(|entry| ((entry.dev, entry.ino), entry))
+                .collect(),
+            opened: chrono::Utc::now().timestamp(),
+        })
+    }
+
+    /// The hash recorded for the file `identity` describes, hashed over
+    /// `sampled` bytes at each end or in full.
+    pub(crate) fn get(&self, identity: &Identity, sampled: Option<u64>) -> Option<&str> {
+        self.entries
+            .get(&identity.inode())
+            .filter(|entry| entry.identity() == *identity && entry.sampled == sampled)
+            .map(|entry| entry.hash.as_str())
+    }
+
+    /// Record what the file `identity` describes hashed to, unless it was
+    /// written too shortly before the scan for that to be sure.
+    pub(crate) fn put(&mut self, dir: &str, identity: Identity, hash: &str, sampled: Option<u64>) {
+        let (ctime, ctime_nsec) = identity.ctime;
+        if ctime > self.opened - SETTLE_SECS {
+            return;
+        }
+        self.entries.insert(
+            identity.inode(),
+            Entry {
+                dev: identity.dev,
+                ino: identity.ino,
+                size: identity.size,
+                ctime,
+                ctime_nsec,
+                hash: hash.to_string(),
+                sampled,
+                dir: dir.to_string(),
+            },
+        );
+    }
+
+    /// Forget every entry, as after one was found to be wrong.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drop the entries `dir`'s scans recorded for files that aren't among
+    /// the inodes `seen` now.
+    pub(crate) fn retain_seen(&mut self, dir: &str, seen: &HashSet<(u64, u64)>) {
+        self.entries
+            .retain(|inode, entry| entry.dir != dir || seen.contains(inode));
+    }
+
+    /// Write the cache back; failing to is only logged, as scans do without
+    /// it.
+    pub(crate) fn save(&self) {
+        let mut entries: Vec<&Entry> = self.entries.values().collect();
+        entries.sort_by_key(|entry| (entry.dev, entry.ino));
+        let file = CacheFile {
+            hash: config::settings().hash,
+            normalize: normalize::identities(),
+            entries: entries.into_iter().cloned().collect(),
+        };
+        let tmp = format!("{}.tmp", CACHE_FILE);
+        let saved = serde_json::to_string(&file)
+            .map_err(std::io::Error::other)
+            .and_then(|data| fs::write(&tmp, data))
+            .and_then(|_| fs::rename(&tmp, CACHE_FILE));
+        if let Err(e) = saved {
+            log::warn!("Could not write {}: {}", CACHE_FILE, e);
+        }
+    }
+}
+
+/// Remove the cache, returning how many entries it held, or `None` if
+/// there was none.
+pub fn clear() -> Result<Option<usize>, String> {
+    let data = match fs::read_to_string(CACHE_FILE) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("could not read {}: {}", CACHE_FILE, e)),
+    };
+    let count = serde_json::from_str::<CacheFile>(&data)
+        .map(|file| file.entries.len())
+        .unwrap_or(0);
+    fs::remove_file(CACHE_FILE).map_err(|e| format!("could not remove {}: {}", CACHE_FILE, e))?;
+    log::info!(event = "hash_cache_cleared", entries = count; "Hash cache cleared ({} entries)", count);
+    Ok(Some(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_found_only_while_size_and_ctime_match() {
+        let identity = Identity {
+            dev: 1,
+            ino: 2,
+            size: 9,
+            ctime: (1_700_000_000, 5),
+        };
+        let mut cache = HashCache {
+            entries: HashMap::new(),
+            opened: 1_800_000_000,
+        };
+        cache.put("/etc/app", identity, "abc", None);
+        assert_eq!(cache.get(&identity, None), Some("abc"));
+        assert_eq!(cache.get(&identity, Some(8)), None);
+        let written = Identity {
+            ctime: (1_700_000_000, 6),
+            ..identity
+        };
+        assert_eq!(cache.get(&written, None), None);
+        let grown = Identity {
+            size: 10,
+            ..identity
+        };
+        assert_eq!(cache.get(&grown, None), None);
+        // Written just before the scan, it may have been read before the
+        // write and is not kept.
+        let recent = Identity {
+            ino: 4,
+            ctime: (1_799_999_999, 0),
+            ..identity
+        };
+        cache.put("/etc/app", recent, "ghi", None);
+        assert_eq!(cache.get(&recent, None), None);
+
+        // Only the directory's own entries go when their files do.
+        cache.put("/etc/other", Identity { ino: 3, ..identity }, "def", None);
+        cache.retain_seen("/etc/app", &HashSet::new());
+        assert_eq!(cache.get(&identity, None), None);
+        assert_eq!(cache.entries.len(), 1);
+    }
+}
@@ -0,0 +1,260 @@
+//! Content-defined chunks of the large files `chunked_hash` names, so that
+//! drift to them says roughly where they changed. A file is cut where a
+//! rolling hash of the bytes before the cut says so, FastCDC-style, rather
+//! than every so many bytes, so that an insertion only changes the chunks
+//! around it and the rest keep their hashes. The snapshot records each
+//! chunk's length and a short hash of it, never its content; a drifted
+//! file's chunks the baseline has none of are the byte ranges that changed.
+//! The whole-file hash is still what comparisons go by: chunks are only
+//! compared when both sides were cut the same way.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::utils;
+
+/// A chunk is at least this long, but for the last.
+const MIN_SIZE: usize = 16 << 10;
+/// What chunks come to on average, which sets the cut masks.
+const AVG_SIZE: usize = 64 << 10;
+const MAX_SIZE: usize = 256 << 10;
+/// Chunks recorded of one file; the last of them takes whatever of the
+/// file is left, so that a file of any size records at most this many.
+pub const MAX_CHUNKS: usize = 4096;
+/// Hex digits kept of each chunk's SHA-256: enough to tell a file's chunks
+/// apart, at a fraction of the snapshot's size.
+const HASH_LEN: usize = 16;
+/// Ranges a drift's reason lists before counting the rest.
+const SHOWN_RANGES: usize = 3;
+
+/// Harder to match before the average size and easier after it, which
+/// keeps chunks near the average (FastCDC's normalized chunking). The
+/// rolling hash shifts left, so its high bits depend on the most bytes.
+const MASK_SMALL: u64 = !0 << (64 - (AVG_SIZE.trailing_zeros() + 2));
+const MASK_LARGE: u64 = !0 << (64 - (AVG_SIZE.trailing_zeros() - 2));
+
+/// A random value for each byte, fixed so that every build cuts alike.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6a09_e667_f3bc_c908;
+    let mut i = 0;
+    while i < 256 {
+        // SplitMix64.
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// One chunk of a file, by its length and a short hash of its bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub len: u64,
+    pub hash: String,
+}
+
+/// A file's chunks, in order, and the average size it was cut for: files
+/// cut for another compare by their whole hash alone.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunks {
+    pub avg_size: u64,
+    pub list: Vec<Chunk>,
+}
+
+/// Where a drifted file's content changed, by its chunks.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkDiff {
+    /// The current file's chunks.
+    pub chunks: usize,
+    /// Those of them the baseline has none of.
+    pub changed: usize,
+    /// The baseline's chunks the current file has none of.
+    pub removed: usize,
+    /// The byte ranges of the current file the changed chunks cover,
+    /// adjacent ones merged, as `[start, end)`.
+    pub ranges: Vec<(u64, u64)>,
+}
+
+/// Cut `content` into chunks.
+pub fn split(content: &[u8]) -> Chunks {
+    split_into(content, MAX_CHUNKS)
+}
+
+/// Cut `content` into at most `most` chunks.
+fn split_into(content: &[u8], most: usize) -> Chunks {
+    let mut list = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let len = match list.len() + 1 == most {
+            true => rest.len(),
+            false => cut(rest),
+        };
+        let (chunk, after) = rest.split_at(len);
+        let mut hash = format!("{:x}", Sha256::digest(chunk));
+        hash.truncate(HASH_LEN);
+        list.push(Chunk {
+            len: len as u64,
+            hash,
+        });
+        rest = after;
+    }
+    Chunks {
+        avg_size: AVG_SIZE as u64,
+        list,
+    }
+}
+
+/// The length of the first chunk of `data`.
+fn cut(data: &[u8]) -> usize {
+    if data.len() <= MIN_SIZE {
+        return data.len();
+    }
+    let normal = AVG_SIZE.min(data.len());
+    let max = MAX_SIZE.min(data.len());
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate().take(max).skip(MIN_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    max
+}
+
+/// Where `new` differs from `old`, if they were cut alike.
+pub fn diff(old: &Chunks, new: &Chunks) -> Option<ChunkDiff> {
+    if old.avg_size != new.avg_size {
+        return None;
+    }
+    let had: std::collections::HashSet<&str> = old.list.iter().map(|c| c.hash.as_str()).collect();
+    let has: std::collections::HashSet<&str> = new.list.iter().map(|c| c.hash.as_str()).collect();
+    let mut diff = ChunkDiff {
+        chunks: new.list.len(),
+        removed: old
+            .list
+            .iter()
+            .filter(|c| !has.contains(c.hash.as_str()))
+            .count(),
+        ..ChunkDiff::default()
+    };
+    let mut offset = 0;
+    for chunk in &new.list {
+        let end = offset + chunk.len;
+        if !had.contains(chunk.hash.as_str()) {
+            diff.changed += 1;
+            match diff.ranges.last_mut() {
+                Some(range) if range.1 == offset => range.1 = end,
+                _ => diff.ranges.push((offset, end)),
+            }
+        }
+        offset = end;
+    }
+    Some(diff)
+}
+
+impl ChunkDiff {
+    /// As a drift's reason: `2 of 640 chunks differ, at 1.2 MiB–1.3 MiB`.
+    pub fn describe(&self) -> String {
+        let mut text = format!(
+            "{} of {} chunk{} differ{}",
+            self.changed,
+            self.chunks,
+            if self.chunks == 1 { "" } else { "s" },
+            if self.changed == 1 { "s" } else { "" }
+        );
+        if !self.ranges.is_empty() {
+            let shown: Vec<String> = self
+                .ranges
+                .iter()
+                .take(SHOWN_RANGES)
+                .map(|(start, end)| {
+                    format!("{}–{}", utils::human_size(*start), utils::human_size(*end))
+                })
+                .collect();
+            text.push_str(&format!(", at {}", shown.join(", ")));
+            if self.ranges.len() > SHOWN_RANGES {
+                text.push_str(&format!(" and {} more", self.ranges.len() - SHOWN_RANGES));
+            }
+        }
+        if self.changed == 0 && self.removed > 0 {
+            text.push_str(&format!(
+                ", {} removed",
+                if self.removed == 1 {
+                    "1 chunk".to_string()
+                } else {
+                    format!("{} chunks", self.removed)
+                }
+            ));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that don't repeat, as a generated file's don't.
+    fn content(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_insertion_changes_only_the_chunks_around_it() {
+        let old = content(4 << 20, 1);
+        let mut new = old.clone();
+        let at = 1 << 20;
+        new.splice(at..at, content(100, 2));
+        let (before, after) = (split(&old), split(&new));
+        assert!(before.list.len() > 16);
+        assert!(before.list.iter().all(|c| c.len as usize <= MAX_SIZE));
+        assert_eq!(
+            before.list.iter().map(|c| c.len).sum::<u64>(),
+            old.len() as u64
+        );
+
+        let diff = diff(&before, &after).unwrap();
+        assert!((1..=2).contains(&diff.changed), "{:?}", diff);
+        let (start, end) = diff.ranges[0];
+        assert!(start <= at as u64 && end > at as u64, "{:?}", diff);
+        let text = diff.describe();
+        assert!(
+            text.contains(&format!(" of {} chunks differ", diff.chunks)),
+            "{}",
+            text
+        );
+        assert!(text.contains(", at "), "{}", text);
+
+        // Cut for another size, they aren't compared.
+        let other = Chunks {
+            avg_size: 1 << 20,
+            ..after.clone()
+        };
+        assert!(super::diff(&before, &other).is_none());
+    }
+
+    #[test]
+    fn the_last_chunk_takes_the_rest_of_a_file_past_the_cap() {
+        let content = content(4 << 20, 3);
+        let chunks = split_into(&content, 4);
+        assert_eq!(chunks.list.len(), 4);
+        assert!(chunks.list[3].len as usize > MAX_SIZE);
+        assert_eq!(
+            chunks.list.iter().map(|c| c.len).sum::<u64>(),
+            content.len() as u64
+        );
+    }
+}
@@ -0,0 +1,689 @@
+//! A collector holding the baselines and drift reports of a small fleet:
+//! `serve` stores what hosts upload under a data directory, and
+//! `snapshot --upload` and `compare --report-upload` send it there.
+//!
+//! Every request needs `Authorization: Bearer <token>`:
+//!
+//! - `GET /hosts`: each host, whether it has a snapshot, and its report count
+//! - `PUT /hosts/{name}/snapshot`, `GET /hosts/{name}/snapshot`
+//! - `POST /hosts/{name}/drift` stores a report; `GET /hosts/{name}/drift`
+//!   lists their IDs, oldest first
+//! - `GET /hosts/{name}/drift/{id}`
+//! - `PUT /baselines/{profile}` stores the next version of a profile's
+//!   baseline, published with `accept --publish`; `GET /baselines/{profile}`
+//!   gives the latest, which `monitor --baseline-subscribe` asks for
+//!
+//! A host uploads as its identity ([`crate::identity`]) unless given another
+//! name, so that one that comes back under another hostname is still one
+//! host. A host's files are `{name}/snapshot.json` and
+//! `{name}/drift/{id}.json`.
+//! `report fleet` reads them in place, without the collector running. A
+//! profile's latest baseline is `.baselines/{profile}.json`, where no host
+//! can be called.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::alert::{self, Severity};
+use crate::listener::{HttpListener, Request, Response};
+use crate::monitor::{self, CancellationToken};
+use crate::publish::Published;
+use crate::{DriftReport, GuardianError, Snapshot, SnapshotMetadata, http, redact};
+
+/// Default for `serve --max-upload`.
+pub const DEFAULT_MAX_UPLOAD: u64 = 32 << 20;
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const DRIFT_DIR: &str = "drift";
+const BASELINES_DIR: &str = ".baselines";
+
+/// A drift report as uploaded, with where and when it was found.
+#[derive(Serialize, Deserialize)]
+pub struct DriftUpload {
+    pub timestamp: DateTime<Utc>,
+    pub directory: String,
+    #[serde(flatten)]
+    pub report: DriftReport,
+}
+
+/// Host names become directory names, so only those that are safe as one
+/// are accepted.
+pub fn check_host_name(name: &str) -> Result<(), String> {
+    check_name("host name", name)
+}
+
+/// Profile names become file names, as host names become directory names.
+pub fn check_profile_name(name: &str) -> Result<(), String> {
+    check_name("profile name", name)
+}
+
+fn check_name(what: &str, name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 253
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid {} '{}' (use letters, digits, '-', '_' and '.', not starting with '.')",
+            what, name
+        ))
+    }
+}
+
+/// Where uploads go and as which host.
+#[derive(Clone)]
+pub struct Upload {
+    /// The collector's base URL, e.g. `http://collector:8080`.
+    pub url: String,
+    pub host: String,
+    pub token: String,
+}
+
+impl Upload {
+    pub fn snapshot(&self, snapshot: &Snapshot) -> Result<(), String> {
+        let body = serde_json::to_value(snapshot).expect("snapshots serialize to JSON");
+        self.send("PUT", "snapshot", &body)
+    }
+
+    pub fn report(&self, dir: &str, report: &DriftReport) -> Result<(), String> {
+        let upload = DriftUpload {
+            timestamp: Utc::now(),
+            directory: dir.to_string(),
+            report: redact::report(report),
+        };
+        let body = serde_json::to_value(&upload).expect("reports serialize to JSON");
+        self.send("POST", DRIFT_DIR, &body)
+    }
+
+    fn send(&self, method: &str, what: &str, body: &serde_json::Value) -> Result<(), String> {
+        let url = format!(
+            "{}/hosts/{}/{}",
+            self.url.trim_end_matches('/'),
+            self.host,
+            what
+        );
+        let auth = [(
+            "Authorization".to_string(),
+            format!("Bearer {}", self.token),
+        )];
+        http::send_json(method, &url, &auth, UPLOAD_TIMEOUT, body)
+    }
+}
+
+/// Serve the collector on `listen` until Ctrl+C or SIGTERM, storing uploads
+/// under `data_dir` and refusing bodies over `max_upload` bytes.
+pub fn serve(
+    listen: SocketAddr,
+    data_dir: &Path,
+    token: String,
+    max_upload: u64,
+) -> Result<(), GuardianError> {
+    if token.trim().is_empty() {
+        return Err(GuardianError::Config(
+            "The collector token must not be empty.".into(),
+        ));
+    }
+    fs::create_dir_all(data_dir).map_err(GuardianError::io(data_dir))?;
+    let collector = Collector {
+        data_dir: data_dir.to_path_buf(),
+        token,
+        publishing: Mutex::new(()),
+    };
+    let _listener = HttpListener::serve(listen, max_upload, move |request| {
+        collector.handle(request).into()
+    })
+    .map_err(|e| GuardianError::Other(format!("Could not listen on {}: {}", listen, e)))?;
+    println!(
+        "Collecting snapshots and drift reports on http://{} into {}",
+        listen,
+        data_dir.display()
+    );
+    log::info!("Collector listening on {}", listen);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| GuardianError::Other(format!("Could not start the collector: {}", e)))?;
+    runtime.block_on(monitor::cancel_on_signal(CancellationToken::new()));
+    log::info!("Collector stopped");
+    Ok(())
+}
+
+struct Collector {
+    data_dir: PathBuf,
+    token: String,
+    /// Held while a baseline is published, so that two hosts publishing at
+    /// once can't both take the same version.
+    publishing: Mutex<()>,
+}
+
+impl Collector {
+    fn handle(&self, request: &Request) -> Response {
+        if !self.authorized(request) {
+            return Response::text(401, "A valid bearer token is required");
+        }
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let method = request.method.as_str();
+        let (host, rest) = match segments.as_slice() {
+            ["hosts"] if method == "GET" => return self.hosts(),
+            ["hosts"] => return Response::text(405, "Only GET is supported"),
+            ["hosts", host, rest @ ..] => (*host, rest),
+            ["baselines", profile] => return self.baseline(method, profile, &request.body),
+            _ => return Response::not_found(),
+        };
+        if let Err(e) = check_host_name(host) {
+            return Response::text(400, e);
+        }
+        let dir = self.data_dir.join(host);
+        match (method, rest) {
+            ("PUT", ["snapshot"]) => put_snapshot(host, &dir, &request.body),
+            ("GET", ["snapshot"]) => stored(&dir.join(SNAPSHOT_FILE)),
+            ("POST", ["drift"]) => post_drift(host, &dir, &request.body),
+            ("GET", ["drift"]) => json_ok(json!({ "host": host, "reports": report_ids(&dir) })),
+            ("GET", ["drift", id]) if is_report_id(id) => {
+                stored(&dir.join(DRIFT_DIR).join(format!("{}.json", id)))
+            }
+            (_, ["snapshot"] | ["drift"]) => Response::text(405, "Method not allowed here"),
+            _ => Response::not_found(),
+        }
+    }
+
+    /// Whether the request carries the token. Compared in constant time, so
+    /// response times don't tell how much of a guess was right.
+    fn authorized(&self, request: &Request) -> bool {
+        let Some(given) = request
+            .header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        given.len() == self.token.len()
+            && given
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    fn baseline(&self, method: &str, profile: &str, body: &[u8]) -> Response {
+        if let Err(e) = check_profile_name(profile) {
+            return Response::text(400, e);
+        }
+        let path = self
+            .data_dir
+            .join(BASELINES_DIR)
+            .join(format!("{}.json", profile));
+        match method {
+            "GET" => stored(&path),
+            "PUT" => {
+                let _publishing = self.publishing.lock().unwrap_or_else(|e| e.into_inner());
+                put_baseline(profile, &path, body)
+            }
+            _ => Response::text(405, "Only GET and PUT are supported"),
+        }
+    }
+
+    fn hosts(&self) -> Response {
+        let hosts: Vec<_> = host_names(&self.data_dir)
+            .unwrap_or_default()
+            .iter()
+            .map(|name| {
+                let dir = self.data_dir.join(name);
+                json!({
+                    "name": name,
+                    "snapshot": dir.join(SNAPSHOT_FILE).is_file(),
+                    "reports": report_ids(&dir).len(),
+                })
+            })
+            .collect();
+        json_ok(json!({ "hosts": hosts }))
+    }
+}
+
+/// The hosts with a directory under `data_dir`, by name.
+fn host_names(data_dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(data_dir)?
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| check_host_name(name).is_ok())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// A host as `report fleet` shows it: its latest report for each directory,
+/// rolled up.
+#[derive(Serialize)]
+pub struct HostSummary {
+    /// The name it uploads as: its identity, unless given another.
+    pub host: String,
+    /// The hostname of the machine that sent the latest report, where the
+    /// report says.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// The directories those reports are of.
+    pub directories: Vec<String>,
+    /// When the collector received the latest of them.
+    pub last_report: Option<DateTime<Utc>>,
+    /// Whole days since the oldest of their baselines was taken, where the
+    /// reports say.
+    pub baseline_age_days: Option<u64>,
+    /// Drifted files by kind.
+    pub drifts: BTreeMap<&'static str, usize>,
+    /// The most serious drift's severity, as this host's rules rate it.
+    pub worst: Option<Severity>,
+    /// It sent no report within `--stale-after`.
+    pub missing: bool,
+    /// Report files passed over because they couldn't be read or parsed,
+    /// with why.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+/// Roll up the reports stored under `data_dir` per host, flagging hosts
+/// that sent none within `stale_after` as missing.
+pub fn fleet(data_dir: &Path, stale_after: Duration) -> Result<Vec<HostSummary>, GuardianError> {
+    let now = Utc::now();
+    let stale_after = chrono::Duration::from_std(stale_after).unwrap_or(chrono::Duration::MAX);
+    Ok(host_names(data_dir)
+        .map_err(GuardianError::io(data_dir))?
+        .into_iter()
+        .map(|host| summarize(&data_dir.join(&host), host, now, stale_after))
+        .collect())
+}
+
+/// Read `host`'s reports from the newest back until every directory its
+/// snapshot covers has one, or they are older than `stale_after` before
+/// the newest: a directory reported on no later than that is no longer
+/// compared. A file that can't be parsed, as one copied in half-written
+/// would be, is passed over for the one before it.
+fn summarize(
+    dir: &Path,
+    host: String,
+    now: DateTime<Utc>,
+    stale_after: chrono::Duration,
+) -> HostSummary {
+    let expected = SnapshotMetadata::load(&dir.join(SNAPSHOT_FILE))
+        .ok()
+        .map(|metadata| metadata.directories);
+    let mut latest: BTreeMap<String, (DateTime<Utc>, DriftUpload)> = BTreeMap::new();
+    let mut newest = None;
+    let mut skipped = Vec::new();
+    for id in report_ids(dir).into_iter().rev() {
+        let Some(received) = received_at(&id) else {
+            continue;
+        };
+        if newest.is_some_and(|newest| newest - received > stale_after) {
+            break;
+        }
+        let path = dir.join(DRIFT_DIR).join(format!("{}.json", id));
+        let upload = fs::read(&path).map_err(|e| e.to_string()).and_then(|body| {
+            serde_json::from_slice::<DriftUpload>(&body).map_err(|e| e.to_string())
+        });
+        let upload = match upload {
+            Ok(upload) => upload,
+            Err(e) => {
+                skipped.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        newest.get_or_insert(received);
+        latest
+            .entry(upload.directory.clone())
+            .or_insert((received, upload));
+        if expected
+            .as_ref()
+            .is_some_and(|dirs| dirs.iter().all(|d| latest.contains_key(d)))
+        {
+            break;
+        }
+    }
+    let mut drifts = BTreeMap::new();
+    let mut worst = None;
+    for (_, upload) in latest.values() {
+        for drift in &upload.report.drifts {
+            *drifts.entry(drift.kind.as_str()).or_default() += 1;
+            worst = worst.max(Some(alert::severity_of(drift)));
+        }
+    }
+    // Reports give the baseline's age when they were made.
+    let baseline_age_days = latest
+        .values()
+        .filter_map(|(received, upload)| {
+            let since = (now - *received).num_days().max(0) as u64;
+            upload.report.baseline_age_days.map(|days| days + since)
+        })
+        .max();
+    let hostname = latest
+        .values()
+        .max_by_key(|(received, _)| *received)
+        .and_then(|(_, upload)| upload.report.invoker.as_ref())
+        .map(|invoker| invoker.host.clone());
+    HostSummary {
+        host,
+        hostname,
+        directories: latest.keys().cloned().collect(),
+        last_report: newest,
+        baseline_age_days,
+        drifts,
+        worst,
+        missing: newest.is_none_or(|newest| now - newest > stale_after),
+        skipped,
+    }
+}
+
+/// When the collector received the report `id`, which is that time.
+fn received_at(id: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(id, "%Y%m%dT%H%M%S%.fZ")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+fn put_snapshot(host: &str, dir: &Path, body: &[u8]) -> Response {
+    let snapshot: Snapshot = match serde_json::from_slice(body) {
+        Ok(snapshot) => snapshot,
+        Err(e) => return Response::text(400, format!("The body is not a snapshot: {}", e)),
+    };
+    if let Err(response) = store(&dir.join(SNAPSHOT_FILE), body) {
+        return response;
+    }
+    log::info!(
+        event = "collector_snapshot",
+        host = host,
+        files = snapshot.entries.len();
+        "Stored the snapshot of {} ({} files)", host, snapshot.entries.len()
+    );
+    json_ok(json!({ "host": host, "files": snapshot.entries.len() }))
+}
+
+/// Store the next version of `profile`'s baseline. The signature is left
+/// to the subscribers, who have the key; what the collector checks is that
+/// the version is the one after the latest, so that hosts publishing at
+/// once, or from an old copy, learn of it rather than undo each other.
+fn put_baseline(profile: &str, path: &Path, body: &[u8]) -> Response {
+    let published: Published = match serde_json::from_slice(body) {
+        Ok(published) => published,
+        Err(e) => return Response::text(400, format!("The body is not a baseline: {}", e)),
+    };
+    if published.profile != profile {
+        return Response::text(
+            400,
+            format!(
+                "The baseline is for profile {}, not {}",
+                published.profile, profile
+            ),
+        );
+    }
+    let latest = fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<Published>(&data).ok())
+        .map_or(0, |latest| latest.version);
+    if published.version != latest + 1 {
+        log::warn!(
+            event = "collector_baseline_conflict",
+            profile = profile,
+            version = published.version,
+            latest = latest,
+            host = published.host.as_str();
+            "Refused version {} of the {} baseline from {}: the latest is version {}",
+            published.version, profile, published.host, latest
+        );
+        return Response::text(
+            409,
+            format!(
+                "The latest {} baseline is version {}, so the next is {}, not {}",
+                profile,
+                latest,
+                latest + 1,
+                published.version
+            ),
+        );
+    }
+    if let Err(response) = store(path, body) {
+        return response;
+    }
+    log::info!(
+        event = "collector_baseline",
+        profile = profile,
+        version = published.version,
+        host = published.host.as_str();
+        "Stored version {} of the {} baseline, published from {}",
+        published.version, profile, published.host
+    );
+    json_ok(json!({ "profile": profile, "version": published.version }))
+}
+
+fn post_drift(host: &str, dir: &Path, body: &[u8]) -> Response {
+    let upload: DriftUpload = match serde_json::from_slice(body) {
+        Ok(upload) => upload,
+        Err(e) => return Response::text(400, format!("The body is not a drift report: {}", e)),
+    };
+    let id = Utc::now().format("%Y%m%dT%H%M%S%.6fZ").to_string();
+    if let Err(response) = store(&dir.join(DRIFT_DIR).join(format!("{}.json", id)), body) {
+        return response;
+    }
+    log::info!(
+        event = "collector_drift",
+        host = host,
+        dir = upload.directory.as_str(),
+        drifts = upload.report.drifts.len();
+        "Stored a drift report from {} for {} ({} drifted files)",
+        host, upload.directory, upload.report.drifts.len()
+    );
+    Response::json(201, &json!({ "host": host, "id": id }))
+}
+
+/// Write `body` to `path` through a temporary file, so readers never see
+/// half an upload.
+fn store(path: &Path, body: &[u8]) -> Result<(), Response> {
+    let temporary = path.with_extension("json.tmp");
+    path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&temporary, body))
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|e| {
+            log::error!("Could not store {}: {}", path.display(), e);
+            Response::text(500, "The upload could not be stored")
+        })
+}
+
+fn stored(path: &Path) -> Response {
+    match fs::read_to_string(path) {
+        Ok(body) => Response {
+            status: 200,
+            content_type: "application/json",
+            body,
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Response::not_found(),
+        Err(e) => {
+            log::error!("Could not read {}: {}", path.display(), e);
+            Response::text(500, "The file could not be read")
+        }
+    }
+}
+
+fn report_ids(dir: &Path) -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(dir.join(DRIFT_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_suffix(".json")
+                .filter(|id| is_report_id(id))
+                .map(str::to_string)
+        })
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// IDs are UTC timestamps such as `20240501T143000.123456Z`.
+fn is_report_id(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.')
+}
+
+fn json_ok(value: serde_json::Value) -> Response {
+    Response::json(200, &value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, token: Option<&str>, body: &str) -> Request {
+        Request {
+            method: method.into(),
+            path: path.into(),
+            headers: token
+                .map(|t| ("authorization".to_string(), format!("Bearer {}", t)))
+                .into_iter()
+                .collect(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn uploads_are_stored_per_host_behind_the_token() {
+        let data_dir =
+            std::env::temp_dir().join(format!("guardian-collector-{}", std::process::id()));
+        let collector = Collector {
+            data_dir: data_dir.clone(),
+            token: "s3cret".into(),
+            publishing: Mutex::new(()),
+        };
+        let snapshot = r#"{"metadata": {}, "entries": [{"path": "a.conf", "hash": "1"}]}"#;
+        let status = |r: Request| collector.handle(&r).status;
+
+        assert_eq!(status(request("GET", "/hosts", None, "")), 401);
+        assert_eq!(status(request("GET", "/hosts", Some("s3cre"), "")), 401);
+        assert_eq!(status(request("GET", "/hosts", Some("s3cret!"), "")), 401);
+        let put = |host: &str, body| {
+            request(
+                "PUT",
+                &format!("/hosts/{}/snapshot", host),
+                Some("s3cret"),
+                body,
+            )
+        };
+        assert_eq!(status(put("..", snapshot)), 400);
+        assert_eq!(status(put("web-01", "{}")), 400);
+        assert_eq!(status(put("web-01", snapshot)), 200);
+
+        let report = r#"{"timestamp": "2024-05-01T12:00:00Z", "directory": "/etc",
+            "drifts": [], "files_tracked": 1}"#;
+        let posted = collector.handle(&request(
+            "POST",
+            "/hosts/web-01/drift",
+            Some("s3cret"),
+            report,
+        ));
+        assert_eq!(posted.status, 201);
+        let id = serde_json::from_str::<serde_json::Value>(&posted.body).unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let get = |path: &str| collector.handle(&request("GET", path, Some("s3cret"), ""));
+        let hosts: serde_json::Value = serde_json::from_str(&get("/hosts").body).unwrap();
+        assert_eq!(
+            hosts["hosts"],
+            json!([{ "name": "web-01", "snapshot": true, "reports": 1 }])
+        );
+        assert_eq!(get("/hosts/web-01/snapshot").body, snapshot);
+        assert_eq!(get(&format!("/hosts/web-01/drift/{}", id)).body, report);
+        assert_eq!(get("/hosts/web-02/snapshot").status, 404);
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn baselines_are_published_one_version_after_another() {
+        let data_dir =
+            std::env::temp_dir().join(format!("guardian-baselines-{}", std::process::id()));
+        let collector = Collector {
+            data_dir: data_dir.clone(),
+            token: "s3cret".into(),
+            publishing: Mutex::new(()),
+        };
+        let put = |profile: &str, version: u64| {
+            let published = Published::new("nginx", version, "web-01", &Snapshot::default(), "k");
+            let body = serde_json::to_string(&published).unwrap();
+            let path = format!("/baselines/{}", profile);
+            collector.handle(&request("PUT", &path, Some("s3cret"), &body))
+        };
+        let get = || collector.handle(&request("GET", "/baselines/nginx", Some("s3cret"), ""));
+
+        assert_eq!(get().status, 404);
+        assert_eq!(put("nginx", 2).status, 409);
+        assert_eq!(put("nginx", 1).status, 200);
+        let conflict = put("nginx", 1);
+        assert_eq!(conflict.status, 409);
+        assert!(conflict.body.contains("the next is 2"), "{}", conflict.body);
+        assert_eq!(put("nginx", 2).status, 200);
+        assert_eq!(put("sshd", 3).status, 400);
+        assert_eq!(put("..", 3).status, 400);
+
+        let latest: Published = serde_json::from_str(&get().body).unwrap();
+        assert_eq!(latest.version, 2);
+        assert!(latest.verify("k").is_ok());
+        // Not a host.
+        assert!(host_names(&data_dir).unwrap().is_empty());
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn fleet_rolls_up_the_latest_good_report_of_each_directory() {
+        let data_dir = std::env::temp_dir().join(format!("guardian-fleet-{}", std::process::id()));
+        let drift = data_dir.join("web-01").join(DRIFT_DIR);
+        fs::create_dir_all(&drift).unwrap();
+        fs::create_dir_all(data_dir.join("db-01")).unwrap();
+        let now = Utc::now();
+        let report = |ago: i64, dir: &str, kinds: &[&str]| {
+            let id = (now - chrono::Duration::minutes(ago)).format("%Y%m%dT%H%M%S%.6fZ");
+            let drifts: Vec<_> = kinds
+                .iter()
+                .map(|kind| json!({ "kind": kind, "path": format!("{}/a.conf", dir) }))
+                .collect();
+            let body = json!({ "timestamp": now, "directory": dir, "drifts": drifts,
+                "files_tracked": 1, "baseline_age_days": 3 });
+            fs::write(drift.join(format!("{}.json", id)), body.to_string()).unwrap();
+        };
+        report(30, "/etc", &["changed", "deleted"]);
+        report(20, "/opt/app", &["new_setuid"]);
+        report(10, "/etc", &[]);
+        // Half-written, so the report before it stands.
+        let id = (now - chrono::Duration::minutes(5)).format("%Y%m%dT%H%M%S%.6fZ");
+        fs::write(drift.join(format!("{}.json", id)), "{\"timestamp\":").unwrap();
+
+        let hosts = fleet(&data_dir, Duration::from_secs(3600)).unwrap();
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0].host, "db-01");
+        assert!(hosts[0].missing && hosts[0].last_report.is_none());
+        let web = &hosts[1];
+        assert!(!web.missing);
+        assert_eq!(web.directories, ["/etc", "/opt/app"]);
+        assert_eq!(web.drifts, BTreeMap::from([("new_setuid", 1)]));
+        assert_eq!(web.worst, Some(Severity::Critical));
+        assert_eq!(web.baseline_age_days, Some(3));
+        assert_eq!(web.skipped.len(), 1);
+
+        // Reports that much older than the newest are no longer current.
+        let hosts = fleet(&data_dir, Duration::from_secs(300)).unwrap();
+        assert_eq!(hosts[1].directories, ["/etc"]);
+        assert!(hosts[1].missing);
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
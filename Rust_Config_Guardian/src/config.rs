@@ -0,0 +1,2654 @@
+//! The optional `guardian.toml` config file.
+//!
+//! Every setting is taken from, in order: its command-line flag, its
+//! `GUARDIAN_*` environment variable, the config file, and the built-in
+//! default. The file is `--config <PATH>` when given, otherwise the first of
+//! `./guardian.toml` and the platform config directory's
+//! `config-guardian/guardian.toml` that exists.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::alert::{AlertChannels, AlertLimits, LISTING_CHANNELS, Severity};
+use crate::annotation::ANNOTATIONS_FILE;
+use crate::archive::Archive;
+use crate::cache::CACHE_FILE;
+use crate::collector::check_host_name;
+use crate::content::CONTENT_DIR;
+use crate::digest::{DIGEST_FILE, DigestPeriod, SENDING_FILE, parse_time};
+use crate::drift;
+use crate::email::{DEFAULT_SUBJECT, EmailConfig, SmtpTls, credentials_from_env, parse_mailbox};
+use crate::exception::EXCEPTIONS_FILE;
+use crate::history::{HISTORY_FILE, RUNS_FILE};
+use crate::http;
+use crate::identity::{self, INSTANCE_ID_FILE, IdentitySource};
+use crate::normalize::{self, Normalizer};
+use crate::pagerduty::{INCIDENTS_FILE, PagerDutyConfig};
+use crate::seal;
+use crate::slack::SlackConfig;
+use crate::snapshot::ScanOptions;
+use crate::teams::TeamsConfig;
+use crate::telegram::TelegramConfig;
+use crate::throttle::{self, Throttle};
+use crate::utils::{self, format_size, parse_duration_or_secs, parse_percent, parse_size};
+use crate::webhook::{self, WebhookConfig};
+use crate::{Drift, DriftKind, SNAPSHOT_FILE, partial_path};
+
+pub const CONFIG_FILE: &str = "guardian.toml";
+pub const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Files a scan of one directory may find before it gives up.
+pub const DEFAULT_MAX_FILES: usize = 250_000;
+/// Retries of a read that failed with a transient error, and the wait
+/// before the first.
+pub const DEFAULT_RETRIES: u32 = 2;
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Percentage of the files that look unchanged a comparison reads anyway.
+pub const DEFAULT_VERIFY_SAMPLE: f64 = 1.0;
+/// How far ahead of the clock a modification time may be before it's taken
+/// as skewed.
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+/// What a sampled hash (see `sample_hash`) reads at each end of a file.
+pub const SAMPLE_HASH_SPAN: u64 = 8 << 20;
+/// Shown by `config show` in place of secrets.
+pub(crate) const MASK: &str = "********";
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Directories used when a command is given none.
+    #[serde(default)]
+    pub directories: Vec<String>,
+    /// Glob patterns for files to leave out, matched against the file name
+    /// and the full path.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Glob patterns for entries to leave out of the baseline as it is
+    /// loaded for a comparison, such as parts of a snapshot taken elsewhere
+    /// that aren't wanted here; matched as `exclude` is, and also against
+    /// the path under the directory compared. Scans aren't affected.
+    #[serde(default)]
+    pub baseline_exclude: Vec<String>,
+    /// Glob patterns for files whose names are kept out of reports, alerts,
+    /// logs and the history, matched as `exclude` is. Their content is
+    /// still compared.
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Glob patterns for the directories new executables are expected in,
+    /// where they are reported as any new file.
+    #[serde(default)]
+    pub expect_executables: Vec<String>,
+    /// Where the snapshot is stored (default: snapshot.json).
+    pub snapshot: Option<PathBuf>,
+    #[serde(default)]
+    pub hash: HashAlgorithm,
+    #[serde(default)]
+    pub hash_display: HashDisplay,
+    /// Show times in UTC rather than the local time zone.
+    #[serde(default)]
+    pub utc: bool,
+    /// Show sizes in binary (KiB, MiB) or SI (kB, MB) units.
+    #[serde(default)]
+    pub size_units: SizeUnits,
+    /// Group digits and mark decimals as this locale does, e.g. "de-DE".
+    pub locale: Option<Locale>,
+    /// Record which mount each file is on, to report files mounted over
+    /// (Linux only).
+    #[serde(default)]
+    pub detect_mounts: bool,
+    /// Record immutable and append-only attributes and report changes to
+    /// them (Linux only).
+    #[serde(default)]
+    pub attrs: bool,
+    /// Record POSIX ACLs and report changes to them (Linux only).
+    #[serde(default)]
+    pub acls: bool,
+    /// Tag files that grow while hashed, or look like logs, as volatile and
+    /// leave their changes out of drift.
+    #[serde(default)]
+    pub skip_growing: bool,
+    /// Report changes to volatile files as drift after all.
+    #[serde(default)]
+    pub include_volatile: bool,
+    /// Report unreadable files apart from drift, so they don't alert or
+    /// count as drift.
+    #[serde(default)]
+    pub ignore_unreadable: bool,
+    /// What a file's metadata may differ in without that being drift, such
+    /// as `["uid", "gid"]` where containers remap IDs.
+    #[serde(default)]
+    pub ignore_fields: Vec<Field>,
+    /// `ignore_fields` for the paths a glob matches, in place of the file's
+    /// list, as `ignore = { "var/run/**" = ["mode"] }`; `[]` ignores
+    /// nothing there. A path several globs match ignores what any of them
+    /// lists.
+    #[serde(default)]
+    pub ignore: BTreeMap<String, Vec<Field>>,
+    /// Whether a file's owner and group are compared by name, so that
+    /// hosts giving an account different IDs agree, or by ID.
+    #[serde(default)]
+    pub compare_ownership_by: Ownership,
+    /// Scan the snapshot, log and other files config-guardian writes, like
+    /// any other file, rather than leaving them out.
+    #[serde(default)]
+    pub track_self: bool,
+    /// Scan a directory given by a symlink through it, rather than refuse.
+    #[serde(default)]
+    pub follow_root_symlink: bool,
+    /// Stop a scan that finds more files than this in one directory
+    /// (default: 250000; 0 for no limit).
+    pub max_files: Option<usize>,
+    /// Files a scan reads and hashes at once (default: one per CPU, up to
+    /// 8, or 1 with `nice`).
+    pub jobs: Option<usize>,
+    /// Throttle scans for a busy host: read one file at a time, at most
+    /// 20 MiB/s and half a CPU, and lower the process's priority.
+    #[serde(default)]
+    pub nice: bool,
+    /// Cap the bandwidth of a scan's reads, in MiB/s.
+    pub io_throttle: Option<f64>,
+    /// Cap the share of a CPU a scan's reads and hashing take, e.g. "25%".
+    #[serde(default, deserialize_with = "percent", serialize_with = "show_percent")]
+    pub cpu_limit: Option<f64>,
+    /// Give up on reading a file after this long, as on a hung network
+    /// mount, and report it unreadable.
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub read_timeout: Option<Duration>,
+    /// Read a file again, up to this many times, when reading it fails with
+    /// one of the `retry_on` errors (default: 2).
+    pub retries: Option<u32>,
+    /// Wait before the first retry, doubled before each one after it
+    /// (default: 100ms).
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub retry_delay: Option<Duration>,
+    /// The OS errors worth retrying, by name (`EIO`, `ESTALE`, ...) or number
+    /// (default: the transient ones network filesystems give).
+    pub retry_on: Option<Vec<String>>,
+    /// Read every file in comparisons, rather than only those whose size
+    /// or modification time changed and a sample of the rest.
+    #[serde(default)]
+    pub paranoid: bool,
+    /// Read every file rather than take hashes from the hash cache.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Keep the content of the files snapshots and `accept` record, so
+    /// that `accept` can say how many lines changed.
+    #[serde(default)]
+    pub keep_content: bool,
+    /// Refuse a snapshot with broken entries rather than drop them.
+    #[serde(default)]
+    pub strict_load: bool,
+    /// Warn when a directory's baseline is older than this, e.g. "90d".
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub max_baseline_age: Option<Duration>,
+    /// Fail rather than warn when the baseline is older than
+    /// `max_baseline_age`.
+    #[serde(default)]
+    pub require_fresh_baseline: bool,
+    /// How far in the future a file's modification time may be before it's
+    /// warned about and the file always read, e.g. "5m" (the default).
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub clock_skew: Option<Duration>,
+    /// The sample: the percentage of files that look unchanged that
+    /// comparisons read anyway, e.g. "1%" (the default).
+    #[serde(default, deserialize_with = "percent", serialize_with = "show_percent")]
+    pub verify_sample: Option<f64>,
+    /// Hash files larger than this, e.g. "1G", over their first and last
+    /// 8 MiB and their length only, rather than all of their content.
+    #[serde(default, deserialize_with = "size", serialize_with = "show_size")]
+    pub sample_hash: Option<u64>,
+    /// Glob patterns for large files to record content-defined chunks of,
+    /// matched as `exclude` is, so that drift to them says which byte
+    /// ranges changed.
+    #[serde(default)]
+    pub chunked_hash: Vec<String>,
+    /// Kernel tunables and other files of `/proc` and `/sys` to track
+    /// beside the directories: each is scanned on its own, with one bounded
+    /// read, and drift to it shows the old and new values.
+    #[serde(default)]
+    pub virtual_files: Vec<String>,
+    /// Commands to pipe the files matching each glob through before they
+    /// are hashed, hashing what they print: `"*.vconf" = "vendor-tool
+    /// --canonical"`. Only taken from a config file others can't write to.
+    #[serde(default)]
+    pub normalize: BTreeMap<String, String>,
+    /// How long a normalizer may take over one file before the file is
+    /// hashed as it is (default: 10s).
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub normalize_timeout: Option<Duration>,
+    #[serde(default)]
+    pub sftp: SftpSettings,
+    #[serde(default)]
+    pub alerts: AlertSettings,
+    /// Severity overrides, first match wins.
+    #[serde(default)]
+    pub severity: Vec<SeverityRule>,
+    /// What each drift adds to a comparison's score, by its severity.
+    #[serde(default)]
+    pub score: ScoreWeights,
+    /// The Nagios state `compare --nagios` reports drift in.
+    #[serde(default)]
+    pub nagios: NagiosSettings,
+    /// This machine's identity, as the collector files its uploads under
+    /// and reports, snapshots and alerts name it, in place of one found
+    /// through `instance_id_sources`.
+    pub instance_id: Option<String>,
+    /// Where the identity comes from, the first that gives one: "cloud",
+    /// "stored" or "hostname" (default: ["cloud", "stored"], or ["stored"]
+    /// in a build without the cloud-identity feature).
+    pub instance_id_sources: Option<Vec<IdentitySource>>,
+    #[serde(default)]
+    pub monitor: MonitorSettings,
+    /// Named sets of directories, excludes, severity rules and alerts, each
+    /// a `[profile.NAME]` section, selected with `--profile NAME`.
+    #[serde(default, rename = "profile")]
+    pub profiles: BTreeMap<String, ProfileSettings>,
+    /// Alert channels defined once, each a `[channel.NAME]` section, for
+    /// profiles to send theirs to by name.
+    #[serde(default, rename = "channel")]
+    pub channels: BTreeMap<String, AlertSettings>,
+}
+
+/// A `[profile.NAME]` section: one thing to watch, such as nginx or sshd,
+/// with what sets it apart from the rest of the file.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSettings {
+    pub description: Option<String>,
+    #[serde(default)]
+    pub directories: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub severity: Vec<SeverityRule>,
+    pub snapshot: Option<PathBuf>,
+    pub alerts: Option<AlertSettings>,
+    /// `[channel.NAME]` sections the profile's alerts also go to.
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+impl ProfileSettings {
+    /// Apply the section to `config`. Its directories, snapshot and alerts
+    /// replace the file's where given; its excludes are added to the
+    /// file's, and its severity rules come first, so they win where both
+    /// match. Fails when it names a channel `config` doesn't define.
+    pub fn apply(&self, config: &mut Config) -> Result<(), String> {
+        if !self.directories.is_empty() {
+            config.directories = self.directories.clone();
+        }
+        config.exclude.extend(self.exclude.iter().cloned());
+        config.severity.splice(0..0, self.severity.iter().cloned());
+        if self.snapshot.is_some() {
+            config.snapshot = self.snapshot.clone();
+        }
+        if let Some(alerts) = self.alerts(&config.channels)? {
+            config.alerts = alerts;
+        }
+        Ok(())
+    }
+
+    /// The profile's own alerts together with those of the channels it
+    /// names, or `None` when it has neither.
+    pub fn alerts(
+        &self,
+        channels: &BTreeMap<String, AlertSettings>,
+    ) -> Result<Option<AlertSettings>, String> {
+        if self.channels.is_empty() {
+            return Ok(self.alerts.clone());
+        }
+        let mut alerts = self.alerts.clone().unwrap_or_default();
+        for name in &self.channels {
+            let channel = channels.get(name).ok_or_else(|| {
+                format!(
+                    "channel '{}' is not defined; add a [channel.{}] section",
+                    name, name
+                )
+            })?;
+            alerts.add(name, channel)?;
+        }
+        Ok(Some(alerts))
+    }
+}
+
+/// How file contents are hashed. Changing it makes every file drift once,
+/// so take a new snapshot afterwards.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Length of a hash in hex characters.
+    pub fn hex_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 64,
+            HashAlgorithm::Sha512 => 128,
+        }
+    }
+
+    pub fn digest(self, content: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha256 => format!("{:x}", Sha256::digest(content)),
+            HashAlgorithm::Sha512 => format!("{:x}", Sha512::digest(content)),
+        }
+    }
+
+    /// [`HashAlgorithm::digest`] of what `reader` yields, read a buffer at a
+    /// time, and how many bytes that was.
+    pub fn digest_reader(self, reader: &mut impl std::io::Read) -> std::io::Result<(String, u64)> {
+        fn stream<D: Digest>(reader: &mut impl std::io::Read) -> std::io::Result<(String, u64)> {
+            let mut digest = D::new();
+            let mut buffer = vec![0; 64 * 1024];
+            let mut total = 0;
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        digest.update(&buffer[..n]);
+                        total += n as u64;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            let hex = digest
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            Ok((hex, total))
+        }
+        match self {
+            HashAlgorithm::Sha256 => stream::<Sha256>(reader),
+            HashAlgorithm::Sha512 => stream::<Sha512>(reader),
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+        })
+    }
+}
+
+/// Hex characters kept by [`HashDisplay::Short`].
+pub const SHORT_HASH_LEN: usize = 12;
+
+/// How hashes are shown in text output. JSON output and the snapshot always
+/// keep the full hash.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashDisplay {
+    /// The first 12 hex characters.
+    #[default]
+    Short,
+    Full,
+    /// Leave hashes out.
+    None,
+}
+
+impl HashDisplay {
+    /// `hash` as it should be shown, `-` for a file that has none, or `None`
+    /// when hashes are left out.
+    pub fn show(self, hash: Option<&str>) -> Option<&str> {
+        match (self, hash) {
+            (HashDisplay::None, _) => None,
+            (_, None) => Some("-"),
+            (HashDisplay::Short, Some(hash)) => Some(&hash[..hash.len().min(SHORT_HASH_LEN)]),
+            (HashDisplay::Full, Some(hash)) => Some(hash),
+        }
+    }
+}
+
+/// The units text output shows sizes in. JSON and CSV output always give
+/// sizes in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeUnits {
+    /// Powers of 1024: KiB, MiB, GiB, TiB.
+    #[default]
+    Binary,
+    /// Powers of 1000: kB, MB, GB, TB.
+    Si,
+}
+
+/// How a locale writes numbers in text output: what goes between groups of
+/// three digits, and what marks the decimals. Only the language (and, for
+/// Switzerland and Liechtenstein, the region) of its tag matter. Without
+/// one, digits aren't grouped, as in JSON and CSV output.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Locale {
+    tag: String,
+    pub group: Option<char>,
+    pub decimal: char,
+}
+
+/// Languages by how they write 1234567.8, the others being as for `en`.
+const COMMA_DECIMALS: &[&str] = &[
+    "da", "de", "el", "es", "hr", "id", "is", "it", "nl", "pt", "ro", "sl", "sr", "tr", "vi",
+];
+const SPACE_GROUPS: &[&str] = &[
+    "bg", "cs", "et", "fi", "fr", "hu", "lt", "lv", "nb", "nn", "no", "pl", "ru", "sk", "sv", "uk",
+];
+const POINT_DECIMALS: &[&str] = &["en", "ga", "he", "hi", "ja", "ko", "ms", "mt", "th", "zh"];
+
+impl Locale {
+    /// Read a tag such as `de`, `de-CH`, `fr_FR.UTF-8` or `C`.
+    pub fn parse(value: &str) -> Result<Locale, String> {
+        let tag = value.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = tag.split(['-', '_']);
+        let language = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let region = parts.next().unwrap_or_default().to_ascii_uppercase();
+        let (group, decimal) = match language.as_str() {
+            "c" | "posix" => (None, '.'),
+            "de" | "it" | "fr" | "rm" if matches!(region.as_str(), "CH" | "LI") => {
+                (Some('\u{2019}'), '.')
+            }
+            language if COMMA_DECIMALS.contains(&language) => (Some('.'), ','),
+            // A no-break space, so that a number isn't wrapped apart.
+            language if SPACE_GROUPS.contains(&language) => (Some('\u{a0}'), ','),
+            language if POINT_DECIMALS.contains(&language) => (Some(','), '.'),
+            _ => {
+                return Err(format!(
+                    "unknown locale '{}' (expected a language tag such as en-US, de-DE, fr-FR or de-CH, or C for no grouping)",
+                    value
+                ));
+            }
+        };
+        Ok(Locale {
+            tag: value.to_string(),
+            group,
+            decimal,
+        })
+    }
+}
+
+impl TryFrom<String> for Locale {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Locale, String> {
+        Locale::parse(&value)
+    }
+}
+
+impl From<Locale> for String {
+    fn from(locale: Locale) -> String {
+        locale.tag
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertSettings {
+    /// Record alerts even when no channel is configured.
+    #[serde(default)]
+    pub enabled: bool,
+    pub webhook_url: Option<String>,
+    /// Extra webhook headers, as 'Name: Value'.
+    #[serde(default)]
+    pub webhook_headers: Vec<String>,
+    pub slack_webhook: Option<String>,
+    pub teams_webhook: Option<String>,
+    pub telegram_token: Option<String>,
+    pub telegram_chat_id: Option<String>,
+    pub pagerduty_routing_key: Option<String>,
+    /// Timeout for each webhook request (default 10s).
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub timeout: Option<Duration>,
+    pub email: Option<EmailSettings>,
+    /// Entries an alert lists (default 50), the most severe first; the
+    /// rest are counted under them.
+    pub max_entries: Option<usize>,
+    /// `max_entries` for single channels, by name: `webhook`, `slack`,
+    /// `teams`, `email` or `telegram`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_entries_per_channel: BTreeMap<String, usize>,
+    /// A link or path to the full report, such as a CI artifact, given
+    /// under alerts that leave entries out.
+    pub report_link: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailSettings {
+    pub host: String,
+    pub port: Option<u16>,
+    #[serde(default = "default_smtp_tls")]
+    pub tls: SmtpTls,
+    /// Falls back to GUARDIAN_SMTP_USERNAME / GUARDIAN_SMTP_PASSWORD.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub attach_report: bool,
+    /// `"daily"` to batch non-critical alerts into one email.
+    pub digest: Option<DigestPeriod>,
+    /// Digest send time as HH:MM (default 08:00).
+    pub send_at: Option<String>,
+}
+
+fn default_smtp_tls() -> SmtpTls {
+    SmtpTls::Starttls
+}
+
+impl EmailSettings {
+    pub fn email_config(&self) -> Result<EmailConfig, String> {
+        Ok(EmailConfig {
+            host: self.host.clone(),
+            port: self.port,
+            tls: self.tls,
+            credentials: match (&self.username, &self.password) {
+                (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+                _ => credentials_from_env(),
+            },
+            from: parse_mailbox(&self.from)?,
+            to: self
+                .to
+                .iter()
+                .map(|to| parse_mailbox(to))
+                .collect::<Result<_, _>>()?,
+            subject: self
+                .subject
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SUBJECT.to_string()),
+            attach_report: self.attach_report,
+            digest: match self.digest {
+                Some(DigestPeriod::Daily) => {
+                    Some(parse_time(self.send_at.as_deref().unwrap_or("08:00"))?)
+                }
+                None => None,
+            },
+        })
+    }
+}
+
+impl AlertSettings {
+    /// The channels configured in the file, with webhook requests timing
+    /// out after `timeout`.
+    pub fn channels(&self, timeout: Duration) -> Result<AlertChannels, String> {
+        Ok(AlertChannels {
+            webhook: match &self.webhook_url {
+                Some(url) => Some(WebhookConfig {
+                    url: url.clone(),
+                    headers: self
+                        .webhook_headers
+                        .iter()
+                        .map(|h| webhook::parse_header(h))
+                        .collect::<Result<_, _>>()?,
+                    timeout,
+                }),
+                None => None,
+            },
+            slack: self.slack_webhook.as_ref().map(|url| SlackConfig {
+                url: url.clone(),
+                timeout,
+            }),
+            teams: self.teams_webhook.as_ref().map(|url| TeamsConfig {
+                url: url.clone(),
+                timeout,
+            }),
+            email: self
+                .email
+                .as_ref()
+                .map(EmailSettings::email_config)
+                .transpose()?,
+            telegram: match (&self.telegram_token, &self.telegram_chat_id) {
+                (Some(token), Some(chat_id)) => Some(TelegramConfig {
+                    token: token.clone(),
+                    chat_id: chat_id.clone(),
+                    timeout,
+                }),
+                _ => None,
+            },
+            pagerduty: self
+                .pagerduty_routing_key
+                .as_ref()
+                .map(|routing_key| PagerDutyConfig {
+                    routing_key: routing_key.clone(),
+                    timeout,
+                }),
+            desktop: false,
+            on_drift: None,
+            limits: AlertLimits {
+                max_entries: self.max_entries,
+                per_channel: self.max_entries_per_channel.clone(),
+                report: self.report_link.clone(),
+            },
+        })
+    }
+
+    /// Add the channels `other`, the `[channel.NAME]` section `name`, sets.
+    /// Alerts go to one of each kind, so a kind set in both is an error.
+    fn add(&mut self, name: &str, other: &AlertSettings) -> Result<(), String> {
+        fn take<T: Clone>(ours: &mut Option<T>, theirs: &Option<T>) -> bool {
+            match (&ours, theirs) {
+                (Some(_), Some(_)) => return false,
+                (None, Some(value)) => *ours = Some(value.clone()),
+                _ => {}
+            }
+            true
+        }
+        let mut clashes = Vec::new();
+        if !take(&mut self.webhook_url, &other.webhook_url) {
+            clashes.push("webhook_url");
+        } else if other.webhook_url.is_some() {
+            self.webhook_headers = other.webhook_headers.clone();
+        }
+        if !take(&mut self.slack_webhook, &other.slack_webhook) {
+            clashes.push("slack_webhook");
+        }
+        if !take(&mut self.teams_webhook, &other.teams_webhook) {
+            clashes.push("teams_webhook");
+        }
+        if !take(&mut self.telegram_token, &other.telegram_token)
+            | !take(&mut self.telegram_chat_id, &other.telegram_chat_id)
+        {
+            clashes.push("telegram");
+        }
+        if !take(
+            &mut self.pagerduty_routing_key,
+            &other.pagerduty_routing_key,
+        ) {
+            clashes.push("pagerduty_routing_key");
+        }
+        if !take(&mut self.email, &other.email) {
+            clashes.push("email");
+        }
+        if !clashes.is_empty() {
+            return Err(format!(
+                "channel '{}' sets {} again",
+                name,
+                clashes.join(", ")
+            ));
+        }
+        self.enabled |= other.enabled;
+        self.timeout = self.timeout.or(other.timeout);
+        self.max_entries = self.max_entries.or(other.max_entries);
+        for (channel, max) in &other.max_entries_per_channel {
+            self.max_entries_per_channel
+                .entry(channel.clone())
+                .or_insert(*max);
+        }
+        self.report_link = self.report_link.clone().or(other.report_link.clone());
+        Ok(())
+    }
+
+    /// Problems with these alerts that parsing alone doesn't catch.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.telegram_token.is_some() != self.telegram_chat_id.is_some() {
+            problems.push("telegram_token and telegram_chat_id must be set together".into());
+        }
+        if let Err(e) = self.channels(DEFAULT_WEBHOOK_TIMEOUT) {
+            problems.push(e);
+        }
+        for channel in self.max_entries_per_channel.keys() {
+            if !LISTING_CHANNELS.contains(&channel.as_str()) {
+                problems.push(format!(
+                    "max_entries_per_channel: unknown channel '{}' (expected {})",
+                    channel,
+                    LISTING_CHANNELS.join(", ")
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Gives drift matching `path` (and `kind`, if set) this severity.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeverityRule {
+    pub path: String,
+    pub kind: Option<DriftKind>,
+    pub severity: Severity,
+}
+
+/// How `sftp://` targets are logged in to.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SftpSettings {
+    /// The private key to log in with (default: the SSH agent's keys).
+    pub identity: Option<PathBuf>,
+    /// The file host keys are checked against (default:
+    /// ~/.ssh/known_hosts).
+    pub known_hosts: Option<PathBuf>,
+}
+
+/// A piece of a file's metadata that `ignore_fields` can leave out of
+/// comparisons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Uid,
+    Gid,
+    Mode,
+    /// The modification time, which is never drift on its own.
+    Mtime,
+    /// The size, which is never drift on its own.
+    Size,
+    /// The attributes `attrs` records and the ACLs `acls` does.
+    Xattrs,
+}
+
+impl Field {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Field::Uid => "uid",
+            Field::Gid => "gid",
+            Field::Mode => "mode",
+            Field::Mtime => "mtime",
+            Field::Size => "size",
+            Field::Xattrs => "xattrs",
+        }
+    }
+}
+
+/// How comparisons tell whether a file's owner or group changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Ownership {
+    /// By the user and group names, or the IDs where a name is missing.
+    #[default]
+    Name,
+    /// By the numeric IDs.
+    Id,
+}
+
+/// The fields comparisons ignore: everywhere, and in place of that for the
+/// paths a glob matches. Reports carry it, so whoever reads one knows what
+/// it left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IgnoredFields {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<Field>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub paths: BTreeMap<String, Vec<Field>>,
+}
+
+impl IgnoredFields {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.paths.is_empty()
+    }
+}
+
+impl std::fmt::Display for IgnoredFields {
+    /// As `uid, gid; mode for var/run/**; nothing for etc/shadow`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = |fields: &[Field]| match fields {
+            [] => "nothing".to_string(),
+            fields => fields
+                .iter()
+                .map(|field| field.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        let mut parts = Vec::new();
+        if !self.fields.is_empty() {
+            parts.push(names(&self.fields));
+        }
+        for (pattern, fields) in &self.paths {
+            parts.push(format!("{} for {}", names(fields), pattern));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// The drift score weights: by default a critical drift, such as a deleted
+/// file, counts 50, a warning, such as a changed file, 5 and a new file 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScoreWeights {
+    pub critical: u64,
+    pub warning: u64,
+    pub info: u64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        ScoreWeights {
+            critical: 50,
+            warning: 5,
+            info: 1,
+        }
+    }
+}
+
+impl ScoreWeights {
+    pub fn of(&self, severity: Severity) -> u64 {
+        match severity {
+            Severity::Critical => self.critical,
+            Severity::Warning => self.warning,
+            Severity::Info => self.info,
+        }
+    }
+}
+
+/// A Nagios service state, as `compare --nagios` reports it: the worst of
+/// the drift's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NagiosState {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl NagiosState {
+    /// The plugin's exit status in this state.
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl fmt::Display for NagiosState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NagiosState::Ok => "OK",
+            NagiosState::Warning => "WARNING",
+            NagiosState::Critical => "CRITICAL",
+        })
+    }
+}
+
+/// The Nagios state of drift by its severity, unless `kinds` names its
+/// kind: by default CRITICAL for critical drift and WARNING for the rest.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NagiosSettings {
+    pub critical: NagiosState,
+    pub warning: NagiosState,
+    pub info: NagiosState,
+    /// The state of the drift kinds each key lists, as `--fail-on` lists
+    /// them: `"new,deleted"`, or `metadata` for the metadata kinds.
+    pub kinds: BTreeMap<String, NagiosState>,
+}
+
+impl Default for NagiosSettings {
+    fn default() -> Self {
+        NagiosSettings {
+            critical: NagiosState::Critical,
+            warning: NagiosState::Warning,
+            info: NagiosState::Warning,
+            kinds: BTreeMap::new(),
+        }
+    }
+}
+
+/// [`NagiosSettings`] with its kinds parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NagiosStates {
+    by_severity: [NagiosState; 3],
+    kinds: Vec<(DriftKind, NagiosState)>,
+}
+
+impl Default for NagiosStates {
+    fn default() -> Self {
+        NagiosStates::new(&NagiosSettings::default()).expect("the default states parse")
+    }
+}
+
+impl NagiosStates {
+    fn new(settings: &NagiosSettings) -> Result<Self, String> {
+        let mut kinds = Vec::new();
+        for (list, state) in &settings.kinds {
+            let listed = drift::parse_kinds(list).map_err(|e| format!("nagios.kinds: {}", e))?;
+            kinds.extend(listed.into_iter().map(|kind| (kind, *state)));
+        }
+        Ok(NagiosStates {
+            by_severity: [settings.info, settings.warning, settings.critical],
+            kinds,
+        })
+    }
+
+    /// The state of drift of `kind` and `severity`. A kind listed under
+    /// several keys takes the worst of their states.
+    pub fn of(&self, kind: DriftKind, severity: Severity) -> NagiosState {
+        self.kinds
+            .iter()
+            .filter(|(listed, _)| *listed == kind)
+            .map(|(_, state)| *state)
+            .max()
+            .unwrap_or(self.by_severity[severity as usize])
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorSettings {
+    /// Poll every N seconds instead of using filesystem events.
+    pub poll: Option<u64>,
+    pub rewatch_timeout: Option<u64>,
+    /// Filesystem events held waiting at most.
+    pub event_queue_size: Option<usize>,
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub alert_cooldown: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub compare_every: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub jitter: Option<Duration>,
+    #[serde(default)]
+    pub schedule_only: bool,
+    /// How long after the first event of a burst the burst is compared.
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub debounce: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub heartbeat: Option<Duration>,
+    pub heartbeat_url: Option<String>,
+    /// Hash the executable on this schedule, alerting when it changed.
+    #[serde(
+        default,
+        deserialize_with = "duration",
+        serialize_with = "show_duration"
+    )]
+    pub verify_install: Option<Duration>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub api_listen: Option<SocketAddr>,
+    #[serde(default)]
+    pub notify_desktop: bool,
+}
+
+impl MonitorSettings {
+    /// Intervals of zero, which would compare without pause.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.poll == Some(0) {
+            problems.push("poll must be longer than zero".to_string());
+        }
+        if self.compare_every.is_some_and(|every| every.is_zero()) {
+            problems.push("compare_every must be longer than zero".to_string());
+        }
+        problems
+    }
+}
+
+const REQUIRE_FRESH_ALONE: &str =
+    "require_fresh_baseline needs max_baseline_age, the age a baseline must be under";
+
+/// Durations are written as in the CLI, e.g. "30s" or "15m".
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_duration_or_secs(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Written back in the largest unit that's exact, e.g. "15m".
+fn show_duration<S: Serializer>(
+    value: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(d) => serializer.serialize_str(&utils::format_duration(*d)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Percentages are written as in the CLI, e.g. "1%".
+fn percent<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_percent(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn show_percent<S: Serializer>(value: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(percent) => serializer.serialize_str(&format!("{}%", percent)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Sizes are written as in the CLI, e.g. "512M" or "1G".
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_size(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn show_size<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(bytes) => serializer.serialize_str(&format_size(*bytes)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Reads `GUARDIAN_*` variables through a lookup function, so tests don't
+/// have to touch the process environment.
+struct Env<F>(F);
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    fn string(&self, name: &str) -> Option<String> {
+        (self.0)(name).filter(|value| !value.is_empty())
+    }
+
+    fn parse<T>(
+        &self,
+        name: &str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, String> {
+        self.string(name)
+            .map(|value| parse(&value).map_err(|e| format!("{}: {}", name, e)))
+            .transpose()
+    }
+
+    fn list(&self, name: &str) -> Option<Vec<String>> {
+        self.string(name).map(|value| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .collect()
+        })
+    }
+
+    fn flag(&self, name: &str) -> Result<Option<bool>, String> {
+        self.parse(name, |value| match value.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(format!("expected true or false, got '{}'", value)),
+        })
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        self.parse(name, |value| {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value '{}'", value))
+        })
+    }
+
+    fn duration(&self, name: &str) -> Result<Option<Duration>, String> {
+        self.parse(name, parse_duration_or_secs)
+    }
+
+    fn choice<T: ValueEnum>(&self, name: &str) -> Result<Option<T>, String> {
+        self.parse(name, |value| T::from_str(value, true))
+    }
+}
+
+/// Replace `field` when the variable was set.
+fn set<T>(field: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *field = value;
+    }
+}
+
+fn mask(field: &mut Option<String>) {
+    if field.is_some() {
+        *field = Some(MASK.to_string());
+    }
+}
+
+/// Webhook and bot URLs carry their secret in the path.
+fn mask_url(field: &mut Option<String>) {
+    if let Some(url) = field {
+        *url = format!("{}/{}", http::origin(url), MASK);
+    }
+}
+
+fn mask_alerts(alerts: &mut AlertSettings) {
+    mask_url(&mut alerts.webhook_url);
+    for header in &mut alerts.webhook_headers {
+        if let Some((name, _)) = header.split_once(':') {
+            *header = format!("{}: {}", name, MASK);
+        }
+    }
+    mask_url(&mut alerts.slack_webhook);
+    mask_url(&mut alerts.teams_webhook);
+    mask(&mut alerts.telegram_token);
+    mask(&mut alerts.pagerduty_routing_key);
+    if let Some(email) = &mut alerts.email {
+        mask(&mut email.password);
+    }
+}
+
+fn platform_config_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(target_os = "macos")]
+    let base = std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join("Library/Application Support"));
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    base.map(|dir| dir.join("config-guardian").join(CONFIG_FILE))
+}
+
+/// Read the config file, `explicit` if given (it must exist) and otherwise
+/// the first default location that exists, then apply the environment. The
+/// path is `None` when there is no file.
+pub fn load(explicit: Option<&Path>) -> Result<(Option<PathBuf>, Config), String> {
+    let path = match explicit {
+        Some(path) => Some(path.to_path_buf()),
+        None => std::iter::once(PathBuf::from(CONFIG_FILE))
+            .chain(platform_config_dir())
+            .find(|path| path.is_file()),
+    };
+    let mut config = match &path {
+        Some(path) => {
+            let data = std::fs::read_to_string(path)
+                .map_err(|e| format!("Could not read config {}: {}", path.display(), e))?;
+            let config: Config = toml::from_str(&data)
+                .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+            if !config.normalize.is_empty() && world_writable(path) {
+                return Err(format!(
+                    "Refusing the normalizers in config {}: anyone can write to it, and so have their commands run",
+                    path.display()
+                ));
+            }
+            config
+        }
+        None => Config::default(),
+    };
+    config.apply_env(|name| std::env::var(name).ok())?;
+    Ok((path, config))
+}
+
+/// Whether anyone may write to `path`, whose commands then can't be run.
+#[cfg(unix)]
+fn world_writable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o002 != 0)
+}
+
+#[cfg(not(unix))]
+fn world_writable(_path: &Path) -> bool {
+    false
+}
+
+impl Config {
+    /// Override values with the `GUARDIAN_*` variables that `var` finds.
+    /// Severity rules can only be set in the file.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        let env = Env(var);
+        if let Some(dirs) = env.string("GUARDIAN_DIRECTORIES") {
+            // Separated like PATH, since directory names may contain commas.
+            self.directories = std::env::split_paths(&dirs)
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .collect();
+        }
+        if let Some(exclude) = env.list("GUARDIAN_EXCLUDE") {
+            self.exclude = exclude;
+        }
+        if let Some(exclude) = env.list("GUARDIAN_BASELINE_EXCLUDE") {
+            self.baseline_exclude = exclude;
+        }
+        if let Some(redact) = env.list("GUARDIAN_REDACT") {
+            self.redact = redact;
+        }
+        if let Some(expected) = env.list("GUARDIAN_EXPECT_EXECUTABLES") {
+            self.expect_executables = expected;
+        }
+        set(
+            &mut self.snapshot,
+            env.string("GUARDIAN_SNAPSHOT_FILE").map(PathBuf::from),
+        );
+        if let Some(hash) = env.choice("GUARDIAN_HASH")? {
+            self.hash = hash;
+        }
+        if let Some(display) = env.choice("GUARDIAN_HASH_DISPLAY")? {
+            self.hash_display = display;
+        }
+        if let Some(by) = env.choice("GUARDIAN_COMPARE_OWNERSHIP_BY")? {
+            self.compare_ownership_by = by;
+        }
+        if let Some(utc) = env.flag("GUARDIAN_UTC")? {
+            self.utc = utc;
+        }
+        if let Some(units) = env.choice("GUARDIAN_SIZE_UNITS")? {
+            self.size_units = units;
+        }
+        set(
+            &mut self.locale,
+            env.parse("GUARDIAN_LOCALE", Locale::parse)?,
+        );
+        if let Some(detect) = env.flag("GUARDIAN_DETECT_MOUNTS")? {
+            self.detect_mounts = detect;
+        }
+        if let Some(attrs) = env.flag("GUARDIAN_ATTRS")? {
+            self.attrs = attrs;
+        }
+        if let Some(acls) = env.flag("GUARDIAN_ACLS")? {
+            self.acls = acls;
+        }
+        if let Some(skip) = env.flag("GUARDIAN_SKIP_GROWING")? {
+            self.skip_growing = skip;
+        }
+        if let Some(include) = env.flag("GUARDIAN_INCLUDE_VOLATILE")? {
+            self.include_volatile = include;
+        }
+        if let Some(ignore) = env.flag("GUARDIAN_IGNORE_UNREADABLE")? {
+            self.ignore_unreadable = ignore;
+        }
+        if let Some(fields) = env.list("GUARDIAN_IGNORE_FIELDS") {
+            self.ignore_fields = fields
+                .iter()
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    Field::from_str(name, true)
+                        .map_err(|_| format!("GUARDIAN_IGNORE_FIELDS: unknown field '{}'", name))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(track) = env.flag("GUARDIAN_TRACK_SELF")? {
+            self.track_self = track;
+        }
+        if let Some(follow) = env.flag("GUARDIAN_FOLLOW_ROOT_SYMLINK")? {
+            self.follow_root_symlink = follow;
+        }
+        set(&mut self.max_files, env.number("GUARDIAN_MAX_FILES")?);
+        set(&mut self.jobs, env.number("GUARDIAN_JOBS")?);
+        if let Some(nice) = env.flag("GUARDIAN_NICE")? {
+            self.nice = nice;
+        }
+        set(&mut self.io_throttle, env.number("GUARDIAN_IO_THROTTLE")?);
+        set(
+            &mut self.cpu_limit,
+            env.parse("GUARDIAN_CPU_LIMIT", parse_percent)?,
+        );
+        set(
+            &mut self.read_timeout,
+            env.duration("GUARDIAN_READ_TIMEOUT")?,
+        );
+        set(&mut self.retries, env.number("GUARDIAN_RETRIES")?);
+        set(&mut self.retry_delay, env.duration("GUARDIAN_RETRY_DELAY")?);
+        set(
+            &mut self.normalize_timeout,
+            env.duration("GUARDIAN_NORMALIZE_TIMEOUT")?,
+        );
+        set(&mut self.retry_on, env.list("GUARDIAN_RETRY_ON"));
+        if let Some(paranoid) = env.flag("GUARDIAN_PARANOID")? {
+            self.paranoid = paranoid;
+        }
+        if let Some(no_cache) = env.flag("GUARDIAN_NO_CACHE")? {
+            self.no_cache = no_cache;
+        }
+        if let Some(keep_content) = env.flag("GUARDIAN_KEEP_CONTENT")? {
+            self.keep_content = keep_content;
+        }
+        if let Some(strict) = env.flag("GUARDIAN_STRICT_LOAD")? {
+            self.strict_load = strict;
+        }
+        set(
+            &mut self.max_baseline_age,
+            env.duration("GUARDIAN_MAX_BASELINE_AGE")?,
+        );
+        if let Some(require) = env.flag("GUARDIAN_REQUIRE_FRESH_BASELINE")? {
+            self.require_fresh_baseline = require;
+        }
+        set(&mut self.clock_skew, env.duration("GUARDIAN_CLOCK_SKEW")?);
+        set(
+            &mut self.verify_sample,
+            env.parse("GUARDIAN_VERIFY_SAMPLE", parse_percent)?,
+        );
+        set(
+            &mut self.sample_hash,
+            env.parse("GUARDIAN_SAMPLE_HASH", parse_size)?,
+        );
+        if let Some(chunked) = env.list("GUARDIAN_CHUNKED_HASH") {
+            self.chunked_hash = chunked;
+        }
+        if let Some(files) = env.list("GUARDIAN_VIRTUAL_FILES") {
+            self.virtual_files = files;
+        }
+        set(&mut self.instance_id, env.string("GUARDIAN_INSTANCE_ID"));
+
+        let alerts = &mut self.alerts;
+        if let Some(enabled) = env.flag("GUARDIAN_ALERT")? {
+            alerts.enabled = enabled;
+        }
+        set(&mut alerts.webhook_url, env.string("GUARDIAN_WEBHOOK_URL"));
+        if let Some(header) = env.string("GUARDIAN_WEBHOOK_HEADER") {
+            alerts.webhook_headers = vec![header];
+        }
+        set(
+            &mut alerts.slack_webhook,
+            env.string("GUARDIAN_SLACK_WEBHOOK"),
+        );
+        set(
+            &mut alerts.teams_webhook,
+            env.string("GUARDIAN_TEAMS_WEBHOOK"),
+        );
+        set(
+            &mut alerts.telegram_token,
+            env.string("GUARDIAN_TELEGRAM_TOKEN"),
+        );
+        set(
+            &mut alerts.telegram_chat_id,
+            env.string("GUARDIAN_TELEGRAM_CHAT_ID"),
+        );
+        set(
+            &mut alerts.pagerduty_routing_key,
+            env.string("GUARDIAN_PAGERDUTY_ROUTING_KEY"),
+        );
+        set(
+            &mut alerts.timeout,
+            env.duration("GUARDIAN_WEBHOOK_TIMEOUT")?,
+        );
+        set(
+            &mut alerts.max_entries,
+            env.number("GUARDIAN_ALERT_MAX_ENTRIES")?,
+        );
+        set(
+            &mut alerts.report_link,
+            env.string("GUARDIAN_ALERT_REPORT_LINK"),
+        );
+
+        let host = env.string("GUARDIAN_SMTP_HOST");
+        if alerts.email.is_none() && host.is_some() {
+            alerts.email = Some(EmailSettings {
+                host: String::new(),
+                port: None,
+                tls: default_smtp_tls(),
+                username: None,
+                password: None,
+                from: String::new(),
+                to: Vec::new(),
+                subject: None,
+                attach_report: false,
+                digest: None,
+                send_at: None,
+            });
+        }
+        if let Some(email) = &mut alerts.email {
+            if let Some(host) = host {
+                email.host = host;
+            }
+            set(&mut email.port, env.number("GUARDIAN_SMTP_PORT")?);
+            if let Some(tls) = env.choice("GUARDIAN_SMTP_TLS")? {
+                email.tls = tls;
+            }
+            set(&mut email.username, env.string("GUARDIAN_SMTP_USERNAME"));
+            set(&mut email.password, env.string("GUARDIAN_SMTP_PASSWORD"));
+            if let Some(from) = env.string("GUARDIAN_EMAIL_FROM") {
+                email.from = from;
+            }
+            if let Some(to) = env.list("GUARDIAN_EMAIL_TO") {
+                email.to = to;
+            }
+            set(&mut email.subject, env.string("GUARDIAN_EMAIL_SUBJECT"));
+            if let Some(attach) = env.flag("GUARDIAN_EMAIL_ATTACH_REPORT")? {
+                email.attach_report = attach;
+            }
+            set(&mut email.digest, env.choice("GUARDIAN_EMAIL_DIGEST")?);
+            set(&mut email.send_at, env.string("GUARDIAN_EMAIL_DIGEST_AT"));
+            if email.from.is_empty() || email.to.is_empty() {
+                return Err(
+                    "GUARDIAN_SMTP_HOST needs GUARDIAN_EMAIL_FROM and GUARDIAN_EMAIL_TO".into(),
+                );
+            }
+        }
+
+        let score = &mut self.score;
+        for (weight, name) in [
+            (&mut score.critical, "GUARDIAN_SCORE_CRITICAL"),
+            (&mut score.warning, "GUARDIAN_SCORE_WARNING"),
+            (&mut score.info, "GUARDIAN_SCORE_INFO"),
+        ] {
+            if let Some(value) = env.number(name)? {
+                *weight = value;
+            }
+        }
+
+        let monitor = &mut self.monitor;
+        set(&mut monitor.poll, env.number("GUARDIAN_POLL")?);
+        set(
+            &mut monitor.rewatch_timeout,
+            env.number("GUARDIAN_REWATCH_TIMEOUT")?,
+        );
+        set(
+            &mut monitor.event_queue_size,
+            env.number("GUARDIAN_EVENT_QUEUE_SIZE")?,
+        );
+        set(
+            &mut monitor.alert_cooldown,
+            env.duration("GUARDIAN_ALERT_COOLDOWN")?,
+        );
+        set(
+            &mut monitor.compare_every,
+            env.duration("GUARDIAN_COMPARE_EVERY")?,
+        );
+        set(&mut monitor.jitter, env.duration("GUARDIAN_JITTER")?);
+        set(&mut monitor.debounce, env.duration("GUARDIAN_DEBOUNCE")?);
+        if let Some(schedule_only) = env.flag("GUARDIAN_SCHEDULE_ONLY")? {
+            monitor.schedule_only = schedule_only;
+        }
+        set(&mut monitor.heartbeat, env.duration("GUARDIAN_HEARTBEAT")?);
+        set(
+            &mut monitor.heartbeat_url,
+            env.string("GUARDIAN_HEARTBEAT_URL"),
+        );
+        set(
+            &mut monitor.verify_install,
+            env.duration("GUARDIAN_VERIFY_INSTALL")?,
+        );
+        set(
+            &mut monitor.metrics_listen,
+            env.number("GUARDIAN_METRICS_LISTEN")?,
+        );
+        set(&mut monitor.api_listen, env.number("GUARDIAN_API_LISTEN")?);
+        if let Some(notify) = env.flag("GUARDIAN_NOTIFY_DESKTOP")? {
+            monitor.notify_desktop = notify;
+        }
+        Ok(())
+    }
+
+    /// A copy safe to print, with tokens, passwords and the secret part of
+    /// webhook URLs masked.
+    pub fn masked(&self) -> Config {
+        let mut config = self.clone();
+        mask_alerts(&mut config.alerts);
+        for profile in config.profiles.values_mut() {
+            if let Some(alerts) = &mut profile.alerts {
+                mask_alerts(alerts);
+            }
+        }
+        for alerts in config.channels.values_mut() {
+            mask_alerts(alerts);
+        }
+        mask_url(&mut config.monitor.heartbeat_url);
+        config
+    }
+
+    /// Problems that parsing alone doesn't catch.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for pattern in &self.exclude {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                problems.push(format!("exclude: invalid glob '{}': {}", pattern, e));
+            }
+        }
+        for pattern in &self.baseline_exclude {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                problems.push(format!(
+                    "baseline_exclude: invalid glob '{}': {}",
+                    pattern, e
+                ));
+            }
+        }
+        for pattern in &self.redact {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                problems.push(format!("redact: invalid glob '{}': {}", pattern, e));
+            }
+        }
+        for pattern in &self.expect_executables {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                problems.push(format!(
+                    "expect_executables: invalid glob '{}': {}",
+                    pattern, e
+                ));
+            }
+        }
+        for name in self.retry_on.iter().flatten() {
+            if let Err(e) = utils::os_error_code(name) {
+                problems.push(format!("retry_on: {}", e));
+            }
+        }
+        for pattern in self.ignore.keys() {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                problems.push(format!("ignore: invalid glob '{}': {}", pattern, e));
+            }
+        }
+        for (pattern, command) in &self.normalize {
+            if let Err(e) = Normalizer::new(pattern, command) {
+                problems.push(e);
+            }
+        }
+        for (i, rule) in self.severity.iter().enumerate() {
+            if let Err(e) = glob::Pattern::new(&rule.path) {
+                problems.push(format!(
+                    "severity[{}]: invalid glob '{}': {}",
+                    i, rule.path, e
+                ));
+            }
+        }
+        if self.require_fresh_baseline && self.max_baseline_age.is_none() {
+            problems.push(REQUIRE_FRESH_ALONE.into());
+        }
+        problems.extend(
+            self.alerts
+                .problems()
+                .into_iter()
+                .map(|problem| format!("alerts: {}", problem)),
+        );
+        problems.extend(
+            self.monitor
+                .problems()
+                .into_iter()
+                .map(|problem| format!("monitor: {}", problem)),
+        );
+        for (name, channel) in &self.channels {
+            problems.extend(
+                channel
+                    .problems()
+                    .into_iter()
+                    .map(|problem| format!("channel.{}: {}", name, problem)),
+            );
+        }
+        for (name, profile) in &self.profiles {
+            if let Err(e) = profile.alerts(&self.channels) {
+                problems.push(format!("profile.{}.channels: {}", name, e));
+            }
+            // Checked on their own, so the file's problems aren't repeated.
+            let own = Config {
+                exclude: profile.exclude.clone(),
+                severity: profile.severity.clone(),
+                alerts: profile.alerts.clone().unwrap_or_default(),
+                ..Config::default()
+            };
+            problems.extend(
+                own.problems()
+                    .into_iter()
+                    .map(|problem| format!("profile.{}.{}", name, problem)),
+            );
+        }
+        problems
+    }
+
+    /// Take the options `baseline` was scanned with wherever these are left
+    /// at their defaults, so that a comparison scans as the snapshot did.
+    /// Those set otherwise are kept, and listed as contradicting it.
+    pub fn inherit(&mut self, baseline: &ScanOptions) -> Inherited {
+        let mut taken = ScanOptions::default();
+        let mut contradicted = Vec::new();
+        let patterns = |p: &[String]| p.iter().cloned().collect::<BTreeSet<_>>();
+        if self.exclude.is_empty() {
+            self.exclude.clone_from(&baseline.exclude);
+            taken.exclude.clone_from(&baseline.exclude);
+        } else if patterns(&self.exclude) != patterns(&baseline.exclude) {
+            contradicted.push(format!(
+                "exclude = {:?} where the baseline has exclude = {:?}",
+                self.exclude, baseline.exclude
+            ));
+        }
+        if self.hash == HashAlgorithm::default() {
+            self.hash = baseline.hash;
+            taken.hash = baseline.hash;
+        } else if self.hash != baseline.hash {
+            contradicted.push(format!(
+                "hash = \"{}\" where the baseline has hash = \"{}\"",
+                self.hash, baseline.hash
+            ));
+        }
+        if self.sample_hash.is_none() {
+            self.sample_hash = baseline.sample_hash;
+            taken.sample_hash = baseline.sample_hash;
+        } else if self.sample_hash != baseline.sample_hash {
+            contradicted.push(format!(
+                "sample_hash = \"{}\" where the baseline has {}",
+                format_size(self.sample_hash.unwrap_or_default()),
+                baseline
+                    .sample_hash
+                    .map_or("no sample_hash".to_string(), |size| format!(
+                        "sample_hash = \"{}\"",
+                        format_size(size)
+                    ))
+            ));
+        }
+        // Only the commands' identities are recorded, so they can't be
+        // taken.
+        let normalizers: BTreeMap<String, String> = self
+            .normalize
+            .iter()
+            .map(|(pattern, command)| (pattern.clone(), normalize::identity(command)))
+            .collect();
+        if normalizers != baseline.normalize {
+            let shown = |normalize: &BTreeMap<String, String>| {
+                if normalize.is_empty() {
+                    "no normalize".to_string()
+                } else {
+                    format!("normalize = {:?}", normalize)
+                }
+            };
+            contradicted.push(format!(
+                "{} where the baseline has {}",
+                shown(&normalizers),
+                shown(&baseline.normalize)
+            ));
+        }
+        for (name, ours, theirs, inherited) in [
+            (
+                "detect_mounts",
+                &mut self.detect_mounts,
+                baseline.detect_mounts,
+                &mut taken.detect_mounts,
+            ),
+            ("attrs", &mut self.attrs, baseline.attrs, &mut taken.attrs),
+            ("acls", &mut self.acls, baseline.acls, &mut taken.acls),
+            (
+                "skip_growing",
+                &mut self.skip_growing,
+                baseline.skip_growing,
+                &mut taken.skip_growing,
+            ),
+            (
+                "track_self",
+                &mut self.track_self,
+                baseline.track_self,
+                &mut taken.track_self,
+            ),
+        ] {
+            if !*ours {
+                *ours = theirs;
+                *inherited = theirs;
+            } else if !theirs {
+                contradicted.push(format!(
+                    "{} = true where the baseline has {} = false",
+                    name, name
+                ));
+            }
+        }
+        Inherited {
+            taken,
+            contradicted,
+        }
+    }
+
+    pub fn settings(&self) -> Result<Settings, String> {
+        let glob = |pattern: &str| {
+            glob::Pattern::new(pattern).map_err(|e| format!("invalid glob '{}': {}", pattern, e))
+        };
+        let snapshot_file = self
+            .snapshot
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(SNAPSHOT_FILE));
+        Ok(Settings {
+            own_files: own_files(&snapshot_file),
+            snapshot_file,
+            exclude: self
+                .exclude
+                .iter()
+                .map(|p| glob(p))
+                .collect::<Result<_, _>>()?,
+            baseline_exclude: self
+                .baseline_exclude
+                .iter()
+                .map(|p| glob(p))
+                .collect::<Result<_, _>>()?,
+            redact: self
+                .redact
+                .iter()
+                .map(|p| glob(p))
+                .collect::<Result<_, _>>()?,
+            reveal_redacted: false,
+            expect_executables: self
+                .expect_executables
+                .iter()
+                .map(|p| glob(p))
+                .collect::<Result<_, _>>()?,
+            hash: self.hash,
+            hash_display: self.hash_display,
+            utc: self.utc,
+            size_units: self.size_units,
+            locale: self.locale.clone(),
+            detect_mounts: self.detect_mounts,
+            attrs: self.attrs,
+            acls: self.acls,
+            skip_growing: self.skip_growing,
+            include_volatile: self.include_volatile,
+            ignore_unreadable: self.ignore_unreadable,
+            ignored: IgnoredFields {
+                fields: self.ignore_fields.clone(),
+                paths: self.ignore.clone(),
+            },
+            ignore: self
+                .ignore
+                .iter()
+                .map(|(pattern, fields)| Ok((glob(pattern)?, fields.clone())))
+                .collect::<Result<_, String>>()?,
+            ownership: self.compare_ownership_by,
+            track_self: self.track_self,
+            jobs: match self.jobs {
+                Some(0) => return Err("jobs must be at least 1".into()),
+                Some(jobs) => jobs,
+                None if self.nice => throttle::NICE_JOBS,
+                None => default_jobs(),
+            },
+            throttle: Throttle {
+                io: match self
+                    .io_throttle
+                    .or(self.nice.then_some(throttle::NICE_IO_THROTTLE))
+                {
+                    Some(rate) if !(rate > 0.0 && rate.is_finite()) => {
+                        return Err("io_throttle must be a positive number of MiB/s".into());
+                    }
+                    rate => rate.map(|rate| (rate * (1u64 << 20) as f64) as u64),
+                },
+                cpu: match self
+                    .cpu_limit
+                    .or(self.nice.then_some(throttle::NICE_CPU_LIMIT))
+                {
+                    Some(share) if share <= 0.0 => {
+                        return Err("cpu_limit must be more than 0%".into());
+                    }
+                    share => share,
+                },
+                nice: self.nice,
+            },
+            read_timeout: self.read_timeout,
+            deadline: None,
+            retries: self.retries.unwrap_or(DEFAULT_RETRIES),
+            retry_delay: self.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY),
+            retry_on: match &self.retry_on {
+                Some(names) => names
+                    .iter()
+                    .map(|name| utils::os_error_code(name).map_err(|e| format!("retry_on: {}", e)))
+                    .collect::<Result<_, _>>()?,
+                None => default_retry_on(),
+            },
+            paranoid: self.paranoid,
+            follow_root_symlink: self.follow_root_symlink,
+            hash_cache: !self.no_cache,
+            keep_content: self.keep_content,
+            strict_load: self.strict_load,
+            max_baseline_age: self.max_baseline_age,
+            require_fresh_baseline: match self.max_baseline_age {
+                None if self.require_fresh_baseline => return Err(REQUIRE_FRESH_ALONE.into()),
+                _ => self.require_fresh_baseline,
+            },
+            clock_skew: self.clock_skew.unwrap_or(DEFAULT_CLOCK_SKEW),
+            verify_sample: self.verify_sample.unwrap_or(DEFAULT_VERIFY_SAMPLE),
+            sample_hash: self.sample_hash,
+            chunked_hash: self
+                .chunked_hash
+                .iter()
+                .map(|p| glob(p))
+                .collect::<Result<_, _>>()?,
+            virtual_files: self.virtual_files.clone(),
+            normalize: self
+                .normalize
+                .iter()
+                .map(|(pattern, command)| Normalizer::new(pattern, command))
+                .collect::<Result<_, _>>()?,
+            normalize_timeout: self.normalize_timeout.unwrap_or(normalize::DEFAULT_TIMEOUT),
+            sftp: self.sftp.clone(),
+            max_files: match self.max_files {
+                None => Some(DEFAULT_MAX_FILES),
+                Some(0) => None,
+                limit => limit,
+            },
+            trace: false,
+            quiet: false,
+            baseline_roots: BTreeMap::new(),
+            scope: Vec::new(),
+            severity: self
+                .severity
+                .iter()
+                .map(|rule| Ok((glob(&rule.path)?, rule.kind, rule.severity)))
+                .collect::<Result<_, String>>()?,
+            score: self.score,
+            nagios: NagiosStates::new(&self.nagios)?,
+            instance_id: match &self.instance_id {
+                Some(id) => {
+                    check_host_name(id).map_err(|e| format!("instance_id: {}", e))?;
+                    Some(id.clone())
+                }
+                None => None,
+            },
+            identity: match &self.instance_id_sources {
+                Some(sources)
+                    if !cfg!(feature = "cloud-identity")
+                        && sources.contains(&IdentitySource::Cloud) =>
+                {
+                    return Err(
+                        "instance_id_sources: \"cloud\" needs a build with the cloud-identity feature".into(),
+                    );
+                }
+                Some(sources) => sources.clone(),
+                None => identity::DEFAULT_SOURCES.to_vec(),
+            },
+        })
+    }
+
+    /// What changed from `self` to `new` that a running monitor can't take
+    /// up: what it watches, where it keeps its state, and how it listens
+    /// for changes and for requests. Each is named as in the file.
+    pub fn restart_only(&self, new: &Config) -> Vec<&'static str> {
+        let (old_monitor, new_monitor) = (&self.monitor, &new.monitor);
+        [
+            ("directories", self.directories != new.directories),
+            ("snapshot", self.snapshot != new.snapshot),
+            ("monitor.poll", old_monitor.poll != new_monitor.poll),
+            (
+                "monitor.compare_every",
+                old_monitor.compare_every != new_monitor.compare_every
+                    || old_monitor.jitter != new_monitor.jitter,
+            ),
+            (
+                "monitor.schedule_only",
+                old_monitor.schedule_only != new_monitor.schedule_only,
+            ),
+            (
+                "monitor.event_queue_size",
+                old_monitor.event_queue_size != new_monitor.event_queue_size,
+            ),
+            (
+                "monitor.rewatch_timeout",
+                old_monitor.rewatch_timeout != new_monitor.rewatch_timeout,
+            ),
+            (
+                "monitor.heartbeat",
+                old_monitor.heartbeat != new_monitor.heartbeat
+                    || old_monitor.heartbeat_url != new_monitor.heartbeat_url,
+            ),
+            (
+                "monitor.verify_install",
+                old_monitor.verify_install != new_monitor.verify_install,
+            ),
+            (
+                "monitor.metrics_listen",
+                old_monitor.metrics_listen != new_monitor.metrics_listen,
+            ),
+            (
+                "monitor.api_listen",
+                old_monitor.api_listen != new_monitor.api_listen,
+            ),
+            ("instance_id", self.instance_id != new.instance_id),
+            (
+                "instance_id_sources",
+                self.instance_id_sources != new.instance_id_sources,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+}
+
+/// What [`Config::inherit`] took from a baseline's scan options.
+#[derive(Debug, Default)]
+pub struct Inherited {
+    /// The options taken, the others left at their defaults.
+    pub taken: ScanOptions,
+    /// Each option set otherwise than the baseline's, as `acls = true where
+    /// the baseline has acls = false`.
+    pub contradicted: Vec<String>,
+}
+
+/// The config settings that scanning and comparison depend on, fixed for
+/// the whole run, or in a monitor until its config file changes.
+pub struct Settings {
+    pub snapshot_file: PathBuf,
+    pub exclude: Vec<glob::Pattern>,
+    /// Entries left out of baselines as they are loaded.
+    pub baseline_exclude: Vec<glob::Pattern>,
+    pub redact: Vec<glob::Pattern>,
+    /// Print redacted paths as they are. Set by `--no-redact` on a terminal
+    /// rather than the config file.
+    pub reveal_redacted: bool,
+    pub expect_executables: Vec<glob::Pattern>,
+    pub hash: HashAlgorithm,
+    pub hash_display: HashDisplay,
+    pub utc: bool,
+    pub size_units: SizeUnits,
+    pub locale: Option<Locale>,
+    pub detect_mounts: bool,
+    pub attrs: bool,
+    pub acls: bool,
+    pub skip_growing: bool,
+    pub include_volatile: bool,
+    pub ignore_unreadable: bool,
+    /// The fields comparisons ignore, as configured.
+    pub ignored: IgnoredFields,
+    ignore: Vec<(glob::Pattern, Vec<Field>)>,
+    /// How owners and groups are compared.
+    pub ownership: Ownership,
+    pub track_self: bool,
+    /// Where the files config-guardian writes itself resolve to; see
+    /// [`Settings::is_own_file`].
+    own_files: Vec<PathBuf>,
+    /// Files a scan of one directory may find; `None` for no limit.
+    pub max_files: Option<usize>,
+    /// Files a scan reads at once.
+    pub jobs: usize,
+    /// The limits scans are paced to.
+    pub throttle: Throttle,
+    pub read_timeout: Option<Duration>,
+    /// When the run must be done by, and the `--timeout` that set it. Set
+    /// by the command rather than the config file.
+    pub deadline: Option<(Instant, Duration)>,
+    /// Times a file is read again after a transient error.
+    pub retries: u32,
+    pub retry_delay: Duration,
+    /// The OS error codes worth retrying.
+    pub retry_on: Vec<i32>,
+    pub paranoid: bool,
+    /// Scan a directory given by a symlink, or a pattern's base directory,
+    /// through it, rather than refuse to.
+    pub follow_root_symlink: bool,
+    /// Take hashes from the hash cache and record them in it. Off unless
+    /// installed, so that scans through the library leave no cache behind.
+    pub hash_cache: bool,
+    /// Keep the content of recorded files; see [`crate::content`].
+    pub keep_content: bool,
+    /// Refuse a snapshot with broken entries rather than drop them.
+    pub strict_load: bool,
+    /// How old a baseline may be before comparisons warn about it.
+    pub max_baseline_age: Option<Duration>,
+    /// Fail comparisons against a baseline older than `max_baseline_age`.
+    pub require_fresh_baseline: bool,
+    /// Modification times further ahead of the clock than this are skewed.
+    pub clock_skew: Duration,
+    /// Percentage of the files that look unchanged a comparison reads.
+    pub verify_sample: f64,
+    /// Files larger than this get a sampled hash.
+    pub sample_hash: Option<u64>,
+    /// Files to record content-defined chunks of.
+    pub chunked_hash: Vec<glob::Pattern>,
+    /// Files read as virtual ones, as they are recorded.
+    pub virtual_files: Vec<String>,
+    pub normalize: Vec<Normalizer>,
+    pub normalize_timeout: Duration,
+    pub sftp: SftpSettings,
+    /// Print what a scan did with each entry, and why. Set by `--verbose`
+    /// rather than the config file.
+    pub trace: bool,
+    /// Keep comparisons' text report off stdout, which `compare --format`
+    /// writes its report to. Set by the command rather than the config file.
+    pub quiet: bool,
+    /// The directory each compared directory's baseline entries were
+    /// recorded under, where that isn't the directory as given: the same
+    /// one by another path, or another with `--allow-root-mismatch`. Set by
+    /// the command rather than the config file.
+    pub baseline_roots: BTreeMap<String, String>,
+    /// The subtrees `compare --scope` narrows comparisons to, relative to
+    /// each target's base directory; none for the whole target.
+    pub scope: Vec<String>,
+    severity: Vec<(glob::Pattern, Option<DriftKind>, Severity)>,
+    pub score: ScoreWeights,
+    pub nagios: NagiosStates,
+    /// `instance_id`, checked; see [`identity::current`].
+    pub instance_id: Option<String>,
+    /// Where the identity comes from. Just the hostname unless installed,
+    /// so that the library leaves no `instance-id` behind.
+    pub identity: Vec<IdentitySource>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            own_files: own_files(Path::new(SNAPSHOT_FILE)),
+            snapshot_file: PathBuf::from(SNAPSHOT_FILE),
+            exclude: Vec::new(),
+            baseline_exclude: Vec::new(),
+            redact: Vec::new(),
+            reveal_redacted: false,
+            expect_executables: Vec::new(),
+            hash: HashAlgorithm::default(),
+            hash_display: HashDisplay::default(),
+            utc: false,
+            size_units: SizeUnits::default(),
+            locale: None,
+            detect_mounts: false,
+            attrs: false,
+            acls: false,
+            skip_growing: false,
+            include_volatile: false,
+            ignore_unreadable: false,
+            ignored: IgnoredFields::default(),
+            ignore: Vec::new(),
+            ownership: Ownership::default(),
+            track_self: false,
+            max_files: Some(DEFAULT_MAX_FILES),
+            jobs: default_jobs(),
+            throttle: Throttle::default(),
+            read_timeout: None,
+            deadline: None,
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            retry_on: default_retry_on(),
+            paranoid: false,
+            follow_root_symlink: false,
+            hash_cache: false,
+            keep_content: false,
+            strict_load: false,
+            max_baseline_age: None,
+            require_fresh_baseline: false,
+            clock_skew: DEFAULT_CLOCK_SKEW,
+            verify_sample: DEFAULT_VERIFY_SAMPLE,
+            sample_hash: None,
+            chunked_hash: Vec::new(),
+            virtual_files: Vec::new(),
+            normalize: Vec::new(),
+            normalize_timeout: normalize::DEFAULT_TIMEOUT,
+            sftp: SftpSettings::default(),
+            trace: false,
+            quiet: false,
+            baseline_roots: BTreeMap::new(),
+            scope: Vec::new(),
+            severity: Vec::new(),
+            score: ScoreWeights::default(),
+            nagios: NagiosStates::default(),
+            instance_id: None,
+            identity: vec![IdentitySource::Hostname],
+        }
+    }
+}
+
+impl Settings {
+    /// These settings, built from a config file changed since `current`
+    /// were, as a running monitor takes them up: with what the command line
+    /// set rather than the file, and the snapshot file, which only a
+    /// restart changes, as they are in `current`.
+    pub fn carry_over(mut self, current: &Settings) -> Settings {
+        self.snapshot_file.clone_from(&current.snapshot_file);
+        self.own_files.clone_from(&current.own_files);
+        self.reveal_redacted = current.reveal_redacted;
+        self.deadline = current.deadline;
+        self.trace = current.trace;
+        self.quiet = current.quiet;
+        self.scope.clone_from(&current.scope);
+        self
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.excluded_by(path).is_some()
+    }
+
+    /// The first exclude pattern matching `path` or its file name.
+    pub fn excluded_by(&self, path: &Path) -> Option<&glob::Pattern> {
+        let full = utils::path_text(path);
+        let name = path.file_name().map(|n| utils::path_text(Path::new(n)));
+        self.exclude.iter().find(|pattern| {
+            pattern.matches(&full) || name.as_deref().is_some_and(|n| pattern.matches(n))
+        })
+    }
+
+    /// The first `baseline_exclude` pattern matching `path`, its file name,
+    /// or the path under `base`, the directory compared.
+    pub fn baseline_excluded_by(&self, path: &Path, base: &Path) -> Option<&glob::Pattern> {
+        let full = utils::path_text(path);
+        let under = path.strip_prefix(base).ok().map(utils::path_text);
+        let name = path.file_name().map(|n| utils::path_text(Path::new(n)));
+        self.baseline_exclude.iter().find(|pattern| {
+            pattern.matches(&full)
+                || under.as_deref().is_some_and(|u| pattern.matches(u))
+                || name.as_deref().is_some_and(|n| pattern.matches(n))
+        })
+    }
+
+    /// Whether `path`, or its file name, matches a `redact` pattern.
+    pub fn is_redacted(&self, path: &str) -> bool {
+        if self.redact.is_empty() {
+            return false;
+        }
+        let name = Path::new(path)
+            .file_name()
+            .map(|n| utils::path_text(Path::new(n)));
+        self.redact.iter().any(|pattern| {
+            pattern.matches(path) || name.as_deref().is_some_and(|n| pattern.matches(n))
+        })
+    }
+
+    /// Whether `path` is in a directory, or is itself a path, that matches
+    /// an `expect_executables` pattern.
+    pub fn expects_executables(&self, path: &str) -> bool {
+        let dir = Path::new(path).parent().map(utils::path_text);
+        self.expect_executables.iter().any(|pattern| {
+            pattern.matches(path) || dir.as_deref().is_some_and(|d| pattern.matches(d))
+        })
+    }
+
+    /// Count `path` among the files config-guardian writes, such as the log
+    /// file, which are configured outside of the config file.
+    pub fn add_own_file(&mut self, path: &Path) {
+        self.own_files.extend(resolve(path));
+    }
+
+    /// Whether `path` is a file config-guardian writes itself: the snapshot
+    /// file, its seal and archive, the log file and its rotations, and the
+    /// history and other state files. Scans and `monitor` leave these out,
+    /// or a run of the tool in the directory it guards would drift the
+    /// directory itself, unless `track_self` is set.
+    pub fn is_own_file(&self, path: &Path) -> bool {
+        if self.track_self {
+            return false;
+        }
+        // The archive is a directory of snapshots, so its files count too.
+        path.ancestors().take(2).any(|path| {
+            let Some(name) = path.file_name() else {
+                return false;
+            };
+            let named = |own: &&PathBuf| {
+                own.file_name()
+                    .is_some_and(|own| name == own || is_rotation(name, own))
+            };
+            // Only paths named like an own file need resolving.
+            let mut named_like = self.own_files.iter().filter(named).peekable();
+            named_like.peek().is_some()
+                && resolve(path)
+                    .is_some_and(|resolved| named_like.any(|own| resolved.parent() == own.parent()))
+        })
+    }
+
+    /// The span a new entry for a file of `len` bytes is hashed with: see
+    /// [`FileHash::sampled`](crate::FileHash::sampled).
+    pub fn sample_span(&self, len: u64) -> Option<u64> {
+        self.sample_hash
+            .filter(|&threshold| len > threshold && len > SAMPLE_HASH_SPAN * 2)
+            .map(|_| SAMPLE_HASH_SPAN)
+    }
+
+    /// Whether `file` gets chunks recorded, by `chunked_hash`.
+    pub fn is_chunked(&self, file: &Path) -> bool {
+        if self.chunked_hash.is_empty() {
+            return false;
+        }
+        let full = utils::path_text(file);
+        let name = file.file_name().map(|n| utils::path_text(Path::new(n)));
+        self.chunked_hash.iter().any(|pattern| {
+            pattern.matches(&full) || name.as_deref().is_some_and(|n| pattern.matches(n))
+        })
+    }
+
+    /// Whether the file recorded as `path` is one of the `virtual_files`.
+    pub fn is_virtual(&self, path: &str) -> bool {
+        self.virtual_files.iter().any(|file| file == path)
+    }
+
+    /// Whether a read that failed with `error` is worth another try.
+    pub fn is_transient(&self, error: &std::io::Error) -> bool {
+        error
+            .raw_os_error()
+            .is_some_and(|code| self.retry_on.contains(&code))
+    }
+
+    /// The fields a comparison ignores for `path`: those of the `ignore`
+    /// globs matching it, or else `ignore_fields`.
+    pub fn ignored_fields(&self, path: &str) -> Vec<Field> {
+        let mut matched = false;
+        let mut fields = Vec::new();
+        for (pattern, listed) in &self.ignore {
+            if pattern.matches(path) {
+                matched = true;
+                for field in listed {
+                    if !fields.contains(field) {
+                        fields.push(*field);
+                    }
+                }
+            }
+        }
+        if matched {
+            fields
+        } else {
+            self.ignored.fields.clone()
+        }
+    }
+
+    /// The severity a rule assigns to this drift, if any matches.
+    pub fn severity_override(&self, kind: DriftKind, path: &str) -> Option<Severity> {
+        self.severity
+            .iter()
+            .find(|(pattern, rule_kind, _)| {
+                rule_kind.is_none_or(|k| k == kind) && pattern.matches(path)
+            })
+            .map(|(_, _, severity)| *severity)
+    }
+
+    /// A drift's severity: the first matching rule's, otherwise its kind's.
+    /// No rule lowers a symlink escaping the tree.
+    pub fn severity_of(&self, drift: &Drift) -> Severity {
+        if drift.kind == DriftKind::SymlinkEscaped {
+            return Severity::Critical;
+        }
+        self.severity_override(drift.kind, &drift.path)
+            .unwrap_or_else(|| Severity::of(drift.kind))
+    }
+
+    /// The Nagios state `drift` puts `compare --nagios` in.
+    pub fn nagios_state(&self, drift: &Drift) -> NagiosState {
+        self.nagios.of(drift.kind, self.severity_of(drift))
+    }
+}
+
+/// One scan thread per CPU, but no more than a disk usually keeps busy.
+fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get().min(8))
+}
+
+fn default_retry_on() -> Vec<i32> {
+    utils::TRANSIENT_ERRORS
+        .iter()
+        .map(|(_, code)| *code)
+        .collect()
+}
+
+/// The files config-guardian writes whatever the command, for a snapshot
+/// saved at `snapshot_file`.
+fn own_files(snapshot_file: &Path) -> Vec<PathBuf> {
+    [
+        snapshot_file.to_path_buf(),
+        seal::path_of(snapshot_file),
+        partial_path(snapshot_file),
+        Archive::of(snapshot_file).dir().to_path_buf(),
+        PathBuf::from(HISTORY_FILE),
+        PathBuf::from(RUNS_FILE),
+        PathBuf::from(DIGEST_FILE),
+        PathBuf::from(SENDING_FILE),
+        PathBuf::from(INCIDENTS_FILE),
+        PathBuf::from(EXCEPTIONS_FILE),
+        PathBuf::from(ANNOTATIONS_FILE),
+        PathBuf::from(INSTANCE_ID_FILE),
+        PathBuf::from(CACHE_FILE),
+        PathBuf::from(CONTENT_DIR),
+    ]
+    .iter()
+    .filter_map(|path| resolve(path))
+    .collect()
+}
+
+/// `path` made absolute, through its directory's real path where that
+/// exists, so that `./snapshot.json` and `/srv/app/snapshot.json` compare
+/// equal. The file itself needn't exist.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = std::fs::canonicalize(dir)
+        .or_else(|_| std::path::absolute(dir))
+        .ok()?;
+    Some(dir.join(name))
+}
+
+/// Whether `name` is `own` rotated, as `drift.log.3` is `drift.log`.
+fn is_rotation(name: &OsStr, own: &OsStr) -> bool {
+    name.as_encoded_bytes()
+        .strip_prefix(own.as_encoded_bytes())
+        .and_then(|rest| rest.strip_prefix(b"."))
+        .is_some_and(|n| !n.is_empty() && n.iter().all(u8::is_ascii_digit))
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+/// The settings a monitor took up from its config file last, if it changed.
+static RELOADED: RwLock<Option<&'static Settings>> = RwLock::new(None);
+
+/// Fix the settings for this run. Only the first call has an effect.
+pub fn install(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+/// Replace the settings installed with `settings`, for a monitor whose
+/// config file changed. Those replaced are kept, as callers may still hold
+/// them, so this is for changes made by hand rather than in a loop.
+pub fn reload(settings: Settings) {
+    let settings = Box::leak(Box::new(settings));
+    *RELOADED.write().unwrap_or_else(|e| e.into_inner()) = Some(settings);
+}
+
+pub fn settings() -> &'static Settings {
+    if let Some(settings) = *RELOADED.read().unwrap_or_else(|e| e.into_inner()) {
+        return settings;
+    }
+    SETTINGS.get_or_init(Settings::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+    }
+
+    fn resolve(file: &str, vars: &[(&str, &str)]) -> Result<Config, String> {
+        let mut config: Config = toml::from_str(file).map_err(|e| e.to_string())?;
+        config.apply_env(env(vars))?;
+        Ok(config)
+    }
+
+    #[test]
+    fn environment_overrides_file_which_overrides_defaults() {
+        let file = r#"
+            snapshot = "file.json"
+            hash = "sha512"
+            [monitor]
+            poll = 20
+            alert_cooldown = "5m"
+        "#;
+
+        let defaults = resolve("", &[]).unwrap();
+        assert_eq!(
+            defaults.settings().unwrap().snapshot_file,
+            PathBuf::from(SNAPSHOT_FILE)
+        );
+        assert!(defaults.hash == HashAlgorithm::Sha256);
+        assert_eq!(defaults.monitor.poll, None);
+
+        let from_file = resolve(file, &[]).unwrap();
+        assert_eq!(from_file.snapshot, Some(PathBuf::from("file.json")));
+        assert!(from_file.hash == HashAlgorithm::Sha512);
+        assert_eq!(from_file.monitor.poll, Some(20));
+        assert_eq!(
+            from_file.monitor.alert_cooldown,
+            Some(Duration::from_secs(300))
+        );
+
+        let from_env = resolve(
+            file,
+            &[
+                ("GUARDIAN_SNAPSHOT_FILE", "env.json"),
+                ("GUARDIAN_HASH", "SHA256"),
+                ("GUARDIAN_POLL", "30"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(from_env.snapshot, Some(PathBuf::from("env.json")));
+        assert!(from_env.hash == HashAlgorithm::Sha256);
+        assert_eq!(from_env.monitor.poll, Some(30));
+        // Settings the environment doesn't mention keep the file's value.
+        assert_eq!(
+            from_env.monitor.alert_cooldown,
+            Some(Duration::from_secs(300))
+        );
+    }
+
+    #[test]
+    fn environment_can_configure_email_without_a_file() {
+        let config = resolve(
+            "",
+            &[
+                ("GUARDIAN_SMTP_HOST", "smtp.example.com"),
+                ("GUARDIAN_EMAIL_FROM", "guardian@example.com"),
+                ("GUARDIAN_EMAIL_TO", "a@example.com, b@example.com"),
+                ("GUARDIAN_SMTP_PASSWORD", "hunter2"),
+            ],
+        )
+        .unwrap();
+        let email = config.alerts.email.as_ref().unwrap();
+        assert_eq!(email.host, "smtp.example.com");
+        assert_eq!(email.to, ["a@example.com", "b@example.com"]);
+        assert_eq!(email.password.as_deref(), Some("hunter2"));
+
+        let missing = resolve("", &[("GUARDIAN_SMTP_HOST", "smtp.example.com")]);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn ignore_globs_replace_the_ignored_fields_for_their_paths() {
+        let file = r#"
+            ignore_fields = ["uid", "gid"]
+            [ignore]
+            "var/run/**" = ["mode"]
+            "var/run/app/*" = ["xattrs"]
+            "etc/shadow" = []
+        "#;
+        let settings = resolve(file, &[]).unwrap().settings().unwrap();
+        assert_eq!(
+            settings.ignored_fields("etc/app.conf"),
+            [Field::Uid, Field::Gid]
+        );
+        assert_eq!(
+            settings.ignored_fields("var/run/app/pid"),
+            [Field::Mode, Field::Xattrs]
+        );
+        assert!(settings.ignored_fields("etc/shadow").is_empty());
+        assert_eq!(
+            settings.ignored.to_string(),
+            "uid, gid; nothing for etc/shadow; mode for var/run/**; xattrs for var/run/app/*"
+        );
+
+        let from_env = resolve(file, &[("GUARDIAN_IGNORE_FIELDS", "mtime, size")]).unwrap();
+        assert_eq!(from_env.ignore_fields, [Field::Mtime, Field::Size]);
+        let error = resolve("", &[("GUARDIAN_IGNORE_FIELDS", "owner")])
+            .err()
+            .unwrap();
+        assert!(error.contains("unknown field 'owner'"), "{}", error);
+    }
+
+    #[test]
+    fn nagios_states_come_from_kinds_before_severities() {
+        let file = r#"
+            [nagios]
+            info = "ok"
+            kinds = { metadata = "ok", "permissions_changed,new_setuid" = "critical" }
+        "#;
+        let settings = resolve(file, &[]).unwrap().settings().unwrap();
+        let state = |kind| settings.nagios.of(kind, Severity::of(kind));
+        assert_eq!(state(DriftKind::New), NagiosState::Ok);
+        assert_eq!(state(DriftKind::Changed), NagiosState::Warning);
+        assert_eq!(state(DriftKind::Deleted), NagiosState::Critical);
+        assert_eq!(state(DriftKind::AclChanged), NagiosState::Ok);
+        // Listed twice, it takes the worse.
+        assert_eq!(state(DriftKind::PermissionsChanged), NagiosState::Critical);
+
+        let error = resolve("[nagios]\nkinds = { renamed = \"ok\" }", &[])
+            .unwrap()
+            .settings()
+            .err()
+            .unwrap();
+        assert!(
+            error.starts_with("nagios.kinds: unknown drift kind 'renamed'"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn redact_globs_match_the_path_or_the_file_name() {
+        let file = r#"redact = ["**/secrets/**", "*.key"]"#;
+        let settings = resolve(file, &[]).unwrap().settings().unwrap();
+        assert!(settings.is_redacted("/etc/app/secrets/db.conf"));
+        assert!(settings.is_redacted("etc/secrets/db.conf"));
+        assert!(settings.is_redacted("/etc/ssl/private/tls.key"));
+        assert!(!settings.is_redacted("/etc/app/app.conf"));
+        assert!(!Settings::default().is_redacted("/etc/ssl/private/tls.key"));
+
+        let from_env = resolve("", &[("GUARDIAN_REDACT", "id_*, *.pem")]).unwrap();
+        assert_eq!(from_env.redact, ["id_*", "*.pem"]);
+        assert!(
+            resolve(r#"redact = ["[unclosed"]"#, &[])
+                .unwrap()
+                .problems()
+                .iter()
+                .any(|p| p.contains("[unclosed"))
+        );
+    }
+
+    #[test]
+    fn executables_are_expected_by_directory() {
+        let file = r#"expect_executables = ["/opt/app/hooks", "*/bin"]"#;
+        let settings = resolve(file, &[]).unwrap().settings().unwrap();
+        assert!(settings.expects_executables("/opt/app/hooks/pre-start"));
+        assert!(settings.expects_executables("/usr/local/bin/tool"));
+        assert!(!settings.expects_executables("/opt/app/hooks.d/pre-start"));
+        assert!(!settings.expects_executables("/etc/app/run.sh"));
+    }
+
+    #[test]
+    fn monitor_intervals_of_zero_are_problems() {
+        let config = resolve(
+            "[monitor]\ncompare_every = \"0s\"",
+            &[("GUARDIAN_POLL", "0")],
+        )
+        .unwrap();
+        assert_eq!(
+            config.problems(),
+            [
+                "monitor: poll must be longer than zero",
+                "monitor: compare_every must be longer than zero"
+            ]
+        );
+        let config = resolve("[monitor]\ncompare_every = \"15m\"\npoll = 30", &[]).unwrap();
+        assert!(config.problems().is_empty());
+    }
+
+    #[test]
+    fn invalid_environment_values_name_the_variable() {
+        let error = resolve("", &[("GUARDIAN_COMPARE_EVERY", "soon")])
+            .err()
+            .unwrap();
+        assert!(error.starts_with("GUARDIAN_COMPARE_EVERY: "), "{}", error);
+    }
+
+    #[test]
+    fn baseline_options_fill_in_defaults_and_contradictions_are_listed() {
+        let baseline = ScanOptions {
+            exclude: vec!["*.bak".into()],
+            hash: HashAlgorithm::Sha512,
+            acls: true,
+            ..ScanOptions::default()
+        };
+        let mut config = resolve("", &[]).unwrap();
+        let inherited = config.inherit(&baseline);
+        assert_eq!(inherited.taken, baseline);
+        assert!(inherited.contradicted.is_empty());
+        assert_eq!(config.exclude, ["*.bak"]);
+        assert_eq!(config.hash, HashAlgorithm::Sha512);
+        assert!(config.acls);
+
+        let mut config = resolve("exclude = [\"*.tmp\"]\nattrs = true\n", &[]).unwrap();
+        let inherited = config.inherit(&baseline);
+        assert_eq!(
+            inherited.taken.to_string(),
+            "hash = \"sha512\", acls = true"
+        );
+        assert_eq!(
+            inherited.contradicted,
+            [
+                "exclude = [\"*.tmp\"] where the baseline has exclude = [\"*.bak\"]",
+                "attrs = true where the baseline has attrs = false",
+            ]
+        );
+        assert_eq!(config.exclude, ["*.tmp"]);
+    }
+
+    #[test]
+    fn masked_config_hides_secrets() {
+        let config = resolve(
+            r#"
+            [alerts]
+            slack_webhook = "https://hooks.slack.com/services/T0/B0/secret"
+            webhook_headers = ["Authorization: Bearer secret"]
+            [alerts.email]
+            host = "smtp.example.com"
+            from = "guardian@example.com"
+            to = ["ops@example.com"]
+            "#,
+            &[
+                ("GUARDIAN_TELEGRAM_TOKEN", "123:secret"),
+                ("GUARDIAN_TELEGRAM_CHAT_ID", "42"),
+                ("GUARDIAN_SMTP_PASSWORD", "secret"),
+            ],
+        )
+        .unwrap();
+        let shown = toml::to_string(&config.masked()).unwrap();
+        assert!(!shown.contains("secret"), "{}", shown);
+        assert!(shown.contains("https://hooks.slack.com/********"));
+        assert!(shown.contains("Authorization: ********"));
+        assert!(shown.contains("chat_id = \"42\""));
+    }
+
+    #[test]
+    fn hashes_are_shown_short_full_or_not_at_all() {
+        let hash = "0123456789abcdef0123";
+        assert_eq!(HashDisplay::Short.show(Some(hash)), Some("0123456789ab"));
+        assert_eq!(HashDisplay::Short.show(Some("abc")), Some("abc"));
+        assert_eq!(HashDisplay::Full.show(Some(hash)), Some(hash));
+        assert_eq!(HashDisplay::Full.show(None), Some("-"));
+        assert_eq!(HashDisplay::None.show(Some(hash)), None);
+    }
+
+    #[test]
+    fn own_files_are_known_by_any_path_to_them() {
+        let dir = std::env::temp_dir().join(format!("guardian-own-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut settings = Settings {
+            own_files: own_files(&dir.join("snapshot.json")),
+            ..Settings::default()
+        };
+        settings.add_own_file(&dir.join("drift.log"));
+        for own in [
+            "snapshot.json",
+            "snapshot.json.seal",
+            "drift.log",
+            "drift.log.3",
+            "snapshot.archive/snapshot-20260101T000000.000Z.json",
+        ] {
+            assert!(settings.is_own_file(&dir.join(own)), "{}", own);
+            let roundabout = dir.join("..").join(dir.file_name().unwrap()).join(own);
+            assert!(
+                settings.is_own_file(&roundabout),
+                "{}",
+                roundabout.display()
+            );
+        }
+        for other in ["app.conf", "drift.log.old", "sub/snapshot.json"] {
+            assert!(!settings.is_own_file(&dir.join(other)), "{}", other);
+        }
+        // Elsewhere, a file of the same name is just a file.
+        assert!(!settings.is_own_file(&std::env::temp_dir().join("snapshot.json")));
+        settings.track_self = true;
+        assert!(!settings.is_own_file(&dir.join("snapshot.json")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
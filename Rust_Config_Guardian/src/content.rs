@@ -0,0 +1,122 @@
+//! Content backups: with `keep_content`, the content of each file a
+//! snapshot or `accept` records is kept in `content/`, named by its hash,
+//! so that `accept` can say what changed between the version it replaces
+//! and the one it takes in. Only files up to [`KEEP_LIMIT`] whose content
+//! still hashes as recorded are kept; the rest, and virtual files, whose
+//! value the snapshot holds itself, aren't.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{FileHash, config};
+
+pub const CONTENT_DIR: &str = "content";
+
+/// Larger files aren't kept.
+pub const KEEP_LIMIT: u64 = 1024 * 1024;
+
+fn path_of(hash: &str) -> Option<PathBuf> {
+    // Only ever a hash, so never a path out of the directory.
+    (!hash.is_empty() && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| Path::new(CONTENT_DIR).join(hash))
+}
+
+/// Keep the content of `entries`' files, with `keep_content`. A file that
+/// can't be kept is logged and left out.
+pub fn keep(entries: &[FileHash]) {
+    if !config::settings().keep_content {
+        return;
+    }
+    for entry in entries {
+        if let Err(e) = keep_one(entry) {
+            log::warn!("Could not keep the content of {}: {}", entry.path, e);
+        }
+    }
+}
+
+fn keep_one(entry: &FileHash) -> std::io::Result<()> {
+    let Some(kept) = path_of(&entry.hash) else {
+        return Ok(());
+    };
+    if entry.sampled.is_some()
+        || entry.virtual_value.is_some()
+        || entry.size.is_some_and(|size| size > KEEP_LIMIT)
+        || kept.exists()
+    {
+        return Ok(());
+    }
+    let content = fs::read(entry.file_path())?;
+    // Changed since it was hashed, or normalized before it was.
+    if content.len() as u64 > KEEP_LIMIT || config::settings().hash.digest(&content) != entry.hash {
+        return Ok(());
+    }
+    fs::create_dir_all(CONTENT_DIR)?;
+    let tmp = kept.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, &kept)
+}
+
+/// The content recorded by `entry`: a virtual file's value, or what was
+/// kept of it.
+pub fn kept(entry: &FileHash) -> Option<Vec<u8>> {
+    if let Some(value) = &entry.virtual_value {
+        return Some(value.clone().into_bytes());
+    }
+    fs::read(path_of(&entry.hash)?).ok()
+}
+
+/// How many lines `new` adds to `old` and removes from it, each line
+/// counted as often as it is in one more than in the other; `None` if
+/// either isn't text.
+pub fn line_changes(old: &[u8], new: &[u8]) -> Option<(usize, usize)> {
+    let text = |content| {
+        std::str::from_utf8(content)
+            .ok()
+            .filter(|text| !text.contains('\0'))
+    };
+    let (old, new) = (text(old)?, text(new)?);
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in old.lines() {
+        *counts.entry(line).or_default() -= 1;
+    }
+    for line in new.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+    let added = counts.values().filter(|&&n| n > 0).sum::<isize>() as usize;
+    let removed = counts
+        .values()
+        .filter(|&&n| n < 0)
+        .map(|n| -n)
+        .sum::<isize>() as usize;
+    Some((added, removed))
+}
+
+/// What changed from `old` to `new`, such as `+3 -1 lines`, where the
+/// content of both is known.
+pub fn summary(old: &FileHash, new: &FileHash) -> Option<String> {
+    let (added, removed) = line_changes(&kept(old)?, &kept(new)?)?;
+    Some(format!("+{} -{} lines", added, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_counted_as_added_and_removed() {
+        assert_eq!(line_changes(b"a\nb\nc\n", b"a\nB\nc\nd\n"), Some((2, 1)));
+        assert_eq!(line_changes(b"x\nx\n", b"x\n"), Some((0, 1)));
+        assert_eq!(line_changes(b"same", b"same"), Some((0, 0)));
+        assert_eq!(line_changes(b"", b"one\ntwo"), Some((2, 0)));
+        assert_eq!(line_changes(b"text", b"bin\0ary"), None);
+        assert_eq!(line_changes(b"\xff\xfe", b"text"), None);
+    }
+
+    #[test]
+    fn only_hashes_name_kept_content() {
+        assert!(path_of("3f2a9c").is_some());
+        assert!(path_of("../snapshot.json").is_none());
+        assert!(path_of("").is_none());
+    }
+}
@@ -0,0 +1,238 @@
+//! The control socket a running monitor serves for `status` and `trigger`:
+//! a Unix domain socket on Unix, a named pipe on Windows. A client sends one
+//! line naming what it wants, `status` or `compare-now`, and is answered
+//! with one JSON document, then the connection is closed. A client that
+//! sends nothing is given the status.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+use crate::api::SharedApiState;
+
+/// Pipe served by the Windows service, which has no PID file to derive a
+/// path from.
+pub const DEFAULT_PIPE: &str = r"\\.\pipe\config-guardian";
+
+/// How long the monitor waits for a client to say what it wants before
+/// taking it to want the status.
+const REQUEST_WAIT: Duration = Duration::from_millis(200);
+
+/// The longest request line read.
+const MAX_REQUEST: usize = 64;
+
+/// Where the monitor sends the summary of a comparison asked for with
+/// `compare-now`.
+pub(crate) type Trigger = oneshot::Sender<serde_json::Value>;
+
+/// Ask the monitor serving `path` for its status.
+///
+/// Fails with [`io::ErrorKind::NotFound`] or
+/// [`io::ErrorKind::ConnectionRefused`] when nothing is serving it, and with
+/// [`io::ErrorKind::TimedOut`] when the monitor doesn't answer in time.
+pub fn query(path: &Path, timeout: Duration) -> io::Result<serde_json::Value> {
+    ask(path, "status", timeout)
+}
+
+/// Have the monitor serving `path` run a full comparison now, and return
+/// its summary: the directory, when it was compared, the files tracked and
+/// the drift found, or the `error` it failed with. Fails as [`query`] does.
+pub fn trigger(path: &Path, timeout: Duration) -> io::Result<serde_json::Value> {
+    ask(path, "compare-now", timeout)
+}
+
+fn ask(path: &Path, command: &str, timeout: Duration) -> io::Result<serde_json::Value> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let reply = runtime.block_on(async {
+        tokio::time::timeout(timeout, read_reply(path, command))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+    })?;
+    serde_json::from_slice(&reply).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(unix)]
+async fn read_reply(path: &Path, command: &str) -> io::Result<Vec<u8>> {
+    let mut stream = tokio::net::UnixStream::connect(path).await?;
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    Ok(reply)
+}
+
+#[cfg(windows)]
+async fn read_reply(path: &Path, command: &str) -> io::Result<Vec<u8>> {
+    let mut pipe = tokio::net::windows::named_pipe::ClientOptions::new().open(path)?;
+    pipe.write_all(format!("{}\n", command).as_bytes()).await?;
+    let mut reply = Vec::new();
+    pipe.read_to_end(&mut reply).await?;
+    Ok(reply)
+}
+
+/// The command a client sent: its first line, or `status` if it sent
+/// nothing in time.
+async fn read_request(client: &mut (impl AsyncRead + Unpin)) -> String {
+    let mut request = Vec::new();
+    let read = async {
+        let mut byte = [0; 1];
+        while request.len() < MAX_REQUEST && client.read(&mut byte).await? == 1 {
+            if byte[0] == b'\n' {
+                break;
+            }
+            request.push(byte[0]);
+        }
+        io::Result::Ok(())
+    };
+    let _ = tokio::time::timeout(REQUEST_WAIT, read).await;
+    let request = String::from_utf8_lossy(&request).trim().to_string();
+    if request.is_empty() {
+        "status".to_string()
+    } else {
+        request
+    }
+}
+
+/// The answer to `request`. `compare-now` waits for the monitor to run the
+/// comparison.
+async fn reply(
+    request: &str,
+    state: &SharedApiState,
+    triggers: &mpsc::UnboundedSender<Trigger>,
+) -> Vec<u8> {
+    let answer = match request {
+        "status" => state.read().unwrap_or_else(|e| e.into_inner()).status(),
+        "compare-now" => {
+            let (tx, rx) = oneshot::channel();
+            match triggers.send(tx) {
+                Ok(()) => rx.await.unwrap_or_else(
+                    |_| serde_json::json!({ "error": "the monitor stopped before comparing" }),
+                ),
+                Err(_) => serde_json::json!({ "error": "the monitor is stopping" }),
+            }
+        }
+        other => serde_json::json!({
+            "error": format!("unknown command '{}'; expected status or compare-now", other)
+        }),
+    };
+    let mut json = serde_json::to_vec(&answer).expect("replies serialize to JSON");
+    json.push(b'\n');
+    json
+}
+
+/// Serve one client, on a task of its own so that a comparison it asked
+/// for doesn't hold up the others.
+async fn answer(
+    mut client: impl AsyncRead + AsyncWriteExt + Unpin,
+    state: SharedApiState,
+    triggers: mpsc::UnboundedSender<Trigger>,
+) {
+    let request = read_request(&mut client).await;
+    log::debug!("Control socket request: {}", request);
+    let reply = reply(&request, &state, &triggers).await;
+    if let Err(e) = client.write_all(&reply).await {
+        log::debug!("Control socket client went away: {}", e);
+    }
+}
+
+/// A bound control socket, served by [`Control::serve`].
+pub(crate) struct Control {
+    path: PathBuf,
+    #[cfg(unix)]
+    listener: tokio::net::UnixListener,
+    #[cfg(windows)]
+    pipe: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+impl Control {
+    /// Bind `path`. Fails if another monitor is already serving it; a socket
+    /// left behind by one that died is replaced.
+    #[cfg(unix)]
+    pub(crate) fn bind(path: &Path) -> io::Result<Control> {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another monitor is serving it",
+            ));
+        }
+        let _ = std::fs::remove_file(path);
+        Ok(Control {
+            path: path.to_path_buf(),
+            listener: tokio::net::UnixListener::bind(path)?,
+        })
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn bind(path: &Path) -> io::Result<Control> {
+        Ok(Control {
+            path: path.to_path_buf(),
+            pipe: tokio::net::windows::named_pipe::ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(path)?,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Answer connections until `shutdown`, then remove the socket.
+    /// `compare-now` requests are passed on through `triggers`.
+    #[cfg(unix)]
+    pub(crate) async fn serve(
+        self,
+        state: SharedApiState,
+        triggers: mpsc::UnboundedSender<Trigger>,
+        shutdown: CancellationToken,
+    ) {
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(answer(stream, state.clone(), triggers.clone()));
+                    }
+                    Err(e) => log::warn!("Control socket {}: {}", self.path.display(), e),
+                },
+            }
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+
+    #[cfg(windows)]
+    pub(crate) async fn serve(
+        mut self,
+        state: SharedApiState,
+        triggers: mpsc::UnboundedSender<Trigger>,
+        shutdown: CancellationToken,
+    ) {
+        use tokio::net::windows::named_pipe::ServerOptions;
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                connected = self.pipe.connect() => {
+                    if let Err(e) = connected {
+                        log::warn!("Control pipe {}: {}", self.path.display(), e);
+                        continue;
+                    }
+                    // The next client connects to a fresh instance.
+                    let next = match ServerOptions::new().create(&self.path) {
+                        Ok(next) => next,
+                        Err(e) => {
+                            log::error!("Control pipe {}: {}", self.path.display(), e);
+                            break;
+                        }
+                    };
+                    let client = std::mem::replace(&mut self.pipe, next);
+                    tokio::spawn(answer(client, state.clone(), triggers.clone()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,448 @@
+#[cfg(unix)]
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::control;
+#[cfg(unix)]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Details about a running daemon, stored next to its PID file so `status`
+/// can report what it is watching.
+#[cfg(unix)]
+#[derive(Serialize, Deserialize)]
+struct DaemonInfo {
+    pid: u32,
+    directory: String,
+    started: u64,
+}
+
+pub fn info_path(pid_file: &Path) -> PathBuf {
+    let mut path = pid_file.as_os_str().to_owned();
+    path.push(".info");
+    PathBuf::from(path)
+}
+
+fn read_pid(pid_file: &Path) -> Option<u32> {
+    fs::read_to_string(pid_file).ok()?.trim().parse().ok()
+}
+
+/// Removes the PID file (and its info file) when the daemon shuts down.
+pub struct PidFile {
+    path: PathBuf,
+    pid: u32,
+}
+
+#[cfg(unix)]
+impl PidFile {
+    fn create(path: &Path, directory: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let pid = std::process::id();
+        fs::write(path, format!("{}\n", pid))?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let directory = fs::canonicalize(directory)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| directory.to_string());
+        let info = DaemonInfo {
+            pid,
+            directory,
+            started,
+        };
+        fs::write(info_path(path), serde_json::to_string_pretty(&info)?)?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+            pid,
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Only clean up if a newer daemon has not taken the file over.
+        if read_pid(&self.path) == Some(self.pid) {
+            let _ = fs::remove_file(&self.path);
+            let _ = fs::remove_file(info_path(&self.path));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    // Signal 0 performs the permission and existence checks without sending anything.
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The PID recorded in `pid_file`, if the process it names is no longer
+/// running.
+#[cfg(unix)]
+pub fn stale_pid(pid_file: &Path) -> Option<u32> {
+    read_pid(pid_file).filter(|&pid| !is_alive(pid))
+}
+
+/// PIDs can't be checked here; no PID file is taken to be stale.
+#[cfg(not(unix))]
+pub fn stale_pid(_pid_file: &Path) -> Option<u32> {
+    None
+}
+
+/// Detach from the terminal and keep running in the background, writing our
+/// PID to `pid_file`. Output is redirected to `log_file`.
+#[cfg(unix)]
+pub fn daemonize(
+    pid_file: &Path,
+    directory: &str,
+    log_file: &Path,
+) -> Result<PidFile, Box<dyn std::error::Error>> {
+    use std::fs::{File, OpenOptions};
+    use std::os::unix::io::AsRawFd;
+
+    if let Some(pid) = read_pid(pid_file)
+        && is_alive(pid)
+    {
+        return Err(format!(
+            "A monitor is already running with PID {} (from {}).",
+            pid,
+            pid_file.display()
+        )
+        .into());
+    }
+
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+    let devnull = File::open("/dev/null")?;
+    println!(
+        "Starting monitor in the background; PID will be written to {}",
+        pid_file.display()
+    );
+
+    // Classic double fork: the intermediate session leader exits so the
+    // daemon can never reacquire a controlling terminal.
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(std::io::Error::last_os_error().into()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        match libc::fork() {
+            -1 => return Err(std::io::Error::last_os_error().into()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        libc::dup2(devnull.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+    }
+
+    log::info!("Daemonized with PID {}", std::process::id());
+    PidFile::create(pid_file, directory)
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(
+    _pid_file: &Path,
+    _directory: &str,
+    _log_file: &Path,
+) -> Result<PidFile, Box<dyn std::error::Error>> {
+    Err(
+        "--daemon is only supported on Unix. On Windows, install monitor as a service with \
+         `service install --config <PATH>` (build with --features windows-service)."
+            .into(),
+    )
+}
+
+/// Send SIGTERM to the daemon recorded in `pid_file` and wait for it to
+/// exit, or with `dry_run` print that it would.
+#[cfg(unix)]
+pub fn stop_daemon(pid_file: &Path, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    use std::time::{Duration, Instant};
+
+    let pid = match read_pid(pid_file) {
+        Some(pid) => pid,
+        None => return Err(format!("No PID file found at {}.", pid_file.display()).into()),
+    };
+    if !is_alive(pid) && dry_run {
+        println!(
+            "Would remove the stale PID file {} for PID {}",
+            pid_file.display(),
+            pid
+        );
+        return Ok(());
+    }
+    if !is_alive(pid) {
+        let _ = fs::remove_file(pid_file);
+        let _ = fs::remove_file(info_path(pid_file));
+        return Err(format!(
+            "No monitor running (stale PID file for PID {} removed).",
+            pid
+        )
+        .into());
+    }
+
+    if dry_run {
+        println!("Would send SIGTERM to monitor PID {}", pid);
+        return Ok(());
+    }
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    log::info!("Sent SIGTERM to monitor PID {}", pid);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while is_alive(pid) {
+        if Instant::now() >= deadline {
+            return Err(format!("Monitor PID {} did not exit within 10s.", pid).into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    println!("Stopped monitor (PID {}).", pid);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn stop_daemon(_pid_file: &Path, _dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    Err("stop is only supported on Unix daemons.".into())
+}
+
+/// How long `status` waits for a monitor to answer on its control socket.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The control socket a daemon with this PID file serves.
+#[cfg(unix)]
+pub fn control_path(pid_file: &Path) -> PathBuf {
+    pid_file.with_extension("sock")
+}
+
+/// The Windows service's control pipe; there is no PID file to follow.
+#[cfg(not(unix))]
+pub fn control_path(_pid_file: &Path) -> PathBuf {
+    PathBuf::from(control::DEFAULT_PIPE)
+}
+
+/// Print whether a monitor is running and return an LSB-style status code:
+/// 0 running, 1 dead with a stale PID file or not answering, 3 not running.
+///
+/// The monitor's control socket is asked first; without one, the PID file
+/// tells whether it's alive.
+pub fn daemon_status(pid_file: &Path, control: &Path) -> i32 {
+    match control::query(control, STATUS_TIMEOUT) {
+        Ok(status) => {
+            print_status(&status);
+            0
+        }
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            pid_status(pid_file, control)
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            println!(
+                "A monitor is serving {} but did not answer within {}s.",
+                control.display(),
+                STATUS_TIMEOUT.as_secs()
+            );
+            1
+        }
+        Err(e) => {
+            println!("Could not query {}: {}", control.display(), e);
+            1
+        }
+    }
+}
+
+/// Have the monitor serving `control` run a full comparison now, print its
+/// summary and return the exit status: 0 compared, whether or not drift
+/// was found, 1 the comparison failed or the monitor didn't answer within
+/// `timeout`, 3 no monitor is serving `control`.
+pub fn trigger_comparison(control: &Path, timeout: Duration) -> i32 {
+    let summary = match control::trigger(control, timeout) {
+        Ok(summary) => summary,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            println!("No monitor is serving {}.", control.display());
+            return 3;
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            println!(
+                "The monitor serving {} did not finish the comparison within {}.",
+                control.display(),
+                crate::utils::format_duration(timeout)
+            );
+            return 1;
+        }
+        Err(e) => {
+            println!("Could not ask {}: {}", control.display(), e);
+            return 1;
+        }
+    };
+    let dir = summary["directory"]
+        .as_str()
+        .unwrap_or("the watched directory");
+    if let Some(error) = summary["error"].as_str() {
+        println!("Comparison of {} failed: {}", dir, error);
+        return 1;
+    }
+    let drift: Vec<crate::Drift> =
+        serde_json::from_value(summary["drift"].clone()).unwrap_or_default();
+    if drift.is_empty() {
+        println!(
+            "Compared {}: no drift in {} files.",
+            dir, summary["files_tracked"]
+        );
+    } else {
+        println!("Compared {}: {} drifted files.", dir, drift.len());
+        for entry in &drift {
+            println!("  {}", entry);
+        }
+    }
+    0
+}
+
+fn print_status(status: &serde_json::Value) {
+    let now = chrono::Local::now();
+    let age = |field: &serde_json::Value| {
+        field
+            .as_str()
+            .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| {
+                format!(
+                    "{}s ago",
+                    (now - t.with_timezone(&chrono::Local)).num_seconds()
+                )
+            })
+    };
+    let directories: Vec<&str> = status["directories"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d.as_str())
+        .collect();
+    println!("Monitor running (PID {}).", status["pid"]);
+    println!("  Watching: {}", directories.join(", "));
+    if let Some(started) = age(&status["started"]) {
+        println!("  Started: {}", started);
+    }
+    let baseline = &status["baseline"];
+    println!(
+        "  Baseline: {} ({} files, taken {})",
+        baseline["path"].as_str().unwrap_or("-"),
+        baseline["files"],
+        age(&baseline["modified"])
+            .as_deref()
+            .unwrap_or("at an unknown time")
+    );
+    let config = &status["config"];
+    if config.is_object() {
+        println!(
+            "  Config: {} (sha256 {}, loaded {})",
+            config["path"].as_str().unwrap_or("-"),
+            config["hash"].as_str().unwrap_or("-"),
+            age(&config["loaded"])
+                .as_deref()
+                .unwrap_or("at an unknown time")
+        );
+    }
+    let result = &status["last_result"];
+    let outcome = match result["status"].as_str() {
+        Some("clean") => "no drift".to_string(),
+        Some("drift") => format!("{} drifted files", result["drifted_files"]),
+        Some("error") => format!("failed: {}", result["error"].as_str().unwrap_or("")),
+        _ => String::new(),
+    };
+    match age(&status["last_comparison"]) {
+        Some(when) => println!("  Last comparison: {}, {}", when, outcome),
+        None => println!("  Last comparison: none yet"),
+    }
+    let scan = &status["scan"];
+    if scan.is_object() {
+        let files = match scan["files_expected"].as_u64() {
+            Some(expected) => format!("{} of {} files", scan["files_scanned"], expected),
+            None => format!("{} files", scan["files_scanned"]),
+        };
+        let hashed = crate::utils::human_size(scan["bytes_hashed"].as_u64().unwrap_or(0));
+        let elapsed = format!(
+            "{:.1}s",
+            scan["elapsed_ms"].as_u64().unwrap_or(0) as f64 / 1000.0
+        );
+        if scan["in_progress"] == true {
+            let phase = match scan["phase"].as_str() {
+                Some("hashing") => "hashing",
+                _ => "checking metadata",
+            };
+            println!(
+                "  Scanning: {}, {}, {} hashed, {} so far",
+                phase, files, hashed, elapsed
+            );
+        } else {
+            println!("  Last scan: {}, {} hashed in {}", files, hashed, elapsed);
+        }
+    }
+    println!(
+        "  Pending drift: {}",
+        status["drift"].as_array().map_or(0, Vec::len)
+    );
+    if status["healthy"] == false {
+        println!("  Warning: the monitor isn't watching right now or has stalled.");
+    }
+}
+
+#[cfg(unix)]
+fn pid_status(pid_file: &Path, _control: &Path) -> i32 {
+    let pid = match read_pid(pid_file) {
+        Some(pid) => pid,
+        None => {
+            println!(
+                "No monitor running (no PID file at {}).",
+                pid_file.display()
+            );
+            return 3;
+        }
+    };
+    if !is_alive(pid) {
+        println!(
+            "Monitor is not running, but {} names PID {}.",
+            pid_file.display(),
+            pid
+        );
+        return 1;
+    }
+
+    println!("Monitor running (PID {}).", pid);
+    if let Some(info) = fs::read_to_string(info_path(pid_file))
+        .ok()
+        .and_then(|data| serde_json::from_str::<DaemonInfo>(&data).ok())
+    {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        println!("  Watching: {}", info.directory);
+        println!("  Uptime: {}s", now.saturating_sub(info.started));
+    }
+    0
+}
+
+#[cfg(not(unix))]
+fn pid_status(_pid_file: &Path, control: &Path) -> i32 {
+    println!(
+        "No monitor running (nothing is serving {}).",
+        control.display()
+    );
+    3
+}
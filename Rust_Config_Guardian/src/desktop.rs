@@ -0,0 +1,61 @@
+//! Desktop notifications for monitor mode.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::alert::Alert;
+use crate::log_file;
+
+/// Paths named in the notification body; the rest are counted.
+const MAX_PATHS: usize = 5;
+
+/// Set once a notification has failed, so a headless machine without a
+/// notification daemon warns once rather than on every event.
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+fn body(alert: &Alert) -> String {
+    let mut lines: Vec<String> = alert
+        .drifts
+        .iter()
+        .take(MAX_PATHS)
+        .map(|d| d.to_string())
+        .collect();
+    if alert.drifts.is_empty() {
+        lines.push(format!("{} in {}", alert.summary, alert.dir));
+    } else if alert.drifts.len() > MAX_PATHS {
+        lines.push(format!("…and {} more", alert.drifts.len() - MAX_PATHS));
+    }
+    if let Some(log) = log_file::active_path() {
+        let log = std::fs::canonicalize(log).unwrap_or_else(|_| log.to_path_buf());
+        lines.push(format!("Details: {}", log.display()));
+    }
+    lines.join("\n")
+}
+
+pub fn notify(alert: &Alert) {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname("Config Guardian")
+        .summary("Config drift detected")
+        .body(&body(alert));
+    #[cfg(not(target_os = "macos"))]
+    {
+        use crate::alert::Severity;
+        use notify_rust::Urgency;
+        notification.urgency(match alert.severity() {
+            Severity::Info => Urgency::Low,
+            Severity::Warning => Urgency::Normal,
+            Severity::Critical => Urgency::Critical,
+        });
+    }
+    if let Err(e) = notification.show() {
+        UNAVAILABLE.store(true, Ordering::Relaxed);
+        eprintln!(
+            "Warning: desktop notifications unavailable ({}); continuing without them",
+            e
+        );
+        log::warn!("Desktop notifications disabled: {}", e);
+    }
+}
@@ -0,0 +1,304 @@
+//! Email digests: batching low-severity email alerts into one message a day.
+//!
+//! Alerts are appended to a pending store on disk so they survive restarts
+//! and can be sent either by the monitor at the scheduled time or by
+//! `digest send` from cron.
+
+use chrono::{DateTime, Local, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::Drift;
+use crate::alert::Alert;
+use crate::email::{self, EmailConfig};
+use crate::identity;
+use crate::utils::format_time;
+
+pub const DIGEST_FILE: &str = "digest-pending.jsonl";
+
+/// The alerts being sent: the pending store is moved here before it is
+/// read, so alerts queued during the send go to a new store instead of
+/// being cleared with this one. Left in place if the send fails, to be
+/// sent first the next time.
+pub const SENDING_FILE: &str = "digest-sending.jsonl";
+
+#[derive(Clone, Copy, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestPeriod {
+    Daily,
+}
+
+/// One queued alert.
+#[derive(Serialize, Deserialize)]
+struct PendingAlert {
+    timestamp: DateTime<Utc>,
+    dir: String,
+    summary: String,
+    drifts: Vec<Drift>,
+}
+
+pub fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| format!("invalid time '{}' (expected HH:MM)", value))
+}
+
+/// Append an alert to the pending digest.
+pub fn queue(alert: &Alert) -> Result<(), String> {
+    let entry = PendingAlert {
+        timestamp: Utc::now(),
+        dir: alert.dir.clone(),
+        summary: alert.summary.clone(),
+        drifts: alert.drifts.clone(),
+    };
+    let line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(DIGEST_FILE)
+        .map_err(|e| format!("could not open {}: {}", DIGEST_FILE, e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("could not write {}: {}", DIGEST_FILE, e))?;
+    log::info!("Queued alert for the email digest: {}", alert.summary);
+    Ok(())
+}
+
+fn load_pending(path: &str) -> Result<Vec<PendingAlert>, String> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("could not read {}: {}", path, e)),
+    };
+    let mut pending = Vec::new();
+    for (number, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => pending.push(entry),
+            // A torn final line from a crash shouldn't block the digest.
+            Err(e) => log::warn!("Skipping line {} of {}: {}", number + 1, path, e),
+        }
+    }
+    Ok(pending)
+}
+
+fn body(pending: &[PendingAlert], host: &str) -> String {
+    let changes: usize = pending.iter().map(|p| p.drifts.len()).sum();
+    let mut body = format!(
+        "Config Guardian on {} recorded {} alerts ({} changes) since the last digest.\n",
+        host,
+        pending.len(),
+        changes
+    );
+    for entry in pending {
+        body.push_str(&format!(
+            "\n{} {}: {}\n",
+            format_time(entry.timestamp),
+            entry.dir,
+            entry.summary
+        ));
+        for drift in &entry.drifts {
+            body.push_str(&format!("  {}\n", drift));
+        }
+    }
+    body
+}
+
+/// Send everything pending as one email and clear the store. Returns the
+/// number of alerts sent; nothing is sent when the store is empty. Alerts
+/// left from a send that failed go first, and those queued since wait for
+/// the next digest.
+pub fn send_pending(config: &EmailConfig) -> Result<usize, String> {
+    if !std::path::Path::new(SENDING_FILE).exists() {
+        match std::fs::rename(DIGEST_FILE, SENDING_FILE) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(format!("could not move {} aside: {}", DIGEST_FILE, e)),
+        }
+    }
+    let pending = load_pending(SENDING_FILE)?;
+    if pending.is_empty() {
+        let _ = std::fs::remove_file(SENDING_FILE);
+        return Ok(0);
+    }
+    let host = identity::label();
+    let subject = format!(
+        "[config-guardian] Daily digest: {} alerts on {}",
+        pending.len(),
+        host
+    );
+    email::send_text(config, &subject, body(&pending, &host))?;
+    std::fs::remove_file(SENDING_FILE)
+        .map_err(|e| format!("digest sent but {} not cleared: {}", SENDING_FILE, e))?;
+    log::info!("Email digest sent with {} alerts", pending.len());
+    Ok(pending.len())
+}
+
+/// When the monitor should next send the digest.
+pub struct DigestTimer {
+    at: NaiveTime,
+    next: DateTime<Local>,
+}
+
+impl DigestTimer {
+    pub fn new(at: NaiveTime) -> Self {
+        DigestTimer {
+            at,
+            next: next_occurrence(at, &Local::now()),
+        }
+    }
+
+    pub fn due(&mut self) -> bool {
+        let now = Local::now();
+        if now < self.next {
+            return false;
+        }
+        self.next = next_occurrence(self.at, &now);
+        true
+    }
+}
+
+fn next_occurrence<Tz: TimeZone>(at: NaiveTime, after: &DateTime<Tz>) -> DateTime<Tz> {
+    let mut date = after.date_naive();
+    loop {
+        // A send time skipped by a DST change falls through to the next day.
+        if let Some(candidate) = date
+            .and_time(at)
+            .and_local_timezone(after.timezone())
+            .earliest()
+            && candidate > *after
+        {
+            return candidate;
+        }
+        date = date.succ_opt().expect("date in range");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, LocalResult, NaiveDate, NaiveDateTime};
+
+    /// A zone an hour east of UTC that springs forward to two hours at
+    /// 02:00 local on 2026-03-29, skipping 02:00 to 03:00 that day.
+    #[derive(Clone, Copy, Debug)]
+    struct Springs;
+
+    fn spring() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, 29)
+            .unwrap()
+            .and_hms_opt(2, 0, 0)
+            .unwrap()
+    }
+
+    fn offset(hours: i32) -> FixedOffset {
+        FixedOffset::east_opt(hours * 3600).unwrap()
+    }
+
+    impl TimeZone for Springs {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Springs
+        }
+
+        fn offset_from_local_date(&self, date: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            if *local < spring() {
+                LocalResult::Single(offset(1))
+            } else if *local < spring() + chrono::Duration::hours(1) {
+                LocalResult::None
+            } else {
+                LocalResult::Single(offset(2))
+            }
+        }
+
+        fn offset_from_utc_date(&self, date: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            if *utc < spring() - chrono::Duration::hours(1) {
+                offset(1)
+            } else {
+                offset(2)
+            }
+        }
+    }
+
+    fn at(date: (i32, u32, u32), time: (u32, u32)) -> DateTime<Springs> {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2)
+            .unwrap()
+            .and_hms_opt(time.0, time.1, 0)
+            .unwrap()
+            .and_local_timezone(Springs)
+            .unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn the_digest_is_next_sent_at_the_first_send_time_to_come() {
+        let after = at((2026, 3, 20), (8, 0));
+        assert_eq!(
+            next_occurrence(time(9, 30), &after),
+            at((2026, 3, 20), (9, 30))
+        );
+        assert_eq!(
+            next_occurrence(time(8, 0), &after),
+            at((2026, 3, 21), (8, 0)),
+            "not again at the moment it was sent"
+        );
+        assert_eq!(
+            next_occurrence(time(7, 0), &after),
+            at((2026, 3, 21), (7, 0))
+        );
+    }
+
+    #[test]
+    fn a_send_time_skipped_by_dst_falls_through_to_the_next_day() {
+        let after = at((2026, 3, 28), (23, 0));
+        assert_eq!(
+            next_occurrence(time(2, 30), &after),
+            at((2026, 3, 30), (2, 30))
+        );
+        assert_eq!(
+            next_occurrence(time(3, 30), &after),
+            at((2026, 3, 29), (3, 30))
+        );
+    }
+
+    #[test]
+    fn torn_lines_are_skipped_when_loading() {
+        let path =
+            std::env::temp_dir().join(format!("guardian-digest-{}.jsonl", std::process::id()));
+        let entry = |summary: &str| {
+            serde_json::to_string(&PendingAlert {
+                timestamp: Utc::now(),
+                dir: "/etc/app".into(),
+                summary: summary.into(),
+                drifts: Vec::new(),
+            })
+            .unwrap()
+        };
+        let torn = entry("torn");
+        let data = format!(
+            "{}\n\n{}\n{}",
+            entry("first"),
+            entry("second"),
+            &torn[..torn.len() / 2]
+        );
+        std::fs::write(&path, data).unwrap();
+        let pending = load_pending(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let summaries: Vec<&str> = pending.iter().map(|p| p.summary.as_str()).collect();
+        assert_eq!(summaries, ["first", "second"]);
+
+        assert!(load_pending(path.to_str().unwrap()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,453 @@
+//! `doctor`: check that this host can run config-guardian, before it is
+//! trusted to report drift.
+
+use std::fs;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::digest::DIGEST_FILE;
+use crate::{GuardianError, Snapshot, config, hash_file, history, monitor, redact, seal, utils};
+use config::HashAlgorithm;
+use monitor::{WATCH_LIMIT_HINT, WatchBackend};
+
+/// How long to wait for each alert endpoint to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Size of the file hashed to measure throughput.
+const SAMPLE_SIZE: usize = 8 * 1024 * 1024;
+/// Below this, scanning a large tree takes long enough to notice.
+const SLOW_HASHING: f64 = 20.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    name: String,
+    detail: String,
+    /// What to do about a warning or failure.
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            status: Status::Pass,
+            name: name.into(),
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            status: Status::Warn,
+            hint: Some(hint.into()),
+            ..Check::pass(name, detail)
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            status: Status::Fail,
+            ..Check::warn(name, detail, hint)
+        }
+    }
+
+    fn print(&self) {
+        let status = match self.status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        println!("{}  {}: {}", status, self.name, self.detail);
+        if let Some(hint) = &self.hint {
+            println!("      {}", hint);
+        }
+    }
+}
+
+/// Run every check and print its result; returns the exit code, 1 if any
+/// check failed. `log_file` is the log file in use, if logging to a file.
+pub fn run(config: &config::Config, log_file: Option<&Path>, network: bool) -> i32 {
+    let mut checks = state_directories(log_file);
+    checks.push(snapshot());
+    checks.extend(clock_skew());
+    checks.push(watcher());
+    if network {
+        checks.extend(alert_endpoints(&config.alerts));
+    }
+    checks.push(hash_throughput());
+
+    for check in &checks {
+        check.print();
+    }
+    let count = |status| checks.iter().filter(|c| c.status == status).count();
+    let failed = count(Status::Fail);
+    let warnings = match count(Status::Warn) {
+        1 => "1 warning".to_string(),
+        n => format!("{} warnings", n),
+    };
+    println!(
+        "\n{} passed, {}, {} failed.",
+        count(Status::Pass),
+        warnings,
+        failed
+    );
+    if failed > 0 { 1 } else { 0 }
+}
+
+/// Whether a file can be created in each directory that state is written to.
+fn state_directories(log_file: Option<&Path>) -> Vec<Check> {
+    let snapshot_file = &config::settings().snapshot_file;
+    let files = [
+        Some(snapshot_file.as_path()),
+        log_file,
+        Some(Path::new(history::HISTORY_FILE)),
+        Some(Path::new(DIGEST_FILE)),
+    ];
+    let mut dirs: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    for file in files.into_iter().flatten() {
+        let dir = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let name = file.file_name().map_or_else(
+            || file.display().to_string(),
+            |n| n.to_string_lossy().into(),
+        );
+        match dirs.iter_mut().find(|(d, _)| *d == dir) {
+            Some((_, names)) => names.push(name),
+            None => dirs.push((dir, vec![name])),
+        }
+    }
+    dirs.into_iter()
+        .map(|(dir, names)| {
+            let what = names.join(", ");
+            match writable(&dir) {
+                Ok(()) => Check::pass(
+                    "state directory",
+                    format!("{} is writable ({})", dir.display(), what),
+                ),
+                Err(e) => Check::fail(
+                    "state directory",
+                    format!("cannot write {} in {}: {}", what, dir.display(), e),
+                    "Run from a directory this user can write to, or move the snapshot \
+                     (`snapshot` in the config file) and the log (--log-file) elsewhere.",
+                ),
+            }
+        })
+        .collect()
+}
+
+fn writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".guardian-doctor-{}", std::process::id()));
+    let result = fs::File::create_new(&probe).and_then(|mut f| f.write_all(b"doctor"));
+    let _ = fs::remove_file(&probe);
+    result
+}
+
+/// Whether the snapshot loads, matches its seal and its entries hold
+/// hashes of the algorithm in use.
+fn snapshot() -> Check {
+    let path = &config::settings().snapshot_file;
+    let snapshot = match Snapshot::load(path) {
+        Ok(snapshot) => snapshot,
+        Err(GuardianError::SnapshotMissing { .. }) => {
+            return Check::warn(
+                "snapshot",
+                format!("{} does not exist yet", path.display()),
+                "Take a baseline with `config-guardian snapshot DIRECTORY`.",
+            );
+        }
+        Err(
+            e @ (GuardianError::SnapshotCorrupt { .. } | GuardianError::SnapshotEntries { .. }),
+        ) => {
+            return Check::fail(
+                "snapshot",
+                e.to_string(),
+                "Run `config-guardian recover` to restore it from the archive, or move it aside \
+                 and take a new snapshot.",
+            );
+        }
+        Err(e) => {
+            return Check::fail(
+                "snapshot",
+                e.to_string(),
+                "Check the file's permissions, and that it is not on a failing disk.",
+            );
+        }
+    };
+    match seal::verify(path) {
+        Ok(true) => {}
+        Ok(false) => {
+            return Check::fail(
+                "snapshot",
+                format!("{} doesn't match its seal", path.display()),
+                "Something other than config-guardian changed it; find out what, then \
+                 restore it from a backup or take a new snapshot.",
+            );
+        }
+        Err(e) => {
+            return Check::fail(
+                "snapshot",
+                format!("could not check {} against its seal: {}", path.display(), e),
+                "Check the file's permissions, and that it is not on a failing disk.",
+            );
+        }
+    }
+    let algorithm = snapshot.metadata.hash;
+    let malformed: Vec<_> = snapshot
+        .entries
+        .iter()
+        .filter(|f| !well_formed(&f.hash, algorithm))
+        .collect();
+    if let Some(first) = malformed.first() {
+        return Check::fail(
+            "snapshot",
+            format!(
+                "{} of {} entries in {} are not {} hashes (first: {})",
+                malformed.len(),
+                snapshot.entries.len(),
+                path.display(),
+                algorithm,
+                first.path
+            ),
+            "The file was edited or damaged; restore it from a backup or take a new snapshot.",
+        );
+    }
+    if algorithm != config::settings().hash {
+        return Check::warn(
+            "snapshot",
+            format!(
+                "{} was taken with {}, but the config uses {}",
+                path.display(),
+                algorithm,
+                config::settings().hash
+            ),
+            "Every file will show as changed; take a new snapshot after switching algorithms.",
+        );
+    }
+    let taken = match snapshot.metadata.created {
+        Some(created) => format!("taken {}", utils::format_time(created)),
+        None => "saved by a version that recorded no metadata".to_string(),
+    };
+    Check::pass(
+        "snapshot",
+        format!(
+            "{} holds {} files, {}",
+            path.display(),
+            snapshot.entries.len(),
+            taken
+        ),
+    )
+}
+
+/// Whether any file the snapshot tracks was modified further in the future
+/// than `clock_skew` allows, naming the one furthest ahead. `None` without
+/// a snapshot, which [`snapshot`] reports.
+fn clock_skew() -> Option<Check> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file).ok()?;
+    let skew = config::settings().clock_skew;
+    let now = SystemTime::now();
+    let ahead: Vec<(Duration, &str)> = snapshot
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let modified = fs::symlink_metadata(&entry.path).ok()?.modified().ok()?;
+            let ahead = modified.duration_since(now).ok()?;
+            (ahead > skew).then_some((ahead, entry.path.as_str()))
+        })
+        .collect();
+    let Some((furthest, path)) = ahead.iter().max() else {
+        return Some(Check::pass(
+            "clock",
+            format!(
+                "no tracked file is modified more than {} in the future",
+                utils::format_duration(skew)
+            ),
+        ));
+    };
+    // In the largest whole unit it's ahead by.
+    let secs = furthest.as_secs();
+    let unit = [86400, 3600, 60]
+        .into_iter()
+        .find(|u| secs >= *u)
+        .unwrap_or(1);
+    let files = match ahead.len() {
+        1 => "1 tracked file is".to_string(),
+        n => format!("{} tracked files are", n),
+    };
+    Some(Check::warn(
+        "clock",
+        format!(
+            "{} modified in the future, {} by over {}",
+            files,
+            redact::shown(path),
+            utils::format_duration(Duration::from_secs(secs / unit * unit))
+        ),
+        "The clock is wrong, or was when they were written: check the RTC and NTP, then touch \
+         the files. Comparisons read them in full meanwhile.",
+    ))
+}
+
+fn well_formed(hash: &str, algorithm: HashAlgorithm) -> bool {
+    hash.len() == algorithm.hex_len() && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Whether the native backend can watch a directory, as `monitor` would.
+fn watcher() -> Check {
+    let dir = scratch_dir("watch");
+    if let Err(e) = fs::create_dir_all(&dir) {
+        return Check::fail(
+            "watcher",
+            format!("could not create {}: {}", dir.display(), e),
+            "Make the temporary directory writable, or set TMPDIR.",
+        );
+    }
+    let path = dir.to_string_lossy();
+    let native = WatchBackend::Auto.probe(&path);
+    let check = match native {
+        Ok(()) => Check::pass("watcher", "the native backend can watch directories"),
+        Err(e) => {
+            let limit = matches!(e.kind, notify::ErrorKind::MaxFilesWatch);
+            match WatchBackend::Poll.probe(&path) {
+                Ok(()) => Check::warn(
+                    "watcher",
+                    format!(
+                        "the native backend failed ({}); monitor would fall back to polling",
+                        e
+                    ),
+                    if limit {
+                        WATCH_LIMIT_HINT
+                    } else {
+                        "Polling notices changes up to ten seconds late; \
+                         pass --watch-backend poll to choose it explicitly."
+                    },
+                ),
+                Err(poll) => Check::fail(
+                    "watcher",
+                    format!("no backend can watch ({}; polling: {})", e, poll),
+                    "Use `monitor --schedule-only --compare-every 5m`, or `compare` from cron.",
+                ),
+            }
+        }
+    };
+    let _ = fs::remove_dir_all(&dir);
+    check
+}
+
+/// Whether each configured alert channel's server accepts a connection.
+/// Nothing is sent.
+fn alert_endpoints(alerts: &config::AlertSettings) -> Vec<Check> {
+    let channels = match alerts.channels(config::DEFAULT_WEBHOOK_TIMEOUT) {
+        Ok(channels) => channels,
+        Err(e) => {
+            return vec![Check::fail(
+                "alerts",
+                e,
+                "Fix the [alerts] section; `config validate` lists every problem.",
+            )];
+        }
+    };
+    let endpoints = channels.endpoints();
+    if endpoints.is_empty() {
+        return vec![Check::warn(
+            "alerts",
+            "no alert channels are configured",
+            "Drift only reaches the log; configure a channel under [alerts] to be told about it.",
+        )];
+    }
+    endpoints
+        .into_iter()
+        .map(|(channel, address)| {
+            let name = format!("{} alerts", channel);
+            let Some(address) = address else {
+                return Check::fail(
+                    name,
+                    "the URL has no host to connect to",
+                    "Give an http:// or https:// URL.",
+                );
+            };
+            match connect(&address) {
+                Ok(elapsed) => Check::pass(
+                    name,
+                    format!(
+                        "{} accepted a connection in {}ms",
+                        address,
+                        elapsed.as_millis()
+                    ),
+                ),
+                Err(e) => Check::fail(
+                    name,
+                    format!("could not connect to {}: {}", address, e),
+                    "Check DNS, the firewall and any proxy between this host and the server.",
+                ),
+            }
+        })
+        .collect()
+}
+
+fn connect(address: &str) -> std::io::Result<Duration> {
+    let start = Instant::now();
+    let mut last = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(_) => return Ok(start.elapsed()),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| std::io::Error::other("the name resolved to no addresses")))
+}
+
+/// How fast files hash with the configured algorithm.
+fn hash_throughput() -> Check {
+    let dir = scratch_dir("hash");
+    let sample = dir.join("sample");
+    let content: Vec<u8> = (0..SAMPLE_SIZE).map(|i| (i % 251) as u8).collect();
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| fs::write(&sample, &content)) {
+        let _ = fs::remove_dir_all(&dir);
+        return Check::fail(
+            "hashing",
+            format!("could not write a sample file: {}", e),
+            "Make the temporary directory writable, or set TMPDIR.",
+        );
+    }
+    let start = Instant::now();
+    let result = hash_file(&sample);
+    let elapsed = start.elapsed();
+    let _ = fs::remove_dir_all(&dir);
+    let algorithm = config::settings().hash;
+    if let Err(e) = result {
+        return Check::fail(
+            "hashing",
+            format!("could not hash a sample file: {}", e),
+            "Check the temporary directory's filesystem.",
+        );
+    }
+    let rate = SAMPLE_SIZE as f64 / elapsed.as_secs_f64().max(1e-6) / 1e6;
+    let detail = format!("{} at {:.0} MB/s", algorithm, rate);
+    if rate < SLOW_HASHING {
+        Check::warn(
+            "hashing",
+            detail,
+            "Scans of large trees will be slow; check the CPU load, and exclude large files \
+             that aren't configuration.",
+        )
+    } else {
+        Check::pass("hashing", detail)
+    }
+}
+
+fn scratch_dir(purpose: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "guardian-doctor-{}-{}",
+        purpose,
+        std::process::id()
+    ))
+}
@@ -0,0 +1,2128 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::GuardianError;
+use crate::alert::{self, Alert, AlertChannels};
+use crate::annotation::{self, Annotation};
+use crate::chunk::{self, ChunkDiff};
+use crate::config::{self, Field, IgnoredFields, Ownership};
+use crate::exception::{self, Excepted};
+use crate::history;
+use crate::identity;
+use crate::invoker::{self, Invoker};
+use crate::redact;
+use crate::snapshot::{
+    EntryType, FileHash, ScanWarning, Snapshot, WarningKind, baseline_at, format_tags, hash_of,
+    restat, scan_against, scan_directory, snapshot_at,
+};
+use crate::staged;
+use crate::utils;
+
+/// Kind of difference found between the snapshot and the directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftKind {
+    Changed,
+    /// Changed to nothing: the file was emptied, as a failed write or a
+    /// stray `>` leaves it.
+    Truncated,
+    /// Changed, from empty: a placeholder file was given content.
+    Populated,
+    New,
+    /// New, with an execute bit set. Reported as [`DriftKind::New`] where
+    /// `expect_executables` says executables belong.
+    #[serde(rename = "new_executable")]
+    NewExecutable,
+    /// New, with the setuid or setgid bit set.
+    #[serde(rename = "new_setuid")]
+    NewSetuid,
+    Deleted,
+    /// In the snapshot and still there, but its content couldn't be read.
+    Unreadable,
+    /// In the snapshot and still there, but another process has it open
+    /// exclusively, as Windows services do with their configs.
+    Locked,
+    /// Changed, and now on a different mount: something was mounted over
+    /// it. Only detected with `detect_mounts`.
+    #[serde(rename = "mount_changed")]
+    MountChanged,
+    /// Same content, but the immutable or append-only attribute was set or
+    /// cleared. Only detected with `attrs`.
+    #[serde(rename = "attributes_changed")]
+    AttributesChanged,
+    /// Same content, but POSIX ACL entries were added, removed or edited.
+    /// Only detected with `acls`.
+    #[serde(rename = "acl_changed")]
+    AclChanged,
+    /// Changed content in a new inode: the file was replaced rather than
+    /// edited in place, which also breaks any hard links to it.
+    #[serde(rename = "inode_replaced")]
+    InodeReplaced,
+    /// Same content, but a hard link to it was added or removed.
+    #[serde(rename = "link_count_changed")]
+    LinkCountChanged,
+    /// Same content, but its mode bits or owner changed. Recorded on Unix.
+    #[serde(rename = "permissions_changed")]
+    PermissionsChanged,
+    /// The path is no longer what it was: a file became a symlink, or a
+    /// directory, or the other way round.
+    #[serde(rename = "type_changed")]
+    TypeChanged,
+    /// A symlink leads somewhere else: its target resolves to another
+    /// path, however it is spelled.
+    #[serde(rename = "symlink_retargeted")]
+    SymlinkRetargeted,
+    /// A symlink that led to a path in the directory scanned now leads out
+    /// of it, as a symlink attack's does; also a new symlink leading out of
+    /// it. A file replaced by a symlink is a type change, wherever it
+    /// leads.
+    #[serde(rename = "symlink_escaped")]
+    SymlinkEscaped,
+    /// The snapshot file itself was changed, or removed, by something other
+    /// than config-guardian. Only `monitor` reports it, naming the file.
+    #[serde(rename = "baseline_modified")]
+    BaselineModified,
+}
+
+impl DriftKind {
+    pub const ALL: [DriftKind; 19] = [
+        DriftKind::Changed,
+        DriftKind::Truncated,
+        DriftKind::Populated,
+        DriftKind::New,
+        DriftKind::NewExecutable,
+        DriftKind::NewSetuid,
+        DriftKind::Deleted,
+        DriftKind::Unreadable,
+        DriftKind::Locked,
+        DriftKind::MountChanged,
+        DriftKind::AttributesChanged,
+        DriftKind::AclChanged,
+        DriftKind::InodeReplaced,
+        DriftKind::LinkCountChanged,
+        DriftKind::PermissionsChanged,
+        DriftKind::TypeChanged,
+        DriftKind::SymlinkRetargeted,
+        DriftKind::SymlinkEscaped,
+        DriftKind::BaselineModified,
+    ];
+
+    /// The kinds where the content is the same and only what's recorded
+    /// about the file changed.
+    pub const METADATA: [DriftKind; 4] = [
+        DriftKind::AttributesChanged,
+        DriftKind::AclChanged,
+        DriftKind::LinkCountChanged,
+        DriftKind::PermissionsChanged,
+    ];
+
+    /// Whether this is a new executable or setuid file.
+    pub fn is_executable(self) -> bool {
+        matches!(self, DriftKind::NewExecutable | DriftKind::NewSetuid)
+    }
+
+    /// Whether this is a new executable or setuid file, or a symlink
+    /// escaping the directory scanned: reported first, and alerted on past
+    /// any cooldown, email digest or severity rule.
+    pub fn is_urgent(self) -> bool {
+        self.is_executable() || self == DriftKind::SymlinkEscaped
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DriftKind::Changed => "changed",
+            DriftKind::Truncated => "truncated",
+            DriftKind::Populated => "populated",
+            DriftKind::New => "new",
+            DriftKind::NewExecutable => "new_executable",
+            DriftKind::NewSetuid => "new_setuid",
+            DriftKind::Deleted => "deleted",
+            DriftKind::Unreadable => "unreadable",
+            DriftKind::Locked => "locked",
+            DriftKind::MountChanged => "mount_changed",
+            DriftKind::AttributesChanged => "attributes_changed",
+            DriftKind::AclChanged => "acl_changed",
+            DriftKind::InodeReplaced => "inode_replaced",
+            DriftKind::LinkCountChanged => "link_count_changed",
+            DriftKind::PermissionsChanged => "permissions_changed",
+            DriftKind::TypeChanged => "type_changed",
+            DriftKind::SymlinkRetargeted => "symlink_retargeted",
+            DriftKind::SymlinkEscaped => "symlink_escaped",
+            DriftKind::BaselineModified => "baseline_modified",
+        }
+    }
+}
+
+/// Parse a comma-separated list of drift kinds by their names, such as
+/// `changed,deleted`; `all` stands for every kind and `metadata` for
+/// [`DriftKind::METADATA`].
+pub fn parse_kinds(list: &str) -> Result<Vec<DriftKind>, String> {
+    let mut kinds = Vec::new();
+    for name in list.split(',').map(str::trim) {
+        let named: &[DriftKind] = match name {
+            "all" => &DriftKind::ALL,
+            "metadata" => &DriftKind::METADATA,
+            "none" => &[],
+            _ => match DriftKind::ALL.iter().find(|k| k.as_str() == name) {
+                Some(kind) => std::slice::from_ref(kind),
+                None => {
+                    let names: Vec<&str> = DriftKind::ALL.iter().map(|k| k.as_str()).collect();
+                    return Err(format!(
+                        "unknown drift kind '{}' (expected all, metadata, none, {})",
+                        name,
+                        names.join(", ")
+                    ));
+                }
+            },
+        };
+        for kind in named {
+            if !kinds.contains(kind) {
+                kinds.push(*kind);
+            }
+        }
+    }
+    Ok(kinds)
+}
+
+impl fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriftKind::Changed => write!(f, "Changed"),
+            DriftKind::Truncated => write!(f, "Truncated"),
+            DriftKind::Populated => write!(f, "Populated"),
+            DriftKind::New => write!(f, "New"),
+            DriftKind::NewExecutable => write!(f, "New executable"),
+            DriftKind::NewSetuid => write!(f, "New setuid binary"),
+            DriftKind::Deleted => write!(f, "Deleted"),
+            DriftKind::Unreadable => write!(f, "Unreadable"),
+            DriftKind::Locked => write!(f, "Locked"),
+            DriftKind::MountChanged => write!(f, "Mount changed over"),
+            DriftKind::AttributesChanged => write!(f, "Attributes changed"),
+            DriftKind::AclChanged => write!(f, "ACL changed"),
+            DriftKind::InodeReplaced => write!(f, "Inode replaced"),
+            DriftKind::LinkCountChanged => write!(f, "Link count changed"),
+            DriftKind::PermissionsChanged => write!(f, "Permissions changed"),
+            DriftKind::TypeChanged => write!(f, "Type changed"),
+            DriftKind::SymlinkRetargeted => write!(f, "Symlink retargeted"),
+            DriftKind::SymlinkEscaped => write!(f, "Symlink retargeted out of the tree"),
+            DriftKind::BaselineModified => {
+                write!(f, "Baseline modified outside of config-guardian")
+            }
+        }
+    }
+}
+
+/// One file that differs from the snapshot.
+///
+/// ```
+/// use config_guardian::{Drift, DriftKind};
+///
+/// let drift = Drift { kind: DriftKind::Changed, path: "/etc/app.conf".to_string(), reason: None };
+/// assert_eq!(drift.to_string(), "Changed: /etc/app.conf");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Drift {
+    pub kind: DriftKind,
+    pub path: String,
+    /// Why, where the kind alone doesn't say: the read error of an
+    /// unreadable file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Drift {
+    /// One drift entry as a self-contained event object, for streaming
+    /// consumers.
+    pub fn event(&self, dir: &str) -> serde_json::Value {
+        serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "directory": dir,
+            "kind": self.kind,
+            "path": self.path,
+        })
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.path)?;
+        if let Some(reason) = &self.reason {
+            write!(f, " ({})", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// `println!`, unless stdout is kept for a machine-readable report; see
+/// [`config::Settings::quiet`].
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !config::settings().quiet {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Result of comparing a directory against the snapshot.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DriftReport {
+    pub drifts: Vec<Drift>,
+    /// Number of files found in the directory during the comparison.
+    pub files_tracked: usize,
+    /// Problems met while scanning the directory.
+    #[serde(default)]
+    pub warnings: Vec<ScanWarning>,
+    /// Files tagged volatile whose content changed, left out of `drifts`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volatile: Vec<String>,
+    /// Unreadable files left out of `drifts` by `ignore_unreadable`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unreadable: Vec<Drift>,
+    /// Drift left out of `drifts` by an exception.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excepted: Vec<Excepted>,
+    /// The baseline snapshot's tags.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// The [`alert::score`] of `drifts`.
+    #[serde(default)]
+    pub score: u64,
+    /// Each drifting file's hash in the baseline and now, by path.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hashes: BTreeMap<String, Hashes>,
+    /// Where the drifted files with chunks on both sides changed, by path;
+    /// see [`config::Settings::chunked_hash`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub chunks: BTreeMap<String, ChunkDiff>,
+    /// The annotations of the files that drifted, by path.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, Annotation>,
+    /// The metadata fields the comparison ignored.
+    #[serde(default, skip_serializing_if = "IgnoredFields::is_empty")]
+    pub ignored: IgnoredFields,
+    /// Whole days since the baseline took the directory's files, where
+    /// that is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_age_days: Option<u64>,
+    /// Who ran the comparison, and how.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoker: Option<Invoker>,
+    /// The identity of the machine it ran on; see [`identity`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// The drift there would be with staged changes applied, rather than
+    /// drift there is; see [`compare_staged`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hypothetical: bool,
+    /// The subtrees the comparison was narrowed to, if any; see
+    /// [`config::Settings::scope`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scope: Vec<String>,
+    /// How many baseline entries `baseline_exclude` left out of the
+    /// comparison; see [`config::Settings::baseline_exclude`].
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub baseline_excluded: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// A file's content hash before and after it drifted; `None` on the side
+/// that has no hash, such as the baseline of a new file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hashes {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// `curr` with the `fields` ignored for it taken from `prev`, so they
+/// compare equal.
+fn ignoring<'a>(prev: &FileHash, curr: &'a FileHash, fields: &[Field]) -> Cow<'a, FileHash> {
+    if fields.is_empty() {
+        return Cow::Borrowed(curr);
+    }
+    let mut curr = curr.clone();
+    if let (Some(was), Some(now)) = (&prev.permissions, &mut curr.permissions) {
+        for field in fields {
+            match field {
+                Field::Uid => {
+                    now.uid = was.uid;
+                    now.user = was.user.clone();
+                }
+                Field::Gid => {
+                    now.gid = was.gid;
+                    now.group = was.group.clone();
+                }
+                Field::Mode => now.mode = was.mode,
+                _ => {}
+            }
+        }
+    }
+    for field in fields {
+        match field {
+            Field::Mtime => curr.modified = prev.modified,
+            Field::Size => curr.size = prev.size,
+            Field::Xattrs => {
+                curr.attributes = prev.attributes.clone();
+                curr.acl = prev.acl.clone();
+            }
+            _ => {}
+        }
+    }
+    Cow::Owned(curr)
+}
+
+/// How `curr` differs from `prev`, the same file's entry in the baseline.
+/// A content change is reported over any other.
+fn change(prev: &FileHash, curr: &FileHash, acls: bool, by: Ownership) -> Option<DriftKind> {
+    fn differ<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+        matches!((a, b), (Some(a), Some(b)) if a != b)
+    }
+    // A path that is no longer a file has no content to compare, and an
+    // entry recorded before types were has none to compare either.
+    let no_longer_a_file = curr.hash.is_empty()
+        && !curr.locked
+        && curr.entry_type.is_some_and(|t| t != EntryType::File);
+    if no_longer_a_file || differ(&prev.entry_type, &curr.entry_type) {
+        return Some(DriftKind::TypeChanged);
+    }
+    // Where it leads explains a change to the content it leads to. Targets
+    // recorded before they were resolved compare as spelled.
+    let retargeted = match (&prev.link_resolved, &curr.link_resolved) {
+        (Some(a), Some(b)) => a != b,
+        _ => differ(&prev.link_target, &curr.link_target),
+    };
+    if retargeted {
+        return Some(match curr.link_escapes && !prev.link_escapes {
+            true => DriftKind::SymlinkEscaped,
+            false => DriftKind::SymlinkRetargeted,
+        });
+    }
+    // Nothing can be told about a file that can't be opened, or compared
+    // with one that never could be, but a file becoming locked is news.
+    if curr.locked {
+        return (!prev.locked).then_some(DriftKind::Locked);
+    }
+    if prev.locked && prev.hash.is_empty() {
+        return None;
+    }
+    let inodes = prev.inode.zip(curr.inode);
+    if prev.hash != curr.hash {
+        Some(if differ(&prev.mount, &curr.mount) {
+            DriftKind::MountChanged
+        } else if curr.size == Some(0) && prev.size.is_some_and(|size| size > 0) {
+            DriftKind::Truncated
+        } else if prev.size == Some(0) && curr.size.is_some_and(|size| size > 0) {
+            DriftKind::Populated
+        } else if inodes.is_some_and(|(a, b)| a.replaced_by(&b)) {
+            DriftKind::InodeReplaced
+        } else {
+            DriftKind::Changed
+        })
+    } else if differ(&prev.attributes, &curr.attributes) {
+        Some(DriftKind::AttributesChanged)
+    } else if matches!((&prev.permissions, &curr.permissions), (Some(a), Some(b)) if !a.same_as(b, by))
+    {
+        Some(DriftKind::PermissionsChanged)
+    } else if inodes.is_some_and(|(a, b)| a.nlink != b.nlink) {
+        Some(DriftKind::LinkCountChanged)
+    } else if acls && prev.acl != curr.acl {
+        Some(DriftKind::AclChanged)
+    } else {
+        None
+    }
+}
+
+/// What a file the baseline doesn't have is: a new executable, or setuid or
+/// setgid file, where its mode bits were recorded, is more than a new file.
+fn new_kind(entry: &FileHash, settings: &config::Settings) -> DriftKind {
+    if entry.link_escapes {
+        return DriftKind::SymlinkEscaped;
+    }
+    let Some(permissions) = &entry.permissions else {
+        return DriftKind::New;
+    };
+    let file = entry.entry_type.is_none_or(|t| t == EntryType::File);
+    if !file || settings.expects_executables(&entry.path) {
+        DriftKind::New
+    } else if permissions.mode & 0o6000 != 0 {
+        DriftKind::NewSetuid
+    } else if permissions.mode & 0o111 != 0 {
+        DriftKind::NewExecutable
+    } else {
+        DriftKind::New
+    }
+}
+
+/// The files of `current` that were changed or added since `baseline`,
+/// followed by the files of `baseline` that are gone.
+///
+/// ```
+/// use config_guardian::{DriftKind, FileHash, Snapshot, compare};
+///
+/// let entry = |path: &str, hash: &str| FileHash {
+///     path: path.into(),
+///     hash: hash.into(),
+///     ..FileHash::default()
+/// };
+/// let baseline = Snapshot {
+///     entries: vec![entry("a.conf", "1"), entry("b.conf", "2")],
+///     ..Snapshot::default()
+/// };
+/// let current = Snapshot {
+///     entries: vec![entry("a.conf", "3"), entry("c.conf", "4")],
+///     ..Snapshot::default()
+/// };
+///
+/// let report = compare(&baseline, &current);
+/// let kinds: Vec<DriftKind> = report.drifts.iter().map(|d| d.kind).collect();
+/// assert_eq!(kinds, [DriftKind::Changed, DriftKind::New, DriftKind::Deleted]);
+/// assert_eq!(report.files_tracked, 2);
+/// ```
+pub fn compare(baseline: &Snapshot, current: &Snapshot) -> DriftReport {
+    let mut drifts = Vec::new();
+
+    // Detect new or changed files
+    // A file without an ACL records none, so ACLs only compare when both
+    // sides looked for them.
+    let acls = baseline.metadata.acls && current.metadata.acls;
+    let (before, now) = (baseline.index(), current.index());
+    let settings = config::settings();
+    let mut chunks = BTreeMap::new();
+    for curr in &current.entries {
+        if let Some(prev) = before.get(curr.path.as_str()) {
+            let curr = &*ignoring(prev, curr, &settings.ignored_fields(&curr.path));
+            if let Some(kind) = change(prev, curr, acls, settings.ownership) {
+                let chunked = match (kind, &prev.chunks, &curr.chunks) {
+                    (DriftKind::Changed, Some(old), Some(new)) => chunk::diff(old, new),
+                    _ => None,
+                };
+                let reason = match (kind, &prev.permissions, &curr.permissions) {
+                    (DriftKind::TypeChanged, ..) => Some(format!(
+                        "{} → {}",
+                        prev.describe_type(),
+                        curr.describe_type()
+                    )),
+                    (DriftKind::SymlinkRetargeted | DriftKind::SymlinkEscaped, ..) => {
+                        Some(format!(
+                            "{} → {}",
+                            prev.link_target
+                                .clone()
+                                .unwrap_or_else(|| prev.describe_type()),
+                            curr.link_target.as_deref().unwrap_or_default()
+                        ))
+                    }
+                    (DriftKind::PermissionsChanged, Some(a), Some(b)) => {
+                        Some(a.describe_change(b, settings.ownership))
+                    }
+                    (DriftKind::Locked, ..) => {
+                        Some("another process has it open exclusively".to_string())
+                    }
+                    (DriftKind::Truncated, ..) => prev
+                        .size
+                        .map(|size| format!("was {}", utils::human_size(size))),
+                    (DriftKind::Populated, ..) => curr
+                        .size
+                        .map(|size| format!("now {}", utils::human_size(size))),
+                    // Virtual files hold a value or two, shown as they were.
+                    (DriftKind::Changed, ..) if prev.virtual_value.is_some() => {
+                        let shown = |f: &FileHash| {
+                            format!(
+                                "{:?}",
+                                f.virtual_value.as_deref().unwrap_or_default().trim_end()
+                            )
+                        };
+                        Some(format!("{} → {}", shown(prev), shown(curr)))
+                    }
+                    (DriftKind::Changed, ..) => chunked.as_ref().map(ChunkDiff::describe),
+                    _ => None,
+                };
+                if let Some(diff) = chunked {
+                    chunks.insert(curr.path.clone(), diff);
+                }
+                drifts.push(Drift {
+                    kind,
+                    path: curr.path.clone(),
+                    reason,
+                });
+            }
+        } else {
+            let kind = new_kind(curr, settings);
+            drifts.push(Drift {
+                kind,
+                path: curr.path.clone(),
+                reason: (kind == DriftKind::SymlinkEscaped)
+                    .then(|| format!("new → {}", curr.link_target.as_deref().unwrap_or_default())),
+            });
+        }
+    }
+
+    // Detect deleted files
+    for prev in &baseline.entries {
+        if !now.contains_key(prev.path.as_str()) {
+            drifts.push(Drift {
+                kind: DriftKind::Deleted,
+                path: prev.path.clone(),
+                reason: None,
+            });
+        }
+    }
+
+    // New setuid files and executables, and links out of the tree, come
+    // first, so they aren't lost in the rest.
+    drifts.sort_by_key(|d| (!d.kind.is_urgent(), d.kind != DriftKind::NewSetuid));
+
+    let hashed = |index: &HashMap<&str, &FileHash>, path: &str| {
+        hash_of(index, path).filter(|hash| !hash.is_empty())
+    };
+    let hashes = drifts
+        .iter()
+        .map(|drift| {
+            let hashes = Hashes {
+                old: hashed(&before, &drift.path),
+                new: hashed(&now, &drift.path),
+            };
+            (drift.path.clone(), hashes)
+        })
+        .collect();
+    DriftReport {
+        score: alert::score(&drifts, config::settings()),
+        hashes,
+        chunks,
+        drifts,
+        files_tracked: current.entries.len(),
+        warnings: Vec::new(),
+        volatile: Vec::new(),
+        unreadable: Vec::new(),
+        excepted: Vec::new(),
+        tags: baseline.metadata.tags.clone(),
+        annotations: BTreeMap::new(),
+        ignored: settings.ignored.clone(),
+        baseline_age_days: None,
+        invoker: Some(invoker::current().clone()),
+        instance_id: Some(identity::current().to_string()),
+        hypothetical: false,
+        scope: Vec::new(),
+        baseline_excluded: 0,
+    }
+}
+
+/// Move content changes to files tagged volatile in either snapshot out of
+/// the report's drift and into its `volatile` list.
+fn set_aside_volatile(report: &mut DriftReport, baseline: &Snapshot, current: &Snapshot) {
+    let (before, now) = (baseline.index(), current.index());
+    let is_volatile = |path: &str| {
+        [before.get(path), now.get(path)]
+            .into_iter()
+            .flatten()
+            .any(|f| f.volatile)
+    };
+    let (volatile, drifts) = report.drifts.drain(..).partition(|d: &Drift| {
+        matches!(
+            d.kind,
+            DriftKind::Changed
+                | DriftKind::Truncated
+                | DriftKind::Populated
+                | DriftKind::InodeReplaced
+        ) && is_volatile(&d.path)
+    });
+    report.drifts = drifts;
+    report.volatile = volatile.into_iter().map(|d| d.path).collect();
+}
+
+/// Say why content drift in a file of the `sampled` ones, which looked
+/// unchanged, matters: its content changed and its size and modification
+/// time were kept, as tampering that covers its tracks does.
+fn mark_sampled(report: &mut DriftReport, sampled: &HashSet<String>) {
+    for drift in &mut report.drifts {
+        let content = matches!(
+            drift.kind,
+            DriftKind::Changed | DriftKind::InodeReplaced | DriftKind::MountChanged
+        );
+        if content && drift.reason.is_none() && sampled.contains(&drift.path) {
+            drift.reason = Some("size and modification time unchanged".to_string());
+        }
+    }
+}
+
+/// Attach the scan's `warnings` to `report`. A file that couldn't be read is
+/// missing from the scan, but not gone: it's reported as unreadable, with
+/// the read error, rather than deleted.
+fn mark_unreadable(report: &mut DriftReport, warnings: Vec<ScanWarning>) {
+    let mut unreadable = HashMap::new();
+    for warning in warnings.iter().rev() {
+        if warning.kind == WarningKind::Unreadable {
+            unreadable.insert(warning.path.as_str(), warning);
+        }
+    }
+    for drift in &mut report.drifts {
+        if drift.kind != DriftKind::Deleted {
+            continue;
+        }
+        if let Some(warning) = unreadable.get(drift.path.as_str()) {
+            drift.kind = DriftKind::Unreadable;
+            drift.reason = warning.error.as_deref().map(short_reason);
+        }
+    }
+    report.warnings = warnings;
+}
+
+/// An I/O error message without its OS error code, lowercased to read as a
+/// reason: `Permission denied (os error 13)` becomes `permission denied`.
+fn short_reason(error: &str) -> String {
+    let error = match error.rfind(" (os error ") {
+        Some(i) if error.ends_with(')') => &error[..i],
+        _ => error,
+    };
+    let mut chars = error.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Move unreadable and locked files out of the report's drift, for
+/// `ignore_unreadable`.
+fn set_aside_unreadable(report: &mut DriftReport) {
+    let (unreadable, drifts) = report
+        .drifts
+        .drain(..)
+        .partition(|d: &Drift| matches!(d.kind, DriftKind::Unreadable | DriftKind::Locked));
+    report.drifts = drifts;
+    report.unreadable = unreadable;
+}
+
+/// Compare `dir` against the saved snapshot, printing, logging and
+/// recording the result in the drift history, and alerting on drift.
+/// Scan warnings are returned in the report rather than printed.
+pub fn compare_with_snapshot(
+    dir: &str,
+    alerts: Option<&AlertChannels>,
+    source: history::Source,
+) -> Result<DriftReport, GuardianError> {
+    compare_against(dir, &config::settings().snapshot_file, alerts, source)
+}
+
+/// [`compare_with_snapshot`], against the snapshot saved at `baseline`. Drift
+/// against any snapshot but the current one isn't recorded in the history,
+/// which tracks drift from the current baseline.
+pub fn compare_against(
+    dir: &str,
+    baseline: &Path,
+    alerts: Option<&AlertChannels>,
+    source: history::Source,
+) -> Result<DriftReport, GuardianError> {
+    let (report, sampled) = measure(dir, baseline, source, None)?;
+    let drifts = &report.drifts;
+    if drifts.is_empty() && !report.scope.is_empty() {
+        // Clean in scope says nothing of the rest, so nothing is resolved.
+        say!("No drift detected in scope {}.", report.scope.join(", "));
+        log::info!(
+            "No configuration drift detected in scope {}.",
+            report.scope.join(", ")
+        );
+    } else if drifts.is_empty() {
+        say!("No drift detected.");
+        log::info!("No configuration drift detected.");
+        if let Some(channels) = alerts {
+            alert::resolve(dir, channels);
+        }
+    } else {
+        let alert = announce(dir, &report);
+        if let Some(channels) = alerts {
+            alert::send(&alert.reported(&report), channels);
+        }
+    }
+    if config::settings().trace && !drifts.is_empty() {
+        say!("Found by:");
+        for drift in drifts {
+            let phase = match &sampled {
+                None => "hashing every file",
+                Some(sampled) if sampled.contains(&drift.path) => {
+                    "hashing the sample of files that looked unchanged"
+                }
+                Some(_) => "the size and modification time check",
+            };
+            say!("  {}: {}", redact::shown(&drift.path), phase);
+        }
+    }
+    announce_set_asides(dir, &report);
+    Ok(report)
+}
+
+/// Warn, or with `require_fresh_baseline` fail, when the snapshot saved at
+/// `baseline` took `dir`'s files longer than `max_baseline_age` ago. A
+/// baseline that can't be read is left for the comparison to report.
+pub fn check_baseline_age(dir: &str, baseline: &Path) -> Result<(), GuardianError> {
+    let settings = config::settings();
+    let Some(max) = settings.max_baseline_age else {
+        return Ok(());
+    };
+    let Some(taken) = Snapshot::load(baseline)
+        .ok()
+        .and_then(|snapshot| snapshot.metadata.taken_at(dir))
+    else {
+        return Ok(());
+    };
+    let age = (chrono::Utc::now() - taken).to_std().unwrap_or_default();
+    if age <= max {
+        return Ok(());
+    }
+    let days = age.as_secs() / 86400;
+    let max = utils::format_duration(max);
+    if settings.require_fresh_baseline {
+        return Err(GuardianError::StaleBaseline {
+            dir: dir.to_string(),
+            days,
+            max,
+        });
+    }
+    eprintln!(
+        "Warning: the baseline for {} is {} days old (taken {}), older than the {} --max-baseline-age allows; take a new snapshot if it isn't meant to be.",
+        dir,
+        days,
+        utils::format_time(taken),
+        max
+    );
+    log::warn!(
+        event = "stale_baseline",
+        dir = dir,
+        age_days = days;
+        "The baseline for {} is {} days old, older than {}", dir, days, max
+    );
+    Ok(())
+}
+
+/// [`compare_with_snapshot`] without the printing and alerting, for a
+/// caller that reports drift its own way, as `monitor` reports only what
+/// changed since its last comparison.
+pub(crate) fn compare_quietly(
+    dir: &str,
+    baseline: &Path,
+    source: history::Source,
+) -> Result<DriftReport, GuardianError> {
+    measure(dir, baseline, source, None).map(|(report, _)| report)
+}
+
+/// What comparing `dir` against the snapshot saved at `baseline` would
+/// report with the files in `staged` laid over it (see [`crate::staged`]),
+/// printed as hypothetical. It is neither alerted on nor recorded in the
+/// history, and the live files are all read, as those that look unchanged
+/// may be replaced.
+pub fn compare_staged(
+    dir: &str,
+    baseline: &Path,
+    staged: &str,
+) -> Result<DriftReport, GuardianError> {
+    let (report, _) = measure(dir, baseline, history::Source::Compare, Some(staged))?;
+    if report.drifts.is_empty() {
+        say!("No drift would be detected with {} applied.", staged);
+    } else {
+        say!(
+            "Hypothetical drift with {} applied ({}):",
+            staged,
+            header(&report)
+        );
+        for drift in redact::shown_drifts(&report.drifts) {
+            say!("  {}", drift);
+        }
+    }
+    log::info!(
+        event = "staged_compared",
+        dir = dir,
+        staged = staged,
+        drifts = report.drifts.len(),
+        score = report.score;
+        "Compared {} with {} laid over it: {} files would drift, score {}",
+        dir, staged, report.drifts.len(), report.score
+    );
+    announce_set_asides(dir, &report);
+    Ok(report)
+}
+
+/// Compare `dir`, with `staged` laid over it if given, against the snapshot
+/// saved at `baseline` and record the result unless staged, returning the
+/// report and the files the scan hashed as a sample, if it sampled.
+fn measure(
+    dir: &str,
+    baseline: &Path,
+    source: history::Source,
+    staged: Option<&str>,
+) -> Result<(DriftReport, Option<HashSet<String>>), GuardianError> {
+    let started = std::time::Instant::now();
+    let (snapshot, left_out) = baseline_at(baseline, dir)?;
+    let mut scan = match staged {
+        Some(_) => scan_directory(dir)?,
+        None => scan_against(dir, &snapshot)?,
+    };
+    if config::settings().trace {
+        say!("{}", scan.stats);
+    }
+    if let Some(staged) = staged {
+        let mut overlay = scan_directory(staged)?;
+        staged::overlay(&mut scan.snapshot, dir, staged, overlay.snapshot);
+        scan.warnings.append(&mut overlay.warnings);
+    }
+    // What the baseline leaves out isn't compared, so isn't new either.
+    scan.snapshot
+        .entries
+        .retain(|f| !left_out.contains(&f.path));
+    let current = scan.snapshot;
+    let mut report = compare(&snapshot, &current);
+    report.baseline_excluded = left_out.len();
+    report.hypothetical = staged.is_some();
+    report.scope.clone_from(&config::settings().scope);
+    report.baseline_age_days = snapshot
+        .metadata
+        .taken_at(dir)
+        .map(|taken| (chrono::Utc::now() - taken).num_days().max(0) as u64);
+    let sampled = scan.sampled;
+    if let Some(sampled) = &sampled {
+        mark_sampled(&mut report, sampled);
+    }
+    mark_unreadable(&mut report, scan.warnings);
+    if !config::settings().include_volatile {
+        set_aside_volatile(&mut report, &snapshot, &current);
+    }
+    if config::settings().ignore_unreadable {
+        set_aside_unreadable(&mut report);
+    }
+    exception::set_aside_excepted(dir, &mut report, source);
+    annotation::attach(&mut report);
+    report.score = alert::score(&report.drifts, config::settings());
+    let drifts = &report.drifts;
+
+    // A narrowed comparison would have drift outside its scope restored.
+    let entries = history_entries(dir, drifts, &snapshot, &current, source);
+    if staged.is_none() && report.scope.is_empty() && baseline == config::settings().snapshot_file {
+        // Drift recorded for what was left out stays as it was.
+        let recorded = if left_out.is_empty() {
+            history::record(dir, source, entries, &snapshot.entries)
+        } else {
+            let compared: Vec<String> = snapshot
+                .entries
+                .iter()
+                .chain(&current.entries)
+                .map(|f| f.path.clone())
+                .collect();
+            history::record_paths(dir, source, entries, &snapshot.entries, &compared)
+        };
+        if let Err(e) = recorded {
+            eprintln!("Warning: could not record drift history: {}", e);
+            log::error!("Could not record drift history: {}", e);
+        }
+        let run = history::Run {
+            finished: chrono::Utc::now(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            files: current.entries.len(),
+            drifts: drifts.len(),
+            source,
+        };
+        if let Err(e) = history::record_run(dir, run) {
+            log::error!("Could not record the comparison: {}", e);
+        }
+    }
+    Ok((report, sampled))
+}
+
+/// Print and log the volatile, unreadable and excepted files `report`
+/// left out of its drift.
+pub(crate) fn announce_set_asides(dir: &str, report: &DriftReport) {
+    if !report.volatile.is_empty() {
+        say!("Volatile files changed (not drift):");
+        for path in &report.volatile {
+            say!("  {}", redact::shown(path));
+        }
+        let paths: Vec<String> = report
+            .volatile
+            .iter()
+            .map(|p| redact::path(p).into_owned())
+            .collect();
+        log::info!(
+            event = "volatile_changed",
+            dir = dir,
+            paths:serde = paths;
+            "Volatile files changed in {}: {}", dir, paths.join(", ")
+        );
+    }
+    if !report.unreadable.is_empty() {
+        say!("Unreadable (not counted as drift):");
+        for drift in redact::shown_drifts(&report.unreadable) {
+            say!("  {}", drift);
+        }
+        let unreadable = redact::drifts(&report.unreadable);
+        let paths: Vec<&str> = unreadable.iter().map(|d| d.path.as_str()).collect();
+        log::warn!(
+            event = "unreadable",
+            dir = dir,
+            paths:serde = paths;
+            "Files in {} could not be read: {}", dir, paths.join(", ")
+        );
+    }
+    if !report.excepted.is_empty() {
+        say!("Excepted (not drift):");
+        for excepted in &report.excepted {
+            say!(
+                "  {}: {} ({}, until {})",
+                excepted.drift.kind,
+                redact::shown(&excepted.drift.path),
+                excepted.exception,
+                utils::format_time(excepted.until)
+            );
+        }
+        let paths: Vec<String> = report
+            .excepted
+            .iter()
+            .map(|e| redact::path(&e.drift.path).into_owned())
+            .collect();
+        log::info!(
+            event = "drift_excepted",
+            dir = dir,
+            paths:serde = paths;
+            "Excepted drift in {}: {}", dir, paths.join(", ")
+        );
+    }
+    if report.baseline_excluded > 0 {
+        say!(
+            "Left out of the baseline by --baseline-exclude: {} entr{}",
+            report.baseline_excluded,
+            if report.baseline_excluded == 1 {
+                "y"
+            } else {
+                "ies"
+            }
+        );
+        log::info!(
+            event = "baseline_excluded",
+            dir = dir,
+            entries = report.baseline_excluded;
+            "Left {} baseline entries of {} out of the comparison",
+            report.baseline_excluded, dir
+        );
+    }
+}
+
+/// What the line heading `report`'s drift says of it: its score, the
+/// snapshot's tags, the fields ignored and the scope it was narrowed to.
+fn header(report: &DriftReport) -> String {
+    let mut header = format!("score {}", report.score);
+    if !report.tags.is_empty() {
+        header.push_str(&format!("; snapshot {}", format_tags(&report.tags)));
+    }
+    if !report.ignored.is_empty() {
+        header.push_str(&format!("; ignoring {}", report.ignored));
+    }
+    if !report.scope.is_empty() {
+        header.push_str(&format!("; scope {}", report.scope.join(", ")));
+    }
+    header
+}
+
+/// Print and log the drift in `report`, which has some, and return the
+/// alert for it.
+pub(crate) fn announce(dir: &str, report: &DriftReport) -> Alert {
+    say!("Drift detected ({}):", header(report));
+    for (drift, shown) in report
+        .drifts
+        .iter()
+        .zip(redact::shown_drifts(&report.drifts))
+    {
+        match report.annotations.get(&drift.path) {
+            Some(annotation) => say!("  {} — {}", shown, annotation),
+            None => say!("  {}", shown),
+        }
+    }
+    let redacted = redact::drifts(&report.drifts);
+    for drift in &redacted {
+        log::warn!(
+            event = "drift",
+            dir = dir,
+            path = drift.path.as_str(),
+            category = drift.kind.as_str(),
+            severity = alert::severity_of(drift).as_str();
+            "Configuration drift detected: {}", drift
+        );
+    }
+    let alert = Alert::drift(dir, &report.drifts)
+        .tagged(&report.tags)
+        .annotated(&report.annotations)
+        .aged(report.baseline_age_days);
+    let paths: Vec<&str> = redacted.iter().map(|d| d.path.as_str()).collect();
+    log::warn!(
+        event = "drift_detected",
+        dir = dir,
+        paths:serde = paths,
+        score = report.score,
+        changed = alert.count(DriftKind::Changed),
+        truncated = alert.count(DriftKind::Truncated),
+        populated = alert.count(DriftKind::Populated),
+        new = alert.count(DriftKind::New),
+        new_executable = alert.count(DriftKind::NewExecutable),
+        new_setuid = alert.count(DriftKind::NewSetuid),
+        deleted = alert.count(DriftKind::Deleted),
+        unreadable = alert.count(DriftKind::Unreadable),
+        locked = alert.count(DriftKind::Locked),
+        mount_changed = alert.count(DriftKind::MountChanged),
+        attributes_changed = alert.count(DriftKind::AttributesChanged),
+        acl_changed = alert.count(DriftKind::AclChanged),
+        inode_replaced = alert.count(DriftKind::InodeReplaced),
+        link_count_changed = alert.count(DriftKind::LinkCountChanged),
+        permissions_changed = alert.count(DriftKind::PermissionsChanged),
+        type_changed = alert.count(DriftKind::TypeChanged),
+        symlink_retargeted = alert.count(DriftKind::SymlinkRetargeted),
+        symlink_escaped = alert.count(DriftKind::SymlinkEscaped),
+        baseline_modified = alert.count(DriftKind::BaselineModified);
+        "Configuration drift detected in {}: {} files, score {}", dir, report.drifts.len(), report.score
+    );
+    alert
+}
+
+fn history_entries(
+    dir: &str,
+    drifts: &[Drift],
+    baseline: &Snapshot,
+    current: &Snapshot,
+    source: history::Source,
+) -> Vec<history::HistoryEntry> {
+    let now = chrono::Utc::now();
+    let (before, after) = (baseline.index(), current.index());
+    drifts
+        .iter()
+        .map(|drift| history::HistoryEntry {
+            timestamp: now,
+            directory: dir.to_string(),
+            category: drift.kind.into(),
+            path: redact::path(&drift.path).into_owned(),
+            old_hash: hash_of(&before, &drift.path),
+            new_hash: hash_of(&after, &drift.path),
+            source,
+            reason: None,
+            first_seen: None,
+            summary: None,
+            invoker: None,
+        })
+        .collect()
+}
+
+/// Compare only what is recorded about the files at `paths` in `dir`
+/// (permissions, attributes, ACLs and links), not their content, as for
+/// metadata events in `monitor`. Files the snapshot doesn't have, and ones
+/// that are gone, are left to a full comparison. Drift found is printed
+/// and logged like [`compare_with_snapshot`]'s, except that these paths
+/// alone are taken as restored in the history when they no longer drift.
+pub fn compare_metadata(
+    dir: &str,
+    paths: &[PathBuf],
+    source: history::Source,
+) -> Result<DriftReport, GuardianError> {
+    let (report, _) = check_metadata(dir, &config::settings().snapshot_file, paths, source)?;
+    if !report.drifts.is_empty() {
+        announce(dir, &report);
+    }
+    Ok(report)
+}
+
+/// [`compare_metadata`] without the printing, against the snapshot saved at
+/// `snapshot_file`, returning the report and the recorded paths that were
+/// checked.
+pub(crate) fn check_metadata(
+    dir: &str,
+    snapshot_file: &Path,
+    paths: &[PathBuf],
+    source: history::Source,
+) -> Result<(DriftReport, Vec<String>), GuardianError> {
+    let mut baseline = snapshot_at(snapshot_file, dir)?;
+    // Events name the file under the watched directory however it was
+    // given, so entries are matched by name.
+    let recorded: Vec<String> = paths
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| utils::path_text(&Path::new(dir).join(name)))
+        .collect();
+    baseline.entries.retain(|f| recorded.contains(&f.path));
+    let current = Snapshot {
+        entries: baseline.entries.iter().filter_map(restat).collect(),
+        metadata: baseline.metadata.clone(),
+    };
+    let mut report = compare(&baseline, &current);
+    report.drifts.retain(|d| d.kind != DriftKind::Deleted);
+    let checked: Vec<String> = baseline.entries.iter().map(|f| f.path.clone()).collect();
+    let entries = history_entries(dir, &report.drifts, &baseline, &current, source);
+    if snapshot_file == config::settings().snapshot_file
+        && let Err(e) = history::record_paths(dir, source, entries, &baseline.entries, &checked)
+    {
+        eprintln!("Warning: could not record drift history: {}", e);
+        log::error!("Could not record drift history: {}", e);
+    }
+    Ok((report, checked))
+}
+
+/// How a path drifted when it was last reported: its kind, reason and new
+/// hash, which tell drifting again differently from drifting still.
+type Reported = (DriftKind, Option<String>, Option<String>);
+
+/// When comparisons in a monitor session first and last found a path
+/// drifting.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub(crate) struct Seen {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// A drift entry with when the session saw it, for the status API and its
+/// event stream.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Observed {
+    #[serde(flatten)]
+    pub drift: Drift,
+    #[serde(flatten)]
+    pub seen: Option<Seen>,
+}
+
+/// A path that drifted and is back to the baseline.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Resolved {
+    pub path: String,
+    pub first_seen: DateTime<Utc>,
+    pub resolved_at: DateTime<Utc>,
+}
+
+/// A directory's drift as last reported, so that `monitor` reports only
+/// what changed from one comparison to the next rather than all of it each
+/// time, and when each path was seen drifting.
+#[derive(Debug, Default)]
+pub(crate) struct DriftTracker {
+    reported: BTreeMap<String, (Reported, Seen)>,
+}
+
+/// What changed in a directory's drift since it was last reported.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DriftDelta {
+    /// Paths that drifted since, or drift differently now.
+    pub drifts: Vec<Drift>,
+    pub resolved: Vec<Resolved>,
+}
+
+impl DriftTracker {
+    fn reported(report: &DriftReport, drift: &Drift) -> Reported {
+        let new_hash = report.hashes.get(&drift.path).and_then(|h| h.new.clone());
+        (drift.kind, drift.reason.clone(), new_hash)
+    }
+
+    /// `path` seen drifting at `at`: since when it was first seen, if it
+    /// already drifted.
+    fn seen(&self, path: &str, at: DateTime<Utc>) -> Seen {
+        Seen {
+            first_seen: self
+                .reported
+                .get(path)
+                .map_or(at, |(_, seen)| seen.first_seen),
+            last_seen: at,
+        }
+    }
+
+    fn resolved(path: &str, seen: &Seen, at: DateTime<Utc>) -> Resolved {
+        Resolved {
+            path: path.to_string(),
+            first_seen: seen.first_seen,
+            resolved_at: at,
+        }
+    }
+
+    /// Take in a comparison of the whole directory made at `at`, returning
+    /// what changed.
+    pub fn update(&mut self, report: &DriftReport, at: DateTime<Utc>) -> DriftDelta {
+        let mut delta = DriftDelta::default();
+        let mut now = BTreeMap::new();
+        for drift in &report.drifts {
+            let reported = Self::reported(report, drift);
+            if self.reported.get(&drift.path).map(|(r, _)| r) != Some(&reported) {
+                delta.drifts.push(drift.clone());
+            }
+            now.insert(drift.path.clone(), (reported, self.seen(&drift.path, at)));
+        }
+        delta.resolved = self
+            .reported
+            .iter()
+            .filter(|(path, _)| !now.contains_key(*path))
+            .map(|(path, (_, seen))| Self::resolved(path, seen, at))
+            .collect();
+        self.reported = now;
+        delta
+    }
+
+    /// Take in a metadata check of the `checked` paths made at `at`, which
+    /// only sees metadata drift: a path drifting in content is left as
+    /// reported.
+    pub fn update_metadata(
+        &mut self,
+        report: &DriftReport,
+        checked: &[String],
+        at: DateTime<Utc>,
+    ) -> DriftDelta {
+        let mut delta = DriftDelta::default();
+        let is_content = |reported: Option<&(Reported, Seen)>| {
+            reported.is_some_and(|(r, _)| !DriftKind::METADATA.contains(&r.0))
+        };
+        for path in checked {
+            if is_content(self.reported.get(path)) {
+                continue;
+            }
+            match report.drifts.iter().find(|d| &d.path == path) {
+                Some(drift) => {
+                    let reported = Self::reported(report, drift);
+                    if self.reported.get(path).map(|(r, _)| r) != Some(&reported) {
+                        delta.drifts.push(drift.clone());
+                    }
+                    let seen = self.seen(path, at);
+                    self.reported.insert(path.clone(), (reported, seen));
+                }
+                None => {
+                    if let Some((_, seen)) = self.reported.remove(path) {
+                        delta.resolved.push(Self::resolved(path, &seen, at));
+                    }
+                }
+            }
+        }
+        delta
+    }
+
+    /// Take `paths` as back to the baseline without reporting them resolved,
+    /// as after they were accepted.
+    pub fn forget(&mut self, paths: &[String]) {
+        for path in paths {
+            self.reported.remove(path);
+        }
+    }
+
+    /// The paths drifting as of the last comparison, the longest drifting
+    /// first.
+    pub fn drifting(&self) -> Vec<(&str, Seen)> {
+        let mut drifting: Vec<(&str, Seen)> = self
+            .reported
+            .iter()
+            .map(|(path, (_, seen))| (path.as_str(), *seen))
+            .collect();
+        drifting.sort_by_key(|(_, seen)| seen.first_seen);
+        drifting
+    }
+
+    /// `drifts` with when each was seen drifting, the longest drifting
+    /// first.
+    pub fn observed(&self, drifts: &[Drift]) -> Vec<Observed> {
+        let mut observed: Vec<Observed> = drifts
+            .iter()
+            .map(|drift| Observed {
+                drift: drift.clone(),
+                seen: self.reported.get(&drift.path).map(|(_, seen)| *seen),
+            })
+            .collect();
+        observed.sort_by_key(|o| o.seen.map(|seen| seen.first_seen));
+        observed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::Severity;
+    use crate::config::{Config, SeverityRule};
+    use crate::snapshot::{Inode, Permissions, SnapshotMetadata};
+
+    #[test]
+    fn kind_lists_name_kinds_and_groups() {
+        assert_eq!(
+            parse_kinds("changed, deleted,changed"),
+            Ok(vec![DriftKind::Changed, DriftKind::Deleted])
+        );
+        assert_eq!(parse_kinds("all").unwrap().len(), DriftKind::ALL.len());
+        assert_eq!(parse_kinds("none"), Ok(vec![]));
+        assert_eq!(
+            parse_kinds("new,metadata"),
+            Ok(vec![
+                DriftKind::New,
+                DriftKind::AttributesChanged,
+                DriftKind::AclChanged,
+                DriftKind::LinkCountChanged,
+                DriftKind::PermissionsChanged,
+            ])
+        );
+        let error = parse_kinds("changed,renamed").unwrap_err();
+        assert!(
+            error.starts_with("unknown drift kind 'renamed'"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn the_tracker_reports_only_what_changed() {
+        let entry = |path: &str, hash: &str| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            ..FileHash::default()
+        };
+        let snapshot = |entries| Snapshot {
+            entries,
+            ..Snapshot::default()
+        };
+        let baseline = snapshot(vec![entry("d/a", "1"), entry("d/b", "2")]);
+        let paths = |delta: &DriftDelta| -> Vec<String> {
+            delta.drifts.iter().map(|d| d.path.clone()).collect()
+        };
+        let at = |minute: i64| DateTime::<Utc>::UNIX_EPOCH + chrono::Duration::minutes(minute);
+        let resolved = |path: &str, first_seen, resolved_at| Resolved {
+            path: path.into(),
+            first_seen: at(first_seen),
+            resolved_at: at(resolved_at),
+        };
+        let mut tracker = DriftTracker::default();
+
+        let a_changed = snapshot(vec![entry("d/a", "3"), entry("d/b", "2")]);
+        let delta = tracker.update(&compare(&baseline, &a_changed), at(1));
+        assert_eq!(
+            (paths(&delta), delta.resolved),
+            (vec!["d/a".to_string()], vec![])
+        );
+
+        // a still drifts as before, so only b is news.
+        let both = snapshot(vec![entry("d/a", "3"), entry("d/b", "4")]);
+        let delta = tracker.update(&compare(&baseline, &both), at(2));
+        assert_eq!(paths(&delta), ["d/b"]);
+
+        // a changed again, and b is back.
+        let a_again = snapshot(vec![entry("d/a", "5"), entry("d/b", "2")]);
+        let delta = tracker.update(&compare(&baseline, &a_again), at(3));
+        assert_eq!(
+            (paths(&delta), delta.resolved),
+            (vec!["d/a".to_string()], vec![resolved("d/b", 2, 3)])
+        );
+
+        // A metadata check can't see a's content, so it leaves a alone. It
+        // has drifted since the first comparison.
+        let clean = compare(&baseline, &baseline);
+        let delta = tracker.update_metadata(&clean, &["d/a".to_string()], at(4));
+        assert_eq!(delta, DriftDelta::default());
+        let seen = Seen {
+            first_seen: at(1),
+            last_seen: at(3),
+        };
+        assert_eq!(tracker.drifting(), [("d/a", seen)]);
+
+        let delta = tracker.update(&clean, at(5));
+        assert_eq!(delta.resolved, [resolved("d/a", 1, 5)]);
+        assert!(tracker.drifting().is_empty());
+    }
+
+    #[test]
+    fn unreadable_files_are_not_reported_as_deleted() {
+        let drift = |kind, path: &str| Drift {
+            kind,
+            path: path.to_string(),
+            reason: None,
+        };
+        let mut report = DriftReport {
+            drifts: vec![
+                drift(DriftKind::Deleted, "d/locked.conf"),
+                drift(DriftKind::Deleted, "d/gone.conf"),
+            ],
+            files_tracked: 0,
+            warnings: Vec::new(),
+            volatile: Vec::new(),
+            unreadable: Vec::new(),
+            excepted: Vec::new(),
+            tags: BTreeMap::new(),
+            score: 0,
+            hashes: BTreeMap::new(),
+            chunks: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            ignored: IgnoredFields::default(),
+            baseline_age_days: None,
+            invoker: None,
+            instance_id: None,
+            hypothetical: false,
+            scope: Vec::new(),
+            baseline_excluded: 0,
+        };
+        let warning = ScanWarning {
+            kind: WarningKind::Unreadable,
+            path: "d/locked.conf".to_string(),
+            error: Some("Permission denied (os error 13)".to_string()),
+        };
+        mark_unreadable(&mut report, vec![warning.clone()]);
+        let unreadable = Drift {
+            reason: Some("permission denied".into()),
+            ..drift(DriftKind::Unreadable, "d/locked.conf")
+        };
+        assert_eq!(
+            unreadable.to_string(),
+            "Unreadable: d/locked.conf (permission denied)"
+        );
+        assert_eq!(
+            report.drifts,
+            [unreadable.clone(), drift(DriftKind::Deleted, "d/gone.conf")]
+        );
+        assert_eq!(report.warnings, [warning]);
+
+        set_aside_unreadable(&mut report);
+        assert_eq!(report.drifts, [drift(DriftKind::Deleted, "d/gone.conf")]);
+        assert_eq!(report.unreadable, [unreadable]);
+    }
+
+    #[test]
+    fn locked_files_are_reported_once_and_not_as_changed() {
+        let entry = |path: &str, hash: &str, locked| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            locked,
+            ..FileHash::default()
+        };
+        let snapshot = |entries| Snapshot {
+            entries,
+            ..Snapshot::default()
+        };
+        let baseline = snapshot(vec![
+            entry("svc.conf", "1", false),
+            entry("still.conf", "2", true),
+            entry("never.conf", "", true),
+        ]);
+        // svc.conf became locked, still.conf stayed so, and never.conf,
+        // never read, now can be.
+        let current = snapshot(vec![
+            entry("svc.conf", "", true),
+            entry("still.conf", "", true),
+            entry("never.conf", "3", false),
+        ]);
+        let report = compare(&baseline, &current);
+        assert_eq!(
+            report.drifts,
+            [Drift {
+                kind: DriftKind::Locked,
+                path: "svc.conf".into(),
+                reason: Some("another process has it open exclusively".into()),
+            }]
+        );
+
+        // Unlocked again, a file last read before it was locked compares
+        // as usual.
+        let current = snapshot(vec![entry("still.conf", "4", false)]);
+        let kinds: Vec<DriftKind> = compare(&baseline, &current)
+            .drifts
+            .iter()
+            .map(|d| d.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [DriftKind::Changed, DriftKind::Deleted, DriftKind::Deleted]
+        );
+    }
+
+    #[test]
+    fn large_snapshots_compare_in_entry_order_without_searching() {
+        // Searching the other side for each entry took minutes at this size.
+        let entry = |i: usize, hash: &str| FileHash {
+            path: format!("d/{:06}.conf", i),
+            hash: hash.into(),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: (0..100_000).map(|i| entry(i, "1")).collect(),
+            ..Snapshot::default()
+        };
+        // Three new files first, then every thousandth file changed and
+        // another deleted.
+        let current = Snapshot {
+            entries: (100_000..100_003)
+                .rev()
+                .map(|i| entry(i, "1"))
+                .chain(
+                    (0..100_000)
+                        .filter(|i| i % 1000 != 500)
+                        .map(|i| entry(i, if i % 1000 == 0 { "2" } else { "1" })),
+                )
+                .collect(),
+            ..Snapshot::default()
+        };
+        let drift = |kind, i: usize| Drift {
+            kind,
+            path: format!("d/{:06}.conf", i),
+            reason: None,
+        };
+        let expected: Vec<Drift> = [100_002, 100_001, 100_000]
+            .into_iter()
+            .map(|i| drift(DriftKind::New, i))
+            .chain((0..100).map(|i| drift(DriftKind::Changed, i * 1000)))
+            .chain((0..100).map(|i| drift(DriftKind::Deleted, i * 1000 + 500)))
+            .collect();
+
+        let started = std::time::Instant::now();
+        let report = compare(&baseline, &current);
+        let took = started.elapsed();
+        assert_eq!(report.drifts, expected);
+        assert!(took < std::time::Duration::from_secs(5), "took {:?}", took);
+    }
+
+    #[test]
+    fn chmod_and_chown_are_reported_with_what_changed() {
+        let entry = |path: &str, hash: &str, mode, uid| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            permissions: Some(Permissions {
+                mode,
+                uid,
+                gid: 0,
+                ..Permissions::default()
+            }),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("d/app.conf", "1", 0o644, 0),
+                entry("d/key.pem", "2", 0o600, 0),
+                entry("d/edited.conf", "3", 0o644, 0),
+                entry("d/old.conf", "4", 0o644, 0),
+            ],
+            ..Snapshot::default()
+        };
+        let mut current = Snapshot {
+            entries: vec![
+                entry("d/app.conf", "1", 0o4666, 0),
+                entry("d/key.pem", "2", 0o600, 1000),
+                entry("d/edited.conf", "9", 0o600, 0),
+                entry("d/old.conf", "4", 0o644, 0),
+            ],
+            ..Snapshot::default()
+        };
+        // Snapshots taken before permissions were recorded don't drift.
+        current.entries[3].permissions = None;
+        let report = compare(&baseline, &current);
+        let reasons: Vec<(DriftKind, Option<&str>)> = report
+            .drifts
+            .iter()
+            .map(|d| (d.kind, d.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                (DriftKind::PermissionsChanged, Some("mode 0644 -> 4666")),
+                (DriftKind::PermissionsChanged, Some("owner 0:0 -> 1000:0")),
+                (DriftKind::Changed, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn new_executables_are_reported_first_by_their_mode() {
+        let entry = |path: &str, mode, entry_type| FileHash {
+            path: path.into(),
+            hash: "1".into(),
+            permissions: Some(Permissions {
+                mode,
+                ..Permissions::default()
+            }),
+            entry_type: Some(entry_type),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![entry("d/app.conf", 0o644, EntryType::File)],
+            ..Snapshot::default()
+        };
+        let mut edited = entry("d/app.conf", 0o644, EntryType::File);
+        edited.hash = "2".into();
+        let current = Snapshot {
+            entries: vec![
+                edited,
+                entry("d/extra.conf", 0o644, EntryType::File),
+                entry("d/run.sh", 0o755, EntryType::File),
+                entry("d/current", 0o777, EntryType::Symlink),
+                entry("d/helper", 0o2750, EntryType::File),
+            ],
+            ..Snapshot::default()
+        };
+        let report = compare(&baseline, &current);
+        let kinds: Vec<(DriftKind, &str)> = report
+            .drifts
+            .iter()
+            .map(|d| (d.kind, d.path.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (DriftKind::NewSetuid, "d/helper"),
+                (DriftKind::NewExecutable, "d/run.sh"),
+                (DriftKind::Changed, "d/app.conf"),
+                (DriftKind::New, "d/extra.conf"),
+                (DriftKind::New, "d/current"),
+            ]
+        );
+        assert_eq!(report.drifts[0].to_string(), "New setuid binary: d/helper");
+    }
+
+    #[test]
+    fn owners_are_compared_by_name_where_they_have_one() {
+        let owned = |uid, user: Option<&str>| Permissions {
+            mode: 0o644,
+            uid,
+            gid: 0,
+            user: user.map(String::from),
+            group: Some("root".into()),
+        };
+        // The same account, with another ID on this host.
+        let (here, there) = (owned(1000, Some("deploy")), owned(1001, Some("deploy")));
+        assert!(here.same_as(&there, Ownership::Name));
+        assert!(!here.same_as(&there, Ownership::Id));
+        assert_eq!(
+            here.describe_change(&there, Ownership::Id),
+            "owner 1000:0 -> 1001:0"
+        );
+
+        let root = owned(0, Some("root"));
+        assert_eq!(
+            root.describe_change(&here, Ownership::Name),
+            "owner root:root -> deploy:root"
+        );
+        let deleted = owned(1002, None);
+        assert!(!root.same_as(&deleted, Ownership::Name));
+        assert_eq!(
+            root.describe_change(&deleted, Ownership::Name),
+            "owner root:root -> 1002:root, compared by ID as uid 1002 has no name"
+        );
+    }
+
+    #[test]
+    fn ignored_fields_compare_equal() {
+        let entry = |mode, uid, attributes: &str| FileHash {
+            path: "d/app.conf".into(),
+            hash: "1".into(),
+            permissions: Some(Permissions {
+                mode,
+                uid,
+                gid: 0,
+                ..Permissions::default()
+            }),
+            attributes: Some(attributes.into()),
+            ..FileHash::default()
+        };
+        let (prev, curr) = (entry(0o644, 0, ""), entry(0o600, 1000, "i"));
+        let kind = |fields: &[Field]| {
+            change(
+                &prev,
+                &ignoring(&prev, &curr, fields),
+                false,
+                Ownership::Name,
+            )
+        };
+        assert_eq!(kind(&[]), Some(DriftKind::AttributesChanged));
+        assert_eq!(kind(&[Field::Xattrs]), Some(DriftKind::PermissionsChanged));
+        let owner_only = ignoring(&prev, &curr, &[Field::Xattrs, Field::Mode]);
+        assert_eq!(
+            prev.permissions
+                .as_ref()
+                .unwrap()
+                .describe_change(owner_only.permissions.as_ref().unwrap(), Ownership::Name),
+            "owner 0:0 -> 1000:0"
+        );
+        assert_eq!(kind(&[Field::Xattrs, Field::Mode, Field::Uid]), None);
+    }
+
+    #[test]
+    fn a_path_of_another_type_is_a_type_change() {
+        let entry = |path: &str, hash: &str, entry_type, target: Option<&str>| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            entry_type,
+            link_target: target.map(Into::into),
+            ..FileHash::default()
+        };
+        let file = Some(EntryType::File);
+        let symlink = Some(EntryType::Symlink);
+        let stub = Some("../run/systemd/resolve/stub-resolv.conf");
+        let baseline = Snapshot {
+            entries: vec![
+                entry("etc/resolv.conf", "1", file, None),
+                entry("etc/hosts", "2", file, None),
+                entry("etc/old.conf", "3", None, None),
+                entry("etc/linked.conf", "4", None, None),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("etc/resolv.conf", "1", symlink, stub),
+                entry("etc/hosts", "", Some(EntryType::Directory), None),
+                entry("etc/old.conf", "", symlink, Some("/nowhere")),
+                // Recorded before types were, so not known to have changed.
+                entry("etc/linked.conf", "4", symlink, Some("hosts")),
+            ],
+            ..Snapshot::default()
+        };
+        let report = compare(&baseline, &current);
+        let reasons: Vec<String> = report.drifts.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "Type changed: etc/resolv.conf (file → symlink -> ../run/systemd/resolve/stub-resolv.conf)",
+                "Type changed: etc/hosts (file → directory)",
+                "Type changed: etc/old.conf (file → symlink -> /nowhere)",
+            ]
+        );
+    }
+
+    #[test]
+    fn symlinks_are_retargeted_by_where_they_lead_and_escapes_come_first() {
+        let link = |path: &str, target: &str, resolved: &str, escapes| FileHash {
+            path: path.into(),
+            hash: "1".into(),
+            entry_type: Some(EntryType::Symlink),
+            link_target: Some(target.into()),
+            link_resolved: Some(resolved.into()),
+            link_escapes: escapes,
+            ..FileHash::default()
+        };
+        let available = "../sites-available/app.conf";
+        let baseline = Snapshot {
+            entries: vec![
+                link(
+                    "enabled/app.conf",
+                    available,
+                    "sites-available/app.conf",
+                    false,
+                ),
+                link(
+                    "enabled/abs.conf",
+                    "/srv/sites-available/app.conf",
+                    "sites-available/app.conf",
+                    false,
+                ),
+                link(
+                    "enabled/b.conf",
+                    "../sites-available/b.conf",
+                    "sites-available/b.conf",
+                    false,
+                ),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                link("enabled/app.conf", "/tmp/evil.conf", "/tmp/evil.conf", true),
+                // Spelled otherwise, but leading to the same file.
+                link(
+                    "enabled/abs.conf",
+                    available,
+                    "sites-available/app.conf",
+                    false,
+                ),
+                link(
+                    "enabled/b.conf",
+                    available,
+                    "sites-available/app.conf",
+                    false,
+                ),
+                link("enabled/new.conf", "/etc/shadow", "/etc/shadow", true),
+            ],
+            ..Snapshot::default()
+        };
+        let report = compare(&baseline, &current);
+        let reasons: Vec<String> = report.drifts.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "Symlink retargeted out of the tree: enabled/app.conf (../sites-available/app.conf → /tmp/evil.conf)",
+                "Symlink retargeted out of the tree: enabled/new.conf (new → /etc/shadow)",
+                "Symlink retargeted: enabled/b.conf (../sites-available/b.conf → ../sites-available/app.conf)",
+            ]
+        );
+        // No severity rule lowers an escape.
+        let config = Config {
+            severity: vec![SeverityRule {
+                path: "enabled/*".into(),
+                kind: None,
+                severity: Severity::Info,
+            }],
+            ..Config::default()
+        };
+        let settings = config.settings().unwrap();
+        let severities: Vec<Severity> = report
+            .drifts
+            .iter()
+            .map(|d| settings.severity_of(d))
+            .collect();
+        assert_eq!(
+            severities,
+            [Severity::Critical, Severity::Critical, Severity::Info]
+        );
+    }
+
+    #[test]
+    fn changed_content_on_another_mount_is_reported_as_mounted_over() {
+        let entry = |path: &str, hash: &str, mount| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            mount,
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("d/bound.conf", "1", Some(28)),
+                entry("d/edited.conf", "2", Some(28)),
+                entry("d/untracked.conf", "3", None),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("d/bound.conf", "4", Some(301)),
+                entry("d/edited.conf", "5", Some(28)),
+                entry("d/untracked.conf", "6", Some(301)),
+            ],
+            ..Snapshot::default()
+        };
+        let kinds: Vec<DriftKind> = compare(&baseline, &current)
+            .drifts
+            .iter()
+            .map(|d| d.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                DriftKind::MountChanged,
+                DriftKind::Changed,
+                DriftKind::Changed
+            ]
+        );
+    }
+
+    #[test]
+    fn attribute_changes_are_reported_when_the_content_is_unchanged() {
+        let entry = |path: &str, hash: &str, attributes: Option<&str>| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            attributes: attributes.map(str::to_string),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("d/resolv.conf", "1", Some("i")),
+                entry("d/edited.conf", "2", Some("i")),
+                entry("d/unsupported.conf", "3", None),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("d/resolv.conf", "1", Some("")),
+                entry("d/edited.conf", "4", Some("")),
+                entry("d/unsupported.conf", "3", Some("")),
+            ],
+            ..Snapshot::default()
+        };
+        assert_eq!(
+            compare(&baseline, &current).drifts,
+            [
+                Drift {
+                    kind: DriftKind::AttributesChanged,
+                    path: "d/resolv.conf".into(),
+                    reason: None,
+                },
+                Drift {
+                    kind: DriftKind::Changed,
+                    path: "d/edited.conf".into(),
+                    reason: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn acl_changes_are_reported_only_when_both_scans_recorded_acls() {
+        let entry = |path: &str, acl: Option<&str>| FileHash {
+            path: path.into(),
+            hash: "1".into(),
+            acl: acl.map(str::to_string),
+            ..FileHash::default()
+        };
+        let snapshot = |acls, entries| Snapshot {
+            entries,
+            metadata: SnapshotMetadata {
+                acls,
+                ..SnapshotMetadata::default()
+            },
+        };
+        let extended = "user::rw-,user:1000:r--,group::r--,mask::r--,other::---";
+        let baseline = || {
+            vec![
+                entry("d/granted.conf", None),
+                entry("d/revoked.conf", Some(extended)),
+                entry("d/same.conf", Some(extended)),
+            ]
+        };
+        let current = vec![
+            entry("d/granted.conf", Some(extended)),
+            entry("d/revoked.conf", None),
+            entry("d/same.conf", Some(extended)),
+        ];
+        assert_eq!(
+            compare(
+                &snapshot(true, baseline()),
+                &snapshot(true, current.clone())
+            )
+            .drifts,
+            [
+                Drift {
+                    kind: DriftKind::AclChanged,
+                    path: "d/granted.conf".into(),
+                    reason: None,
+                },
+                Drift {
+                    kind: DriftKind::AclChanged,
+                    path: "d/revoked.conf".into(),
+                    reason: None,
+                },
+            ]
+        );
+        assert_eq!(
+            compare(&snapshot(false, baseline()), &snapshot(true, current)).drifts,
+            []
+        );
+    }
+
+    #[test]
+    fn emptied_and_filled_files_are_told_from_other_changes() {
+        let entry = |path: &str, hash: &str, size| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            size,
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("d/app.conf", "1", Some(4300)),
+                entry("d/placeholder.conf", "e", Some(0)),
+                entry("d/still-empty.conf", "e", Some(0)),
+                entry("d/old.conf", "2", None),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("d/app.conf", "e", Some(0)),
+                entry("d/placeholder.conf", "3", Some(12)),
+                entry("d/still-empty.conf", "e", Some(0)),
+                // Recorded before sizes were, its emptying is only a change.
+                entry("d/old.conf", "e", Some(0)),
+            ],
+            ..Snapshot::default()
+        };
+        let report = compare(&baseline, &current);
+        assert_eq!(
+            report.drifts,
+            [
+                Drift {
+                    kind: DriftKind::Truncated,
+                    path: "d/app.conf".into(),
+                    reason: Some(format!("was {}", utils::human_size(4300))),
+                },
+                Drift {
+                    kind: DriftKind::Populated,
+                    path: "d/placeholder.conf".into(),
+                    reason: Some(format!("now {}", utils::human_size(12))),
+                },
+                Drift {
+                    kind: DriftKind::Changed,
+                    path: "d/old.conf".into(),
+                    reason: None,
+                },
+            ]
+        );
+        assert_eq!(
+            crate::alert::Severity::of(DriftKind::Truncated),
+            crate::alert::Severity::Critical
+        );
+        assert_eq!(
+            report.drifts[0].to_string(),
+            format!("Truncated: d/app.conf (was {})", utils::human_size(4300))
+        );
+    }
+
+    #[test]
+    fn replaced_inodes_and_link_counts_are_reported() {
+        let entry = |path: &str, hash: &str, ino, nlink| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            inode: Some(Inode { dev: 1, ino, nlink }),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("d/replaced.conf", "1", 10, 2),
+                entry("d/edited.conf", "2", 11, 1),
+                entry("d/linked.conf", "3", 12, 1),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("d/replaced.conf", "4", 20, 1),
+                entry("d/edited.conf", "5", 11, 1),
+                entry("d/linked.conf", "3", 12, 2),
+            ],
+            ..Snapshot::default()
+        };
+        let kinds: Vec<_> = compare(&baseline, &current)
+            .drifts
+            .into_iter()
+            .map(|d| d.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                DriftKind::InodeReplaced,
+                DriftKind::Changed,
+                DriftKind::LinkCountChanged
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_to_volatile_files_are_set_aside() {
+        let entry = |path: &str, hash: &str, volatile| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            volatile,
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("d/app.log", "1", true),
+                entry("d/app.conf", "2", false),
+                entry("d/spool", "3", false),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("d/app.log", "4", true),
+                entry("d/app.conf", "5", false),
+                entry("d/spool", "6", true),
+            ],
+            ..Snapshot::default()
+        };
+        let mut report = compare(&baseline, &current);
+        set_aside_volatile(&mut report, &baseline, &current);
+        assert_eq!(
+            report.drifts,
+            [Drift {
+                kind: DriftKind::Changed,
+                path: "d/app.conf".into(),
+                reason: None,
+            }]
+        );
+        assert_eq!(report.volatile, ["d/app.log", "d/spool"]);
+    }
+}
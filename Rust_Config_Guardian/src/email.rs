@@ -0,0 +1,218 @@
+//! SMTP email alert channel.
+//!
+//! Credentials never come from the command line, where they would show up
+//! in `ps` and shell history: they are read from `GUARDIAN_SMTP_USERNAME`
+//! and `GUARDIAN_SMTP_PASSWORD`, or from the service config file.
+
+use chrono::NaiveTime;
+use lettre::message::MessageBuilder;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{Message, SmtpTransport, Transport};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::alert::{Alert, Listing};
+use crate::identity;
+
+pub const DEFAULT_SUBJECT: &str = "[config-guardian] {summary} in {dir} on {host}";
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How the SMTP connection is secured.
+#[derive(Clone, Copy, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain connection upgraded with STARTTLS (port 587).
+    Starttls,
+    /// TLS from the first byte (port 465).
+    Tls,
+    /// No encryption; only for trusted local relays (port 25).
+    None,
+}
+
+impl SmtpTls {
+    pub(crate) fn default_port(self) -> u16 {
+        match self {
+            SmtpTls::Starttls => 587,
+            SmtpTls::Tls => 465,
+            SmtpTls::None => 25,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    /// Username and password, if the server requires authentication.
+    pub credentials: Option<(String, String)>,
+    pub from: Mailbox,
+    pub to: Vec<Mailbox>,
+    /// Subject template; `{summary}`, `{dir}`, `{host}`, `{severity}` and
+    /// `{score}` are substituted.
+    pub subject: String,
+    /// Attach the drift entries as `drift-report.json`.
+    pub attach_report: bool,
+    /// Send time for a daily digest; when set, only critical alerts are
+    /// emailed immediately.
+    pub digest: Option<NaiveTime>,
+}
+
+/// SMTP credentials from the environment, if both variables are set.
+pub fn credentials_from_env() -> Option<(String, String)> {
+    let username = std::env::var("GUARDIAN_SMTP_USERNAME").ok()?;
+    let password = std::env::var("GUARDIAN_SMTP_PASSWORD").ok()?;
+    Some((username, password))
+}
+
+pub fn parse_mailbox(value: &str) -> Result<Mailbox, String> {
+    value
+        .parse()
+        .map_err(|e| format!("invalid email address '{}': {}", value, e))
+}
+
+fn render(template: &str, alert: &Alert, host: &str) -> String {
+    template
+        .replace("{summary}", &alert.summary)
+        .replace("{dir}", &alert.dir)
+        .replace("{host}", host)
+        .replace("{severity}", &alert.severity().to_string())
+        .replace("{score}", &alert.score().to_string())
+}
+
+fn body(alert: &Alert, host: &str, listing: &Listing, attached: bool) -> String {
+    let mut body = format!(
+        "Config Guardian on {} reports {} in {} (severity: {}, score: {}).\n",
+        host,
+        alert.summary,
+        alert.dir,
+        alert.severity(),
+        alert.score()
+    );
+    let (listed, omitted) = alert.listed(listing);
+    if !listed.is_empty() {
+        body.push('\n');
+        for drift in listed {
+            body.push_str(&format!("  {}\n", drift));
+        }
+    }
+    if omitted > 0 {
+        body.push_str(&format!("  {}\n", listing.trailer(omitted, attached)));
+    }
+    body
+}
+
+fn message_builder(config: &EmailConfig, subject: String) -> MessageBuilder {
+    let mut builder = Message::builder()
+        .from(config.from.clone())
+        .subject(subject);
+    for to in &config.to {
+        builder = builder.to(to.clone());
+    }
+    builder
+}
+
+fn build_message(
+    config: &EmailConfig,
+    alert: &Alert,
+    listing: &Listing,
+) -> Result<Message, String> {
+    let host = identity::label();
+    let builder = message_builder(config, render(&config.subject, alert, &host));
+
+    let attached = config.attach_report && !alert.drifts.is_empty();
+    let text = SinglePart::plain(body(alert, &host, listing, attached));
+    let result = if attached {
+        let report = serde_json::to_string_pretty(&alert.drifts).map_err(|e| e.to_string())?;
+        let attachment = Attachment::new("drift-report.json".to_string())
+            .body(report, ContentType::parse("application/json").unwrap());
+        builder.multipart(MultiPart::mixed().singlepart(text).singlepart(attachment))
+    } else {
+        builder.singlepart(text)
+    };
+    result.map_err(|e| format!("could not build email: {}", e))
+}
+
+fn transport(config: &EmailConfig) -> Result<SmtpTransport, String> {
+    let port = config.port.unwrap_or(config.tls.default_port());
+    let tls = match config.tls {
+        SmtpTls::None => Tls::None,
+        SmtpTls::Starttls | SmtpTls::Tls => {
+            let params = TlsParameters::new(config.host.clone())
+                .map_err(|e| format!("TLS setup for {} failed: {}", config.host, e))?;
+            if matches!(config.tls, SmtpTls::Tls) {
+                Tls::Wrapper(params)
+            } else {
+                Tls::Required(params)
+            }
+        }
+    };
+    let mut builder = SmtpTransport::builder_dangerous(&config.host)
+        .port(port)
+        .tls(tls)
+        .timeout(Some(SMTP_TIMEOUT));
+    if let Some((username, password)) = &config.credentials {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
+
+/// Turn an SMTP failure into a message that says what to check.
+fn describe(config: &EmailConfig, e: &lettre::transport::smtp::Error) -> String {
+    let server = format!(
+        "{}:{}",
+        config.host,
+        config.port.unwrap_or(config.tls.default_port())
+    );
+    if e.status()
+        .is_some_and(|code| code.to_string().starts_with("53"))
+    {
+        format!(
+            "SMTP authentication with {} failed: {}; check GUARDIAN_SMTP_USERNAME and GUARDIAN_SMTP_PASSWORD",
+            server, e
+        )
+    } else if e.is_tls() {
+        format!(
+            "TLS with {} failed: {}; check that --smtp-tls matches what the server expects",
+            server, e
+        )
+    } else if e.is_response() {
+        format!("SMTP server {} rejected the email: {}", server, e)
+    } else {
+        format!(
+            "could not reach SMTP server {}: {}; check --smtp-host and --smtp-port",
+            server, e
+        )
+    }
+}
+
+fn deliver(config: &EmailConfig, message: &Message) -> Result<(), String> {
+    transport(config)?
+        .send(message)
+        .map_err(|e| describe(config, &e))?;
+    log::info!(
+        "Email sent to {} recipient(s) via {}",
+        config.to.len(),
+        config.host
+    );
+    Ok(())
+}
+
+pub fn send_email_alert(
+    config: &EmailConfig,
+    alert: &Alert,
+    listing: &Listing,
+) -> Result<(), String> {
+    deliver(config, &build_message(config, alert, listing)?)
+}
+
+/// Send a plain-text email that isn't tied to a single alert.
+pub fn send_text(config: &EmailConfig, subject: &str, body: String) -> Result<(), String> {
+    let message = message_builder(config, subject.to_string())
+        .singlepart(SinglePart::plain(body))
+        .map_err(|e| format!("could not build email: {}", e))?;
+    deliver(config, &message)
+}
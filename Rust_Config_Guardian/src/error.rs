@@ -0,0 +1,131 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::utils;
+
+/// Why an operation failed, with the path involved where there is one.
+#[derive(Debug, thiserror::Error)]
+pub enum GuardianError {
+    #[error("No {} found. Run 'snapshot' command first.", path.display())]
+    SnapshotMissing { path: PathBuf },
+    #[error("{} is not a valid snapshot: {source}", path.display())]
+    SnapshotCorrupt {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// `--strict-load` was given and loading the snapshot would drop
+    /// entries.
+    #[error(
+        "{} has {} broken {} (--strict-load): {}",
+        path.display(),
+        problems.len(),
+        if problems.len() == 1 { "entry" } else { "entries" },
+        problems.join("; ")
+    )]
+    SnapshotEntries {
+        path: PathBuf,
+        problems: Vec<String>,
+    },
+    /// `compare --against` asked for more archived snapshots than there are.
+    #[error(
+        "Not enough snapshot history: going back {wanted} needs {wanted} archived {}, but {} holds {available}. One is archived each time 'snapshot' replaces the snapshot.",
+        if *wanted == 1 { "snapshot" } else { "snapshots" },
+        dir.display()
+    )]
+    ArchiveMissing {
+        dir: PathBuf,
+        wanted: usize,
+        available: usize,
+    },
+    /// A scan found more files than `max_files` allows.
+    #[error(
+        "Stopped scanning {} after finding {limit} files ({seen} entries looked at), the limit for one directory. Exclude what needn't be tracked, or raise the limit with --max-files N (0 for no limit).",
+        dir.display()
+    )]
+    TooManyFiles {
+        dir: PathBuf,
+        limit: usize,
+        seen: usize,
+    },
+    #[error("{} is not a valid directory.", path.display())]
+    NotADirectory { path: PathBuf },
+    /// A glob pattern given to scan matched nothing.
+    #[error("No files match {pattern}.")]
+    NoMatches { pattern: String },
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    /// The watched directory went away and could not be watched again.
+    #[error("Could not re-watch {} within {}; giving up.", path.display(), utils::format_duration(*timeout))]
+    WatchLost { path: PathBuf, timeout: Duration },
+    /// Neither the requested watch backend nor polling could watch the
+    /// directory.
+    #[error("Could not watch {} with any backend: {reason}", path.display())]
+    WatchFailed { path: PathBuf, reason: String },
+    /// A scan was stopped by Ctrl+C before it was done.
+    #[error(
+        "Interrupted while scanning {}, with {done} of {total} files read; nothing was saved.",
+        dir.display()
+    )]
+    Interrupted {
+        dir: PathBuf,
+        done: usize,
+        total: usize,
+    },
+    /// A scan was still going when `--timeout` ran out.
+    #[error(
+        "Gave up scanning {} when the {timeout:?} timeout ran out, with {done} of {total} files read; nothing was saved.",
+        dir.display()
+    )]
+    TimedOut {
+        dir: PathBuf,
+        timeout: Duration,
+        done: usize,
+        total: usize,
+    },
+    #[error("{0}")]
+    Alert(String),
+    /// `--require-fresh-baseline` was given and the baseline is older than
+    /// `--max-baseline-age`.
+    #[error(
+        "The baseline for {dir} is {days} days old, older than the {max} --max-baseline-age allows (--require-fresh-baseline); take a new snapshot, or compare --against one."
+    )]
+    StaleBaseline { dir: String, days: u64, max: String },
+    /// `--strict` was given and the run had warnings.
+    #[error("Failing because of the warnings above (--strict)")]
+    Strict { warnings: usize },
+    /// Invalid settings, from the command line, environment or config file.
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl GuardianError {
+    /// For `map_err`: an I/O error on `path`.
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> GuardianError {
+        let path = path.into();
+        move |source| GuardianError::Io { path, source }
+    }
+}
+
+impl From<String> for GuardianError {
+    fn from(message: String) -> Self {
+        GuardianError::Other(message)
+    }
+}
+
+impl From<&str> for GuardianError {
+    fn from(message: &str) -> Self {
+        GuardianError::Other(message.to_string())
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for GuardianError {
+    fn from(error: Box<dyn std::error::Error>) -> Self {
+        match error.downcast::<GuardianError>() {
+            Ok(error) => *error,
+            Err(error) => GuardianError::Other(error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Logging to the Windows Application event log under the `ConfigGuardian`
+//! source. Drift detections become Warning events and operational errors
+//! become Error events; informational records stay out of the event log.
+
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use std::ptr;
+use windows_sys::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_SUCCESS, HANDLE};
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+    REPORT_EVENT_TYPE, RegisterEventSourceW, ReportEventW,
+};
+use windows_sys::Win32::System::Registry::{
+    HKEY, HKEY_LOCAL_MACHINE, KEY_READ, KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ,
+    REG_OPTION_NON_VOLATILE, RegCloseKey, RegCreateKeyExW, RegOpenKeyExW, RegSetValueExW,
+};
+
+const SOURCE: &str = "ConfigGuardian";
+const SOURCE_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application\ConfigGuardian";
+/// EventCreate.exe ships a message table mapping IDs 1-1000 to "%1", so
+/// events render their text without a message DLL of our own.
+const MESSAGE_FILE: &str = r"%SystemRoot%\System32\EventCreate.exe";
+
+const EVENT_DRIFT: u32 = 1;
+const EVENT_WARNING: u32 = 2;
+const EVENT_ERROR: u32 = 3;
+
+pub struct EventLogLogger {
+    source: HANDLE,
+}
+
+// Event source handles may be used from any thread.
+unsafe impl Send for EventLogLogger {}
+unsafe impl Sync for EventLogLogger {}
+
+impl EventLogLogger {
+    /// Open the event source, registering it first if this is the first run.
+    pub fn open() -> Result<Self, String> {
+        register_source()?;
+        let name = wide(SOURCE);
+        let source = unsafe { RegisterEventSourceW(ptr::null(), name.as_ptr()) };
+        if source.is_null() {
+            return Err(format!(
+                "could not open event source {}: {}",
+                SOURCE,
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(EventLogLogger { source })
+    }
+}
+
+impl Drop for EventLogLogger {
+    fn drop(&mut self) {
+        unsafe { DeregisterEventSource(self.source) };
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Create the source's registry key unless it already exists. Creating it
+/// needs administrator rights, so that only has to happen once.
+fn register_source() -> Result<(), String> {
+    let key_name = wide(SOURCE_KEY);
+    let mut key: HKEY = ptr::null_mut();
+    let rc = unsafe { RegOpenKeyExW(HKEY_LOCAL_MACHINE, key_name.as_ptr(), 0, KEY_READ, &mut key) };
+    if rc == ERROR_SUCCESS {
+        unsafe { RegCloseKey(key) };
+        return Ok(());
+    }
+
+    let rc = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            key_name.as_ptr(),
+            0,
+            ptr::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            ptr::null(),
+            &mut key,
+            ptr::null_mut(),
+        )
+    };
+    if rc == ERROR_ACCESS_DENIED {
+        return Err(format!(
+            "registering the {} event source needs administrator rights; run once from an elevated prompt, or use --log-target file",
+            SOURCE
+        ));
+    }
+    if rc != ERROR_SUCCESS {
+        return Err(format!(
+            "could not register event source {}: {}",
+            SOURCE,
+            std::io::Error::from_raw_os_error(rc as i32)
+        ));
+    }
+
+    let message_file = wide(MESSAGE_FILE);
+    let types_supported: u32 =
+        (EVENTLOG_ERROR_TYPE | EVENTLOG_WARNING_TYPE | EVENTLOG_INFORMATION_TYPE) as u32;
+    let result = unsafe {
+        let rc = RegSetValueExW(
+            key,
+            wide("EventMessageFile").as_ptr(),
+            0,
+            REG_EXPAND_SZ,
+            message_file.as_ptr().cast(),
+            (message_file.len() * 2) as u32,
+        );
+        if rc == ERROR_SUCCESS {
+            RegSetValueExW(
+                key,
+                wide("TypesSupported").as_ptr(),
+                0,
+                REG_DWORD,
+                (&types_supported as *const u32).cast(),
+                4,
+            )
+        } else {
+            rc
+        }
+    };
+    unsafe { RegCloseKey(key) };
+    if result != ERROR_SUCCESS {
+        return Err(format!(
+            "could not register event source {}: {}",
+            SOURCE,
+            std::io::Error::from_raw_os_error(result as i32)
+        ));
+    }
+    Ok(())
+}
+
+/// Collects a record's key-values; drift records carry `event=drift`.
+#[derive(Default)]
+struct Fields {
+    is_drift: bool,
+    lines: Vec<String>,
+}
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        if key.as_str() == "event" {
+            self.is_drift = matches!(value.to_string().as_str(), "drift" | "drift_detected");
+        } else {
+            self.lines.push(format!("{}: {}", key, value));
+        }
+        Ok(())
+    }
+}
+
+impl Log for EventLogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn && metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+        let (kind, id): (REPORT_EVENT_TYPE, u32) = match record.level() {
+            Level::Error => (EVENTLOG_ERROR_TYPE, EVENT_ERROR),
+            _ if fields.is_drift => (EVENTLOG_WARNING_TYPE, EVENT_DRIFT),
+            _ => (EVENTLOG_WARNING_TYPE, EVENT_WARNING),
+        };
+
+        // The structured fields go below the message, as Event Viewer has
+        // nowhere else to show them.
+        let mut text = record.args().to_string();
+        if !fields.lines.is_empty() {
+            text.push_str("\r\n\r\n");
+            text.push_str(&fields.lines.join("\r\n"));
+        }
+        let message = wide(&text);
+        let strings = [message.as_ptr()];
+        // Nowhere left to report a failure to log.
+        unsafe {
+            ReportEventW(
+                self.source,
+                kind,
+                0,
+                id,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            )
+        };
+    }
+
+    fn flush(&self) {}
+}
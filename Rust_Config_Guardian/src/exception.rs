@@ -0,0 +1,264 @@
+//! Drift exceptions: change management's record that a file is expected to
+//! drift until a given time, and why. `exception add` keeps them in
+//! `exceptions.json`; comparisons report the drift they match as excepted
+//! rather than as drift, so it neither alerts nor fails a run, until they
+//! expire. Each one added, removed or expired is recorded in the history,
+//! so that why drift was tolerated, and for how long, can be told later.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::history::{self, Category, OutputFormat, Source, When};
+use crate::utils::{format_time, parse_duration, path_text};
+use crate::{Drift, DriftReport, redact};
+
+pub const EXCEPTIONS_FILE: &str = "exceptions.json";
+
+/// Drift to the files `path` matches is expected until `until`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Exception {
+    /// A path as comparisons record it, or a glob of them.
+    pub path: String,
+    pub until: DateTime<Utc>,
+    pub reason: String,
+    pub added: DateTime<Utc>,
+}
+
+impl Exception {
+    fn matches(&self, path: &str) -> bool {
+        self.path == path || glob::Pattern::new(&self.path).is_ok_and(|p| p.matches(path))
+    }
+
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.until <= now
+    }
+
+    /// The directory the history records it under: that of its path.
+    fn directory(&self) -> String {
+        Path::new(&self.path)
+            .parent()
+            .map(path_text)
+            .unwrap_or_default()
+    }
+
+    fn record(&self, category: Category, source: Source) {
+        let reason = format!("{}, until {}", self.reason, format_time(self.until));
+        if let Err(e) =
+            history::record_exception(&self.directory(), category, &self.path, &reason, source)
+        {
+            eprintln!("Warning: could not record drift history: {}", e);
+            log::error!("Could not record drift history: {}", e);
+        }
+    }
+}
+
+/// Drift left out of a report by the exception it matched.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Excepted {
+    #[serde(flatten)]
+    pub drift: Drift,
+    /// The exception's reason.
+    pub exception: String,
+    pub until: DateTime<Utc>,
+}
+
+/// Parse `--until`: a time as `history --since` takes one, or a duration
+/// (`2w`) meaning that long from now.
+pub fn parse_until(value: &str) -> Result<When, String> {
+    if let Ok(duration) = parse_duration(value) {
+        return chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|duration| Utc::now().checked_add_signed(duration))
+            .map(When::At)
+            .ok_or_else(|| format!("time '{}' is out of range", value));
+    }
+    history::parse_when(value).map_err(|_| {
+        format!(
+            "invalid time '{}' (expected e.g. 2024-05-01, '2024-05-01 14:30', '2024-05-01 14:30 +02:00', RFC 3339, or 7d for seven days from now)",
+            value
+        )
+    })
+}
+
+pub fn load() -> Result<Vec<Exception>, String> {
+    match std::fs::read_to_string(EXCEPTIONS_FILE) {
+        Ok(data) => serde_json::from_str(&data)
+            .map_err(|e| format!("could not parse {}: {}", EXCEPTIONS_FILE, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("could not read {}: {}", EXCEPTIONS_FILE, e)),
+    }
+}
+
+fn save(exceptions: &[Exception]) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(exceptions).map_err(|e| e.to_string())?;
+    let tmp = format!("{}.tmp", EXCEPTIONS_FILE);
+    std::fs::write(&tmp, data)
+        .and_then(|_| std::fs::rename(&tmp, EXCEPTIONS_FILE))
+        .map_err(|e| format!("could not write {}: {}", EXCEPTIONS_FILE, e))
+}
+
+/// Except drift to `path` until `until`, replacing any exception for the
+/// same path.
+pub fn add(path: &str, until: DateTime<Utc>, reason: &str) -> Result<Exception, String> {
+    let now = Utc::now();
+    if until <= now {
+        return Err(format!("--until {} has already passed", format_time(until)));
+    }
+    if let Err(e) = glob::Pattern::new(path) {
+        return Err(format!("invalid glob '{}': {}", path, e));
+    }
+    let mut exceptions = load()?;
+    exceptions.retain(|exception| exception.path != path);
+    let exception = Exception {
+        path: path.to_string(),
+        until,
+        reason: reason.to_string(),
+        added: now,
+    };
+    exceptions.push(exception.clone());
+    save(&exceptions)?;
+    exception.record(Category::ExceptionAdded, Source::Exception);
+    log::info!(
+        event = "exception_added",
+        path = redact::path(path).as_ref(),
+        until = until.to_rfc3339().as_str(),
+        reason = reason;
+        "Drift to {} excepted until {}: {}", redact::path(path), until, reason
+    );
+    Ok(exception)
+}
+
+/// Withdraw the exception for `path`, returning it if there was one.
+pub fn remove(path: &str) -> Result<Option<Exception>, String> {
+    let mut exceptions = load()?;
+    let Some(index) = exceptions.iter().position(|e| e.path == path) else {
+        return Ok(None);
+    };
+    let exception = exceptions.remove(index);
+    save(&exceptions)?;
+    exception.record(Category::ExceptionRemoved, Source::Exception);
+    log::info!(
+        event = "exception_removed",
+        path = redact::path(path).as_ref();
+        "Exception for {} removed", redact::path(path)
+    );
+    Ok(Some(exception))
+}
+
+/// Print the exceptions, soonest to expire first.
+pub fn list(format: OutputFormat) -> Result<(), String> {
+    let mut exceptions = load()?;
+    exceptions.sort_by_key(|exception| exception.until);
+    match format {
+        OutputFormat::Json => {
+            let data = serde_json::to_string_pretty(&exceptions).map_err(|e| e.to_string())?;
+            println!("{}", data);
+        }
+        OutputFormat::Text if exceptions.is_empty() => println!("No exceptions."),
+        OutputFormat::Text => {
+            let now = Utc::now();
+            println!("{:<25}  {:<7}  {:<30}  REASON", "UNTIL", "STATE", "PATH");
+            for exception in &exceptions {
+                println!(
+                    "{:<25}  {:<7}  {:<30}  {}",
+                    format_time(exception.until),
+                    if exception.expired(now) {
+                        "expired"
+                    } else {
+                        "active"
+                    },
+                    redact::shown(&exception.path),
+                    exception.reason
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move the drift an exception matches out of `report`'s drift and into its
+/// `excepted` list. Exceptions that have expired are removed on the way,
+/// with a warning for those whose drift is still there.
+pub(crate) fn set_aside_excepted(dir: &str, report: &mut DriftReport, source: Source) {
+    let exceptions = match load() {
+        Ok(exceptions) => exceptions,
+        Err(e) => {
+            eprintln!("Warning: exceptions not applied: {}", e);
+            log::error!("Could not load the drift exceptions: {}", e);
+            return;
+        }
+    };
+    if exceptions.is_empty() {
+        return;
+    }
+    let now = Utc::now();
+    let (expired, active): (Vec<Exception>, Vec<Exception>) =
+        exceptions.into_iter().partition(|e| e.expired(now));
+    if !expired.is_empty() {
+        if let Err(e) = save(&active) {
+            log::error!("Could not remove expired exceptions: {}", e);
+        }
+        for exception in &expired {
+            exception.record(Category::ExceptionExpired, source);
+            let persists = report.drifts.iter().any(|d| exception.matches(&d.path));
+            if persists {
+                eprintln!(
+                    "Warning: the exception for {} ({}) expired {}; its drift is reported again.",
+                    redact::shown(&exception.path),
+                    exception.reason,
+                    format_time(exception.until)
+                );
+            }
+            log::warn!(
+                event = "exception_expired",
+                dir = dir,
+                path = redact::path(&exception.path).as_ref(),
+                drifting = persists;
+                "Exception for {} expired at {}", redact::path(&exception.path), exception.until
+            );
+        }
+    }
+    let mut drifts = Vec::new();
+    for drift in report.drifts.drain(..) {
+        match active.iter().find(|e| e.matches(&drift.path)) {
+            Some(exception) => report.excepted.push(Excepted {
+                drift,
+                exception: exception.reason.clone(),
+                until: exception.until,
+            }),
+            None => drifts.push(drift),
+        }
+    }
+    report.drifts = drifts;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exceptions_match_their_path_or_glob_until_they_expire() {
+        let now = Utc::now();
+        let exception = |path: &str| Exception {
+            path: path.into(),
+            until: now,
+            reason: "CHG-1234".into(),
+            added: now,
+        };
+        assert!(exception("/etc/app/flag.conf").matches("/etc/app/flag.conf"));
+        assert!(exception("/etc/app/*.conf").matches("/etc/app/flag.conf"));
+        assert!(!exception("/etc/app/flag.conf").matches("/etc/app/app.conf"));
+        assert!(exception("/etc/app/flag.conf").expired(now));
+        assert!(!exception("/etc/app/flag.conf").expired(now - chrono::Duration::seconds(1)));
+        assert_eq!(exception("/etc/app/flag.conf").directory(), "/etc/app");
+
+        let Ok(When::At(until)) = parse_until("2h") else {
+            panic!("a duration is a time from now");
+        };
+        assert!(until > now + chrono::Duration::minutes(119));
+        assert!(matches!(parse_until("2024-07-01"), Ok(When::Wall(_))));
+        let error = parse_until("soon").unwrap_err();
+        assert!(error.contains("7d for seven days from now"), "{}", error);
+    }
+}
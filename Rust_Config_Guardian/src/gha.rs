@@ -0,0 +1,214 @@
+//! `compare --format gha`: drift as GitHub Actions workflow commands, which
+//! the run's page shows as annotations, and a table of it in the job's step
+//! summary.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::alert::{self, Severity};
+use crate::config::SHORT_HASH_LEN;
+use crate::{Drift, GuardianError};
+use config_guardian::{DriftReport, Hashes};
+
+pub const TITLE: &str = "Config drift";
+/// The title of drift `compare --staged` predicts.
+pub const HYPOTHETICAL_TITLE: &str = "Config drift (hypothetical)";
+
+fn title(hypothetical: bool) -> &'static str {
+    if hypothetical {
+        HYPOTHETICAL_TITLE
+    } else {
+        TITLE
+    }
+}
+
+/// Whether compare runs in a GitHub Actions job, as the runner tells its
+/// steps.
+pub fn detected() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+/// The annotation command for drift at `severity`.
+fn command(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Critical => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "notice",
+    }
+}
+
+/// `text` escaped as a workflow command's message.
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// `text` escaped as a workflow command's property value.
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn short(hash: Option<&str>) -> String {
+    match hash {
+        None => "-".to_string(),
+        Some(hash) if hash.len() > SHORT_HASH_LEN => format!("{}…", &hash[..SHORT_HASH_LEN]),
+        Some(hash) => hash.to_string(),
+    }
+}
+
+/// What drifted, as `Changed (hash abc… → def…)`.
+fn message(drift: &Drift, hashes: &Hashes) -> String {
+    let mut message = drift.kind.to_string();
+    if hashes.old.is_some() && hashes.new.is_some() {
+        message.push_str(&format!(
+            " (hash {} → {})",
+            short(hashes.old.as_deref()),
+            short(hashes.new.as_deref())
+        ));
+    } else if let Some(reason) = &drift.reason {
+        message.push_str(&format!(" ({})", reason));
+    }
+    message
+}
+
+/// One annotation line per drifting file of `report`.
+pub fn annotations(report: &DriftReport) -> Vec<String> {
+    report
+        .drifts
+        .iter()
+        .map(|drift| {
+            let hashes = report.hashes.get(&drift.path).cloned().unwrap_or_default();
+            format!(
+                "::{} file={},title={}::{}",
+                command(alert::severity_of(drift)),
+                escape_property(&drift.path.replace('\\', "/")),
+                escape_property(title(report.hypothetical)),
+                escape_data(&message(drift, &hashes))
+            )
+        })
+        .collect()
+}
+
+/// `text` safe in a markdown table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+/// The step summary of the comparisons of `reports`' directories: how much
+/// drifted, and a table of it.
+pub fn summary(reports: &[(String, DriftReport)]) -> String {
+    let drifts: usize = reports.iter().map(|(_, r)| r.drifts.len()).sum();
+    let hypothetical = reports.iter().any(|(_, r)| r.hypothetical);
+    let mut summary = format!("### {}\n\n", title(hypothetical));
+    if drifts == 0 {
+        summary.push_str(&format!(
+            "No drift in {} director{}.\n",
+            reports.len(),
+            if reports.len() == 1 { "y" } else { "ies" }
+        ));
+        return summary;
+    }
+    summary.push_str(&format!(
+        "{} drifted file{}.\n\n| Directory | File | Drift | Severity | Old hash | New hash |\n|---|---|---|---|---|---|\n",
+        drifts,
+        if drifts == 1 { "" } else { "s" }
+    ));
+    for (dir, report) in reports {
+        for drift in &report.drifts {
+            let hashes = report.hashes.get(&drift.path).cloned().unwrap_or_default();
+            summary.push_str(&format!(
+                "| {} | `{}` | {} | {} | {} | {} |\n",
+                cell(dir),
+                cell(&drift.path),
+                drift.kind,
+                alert::severity_of(drift),
+                short(hashes.old.as_deref()),
+                short(hashes.new.as_deref())
+            ));
+        }
+    }
+    summary
+}
+
+/// Append `summary` to the step summary file the runner names in
+/// `GITHUB_STEP_SUMMARY`, if it names one.
+pub fn write_summary(summary: &str) -> Result<(), GuardianError> {
+    let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY").filter(|p| !p.is_empty()) else {
+        return Ok(());
+    };
+    let path = Path::new(&path);
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(summary.as_bytes()))
+        .map_err(|source| GuardianError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileHash, Snapshot};
+    use config_guardian::compare;
+
+    fn report() -> DriftReport {
+        let entry = |path: &str, hash: &str| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("d/app.conf", "aaaaaaaaaaaaaaaa"),
+                entry("d/old.conf", "bb"),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("d/app.conf", "cccccccccccccccc"),
+                entry("d/a,b:c.conf", "dd"),
+            ],
+            ..Snapshot::default()
+        };
+        compare(&baseline, &current)
+    }
+
+    #[test]
+    fn drift_becomes_annotations_at_its_severity() {
+        assert_eq!(
+            annotations(&report()),
+            [
+                "::warning file=d/app.conf,title=Config drift::Changed (hash aaaaaaaaaaaa… → cccccccccccc…)",
+                "::notice file=d/a%2Cb%3Ac.conf,title=Config drift::New",
+                "::error file=d/old.conf,title=Config drift::Deleted",
+            ]
+        );
+        assert_eq!(escape_data("50%\nmore"), "50%25%0Amore");
+    }
+
+    #[test]
+    fn the_summary_tables_the_drift() {
+        let summary = summary(&[("d".to_string(), report())]);
+        assert!(summary.starts_with("### Config drift\n\n3 drifted files.\n\n| Directory |"));
+        assert!(
+            summary.contains(
+                "| d | `d/app.conf` | Changed | warning | aaaaaaaaaaaa… | cccccccccccc… |\n"
+            ),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("| d | `d/old.conf` | Deleted | critical | bb | - |\n"));
+        assert_eq!(
+            super::summary(&[(
+                "d".to_string(),
+                compare(&Snapshot::default(), &Snapshot::default())
+            )]),
+            "### Config drift\n\nNo drift in 1 directory.\n"
+        );
+    }
+}
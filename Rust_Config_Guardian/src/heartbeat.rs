@@ -0,0 +1,71 @@
+//! Periodic "still alive" messages from monitor mode, so a monitor that died
+//! silently is noticed. Heartbeats are independent of drift alerting and are
+//! never subject to its throttling.
+
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant};
+
+use crate::http;
+use crate::utils::{format_duration, format_time};
+
+const URL_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Heartbeat {
+    interval: Duration,
+    next_beat: Instant,
+    url: Option<String>,
+}
+
+/// What a heartbeat reports about the session since the previous one.
+pub struct HeartbeatStatus {
+    pub uptime: Duration,
+    pub drift_in_interval: u64,
+    pub files_tracked: usize,
+    pub last_comparison: Option<DateTime<Utc>>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, url: Option<String>) -> Self {
+        Heartbeat {
+            interval,
+            next_beat: Instant::now() + interval,
+            url,
+        }
+    }
+
+    pub fn due(&self) -> bool {
+        Instant::now() >= self.next_beat
+    }
+
+    pub fn beat(&mut self, status: &HeartbeatStatus) {
+        self.next_beat = Instant::now() + self.interval;
+
+        let last = status
+            .last_comparison
+            .map(format_time)
+            .unwrap_or_else(|| "never".to_string());
+        let message = format!(
+            "Heartbeat: alive, {} drift in last {}, watching {} files, uptime {}s, last comparison {}",
+            status.drift_in_interval,
+            format_duration(self.interval),
+            status.files_tracked,
+            status.uptime.as_secs(),
+            last
+        );
+        println!("{}", message);
+        log::info!("{}", message);
+
+        if let Some(url) = &self.url {
+            // Pinged from a thread so a slow endpoint can't stall event handling.
+            let url = url.clone();
+            std::thread::spawn(move || {
+                let result = http::agent(URL_TIMEOUT)
+                    .and_then(|agent| agent.get(&url).call().map_err(|e| http::describe(&e)));
+                if let Err(e) = result {
+                    // Check URLs identify the check, so only the host is logged.
+                    log::warn!("Heartbeat ping to {} failed: {}", http::origin(&url), e);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,869 @@
+//! Persistent drift history: every drift entry observed by `compare` or
+//! `monitor` is appended to a JSONL file so past changes can be queried
+//! with `history`.
+//!
+//! The same drift is re-detected by every comparison until the snapshot is
+//! retaken, so an entry is only recorded when it differs from the latest
+//! record for that path. A drifted file that later matches the snapshot
+//! again is recorded as restored, and `accept`/`snapshot` record the drift
+//! they absorb into the baseline as accepted. Drift exceptions being added,
+//! removed and expiring are recorded too, with their reason. Records older
+//! than the retention period are trimmed whenever the file is written.
+//!
+//! The last comparison of each directory, when it finished and how long it
+//! took, is kept apart in a small JSON file, for `stats`.
+//!
+//! Each record names who ran the command that made it, and how (see
+//! [`crate::invoker`]), for `history --user`.
+//!
+//! Files `redact` lists are recorded by their placeholder, never by name.
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::{self, HashDisplay, SHORT_HASH_LEN};
+use crate::invoker::{self, Invoker};
+use crate::utils::{self, format_time, parse_duration};
+use crate::{DriftKind, FileHash, redact};
+
+pub const HISTORY_FILE: &str = "drift-history.jsonl";
+pub const RUNS_FILE: &str = "compare-runs.json";
+const RETENTION_DAYS: i64 = 90;
+
+/// What recorded the entry.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Compare,
+    Monitor,
+    Snapshot,
+    Accept,
+    Exception,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Compare => "compare",
+            Source::Monitor => "monitor",
+            Source::Snapshot => "snapshot",
+            Source::Accept => "accept",
+            Source::Exception => "exception",
+        }
+    }
+}
+
+/// A drift kind, or what later happened to a drift.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Changed,
+    Truncated,
+    Populated,
+    New,
+    #[serde(rename = "new_executable")]
+    NewExecutable,
+    #[serde(rename = "new_setuid")]
+    NewSetuid,
+    Deleted,
+    Unreadable,
+    Locked,
+    #[serde(rename = "mount_changed")]
+    MountChanged,
+    #[serde(rename = "attributes_changed")]
+    AttributesChanged,
+    #[serde(rename = "acl_changed")]
+    AclChanged,
+    #[serde(rename = "inode_replaced")]
+    InodeReplaced,
+    #[serde(rename = "link_count_changed")]
+    LinkCountChanged,
+    #[serde(rename = "permissions_changed")]
+    PermissionsChanged,
+    #[serde(rename = "type_changed")]
+    TypeChanged,
+    #[serde(rename = "symlink_retargeted")]
+    SymlinkRetargeted,
+    #[serde(rename = "symlink_escaped")]
+    SymlinkEscaped,
+    #[serde(rename = "baseline_modified")]
+    BaselineModified,
+    /// The current content was taken into the baseline.
+    Accepted,
+    /// The file matches the baseline again.
+    Restored,
+    /// Drift to the path was excepted, until a time.
+    #[serde(rename = "exception_added")]
+    ExceptionAdded,
+    #[serde(rename = "exception_removed")]
+    ExceptionRemoved,
+    #[serde(rename = "exception_expired")]
+    ExceptionExpired,
+    /// A reviewed report was applied; the reason says what came of it.
+    Reviewed,
+}
+
+impl Category {
+    fn as_str(self) -> &'static str {
+        match self {
+            Category::Changed => "changed",
+            Category::Truncated => "truncated",
+            Category::Populated => "populated",
+            Category::New => "new",
+            Category::NewExecutable => "new_executable",
+            Category::NewSetuid => "new_setuid",
+            Category::Deleted => "deleted",
+            Category::Unreadable => "unreadable",
+            Category::Locked => "locked",
+            Category::MountChanged => "mount_changed",
+            Category::AttributesChanged => "attributes_changed",
+            Category::AclChanged => "acl_changed",
+            Category::InodeReplaced => "inode_replaced",
+            Category::LinkCountChanged => "link_count_changed",
+            Category::PermissionsChanged => "permissions_changed",
+            Category::TypeChanged => "type_changed",
+            Category::SymlinkRetargeted => "symlink_retargeted",
+            Category::SymlinkEscaped => "symlink_escaped",
+            Category::BaselineModified => "baseline_modified",
+            Category::Accepted => "accepted",
+            Category::Restored => "restored",
+            Category::ExceptionAdded => "exception_added",
+            Category::ExceptionRemoved => "exception_removed",
+            Category::ExceptionExpired => "exception_expired",
+            Category::Reviewed => "reviewed",
+        }
+    }
+
+    fn is_exception(self) -> bool {
+        matches!(
+            self,
+            Category::ExceptionAdded | Category::ExceptionRemoved | Category::ExceptionExpired
+        )
+    }
+
+    pub fn is_drift(self) -> bool {
+        matches!(
+            self,
+            Category::Changed
+                | Category::Truncated
+                | Category::Populated
+                | Category::New
+                | Category::NewExecutable
+                | Category::NewSetuid
+                | Category::Deleted
+                | Category::Unreadable
+                | Category::Locked
+                | Category::MountChanged
+                | Category::AttributesChanged
+                | Category::AclChanged
+                | Category::InodeReplaced
+                | Category::LinkCountChanged
+                | Category::PermissionsChanged
+                | Category::TypeChanged
+                | Category::SymlinkRetargeted
+                | Category::SymlinkEscaped
+                | Category::BaselineModified
+        )
+    }
+}
+
+impl From<DriftKind> for Category {
+    fn from(kind: DriftKind) -> Self {
+        match kind {
+            DriftKind::Changed => Category::Changed,
+            DriftKind::Truncated => Category::Truncated,
+            DriftKind::Populated => Category::Populated,
+            DriftKind::New => Category::New,
+            DriftKind::NewExecutable => Category::NewExecutable,
+            DriftKind::NewSetuid => Category::NewSetuid,
+            DriftKind::Deleted => Category::Deleted,
+            DriftKind::Unreadable => Category::Unreadable,
+            DriftKind::Locked => Category::Locked,
+            DriftKind::MountChanged => Category::MountChanged,
+            DriftKind::AttributesChanged => Category::AttributesChanged,
+            DriftKind::AclChanged => Category::AclChanged,
+            DriftKind::InodeReplaced => Category::InodeReplaced,
+            DriftKind::LinkCountChanged => Category::LinkCountChanged,
+            DriftKind::PermissionsChanged => Category::PermissionsChanged,
+            DriftKind::TypeChanged => Category::TypeChanged,
+            DriftKind::SymlinkRetargeted => Category::SymlinkRetargeted,
+            DriftKind::SymlinkEscaped => Category::SymlinkEscaped,
+            DriftKind::BaselineModified => Category::BaselineModified,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    pub directory: String,
+    pub category: Category,
+    pub path: String,
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+    pub source: Source,
+    /// Why, for an exception: its reason and expiry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// When the path first drifted, for a record that ends drift or finds
+    /// it drifting differently.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<DateTime<Utc>>,
+    /// What an accepted change changed, such as `+3 -1 lines`, where the
+    /// content before and after it is known; see [`crate::content`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Who ran the command that recorded it; filled in as it's written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoker: Option<Invoker>,
+}
+
+impl HistoryEntry {
+    fn same_change(&self, other: &HistoryEntry) -> bool {
+        self.category == other.category
+            && self.old_hash == other.old_hash
+            && self.new_hash == other.new_hash
+    }
+}
+
+/// Which records `history` prints.
+pub struct HistoryFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub path: Option<glob::Pattern>,
+    /// The effective user or, under sudo, the one who ran it.
+    pub user: Option<String>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp <= until)
+            && self.path.as_ref().is_none_or(|p| p.matches(&entry.path))
+            && self.user.as_ref().is_none_or(|user| {
+                entry
+                    .invoker
+                    .as_ref()
+                    .is_some_and(|invoker| invoker.is(user))
+            })
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// A time given to `--since` or `--until`. One without an offset is taken
+/// as local time, or UTC with `--utc`, which isn't known when it's parsed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum When {
+    At(DateTime<Utc>),
+    Wall(NaiveDateTime),
+}
+
+impl When {
+    pub fn resolve(self) -> Result<DateTime<Utc>, String> {
+        match self {
+            When::At(time) => Ok(time),
+            When::Wall(naive) if config::settings().utc => Ok(naive.and_utc()),
+            When::Wall(naive) => Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|time| time.to_utc())
+                .ok_or_else(|| format!("{} does not exist in the local time zone", naive)),
+        }
+    }
+}
+
+/// Parse a point in time: RFC 3339, `YYYY-MM-DD HH:MM[:SS]` with or without
+/// an offset, `YYYY-MM-DD`, or a duration (`7d`, `12h`) meaning that long
+/// ago.
+pub fn parse_when(value: &str) -> Result<When, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(When::At(time.to_utc()));
+    }
+    // Either `T` or a space may separate the date from the time.
+    let spaced = value.replacen('T', " ", 1);
+    for format in ["%Y-%m-%d %H:%M:%S %#z", "%Y-%m-%d %H:%M %#z"] {
+        if let Ok(time) = DateTime::parse_from_str(&spaced, format) {
+            return Ok(When::At(time.to_utc()));
+        }
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(&spaced, format) {
+            return Ok(When::Wall(naive));
+        }
+    }
+    if let Some(midnight) = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+    {
+        return Ok(When::Wall(midnight));
+    }
+    let ago = parse_duration(value).map_err(|_| {
+        format!(
+            "invalid time '{}' (expected e.g. 2024-05-01, '2024-05-01 14:30', '2024-05-01 14:30 +02:00', RFC 3339, or 7d for seven days ago)",
+            value
+        )
+    })?;
+    Duration::from_std(ago)
+        .ok()
+        .and_then(|ago| Utc::now().checked_sub_signed(ago))
+        .map(When::At)
+        .ok_or_else(|| format!("time '{}' is out of range", value))
+}
+
+pub fn load() -> Result<Vec<HistoryEntry>, String> {
+    let data = match std::fs::read_to_string(HISTORY_FILE) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("could not read {}: {}", HISTORY_FILE, e)),
+    };
+    let mut entries = Vec::new();
+    for (number, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            // A torn final line from a crash shouldn't hide the rest.
+            Err(e) => log::warn!("Skipping line {} of {}: {}", number + 1, HISTORY_FILE, e),
+        }
+    }
+    Ok(entries)
+}
+
+fn write_all(entries: &[HistoryEntry]) -> Result<(), String> {
+    let mut data = String::new();
+    for entry in entries {
+        data.push_str(&serde_json::to_string(entry).map_err(|e| e.to_string())?);
+        data.push('\n');
+    }
+    // Written aside and renamed so a crash can't truncate the history.
+    let tmp = format!("{}.tmp", HISTORY_FILE);
+    std::fs::write(&tmp, data)
+        .and_then(|_| std::fs::rename(&tmp, HISTORY_FILE))
+        .map_err(|e| format!("could not write {}: {}", HISTORY_FILE, e))
+}
+
+fn append(history: Vec<HistoryEntry>, mut fresh: Vec<HistoryEntry>) -> Result<(), String> {
+    for entry in &mut fresh {
+        entry
+            .invoker
+            .get_or_insert_with(|| invoker::current().clone());
+    }
+    let cutoff = Utc::now() - Duration::days(RETENTION_DAYS);
+    if history.iter().any(|entry| entry.timestamp < cutoff) {
+        let mut history: Vec<HistoryEntry> = history
+            .into_iter()
+            .filter(|entry| entry.timestamp >= cutoff)
+            .collect();
+        history.extend(fresh);
+        return write_all(&history);
+    }
+    if fresh.is_empty() {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(HISTORY_FILE)
+        .map_err(|e| format!("could not open {}: {}", HISTORY_FILE, e))?;
+    for entry in &fresh {
+        let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        writeln!(file, "{}", line)
+            .map_err(|e| format!("could not write {}: {}", HISTORY_FILE, e))?;
+    }
+    Ok(())
+}
+
+/// `snapshot`'s hashes by the path the history records, the first entry
+/// for a path winning.
+fn hashes_of(snapshot: &[FileHash]) -> HashMap<Cow<'_, str>, &str> {
+    snapshot
+        .iter()
+        .rev()
+        .map(|f| (redact::path(&f.path), f.hash.as_str()))
+        .collect()
+}
+
+/// The latest record for each path in `dir`.
+fn latest_in<'a>(history: &'a [HistoryEntry], dir: &str) -> HashMap<&'a str, &'a HistoryEntry> {
+    let mut latest = HashMap::new();
+    for entry in history.iter().filter(|entry| entry.directory == dir) {
+        latest.insert(entry.path.as_str(), entry);
+    }
+    latest
+}
+
+/// When each path of `dir` that drifts as of its latest record first
+/// drifted: the time of the first drift record since it last didn't.
+fn drifting_since<'a>(history: &'a [HistoryEntry], dir: &str) -> HashMap<&'a str, DateTime<Utc>> {
+    let mut since = HashMap::new();
+    for entry in history.iter().filter(|entry| entry.directory == dir) {
+        if entry.category.is_exception() {
+            continue;
+        }
+        if entry.category.is_drift() {
+            since.entry(entry.path.as_str()).or_insert(entry.timestamp);
+        } else {
+            since.remove(entry.path.as_str());
+        }
+    }
+    since
+}
+
+/// Record the result of comparing `dir` against `snapshot`: `drifts` holds
+/// every drift entry found, recorded unless it is already the latest record
+/// for its path. Paths whose latest record is a drift but that no longer
+/// drift are recorded as restored.
+pub fn record(
+    dir: &str,
+    source: Source,
+    drifts: Vec<HistoryEntry>,
+    snapshot: &[FileHash],
+) -> Result<(), String> {
+    record_in(dir, source, drifts, snapshot, None)
+}
+
+/// [`record`] for a comparison of just `paths` in `dir`: other paths are
+/// left as they are, rather than recorded as restored.
+pub fn record_paths(
+    dir: &str,
+    source: Source,
+    drifts: Vec<HistoryEntry>,
+    snapshot: &[FileHash],
+    paths: &[String],
+) -> Result<(), String> {
+    record_in(dir, source, drifts, snapshot, Some(paths))
+}
+
+fn record_in(
+    dir: &str,
+    source: Source,
+    drifts: Vec<HistoryEntry>,
+    snapshot: &[FileHash],
+    paths: Option<&[String]>,
+) -> Result<(), String> {
+    let history = load()?;
+    let mut latest = latest_in(&history, dir);
+    if let Some(paths) = paths {
+        latest.retain(|path, _| paths.iter().any(|p| redact::path(p) == *path));
+    }
+    let since = drifting_since(&history, dir);
+    let now = Utc::now();
+    let hashes = hashes_of(snapshot);
+    let drifting: HashSet<&str> = drifts.iter().map(|d| d.path.as_str()).collect();
+    let mut fresh: Vec<HistoryEntry> = latest
+        .values()
+        .filter(|last| last.category.is_drift())
+        .filter(|last| !drifting.contains(last.path.as_str()))
+        .map(|last| HistoryEntry {
+            timestamp: now,
+            directory: dir.to_string(),
+            category: Category::Restored,
+            path: last.path.clone(),
+            old_hash: last.new_hash.clone(),
+            new_hash: hashes.get(last.path.as_str()).map(|h| h.to_string()),
+            source,
+            reason: None,
+            first_seen: since.get(last.path.as_str()).copied(),
+            summary: None,
+            invoker: None,
+        })
+        .collect();
+    fresh.sort_by(|a, b| a.path.cmp(&b.path));
+    fresh.extend(
+        drifts
+            .into_iter()
+            .filter(|entry| {
+                latest
+                    .get(entry.path.as_str())
+                    .is_none_or(|last| !last.same_change(entry))
+            })
+            .map(|entry| HistoryEntry {
+                first_seen: since.get(entry.path.as_str()).copied(),
+                ..entry
+            }),
+    );
+    append(history, fresh)
+}
+
+/// Record that `path` in `dir` was taken into the baseline with `new_hash`,
+/// with `summary` saying what changed.
+pub fn record_accept(
+    dir: &str,
+    path: &str,
+    old_hash: Option<String>,
+    new_hash: Option<String>,
+    reason: Option<&str>,
+    summary: Option<String>,
+) -> Result<(), String> {
+    let history = load()?;
+    let entry = HistoryEntry {
+        timestamp: Utc::now(),
+        directory: dir.to_string(),
+        category: Category::Accepted,
+        path: redact::path(path).into_owned(),
+        old_hash,
+        new_hash,
+        source: Source::Accept,
+        reason: reason.map(str::to_string),
+        first_seen: None,
+        summary,
+        invoker: None,
+    };
+    append(history, vec![entry])
+}
+
+/// Record that the exception for `path` was added, removed or expired, as
+/// `category` says, with its `reason`.
+pub fn record_exception(
+    dir: &str,
+    category: Category,
+    path: &str,
+    reason: &str,
+    source: Source,
+) -> Result<(), String> {
+    let history = load()?;
+    let entry = HistoryEntry {
+        timestamp: Utc::now(),
+        directory: dir.to_string(),
+        category,
+        path: redact::path(path).into_owned(),
+        old_hash: None,
+        new_hash: None,
+        source,
+        reason: Some(reason.to_string()),
+        first_seen: None,
+        summary: None,
+        invoker: None,
+    };
+    append(history, vec![entry])
+}
+
+/// Record that the reviewed report at `report` was applied, with `summary`
+/// saying how many of its entries were, and which were refused.
+pub fn record_review(report: &str, summary: &str) -> Result<(), String> {
+    let history = load()?;
+    let entry = HistoryEntry {
+        timestamp: Utc::now(),
+        directory: Path::new(report)
+            .parent()
+            .map(utils::path_text)
+            .unwrap_or_default(),
+        category: Category::Reviewed,
+        path: report.to_string(),
+        old_hash: None,
+        new_hash: None,
+        source: Source::Accept,
+        reason: Some(summary.to_string()),
+        first_seen: None,
+        summary: None,
+        invoker: None,
+    };
+    append(history, vec![entry])
+}
+
+/// A new snapshot of `dir` accepts every drift still outstanding there.
+pub fn record_snapshot(dir: &str, snapshot: &[FileHash]) -> Result<(), String> {
+    let history = load()?;
+    let now = Utc::now();
+    let hashes = hashes_of(snapshot);
+    let since = drifting_since(&history, dir);
+    let mut fresh: Vec<HistoryEntry> = latest_in(&history, dir)
+        .into_values()
+        .filter(|last| last.category.is_drift())
+        .map(|last| HistoryEntry {
+            timestamp: now,
+            directory: dir.to_string(),
+            category: Category::Accepted,
+            path: last.path.clone(),
+            old_hash: last.old_hash.clone(),
+            new_hash: hashes.get(last.path.as_str()).map(|h| h.to_string()),
+            source: Source::Snapshot,
+            reason: None,
+            first_seen: since.get(last.path.as_str()).copied(),
+            summary: None,
+            invoker: None,
+        })
+        .collect();
+    fresh.sort_by(|a, b| a.path.cmp(&b.path));
+    append(history, fresh)
+}
+
+/// The directory a path was last recorded under, if any.
+pub fn directory_of(path: &str) -> Result<Option<String>, String> {
+    Ok(load()?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.path == redact::path(path))
+        .map(|entry| entry.directory))
+}
+
+/// The last comparison of a directory against the current snapshot.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub finished: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub files: usize,
+    pub drifts: usize,
+    pub source: Source,
+}
+
+/// The last comparison of each directory, by directory.
+pub fn load_runs() -> Result<BTreeMap<String, Run>, String> {
+    match std::fs::read_to_string(RUNS_FILE) {
+        Ok(data) => {
+            serde_json::from_str(&data).map_err(|e| format!("could not parse {}: {}", RUNS_FILE, e))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("could not read {}: {}", RUNS_FILE, e)),
+    }
+}
+
+/// Record `run` as the last comparison of `dir`. A damaged file is
+/// replaced rather than kept failing every comparison.
+pub fn record_run(dir: &str, run: Run) -> Result<(), String> {
+    let mut runs = load_runs().unwrap_or_default();
+    runs.insert(dir.to_string(), run);
+    let data = serde_json::to_string_pretty(&runs).map_err(|e| e.to_string())?;
+    let tmp = format!("{}.tmp", RUNS_FILE);
+    std::fs::write(&tmp, data)
+        .and_then(|_| std::fs::rename(&tmp, RUNS_FILE))
+        .map_err(|e| format!("could not write {}: {}", RUNS_FILE, e))
+}
+
+/// `hash` as text output shows it.
+fn shown(hash: &Option<String>) -> &str {
+    config::settings()
+        .hash_display
+        .show(hash.as_deref())
+        .unwrap_or_default()
+}
+
+/// `cells` as padded hash columns, or nothing when hashes are left out.
+fn hash_columns(cells: &[&str]) -> String {
+    let settings = config::settings();
+    let width = match settings.hash_display {
+        HashDisplay::Short => SHORT_HASH_LEN,
+        HashDisplay::Full => settings.hash.hex_len(),
+        HashDisplay::None => return String::new(),
+    };
+    cells
+        .iter()
+        .map(|cell| format!("{:<width$}  ", cell))
+        .collect()
+}
+
+/// Print the records matching `filter`, oldest first.
+pub fn show(
+    filter: &HistoryFilter,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entries: Vec<HistoryEntry> = load()?
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .collect();
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        OutputFormat::Text => {
+            if entries.is_empty() {
+                println!("No matching drift history.");
+                return Ok(());
+            }
+            println!(
+                "{:<25}  {:<8}  {:<8}  {}PATH",
+                "TIME",
+                "SOURCE",
+                "CATEGORY",
+                hash_columns(&["OLD", "NEW"])
+            );
+            for entry in &entries {
+                let reason = match (&entry.reason, entry.first_seen) {
+                    (Some(reason), _) => format!(" ({})", reason),
+                    (None, Some(since)) => format!(" (drifting since {})", format_time(since)),
+                    (None, None) => String::new(),
+                };
+                let by = match &entry.invoker {
+                    Some(invoker) => format!(" by {}", invoker),
+                    None => String::new(),
+                };
+                let summary = match &entry.summary {
+                    Some(summary) => format!(" [{}]", summary),
+                    None => String::new(),
+                };
+                println!(
+                    "{:<25}  {:<8}  {:<8}  {}{}{}{}{}",
+                    format_time(entry.timestamp),
+                    entry.source.as_str(),
+                    entry.category.as_str(),
+                    hash_columns(&[shown(&entry.old_hash), shown(&entry.new_hash)]),
+                    entry.path,
+                    summary,
+                    reason,
+                    by
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One recorded version of a file: a hash first observed at `first_seen`,
+/// and what later happened to it.
+#[derive(Serialize)]
+struct Version {
+    hash: Option<String>,
+    first_seen: DateTime<Utc>,
+    change: Category,
+    outcome: Option<Category>,
+    outcome_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct Timeline {
+    path: String,
+    /// The baseline hash before the first recorded change.
+    baseline_hash: Option<String>,
+    versions: Vec<Version>,
+}
+
+fn build_timelines(entries: &[HistoryEntry]) -> Vec<Timeline> {
+    let mut timelines: Vec<Timeline> = Vec::new();
+    for entry in entries {
+        let index = match timelines.iter().position(|t| t.path == entry.path) {
+            Some(index) => index,
+            None => {
+                timelines.push(Timeline {
+                    path: entry.path.clone(),
+                    baseline_hash: entry.old_hash.clone(),
+                    versions: Vec::new(),
+                });
+                timelines.len() - 1
+            }
+        };
+        let versions = &mut timelines[index].versions;
+        let open = versions.last_mut().filter(|v| v.outcome.is_none());
+        match (entry.category, open) {
+            (Category::Accepted | Category::Restored, Some(open)) => {
+                open.outcome = Some(entry.category);
+                open.outcome_at = Some(entry.timestamp);
+            }
+            // Restoring something never seen drift leaves nothing to show,
+            // and an exception is no version of the file.
+            (Category::Restored, None) => {}
+            (category, _) if category.is_exception() => {}
+            (category, _) => {
+                // A later change supersedes any version still open.
+                versions.push(Version {
+                    hash: entry.new_hash.clone(),
+                    first_seen: entry.timestamp,
+                    change: category,
+                    outcome: (category == Category::Accepted).then_some(Category::Accepted),
+                    outcome_at: (category == Category::Accepted).then_some(entry.timestamp),
+                });
+            }
+        }
+    }
+    timelines
+}
+
+/// Print, for every path matching `filter`, each recorded hash in order with
+/// when it was first observed and whether it was later accepted or restored.
+pub fn show_timeline(
+    filter: &HistoryFilter,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entries: Vec<HistoryEntry> = load()?
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .collect();
+    let timelines = build_timelines(&entries);
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&timelines)?),
+        OutputFormat::Text => {
+            if timelines.is_empty() {
+                println!("No matching drift history.");
+            }
+            for (i, timeline) in timelines.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                println!("{}", timeline.path);
+                if config::settings().hash_display != HashDisplay::None {
+                    println!("  baseline hash: {}", shown(&timeline.baseline_hash));
+                }
+                println!(
+                    "  {:<25}  {}{:<8}  OUTCOME",
+                    "FIRST SEEN",
+                    hash_columns(&["HASH"]),
+                    "CHANGE"
+                );
+                for version in &timeline.versions {
+                    let outcome = match (version.outcome, version.outcome_at) {
+                        (Some(outcome), Some(at)) => {
+                            format!("{} {}", outcome.as_str(), format_time(at))
+                        }
+                        _ => "-".to_string(),
+                    };
+                    println!(
+                        "  {:<25}  {}{:<8}  {}",
+                        format_time(version.first_seen),
+                        hash_columns(&[shown(&version.hash)]),
+                        version.change.as_str(),
+                        outcome
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_parse_with_and_without_offsets() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+        let wall = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(
+            parse_when("2024-05-01T14:30:00+02:00"),
+            Ok(When::At(at("2024-05-01T12:30:00Z")))
+        );
+        assert_eq!(
+            parse_when("2024-05-01 14:30 +02:00"),
+            Ok(When::At(at("2024-05-01T12:30:00Z")))
+        );
+        assert_eq!(
+            parse_when("2024-05-01T14:30-0500"),
+            Ok(When::At(at("2024-05-01T19:30:00Z")))
+        );
+        assert_eq!(
+            parse_when("2024-05-01 14:30"),
+            Ok(When::Wall(wall("2024-05-01 14:30:00")))
+        );
+        assert_eq!(
+            parse_when("2024-05-01T14:30:15"),
+            Ok(When::Wall(wall("2024-05-01 14:30:15")))
+        );
+        assert_eq!(
+            parse_when("2024-05-01"),
+            Ok(When::Wall(wall("2024-05-01 00:00:00")))
+        );
+        let Ok(When::At(ago)) = parse_when("2h") else {
+            panic!("a duration is a time that long ago")
+        };
+        assert!((Utc::now() - ago - Duration::hours(2)).num_seconds().abs() < 5);
+        assert!(parse_when("yesterday").is_err());
+    }
+}
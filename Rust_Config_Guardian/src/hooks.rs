@@ -0,0 +1,131 @@
+//! Running a user-supplied command when drift is detected.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::alert::{Alert, Listing, Notifier};
+use crate::utils::format_duration;
+use crate::{DriftReport, redact};
+
+/// How to run the `--on-drift` command.
+#[derive(Clone)]
+pub struct DriftHook {
+    pub command: String,
+    pub timeout: Duration,
+    /// Run through the platform shell instead of executing directly.
+    pub shell: bool,
+}
+
+fn build_command(hook: &DriftHook) -> Result<Command, String> {
+    if hook.shell {
+        #[cfg(windows)]
+        let command = {
+            let mut c = Command::new("cmd");
+            c.arg("/C").arg(&hook.command);
+            c
+        };
+        #[cfg(not(windows))]
+        let command = {
+            let mut c = Command::new("sh");
+            c.arg("-c").arg(&hook.command);
+            c
+        };
+        return Ok(command);
+    }
+
+    let args = shlex::split(&hook.command)
+        .filter(|args| !args.is_empty())
+        .ok_or_else(|| format!("Could not parse --on-drift command: {}", hook.command))?;
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    Ok(command)
+}
+
+/// The hook is a channel like any other, so [`crate::alert::send`] reports
+/// its failures without letting them take down monitor mode.
+impl Notifier for DriftHook {
+    fn name(&self) -> &'static str {
+        "on-drift"
+    }
+
+    /// Run the hook with the alert's drift report as JSON on stdin. Alerts
+    /// about something other than drift carry no report, and don't run it.
+    fn notify(&self, alert: &Alert, _listing: &Listing) -> Result<(), String> {
+        match &alert.report {
+            Some(report) => run_drift_hook(self, &alert.dir, report).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn run_drift_hook(
+    hook: &DriftHook,
+    dir: &str,
+    report: &DriftReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let report = &redact::report(report);
+    let json = serde_json::to_string_pretty(report)?;
+    let report_path = std::env::temp_dir().join(format!(
+        "guardian-report-{}-{}.json",
+        std::process::id(),
+        chrono::Utc::now().timestamp_millis()
+    ));
+    std::fs::write(&report_path, &json)?;
+    let result = spawn_and_wait(hook, dir, report, &report_path, json);
+    let _ = std::fs::remove_file(&report_path);
+    result
+}
+
+fn spawn_and_wait(
+    hook: &DriftHook,
+    dir: &str,
+    report: &DriftReport,
+    report_path: &Path,
+    json: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = build_command(hook)?
+        .env("GUARDIAN_DRIFT_COUNT", report.drifts.len().to_string())
+        .env("GUARDIAN_DIR", dir)
+        .env("GUARDIAN_REPORT_PATH", report_path)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    // Write from a thread so a hook that never reads stdin can't block us
+    // past the timeout.
+    let mut stdin = child.stdin.take().ok_or("hook stdin unavailable")?;
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(json.as_bytes());
+    });
+
+    log::info!(
+        "Started on-drift hook '{}' (PID {})",
+        hook.command,
+        child.id()
+    );
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= hook.timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "killed after exceeding {} timeout",
+                format_duration(hook.timeout)
+            )
+            .into());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let _ = writer.join();
+
+    if status.success() {
+        log::info!("on-drift hook '{}' completed", hook.command);
+        Ok(())
+    } else {
+        Err(format!("exited with {}", status).into())
+    }
+}
@@ -0,0 +1,129 @@
+//! Shared HTTP client used by the alert channels, heartbeat pings and
+//! collector uploads.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Number of retries after the first attempt fails.
+const RETRIES: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Build an agent using the platform TLS stack (OpenSSL, Schannel or
+/// Security.framework) and its certificate store.
+pub fn agent(timeout: Duration) -> Result<ureq::Agent, String> {
+    let tls = native_tls::TlsConnector::new().map_err(|e| format!("TLS setup failed: {}", e))?;
+    Ok(ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .tls_connector(Arc::new(tls))
+        .build())
+}
+
+/// Scheme and host of a URL. Webhook and bot URLs carry their secret in
+/// the path, so only this part may appear in logs and error messages.
+pub fn origin(url: &str) -> &str {
+    let start = url.find("://").map_or(0, |i| i + 3);
+    match url[start..].find('/') {
+        Some(end) => &url[..start + end],
+        None => url,
+    }
+}
+
+/// The `host:port` a URL connects to, with the scheme's default port when
+/// none is given.
+pub fn address(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    if host.is_empty() {
+        return None;
+    }
+    if host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'))
+    {
+        return Some(host.to_string());
+    }
+    let port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+    Some(format!("{}:{}", host, port))
+}
+
+/// An error without the request URL, which ureq's own Display includes.
+pub fn describe(error: &ureq::Error) -> String {
+    match error {
+        ureq::Error::Status(code, _) => format!("HTTP {}", code),
+        ureq::Error::Transport(t) => match t.message() {
+            Some(message) => format!("{}: {}", t.kind(), message),
+            None => t.kind().to_string(),
+        },
+    }
+}
+
+/// POST a JSON body, retrying with exponential backoff on transport errors,
+/// 429 and 5xx responses. Other 4xx responses fail immediately since
+/// repeating the request won't help.
+pub fn post_json(
+    url: &str,
+    headers: &[(String, String)],
+    timeout: Duration,
+    body: &serde_json::Value,
+) -> Result<(), String> {
+    send_json("POST", url, headers, timeout, body)
+}
+
+/// [`post_json`] with another method, such as PUT.
+pub fn send_json(
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    timeout: Duration,
+    body: &serde_json::Value,
+) -> Result<(), String> {
+    let agent = agent(timeout)?;
+    let target = origin(url);
+    let body = body.to_string();
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 0..=RETRIES {
+        let mut request = agent
+            .request(method, url)
+            .set("Content-Type", "application/json");
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let error = match request.send_string(&body) {
+            Ok(_) => return Ok(()),
+            Err(ureq::Error::Status(code, _)) if code != 429 && code < 500 => {
+                return Err(format!(
+                    "{} rejected the request with HTTP {}",
+                    target, code
+                ));
+            }
+            Err(e) => describe(&e),
+        };
+        if attempt == RETRIES {
+            return Err(format!(
+                "{} failed after {} attempts: {}",
+                target,
+                attempt + 1,
+                error
+            ));
+        }
+        log::warn!(
+            "{} to {} failed (attempt {}): {}; retrying in {}s",
+            method,
+            target,
+            attempt + 1,
+            error,
+            backoff.as_secs()
+        );
+        std::thread::sleep(backoff);
+        backoff *= 2;
+    }
+    unreachable!("the final attempt always returns")
+}
@@ -0,0 +1,191 @@
+//! Which machine this is, as the collector files its uploads under and as
+//! reports, snapshots and alerts name it. A hostname won't do on autoscaled
+//! VMs and containers, which come and go under names that repeat, or that
+//! never do. The identity is `--instance-id` (or `GUARDIAN_INSTANCE_ID`, or
+//! `instance_id` in the config file) where that is given; otherwise the
+//! first that `instance_id_sources` lists of:
+//!
+//! - `cloud`: the ID of the cloud instance it runs on, from the metadata
+//!   service of EC2, Google Compute Engine or Azure; only with the
+//!   `cloud-identity` feature
+//! - `stored`: a random ID written to `instance-id` in the working
+//!   directory on the first run, and read back on the others
+//! - `hostname`
+//!
+//! `instance_id_sources` defaults to `["cloud", "stored"]`, or `["stored"]`
+//! without the feature. The identity is settled the first time it's asked
+//! for, and kept for the life of the process.
+
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::sync::OnceLock;
+
+use crate::{collector, config, utils};
+
+/// Where the `stored` identity is kept, relative to the working directory.
+pub const INSTANCE_ID_FILE: &str = "instance-id";
+
+/// Where an identity comes from, as `instance_id_sources` names it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentitySource {
+    Cloud,
+    Stored,
+    Hostname,
+}
+
+/// What `instance_id_sources` is when the config file doesn't say.
+pub const DEFAULT_SOURCES: &[IdentitySource] = if cfg!(feature = "cloud-identity") {
+    &[IdentitySource::Cloud, IdentitySource::Stored]
+} else {
+    &[IdentitySource::Stored]
+};
+
+impl IdentitySource {
+    fn identity(self) -> Option<String> {
+        match self {
+            #[cfg(feature = "cloud-identity")]
+            IdentitySource::Cloud => cloud::instance_id(),
+            // The config file is refused for listing it.
+            #[cfg(not(feature = "cloud-identity"))]
+            IdentitySource::Cloud => None,
+            IdentitySource::Stored => stored(),
+            IdentitySource::Hostname => Some(utils::hostname()),
+        }
+    }
+}
+
+static CURRENT: OnceLock<String> = OnceLock::new();
+
+/// This machine's identity, settled the first time it's asked for.
+pub fn current() -> &'static str {
+    CURRENT.get_or_init(|| {
+        let settings = config::settings();
+        if let Some(id) = &settings.instance_id {
+            return id.clone();
+        }
+        settings
+            .identity
+            .iter()
+            .find_map(|source| source.identity())
+            .unwrap_or_else(utils::hostname)
+    })
+}
+
+/// The hostname, for people to read, with the identity after it where
+/// that is another: `web-1 (i-0a1b2c3d)`.
+pub fn label() -> String {
+    let host = utils::hostname();
+    match current() {
+        id if id == host => host,
+        id => format!("{} ({})", host, id),
+    }
+}
+
+/// The ID in `instance-id`, made up and written there when there is none.
+/// Two processes starting at once agree on it: it's linked into place,
+/// which fails for the second, who reads the first's.
+fn stored() -> Option<String> {
+    let read = || match std::fs::read_to_string(INSTANCE_ID_FILE) {
+        Ok(text) => {
+            let id = text.trim().to_string();
+            match collector::check_host_name(&id) {
+                Ok(()) => Ok(Some(id)),
+                Err(e) => Err(format!("{}: {}", INSTANCE_ID_FILE, e)),
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("could not read {}: {}", INSTANCE_ID_FILE, e)),
+    };
+    let found = read().and_then(|found| match found {
+        Some(id) => Ok(id),
+        None => {
+            let id = format!("{:016x}{:016x}", utils::random_u64(), utils::random_u64());
+            let tmp = format!("{}.{}.tmp", INSTANCE_ID_FILE, std::process::id());
+            let linked = std::fs::write(&tmp, format!("{}\n", id))
+                .and_then(|_| std::fs::hard_link(&tmp, INSTANCE_ID_FILE));
+            let _ = std::fs::remove_file(&tmp);
+            match linked {
+                Ok(()) => {
+                    log::info!(
+                        event = "instance_id_created",
+                        instance_id = id.as_str();
+                        "Identified this machine as {}, kept in {}", id, INSTANCE_ID_FILE
+                    );
+                    Ok(id)
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    read()?.ok_or_else(|| format!("{} went away", INSTANCE_ID_FILE))
+                }
+                Err(e) => Err(format!("could not write {}: {}", INSTANCE_ID_FILE, e)),
+            }
+        }
+    });
+    match found {
+        Ok(id) => Some(id),
+        Err(e) => {
+            eprintln!("Warning: no stored identity: {}", e);
+            log::warn!("No stored identity: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "cloud-identity")]
+mod cloud {
+    use std::time::Duration;
+
+    use crate::{collector, http};
+
+    /// Off a cloud, each metadata service fails to answer within this.
+    const TIMEOUT: Duration = Duration::from_millis(500);
+    const LINK_LOCAL: &str = "http://169.254.169.254";
+
+    /// The ID of the instance, from whichever provider's metadata service
+    /// answers.
+    pub(super) fn instance_id() -> Option<String> {
+        let agent = http::agent(TIMEOUT).ok()?;
+        ec2(&agent)
+            .or_else(|| gce(&agent))
+            .or_else(|| azure(&agent))
+            .map(|id| id.trim().to_string())
+            .filter(|id| collector::check_host_name(id).is_ok())
+    }
+
+    fn text(request: ureq::Request) -> Option<String> {
+        request.call().ok()?.into_string().ok()
+    }
+
+    /// Through IMDSv2, which wants a session token first.
+    fn ec2(agent: &ureq::Agent) -> Option<String> {
+        let token = text(
+            agent
+                .put(&format!("{}/latest/api/token", LINK_LOCAL))
+                .set("X-aws-ec2-metadata-token-ttl-seconds", "60"),
+        )?;
+        text(
+            agent
+                .get(&format!("{}/latest/meta-data/instance-id", LINK_LOCAL))
+                .set("X-aws-ec2-metadata-token", token.trim()),
+        )
+    }
+
+    fn gce(agent: &ureq::Agent) -> Option<String> {
+        text(
+            agent
+                .get("http://metadata.google.internal/computeMetadata/v1/instance/id")
+                .set("Metadata-Flavor", "Google"),
+        )
+    }
+
+    fn azure(agent: &ureq::Agent) -> Option<String> {
+        text(
+            agent
+                .get(&format!(
+                    "{}/metadata/instance/compute/vmId?api-version=2021-02-01&format=text",
+                    LINK_LOCAL
+                ))
+                .set("Metadata", "true"),
+        )
+    }
+}
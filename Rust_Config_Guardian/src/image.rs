@@ -0,0 +1,1066 @@
+//! Baselines from container images. Config baked into an image is what the
+//! directory it's mounted at in a running container should hold, so
+//! `snapshot --from-tar LAYER.tar` and `--from-oci-dir LAYOUT` take the
+//! files an image layer, or the layers of an OCI image layout applied in
+//! order, hold under a prefix as the snapshot of the live directory. They
+//! are read straight out of the archives, which are never extracted;
+//! `compare` then scans the live directory as usual.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config;
+use crate::identity;
+use crate::invoker;
+use crate::snapshot::save_scans;
+use crate::{
+    EntryType, FileHash, GuardianError, Permissions, Scan, ScanOptions, ScanStats, ScanWarning,
+    Snapshot, SnapshotMetadata, WarningKind, utils,
+};
+
+/// Where an image's files are read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Image {
+    /// A tar archive, such as one layer of an image, gzip-compressed or not.
+    Tar(PathBuf),
+    /// An OCI image layout, as `skopeo copy docker://IMAGE oci:DIR` writes
+    /// one.
+    OciDir(PathBuf),
+}
+
+impl fmt::Display for Image {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Image::Tar(path) | Image::OciDir(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Image {
+    /// The layers to apply, lowest first.
+    fn layers(&self) -> Result<Vec<PathBuf>, GuardianError> {
+        match self {
+            Image::Tar(path) => Ok(vec![path.clone()]),
+            Image::OciDir(dir) => oci_layers(dir),
+        }
+    }
+}
+
+/// Symlinks followed resolving one path before giving up, as Linux does.
+const MAX_SYMLINKS: usize = 40;
+/// Indexes an OCI layout may nest before its manifest.
+const MAX_INDEXES: usize = 8;
+
+/// A descriptor, or the part of an index or manifest this reads.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    platform: Option<Platform>,
+    manifests: Vec<Descriptor>,
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Platform {
+    os: String,
+    architecture: String,
+}
+
+/// Where the OCI layout `dir` keeps the blob `digest`.
+fn blob_path(dir: &Path, digest: &str) -> Result<PathBuf, GuardianError> {
+    let invalid =
+        || GuardianError::Config(format!("{}: invalid digest '{}'", dir.display(), digest));
+    let (algorithm, hex) = digest.split_once(':').ok_or_else(invalid)?;
+    let safe = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric());
+    if !safe(algorithm) || !safe(hex) {
+        return Err(invalid());
+    }
+    Ok(dir.join("blobs").join(algorithm).join(hex))
+}
+
+/// The JSON document at `path`, checked against `digest` if there is one.
+fn load_document(path: &Path, digest: Option<&str>) -> Result<Descriptor, GuardianError> {
+    let bytes = fs::read(path).map_err(GuardianError::io(path))?;
+    if let Some(hex) = digest.and_then(|d| d.strip_prefix("sha256:"))
+        && format!("{:x}", Sha256::digest(&bytes)) != hex
+    {
+        return Err(GuardianError::Config(format!(
+            "{} doesn't match its digest sha256:{}",
+            path.display(),
+            hex
+        )));
+    }
+    serde_json::from_slice(&bytes)
+        .map_err(|e| GuardianError::Config(format!("{}: {}", path.display(), e)))
+}
+
+/// The architecture as OCI platforms name it.
+fn oci_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "powerpc64" => "ppc64le",
+        arch => arch,
+    }
+}
+
+/// The manifest of `manifests` for this host: the only one, or the one for
+/// Linux on this architecture.
+fn choose<'a>(dir: &Path, manifests: &'a [Descriptor]) -> Result<&'a Descriptor, GuardianError> {
+    if let [only] = manifests {
+        return Ok(only);
+    }
+    let arch = oci_architecture();
+    manifests
+        .iter()
+        .find(|m| {
+            m.platform
+                .as_ref()
+                .is_some_and(|p| p.os == "linux" && p.architecture == arch)
+        })
+        .ok_or_else(|| {
+            let platforms: Vec<String> = manifests
+                .iter()
+                .filter_map(|m| m.platform.as_ref())
+                .map(|p| format!("{}/{}", p.os, p.architecture))
+                .collect();
+            GuardianError::Config(format!(
+                "{} holds no image for linux/{} (it has {})",
+                dir.display(),
+                arch,
+                if platforms.is_empty() {
+                    "none with a platform".to_string()
+                } else {
+                    platforms.join(", ")
+                }
+            ))
+        })
+}
+
+/// The layer blobs of the image in the OCI layout `dir`, lowest first.
+fn oci_layers(dir: &Path) -> Result<Vec<PathBuf>, GuardianError> {
+    let mut document = load_document(&dir.join("index.json"), None)?;
+    for _ in 0..MAX_INDEXES {
+        if document.manifests.is_empty() {
+            break;
+        }
+        let chosen = choose(dir, &document.manifests)?;
+        let digest = chosen.digest.clone();
+        document = load_document(&blob_path(dir, &digest)?, Some(&digest))?;
+    }
+    if document.layers.is_empty() {
+        return Err(GuardianError::Config(format!(
+            "{} holds no image layers",
+            dir.display()
+        )));
+    }
+    document
+        .layers
+        .iter()
+        .map(|layer| {
+            if layer.media_type.ends_with("+zstd") {
+                return Err(GuardianError::Config(format!(
+                    "{} is zstd-compressed, which isn't supported; convert the image to gzip layers",
+                    layer.digest
+                )));
+            }
+            blob_path(dir, &layer.digest)
+        })
+        .collect()
+}
+
+/// The layer at `path`, decompressed if it's gzipped.
+fn open_layer(path: &Path) -> io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let magic = reader.fill_buf()?;
+    Ok(match magic {
+        [0x1f, 0x8b, ..] => Box::new(MultiGzDecoder::new(reader)),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "zstd-compressed archives aren't supported; decompress it first",
+            ));
+        }
+        _ => Box::new(reader),
+    })
+}
+
+const BLOCK: u64 = 512;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// A tar entry, with the GNU long names and PAX records before it applied.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Header {
+    name: String,
+    kind: u8,
+    link: String,
+    size: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: i64,
+}
+
+impl Header {
+    /// Whether the entry's content follows it in the archive.
+    fn has_content(&self) -> bool {
+        !matches!(self.kind, b'1'..=b'6')
+    }
+}
+
+/// Reads a tar archive entry by entry, in the ustar, GNU and PAX formats.
+struct Tar<R> {
+    inner: R,
+    /// Content of the current entry not read yet, and its padding.
+    content: u64,
+    padding: u64,
+    /// PAX records of global headers, which apply to every entry after.
+    globals: Vec<(String, String)>,
+}
+
+impl<R: Read> Tar<R> {
+    fn new(inner: R) -> Self {
+        Tar {
+            inner,
+            content: 0,
+            padding: 0,
+            globals: Vec::new(),
+        }
+    }
+
+    /// The next block, or `None` at the end of the archive.
+    fn block(&mut self) -> io::Result<Option<[u8; BLOCK as usize]>> {
+        let mut block = [0; BLOCK as usize];
+        let mut filled = 0;
+        while filled < block.len() {
+            match self.inner.read(&mut block[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+        Ok(Some(block))
+    }
+
+    fn skip(&mut self) -> io::Result<()> {
+        let left = self.content + self.padding;
+        let skipped = io::copy(&mut (&mut self.inner).take(left), &mut io::sink())?;
+        if skipped < left {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        (self.content, self.padding) = (0, 0);
+        Ok(())
+    }
+
+    fn start(&mut self, size: u64) {
+        self.content = size;
+        self.padding = size.next_multiple_of(BLOCK) - size;
+    }
+
+    /// The content of a header that holds a name or PAX records.
+    fn meta_content(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        if size > 1 << 20 {
+            return Err(invalid("oversized extended header"));
+        }
+        self.start(size);
+        let mut content = Vec::new();
+        (&mut self.inner).take(size).read_to_end(&mut content)?;
+        if content.len() as u64 != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.content = 0;
+        self.skip()?;
+        Ok(content)
+    }
+
+    /// The next entry, past what's left of the last one.
+    fn next_entry(&mut self) -> io::Result<Option<Header>> {
+        self.skip()?;
+        let mut long_name = None;
+        let mut long_link = None;
+        let mut records = Vec::new();
+        loop {
+            let Some(block) = self.block()? else {
+                return Ok(None);
+            };
+            if block.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+            let mut header = parse_header(&block)?;
+            match header.kind {
+                b'L' => long_name = Some(name_text(trim_nul(&self.meta_content(header.size)?))),
+                b'K' => long_link = Some(name_text(trim_nul(&self.meta_content(header.size)?))),
+                b'x' => records.extend(parse_pax(&self.meta_content(header.size)?)?),
+                b'g' => {
+                    let globals = parse_pax(&self.meta_content(header.size)?)?;
+                    self.globals.extend(globals);
+                }
+                _ => {
+                    if let Some(name) = long_name {
+                        header.name = name;
+                    }
+                    if let Some(link) = long_link {
+                        header.link = link;
+                    }
+                    // Later records override earlier ones, and an entry's
+                    // own the global ones.
+                    for (key, value) in self.globals.iter().chain(&records) {
+                        apply_pax(&mut header, key, value)?;
+                    }
+                    if header.has_content() {
+                        self.start(header.size);
+                    }
+                    return Ok(Some(header));
+                }
+            }
+        }
+    }
+
+    /// Hash the current entry's content.
+    fn hash_content(&mut self) -> io::Result<(String, u64)> {
+        let size = self.content;
+        let (hash, read) = config::settings()
+            .hash
+            .digest_reader(&mut (&mut self.inner).take(size))?;
+        if read != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.content = 0;
+        Ok((hash, read))
+    }
+}
+
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+/// A name from an archive, with bytes that aren't UTF-8 spelled out as a
+/// scan of the extracted file would record them.
+fn name_text(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut text = String::new();
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            let _ = write!(text, "\\x{:02X}", byte);
+        }
+    }
+    text
+}
+
+/// A numeric header field: octal, or GNU base-256 for values octal can't
+/// hold.
+fn number(field: &[u8]) -> io::Result<u64> {
+    if let Some((&first, rest)) = field.split_first()
+        && first & 0x80 != 0
+    {
+        if first == 0xff {
+            return Err(invalid("negative number in tar header"));
+        }
+        return rest.iter().try_fold(u64::from(first & 0x7f), |value, &b| {
+            value
+                .checked_mul(256)
+                .map(|v| v + u64::from(b))
+                .ok_or_else(|| invalid("number out of range in tar header"))
+        });
+    }
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim_matches(|c| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8)
+        .map_err(|_| invalid(format!("invalid number '{}' in tar header", text)))
+}
+
+fn parse_header(block: &[u8; BLOCK as usize]) -> io::Result<Header> {
+    let recorded = number(&block[148..156])?;
+    let unsigned: u64 = block
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                u64::from(b)
+            }
+        })
+        .sum();
+    // Some old archivers summed the bytes as signed.
+    let signed: i64 = block
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                i64::from(b as i8)
+            }
+        })
+        .sum();
+    if recorded != unsigned && recorded as i64 != signed {
+        return Err(invalid("tar header checksum mismatch; not a tar archive?"));
+    }
+    let mut name = trim_nul(&block[0..100]).to_vec();
+    // POSIX ustar splits long names into a prefix and a name; GNU tar puts
+    // other fields there.
+    if &block[257..263] == b"ustar\0" {
+        let prefix = trim_nul(&block[345..500]);
+        if !prefix.is_empty() {
+            name = [prefix, b"/", &name].concat();
+        }
+    }
+    Ok(Header {
+        name: name_text(&name),
+        kind: block[156],
+        link: name_text(trim_nul(&block[157..257])),
+        size: number(&block[124..136])?,
+        mode: number(&block[100..108])? as u32,
+        uid: number(&block[108..116])? as u32,
+        gid: number(&block[116..124])? as u32,
+        mtime: number(&block[136..148])? as i64,
+    })
+}
+
+/// The records of a PAX extended header, `LENGTH KEY=VALUE\n` each.
+fn parse_pax(mut content: &[u8]) -> io::Result<Vec<(String, String)>> {
+    let mut records = Vec::new();
+    while !content.is_empty() {
+        let bad = || invalid("malformed PAX record");
+        let space = content.iter().position(|&b| b == b' ').ok_or_else(bad)?;
+        let length: usize = std::str::from_utf8(&content[..space])
+            .ok()
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(bad)?;
+        if length <= space + 1 || length > content.len() || content[length - 1] != b'\n' {
+            return Err(bad());
+        }
+        let record = &content[space + 1..length - 1];
+        let equals = record.iter().position(|&b| b == b'=').ok_or_else(bad)?;
+        records.push((
+            String::from_utf8_lossy(&record[..equals]).into_owned(),
+            name_text(&record[equals + 1..]),
+        ));
+        content = &content[length..];
+    }
+    Ok(records)
+}
+
+fn apply_pax(header: &mut Header, key: &str, value: &str) -> io::Result<()> {
+    let number = |value: &str| -> io::Result<u64> {
+        value
+            .parse()
+            .map_err(|_| invalid(format!("invalid PAX {} '{}'", key, value)))
+    };
+    match key {
+        "path" => header.name = value.to_string(),
+        "linkpath" => header.link = value.to_string(),
+        "size" => header.size = number(value)?,
+        "uid" => header.uid = number(value)? as u32,
+        "gid" => header.gid = number(value)? as u32,
+        // Times may have a fraction of a second, which scans don't keep.
+        "mtime" => {
+            let seconds = value.split('.').next().unwrap_or(value);
+            header.mtime = seconds
+                .parse()
+                .map_err(|_| invalid(format!("invalid PAX mtime '{}'", value)))?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// `name` as a path relative to the image's root, `.` and `..` resolved
+/// within it; empty for the root itself.
+fn normalize(name: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Where a regular file's content is: the layer, and the entry within it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Content {
+    layer: usize,
+    entry: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    File {
+        content: Content,
+        header: Header,
+    },
+    Symlink {
+        header: Header,
+    },
+    Directory,
+    Special,
+    /// A hard link to a path the image doesn't hold as a file.
+    Broken(String),
+}
+
+/// The image's filesystem: every path, with the layer that put it there.
+#[derive(Default)]
+struct Tree {
+    nodes: BTreeMap<String, (usize, Node)>,
+}
+
+impl Tree {
+    /// Remove `path` and what's under it, of the layers below `layer`.
+    fn remove(&mut self, path: &str, layer: usize, keep_self: bool) {
+        let under = format!("{}/", path);
+        self.nodes.retain(|p, (from, _)| {
+            *from >= layer || !(p.starts_with(&under) || (!keep_self && p == path))
+        });
+    }
+
+    /// Apply entry `entry` of layer `layer`, or the whiteout it is.
+    fn apply(&mut self, layer: usize, entry: usize, header: &Header) {
+        let path = normalize(&header.name);
+        if path.is_empty() {
+            return;
+        }
+        let (dir, name) = (parent(&path), &path[parent(&path).len()..]);
+        let name = name.trim_start_matches('/');
+        if name == ".wh..wh..opq" {
+            if dir.is_empty() {
+                self.nodes.retain(|_, (from, _)| *from >= layer);
+            } else {
+                self.remove(dir, layer, true);
+            }
+            return;
+        }
+        if let Some(hidden) = name.strip_prefix(".wh.") {
+            self.remove(&join(dir, hidden), layer, false);
+            return;
+        }
+        let node = match header.kind {
+            b'0' | b'\0' | b'7' => Node::File {
+                content: Content { layer, entry },
+                header: header.clone(),
+            },
+            b'1' => match self.nodes.get(&normalize(&header.link)) {
+                Some((_, Node::File { content, header })) => Node::File {
+                    content: *content,
+                    header: header.clone(),
+                },
+                _ => Node::Broken(format!(
+                    "hard link to {}, which the image doesn't hold",
+                    header.link
+                )),
+            },
+            b'2' => Node::Symlink {
+                header: header.clone(),
+            },
+            b'5' => Node::Directory,
+            _ => Node::Special,
+        };
+        if !matches!(node, Node::Directory) {
+            self.remove(&path, usize::MAX, true);
+        }
+        self.nodes.insert(path, (layer, node));
+    }
+
+    fn get(&self, path: &str) -> Option<&Node> {
+        self.nodes.get(path).map(|(_, node)| node)
+    }
+
+    /// Whether `path` is a directory, listed or implied by what's in it.
+    fn is_directory(&self, path: &str) -> bool {
+        path.is_empty()
+            || matches!(self.get(path), Some(Node::Directory))
+            || self
+                .nodes
+                .range(format!("{}/", path)..)
+                .next()
+                .is_some_and(|(p, _)| p.starts_with(&format!("{}/", path)))
+    }
+
+    /// `path` with every symlink in it followed, within the image; `None`
+    /// for a loop.
+    fn resolve(&self, path: &str) -> Option<String> {
+        let mut resolved = String::new();
+        let mut rest: VecDeque<String> = path.split('/').map(str::to_string).collect();
+        let mut followed = 0;
+        while let Some(part) = rest.pop_front() {
+            match part.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    resolved = parent(&resolved).to_string();
+                    continue;
+                }
+                _ => {}
+            }
+            let next = join(&resolved, &part);
+            if let Some(Node::Symlink { header }) = self.get(&next) {
+                followed += 1;
+                if followed > MAX_SYMLINKS {
+                    return None;
+                }
+                if header.link.starts_with('/') {
+                    resolved.clear();
+                }
+                for part in header.link.split('/').rev() {
+                    rest.push_front(part.to_string());
+                }
+            } else {
+                resolved = next;
+            }
+        }
+        Some(resolved)
+    }
+}
+
+/// Read every layer, hashing the content of the entries `wanted` picks.
+fn read_layers(
+    layers: &[PathBuf],
+    tree: Option<&mut Tree>,
+    mut wanted: impl FnMut(Content, &str) -> bool,
+    hashes: &mut HashMap<Content, String>,
+    bytes_read: &mut u64,
+) -> Result<(), GuardianError> {
+    let mut tree = tree;
+    for (layer, path) in layers.iter().enumerate() {
+        let mut tar = Tar::new(open_layer(path).map_err(GuardianError::io(path))?);
+        let mut entry = 0;
+        while let Some(header) = tar.next_entry().map_err(GuardianError::io(path))? {
+            let content = Content { layer, entry };
+            if header.has_content()
+                && !hashes.contains_key(&content)
+                && wanted(content, &normalize(&header.name))
+            {
+                let (hash, read) = tar.hash_content().map_err(GuardianError::io(path))?;
+                *bytes_read += read;
+                hashes.insert(content, hash);
+            }
+            if let Some(tree) = tree.as_deref_mut() {
+                tree.apply(layer, entry, &header);
+            }
+            entry += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Scan the files `image` holds directly under `prefix` as
+/// [`crate::scan_directory`] scans a directory, recording them under `dir`:
+/// the files in it, hashed in full, with their size, modification time,
+/// permissions and type, and symlinks followed within the image.
+pub fn scan(image: &Image, prefix: &str, dir: &str) -> Result<Scan, GuardianError> {
+    let started = Instant::now();
+    let layers = image.layers()?;
+    let settings = config::settings();
+    let wanted_dir = normalize(prefix);
+
+    // Files directly under the prefix are hashed as the layers are read;
+    // anything else a symlink or hard link there leads to takes a second
+    // pass.
+    let mut tree = Tree::default();
+    let mut hashes = HashMap::new();
+    let mut bytes_read = 0;
+    read_layers(
+        &layers,
+        Some(&mut tree),
+        |_, path| parent(path) == wanted_dir,
+        &mut hashes,
+        &mut bytes_read,
+    )?;
+    let listed = tree.resolve(&wanted_dir).filter(|d| tree.is_directory(d));
+    let Some(listed) = listed else {
+        return Err(GuardianError::Config(format!(
+            "{} has no directory /{}",
+            image, wanted_dir
+        )));
+    };
+    // Archives needn't list the directories they hold files in.
+    let under = if listed.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", listed)
+    };
+    let names: BTreeSet<&str> = tree
+        .nodes
+        .keys()
+        .filter_map(|path| path.strip_prefix(&under))
+        .filter_map(|rest| rest.split('/').next().filter(|name| !name.is_empty()))
+        .collect();
+    let children: Vec<(String, String)> = names
+        .into_iter()
+        .map(|name| (name.to_string(), join(&listed, name)))
+        .collect();
+    static DIRECTORY: Node = Node::Directory;
+    let target_of = |path: &str| -> Option<&Node> {
+        let resolved = tree.resolve(path)?;
+        match tree.get(&resolved) {
+            None if tree.is_directory(&resolved) => Some(&DIRECTORY),
+            node => node,
+        }
+    };
+    let needed: HashSet<Content> = children
+        .iter()
+        .filter_map(|(_, path)| match target_of(path)? {
+            Node::File { content, .. } => Some(*content),
+            _ => None,
+        })
+        .filter(|content| !hashes.contains_key(content))
+        .collect();
+    if !needed.is_empty() {
+        read_layers(
+            &layers,
+            None,
+            |content, _| needed.contains(&content),
+            &mut hashes,
+            &mut bytes_read,
+        )?;
+    }
+
+    let mut stats = ScanStats::default();
+    let mut warnings = Vec::new();
+    let mut entries = Vec::new();
+    for (name, path) in &children {
+        let recorded = utils::path_text(&Path::new(dir).join(name));
+        if let Some(pattern) = settings.excluded_by(Path::new(&recorded)) {
+            log::debug!(event = "scan_decision", path = recorded.as_str(); "{}: excluded by glob {}", recorded, pattern);
+            stats.excluded += 1;
+            continue;
+        }
+        let link_target = match tree.get(path) {
+            Some(Node::Symlink { header }) => Some(header.link.clone()),
+            _ => None,
+        };
+        match target_of(path) {
+            Some(Node::Directory) => stats.dirs_seen += 1,
+            Some(Node::File { content, header }) => {
+                stats.files_hashed += 1;
+                entries.push(FileHash {
+                    path: recorded,
+                    hash: hashes[content].clone(),
+                    size: Some(header.size),
+                    modified: DateTime::from_timestamp(header.mtime, 0),
+                    permissions: Some(Permissions {
+                        mode: header.mode & 0o7777,
+                        uid: header.uid,
+                        gid: header.gid,
+                        // The image's names for its IDs needn't be the
+                        // host's, so they are compared by ID.
+                        ..Permissions::default()
+                    }),
+                    entry_type: Some(if link_target.is_some() {
+                        EntryType::Symlink
+                    } else {
+                        EntryType::File
+                    }),
+                    link_target,
+                    ..FileHash::default()
+                });
+            }
+            Some(Node::Broken(error)) => {
+                stats.unreadable += 1;
+                warnings.push(ScanWarning {
+                    kind: WarningKind::Unreadable,
+                    path: recorded,
+                    error: Some(error.clone()),
+                });
+            }
+            // Special files, and symlinks that lead nowhere in the image.
+            Some(Node::Special | Node::Symlink { .. }) | None => {
+                stats.special += 1;
+                warnings.push(ScanWarning {
+                    kind: WarningKind::Special,
+                    path: recorded,
+                    error: None,
+                });
+            }
+        }
+    }
+    if entries.len() + stats.excluded + stats.special + stats.unreadable == 0 {
+        warnings.push(ScanWarning {
+            kind: WarningKind::EmptyDirectory,
+            path: dir.to_string(),
+            error: None,
+        });
+    }
+    for warning in &warnings {
+        log::warn!(
+            event = "scan_warning",
+            kind:serde = warning.kind,
+            path = warning.path.as_str();
+            "{}", warning
+        );
+    }
+    stats.bytes_read = bytes_read;
+    stats.duration_ms = started.elapsed().as_millis() as u64;
+    Ok(Scan {
+        snapshot: Snapshot {
+            metadata: SnapshotMetadata {
+                created: Some(Utc::now()),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                hash: settings.hash,
+                directories: vec![dir.to_string()],
+                stats: BTreeMap::from([(dir.to_string(), stats.clone())]),
+                acls: false,
+                tags: BTreeMap::new(),
+                taken: BTreeMap::new(),
+                options: BTreeMap::from([(
+                    dir.to_string(),
+                    // Normalizers only run over local files.
+                    ScanOptions {
+                        normalize: BTreeMap::new(),
+                        ..ScanOptions::current()
+                    },
+                )]),
+                invoker: Some(invoker::current().clone()),
+                instance_id: Some(identity::current().to_string()),
+                published: None,
+            },
+            entries,
+        },
+        warnings,
+        stats,
+        sampled: None,
+    })
+}
+
+/// Replace the snapshot's entries for `dir` with [`scan`] of `image`'s
+/// files under `prefix`, and its tags with `tags`, as
+/// [`crate::take_snapshot`] does with a scan of the directory.
+pub fn take_snapshot(
+    image: &Image,
+    prefix: &str,
+    dir: &str,
+    tags: &BTreeMap<String, String>,
+) -> Result<Scan, GuardianError> {
+    log::info!("Taking snapshot of directory {} from {}", dir, image);
+    let scan = scan(image, prefix, dir)?;
+    save_scans(&[dir.to_string()], std::slice::from_ref(&scan), tags)?;
+    Ok(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ustar header block for `name`, with `size` bytes of content.
+    fn header(name: &str, kind: u8, link: &str, size: usize) -> [u8; 512] {
+        let mut block = [0u8; 512];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[100..107].copy_from_slice(b"0000644");
+        block[108..115].copy_from_slice(b"0000000");
+        block[116..123].copy_from_slice(b"0000000");
+        block[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        block[136..147].copy_from_slice(b"14530000000");
+        block[156] = kind;
+        block[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        block[148..156].copy_from_slice(b"        ");
+        let sum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+        block[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        block
+    }
+
+    fn entry(archive: &mut Vec<u8>, name: &str, kind: u8, link: &str, content: &[u8]) {
+        archive.extend(header(name, kind, link, content.len()));
+        archive.extend(content);
+        archive.resize(archive.len().next_multiple_of(512), 0);
+    }
+
+    #[test]
+    fn headers_take_long_names_and_pax_records() {
+        let long = format!("etc/{}/app.conf", "d".repeat(120));
+        let mut archive = Vec::new();
+        entry(
+            &mut archive,
+            "././@LongLink",
+            b'L',
+            "",
+            format!("{}\0", long).as_bytes(),
+        );
+        entry(&mut archive, "truncated", b'0', "", b"listen 80\n");
+        // The length counts the whole record, itself included.
+        let records = "27 path=etc/pax-named.conf\n19 mtime=1700000.5\n";
+        entry(&mut archive, "PaxHeader", b'x', "", records.as_bytes());
+        entry(&mut archive, "short", b'0', "", b"x");
+        entry(&mut archive, "etc/link", b'2', "pax-named.conf", b"");
+        archive.extend([0u8; 1024]);
+
+        let mut tar = Tar::new(archive.as_slice());
+        let names: Vec<(String, i64)> = std::iter::from_fn(|| tar.next_entry().unwrap())
+            .map(|h| (h.name, h.mtime))
+            .collect();
+        assert_eq!(names[0].0, long);
+        assert_eq!(names[1], ("etc/pax-named.conf".to_string(), 1_700_000));
+        assert_eq!(names[2].0, "etc/link");
+
+        let mut corrupt = archive.clone();
+        corrupt[10] ^= 1;
+        assert!(Tar::new(corrupt.as_slice()).next_entry().is_err());
+        assert_eq!(number(&[0x80, 0, 0, 1, 0]).unwrap(), 256);
+        assert_eq!(normalize("./etc/../etc//nginx/"), "etc/nginx");
+    }
+
+    #[test]
+    fn layers_apply_whiteouts_and_links_resolve_within_the_image() {
+        let layer = |entries: &[(&str, u8, &str)]| {
+            let mut tree = Tree::default();
+            for (i, (name, kind, link)) in entries.iter().enumerate() {
+                let header = Header {
+                    name: name.to_string(),
+                    kind: *kind,
+                    link: link.to_string(),
+                    ..Header::default()
+                };
+                tree.apply(0, i, &header);
+            }
+            tree
+        };
+        let mut tree = layer(&[
+            ("etc/app/", b'5', ""),
+            ("etc/app/a.conf", b'0', ""),
+            ("etc/app/b.conf", b'0', ""),
+            ("etc/app/hard.conf", b'1', "etc/app/a.conf"),
+            ("etc/current", b'2', "app"),
+            ("etc/app/abs", b'2', "/etc/app/../app/b.conf"),
+            ("etc/app/loop", b'2', "loop"),
+        ]);
+        match tree.get("etc/app/hard.conf") {
+            Some(Node::File { content, .. }) => assert_eq!(content.entry, 1),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(tree.resolve("etc/current/abs").unwrap(), "etc/app/b.conf");
+        assert_eq!(tree.resolve("etc/app/loop"), None);
+
+        let whiteouts = [("etc/app/.wh.b.conf", b'0'), ("etc/.wh..wh..opq", b'0')];
+        for (i, (name, kind)) in whiteouts.iter().enumerate() {
+            let header = Header {
+                name: name.to_string(),
+                kind: *kind,
+                ..Header::default()
+            };
+            tree.apply(1, i, &header);
+            if i == 0 {
+                assert!(tree.get("etc/app/b.conf").is_none());
+                assert!(tree.get("etc/app/a.conf").is_some());
+            }
+        }
+        assert!(!tree.nodes.keys().any(|p| p.starts_with("etc/")));
+    }
+
+    /// A fixture of `tests/fixtures/images`, which `make.py` there writes.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/images")
+            .join(name)
+    }
+
+    fn sha256(content: &str) -> String {
+        format!("{:x}", Sha256::digest(content))
+    }
+
+    /// The names and hashes `image` holds under `prefix`.
+    fn files(image: &Image, prefix: &str) -> Vec<(String, String)> {
+        scan(image, prefix, "live")
+            .unwrap()
+            .snapshot
+            .entries
+            .into_iter()
+            .map(|e| (e.path["live/".len()..].to_string(), e.hash))
+            .collect()
+    }
+
+    #[test]
+    fn tar_layers_record_links_long_names_and_pax_entries() {
+        let layer = Image::Tar(fixture("layer-gnu.tar"));
+        let scan = scan(&layer, "/etc/app/", "live").unwrap();
+        let entry = |name: &str| {
+            let path = utils::path_text(&Path::new("live").join(name));
+            scan.snapshot
+                .entries
+                .iter()
+                .find(|e| e.path == path)
+                .unwrap()
+        };
+        let app = entry("app.conf");
+        assert_eq!(app.hash, sha256("listen 80\n"));
+        assert_eq!(app.size, Some(10));
+        assert_eq!(app.modified.unwrap().timestamp(), 1_700_000_000);
+        assert_eq!(app.permissions.as_ref().unwrap().mode, 0o644);
+        assert_eq!(entry("hard.conf").hash, app.hash);
+        let current = entry("current.conf");
+        assert_eq!(current.hash, app.hash);
+        assert_eq!(current.entry_type, Some(EntryType::Symlink));
+        assert_eq!(current.link_target.as_deref(), Some("app.conf"));
+        // Outside the prefix, through a symlink and a hard link.
+        assert_eq!(entry("shared.conf").hash, sha256("defaults\n"));
+        assert_eq!(entry("linked.conf").hash, sha256("defaults\n"));
+        let long = format!("{}name.conf", "long-".repeat(24));
+        assert_eq!(entry(&long).hash, sha256("long\n"));
+        assert_eq!(scan.snapshot.entries.len(), 6);
+        assert_eq!((scan.stats.dirs_seen, scan.stats.special), (1, 1));
+        assert_eq!(
+            scan.warnings[0].path,
+            utils::path_text(&Path::new("live").join("dangling.conf"))
+        );
+
+        let pax = Image::Tar(fixture("layer-pax.tar.gz"));
+        assert_eq!(
+            files(&pax, "etc/app"),
+            [
+                ("café.conf".to_string(), sha256("accent\n")),
+                (long, sha256("long\n"))
+            ]
+        );
+    }
+
+    #[test]
+    fn oci_layouts_apply_their_layers_in_order() {
+        let layout = Image::OciDir(fixture("oci"));
+        let file = |name: &str, content: &str| (name.to_string(), sha256(content));
+        assert_eq!(
+            files(&layout, "etc/app"),
+            [file("a.conf", "a2\n"), file("c.conf", "c\n")]
+        );
+        assert_eq!(files(&layout, "etc/opaque"), [file("new.conf", "new\n")]);
+        let deep = format!("etc/{}dir", "deep-".repeat(24));
+        assert_eq!(files(&layout, &deep), [file("x.conf", "x\n")]);
+        assert!(scan(&layout, "etc/missing", "live").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! `verify-install`: the check of config-guardian's own executable. A tool
+//! that tells whether other files were tampered with should be able to say
+//! the same of itself, so it hashes the running executable with SHA-256 and
+//! compares the digest with one given by `--expected` or embedded at build
+//! time from `GUARDIAN_EXPECTED_DIGEST` (see build.rs).
+//!
+//! An embedded digest can't be that of the binary holding it, so the
+//! executable is hashed with the embedded digest read as the zeros a build
+//! without one has there. Packagers build once without it, take that
+//! build's `sha256sum`, and build again with it set, both stripped and
+//! without a build ID, which would change with it; a binary with nothing
+//! embedded hashes as `sha256sum` hashes it.
+//!
+//! `monitor --verify-install INTERVAL` repeats the check while it runs,
+//! against the embedded digest or the one it started with, and alerts
+//! critically when the binary changed under it.
+
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::GuardianError;
+use crate::utils::path_text;
+
+const EMBEDDED: &str = env!("GUARDIAN_EMBEDDED_DIGEST");
+
+/// The digest embedded at build time, if one was.
+pub fn embedded() -> Option<&'static str> {
+    // Kept opaque, so that the build with zeros compiles to the same code.
+    let digest = std::hint::black_box(EMBEDDED);
+    (!digest.bytes().all(|b| b == b'0')).then_some(digest)
+}
+
+/// Parse `--expected`: a SHA-256 digest, as `sha256sum` prints it, with or
+/// without a `sha256:` prefix.
+pub fn parse_digest(value: &str) -> Result<String, String> {
+    let digest = value.trim();
+    let digest = digest.strip_prefix("sha256:").unwrap_or(digest);
+    if digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(digest.to_ascii_lowercase())
+    } else {
+        Err(format!(
+            "'{}' is not a SHA-256 digest (64 hex digits)",
+            value
+        ))
+    }
+}
+
+/// The running executable's path.
+pub fn executable() -> Result<PathBuf, GuardianError> {
+    std::env::current_exe().map_err(GuardianError::io("the running executable"))
+}
+
+/// The digest of the executable at `path`, with the embedded digest read as
+/// zeros.
+pub fn digest(path: &Path) -> Result<String, GuardianError> {
+    let mut content = std::fs::read(path).map_err(GuardianError::io(path))?;
+    if let Some(embedded) = embedded() {
+        let embedded = embedded.as_bytes();
+        let mut at = 0;
+        while let Some(found) = content[at..]
+            .windows(embedded.len())
+            .position(|window| window == embedded)
+        {
+            let start = at + found;
+            content[start..start + embedded.len()].fill(b'0');
+            at = start + embedded.len();
+        }
+    }
+    Ok(format!("{:x}", Sha256::digest(&content)))
+}
+
+/// The executable's digest, and whether it is the `expected` one.
+pub struct Verified {
+    pub path: PathBuf,
+    pub actual: String,
+    pub expected: String,
+}
+
+impl Verified {
+    pub fn matches(&self) -> bool {
+        self.actual == self.expected
+    }
+}
+
+/// Hash the running executable and compare it with `expected`, or else
+/// the embedded digest.
+pub fn verify(expected: Option<&str>) -> Result<Verified, GuardianError> {
+    let expected = match expected.or(embedded()) {
+        Some(expected) => parse_digest(expected).map_err(GuardianError::Config)?,
+        None => {
+            return Err(GuardianError::Config(
+                "no digest to verify against: this build has none embedded; pass --expected <HASH>"
+                    .into(),
+            ));
+        }
+    };
+    let path = executable()?;
+    let actual = digest(&path)?;
+    Ok(Verified {
+        path,
+        actual,
+        expected,
+    })
+}
+
+/// Monitor's repeated check, on `--verify-install`.
+pub(crate) struct InstallCheck {
+    path: PathBuf,
+    expected: String,
+    interval: Duration,
+    next: Instant,
+    /// The digest last alerted on, so that a changed binary is alerted on
+    /// once rather than at every check.
+    alerted: Option<String>,
+}
+
+impl InstallCheck {
+    /// Check every `interval` from now on, the first time right away. The
+    /// digest to expect is the embedded one, or else the executable's now;
+    /// `None` with a warning when it can't be read.
+    pub(crate) fn new(interval: Duration) -> Option<InstallCheck> {
+        let started = executable().and_then(|path| {
+            let expected = match embedded() {
+                Some(embedded) => embedded.to_string(),
+                None => digest(&path)?,
+            };
+            Ok((path, expected))
+        });
+        match started {
+            Ok((path, expected)) => Some(InstallCheck {
+                path,
+                expected,
+                interval,
+                next: Instant::now(),
+                alerted: None,
+            }),
+            Err(e) => {
+                eprintln!("Warning: --verify-install is off: {}", e);
+                log::error!("Could not hash the running executable: {}", e);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn due(&self) -> bool {
+        Instant::now() >= self.next
+    }
+
+    /// Hash the executable again, giving what to alert on when it isn't
+    /// what was expected and wasn't already alerted on.
+    pub(crate) fn check(&mut self) -> Option<String> {
+        self.next = Instant::now() + self.interval;
+        let found = match digest(&self.path) {
+            Ok(actual) if actual == self.expected => {
+                log::debug!("{} still hashes to {}", self.path.display(), actual);
+                self.alerted = None;
+                return None;
+            }
+            Ok(actual) => actual,
+            Err(e) => format!("unreadable ({})", e),
+        };
+        if self.alerted.as_ref() == Some(&found) {
+            return None;
+        }
+        let problem = format!(
+            "config-guardian's own executable {} changed: sha256 {} where {} was expected",
+            path_text(&self.path),
+            found,
+            self.expected
+        );
+        self.alerted = Some(found);
+        Some(problem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_are_taken_as_sha256sum_prints_them() {
+        let digest = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        assert_eq!(parse_digest(digest).unwrap(), digest.to_ascii_lowercase());
+        assert_eq!(
+            parse_digest(&format!("sha256:{}", digest)).unwrap(),
+            digest.to_ascii_lowercase()
+        );
+        assert!(parse_digest("e3b0c442").is_err());
+        assert!(parse_digest(&"g".repeat(64)).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Who ran config-guardian, and how: the effective user (and, under sudo,
+//! the user who ran sudo), the host, when the process started and its
+//! command line. It is recorded in every history record, in the snapshot's
+//! metadata and in drift reports, so that an audit can tell who accepted a
+//! change and with what arguments. The command line is taken from the
+//! arguments alone, with the values of the flags that take secrets masked;
+//! secrets given through the environment are never read into it, and an
+//! argument that holds one anyway is masked too.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::config::MASK;
+use crate::{http, utils};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invoker {
+    /// The effective user: its name, or its ID where it has none.
+    pub user: String,
+    /// `SUDO_USER`: who ran sudo to become `user`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sudo_user: Option<String>,
+    pub host: String,
+    /// When the process started.
+    pub started: DateTime<Utc>,
+    /// The arguments, secrets masked.
+    pub command: Vec<String>,
+}
+
+impl Invoker {
+    /// Whether `name` is the effective user or the one behind sudo.
+    pub fn is(&self, name: &str) -> bool {
+        self.user == name || self.sudo_user.as_deref() == Some(name)
+    }
+}
+
+/// `user`, or `user (sudo from who)`.
+impl std::fmt::Display for Invoker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.sudo_user {
+            Some(sudo) => write!(f, "{} (sudo from {})", self.user, sudo),
+            None => write!(f, "{}", self.user),
+        }
+    }
+}
+
+/// Flags whose value is a URL with its secret in the path.
+const SECRET_URLS: &[&str] = &[
+    "--webhook-url",
+    "--slack-webhook",
+    "--teams-webhook",
+    "--heartbeat-url",
+];
+
+/// Flags whose value is a secret outright.
+const SECRETS: &[&str] = &[
+    "--telegram-token",
+    "--pagerduty-routing-key",
+    "--upload-token",
+    "--token",
+];
+
+/// Names of the environment variables whose values are secrets, by the
+/// words in them.
+const SECRET_VARIABLES: &[&str] = &[
+    "TOKEN", "PASSWORD", "KEY", "SECRET", "WEBHOOK", "URL", "HEADER",
+];
+
+static CURRENT: OnceLock<Invoker> = OnceLock::new();
+
+/// This process's invoker, read the first time it's asked for; call it as
+/// the process starts, so that `started` is when it did.
+pub fn current() -> &'static Invoker {
+    CURRENT.get_or_init(|| {
+        let secrets: Vec<String> = std::env::vars()
+            .filter(|(name, _)| {
+                name.starts_with("GUARDIAN_")
+                    && SECRET_VARIABLES.iter().any(|word| name.contains(word))
+            })
+            .map(|(_, value)| value)
+            .filter(|value| value.len() >= 4)
+            .collect();
+        let args: Vec<String> = std::env::args_os()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        Invoker {
+            user: effective_user(),
+            sudo_user: sudo_user(),
+            host: utils::hostname(),
+            started: Utc::now(),
+            command: sanitize(&args, &secrets),
+        }
+    })
+}
+
+#[cfg(unix)]
+fn effective_user() -> String {
+    // SAFETY: geteuid can't fail.
+    let uid = unsafe { libc::geteuid() };
+    crate::owners::user_name(uid).unwrap_or_else(|| uid.to_string())
+}
+
+#[cfg(not(unix))]
+fn effective_user() -> String {
+    std::env::var("USERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn sudo_user() -> Option<String> {
+    std::env::var("SUDO_USER")
+        .ok()
+        .filter(|user| !user.is_empty())
+}
+
+#[cfg(not(unix))]
+fn sudo_user() -> Option<String> {
+    None
+}
+
+/// `args` with the values of the flags that take secrets masked, whether
+/// given as `--flag VALUE` or `--flag=VALUE`, and any argument holding one
+/// of `secrets` masked whole.
+fn sanitize(args: &[String], secrets: &[String]) -> Vec<String> {
+    let masked = |flag: &str, value: &str| -> Option<String> {
+        if SECRET_URLS.contains(&flag) {
+            Some(format!("{}/{}", http::origin(value), MASK))
+        } else if SECRETS.contains(&flag) {
+            Some(MASK.to_string())
+        } else if flag == "--webhook-header" {
+            Some(match value.split_once(':') {
+                Some((name, _)) => format!("{}: {}", name, MASK),
+                None => MASK.to_string(),
+            })
+        } else {
+            None
+        }
+    };
+    let mut command = Vec::with_capacity(args.len());
+    let mut flag: Option<&str> = None;
+    for arg in args {
+        let shown = if let Some(value) = flag.take().and_then(|flag| masked(flag, arg)) {
+            value
+        } else if let Some((name, value)) = arg.split_once('=')
+            && let Some(value) = masked(name, value)
+        {
+            format!("{}={}", name, value)
+        } else if secrets.iter().any(|secret| arg.contains(secret.as_str())) {
+            MASK.to_string()
+        } else {
+            if arg.starts_with("--") {
+                flag = Some(arg);
+            }
+            arg.clone()
+        };
+        command.push(shown);
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_masked_in_the_command_line() {
+        let args: Vec<String> = [
+            "config-guardian",
+            "compare",
+            "/etc/app",
+            "--slack-webhook",
+            "https://hooks.slack.com/services/T0/B0/secret",
+            "--webhook-header=Authorization: Bearer secret",
+            "--telegram-token",
+            "123:secret",
+            "--report-upload",
+            "http://collector:8080",
+            "--tag",
+            "from-env-s3cr3t",
+        ]
+        .map(String::from)
+        .to_vec();
+        let command = sanitize(&args, &["s3cr3t".to_string()]);
+        assert_eq!(
+            command,
+            [
+                "config-guardian",
+                "compare",
+                "/etc/app",
+                "--slack-webhook",
+                "https://hooks.slack.com/********",
+                "--webhook-header=Authorization: ********",
+                "--telegram-token",
+                "********",
+                "--report-upload",
+                "http://collector:8080",
+                "--tag",
+                "********",
+            ]
+        );
+    }
+}
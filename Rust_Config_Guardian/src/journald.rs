@@ -0,0 +1,116 @@
+//! Logging to systemd-journald over its native socket protocol, so drift
+//! records arrive as structured fields rather than a flat message.
+
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use std::os::unix::net::UnixDatagram;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+pub struct JournaldLogger {
+    socket: UnixDatagram,
+}
+
+impl JournaldLogger {
+    pub fn connect() -> Result<Self, String> {
+        let socket = UnixDatagram::unbound().map_err(|e| e.to_string())?;
+        socket.connect(JOURNAL_SOCKET).map_err(|e| {
+            format!(
+                "journald is not available ({}: {}); is this a systemd system? Use --log-target file instead",
+                JOURNAL_SOCKET, e
+            )
+        })?;
+        Ok(JournaldLogger { socket })
+    }
+}
+
+/// Syslog priority: from the record's `severity` field when it has one
+/// (drift records do), otherwise from the log level.
+fn priority(level: Level, severity: Option<&str>) -> u8 {
+    match severity {
+        Some("critical") => 2,
+        Some("warning") => 4,
+        Some("info") => 5,
+        _ => match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        },
+    }
+}
+
+/// Append a field in the journal's export format. Values containing a
+/// newline need the length-prefixed binary form.
+fn push_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+/// Collects a record's key-values as `GUARDIAN_<KEY>` fields.
+struct Fields {
+    fields: Vec<(String, String)>,
+    severity: Option<String>,
+}
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = value.to_string();
+        if key.as_str() == "severity" {
+            self.severity = Some(value.clone());
+        }
+        let name: String = key
+            .as_str()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.fields.push((format!("GUARDIAN_{}", name), value));
+        Ok(())
+    }
+}
+
+impl Log for JournaldLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut fields = Fields {
+            fields: Vec::new(),
+            severity: None,
+        };
+        let _ = record.key_values().visit(&mut fields);
+
+        let mut buf = Vec::new();
+        push_field(&mut buf, "MESSAGE", &record.args().to_string());
+        push_field(
+            &mut buf,
+            "PRIORITY",
+            &priority(record.level(), fields.severity.as_deref()).to_string(),
+        );
+        push_field(&mut buf, "SYSLOG_IDENTIFIER", "config-guardian");
+        for (name, value) in &fields.fields {
+            push_field(&mut buf, name, value);
+        }
+        // Nowhere left to report a failure to log.
+        let _ = self.socket.send(&buf);
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,191 @@
+//! `compare --format junit`: drift as JUnit XML test results, which Jenkins
+//! and GitLab render as test reports. Each directory is a test suite and
+//! each drifting file a test case, failed when the drift is a warning or
+//! critical.
+
+use std::fmt::Write;
+
+use crate::alert::{self, Severity};
+use crate::manual::BIN_NAME;
+use config_guardian::DriftReport;
+
+/// The testcase name of a directory that didn't drift, so that its suite
+/// isn't empty.
+const NO_DRIFT: &str = "no drift";
+
+/// `text` safe in XML character data and in double-quoted attributes.
+/// Characters XML 1.0 can't carry at all, such as most control characters
+/// in a file name, become U+FFFD.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Kept as references, which attribute normalization leaves alone.
+            '\t' => escaped.push_str("&#9;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\r' => escaped.push_str("&#13;"),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether drift at `severity` fails its test case.
+fn fails(severity: Severity) -> bool {
+    severity != Severity::Info
+}
+
+/// The JUnit XML document of the comparisons of `reports`' directories.
+pub fn document(reports: &[(String, DriftReport)]) -> String {
+    let cases = |report: &DriftReport| report.drifts.len().max(1);
+    let failed = |report: &DriftReport| {
+        report
+            .drifts
+            .iter()
+            .filter(|drift| fails(alert::severity_of(drift)))
+            .count()
+    };
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"0\">",
+        BIN_NAME,
+        reports.iter().map(|(_, r)| cases(r)).sum::<usize>(),
+        reports.iter().map(|(_, r)| failed(r)).sum::<usize>()
+    )
+    .unwrap();
+    for (dir, report) in reports {
+        let dir = escape(dir);
+        writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"0\">",
+            dir,
+            cases(report),
+            failed(report)
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            "    <properties>\n      <property name=\"files_tracked\" value=\"{}\"/>\n      <property name=\"score\" value=\"{}\"/>\n    </properties>",
+            report.files_tracked, report.score
+        )
+        .unwrap();
+        if report.drifts.is_empty() {
+            writeln!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{}\"/>",
+                dir, NO_DRIFT
+            )
+            .unwrap();
+        }
+        for drift in &report.drifts {
+            let name = escape(&drift.path);
+            let severity = alert::severity_of(drift);
+            let hashes = report.hashes.get(&drift.path).cloned().unwrap_or_default();
+            let details = format!(
+                "{}\nseverity: {}\nold hash: {}\nnew hash: {}",
+                drift,
+                severity,
+                hashes.old.as_deref().unwrap_or("-"),
+                hashes.new.as_deref().unwrap_or("-")
+            );
+            if fails(severity) {
+                writeln!(
+                    xml,
+                    "    <testcase classname=\"{}\" name=\"{}\">\n      <failure type=\"{}\" message=\"{}\">{}</failure>\n    </testcase>",
+                    dir,
+                    name,
+                    drift.kind.as_str(),
+                    escape(&drift.to_string()),
+                    escape(&details)
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    xml,
+                    "    <testcase classname=\"{}\" name=\"{}\">\n      <system-out>{}</system-out>\n    </testcase>",
+                    dir,
+                    name,
+                    escape(&details)
+                )
+                .unwrap();
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileHash, Snapshot};
+    use config_guardian::compare;
+
+    #[test]
+    fn escaping_keeps_any_path_well_formed() {
+        assert_eq!(
+            escape("a&b <c> \"d\" 'e'"),
+            "a&amp;b &lt;c&gt; &quot;d&quot; &apos;e&apos;"
+        );
+        assert_eq!(escape("new\nline\ttab"), "new&#10;line&#9;tab");
+        assert_eq!(escape("bell\u{7}caf\u{e9}"), "bell\u{fffd}caf\u{e9}");
+    }
+
+    #[test]
+    fn drift_fails_test_cases_with_both_hashes() {
+        let entry = |path: &str, hash: &str| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![entry("d/app.conf", "aa11"), entry("d/<old>.conf", "bb22")],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![entry("d/app.conf", "cc33"), entry("d/a&b.conf", "dd44")],
+            ..Snapshot::default()
+        };
+        let clean = compare(&current, &current);
+        let reports = [
+            ("d".to_string(), compare(&baseline, &current)),
+            ("e".to_string(), clean),
+        ];
+        assert_eq!(
+            document(&reports),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="config-guardian" tests="4" failures="2" errors="0" skipped="0">
+  <testsuite name="d" tests="3" failures="2" errors="0" skipped="0">
+    <properties>
+      <property name="files_tracked" value="2"/>
+      <property name="score" value="56"/>
+    </properties>
+    <testcase classname="d" name="d/app.conf">
+      <failure type="changed" message="Changed: d/app.conf">Changed: d/app.conf&#10;severity: warning&#10;old hash: aa11&#10;new hash: cc33</failure>
+    </testcase>
+    <testcase classname="d" name="d/a&amp;b.conf">
+      <system-out>New: d/a&amp;b.conf&#10;severity: info&#10;old hash: -&#10;new hash: dd44</system-out>
+    </testcase>
+    <testcase classname="d" name="d/&lt;old&gt;.conf">
+      <failure type="deleted" message="Deleted: d/&lt;old&gt;.conf">Deleted: d/&lt;old&gt;.conf&#10;severity: critical&#10;old hash: bb22&#10;new hash: -</failure>
+    </testcase>
+  </testsuite>
+  <testsuite name="e" tests="1" failures="0" errors="0" skipped="0">
+    <properties>
+      <property name="files_tracked" value="2"/>
+      <property name="score" value="0"/>
+    </properties>
+    <testcase classname="e" name="no drift"/>
+  </testsuite>
+</testsuites>"#
+        );
+    }
+}
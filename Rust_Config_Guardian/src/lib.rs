@@ -0,0 +1,92 @@
+//! Detect configuration drift in files.
+//!
+//! A [`Snapshot`] records the hash of every file in a directory. Scanning the
+//! directory again and comparing the two gives a [`DriftReport`] of the files
+//! that were changed, added or deleted since:
+//!
+//! ```
+//! use config_guardian::{DriftKind, compare, scan_directory};
+//!
+//! let dir = std::env::temp_dir().join(format!("guardian-doc-crate-{}", std::process::id()));
+//! std::fs::create_dir_all(&dir)?;
+//! std::fs::write(dir.join("app.conf"), "listen 80")?;
+//! let baseline = scan_directory(dir.to_str().unwrap())?.snapshot;
+//!
+//! std::fs::write(dir.join("app.conf"), "listen 8080")?;
+//! let current = scan_directory(dir.to_str().unwrap())?.snapshot;
+//! let report = compare(&baseline, &current);
+//! assert_eq!(report.drifts[0].kind, DriftKind::Changed);
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`Monitor`] watches a directory and reports drift as it happens. Scanning
+//! honours the process-wide [`config::Settings`], which default to SHA-256
+//! with nothing excluded; [`config::install`] replaces them.
+
+mod accept;
+pub mod alert;
+pub mod annotation;
+mod api;
+pub mod archive;
+pub mod cache;
+pub mod chunk;
+pub mod collector;
+pub mod config;
+pub mod content;
+pub mod control;
+mod desktop;
+pub mod digest;
+mod drift;
+pub mod email;
+mod error;
+pub mod exception;
+mod heartbeat;
+pub mod history;
+pub mod hooks;
+mod http;
+pub mod identity;
+pub mod image;
+pub mod install;
+pub mod invoker;
+mod listener;
+pub mod log_file;
+pub mod manifest;
+mod metrics;
+pub mod monitor;
+pub mod normalize;
+#[cfg(unix)]
+mod owners;
+pub mod pagerduty;
+pub mod profile;
+pub mod progress;
+pub mod publish;
+pub mod redact;
+pub mod reload;
+pub mod remote;
+pub mod seal;
+pub mod slack;
+mod snapshot;
+pub mod staged;
+mod systemd;
+pub mod teams;
+pub mod telegram;
+pub mod throttle;
+pub mod utils;
+mod watch;
+pub mod webhook;
+
+pub use accept::{Review, accept, accept_reviewed};
+pub use drift::{
+    Drift, DriftKind, DriftReport, Hashes, check_baseline_age, compare, compare_against,
+    compare_metadata, compare_staged, compare_with_snapshot, parse_kinds,
+};
+pub use error::GuardianError;
+pub use monitor::{Monitor, MonitorHandle};
+pub use snapshot::{
+    Candidate, Decision, EntryType, FileHash, Permissions, SNAPSHOT_FILE, Scan, ScanOptions,
+    ScanStats, ScanWarning, Snapshot, SnapshotMetadata, Walk, WarningKind, base_of, baseline_at,
+    cancel_scans, check_scope, check_target, chunks_of, classify, format_tags, hash_file,
+    hash_file_as, is_pattern, matches_pattern, parse_tag, partial_path, scan_against,
+    scan_directory, snapshot_at, snapshot_of, take_snapshot, take_snapshots, walk_directory,
+};
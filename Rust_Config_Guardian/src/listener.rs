@@ -0,0 +1,267 @@
+//! Minimal HTTP/1.1 responder for the monitor's read-only endpoints
+//! (metrics and the status API) and the collector. Requests are served one
+//! at a time on a background thread. Event streams get a thread of their
+//! own for as long as the client stays.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Event streams send a heartbeat this often; a failed write is how a
+/// disconnected client is noticed.
+const STREAM_HEARTBEAT: Duration = Duration::from_secs(30);
+const STREAM_STOP_POLL: Duration = Duration::from_millis(500);
+
+/// What a handler answers with.
+pub enum Reply {
+    Full(Response),
+    /// A `text/event-stream` fed with pre-formatted events until the
+    /// sender is dropped or the client goes away.
+    Stream(Receiver<String>),
+}
+
+impl From<Response> for Reply {
+    fn from(response: Response) -> Self {
+        Reply::Full(response)
+    }
+}
+
+/// A request as handlers see it. The query string is dropped from `path`.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of the first header called `name`, in any case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, value: &serde_json::Value) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: format!("{}\n", value),
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: format!("{}\n", body.into()),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Response::text(404, "Not found")
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// A bound listener. Dropping it stops the thread and waits for it.
+pub struct HttpListener {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HttpListener {
+    /// Bind `addr` now, so a taken port fails startup rather than going
+    /// unnoticed, and answer GET requests with `handler(path)`.
+    pub fn start<F>(addr: SocketAddr, handler: F) -> std::io::Result<Self>
+    where
+        F: Fn(&str) -> Reply + Send + 'static,
+    {
+        Self::serve(addr, 0, move |request| {
+            if request.method == "GET" {
+                handler(&request.path)
+            } else {
+                Response::text(405, "Only GET is supported").into()
+            }
+        })
+    }
+
+    /// Like [`HttpListener::start`], but for any method: `handler` is given
+    /// the whole request. Bodies over `max_body` bytes are refused without
+    /// reaching it.
+    pub fn serve<F>(addr: SocketAddr, max_body: u64, handler: F) -> std::io::Result<Self>
+    where
+        F: Fn(&Request) -> Reply + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let mut streams: Vec<JoinHandle<()>> = Vec::new();
+            while !thread_stop.load(Ordering::SeqCst) {
+                streams.retain(|s| !s.is_finished());
+                match listener.accept() {
+                    Ok((stream, _)) => match respond(stream, max_body, &handler) {
+                        Ok(None) => {}
+                        Ok(Some((stream, events))) => {
+                            let stop = thread_stop.clone();
+                            streams.push(std::thread::spawn(move || {
+                                pump_events(stream, events, &stop)
+                            }));
+                        }
+                        Err(e) => log::warn!("HTTP request on {} failed: {}", addr, e),
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                    Err(e) => {
+                        log::warn!("Listener error on {}: {}", addr, e);
+                        std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                    }
+                }
+            }
+            for stream in streams {
+                let _ = stream.join();
+            }
+        });
+        Ok(HttpListener {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for HttpListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Answer one request. Event streams are handed back with their
+/// headers already sent.
+fn respond(
+    stream: TcpStream,
+    max_body: u64,
+    handler: &dyn Fn(&Request) -> Reply,
+) -> std::io::Result<Option<(TcpStream, Receiver<String>)>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut headers = Vec::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let reply = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => {
+            let mut request = Request {
+                method: method.to_string(),
+                path: path.split('?').next().unwrap_or(path).to_string(),
+                headers,
+                body: Vec::new(),
+            };
+            let length = request.header("Content-Length").map(str::parse::<u64>);
+            match length {
+                None | Some(Ok(0)) => handler(&request),
+                Some(Ok(length)) if length > max_body => Response::text(
+                    413,
+                    format!("The body is over the limit of {} bytes", max_body),
+                )
+                .into(),
+                Some(Ok(length)) => {
+                    request.body.reserve(length as usize);
+                    (&mut reader).take(length).read_to_end(&mut request.body)?;
+                    handler(&request)
+                }
+                Some(Err(_)) => Response::text(400, "Invalid Content-Length").into(),
+            }
+        }
+        _ => Reply::Full(Response::not_found()),
+    };
+    let response = match reply {
+        Reply::Full(response) => response,
+        Reply::Stream(events) => {
+            (&stream).write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+            )?;
+            return Ok(Some((stream, events)));
+        }
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    Ok(None)
+}
+
+/// Forward events to a streaming client until it disconnects, the sender
+/// goes away or the listener stops.
+fn pump_events(mut stream: TcpStream, events: Receiver<String>, stop: &AtomicBool) {
+    let mut last_write = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        let message = match events.recv_timeout(STREAM_STOP_POLL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) if last_write.elapsed() >= STREAM_HEARTBEAT => {
+                format!(
+                    "event: heartbeat\ndata: {{\"timestamp\":\"{}\"}}\n\n",
+                    chrono::Utc::now().to_rfc3339()
+                )
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if stream.write_all(message.as_bytes()).is_err() {
+            return;
+        }
+        last_write = Instant::now();
+    }
+}
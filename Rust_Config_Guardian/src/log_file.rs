@@ -0,0 +1,189 @@
+//! The log file writer. The file is only created when the first record is
+//! written, and if it can't be, records go to stderr instead so an
+//! unwritable working directory doesn't stop the tool from running.
+//!
+//! With a size limit, the file is rotated to `<path>.1` … `<path>.N` once it
+//! grows past the limit. Rotation only happens between lines: the logger
+//! writes a record in several pieces, and a record must never be split
+//! across two files.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static ACTIVE_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// The log file in use, if logging goes to a file.
+pub fn active_path() -> Option<&'static Path> {
+    ACTIVE_PATH.get().map(PathBuf::as_path)
+}
+
+/// When to rotate the log file, and how many old files to keep.
+#[derive(Clone, Copy)]
+pub struct Rotation {
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+enum State {
+    Unopened,
+    Open(File),
+    Stderr(io::Stderr),
+}
+
+pub struct LogFile {
+    path: PathBuf,
+    state: State,
+    rotation: Option<Rotation>,
+    /// Bytes in the active file.
+    size: u64,
+    /// Whether the last byte written ended a line.
+    at_line_start: bool,
+}
+
+impl LogFile {
+    pub fn new(path: &Path, rotation: Option<Rotation>) -> Self {
+        let _ = ACTIVE_PATH.set(path.to_path_buf());
+        LogFile {
+            path: path.to_path_buf(),
+            state: State::Unopened,
+            rotation,
+            size: 0,
+            at_line_start: true,
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        if let State::Unopened = self.state {
+            // Appended to rather than truncated so `status`/`stop` don't wipe a
+            // running daemon's log, and so the daemon can share it with its
+            // redirected stdout.
+            self.state = match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(file) => {
+                    self.size = file.metadata().map(|m| m.len()).unwrap_or(0);
+                    State::Open(file)
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Warning: could not open log file {}: {}; logging to stderr instead",
+                        self.path.display(),
+                        e
+                    );
+                    State::Stderr(io::stderr())
+                }
+            };
+        }
+        match &mut self.state {
+            State::Open(file) => file,
+            State::Stderr(stderr) => stderr,
+            State::Unopened => unreachable!(),
+        }
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    /// Shift `<path>.N-1` … `<path>` up by one, dropping the oldest, and
+    /// leave the next write to start a fresh file.
+    fn rotate(&mut self, keep: usize) {
+        self.state = State::Unopened;
+        let result = (|| {
+            for n in (1..keep).rev() {
+                match std::fs::rename(self.numbered(n), self.numbered(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            if keep == 0 {
+                std::fs::remove_file(&self.path)
+            } else {
+                std::fs::rename(&self.path, self.numbered(1))
+            }
+        })();
+        if let Err(e) = result {
+            // Keep appending, and try again once another limit's worth is written.
+            eprintln!(
+                "Warning: could not rotate log file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let (Some(rotation), State::Open(_)) = (self.rotation, &self.state)
+            && self.at_line_start
+            && self.size >= rotation.max_size
+        {
+            self.rotate(rotation.keep);
+        }
+        let written = self.writer().write(buf)?;
+        if written > 0 {
+            self.size += written as u64;
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.state {
+            State::Unopened => Ok(()),
+            State::Open(file) => file.flush(),
+            State::Stderr(stderr) => stderr.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_between_lines_once_past_the_limit() {
+        let dir = std::env::temp_dir().join(format!("guardian-rotate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("drift.log");
+        let mut log = LogFile::new(
+            &path,
+            Some(Rotation {
+                max_size: 100,
+                keep: 2,
+            }),
+        );
+
+        // Ten 40-byte lines, each written in two pieces like a logger does.
+        for i in 0..10 {
+            log.write_all(format!("line {:02} ", i).as_bytes()).unwrap();
+            log.write_all(format!("{:>31}\n", "").as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+
+        let read = |p: &Path| std::fs::read_to_string(p).unwrap();
+        let rotated_2 = read(&dir.join("drift.log.2"));
+        let rotated_1 = read(&dir.join("drift.log.1"));
+        let active = read(&path);
+        assert_eq!(rotated_2.len(), 120);
+        assert_eq!(rotated_1.len(), 120);
+        assert_eq!(active.len(), 40);
+        assert!(!dir.join("drift.log.3").exists());
+        assert!(rotated_2.starts_with("line 03 "));
+        assert!(rotated_1.starts_with("line 06 "));
+        assert!(active.starts_with("line 09 "));
+        for content in [&rotated_2, &rotated_1, &active] {
+            assert!(content.lines().all(|l| l.len() == 39));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+//! Optional JSON rendering of log records: one object per line, carrying the
+//! record's key-values as top-level fields, for log aggregators.
+
+use log::kv::{Key, Value, VisitSource};
+use log::{Log, Metadata, Record};
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Render a record as a single-line JSON object: `ts`, `level` and `msg`,
+/// followed by the record's key-values in the order they were given.
+pub fn json_line(record: &Record) -> String {
+    let mut line = format!(
+        "{{\"ts\":{},\"level\":{},\"msg\":{}",
+        quote(&chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+        quote(record.level().as_str()),
+        quote(&record.args().to_string())
+    );
+    let mut fields = Fields(&mut line);
+    let _ = record.key_values().visit(&mut fields);
+    line.push('}');
+    line
+}
+
+fn quote(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+struct Fields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = serde_json::to_string(&value).unwrap_or_else(|_| quote(&value.to_string()));
+        self.0.push(',');
+        self.0.push_str(&quote(key.as_str()));
+        self.0.push(':');
+        self.0.push_str(&value);
+        Ok(())
+    }
+}
+
+/// Hands each record to `inner` with its message replaced by the JSON line,
+/// so any target can carry the JSON format.
+pub struct JsonMessages<L> {
+    pub inner: L,
+}
+
+impl<L: Log> Log for JsonMessages<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = json_line(record);
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!("{}", line))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .key_values(record.key_values())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
@@ -1,92 +1,2306 @@
-use clap::Parser;
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use sha2::{Digest, Sha256};
-use simplelog::{Config, LevelFilter, WriteLogger};
-use std::fs;
-use std::fs::File;
-use std::path::Path;
-use std::sync::mpsc::channel;
+use chrono::NaiveTime;
+use clap::{CommandFactory, Parser};
+use lettre::message::Mailbox;
+use simplelog::{Config, ConfigBuilder, LevelFilter, WriteLogger};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
-mod utils;
-use utils::is_valid_directory;
+use config_guardian::{
+    Decision, Drift, DriftKind, FileHash, GuardianError, SNAPSHOT_FILE, ScanOptions, ScanStats,
+    ScanWarning, Snapshot, SnapshotMetadata, accept, accept_reviewed, alert, annotation, archive,
+    base_of, cache, cancel_scans, check_baseline_age, check_scope, check_target, collector, config,
+    control, digest, email, exception, format_tags, hash_file, hash_file_as, history, hooks,
+    identity, image, install, invoker, is_pattern, log_file, manifest, monitor, pagerduty,
+    parse_kinds, parse_tag, profile, publish, redact, reload, remote, seal, slack, take_snapshots,
+    teams, telegram, throttle, utils, walk_directory, webhook,
+};
 
-#[derive(Serialize, Deserialize)]
-struct FileHash {
-    path: String,
-    hash: String,
-}
+mod all_profiles;
+mod daemon;
+mod doctor;
+#[cfg(windows)]
+mod eventlog;
+mod gha;
+#[cfg(target_os = "linux")]
+mod journald;
+mod junit;
+mod log_format;
+mod manual;
+mod nagios;
+mod recover;
+mod report;
+mod sarif;
+#[cfg(all(windows, feature = "windows-service"))]
+mod service;
+mod stats;
+mod syslog;
+mod verify;
+use alert::AlertChannels;
+use config::{HashDisplay, SizeUnits};
+use daemon::{control_path, daemon_status, daemonize, info_path, stop_daemon, trigger_comparison};
+use digest::DigestPeriod;
+use email::{EmailConfig, SmtpTls};
+use hooks::DriftHook;
+use image::Image;
+use log_format::LogFormat;
+use monitor::{Monitor, MonitorOptions, WatchBackend};
+use pagerduty::PagerDutyConfig;
+use reload::{ConfigReload, Reloaded};
+use remote::is_remote;
+use slack::SlackConfig;
+use teams::TeamsConfig;
+use telegram::TelegramConfig;
+use utils::{is_valid_directory, parse_duration, parse_interval, parse_size};
+use webhook::WebhookConfig;
+
+const LOG_FILE: &str = "drift.log";
+const DEFAULT_PID_FILE: &str = "guardian.pid";
 
 #[derive(Parser)]
-#[command(author, version, about = "Detect configuration drift in files.", long_about = None)]
+#[command(
+    author,
+    version,
+    about = "Detect configuration drift in files.",
+    long_about = manual::ABOUT,
+    after_long_help = manual::AFTER_HELP
+)]
 struct Cli {
+    /// Config file (default: ./guardian.toml, then the user config directory).
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Use a profile's directories, excludes, severity rules and alerts: a [profile.NAME] of the config file, or a built-in one (see `profiles list`).
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+    /// List each warning instead of just counting them.
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// How hashes are shown in text output [default: short, 12 characters].
+    #[arg(long, value_enum, global = true, value_name = "HOW")]
+    hash_display: Option<HashDisplay>,
+    /// Show times in UTC instead of the local time zone.
+    #[arg(long, global = true)]
+    utc: bool,
+    /// Units text output shows sizes in [default: binary, as in 1.5 KiB].
+    #[arg(long, value_enum, global = true, value_name = "UNITS")]
+    size_units: Option<SizeUnits>,
+    /// Group digits and mark decimals in text output as this locale does, e.g. de-DE [default: no grouping].
+    #[arg(long, global = true, value_name = "TAG", value_parser = config::Locale::parse)]
+    locale: Option<config::Locale>,
+    /// Print what snapshot, accept, import, stop or monitor --auto-accept would change, changing nothing; exit 1 if nothing would change.
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Refuse a snapshot with broken entries (malformed, duplicated, or leading out of their directory) rather than drop them with a warning.
+    #[arg(long, global = true)]
+    strict_load: bool,
+    /// Show the names of files listed in redact on the terminal; what's logged, alerted on or recorded stays redacted.
+    #[arg(long, global = true)]
+    no_redact: bool,
+    /// Identify this machine as ID in reports, snapshots, alerts and uploads to a collector, rather than by its cloud instance ID or the ID stored in instance-id; for VMs and containers whose hostnames come and go.
+    #[arg(long, global = true, value_name = "ID")]
+    instance_id: Option<String>,
+    #[command(flatten)]
+    log: LogArgs,
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+/// The global flags that override the config file's settings, kept apart
+/// so that `monitor` can apply them again to a config file it reloads.
+#[derive(Clone)]
+struct Overrides {
+    hash_display: Option<HashDisplay>,
+    utc: bool,
+    strict_load: bool,
+    size_units: Option<SizeUnits>,
+    locale: Option<config::Locale>,
+    instance_id: Option<String>,
+}
+
+impl Overrides {
+    fn of(cli: &Cli) -> Self {
+        Overrides {
+            hash_display: cli.hash_display,
+            utc: cli.utc,
+            strict_load: cli.strict_load,
+            size_units: cli.size_units,
+            locale: cli.locale.clone(),
+            instance_id: cli.instance_id.clone(),
+        }
+    }
+
+    fn apply(&self, config: &mut config::Config) {
+        if let Some(display) = self.hash_display {
+            config.hash_display = display;
+        }
+        config.utc |= self.utc;
+        config.strict_load |= self.strict_load;
+        if let Some(units) = self.size_units {
+            config.size_units = units;
+        }
+        if let Some(locale) = &self.locale {
+            config.locale = Some(locale.clone());
+        }
+        if let Some(id) = &self.instance_id {
+            config.instance_id = Some(id.clone());
+        }
+    }
+}
+
+/// Logging options, accepted by every subcommand.
+#[derive(clap::Args)]
+struct LogArgs {
+    /// Where log records go: file, syslog, syslog:udp://host:514,
+    /// syslog:unix:///dev/log, journald (Linux) or eventlog (Windows).
+    #[arg(long, global = true, value_name = "TARGET", value_parser = LogTarget::parse, env = "GUARDIAN_LOG_TARGET", default_value = "file")]
+    log_target: LogTarget,
+    /// How log records are written.
+    #[arg(
+        long,
+        value_enum,
+        global = true,
+        env = "GUARDIAN_LOG_FORMAT",
+        default_value = "text"
+    )]
+    log_format: LogFormat,
+    /// Log file for `--log-target file`; created when the first record is written.
+    #[arg(long, global = true, value_name = "PATH", env = "GUARDIAN_LOG_FILE", default_value = LOG_FILE)]
+    log_file: PathBuf,
+    /// Least severe records to log (GUARDIAN_LOG is also read) [default: info].
+    #[arg(long, value_enum, global = true, env = "GUARDIAN_LOG_LEVEL")]
+    log_level: Option<LogLevel>,
+    /// Rotate the log file once it grows past this size (e.g. 10M).
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size, env = "GUARDIAN_LOG_MAX_SIZE")]
+    log_max_size: Option<u64>,
+    /// Rotated log files to keep, as <PATH>.1 … <PATH>.N.
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        env = "GUARDIAN_LOG_KEEP",
+        default_value_t = 5
+    )]
+    log_keep: usize,
+}
+
+impl Default for LogArgs {
+    fn default() -> Self {
+        LogArgs {
+            log_target: LogTarget::File,
+            log_format: LogFormat::Text,
+            log_file: PathBuf::from(LOG_FILE),
+            log_level: None,
+            log_max_size: None,
+            log_keep: 5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogArgs {
+    /// GUARDIAN_LOG was this setting's variable before GUARDIAN_LOG_LEVEL.
+    fn level(&self) -> LogLevel {
+        self.log_level
+            .or_else(|| {
+                let value = std::env::var("GUARDIAN_LOG").ok()?;
+                clap::ValueEnum::from_str(&value, true).ok()
+            })
+            .unwrap_or(LogLevel::Info)
+    }
+}
+
+impl LogLevel {
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// What `compare` writes to stdout.
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum CompareFormat {
+    Text,
+    /// A SARIF 2.1.0 log of every directory's drift, for code scanning.
+    Sarif,
+    /// JUnit XML test results, a test suite per directory, for CI test reports.
+    Junit,
+    /// The text report plus GitHub Actions annotations and a step summary.
+    Gha,
+}
+
+impl CompareFormat {
+    /// The format when --format isn't given: gha in a GitHub Actions job.
+    fn detect() -> Self {
+        if gha::detected() {
+            CompareFormat::Gha
+        } else {
+            CompareFormat::Text
+        }
+    }
+}
+
+/// What `export` writes.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ExportFormat {
+    /// `HASH  NAME` lines, as `sha256sum` writes and `sha256sum -c` checks.
+    Sha256sums,
+}
+
+#[derive(Clone)]
+enum LogTarget {
+    /// Append to drift.log in the working directory.
+    File,
+    /// Send RFC 5424 messages to a syslog daemon.
+    Syslog(syslog::SyslogAddr),
+    /// Send structured records to systemd-journald (Linux).
+    Journald,
+    /// Write drift and errors to the Windows Application event log.
+    #[cfg(windows)]
+    Eventlog,
+}
+
+impl LogTarget {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "file" => Ok(LogTarget::File),
+            "syslog" => Ok(LogTarget::Syslog(syslog::SyslogAddr::local())),
+            "journald" => Ok(LogTarget::Journald),
+            #[cfg(windows)]
+            "eventlog" => Ok(LogTarget::Eventlog),
+            _ => match value.strip_prefix("syslog:") {
+                Some(addr) => syslog::SyslogAddr::parse(addr).map(LogTarget::Syslog),
+                None => Err(format!(
+                    "unknown log target '{}' (expected file, syslog[:ADDR], journald or eventlog)",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+/// Alerting options shared by `compare` and `monitor`.
+#[derive(Clone, clap::Args)]
+struct AlertArgs {
+    /// Send alerts when drift is detected (implied by configuring a channel).
+    #[arg(long, action)]
+    alert: bool,
+    /// POST a JSON drift summary to this URL.
+    #[arg(long, value_name = "URL")]
+    webhook_url: Option<String>,
+    /// Extra header for webhook requests, as 'Name: Value' (repeatable).
+    #[arg(long = "webhook-header", value_name = "HEADER", value_parser = webhook::parse_header)]
+    webhook_headers: Vec<(String, String)>,
+    /// Post alerts to this Slack incoming-webhook URL.
+    #[arg(long, value_name = "URL")]
+    slack_webhook: Option<String>,
+    /// Post alerts to this Microsoft Teams incoming-webhook URL.
+    #[arg(long, value_name = "URL")]
+    teams_webhook: Option<String>,
+    /// Telegram bot token for alerts; prefer GUARDIAN_TELEGRAM_TOKEN, which
+    /// stays out of `ps` and shell history.
+    #[arg(long, value_name = "TOKEN")]
+    telegram_token: Option<String>,
+    /// Telegram chat to send alerts to.
+    #[arg(long, value_name = "ID")]
+    telegram_chat_id: Option<String>,
+    /// PagerDuty Events API v2 routing key; drift triggers an incident and a
+    /// later clean comparison resolves it. Prefer GUARDIAN_PAGERDUTY_ROUTING_KEY.
+    #[arg(long, value_name = "KEY")]
+    pagerduty_routing_key: Option<String>,
+    #[command(flatten)]
+    email: EmailArgs,
+    /// Timeout for each webhook request [default: 10s].
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    webhook_timeout: Option<Duration>,
+    #[command(flatten)]
+    hook: HookArgs,
+}
+
+impl AlertArgs {
+    /// The configured channels, or `None` if alerting is off. Each channel
+    /// given on the command line replaces the same channel in `config`.
+    fn channels(&self, config: &config::AlertSettings) -> Result<Option<AlertChannels>, String> {
+        let timeout = self
+            .webhook_timeout
+            .or(config.timeout)
+            .unwrap_or(config::DEFAULT_WEBHOOK_TIMEOUT);
+        let configured = config.channels(timeout)?;
+        let channels = AlertChannels {
+            webhook: self.webhook_url.as_ref().map(|url| WebhookConfig {
+                url: url.clone(),
+                headers: self.webhook_headers.clone(),
+                timeout,
+            }),
+            slack: self.slack_webhook.as_ref().map(|url| SlackConfig {
+                url: url.clone(),
+                timeout,
+            }),
+            teams: self.teams_webhook.as_ref().map(|url| TeamsConfig {
+                url: url.clone(),
+                timeout,
+            }),
+            email: self.email.config(),
+            telegram: match (
+                self.telegram_token
+                    .as_ref()
+                    .or(config.telegram_token.as_ref()),
+                self.telegram_chat_id
+                    .as_ref()
+                    .or(config.telegram_chat_id.as_ref()),
+            ) {
+                (Some(token), Some(chat_id)) => Some(TelegramConfig {
+                    token: token.clone(),
+                    chat_id: chat_id.clone(),
+                    timeout,
+                }),
+                (None, None) => None,
+                _ => {
+                    return Err(
+                        "--telegram-token and --telegram-chat-id must be set together".into(),
+                    );
+                }
+            },
+            pagerduty: self
+                .pagerduty_routing_key
+                .as_ref()
+                .map(|routing_key| PagerDutyConfig {
+                    routing_key: routing_key.clone(),
+                    timeout,
+                }),
+            desktop: false,
+            on_drift: self.hook.hook(),
+            limits: Default::default(),
+        };
+        let channels = AlertChannels {
+            webhook: channels.webhook.or(configured.webhook),
+            slack: channels.slack.or(configured.slack),
+            teams: channels.teams.or(configured.teams),
+            email: channels.email.or(configured.email),
+            telegram: channels.telegram,
+            pagerduty: channels.pagerduty.or(configured.pagerduty),
+            desktop: false,
+            on_drift: channels.on_drift,
+            limits: configured.limits,
+        };
+        Ok((self.alert || config.enabled || !channels.is_empty()).then_some(channels))
+    }
+}
+
+/// SMTP options, used by alerting and `digest send`.
+#[derive(Clone, clap::Args)]
+struct EmailArgs {
+    /// SMTP server for email alerts. Credentials are read from
+    /// GUARDIAN_SMTP_USERNAME and GUARDIAN_SMTP_PASSWORD.
+    #[arg(long, value_name = "HOST", requires_all = ["email_from", "email_to"])]
+    smtp_host: Option<String>,
+    /// SMTP port (default: 587 for starttls, 465 for tls, 25 for none).
+    #[arg(long, value_name = "PORT", requires = "smtp_host")]
+    smtp_port: Option<u16>,
+    #[arg(long, value_enum, default_value = "starttls")]
+    smtp_tls: SmtpTls,
+    /// Sender address for email alerts.
+    #[arg(long, value_name = "ADDRESS", value_parser = email::parse_mailbox, requires = "smtp_host")]
+    email_from: Option<Mailbox>,
+    /// Recipient for email alerts (repeatable).
+    #[arg(long, value_name = "ADDRESS", value_parser = email::parse_mailbox, requires = "smtp_host")]
+    email_to: Vec<Mailbox>,
+    /// Email subject; {summary}, {dir}, {host}, {severity} and {score} are substituted.
+    #[arg(long, value_name = "TEMPLATE", default_value = email::DEFAULT_SUBJECT)]
+    email_subject: String,
+    /// Attach the drift entries to alert emails as JSON.
+    #[arg(long, action, requires = "smtp_host")]
+    email_attach_report: bool,
+    /// Collect email alerts into one digest instead of sending each one.
+    /// Critical drift is still sent immediately.
+    #[arg(long, value_enum, value_name = "PERIOD", requires = "smtp_host")]
+    email_digest: Option<DigestPeriod>,
+    /// Local time at which monitor mode sends the digest, as HH:MM.
+    #[arg(long, value_name = "HH:MM", value_parser = digest::parse_time, default_value = "08:00")]
+    email_digest_at: NaiveTime,
+}
+
+impl EmailArgs {
+    fn config(&self) -> Option<EmailConfig> {
+        self.smtp_host.as_ref().map(|host| EmailConfig {
+            host: host.clone(),
+            port: self.smtp_port,
+            tls: self.smtp_tls,
+            credentials: email::credentials_from_env(),
+            from: self.email_from.clone().expect("required by clap"),
+            to: self.email_to.clone(),
+            subject: self.email_subject.clone(),
+            attach_report: self.email_attach_report,
+            digest: self.email_digest.map(|_| self.email_digest_at),
+        })
+    }
+}
+
+/// What `snapshot`, `compare` and `monitor` scan and record.
+#[derive(Clone, clap::Args)]
+struct ScanArgs {
+    /// Run once for each [profile.NAME] of the config file, then sum the runs up.
+    #[arg(long, action, conflicts_with_all = ["directory", "profile"])]
+    all_profiles: bool,
+    /// Record which mount each file is on and report files mounted over (Linux only).
+    #[arg(long, action)]
+    detect_mounts: bool,
+    /// Record the immutable and append-only attributes and report changes to them (Linux only).
+    #[arg(long, action)]
+    attrs: bool,
+    /// Record POSIX ACLs and report entries added, removed or edited (Linux only).
+    #[arg(long, action)]
+    acls: bool,
+    /// Tag files that grow while hashed, and *.log and *.journal files, as volatile.
+    #[arg(long, action)]
+    skip_growing: bool,
+    /// Report changes to files tagged volatile as drift.
+    #[arg(long, action)]
+    include_volatile: bool,
+    /// Report files that can't be read apart from drift, without alerting on them.
+    #[arg(long, action)]
+    ignore_unreadable: bool,
+    /// Don't count differences in these metadata fields as drift, in place of the config's ignore_fields.
+    #[arg(long, value_enum, value_name = "FIELDS", value_delimiter = ',')]
+    ignore_fields: Vec<config::Field>,
+    /// Compare owners and groups by name, falling back to the ID where one has none, or by ID only.
+    #[arg(long, value_enum, value_name = "BY")]
+    compare_ownership_by: Option<config::Ownership>,
+    /// Scan the snapshot, log and other files config-guardian writes, too.
+    #[arg(long, action)]
+    track_self: bool,
+    /// Scan a directory given as a symlink, or a pattern's base directory, through it rather than refuse.
+    #[arg(long, action)]
+    follow_root_symlink: bool,
+    /// Give up on reading a file after DURATION (e.g. 30s) and report it unreadable.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    read_timeout: Option<Duration>,
+    /// Give up on a directory holding more than N files to track [default: 250000; 0: no limit].
+    #[arg(long, value_name = "N")]
+    max_files: Option<usize>,
+    /// Read and hash up to N files at once [default: one per CPU, up to 8; with --nice, 1].
+    #[arg(long, value_name = "N")]
+    jobs: Option<usize>,
+    /// Go easy on a busy host: read one file at a time, at most 20 MiB/s and half a CPU, at a lowered priority.
+    #[arg(long, action)]
+    nice: bool,
+    /// Cap the bandwidth of the scan's reads at RATE MiB/s.
+    #[arg(long, value_name = "RATE")]
+    io_throttle: Option<f64>,
+    /// Cap the share of a CPU the scan's reads and hashing take (e.g. 25%).
+    #[arg(long, value_name = "PERCENT", value_parser = utils::parse_percent)]
+    cpu_limit: Option<f64>,
+    /// Read a file up to N more times after a transient error, as on a network filesystem [default: 2].
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+    /// Wait DURATION before the first retry, doubling it each time after [default: 100ms].
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    retry_delay: Option<Duration>,
+    /// Compare by reading every file, even those whose size and modification time match the snapshot.
+    #[arg(long, action)]
+    paranoid: bool,
+    /// Read every file, rather than take hashes from the hash cache for files it has unchanged.
+    #[arg(long, action)]
+    no_cache: bool,
+    /// Read this share of the files that look unchanged anyway, in comparisons [default: 1%].
+    #[arg(long, value_name = "PERCENT", value_parser = utils::parse_percent)]
+    verify_sample: Option<f64>,
+    /// Hash files larger than SIZE (e.g. 1G) over their first and last 8 MiB and their length only.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    sample_hash: Option<u64>,
+    /// Record content-defined chunks of the files matching GLOB (repeatable), so that drift to them says which byte ranges changed.
+    #[arg(long = "chunked-hash", value_name = "GLOB")]
+    chunked_hash: Vec<String>,
+    /// Track PATH, a file of /proc or /sys such as a kernel tunable, beside the directories, read with one bounded read (repeatable).
+    #[arg(long = "virtual-file", value_name = "PATH")]
+    virtual_file: Vec<String>,
+    /// Warn when a directory's baseline is older than DURATION (e.g. 90d).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    max_baseline_age: Option<Duration>,
+    /// Fail with exit 2, rather than warn, when the baseline is older than --max-baseline-age.
+    #[arg(long, action)]
+    require_fresh_baseline: bool,
+    /// Fail with exit 2, rather than warn, when the config or flags set scan options otherwise than the baseline was taken with.
+    #[arg(long, action)]
+    strict_options: bool,
+    /// Compare a directory the baseline wasn't taken of with the one it was, as if that were it.
+    #[arg(long, action)]
+    allow_root_mismatch: bool,
+    /// Take modification times more than DURATION ahead of the clock as skewed: warn and read the file [default: 5m].
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    clock_skew: Option<Duration>,
+    /// Log in to sftp:// targets with this private key [default: the SSH agent's keys].
+    #[arg(long, value_name = "PATH")]
+    identity: Option<PathBuf>,
+    /// Check sftp:// targets' host keys against this file [default: ~/.ssh/known_hosts].
+    #[arg(long, value_name = "PATH")]
+    known_hosts: Option<PathBuf>,
+}
+
+impl ScanArgs {
+    /// Apply the flags on top of the config file's settings.
+    fn apply(&self, config: &mut config::Config) {
+        config.detect_mounts |= self.detect_mounts;
+        config.attrs |= self.attrs;
+        config.acls |= self.acls;
+        config.skip_growing |= self.skip_growing;
+        config.include_volatile |= self.include_volatile;
+        config.ignore_unreadable |= self.ignore_unreadable;
+        if !self.ignore_fields.is_empty() {
+            config.ignore_fields = self.ignore_fields.clone();
+        }
+        config.track_self |= self.track_self;
+        config.follow_root_symlink |= self.follow_root_symlink;
+        if let Some(by) = self.compare_ownership_by {
+            config.compare_ownership_by = by;
+        }
+        if self.max_files.is_some() {
+            config.max_files = self.max_files;
+        }
+        if self.jobs.is_some() {
+            config.jobs = self.jobs;
+        }
+        config.nice |= self.nice;
+        if self.io_throttle.is_some() {
+            config.io_throttle = self.io_throttle;
+        }
+        if self.cpu_limit.is_some() {
+            config.cpu_limit = self.cpu_limit;
+        }
+        if self.read_timeout.is_some() {
+            config.read_timeout = self.read_timeout;
+        }
+        if self.retries.is_some() {
+            config.retries = self.retries;
+        }
+        if self.retry_delay.is_some() {
+            config.retry_delay = self.retry_delay;
+        }
+        config.paranoid |= self.paranoid;
+        config.no_cache |= self.no_cache;
+        if self.verify_sample.is_some() {
+            config.verify_sample = self.verify_sample;
+        }
+        if self.sample_hash.is_some() {
+            config.sample_hash = self.sample_hash;
+        }
+        if !self.chunked_hash.is_empty() {
+            config.chunked_hash = self.chunked_hash.clone();
+        }
+        if !self.virtual_file.is_empty() {
+            config.virtual_files = self.virtual_file.clone();
+        }
+        if self.max_baseline_age.is_some() {
+            config.max_baseline_age = self.max_baseline_age;
+        }
+        config.require_fresh_baseline |= self.require_fresh_baseline;
+        if self.clock_skew.is_some() {
+            config.clock_skew = self.clock_skew;
+        }
+        if self.identity.is_some() {
+            config.sftp.identity = self.identity.clone();
+        }
+        if self.known_hosts.is_some() {
+            config.sftp.known_hosts = self.known_hosts.clone();
+        }
+    }
+}
+
+/// Who `snapshot --upload` and `compare --report-upload` upload as.
+#[derive(clap::Args)]
+struct CollectorArgs {
+    /// Name to upload as [default: this machine's identity; see --instance-id].
+    #[arg(long, value_name = "NAME")]
+    host_name: Option<String>,
+    /// The collector's token; prefer GUARDIAN_COLLECTOR_TOKEN, which keeps it out of `ps`.
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        env = "GUARDIAN_COLLECTOR_TOKEN",
+        hide_env_values = true
+    )]
+    upload_token: Option<String>,
+}
+
+impl CollectorArgs {
+    fn upload(&self, url: &Option<String>) -> Result<Option<collector::Upload>, GuardianError> {
+        let Some(url) = url else {
+            return Ok(None);
+        };
+        let host = self
+            .host_name
+            .clone()
+            .unwrap_or_else(|| identity::current().to_string());
+        collector::check_host_name(&host).map_err(GuardianError::Config)?;
+        let token = self.upload_token.clone().ok_or_else(|| {
+            GuardianError::Config(
+                "Uploading needs the collector's token: set GUARDIAN_COLLECTOR_TOKEN or pass --upload-token.".into(),
+            )
+        })?;
+        Ok(Some(collector::Upload {
+            url: url.clone(),
+            host,
+            token,
+        }))
+    }
+}
+
+/// The key `accept --publish` signs baselines with and `monitor
+/// --baseline-subscribe` checks them with.
+#[derive(clap::Args)]
+struct BaselineKeyArgs {
+    /// Key published baselines are signed and checked with, the same on every host; prefer GUARDIAN_BASELINE_KEY, which keeps it out of `ps`.
+    #[arg(
+        long,
+        value_name = "KEY",
+        env = "GUARDIAN_BASELINE_KEY",
+        hide_env_values = true
+    )]
+    baseline_key: Option<String>,
+}
+
+impl BaselineKeyArgs {
+    /// The baselines published for `profile`, or the default profile, at
+    /// the collector at `url`.
+    fn baselines(
+        &self,
+        url: &str,
+        token: Option<String>,
+        profile: Option<&str>,
+    ) -> Result<publish::Baselines, GuardianError> {
+        let profile = profile.unwrap_or(publish::DEFAULT_PROFILE).to_string();
+        collector::check_profile_name(&profile).map_err(GuardianError::Config)?;
+        let token = token.ok_or_else(|| {
+            GuardianError::Config(
+                "Published baselines need the collector's token: set GUARDIAN_COLLECTOR_TOKEN or pass --upload-token.".into(),
+            )
+        })?;
+        let key = self.baseline_key.clone().filter(|key| !key.is_empty()).ok_or_else(|| {
+            GuardianError::Config(
+                "Published baselines are signed: set GUARDIAN_BASELINE_KEY or pass --baseline-key, the same on every host.".into(),
+            )
+        })?;
+        Ok(publish::Baselines {
+            url: url.to_string(),
+            token,
+            profile,
+            key,
+        })
+    }
+}
+
+/// `--on-drift` options, an alert channel of `compare` and `monitor`.
+#[derive(Clone, clap::Args)]
+struct HookArgs {
+    /// Run COMMAND when drift is detected, with the JSON report on stdin.
+    #[arg(long, value_name = "COMMAND")]
+    on_drift: Option<String>,
+    /// Kill the --on-drift command if it runs longer than this.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "60s")]
+    on_drift_timeout: Duration,
+    /// Run the --on-drift command through the shell instead of executing it directly.
+    #[arg(long, action)]
+    on_drift_shell: bool,
+}
+
+impl HookArgs {
+    fn hook(&self) -> Option<DriftHook> {
+        self.on_drift.as_ref().map(|command| DriftHook {
+            command: command.clone(),
+            timeout: self.on_drift_timeout,
+            shell: self.on_drift_shell,
+        })
+    }
+}
+
+// Parsed once per run, so the size of the monitor variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(clap::Subcommand)]
 enum Commands {
     /// Take a snapshot of configuration files.
+    #[command(after_long_help = manual::SNAPSHOT_HELP)]
     Snapshot {
-        #[arg(value_name = "DIRECTORY")]
+        #[arg(value_name = "DIRECTORY", value_hint = clap::ValueHint::DirPath)]
         directory: Option<String>,
+        #[command(flatten)]
+        scan: ScanArgs,
+        /// Fail if any file was skipped or couldn't be read.
+        #[arg(long)]
+        strict: bool,
+        /// Give up, saving nothing, if the scans take longer than DURATION (e.g. 10m).
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// Show why a snapshot would or wouldn't hash PATH, step by step, without saving anything.
+        #[arg(long, value_name = "PATH", conflicts_with = "upload")]
+        explain: Option<String>,
+        /// Label the snapshot, e.g. --tag release=2.7.1; repeat for more tags.
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+        /// Also upload the snapshot to the collector at URL (see `serve`).
+        #[arg(long, value_name = "URL")]
+        upload: Option<String>,
+        #[command(flatten)]
+        collector: CollectorArgs,
+        /// Take DIRECTORY's files from this tar archive, such as an image layer, gzipped or not, rather than from the directory.
+        #[arg(long, value_name = "TAR", conflicts_with_all = ["from_oci_dir", "explain"])]
+        from_tar: Option<PathBuf>,
+        /// Take DIRECTORY's files from the image in this OCI image layout, its layers applied in order.
+        #[arg(long, value_name = "LAYOUT", conflicts_with = "explain")]
+        from_oci_dir: Option<PathBuf>,
+        /// The directory of the image that DIRECTORY is mounted from [default: DIRECTORY's own path].
+        #[arg(long, value_name = "PREFIX")]
+        strip_prefix: Option<String>,
     },
     /// Compare current files with the last snapshot.
+    #[command(after_long_help = manual::COMPARE_HELP)]
     Compare {
-        #[arg(value_name = "DIRECTORY")]
+        #[arg(value_name = "DIRECTORY", value_hint = clap::ValueHint::DirPath)]
         directory: Option<String>,
-        #[arg(long, action)]
-        alert: bool,
+        #[command(flatten)]
+        scan: ScanArgs,
+        /// Fail if any file was skipped or couldn't be read.
+        #[arg(long)]
+        strict: bool,
+        /// Give up, saving nothing, if the scans take longer than DURATION (e.g. 10m).
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        #[command(flatten)]
+        alerts: AlertArgs,
+        /// Also upload each directory's drift report to the collector at URL (see `serve`).
+        #[arg(long, value_name = "URL")]
+        report_upload: Option<String>,
+        /// Compare with an archived snapshot instead: previous (or -1) for the one the last snapshot replaced, -2 for the one before, ...
+        #[arg(long, value_name = "SNAPSHOT", allow_hyphen_values = true, value_parser = archive::parse_against)]
+        against: Option<usize>,
+        /// Report the drift there would be with the files in DIR laid over the directory's, an empty NAME.whiteout standing for NAME's deletion; nothing is alerted on, uploaded or recorded.
+        #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath, conflicts_with_all = ["alert", "report_upload"])]
+        staged: Option<String>,
+        /// Only compare the files under PATH, relative to the directory or the pattern's base directory, leaving the rest out of the report; repeat for more.
+        #[arg(long, value_name = "PATH")]
+        scope: Vec<String>,
+        /// Leave the entries matching GLOB out of the baseline as it is loaded (repeatable), so they aren't compared at all.
+        #[arg(long = "baseline-exclude", value_name = "GLOB")]
+        baseline_exclude: Vec<String>,
+        /// Exit 1 if drift of these kinds is found: a comma-separated list such as changed,deleted, all or metadata, or none to exit 0 whatever is found [default: all].
+        #[arg(long, value_name = "KINDS", num_args = 0..=1, default_missing_value = "all", value_parser = parse_kinds)]
+        // Spelled out so clap takes the list as one value rather than many.
+        fail_on: Option<::std::vec::Vec<DriftKind>>,
+        /// Write the drift to stdout as text, a SARIF log, JUnit XML or GitHub Actions annotations; warnings still go to stderr [default: gha in GitHub Actions, otherwise text].
+        #[arg(long, value_enum)]
+        format: Option<CompareFormat>,
+        /// Write the --format report to FILE instead, leaving the text report on stdout.
+        #[arg(long, value_name = "FILE")]
+        report_out: Option<PathBuf>,
+        /// Report as a Nagios check plugin: one status line with the drift counted as performance data, exiting 0 (OK), 1 (WARNING), 2 (CRITICAL) or 3 (UNKNOWN).
+        #[arg(long, conflicts_with_all = ["format", "report_out", "fail_on"])]
+        nagios: bool,
+        #[command(flatten)]
+        collector: CollectorArgs,
     },
     /// Monitor directory for changes and detect drift.
+    #[command(after_long_help = manual::MONITOR_HELP)]
     Monitor {
-        #[arg(value_name = "DIRECTORY")]
+        #[arg(value_name = "DIRECTORY", value_hint = clap::ValueHint::DirPath)]
+        directory: Option<String>,
+        #[command(flatten)]
+        scan: ScanArgs,
+        /// Leave the entries matching GLOB out of the baseline as it is loaded (repeatable), so they aren't compared at all.
+        #[arg(long = "baseline-exclude", value_name = "GLOB")]
+        baseline_exclude: Vec<String>,
+        #[command(flatten)]
+        alerts: AlertArgs,
+        /// Show a desktop notification when drift is detected.
+        #[arg(long, action)]
+        notify_desktop: bool,
+        /// Exit with code 1 on shutdown if any drift occurred during the session.
+        #[arg(long, action)]
+        exit_drift: bool,
+        /// Stop after handling N filesystem events (N polls with --poll), exiting as with --exit-drift.
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        max_events: Option<u64>,
+        /// Stop after running for DURATION (e.g. 10m), exiting as with --exit-drift.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        duration: Option<Duration>,
+        /// Wait for the first drift: report it and exit 1, or exit 0 when --duration runs out first.
+        #[arg(long, action)]
+        once: bool,
+        /// Report all drift found on every comparison, not only what changed since the last one.
+        #[arg(long, action)]
+        no_dedup: bool,
+        /// Accept each drift into the snapshot once reported, recording change rather than guarding against it.
+        #[arg(long, action)]
+        auto_accept: bool,
+        /// Only compare on events for files matching GLOB, by path or name (e.g. '*.conf'); repeat for more.
+        #[arg(long, value_name = "GLOB", value_parser = parse_glob, conflicts_with_all = ["poll", "schedule_only"])]
+        watch_only: Vec<glob::Pattern>,
+        /// Give up (and exit non-zero) if the directory cannot be re-watched within DURATION [default: 5m].
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        rewatch_timeout: Option<Duration>,
+        /// Hold at most N file events waiting; past that they're dropped, and the directory is compared once they stop [default: 4096].
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        event_queue_size: Option<u64>,
+        /// Skip the filesystem watcher and compare every INTERVAL (e.g. 30s) instead.
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+        poll: Option<Duration>,
+        /// Where filesystem events come from; falls back to poll if the backend can't watch.
+        #[arg(long, value_enum, value_name = "BACKEND", default_value = "auto", conflicts_with_all = ["poll", "schedule_only"])]
+        watch_backend: WatchBackend,
+        /// After an alert, hold back repeat alerts for the same files for this long.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        alert_cooldown: Option<Duration>,
+        /// Also run a full comparison on this schedule (e.g. 15m), even without events.
+        #[arg(long, value_name = "DURATION", value_parser = parse_interval)]
+        compare_every: Option<Duration>,
+        /// Delay the schedule's start by a random amount up to DURATION.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        jitter: Option<Duration>,
+        /// Don't watch for filesystem events; only run scheduled comparisons.
+        #[arg(long, action, conflicts_with = "poll")]
+        schedule_only: bool,
+        /// Emit an "alive" message through the log on this interval.
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_duration)]
+        heartbeat: Option<Duration>,
+        /// Also GET this URL on every heartbeat (e.g. a healthchecks.io check).
+        #[arg(long, value_name = "URL")]
+        heartbeat_url: Option<String>,
+        /// Take in each newer baseline published for the profile (see `accept --publish`) at the collector at URL, and compare against it right away.
+        #[arg(long, value_name = "URL")]
+        baseline_subscribe: Option<String>,
+        /// How often --baseline-subscribe asks for a newer baseline [default: 1m].
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_duration, requires = "baseline_subscribe")]
+        baseline_poll: Option<Duration>,
+        /// The collector's token, for --baseline-subscribe; prefer GUARDIAN_COLLECTOR_TOKEN, which keeps it out of `ps`.
+        #[arg(
+            long,
+            value_name = "TOKEN",
+            env = "GUARDIAN_COLLECTOR_TOKEN",
+            hide_env_values = true
+        )]
+        upload_token: Option<String>,
+        #[command(flatten)]
+        baseline_key: BaselineKeyArgs,
+        /// Hash this executable at startup and every INTERVAL (e.g. 1h), alerting critically if it changed.
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_duration)]
+        verify_install: Option<Duration>,
+        /// Serve Prometheus metrics at http://ADDR/metrics.
+        #[arg(long, value_name = "ADDR", conflicts_with = "all_profiles")]
+        metrics_listen: Option<SocketAddr>,
+        /// Serve a read-only JSON API (/status, /drift, /healthz) on ADDR.
+        #[arg(long, value_name = "ADDR", conflicts_with = "all_profiles")]
+        api_listen: Option<SocketAddr>,
+        /// Answer `status` and `trigger` on this socket, or named pipe on Windows [default with --daemon: the PID file with .sock].
+        #[arg(long, value_name = "PATH", conflicts_with = "all_profiles")]
+        control_socket: Option<PathBuf>,
+        /// Detach and run in the background (Unix only).
+        #[arg(long, action, conflicts_with = "all_profiles")]
+        daemon: bool,
+        /// PID file written by --daemon.
+        #[arg(long, value_name = "PATH", default_value = DEFAULT_PID_FILE)]
+        pid_file: PathBuf,
+    },
+    /// Stop a monitor started with --daemon.
+    Stop {
+        #[arg(long, value_name = "PATH", default_value = DEFAULT_PID_FILE)]
+        pid_file: PathBuf,
+    },
+    /// Report whether a monitor daemon is running.
+    #[command(after_long_help = manual::STATUS_HELP)]
+    Status {
+        #[arg(long, value_name = "PATH", default_value = DEFAULT_PID_FILE)]
+        pid_file: PathBuf,
+        /// Control socket to ask [default: the PID file with .sock].
+        #[arg(long, value_name = "PATH")]
+        control_socket: Option<PathBuf>,
+    },
+    /// Have a running monitor compare its directory now, and print what it found.
+    #[command(after_long_help = manual::TRIGGER_HELP)]
+    Trigger {
+        #[arg(long, value_name = "PATH", default_value = DEFAULT_PID_FILE)]
+        pid_file: PathBuf,
+        /// Control socket to ask [default: the PID file with .sock].
+        #[arg(long, value_name = "PATH")]
+        control_socket: Option<PathBuf>,
+        /// How long to wait for the comparison to finish.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "10m")]
+        timeout: Duration,
+    },
+    /// Show recorded drift history.
+    #[command(after_long_help = manual::HISTORY_HELP)]
+    History {
+        /// Only records at or after this time (e.g. 2024-05-01, '2024-05-01 14:30 +02:00', or 7d for a week ago).
+        #[arg(long, value_name = "TIME", value_parser = history::parse_when)]
+        since: Option<history::When>,
+        /// Only records at or before this time.
+        #[arg(long, value_name = "TIME", value_parser = history::parse_when)]
+        until: Option<history::When>,
+        /// Only records for paths matching this glob.
+        #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+        path: Option<glob::Pattern>,
+        /// Only records made by commands this user ran, as the effective user or through sudo.
+        #[arg(long, value_name = "NAME")]
+        user: Option<String>,
+        /// Show each recorded version of the matching files and what became of it.
+        #[arg(long, action, requires = "path")]
+        timeline: bool,
+        #[arg(long, value_enum, default_value = "text")]
+        format: history::OutputFormat,
+    },
+    /// Tracked files and bytes, the baseline's age, the last comparison and recent drift.
+    #[command(after_long_help = manual::STATS_HELP)]
+    Stats {
+        #[arg(long, value_enum, default_value = "text")]
+        format: history::OutputFormat,
+    },
+    /// Take the current content of drifted files into the snapshot.
+    #[command(after_long_help = manual::ACCEPT_HELP)]
+    Accept {
+        /// Files to accept, as shown in drift reports.
+        #[arg(value_name = "PATH", required_unless_present = "from_report", conflicts_with = "from_report", value_hint = clap::ValueHint::FilePath)]
+        paths: Vec<String>,
+        /// Accept the entries approved in this reviewed JSON drift report, refusing those changed since.
+        #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        from_report: Option<PathBuf>,
+        /// Also add a tag to the snapshot saying why, e.g. --tag reason=CHG-4432.
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+        /// Say why, e.g. a change ticket, in each file's drift history record.
+        #[arg(long, value_name = "TEXT")]
+        reason: Option<String>,
+        /// Then publish the snapshot to the collector at URL as the next version of the profile's baseline, for monitors with --baseline-subscribe.
+        #[arg(long, value_name = "URL")]
+        publish: Option<String>,
+        #[command(flatten)]
+        collector: CollectorArgs,
+        #[command(flatten)]
+        baseline_key: BaselineKeyArgs,
+    },
+    /// Write the snapshot as a manifest for other tools, such as `sha256sum -c`.
+    #[command(after_long_help = manual::EXPORT_HELP)]
+    Export {
+        /// Only the files under DIRECTORY, named relative to it [default: every file, named as recorded].
+        #[arg(value_name = "DIRECTORY", value_hint = clap::ValueHint::DirPath)]
         directory: Option<String>,
+        #[arg(long, value_enum, default_value = "sha256sums")]
+        format: ExportFormat,
+    },
+    /// Take a SHA256SUMS manifest, such as a vendor publishes, as the snapshot of its files.
+    #[command(after_long_help = manual::IMPORT_HELP)]
+    Import {
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        file: PathBuf,
+        /// Directory the manifest's names are relative to.
+        #[arg(long, value_name = "DIRECTORY", default_value = ".", value_hint = clap::ValueHint::DirPath)]
+        directory: String,
+        /// Label the snapshot, e.g. --tag vendor_release=1.4.2; repeat for more tags.
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+    },
+    /// List the snapshot and the archived snapshots, newest first.
+    #[command(after_long_help = manual::LIST_HELP)]
+    List {
+        /// Only snapshots with this tag; repeat to require several.
+        #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+        /// Flag the directories whose baseline is older than DURATION (e.g. 90d).
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        max_baseline_age: Option<Duration>,
+        #[arg(long, value_enum, default_value = "text")]
+        format: history::OutputFormat,
+    },
+    /// Check individual files against the snapshot, without scanning their directories.
+    #[command(after_long_help = manual::VERIFY_HELP)]
+    Verify {
+        /// Files to check, as recorded or relative to a snapshot directory.
+        #[arg(value_name = "PATH", required = true, value_hint = clap::ValueHint::FilePath)]
+        paths: Vec<String>,
+        #[arg(long, value_enum, default_value = "text")]
+        format: history::OutputFormat,
+    },
+    /// Check this executable against the digest it was built with, or the one given.
+    #[command(after_long_help = manual::VERIFY_INSTALL_HELP)]
+    VerifyInstall {
+        /// SHA-256 the executable should hash to, as sha256sum prints it [default: the one embedded at build time].
+        #[arg(long, value_name = "HASH")]
+        expected: Option<String>,
+        #[arg(long, value_enum, default_value = "text")]
+        format: history::OutputFormat,
+    },
+    /// Tolerate drift to a file until a given time, with the reason on record.
+    #[command(after_long_help = manual::EXCEPTION_HELP)]
+    Exception {
+        #[command(subcommand)]
+        action: ExceptionAction,
+    },
+    /// Record who owns a tracked file and what to expect of it, for drift reports and alerts.
+    #[command(after_long_help = manual::ANNOTATE_HELP)]
+    Annotate {
+        /// File as recorded in the snapshot, or relative to a snapshot directory.
+        #[arg(value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+        path: String,
+        /// Team or person who owns the file.
+        #[arg(long, value_name = "TEAM")]
+        owner: Option<String>,
+        /// What to expect of the file, e.g. when it changes.
+        #[arg(long, value_name = "TEXT")]
+        note: Option<String>,
+        /// Drop what the file's annotation says before setting --owner and --note.
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Show what is recorded against the snapshot's entries.
+    Show {
+        /// List every annotated file, flagging those the snapshot no longer has as stale.
+        #[arg(long, required = true)]
+        annotations: bool,
+        #[arg(long, value_enum, default_value = "text")]
+        format: history::OutputFormat,
+    },
+    /// Manage the hash cache scans take unchanged files' hashes from.
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Manage the email digest.
+    Digest {
+        #[command(subcommand)]
+        action: DigestAction,
+    },
+    /// Print a shell completion script to stdout.
+    #[command(after_long_help = manual::COMPLETIONS_HELP)]
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Write man pages for the tool and each subcommand (for packagers).
+    #[command(hide = true)]
+    Mangen {
+        #[arg(value_name = "OUT_DIR", value_hint = clap::ValueHint::DirPath)]
+        out_dir: PathBuf,
+    },
+    /// Inspect the config file.
+    #[command(after_long_help = manual::CONFIG_HELP)]
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Check that this host can run config-guardian, with hints for anything that can't.
+    #[command(after_long_help = manual::DOCTOR_HELP)]
+    Doctor {
+        /// Also check that each configured alert channel's server accepts a connection.
+        #[arg(long, action)]
+        network: bool,
+    },
+    /// Find what a crash or a full disk left in the state directory, and with --apply fix it.
+    #[command(after_long_help = manual::RECOVER_HELP)]
+    Recover {
+        /// Restore, remove and truncate as the report says; without it nothing is changed.
         #[arg(long, action)]
-        alert: bool,
+        apply: bool,
+        /// PID file of a monitor started with --daemon.
+        #[arg(long, value_name = "PATH", default_value = DEFAULT_PID_FILE)]
+        pid_file: PathBuf,
+    },
+    /// Collect snapshots and drift reports uploaded by other hosts.
+    #[command(after_long_help = manual::SERVE_HELP)]
+    Serve {
+        /// Address to listen on, e.g. 0.0.0.0:8080.
+        #[arg(long, value_name = "ADDR")]
+        listen: SocketAddr,
+        /// Directory to store each host's uploads in.
+        #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::DirPath)]
+        data_dir: PathBuf,
+        /// Token clients must send; prefer GUARDIAN_COLLECTOR_TOKEN, which keeps it out of `ps`.
+        #[arg(
+            long,
+            value_name = "TOKEN",
+            env = "GUARDIAN_COLLECTOR_TOKEN",
+            hide_env_values = true
+        )]
+        token: String,
+        /// Refuse uploads larger than this [default: 32M].
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_upload: Option<u64>,
+    },
+    /// Summarise what the snapshot tracks.
+    #[command(after_long_help = manual::REPORT_HELP)]
+    Report {
+        #[command(subcommand)]
+        action: ReportAction,
+    },
+    /// Inspect the built-in profiles.
+    Profiles {
+        #[command(subcommand)]
+        action: ProfilesAction,
+    },
+    /// Install, remove, or run monitor as a Windows service.
+    #[cfg(all(windows, feature = "windows-service"))]
+    Service {
+        #[command(subcommand)]
+        action: service::ServiceAction,
     },
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize logging
-    WriteLogger::init(
-        LevelFilter::Info,
-        Config::default(),
-        File::create("drift.log")?,
-    )?;
+#[derive(clap::Subcommand)]
+enum DigestAction {
+    /// Email everything queued since the last digest (for cron).
+    Send {
+        #[command(flatten)]
+        email: EmailArgs,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum CacheAction {
+    /// Remove the hash cache, so that the next scans read every file.
+    Clear,
+}
+
+#[derive(clap::Subcommand)]
+enum ExceptionAction {
+    /// Report drift to PATH as excepted, not drift, until TIME.
+    Add {
+        /// File as comparisons record it, or a glob of them.
+        #[arg(long, value_name = "PATH")]
+        path: String,
+        /// When the exception expires, e.g. 2024-07-01, '2024-07-01 18:00' or 2w from now.
+        #[arg(long, value_name = "TIME", value_parser = exception::parse_until)]
+        until: history::When,
+        /// Why the drift is expected, e.g. a change ticket.
+        #[arg(long, value_name = "TEXT")]
+        reason: String,
+    },
+    /// Withdraw the exception for PATH.
+    Remove {
+        #[arg(long, value_name = "PATH")]
+        path: String,
+    },
+    /// List the exceptions and when each expires.
+    List {
+        #[arg(long, value_enum, default_value = "text")]
+        format: history::OutputFormat,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ReportAction {
+    /// File counts, sizes, modification times and the largest files for each directory.
+    Inventory {
+        #[arg(long, value_enum, default_value = "text")]
+        format: report::ReportFormat,
+        /// Number of largest files to list per directory.
+        #[arg(long, value_name = "N", default_value_t = 5)]
+        top: usize,
+    },
+    /// Sets of tracked files with identical content, and the bytes they waste.
+    Duplicates {
+        #[arg(long, value_enum, default_value = "text")]
+        format: report::ReportFormat,
+        /// Ignore files smaller than this; by default only empty files are ignored.
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "1")]
+        min_size: u64,
+    },
+    /// The latest drift of each host that reports to a collector, from its data directory.
+    Fleet {
+        /// The collector's data directory, as given to `serve --data-dir`.
+        #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::DirPath)]
+        data_dir: PathBuf,
+        /// Flag hosts that sent no report within DURATION as missing.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1d")]
+        stale_after: Duration,
+        #[arg(long, value_enum, default_value = "text")]
+        format: report::FleetFormat,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ProfilesAction {
+    /// List the built-in profiles and the directory each one watches.
+    List,
+}
+
+#[derive(clap::Subcommand)]
+enum ConfigAction {
+    /// Parse the config file and report problems, without running anything.
+    Validate,
+    /// Print the settings in effect from the config file and environment,
+    /// with secrets masked.
+    Show,
+}
+
+fn init_logging(args: &LogArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let level = args.level().filter();
+    if level == LevelFilter::Off {
+        return Ok(());
+    }
+    let (logger, level): (Box<dyn log::Log>, LevelFilter) = match &args.log_target {
+        LogTarget::File => {
+            // JSON lines must not get simplelog's time and level prefix.
+            let config = match args.log_format {
+                LogFormat::Text => Config::default(),
+                LogFormat::Json => ConfigBuilder::new()
+                    .set_time_level(LevelFilter::Off)
+                    .set_max_level(LevelFilter::Off)
+                    .set_thread_level(LevelFilter::Off)
+                    .set_target_level(LevelFilter::Off)
+                    .set_location_level(LevelFilter::Off)
+                    .build(),
+            };
+            (
+                WriteLogger::new(
+                    level,
+                    config,
+                    log_file::LogFile::new(
+                        &args.log_file,
+                        args.log_max_size.map(|max_size| log_file::Rotation {
+                            max_size,
+                            keep: args.log_keep,
+                        }),
+                    ),
+                ),
+                level,
+            )
+        }
+        LogTarget::Syslog(addr) => (Box::new(syslog::SyslogLogger::connect(addr)?), level),
+        #[cfg(target_os = "linux")]
+        LogTarget::Journald => (Box::new(journald::JournaldLogger::connect()?), level),
+        #[cfg(not(target_os = "linux"))]
+        LogTarget::Journald => {
+            return Err("--log-target journald is only available on Linux".into());
+        }
+        // Informational records would drown out drift in the event log.
+        #[cfg(windows)]
+        LogTarget::Eventlog => (
+            Box::new(eventlog::EventLogLogger::open()?),
+            level.min(LevelFilter::Warn),
+        ),
+    };
+    match args.log_format {
+        LogFormat::Text => log::set_boxed_logger(logger)?,
+        LogFormat::Json => {
+            log::set_boxed_logger(Box::new(log_format::JsonMessages { inner: logger }))?
+        }
+    }
+    log::set_max_level(level);
     log::info!("Configuration Drift Detector started.");
+    Ok(())
+}
+
+/// Lower the process's priority for `--nice`, before any scan starts its
+/// threads, and log the limits scans run under.
+fn throttle_scans() {
+    let settings = config::settings();
+    let limits = settings.throttle;
+    if limits.is_none() {
+        return;
+    }
+    if limits.nice
+        && let Err(e) = throttle::lower_priority()
+    {
+        eprintln!(
+            "Warning: could not lower the process's priority for --nice: {}",
+            e
+        );
+        log::warn!(event = "nice_failed"; "Could not lower the process's priority: {}", e);
+    }
+    log::info!(
+        event = "scan_throttle",
+        jobs = settings.jobs,
+        io_throttle = limits.io,
+        cpu_limit = limits.cpu,
+        nice = limits.nice;
+        "Scans throttled: {} file(s) read at once, {}",
+        settings.jobs,
+        limits
+    );
+}
+
+fn main() {
+    // Read as the process starts, so that it records when it did.
+    invoker::current();
+    // A check plugin is UNKNOWN whatever went wrong, however early.
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) if e.use_stderr() && std::env::args_os().any(|arg| arg == "--nagios") => {
+            let error = e.to_string();
+            println!("{}", nagios::unknown(error.trim_start_matches("error: ")));
+            std::process::exit(nagios::UNKNOWN);
+        }
+        Err(e) => e.exit(),
+    };
+    if let Err(e) = run(&cli) {
+        if let Some(Commands::Compare { nagios: true, .. }) = &cli.command {
+            println!("{}", nagios::unknown(&e.to_string()));
+            std::process::exit(nagios::UNKNOWN);
+        }
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+/// Have Ctrl+C (and SIGTERM or SIGHUP) stop the scans between files, so
+/// that `snapshot` and `compare` fail without saving anything rather than
+/// being killed halfway through writing.
+fn cancel_scans_on_signal() {
+    std::thread::spawn(|| {
+        let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        else {
+            return;
+        };
+        runtime.block_on(monitor::cancel_on_signal(CancellationToken::new()));
+        cancel_scans();
+    });
+}
+
+/// The exit status for `error`, as documented in `manual::AFTER_HELP`.
+fn exit_code(error: &GuardianError) -> i32 {
+    match error {
+        GuardianError::Config(_)
+        | GuardianError::Other(_)
+        | GuardianError::TooManyFiles { .. }
+        | GuardianError::StaleBaseline { .. } => 2,
+        GuardianError::SnapshotMissing { .. } | GuardianError::ArchiveMissing { .. } => 3,
+        GuardianError::SnapshotCorrupt { .. } | GuardianError::SnapshotEntries { .. } => 4,
+        GuardianError::NotADirectory { .. }
+        | GuardianError::NoMatches { .. }
+        | GuardianError::Io { .. } => 5,
+        GuardianError::WatchLost { .. } | GuardianError::WatchFailed { .. } => 6,
+        GuardianError::Alert(_) => 7,
+        GuardianError::Strict { .. } => 8,
+        GuardianError::TimedOut { .. } => 9,
+        GuardianError::Interrupted { .. } => 130,
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), GuardianError> {
+    // Services start in System32 and set up their own working directory and logger.
+    #[cfg(all(windows, feature = "windows-service"))]
+    if let Some(Commands::Service { action }) = &cli.command {
+        return Ok(service::handle(action)?);
+    }
+
+    // Needs no config, state or log, so it works on a fresh install.
+    if let Some(Commands::Completions { shell }) = &cli.command {
+        clap_complete::generate(
+            *shell,
+            &mut Cli::command(),
+            "config-guardian",
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+    if let Some(Commands::Mangen { out_dir }) = &cli.command {
+        let pages = manual::generate(out_dir).map_err(GuardianError::io(out_dir))?;
+        println!("Wrote {} man pages to {}", pages, out_dir.display());
+        return Ok(());
+    }
+    if let Some(Commands::Config { action }) = &cli.command {
+        std::process::exit(config_command(
+            action,
+            cli.config.as_deref(),
+            cli.profile.as_deref(),
+        ));
+    }
+    let (config_path, mut config) =
+        config::load(cli.config.as_deref()).map_err(GuardianError::Config)?;
+    // As the file has it, for `monitor` to tell what changed when it's edited.
+    let loaded = config.clone();
+    if let Some(Commands::Profiles {
+        action: ProfilesAction::List,
+    }) = &cli.command
+    {
+        list_profiles(&config);
+        return Ok(());
+    }
+    if let Some(name) = &cli.profile {
+        profile::select(&mut config, name).map_err(GuardianError::Config)?;
+    }
+    if let Some(
+        Commands::Snapshot { scan, .. }
+        | Commands::Compare { scan, .. }
+        | Commands::Monitor { scan, .. },
+    ) = &cli.command
+        && scan.all_profiles
+    {
+        if let Some(Commands::Compare {
+            format: Some(CompareFormat::Sarif | CompareFormat::Junit),
+            ..
+        }) = &cli.command
+        {
+            return Err(GuardianError::Config(
+                "--all-profiles can't put several runs in one SARIF or JUnit report; pass --profile for each".into(),
+            ));
+        }
+        if let Some(Commands::Compare { nagios: true, .. }) = &cli.command {
+            return Err(GuardianError::Config(
+                "--all-profiles can't put several runs in one Nagios check; pass --profile for each"
+                    .into(),
+            ));
+        }
+        let together = matches!(cli.command, Some(Commands::Monitor { .. }));
+        std::process::exit(all_profiles::run(&config, together)?);
+    }
+    let overrides = Overrides::of(cli);
+    overrides.apply(&mut config);
+    if let Some(
+        Commands::Snapshot { scan, .. }
+        | Commands::Compare { scan, .. }
+        | Commands::Monitor { scan, .. },
+    ) = &cli.command
+    {
+        scan.apply(&mut config);
+    }
+    if let Some(
+        Commands::Compare {
+            baseline_exclude, ..
+        }
+        | Commands::Monitor {
+            baseline_exclude, ..
+        },
+    ) = &cli.command
+        && !baseline_exclude.is_empty()
+    {
+        config.baseline_exclude = baseline_exclude.clone();
+    }
+    let baseline_roots = match &cli.command {
+        Some(Commands::Compare {
+            directory,
+            scan,
+            against,
+            ..
+        }) => check_baseline(&mut config, directory, *against, scan)?,
+        Some(Commands::Monitor {
+            directory, scan, ..
+        }) => check_baseline(&mut config, directory, None, scan)?,
+        _ => BTreeMap::new(),
+    };
+    let mut settings = config.settings().map_err(GuardianError::Config)?;
+    settings.baseline_roots = baseline_roots;
+    if let Some(Commands::Compare { scope, .. }) = &cli.command {
+        settings.scope.clone_from(scope);
+    }
+    settings.trace = cli.verbose
+        && matches!(
+            cli.command,
+            Some(Commands::Snapshot { .. } | Commands::Compare { .. })
+        );
+    if cli.verbose
+        && matches!(
+            cli.command,
+            Some(Commands::Compare { .. } | Commands::Monitor { .. })
+        )
+    {
+        if settings.ignored.is_empty() {
+            eprintln!("Ignoring no metadata fields");
+        } else {
+            eprintln!("Ignoring metadata fields: {}", settings.ignored);
+        }
+    }
+    // Set on the command line, so the config file doesn't know them.
+    if matches!(cli.log.log_target, LogTarget::File) {
+        settings.add_own_file(&cli.log.log_file);
+    }
+    if let Some(Commands::Monitor { pid_file, .. }) = &cli.command {
+        settings.add_own_file(pid_file);
+        settings.add_own_file(&info_path(pid_file));
+    }
+    settings.quiet = matches!(
+        &cli.command,
+        Some(Commands::Compare {
+            format: Some(CompareFormat::Sarif | CompareFormat::Junit),
+            report_out: None,
+            ..
+        }) | Some(Commands::Compare { nagios: true, .. })
+    );
+    if let Some(Commands::Snapshot { timeout, .. } | Commands::Compare { timeout, .. }) =
+        &cli.command
+    {
+        settings.deadline = timeout.map(|timeout| (Instant::now() + timeout, timeout));
+        cancel_scans_on_signal();
+    }
+    if cli.no_redact {
+        if !std::io::stdout().is_terminal() {
+            return Err(GuardianError::Config(
+                "--no-redact only shows redacted names on a terminal".to_string(),
+            ));
+        }
+        settings.reveal_redacted = true;
+    }
+    config::install(settings);
 
-    let cli = Cli::parse();
+    // Reports on the log file rather than writing to it.
+    if let Some(Commands::Doctor { network }) = &cli.command {
+        let log_file = matches!(cli.log.log_target, LogTarget::File).then_some(&cli.log.log_file);
+        std::process::exit(doctor::run(
+            &config,
+            log_file.map(PathBuf::as_path),
+            *network,
+        ));
+    }
 
+    init_logging(&cli.log)?;
+    if matches!(
+        cli.command,
+        Some(Commands::Snapshot { .. } | Commands::Compare { .. } | Commands::Monitor { .. })
+    ) {
+        throttle_scans();
+    }
+
+    // A directory on the command line replaces the config file's list.
+    let directories = |directory: &Option<String>| match directory {
+        Some(dir) => vec![dir.clone()],
+        None if config.directories.is_empty() => vec![".".to_string()],
+        None => config.directories.clone(),
+    };
+    // Virtual files are scanned beside whatever directories are.
+    let with_virtual_files = |mut dirs: Vec<String>| {
+        for file in &config::settings().virtual_files {
+            if !dirs.contains(file) {
+                dirs.push(file.clone());
+            }
+        }
+        dirs
+    };
+
+    if cli.dry_run {
+        check_dry_run(&cli.command)?;
+    }
     match &cli.command {
-        Some(Commands::Snapshot { directory }) => {
-            let dir = directory.as_deref().unwrap_or(".");
-            if !is_valid_directory(dir) {
-                return Err("Provided path is not a valid directory.".into());
+        Some(Commands::Snapshot {
+            directory,
+            strict,
+            explain,
+            tags,
+            upload,
+            collector,
+            from_tar,
+            from_oci_dir,
+            strip_prefix,
+            ..
+        }) => {
+            let image = match (from_tar, from_oci_dir) {
+                (Some(tar), _) => Some(Image::Tar(tar.clone())),
+                (_, Some(layout)) => Some(Image::OciDir(layout.clone())),
+                (None, None) => None,
+            };
+            let dirs = match &image {
+                Some(_) => directories(directory),
+                None => with_virtual_files(directories(directory)),
+            };
+            match &image {
+                Some(_) if dirs.len() != 1 || is_pattern(&dirs[0]) || is_remote(&dirs[0]) => {
+                    return Err(GuardianError::Config(
+                        "--from-tar and --from-oci-dir take the snapshot of one local directory"
+                            .into(),
+                    ));
+                }
+                // The directory is where the image is mounted, which needn't
+                // be on this host.
+                Some(_) => {}
+                None if strip_prefix.is_some() => {
+                    return Err(GuardianError::Config(
+                        "--strip-prefix needs --from-tar or --from-oci-dir".into(),
+                    ));
+                }
+                None => {
+                    for dir in &dirs {
+                        check_target(dir)?;
+                    }
+                }
+            }
+            if let Some(path) = explain {
+                return explain_path(path, &dirs);
+            }
+            if cli.dry_run {
+                return snapshot_dry_run(&dirs, cli.verbose, *strict);
+            }
+            let upload = collector.upload(upload)?;
+            let tags = tags.iter().cloned().collect();
+            let scans = match &image {
+                Some(image) => {
+                    let prefix = strip_prefix.as_deref().unwrap_or(&dirs[0]);
+                    vec![image::take_snapshot(image, prefix, &dirs[0], &tags)?]
+                }
+                None => take_snapshots(&dirs, &tags)?,
+            };
+            let mut stats = ScanStats::default();
+            let mut warnings = Vec::new();
+            let mut volatile = Vec::new();
+            for (dir, scan) in dirs.iter().zip(scans) {
+                if let Err(e) = history::record_snapshot(dir, &scan.snapshot.entries) {
+                    eprintln!("Warning: could not record drift history: {}", e);
+                }
+                stats.add(&scan.stats);
+                warnings.extend(scan.warnings);
+                volatile.extend(
+                    scan.snapshot
+                        .entries
+                        .into_iter()
+                        .filter(|f| f.volatile)
+                        .map(|f| f.path),
+                );
             }
-            log::info!("Taking snapshot of directory: {}", dir);
-            let snapshot = take_snapshot(dir)?;
             println!(
-                "Snapshot taken and saved to snapshot.json ({} files)",
-                snapshot.len()
+                "Snapshot taken and saved to {} ({} files)",
+                config::settings().snapshot_file.display(),
+                stats.files_hashed
             );
+            println!("{}", stats);
+            if !volatile.is_empty() {
+                println!("Volatile, changes not reported as drift:");
+                for path in &volatile {
+                    println!("  {}", path);
+                }
+            }
+            if let Some(upload) = &upload {
+                let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+                upload.snapshot(&snapshot).map_err(|e| {
+                    GuardianError::Alert(format!("Could not upload the snapshot: {}", e))
+                })?;
+                println!("Uploaded to {} as {}", upload.url, upload.host);
+            }
+            report_warnings(&warnings, cli.verbose, *strict)?;
         }
-        Some(Commands::Compare { directory, alert }) => {
-            let dir = directory.as_deref().unwrap_or(".");
-            if !is_valid_directory(dir) {
-                return Err("Provided path is not a valid directory.".into());
+        Some(Commands::Compare {
+            directory,
+            strict,
+            alerts,
+            report_upload,
+            collector,
+            against,
+            staged,
+            scope,
+            fail_on,
+            format,
+            report_out,
+            nagios,
+            ..
+        }) => {
+            let format = match nagios {
+                true => CompareFormat::Text,
+                false => format.unwrap_or_else(CompareFormat::detect),
+            };
+            if matches!(format, CompareFormat::Text | CompareFormat::Gha) && report_out.is_some() {
+                return Err(GuardianError::Config(
+                    "--report-out needs --format sarif or junit".into(),
+                ));
+            }
+            // Patterns the snapshot was taken of are expanded again.
+            let dirs = match directory {
+                None if config.directories.is_empty() => recorded_patterns()
+                    .filter(|patterns| !patterns.is_empty())
+                    .unwrap_or_else(|| vec![".".to_string()]),
+                _ => directories(directory),
+            };
+            let dirs = with_virtual_files(dirs);
+            let settings = config::settings();
+            if let Some(dir) = dirs.iter().find(|dir| {
+                !is_pattern(dir)
+                    && !is_remote(dir)
+                    && !settings.is_virtual(dir)
+                    && !is_valid_directory(dir)
+            }) {
+                return Err(GuardianError::NotADirectory { path: dir.into() });
+            }
+            for dir in dirs.iter().filter(|dir| is_remote(dir)) {
+                remote::check(dir)?;
+            }
+            if !scope.is_empty() && dirs.iter().any(|dir| is_remote(dir)) {
+                return Err(GuardianError::Config(
+                    "--scope only narrows the scans of local targets".into(),
+                ));
+            }
+            if let Some(staged) = staged {
+                if dirs.len() != 1 || is_pattern(&dirs[0]) || is_remote(&dirs[0]) {
+                    return Err(GuardianError::Config(
+                        "--staged needs a single local directory to lay it over".into(),
+                    ));
+                }
+                if !is_valid_directory(staged) {
+                    return Err(GuardianError::NotADirectory {
+                        path: staged.into(),
+                    });
+                }
+            }
+            let mut channels = match staged {
+                Some(_) => None,
+                None => alerts
+                    .channels(&config.alerts)
+                    .map_err(GuardianError::Config)?,
+            };
+            alert::warn_if_log_only(channels.as_ref());
+            // Alerts leaving entries out point to the full report written here.
+            if let (Some(channels), Some(path)) = (&mut channels, report_out) {
+                channels
+                    .limits
+                    .report
+                    .get_or_insert_with(|| path.display().to_string());
+            }
+            let upload = collector.upload(report_upload)?;
+            let mut upload_failed = false;
+            let mut warnings = Vec::new();
+            let mut failing = 0;
+            let fail_on = fail_on.as_deref().unwrap_or(&DriftKind::ALL);
+            let mut reports = Vec::new();
+            let snapshot_file = &config::settings().snapshot_file;
+            let baseline = match against {
+                Some(back) => {
+                    let baseline = archive::Archive::of(snapshot_file).back(*back)?;
+                    let taken = Snapshot::load(&baseline)?.metadata.created;
+                    eprintln!(
+                        "Comparing against archived snapshot {}{}",
+                        baseline.display(),
+                        taken.map_or(String::new(), |t| format!(
+                            ", taken {}",
+                            utils::format_time(t)
+                        ))
+                    );
+                    baseline
+                }
+                None => snapshot_file.clone(),
+            };
+            for dir in &dirs {
+                check_baseline_age(dir, &baseline)?;
+                check_scope(&baseline, dir)?;
+            }
+            for dir in &dirs {
+                log::info!(
+                    "Comparing directory: {} (alert: {})",
+                    dir,
+                    channels.is_some()
+                );
+                let mut report = match staged {
+                    Some(staged) => config_guardian::compare_staged(dir, &baseline, staged)?,
+                    None => config_guardian::compare_against(
+                        dir,
+                        &baseline,
+                        channels.as_ref(),
+                        history::Source::Compare,
+                    )?,
+                };
+                // Like alerts, a failed upload doesn't stop the other directories.
+                if let Some(upload) = &upload
+                    && let Err(e) = upload.report(dir, &report)
+                {
+                    eprintln!("Warning: could not upload the report for {}: {}", dir, e);
+                    log::error!("Could not upload the drift report for {}: {}", dir, e);
+                    upload_failed = true;
+                }
+                failing += report
+                    .drifts
+                    .iter()
+                    .filter(|d| fail_on.contains(&d.kind))
+                    .count();
+                warnings.append(&mut report.warnings);
+                // What's written below may be uploaded as a CI artifact.
+                reports.push((dir.clone(), redact::report(&report)));
+            }
+            let output = match format {
+                CompareFormat::Text => None,
+                CompareFormat::Gha => {
+                    for (_, report) in &reports {
+                        for annotation in gha::annotations(report) {
+                            println!("{}", annotation);
+                        }
+                    }
+                    if let Err(e) = gha::write_summary(&gha::summary(&reports)) {
+                        eprintln!("Warning: could not write the step summary: {}", e);
+                    }
+                    None
+                }
+                CompareFormat::Sarif => {
+                    let drifts: Vec<Drift> = reports
+                        .iter()
+                        .flat_map(|(_, report)| report.drifts.iter().cloned())
+                        .collect();
+                    let log = sarif::log(&drifts);
+                    Some(serde_json::to_string_pretty(&log).expect("SARIF logs serialize to JSON"))
+                }
+                CompareFormat::Junit => Some(junit::document(&reports)),
+            };
+            match (output, report_out) {
+                (Some(output), Some(path)) => {
+                    std::fs::write(path, output + "\n").map_err(|source| GuardianError::Io {
+                        path: path.clone(),
+                        source,
+                    })?
+                }
+                (Some(output), None) => println!("{}", output),
+                (None, _) => {}
+            }
+            report_warnings(&warnings, cli.verbose, *strict)?;
+            if upload_failed {
+                return Err(GuardianError::Alert(
+                    "Some drift reports could not be uploaded".into(),
+                ));
+            }
+            if *nagios {
+                let (state, line) = nagios::check(&reports);
+                println!("{}", line);
+                std::process::exit(state.code());
+            }
+            if failing > 0 {
+                std::process::exit(1);
             }
-            log::info!("Comparing directory: {} (alert: {})", dir, alert);
-            compare_with_snapshot(dir, *alert)?;
         }
-        Some(Commands::Monitor { directory, alert }) => {
-            let dir = directory.as_deref().unwrap_or(".");
+        Some(Commands::Monitor {
+            directory,
+            alerts,
+            notify_desktop,
+            exit_drift,
+            max_events,
+            duration,
+            once,
+            watch_only,
+            no_dedup,
+            auto_accept,
+            rewatch_timeout,
+            event_queue_size,
+            poll,
+            watch_backend,
+            alert_cooldown,
+            compare_every,
+            jitter,
+            schedule_only,
+            heartbeat,
+            heartbeat_url,
+            baseline_subscribe,
+            baseline_poll,
+            upload_token,
+            baseline_key,
+            verify_install,
+            metrics_listen,
+            api_listen,
+            control_socket,
+            daemon,
+            pid_file,
+            scan,
+            ..
+        }) => {
+            let dirs = directories(directory);
+            let [dir] = dirs.as_slice() else {
+                return Err(GuardianError::Config(format!(
+                    "monitor watches one directory, but the config file lists {}; pass DIRECTORY",
+                    dirs.len()
+                )));
+            };
+            let dir = dir.as_str();
+            if is_remote(dir) {
+                return Err(GuardianError::Config(format!(
+                    "monitor can't watch {}, as nothing tells it when remote files change; run 'compare' on a schedule instead",
+                    dir
+                )));
+            }
             if !is_valid_directory(dir) {
-                return Err("Provided path is not a valid directory.".into());
+                return Err(GuardianError::NotADirectory { path: dir.into() });
+            }
+            check_baseline_age(dir, &config::settings().snapshot_file)?;
+            let settings = &config.monitor;
+            if let Some(problem) = settings.problems().into_iter().next() {
+                return Err(GuardianError::Config(format!("monitor: {}", problem)));
+            }
+            let compare_every = compare_every.or(settings.compare_every);
+            let schedule_only = *schedule_only || settings.schedule_only;
+            let heartbeat = heartbeat.or(settings.heartbeat);
+            let heartbeat_url = heartbeat_url.clone().or(settings.heartbeat_url.clone());
+            if compare_every.is_none() && (jitter.is_some() || schedule_only) {
+                return Err(GuardianError::Config(
+                    "--jitter and --schedule-only need --compare-every".into(),
+                ));
+            }
+            if heartbeat.is_none() && heartbeat_url.is_some() {
+                return Err(GuardianError::Config(
+                    "--heartbeat-url needs --heartbeat".into(),
+                ));
+            }
+            let subscribe = baseline_subscribe
+                .as_deref()
+                .map(|url| {
+                    baseline_key.baselines(url, upload_token.clone(), cli.profile.as_deref())
+                })
+                .transpose()?;
+            let pid_guard = if *daemon {
+                Some(daemonize(pid_file, dir, &cli.log.log_file)?)
+            } else {
+                None
+            };
+            let mut channels = alerts
+                .channels(&config.alerts)
+                .map_err(GuardianError::Config)?;
+            if *notify_desktop || settings.notify_desktop {
+                channels.get_or_insert_default().desktop = true;
+            }
+            alert::warn_if_log_only(channels.as_ref());
+            log::info!(
+                "Monitoring directory: {} (alert: {})",
+                dir,
+                channels.is_some()
+            );
+            let reload = match &config_path {
+                Some(path) => {
+                    let prepare = prepare_reload(
+                        cli.profile.clone(),
+                        overrides.clone(),
+                        scan.clone(),
+                        directory.clone(),
+                        alerts.clone(),
+                        *notify_desktop,
+                        *alert_cooldown,
+                    );
+                    Some(ConfigReload::new(path, loaded.clone(), prepare)?)
+                }
+                None => None,
+            };
+            let options = MonitorOptions {
+                alerts: channels,
+                rewatch_timeout: rewatch_timeout.unwrap_or_else(|| {
+                    Duration::from_secs(settings.rewatch_timeout.unwrap_or(300))
+                }),
+                // The file's schedule-only mode doesn't apply when --poll is given.
+                poll: poll.or(settings.poll.map(Duration::from_secs)),
+                watch_backend: *watch_backend,
+                alert_cooldown: alert_cooldown.or(settings.alert_cooldown),
+                compare_every,
+                jitter: jitter.or(settings.jitter).unwrap_or_default(),
+                schedule_only: schedule_only && poll.is_none(),
+                heartbeat,
+                heartbeat_url,
+                verify_install: verify_install.or(settings.verify_install),
+                metrics_listen: metrics_listen.or(settings.metrics_listen),
+                api_listen: api_listen.or(settings.api_listen),
+                control_socket: control_socket
+                    .clone()
+                    .or_else(|| daemon.then(|| control_path(pid_file))),
+                max_events: *max_events,
+                duration: *duration,
+                once: *once,
+                watch_only: watch_only.clone(),
+                dedup: !*no_dedup,
+                auto_accept: *auto_accept,
+                dry_run: cli.dry_run,
+                event_queue_size: event_queue_size
+                    .map(|size| size as usize)
+                    .or(settings.event_queue_size)
+                    .unwrap_or(MonitorOptions::default().event_queue_size),
+                subscribe,
+                subscribe_every: baseline_poll.unwrap_or(publish::SUBSCRIBE_INTERVAL),
+                debounce: settings
+                    .debounce
+                    .unwrap_or(MonitorOptions::default().debounce),
+                reload,
+                ..MonitorOptions::default()
+            };
+            let summary = Monitor::new(dir).options(options).run()?;
+            summary.print();
+            log::logger().flush();
+            drop(pid_guard);
+            // A bounded session is a test run, which drift fails.
+            let exit_drift = *exit_drift || *once || max_events.is_some() || duration.is_some();
+            if exit_drift && summary.drift_incidents > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Stop { pid_file }) => {
+            stop_daemon(pid_file, cli.dry_run)?;
+        }
+        Some(Commands::History {
+            since,
+            until,
+            path,
+            user,
+            timeline,
+            format,
+        }) => {
+            let resolve = |when: &Option<history::When>| {
+                when.map(history::When::resolve)
+                    .transpose()
+                    .map_err(GuardianError::Config)
+            };
+            let filter = history::HistoryFilter {
+                since: resolve(since)?,
+                until: resolve(until)?,
+                path: path.clone(),
+                user: user.clone(),
+            };
+            if *timeline {
+                history::show_timeline(&filter, *format)?;
+            } else {
+                history::show(&filter, *format)?;
+            }
+        }
+        Some(Commands::Serve {
+            listen,
+            data_dir,
+            token,
+            max_upload,
+        }) => collector::serve(
+            *listen,
+            data_dir,
+            token.clone(),
+            max_upload.unwrap_or(collector::DEFAULT_MAX_UPLOAD),
+        )?,
+        Some(Commands::Stats { format }) => stats::run(*format)?,
+        Some(Commands::Accept {
+            from_report: Some(report),
+            tags,
+            reason,
+            publish,
+            collector,
+            baseline_key,
+            ..
+        }) => {
+            let publish = publish_target(publish, collector, baseline_key, cli.profile.as_deref())?;
+            let review = accept_reviewed(report, tags, reason.as_deref(), cli.dry_run)?;
+            if let Some((baselines, host)) = &publish {
+                publish_baseline(baselines, host, cli.dry_run)?;
+            }
+            println!(
+                "{}{}.",
+                if cli.dry_run {
+                    "Dry run, nothing saved: "
+                } else {
+                    ""
+                },
+                review
+            );
+            if !review.stale.is_empty() || (cli.dry_run && review.applied == 0 && tags.is_empty()) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Accept {
+            paths,
+            tags,
+            reason,
+            publish,
+            collector,
+            baseline_key,
+            ..
+        }) => {
+            let publish = publish_target(publish, collector, baseline_key, cli.profile.as_deref())?;
+            let accepted = accept(paths, tags, reason.as_deref(), cli.dry_run)?;
+            if let Some((baselines, host)) = &publish {
+                publish_baseline(baselines, host, cli.dry_run)?;
+            }
+            if cli.dry_run && accepted == 0 && tags.is_empty() {
+                std::process::exit(1);
             }
-            log::info!("Monitoring directory: {} (alert: {})", dir, alert);
-            monitor_directory(dir, *alert)?;
         }
+        Some(Commands::List {
+            tags,
+            max_baseline_age,
+            format,
+        }) => list(
+            tags,
+            max_baseline_age.or(config::settings().max_baseline_age),
+            *format,
+        )?,
+        Some(Commands::Export { directory, format }) => export(directory.as_deref(), *format)?,
+        Some(Commands::Import {
+            file,
+            directory,
+            tags,
+        }) => {
+            let tags = tags.iter().cloned().collect();
+            let (dirs, entries) = manifest::import(file, directory, &tags, cli.dry_run)?;
+            println!(
+                "{} {} files from {} into {}, for {}",
+                if cli.dry_run {
+                    "Would import"
+                } else {
+                    "Imported"
+                },
+                entries,
+                file.display(),
+                config::settings().snapshot_file.display(),
+                dirs.join(", ")
+            );
+            if cli.dry_run && entries == 0 {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Report {
+            action: ReportAction::Inventory { format, top },
+        }) => report::run_inventory(*format, *top)?,
+        Some(Commands::Report {
+            action: ReportAction::Duplicates { format, min_size },
+        }) => report::run_duplicates(*format, *min_size)?,
+        Some(Commands::Report {
+            action:
+                ReportAction::Fleet {
+                    data_dir,
+                    stale_after,
+                    format,
+                },
+        }) => report::run_fleet(data_dir, *stale_after, *format)?,
+        Some(Commands::VerifyInstall { expected, format }) => {
+            let verified = install::verify(expected.as_deref())?;
+            match format {
+                history::OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::json!({
+                        "path": utils::path_text(&verified.path),
+                        "expected": verified.expected,
+                        "actual": verified.actual,
+                        "matches": verified.matches(),
+                    })
+                ),
+                history::OutputFormat::Text if verified.matches() => println!(
+                    "{}: OK (sha256 {})",
+                    verified.path.display(),
+                    verified.actual
+                ),
+                history::OutputFormat::Text => println!(
+                    "{}: CHANGED (expected {}, got {})",
+                    verified.path.display(),
+                    verified.expected,
+                    verified.actual
+                ),
+            }
+            if !verified.matches() {
+                log::error!(
+                    event = "install_changed",
+                    path = utils::path_text(&verified.path).as_str();
+                    "{} hashes to {} where {} was expected",
+                    verified.path.display(), verified.actual, verified.expected
+                );
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Verify { paths, format }) => {
+            std::process::exit(verify::verify(paths, *format)?);
+        }
+        Some(Commands::Exception { action }) => match action {
+            ExceptionAction::Add {
+                path,
+                until,
+                reason,
+            } => {
+                let until = until.resolve().map_err(GuardianError::Config)?;
+                let exception =
+                    exception::add(path, until, reason).map_err(GuardianError::Config)?;
+                println!(
+                    "Drift to {} is excepted until {}.",
+                    redact::shown(&exception.path),
+                    utils::format_time(exception.until)
+                );
+            }
+            ExceptionAction::Remove { path } => {
+                match exception::remove(path).map_err(GuardianError::Config)? {
+                    Some(_) => println!("Exception for {} removed.", redact::shown(path)),
+                    None => {
+                        return Err(GuardianError::Config(format!(
+                            "there is no exception for {}; see 'exception list'",
+                            path
+                        )));
+                    }
+                }
+            }
+            ExceptionAction::List { format } => {
+                exception::list(*format).map_err(GuardianError::Config)?
+            }
+        },
+        Some(Commands::Annotate {
+            path,
+            owner,
+            note,
+            clear,
+        }) => {
+            if owner.is_none() && note.is_none() && !clear {
+                return Err(GuardianError::Config(
+                    "annotate needs --owner, --note or --clear".into(),
+                ));
+            }
+            let (path, annotation) =
+                annotation::annotate(path, owner.as_deref(), note.as_deref(), *clear)?;
+            if annotation.owner.is_none() && annotation.note.is_none() {
+                println!("Annotation of {} cleared.", redact::shown(&path));
+            } else {
+                println!("Annotated {}: {}", redact::shown(&path), annotation);
+            }
+        }
+        Some(Commands::Show { format, .. }) => annotation::list(*format)?,
+        Some(Commands::Cache {
+            action: CacheAction::Clear,
+        }) => match cache::clear().map_err(GuardianError::Config)? {
+            Some(entries) => println!(
+                "Hash cache cleared ({} entries).",
+                utils::human_count(entries as u64)
+            ),
+            None => println!("No hash cache to clear."),
+        },
+        Some(Commands::Digest {
+            action: DigestAction::Send { email },
+        }) => {
+            let email = match email.config() {
+                Some(email) => email,
+                None => config
+                    .alerts
+                    .email
+                    .as_ref()
+                    .ok_or_else(|| {
+                        GuardianError::Config(
+                            "digest send needs --smtp-host, --email-from and --email-to".into(),
+                        )
+                    })?
+                    .email_config()
+                    .map_err(GuardianError::Config)?,
+            };
+            match digest::send_pending(&email).map_err(GuardianError::Alert)? {
+                0 => println!("No pending alerts; digest not sent."),
+                n => println!("Digest sent with {} alerts.", n),
+            }
+        }
+        Some(Commands::Status {
+            pid_file,
+            control_socket,
+        }) => {
+            let control = control_socket
+                .clone()
+                .unwrap_or_else(|| control_path(pid_file));
+            std::process::exit(daemon_status(pid_file, &control));
+        }
+        Some(Commands::Recover { apply, pid_file }) => {
+            std::process::exit(recover::run(pid_file, *apply));
+        }
+        Some(Commands::Trigger {
+            pid_file,
+            control_socket,
+            timeout,
+        }) => {
+            let control = control_socket
+                .clone()
+                .unwrap_or_else(|| control_path(pid_file));
+            std::process::exit(trigger_comparison(&control, *timeout));
+        }
+        Some(
+            Commands::Completions { .. }
+            | Commands::Mangen { .. }
+            | Commands::Config { .. }
+            | Commands::Doctor { .. }
+            | Commands::Profiles { .. },
+        ) => {
+            unreachable!("handled before logging is set up")
+        }
+        #[cfg(all(windows, feature = "windows-service"))]
+        Some(Commands::Service { .. }) => unreachable!("handled before logging is set up"),
         None => {
             println!("No command provided. Use --help for options.");
         }
@@ -95,114 +2309,806 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn take_snapshot(dir: &str) -> Result<Vec<FileHash>, Box<dyn std::error::Error>> {
-    let mut hashes = Vec::new();
-
-    let dir_entries = fs::read_dir(dir);
-    if let Ok(entries) = dir_entries {
-        if entries.count() == 0 {
-            println!("Warning: Directory {} is empty.", dir);
+/// Close a run with a count of `warnings`, or each of them if `verbose`;
+/// with `strict`, any warning fails the run.
+fn report_warnings(
+    warnings: &[ScanWarning],
+    verbose: bool,
+    strict: bool,
+) -> Result<(), GuardianError> {
+    let count = match warnings.len() {
+        0 => return Ok(()),
+        1 => "1 warning".to_string(),
+        n => format!("{} warnings", n),
+    };
+    if verbose {
+        for warning in warnings {
+            let shown = ScanWarning {
+                path: redact::shown(&warning.path).into_owned(),
+                ..warning.clone()
+            };
+            eprintln!("Warning: {}", shown);
         }
+        eprintln!("Completed with {}.", count);
+    } else {
+        eprintln!("Completed with {} (run with --verbose for details)", count);
+    }
+    if strict {
+        return Err(GuardianError::Strict {
+            warnings: warnings.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Where `accept --publish` publishes the profile's baseline, and as which
+/// host, checked before anything is accepted.
+fn publish_target(
+    url: &Option<String>,
+    collector: &CollectorArgs,
+    key: &BaselineKeyArgs,
+    profile: Option<&str>,
+) -> Result<Option<(publish::Baselines, String)>, GuardianError> {
+    let Some(upload) = collector.upload(url)? else {
+        return Ok(None);
+    };
+    let baselines = key.baselines(&upload.url, Some(upload.token), profile)?;
+    Ok(Some((baselines, upload.host)))
+}
+
+/// Publish the snapshot, as just accepted, as the next version of the
+/// profile's baseline, and record the version in it.
+fn publish_baseline(
+    baselines: &publish::Baselines,
+    host: &str,
+    dry_run: bool,
+) -> Result<(), GuardianError> {
+    if dry_run {
+        println!(
+            "Would publish the snapshot as the next {} baseline at {}",
+            baselines.profile, baselines.url
+        );
+        return Ok(());
+    }
+    let path = &config::settings().snapshot_file;
+    let mut snapshot = Snapshot::load(path)?;
+    let published = baselines
+        .publish(host, &snapshot)
+        .map_err(|e| GuardianError::Alert(format!("Could not publish the baseline: {}", e)))?;
+    snapshot.metadata.published = Some(published.version);
+    snapshot.save(path)?;
+    println!(
+        "Published the snapshot as version {} of the {} baseline at {}",
+        published.version, baselines.profile, baselines.url
+    );
+    log::info!(
+        event = "baseline_published",
+        profile = baselines.profile.as_str(),
+        version = published.version;
+        "Published version {} of the {} baseline to {}",
+        published.version, baselines.profile, baselines.url
+    );
+    Ok(())
+}
+
+/// The built-in profiles, then the config file's, which hide built-in
+/// ones of the same name.
+fn list_profiles(config: &config::Config) {
+    let mut rows: Vec<(String, String, String)> = profile::all()
+        .into_iter()
+        .filter(|p| !config.profiles.contains_key(p.name))
+        .map(|p| (p.name.to_string(), p.directories.join(", "), p.description))
+        .collect();
+    rows.extend(config.profiles.iter().map(|(name, p)| {
+        (
+            name.clone(),
+            p.directories.join(", "),
+            p.description
+                .clone()
+                .unwrap_or_else(|| "From the config file".to_string()),
+        )
+    }));
+    let width = rows
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, directories, description) in &rows {
+        println!("{:<width$}  {:<12}  {}", name, directories, description);
     }
+}
 
-    for entry in fs::read_dir(dir)? {
-        let entry = match entry {
-            Ok(e) => e,
+/// `config validate` and `config show`; returns the exit code.
+fn config_command(action: &ConfigAction, explicit: Option<&Path>, profile: Option<&str>) -> i32 {
+    let (path, mut config) = match config::load(explicit) {
+        Ok(found) => found,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    if let Some(name) = profile
+        && let Err(e) = profile::select(&mut config, name)
+    {
+        println!("{}", e);
+        return 1;
+    }
+    let source = match &path {
+        Some(path) => format!("{} and the environment", path.display()),
+        None => "the environment (no config file found)".to_string(),
+    };
+    match action {
+        ConfigAction::Validate => {
+            let problems = config.problems();
+            if problems.is_empty() {
+                println!("Config from {} is valid.", source);
+                return 0;
+            }
+            println!(
+                "Config from {} has {} problem{}:",
+                source,
+                problems.len(),
+                if problems.len() == 1 { "" } else { "s" }
+            );
+            for problem in &problems {
+                println!("  {}", problem);
+            }
+            1
+        }
+        ConfigAction::Show => match toml::to_string(&config.masked()) {
+            Ok(text) => {
+                println!("# Settings from {}", source);
+                print!("{}", text);
+                0
+            }
             Err(e) => {
-                eprintln!("Warning: Could not read directory entry: {}", e);
-                continue;
+                println!("Could not render the config: {}", e);
+                1
             }
-        };
+        },
+    }
+}
 
-        let path = entry.path();
-        if path.is_file() {
-            match fs::read(&path) {
-                Ok(content) => {
-                    let mut hasher = Sha256::new();
-                    hasher.update(&content);
-                    let hash = format!("{:x}", hasher.finalize());
-                    hashes.push(FileHash {
-                        path: path.to_string_lossy().into_owned(),
-                        hash,
-                    });
-                }
-                Err(e) => eprintln!("Warning: Could not read file {}: {}", path.display(), e),
+/// `export`: print the snapshot's entries as a manifest.
+fn export(dir: Option<&str>, format: ExportFormat) -> Result<(), GuardianError> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    match format {
+        ExportFormat::Sha256sums => {
+            let (sums, left_out) =
+                manifest::sums_of(&snapshot, dir).map_err(GuardianError::Config)?;
+            for sum in &sums {
+                println!("{}", manifest::line(sum));
+            }
+            if left_out > 0 {
+                eprintln!(
+                    "Warning: left out {} file{} whose hash isn't of all of the file (sampled, or never read)",
+                    left_out,
+                    if left_out == 1 { "" } else { "s" }
+                );
+            }
+            if let Some(dir) = dir
+                && sums.is_empty()
+                && left_out == 0
+            {
+                eprintln!("Warning: the snapshot has no files under {}", dir);
             }
         }
     }
+    Ok(())
+}
 
-    let json = serde_json::to_string_pretty(&hashes)?;
-    fs::write("snapshot.json", json)?;
-    Ok(hashes)
+fn parse_glob(value: &str) -> Result<glob::Pattern, String> {
+    glob::Pattern::new(value).map_err(|e| format!("invalid glob '{}': {}", value, e))
 }
 
-fn compare_with_snapshot(dir: &str, _alert: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let snapshot_data = fs::read_to_string("snapshot.json");
-    let snapshot: Vec<FileHash> = match snapshot_data {
-        Ok(data) => serde_json::from_str(&data)?,
-        Err(_) => return Err("No snapshot.json found. Run 'snapshot' command first.".into()),
+/// `snapshot --dry-run`: walk `dirs` as a snapshot would and print what it
+/// would hash. Exits 1 if nothing matched.
+/// The glob patterns the saved snapshot was taken of, if it loads.
+/// Have `config` scan as the baseline did, taking the options the snapshot
+/// recorded for the directories compared wherever it leaves them at their
+/// defaults, and say which it took. Options set otherwise are warned about,
+/// or with `--strict-options` fail the run.
+/// Check that the baseline suits the comparison about to be made: that it
+/// was taken of the directories compared, and with the options they are
+/// scanned with, taking up its options where the config leaves them at
+/// their defaults. Returns the directories whose entries it recorded under
+/// another path, for [`config::Settings::baseline_roots`].
+fn check_baseline(
+    config: &mut config::Config,
+    directory: &Option<String>,
+    against: Option<usize>,
+    scan: &ScanArgs,
+) -> Result<BTreeMap<String, String>, GuardianError> {
+    let snapshot_file = config
+        .snapshot
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(SNAPSHOT_FILE));
+    // A baseline that's missing or can't be read is for the comparison to
+    // report.
+    let baseline = match against {
+        Some(back) => match archive::Archive::of(&snapshot_file).back(back) {
+            Ok(path) => path,
+            Err(_) => return Ok(BTreeMap::new()),
+        },
+        None => snapshot_file,
     };
+    let Ok(metadata) = SnapshotMetadata::load(&baseline) else {
+        return Ok(BTreeMap::new());
+    };
+    // The directories the comparison will scan, as it works them out.
+    let dirs = match directory {
+        Some(dir) => vec![dir.clone()],
+        None if config.directories.is_empty() => {
+            let patterns: Vec<String> = metadata
+                .directories
+                .iter()
+                .filter(|dir| is_pattern(dir))
+                .cloned()
+                .collect();
+            if patterns.is_empty() {
+                vec![".".to_string()]
+            } else {
+                patterns
+            }
+        }
+        None => config.directories.clone(),
+    };
+    let roots = baseline_roots(&dirs, &metadata.directories, scan.allow_root_mismatch)?;
+    let recorded: Vec<&ScanOptions> = dirs
+        .iter()
+        .filter_map(|dir| metadata.options.get(roots.get(dir).unwrap_or(dir)))
+        .collect();
+    let Some(options) = recorded.first() else {
+        return Ok(roots);
+    };
+    if recorded.iter().any(|other| other != options) {
+        eprintln!(
+            "Warning: the directories compared were taken into the snapshot with different scan options, so the config's are used; see `list`."
+        );
+        return Ok(roots);
+    }
+    let inherited = config.inherit(options);
+    if inherited.taken != ScanOptions::default() {
+        eprintln!("Using the baseline's scan options: {}", inherited.taken);
+    }
+    if inherited.contradicted.is_empty() {
+        return Ok(roots);
+    }
+    let differences = inherited.contradicted.join("; ");
+    if scan.strict_options {
+        return Err(GuardianError::Config(format!(
+            "the scan options differ from the baseline's: {}",
+            differences
+        )));
+    }
+    eprintln!(
+        "Warning: the scan options differ from the baseline's: {}. Files may be reported as drifting that haven't changed; take a new snapshot, or scan as the baseline was.",
+        differences
+    );
+    Ok(roots)
+}
 
-    let current = take_snapshot(dir)?; // Re-uses take_snapshot to get current hashes
-
-    let mut drifts = Vec::new();
+/// How `monitor` builds what it takes up from its config file when it
+/// changes: as at startup, with the same flags on top.
+fn prepare_reload(
+    profile: Option<String>,
+    overrides: Overrides,
+    scan: ScanArgs,
+    directory: Option<String>,
+    alerts: AlertArgs,
+    notify_desktop: bool,
+    alert_cooldown: Option<Duration>,
+) -> reload::Prepare {
+    Box::new(move |mut config| {
+        if let Some(name) = &profile {
+            profile::select(&mut config, name)?;
+        }
+        overrides.apply(&mut config);
+        scan.apply(&mut config);
+        let roots =
+            check_baseline(&mut config, &directory, None, &scan).map_err(|e| e.to_string())?;
+        let mut settings = config.settings()?;
+        settings.baseline_roots = roots;
+        let mut channels = alerts.channels(&config.alerts)?;
+        if notify_desktop || config.monitor.notify_desktop {
+            channels.get_or_insert_default().desktop = true;
+        }
+        Ok(Reloaded {
+            settings,
+            alerts: channels,
+            alert_cooldown: alert_cooldown.or(config.monitor.alert_cooldown),
+            debounce: config
+                .monitor
+                .debounce
+                .unwrap_or(MonitorOptions::default().debounce),
+        })
+    })
+}
 
-    // Detect new or changed files
-    for curr in &current {
-        if let Some(prev) = snapshot.iter().find(|p| p.path == curr.path) {
-            if prev.hash != curr.hash {
-                drifts.push(format!("Changed: {}", curr.path));
+/// The directory the baseline, taken of `recorded`, holds each of `dirs`'
+/// entries under, where that isn't the directory as given: the same one by
+/// another path, or with `allow_mismatch`, the one directory it was taken
+/// of. A directory it has no entries for is otherwise an error, as all its
+/// files would be reported new.
+fn baseline_roots(
+    dirs: &[String],
+    recorded: &[String],
+    allow_mismatch: bool,
+) -> Result<BTreeMap<String, String>, GuardianError> {
+    let mut roots = BTreeMap::new();
+    // Snapshots saved as a bare list don't say what they were taken of.
+    if recorded.is_empty() {
+        return Ok(roots);
+    }
+    let local = |dir: &str| !is_pattern(dir) && !is_remote(dir);
+    let canonical = |dir: &str| std::fs::canonicalize(dir).ok();
+    for dir in dirs {
+        if recorded.contains(dir) || !local(dir) {
+            continue;
+        }
+        // A pattern below the directory's parents may take in its files,
+        // which are recorded by their paths as the directory's would be.
+        if recorded
+            .iter()
+            .any(|root| is_pattern(root) && Path::new(dir).starts_with(base_of(root)))
+        {
+            continue;
+        }
+        let here = canonical(dir);
+        if let Some(root) = recorded
+            .iter()
+            .filter(|root| local(root))
+            .find(|root| here.is_some() && canonical(root) == here)
+        {
+            roots.insert(dir.clone(), root.clone());
+            continue;
+        }
+        let taken = recorded.join(", ");
+        match (allow_mismatch, recorded) {
+            (true, [root]) if local(root) => {
+                eprintln!(
+                    "Warning: comparing {} with the baseline taken of {}, as --allow-root-mismatch asks.",
+                    dir, root
+                );
+                roots.insert(dir.clone(), root.clone());
+            }
+            (true, _) => {
+                return Err(GuardianError::Config(format!(
+                    "the baseline was taken of {}, so there's no telling which to compare {} with",
+                    taken, dir
+                )));
+            }
+            (false, _) => {
+                return Err(GuardianError::Config(format!(
+                    "the baseline was taken of {}, not {}; take a snapshot of {}, or pass --allow-root-mismatch to compare it with the baseline's directory",
+                    taken, dir, dir
+                )));
             }
-        } else {
-            drifts.push(format!("New: {}", curr.path));
         }
     }
+    Ok(roots)
+}
+
+fn recorded_patterns() -> Option<Vec<String>> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file).ok()?;
+    Some(
+        snapshot
+            .metadata
+            .directories
+            .into_iter()
+            .filter(|dir| is_pattern(dir))
+            .collect(),
+    )
+}
 
-    // Detect deleted files
-    for prev in &snapshot {
-        if !current.iter().any(|c| c.path == prev.path) {
-            drifts.push(format!("Deleted: {}", prev.path));
+fn snapshot_dry_run(dirs: &[String], verbose: bool, strict: bool) -> Result<(), GuardianError> {
+    let mut files = 0;
+    let mut bytes = 0;
+    let mut stats = ScanStats::default();
+    let mut warnings = Vec::new();
+    for dir in dirs {
+        let walk = walk_directory(dir)?;
+        for file in &walk.files {
+            if verbose {
+                println!(
+                    "  {} ({})",
+                    file.path,
+                    utils::human_size(file.metadata.len())
+                );
+            }
+            bytes += file.metadata.len();
         }
+        files += walk.files.len();
+        stats.add(&walk.stats);
+        warnings.extend(walk.warnings);
+    }
+    let mut summary = format!(
+        "Would hash {} files ({})",
+        utils::human_count(files as u64),
+        utils::human_size(bytes)
+    );
+    if let Some(skipped) = stats.skipped() {
+        summary.push_str("; would skip ");
+        summary.push_str(&skipped);
+    }
+    println!("{}", summary);
+    print_archive_plan(&config::settings().snapshot_file);
+    println!("Dry run: nothing was read and no snapshot was saved.");
+    report_warnings(&warnings, verbose, strict)?;
+    if files == 0 {
+        std::process::exit(1);
     }
+    Ok(())
+}
 
-    if drifts.is_empty() {
-        println!("No drift detected.");
-        log::info!("No configuration drift detected.");
-    } else {
-        println!("Drift detected:");
-        for drift in &drifts {
-            println!("  {}", drift);
+/// Refuse `--dry-run` for a command it can't preview, rather than run it
+/// for real.
+fn check_dry_run(command: &Option<Commands>) -> Result<(), GuardianError> {
+    const PREVIEWED: &str = "snapshot, accept, import, stop and monitor --auto-accept";
+    let refused = match command {
+        Some(Commands::Snapshot {
+            explain: Some(_), ..
+        }) => "snapshot --explain",
+        Some(Commands::Snapshot {
+            upload: Some(_), ..
+        }) => "snapshot --upload",
+        Some(Commands::Snapshot {
+            from_tar: Some(_), ..
+        }) => "snapshot --from-tar",
+        Some(Commands::Snapshot {
+            from_oci_dir: Some(_),
+            ..
+        }) => "snapshot --from-oci-dir",
+        Some(Commands::Snapshot { .. })
+        | Some(Commands::Accept { .. })
+        | Some(Commands::Import { .. })
+        | Some(Commands::Stop { .. })
+        | Some(Commands::Monitor {
+            auto_accept: true, ..
+        }) => return Ok(()),
+        Some(Commands::Monitor { .. }) => "monitor without --auto-accept",
+        _ => {
+            return Err(GuardianError::Config(format!(
+                "--dry-run only previews {}",
+                PREVIEWED
+            )));
         }
-        log::warn!("Configuration drift detected: {:?}", drifts);
+    };
+    Err(GuardianError::Config(format!(
+        "--dry-run can't preview {}; it previews {}",
+        refused, PREVIEWED
+    )))
+}
 
-        // Alert functionality is disabled as requested
-        // If you want to re-enable email alerts later, uncomment and configure send_email_alert()
+/// Print what saving over `snapshot_file` would do to its archive.
+fn print_archive_plan(snapshot_file: &Path) {
+    for action in archive::Archive::of(snapshot_file).plan(snapshot_file) {
+        println!("Would {}", action);
     }
+}
 
+/// `snapshot --explain`: each step deciding whether a snapshot of `dirs`
+/// would hash `path`. Exits 1 if it wouldn't.
+fn explain_path(path: &str, dirs: &[String]) -> Result<(), GuardianError> {
+    let given = Path::new(path);
+    let Some(name) = given.file_name() else {
+        return Err(GuardianError::Config(format!(
+            "{} is not a file path",
+            path
+        )));
+    };
+    let parent = match given.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let canonical = |p: &Path| std::fs::canonicalize(p).ok();
+    let matched = dirs
+        .iter()
+        .find(|dir| is_pattern(dir) && config_guardian::matches_pattern(dir, path));
+    let scanned = dirs.iter().find(|dir| {
+        Path::new(dir) == parent
+            || canonical(Path::new(dir)).is_some_and(|d| Some(d) == canonical(parent))
+    });
+    println!("{}", path);
+    let recorded = match (matched, scanned) {
+        (Some(pattern), _) => {
+            println!("  matched by pattern {}, recorded as {}", pattern, path);
+            given.to_path_buf()
+        }
+        (None, Some(dir)) => {
+            let recorded = Path::new(dir).join(name);
+            println!(
+                "  in scanned directory {}, recorded as {}",
+                dir,
+                recorded.display()
+            );
+            recorded
+        }
+        (None, None) => {
+            println!(
+                "  not scanned: snapshot only reads the files directly in {}",
+                dirs.join(", ")
+            );
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = std::fs::symlink_metadata(given) {
+        println!("  not found ({})", e);
+        std::process::exit(1);
+    }
+    let target = matched.or(scanned).map(String::as_str).unwrap_or(".");
+    if let Some(pattern) = config::settings().baseline_excluded_by(&recorded, &base_of(target)) {
+        println!(
+            "  matches baseline_exclude {}: left out of the baseline when compared",
+            pattern
+        );
+    }
+    let hashed = match config_guardian::classify(&recorded, given) {
+        Ok(metadata) => {
+            println!(
+                "  not excluded ({} patterns checked)",
+                config::settings().exclude.len()
+            );
+            println!("  regular file, {}", utils::human_size(metadata.len()));
+            let sampled = config::settings().sample_span(metadata.len());
+            if config::settings().is_virtual(&utils::path_text(&recorded)) {
+                println!(
+                    "  in virtual_files: hashed on one bounded read, and read on every comparison"
+                );
+            } else if let Some(span) = sampled {
+                println!(
+                    "  larger than sample_hash: sampled hash of the first and last {} and the length",
+                    utils::human_size(span)
+                );
+            } else if config::settings().is_chunked(given) {
+                println!("  matches chunked_hash: its content-defined chunks are recorded");
+            }
+            let reading = std::time::Instant::now();
+            match hash_file_as(given, sampled) {
+                Ok(hash) => {
+                    let took = Decision::Hashed(reading.elapsed());
+                    match config::settings().hash_display.show(Some(&hash)) {
+                        Some(hash) => println!("  {}: {}", took, hash),
+                        None => println!("  {}", took),
+                    }
+                    true
+                }
+                Err(e) => {
+                    println!("  {}", Decision::Unreadable(e.to_string()));
+                    false
+                }
+            }
+        }
+        Err(decision) => {
+            println!("  {}", decision);
+            false
+        }
+    };
+    let recorded = utils::path_text(&recorded);
+    match Snapshot::load(&config::settings().snapshot_file) {
+        Ok(snapshot) => match snapshot.get(&recorded) {
+            Some(entry) => {
+                let sampled = if entry.sampled.is_some() {
+                    " (sampled hash)"
+                } else {
+                    ""
+                };
+                match config::settings().hash_display.show(Some(&entry.hash)) {
+                    Some(hash) => println!("  in the saved snapshot with hash {}{}", hash, sampled),
+                    None => println!("  in the saved snapshot{}", sampled),
+                }
+            }
+            None => println!("  not in the saved snapshot"),
+        },
+        Err(GuardianError::SnapshotMissing { .. }) => println!("  no snapshot saved yet"),
+        Err(e) => return Err(e),
+    }
+    if !hashed {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
-fn monitor_directory(dir: &str, _alert: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let (tx, rx) = channel();
-    let mut watcher = RecommendedWatcher::new(tx, std::time::Duration::from_secs(1))?;
-    watcher.watch(Path::new(dir), RecursiveMode::NonRecursive)?;
-
-    println!("Monitoring {} for changes... (Press Ctrl+C to stop)", dir);
+/// A saved snapshot as `list` shows it.
+#[derive(serde::Serialize)]
+struct Listed {
+    path: PathBuf,
+    created: Option<chrono::DateTime<chrono::Utc>>,
+    files: usize,
+    tags: std::collections::BTreeMap<String, String>,
+    /// The directories of the current snapshot whose baseline is older than
+    /// `--max-baseline-age`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stale: Vec<Stale>,
+    /// The scan options each directory was taken with.
+    #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    options: std::collections::BTreeMap<String, ScanOptions>,
+    /// Who saved it, and how.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    saved_by: Option<invoker::Invoker>,
+}
 
-    let mut last_check = Instant::now();
-    const DEBOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+#[derive(serde::Serialize)]
+struct Stale {
+    dir: String,
+    age_days: u64,
+}
 
-    loop {
-        match rx.recv() {
-            Ok(Ok(event)) => {
-                if last_check.elapsed() >= DEBOUNCE_INTERVAL {
-                    println!("Change detected: {:?}", event);
-                    let _ = compare_with_snapshot(dir, _alert);
-                    last_check = Instant::now();
+fn list(
+    tags: &[(String, String)],
+    max_baseline_age: Option<Duration>,
+    format: history::OutputFormat,
+) -> Result<(), GuardianError> {
+    let snapshot_file = &config::settings().snapshot_file;
+    let archive = archive::Archive::of(snapshot_file);
+    let mut listed = Vec::new();
+    let paths = snapshot_file.is_file().then(|| snapshot_file.clone());
+    for path in paths.into_iter().chain(archive.list()) {
+        let snapshot = Snapshot::load(&path)?;
+        let metadata = snapshot.metadata;
+        if tags
+            .iter()
+            .all(|(key, value)| metadata.tags.get(key) == Some(value))
+        {
+            let mut stale = Vec::new();
+            // Only the current snapshot is compared against by default.
+            if let Some(max) = max_baseline_age
+                && path == *snapshot_file
+            {
+                for dir in &metadata.directories {
+                    let Some(taken) = metadata.taken_at(dir) else {
+                        continue;
+                    };
+                    let age = (chrono::Utc::now() - taken).to_std().unwrap_or_default();
+                    if age > max {
+                        stale.push(Stale {
+                            dir: dir.clone(),
+                            age_days: age.as_secs() / 86400,
+                        });
+                    }
+                }
+            }
+            listed.push(Listed {
+                path,
+                created: metadata.created,
+                files: snapshot.entries.len(),
+                tags: metadata.tags,
+                stale,
+                options: metadata.options,
+                saved_by: metadata.invoker,
+            });
+        }
+    }
+    match format {
+        history::OutputFormat::Json => match serde_json::to_string_pretty(&listed) {
+            Ok(json) => println!("{}", json),
+            Err(e) => return Err(GuardianError::Other(e.to_string())),
+        },
+        history::OutputFormat::Text if listed.is_empty() => println!("No snapshots found."),
+        history::OutputFormat::Text => {
+            for snapshot in &listed {
+                let created = snapshot
+                    .created
+                    .map_or_else(|| "unknown time".to_string(), utils::format_time);
+                let mut line = format!(
+                    "{}  {}  {} files",
+                    created,
+                    snapshot.path.display(),
+                    snapshot.files
+                );
+                if !snapshot.tags.is_empty() {
+                    line.push_str("  ");
+                    line.push_str(&format_tags(&snapshot.tags));
+                }
+                println!("{}", line);
+                if let Some(invoker) = &snapshot.saved_by {
+                    println!(
+                        "  saved by {} on {}: {}",
+                        invoker,
+                        invoker.host,
+                        invoker.command.join(" ")
+                    );
+                }
+                // Directories taken with the defaults go without saying.
+                for (dir, options) in snapshot
+                    .options
+                    .iter()
+                    .filter(|(_, options)| **options != ScanOptions::default())
+                {
+                    println!("  scan options for {}: {}", dir, options);
+                }
+                for stale in &snapshot.stale {
+                    println!("  stale: {} ({} days old)", stale.dir, stale.age_days);
                 }
             }
-            Ok(Err(e)) => println!("Watch error: {:?}", e),
-            Err(e) => println!("Channel error: {:?}", e),
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The webhook timeout of `args` when the file sets 20s and the
+    /// environment gives `env` for GUARDIAN_WEBHOOK_TIMEOUT.
+    fn webhook_timeout(file: &str, env: Option<&str>, args: &[&str]) -> Duration {
+        let mut config: config::Config = toml::from_str(file).unwrap();
+        config
+            .apply_env(|name| {
+                (name == "GUARDIAN_WEBHOOK_TIMEOUT")
+                    .then(|| env.map(str::to_string))
+                    .flatten()
+            })
+            .unwrap();
+        let cli = Cli::try_parse_from(
+            [
+                "config-guardian",
+                "compare",
+                "--webhook-url",
+                "http://localhost/hook",
+            ]
+            .iter()
+            .chain(args),
+        )
+        .unwrap();
+        let Some(Commands::Compare { alerts, .. }) = cli.command else {
+            unreachable!()
+        };
+        alerts
+            .channels(&config.alerts)
+            .unwrap()
+            .unwrap()
+            .webhook
+            .unwrap()
+            .timeout
+    }
+
+    #[test]
+    fn flag_beats_environment_beats_file_beats_default() {
+        let file = "[alerts]\ntimeout = \"20s\"";
+        assert_eq!(
+            webhook_timeout("", None, &[]),
+            config::DEFAULT_WEBHOOK_TIMEOUT
+        );
+        assert_eq!(webhook_timeout(file, None, &[]), Duration::from_secs(20));
+        assert_eq!(
+            webhook_timeout(file, Some("30s"), &[]),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            webhook_timeout(file, Some("30s"), &["--webhook-timeout", "40s"]),
+            Duration::from_secs(40)
+        );
+    }
+
+    #[test]
+    fn channel_flags_replace_configured_channels() {
+        let mut config: config::Config =
+            toml::from_str("[alerts]\nslack_webhook = \"https://file.example/hook\"").unwrap();
+        config
+            .apply_env(|name| {
+                (name == "GUARDIAN_SLACK_WEBHOOK").then(|| "https://env.example/hook".to_string())
+            })
+            .unwrap();
+        let slack = |args: &[&str]| {
+            let cli =
+                Cli::try_parse_from(["config-guardian", "compare"].iter().chain(args)).unwrap();
+            let Some(Commands::Compare { alerts, .. }) = cli.command else {
+                unreachable!()
+            };
+            alerts
+                .channels(&config.alerts)
+                .unwrap()
+                .unwrap()
+                .slack
+                .unwrap()
+                .url
+        };
+        assert_eq!(slack(&[]), "https://env.example/hook");
+        assert_eq!(
+            slack(&["--slack-webhook", "https://cli.example/hook"]),
+            "https://cli.example/hook"
+        );
+    }
 }
@@ -0,0 +1,292 @@
+//! SHA256SUMS manifests, as `sha256sum` writes and checks them and vendors
+//! publish them: `export --format sha256sums` writes the snapshot as one,
+//! and `import` takes one in as the snapshot of what a deployment should
+//! hold.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::Utc;
+
+use crate::archive::Archive;
+use crate::config::{self, HashAlgorithm};
+use crate::{FileHash, GuardianError, Snapshot, history, utils};
+
+/// One line of a manifest: the SHA-256 of the file at `path`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sum {
+    pub hash: String,
+    pub path: String,
+}
+
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == HashAlgorithm::Sha256.hex_len() && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The escaped form `sha256sum` gives names holding a backslash or a line
+/// break, which it marks by starting the line with a backslash.
+fn unescape(name: &str) -> Result<String, String> {
+    let mut plain = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            plain.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => plain.push('\\'),
+            Some('n') => plain.push('\n'),
+            Some('r') => plain.push('\r'),
+            _ => return Err(format!("invalid escape in '{}'", name)),
+        }
+    }
+    Ok(plain)
+}
+
+/// Parse the lines of a manifest, in `sha256sum`'s form (`HASH  NAME`, or
+/// `HASH *NAME` for binary mode) or its `--tag` form (`SHA256 (NAME) =
+/// HASH`). Blank lines and `#` comments are skipped.
+pub fn parse(text: &str) -> Result<Vec<Sum>, String> {
+    let mut sums: Vec<Sum> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || format!("line {}: not a SHA256SUMS line: {}", number + 1, line);
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (hash, name) = if let Some(tagged) = line.strip_prefix("SHA256 (") {
+            let (name, hash) = tagged.rsplit_once(") = ").ok_or_else(invalid)?;
+            (hash, name)
+        } else {
+            let (hash, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            let name = rest
+                .strip_prefix(' ')
+                .or_else(|| rest.strip_prefix('*'))
+                .ok_or_else(invalid)?;
+            (hash, name)
+        };
+        if !is_sha256(hash) || name.is_empty() {
+            return Err(invalid());
+        }
+        let path = if escaped {
+            unescape(name).map_err(|e| format!("line {}: {}", number + 1, e))?
+        } else {
+            name.to_string()
+        };
+        if sums.iter().any(|sum| sum.path == path) {
+            return Err(format!("line {}: {} is listed twice", number + 1, path));
+        }
+        sums.push(Sum {
+            hash: hash.to_ascii_lowercase(),
+            path,
+        });
+    }
+    Ok(sums)
+}
+
+/// `sum` as `sha256sum` writes it, escaping the name if it must.
+pub fn line(sum: &Sum) -> String {
+    if sum.path.contains(['\\', '\n', '\r']) {
+        let name = sum
+            .path
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        format!("\\{}  {}", sum.hash, name)
+    } else {
+        format!("{}  {}", sum.hash, sum.path)
+    }
+}
+
+/// The sums of `snapshot`'s entries: every entry with its path as
+/// recorded, or only those under `dir`, relative to it. Entries whose hash
+/// isn't the SHA-256 of all of the file, because it was sampled or the file
+/// was never read, are left out; the second value counts them.
+pub fn sums_of(snapshot: &Snapshot, dir: Option<&str>) -> Result<(Vec<Sum>, usize), String> {
+    if snapshot.metadata.hash != HashAlgorithm::Sha256 {
+        return Err(format!(
+            "the snapshot's hashes are {}, not the SHA-256 a SHA256SUMS manifest holds",
+            snapshot.metadata.hash
+        ));
+    }
+    let mut sums = Vec::new();
+    let mut left_out = 0;
+    for entry in &snapshot.entries {
+        let path = match dir {
+            Some(dir) => match Path::new(&entry.path).strip_prefix(dir) {
+                // Manifests name files with `/`, wherever they were made.
+                Ok(relative) => utils::path_text(relative).replace(std::path::MAIN_SEPARATOR, "/"),
+                Err(_) => continue,
+            },
+            None => entry.path.clone(),
+        };
+        if entry.sampled.is_some() || entry.hash.is_empty() {
+            left_out += 1;
+            continue;
+        }
+        sums.push(Sum {
+            hash: entry.hash.clone(),
+            path,
+        });
+    }
+    Ok((sums, left_out))
+}
+
+/// Take the manifest at `file` in as the snapshot of the directories its
+/// files are in, its names taken relative to `dir`. The entries carry only
+/// the hash, so comparisons read every file. As with `snapshot`, what the
+/// snapshot held for other directories is kept, and the snapshot replaced
+/// is archived. With `dry_run`, nothing is saved or archived, only printed.
+/// Returns the directories and the number of entries.
+pub fn import(
+    file: &Path,
+    dir: &str,
+    tags: &BTreeMap<String, String>,
+    dry_run: bool,
+) -> Result<(Vec<String>, usize), GuardianError> {
+    let settings = config::settings();
+    if settings.hash != HashAlgorithm::Sha256 {
+        return Err(GuardianError::Config(format!(
+            "A SHA256SUMS manifest holds SHA-256 hashes, but hash is set to {}",
+            settings.hash
+        )));
+    }
+    let text = std::fs::read_to_string(file).map_err(GuardianError::io(file))?;
+    let sums =
+        parse(&text).map_err(|e| GuardianError::Config(format!("{}: {}", file.display(), e)))?;
+    let entries: Vec<FileHash> = sums
+        .into_iter()
+        .map(|sum| FileHash {
+            // Named as a scan of the directory would name the file.
+            path: utils::path_text(
+                &Path::new(dir).join(sum.path.replace('/', std::path::MAIN_SEPARATOR_STR)),
+            ),
+            hash: sum.hash,
+            ..FileHash::default()
+        })
+        .collect();
+    let mut dirs: Vec<String> = Vec::new();
+    for entry in &entries {
+        let parent = Path::new(&entry.path)
+            .parent()
+            .map(utils::path_text)
+            .unwrap_or_default();
+        if !dirs.contains(&parent) {
+            dirs.push(parent);
+        }
+    }
+
+    let path = &settings.snapshot_file;
+    let mut snapshot = Snapshot::load(path).unwrap_or_default();
+    snapshot.entries.retain(|f| {
+        let parent = Path::new(&f.path).parent();
+        !dirs.iter().any(|dir| parent == Some(Path::new(dir)))
+    });
+    snapshot.entries.extend(entries.iter().cloned());
+    let metadata = &mut snapshot.metadata;
+    let now = Utc::now();
+    for dir in &dirs {
+        if !metadata.directories.contains(dir) {
+            metadata.directories.push(dir.clone());
+        }
+        metadata.stats.remove(dir);
+        metadata.taken.insert(dir.clone(), now);
+    }
+    metadata.created = Some(now);
+    metadata.version = env!("CARGO_PKG_VERSION").to_string();
+    metadata.hash = HashAlgorithm::Sha256;
+    metadata.tags = tags.clone();
+    if dry_run {
+        for action in Archive::of(path).plan(path) {
+            println!("Would {}", action);
+        }
+        return Ok((dirs, entries.len()));
+    }
+    if let Some(copy) = Archive::of(path).add(path)? {
+        log::info!("Archived the previous snapshot as {}", copy.display());
+    }
+    snapshot.save(path)?;
+    log::info!(
+        event = "import";
+        "Imported {} entries from {} into {}",
+        entries.len(),
+        file.display(),
+        path.display()
+    );
+    for dir in &dirs {
+        let covered: Vec<FileHash> = entries
+            .iter()
+            .filter(|f| Path::new(&f.path).parent() == Some(Path::new(dir)))
+            .cloned()
+            .collect();
+        if let Err(e) = history::record_snapshot(dir, &covered) {
+            eprintln!("Warning: could not record drift history: {}", e);
+        }
+    }
+    Ok((dirs, entries.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const B: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn manifests_parse_in_either_form() {
+        let text = format!(
+            "# vendor release 1.2\n{A}  etc/app.conf\n{B} *bin/tool\r\n\nSHA256 (lib/a (1).so) = {}\n\\{A}  odd\\\\name\\n\n",
+            B.to_uppercase()
+        );
+        let sums = parse(&text).unwrap();
+        let paths: Vec<&str> = sums.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["etc/app.conf", "bin/tool", "lib/a (1).so", "odd\\name\n"]
+        );
+        assert_eq!(sums[2].hash, B);
+        assert_eq!(line(&sums[0]), format!("{A}  etc/app.conf"));
+        assert_eq!(line(&sums[3]), format!("\\{A}  odd\\\\name\\n"));
+
+        assert!(parse("abc  short.conf").unwrap_err().starts_with("line 1:"));
+        assert!(parse(&format!("{A} x")).is_err());
+        assert!(
+            parse(&format!("{A}  x\n{B}  x\n"))
+                .unwrap_err()
+                .contains("listed twice")
+        );
+    }
+
+    #[test]
+    fn sums_are_relative_to_the_directory_and_whole_file_only() {
+        let entry = |path: &str, hash: &str, sampled| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            sampled,
+            ..FileHash::default()
+        };
+        let snapshot = Snapshot {
+            entries: vec![
+                entry("/srv/app/app.conf", A, None),
+                entry("/srv/app/big.img", B, Some(1 << 23)),
+                entry("/etc/other.conf", B, None),
+            ],
+            ..Snapshot::default()
+        };
+        let (sums, left_out) = sums_of(&snapshot, Some("/srv/app")).unwrap();
+        assert_eq!(
+            sums,
+            [Sum {
+                hash: A.into(),
+                path: "app.conf".into()
+            }]
+        );
+        assert_eq!(left_out, 1);
+        assert_eq!(sums_of(&snapshot, None).unwrap().0.len(), 2);
+    }
+}
@@ -0,0 +1,506 @@
+//! Long-form help: examples, exit status, environment and files. The texts
+//! are shown by `--help` and rendered into man pages by the hidden `mangen`
+//! subcommand, one page for the tool and one per subcommand.
+//!
+//! A text is a series of sections, each a heading line ending in `:`
+//! followed by lines indented by two spaces.
+
+use clap::CommandFactory;
+use roff::{Roff, roman};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::Cli;
+
+pub const BIN_NAME: &str = "config-guardian";
+
+pub const ABOUT: &str = "\
+Detect configuration drift in files.
+
+config-guardian records a hash of every file in a directory as a snapshot, \
+then reports files that were changed, added or deleted since. It can compare \
+once, from cron or CI, or watch the directory and report drift as it \
+happens, alerting through a webhook, Slack, Teams, Telegram, PagerDuty, \
+email or desktop notifications.
+
+Each setting is taken from its command-line flag, then its GUARDIAN_* \
+environment variable, then guardian.toml, then the built-in default.";
+
+pub const AFTER_HELP: &str = "\
+Examples:
+  config-guardian snapshot /etc/nginx
+  config-guardian compare /etc/nginx --slack-webhook https://hooks.slack.com/services/...
+  config-guardian monitor /etc/nginx --compare-every 15m --daemon
+  config-guardian history --since 7d
+
+Exit status:
+  0  Success. compare exits 0 when it finds no drift of a --fail-on
+     kind (all kinds unless given; none with --fail-on none).
+  1  Drift of a --fail-on kind in compare, or drift during a
+     monitor --exit-drift session.
+  2  Invalid command-line usage or settings, a directory holding more files
+     than --max-files, or an error not listed below.
+  3  There is no snapshot yet; run snapshot first.
+  4  The snapshot file is not valid JSON, or has broken entries (--strict-load).
+  5  A directory or file could not be read or written, or a glob pattern
+     given in place of a directory matched nothing.
+  6  monitor could not watch its directory, or lost it and could not watch it again.
+  7  An alert, digest or upload could not be delivered.
+  8  --strict was given and files were skipped or couldn't be read.
+  9  A scan took longer than --timeout; nothing was saved.
+  130  Interrupted by Ctrl+C or SIGTERM during a scan; nothing was saved.
+  status, verify, doctor and config validate have their own codes; see their help.
+
+Environment:
+  GUARDIAN_*                      Any setting, e.g. GUARDIAN_SNAPSHOT_FILE or
+                                  GUARDIAN_WEBHOOK_URL. Flags take precedence.
+  GUARDIAN_LOG_LEVEL              Least severe records to log (or GUARDIAN_LOG).
+  GUARDIAN_SMTP_USERNAME          SMTP credentials for email alerts.
+  GUARDIAN_SMTP_PASSWORD
+  GUARDIAN_TELEGRAM_TOKEN         Telegram bot token.
+  GUARDIAN_PAGERDUTY_ROUTING_KEY  PagerDuty Events API v2 routing key.
+  XDG_CONFIG_HOME                 Searched for config-guardian/guardian.toml.
+
+Files:
+  guardian.toml             Config file, in the working or user config directory.
+  snapshot.json             The snapshot compared against.
+  drift.log                 The log, unless --log-target or --log-file says otherwise.
+  drift-history.jsonl       Every drift found, for the history command.
+  compare-runs.json         The last comparison of each directory, for stats.
+  digest-pending.jsonl      Alerts waiting for the email digest.
+  pagerduty-incidents.json  Open PagerDuty incidents.
+  exceptions.json           Drift exceptions added with exception add.
+  annotations.json          Owners and notes added with annotate.
+  instance-id               This machine's identity, made up on the first run.
+  hash-cache.json           Hashes scans read, by inode, size and ctime.
+  guardian.pid              PID file of a monitor started with --daemon.";
+
+pub const SNAPSHOT_HELP: &str = "\
+Examples:
+  config-guardian snapshot /etc/nginx
+  config-guardian snapshot        # every directory in guardian.toml
+  config-guardian snapshot /etc/nginx --tag release=2.7.1 --tag change_ticket=CHG-4432
+  config-guardian snapshot /srv/conf --dry-run --verbose   # what would be hashed
+  config-guardian snapshot /etc/nginx --explain /etc/nginx/nginx.conf.bak
+  config-guardian snapshot '/etc/**/*.conf'    # the files a pattern matches
+
+The snapshot this one replaces is kept in snapshot.archive/ for
+compare --against.
+
+Exit status:
+  0  The snapshot was saved, even if some files were skipped.
+  1  --dry-run was given and no file would be hashed, or --explain was
+     given and the file wouldn't be.
+  8  --strict was given and files were skipped or couldn't be read.
+  9  The scans took longer than --timeout; the old snapshot was kept.
+  130  Interrupted during the scans; the old snapshot was kept.";
+
+pub const COMPARE_HELP: &str = "\
+Examples:
+  config-guardian compare /etc/nginx
+  config-guardian compare /etc/nginx --on-drift ./remediate.sh --alert-cooldown 1h
+  config-guardian compare /etc/nginx --against -2    # the snapshot two back
+  config-guardian compare /etc/nginx --fail-on changed,deleted   # new files don't fail CI
+  config-guardian compare /etc/nginx --staged build/etc/nginx   # would the deployment drift?
+  config-guardian compare /etc/nginx --alert --fail-on none   # alert, but exit 0 from cron
+  config-guardian compare /etc/nginx --format sarif > drift.sarif
+  config-guardian compare /etc/nginx --format junit --report-out artifacts/drift.xml
+  config-guardian compare /etc/nginx --format text   # no annotations in GitHub Actions
+  config-guardian compare /etc/nginx --nagios   # as a Nagios or Icinga check
+
+Exit status:
+  0  The comparison ran and found no drift of a --fail-on kind: none at
+     all without --fail-on, and whatever it found with --fail-on none.
+  1  Drift of a --fail-on kind was found, any kind without --fail-on.
+  2-5  It could not run; see config-guardian --help. That includes 2 for
+     a baseline older than --max-baseline-age with --require-fresh-baseline.
+  8  --strict was given and files were skipped or couldn't be read.
+  9  The scans took longer than --timeout.
+  130  Interrupted during the scans.
+
+With --nagios, the exit status is the check's state instead: 0 OK, 1
+WARNING, 2 CRITICAL, and 3 UNKNOWN for a comparison that couldn't run.";
+
+pub const MONITOR_HELP: &str = "\
+Examples:
+  config-guardian monitor /etc/nginx --notify-desktop
+  config-guardian monitor /etc/nginx --poll 30s --daemon
+  config-guardian monitor /srv/nfs/conf --watch-backend poll
+  config-guardian monitor /etc/nginx --compare-every 15m --api-listen 127.0.0.1:9101
+  config-guardian monitor /etc/nginx --duration 10m --max-events 100   # a bounded smoke test
+  config-guardian monitor /etc/myapp --once || ./react-to-change.sh
+  config-guardian monitor /etc/myapp --watch-only '*.conf' --watch-only '*.yaml'
+  config-guardian monitor ~/dev/conf --auto-accept   # record changes, don't guard
+  config-guardian monitor --all-profiles   # every [profile.NAME] of the config file
+  config-guardian monitor /etc/nginx --profile nginx --baseline-subscribe http://collector:8080
+
+--baseline-subscribe asks the collector for a newer baseline published for
+the profile (see accept --publish) at the start and every --baseline-poll,
+checks its signature with GUARDIAN_BASELINE_KEY, saves it as the baseline and
+compares against it right away. A version older than the one taken in last,
+or one the key didn't sign, is refused and alerted on as critical.
+
+Exit status:
+  0  Stopped by Ctrl+C, SIGTERM, the service manager, --max-events or
+     --duration, unless 1 applies.
+  1  --exit-drift, --once, --max-events or --duration was given and drift
+     occurred.
+  6  No watch backend could watch the directory, or it could not be
+     re-watched within --rewatch-timeout.
+  Other errors exit as listed by config-guardian --help.";
+
+pub const STATUS_HELP: &str = "\
+Exit status:
+  0  A monitor is running.
+  1  The PID file names a process that is no longer running, or the
+     monitor did not answer on its control socket.
+  3  No monitor is running.";
+
+pub const TRIGGER_HELP: &str = "\
+Examples:
+  config-guardian trigger                     # right after a deploy
+  config-guardian trigger --control-socket /run/guardian/nginx.sock
+
+The monitor runs a full comparison, reports its drift through its alert
+channels as any other, and answers with what it found. Triggers that
+arrive together share one comparison.
+
+Exit status:
+  0  The comparison ran, whether or not drift was found.
+  1  The comparison failed, or the monitor did not answer within --timeout.
+  3  No monitor is serving the control socket.";
+
+pub const HISTORY_HELP: &str = "\
+Examples:
+  config-guardian history --since 7d --path '*/sshd_config'
+  config-guardian history --path /etc/ssh/sshd_config --timeline
+  config-guardian history --since 2024-05-01 --until '2024-05-02 12:00' --format json
+  config-guardian history --since 1d --user alice   # also what alice ran through sudo
+  config-guardian history --utc --since '2024-05-01 14:30 +02:00'";
+
+pub const ACCEPT_HELP: &str = "\
+Examples:
+  config-guardian accept /etc/nginx/nginx.conf /etc/nginx/sites-enabled/old-site.conf
+  config-guardian accept /etc/nginx/nginx.conf --tag reason=CHG-4432
+  config-guardian accept /etc/nginx/nginx.conf --reason 'CHG-4432: raise worker_connections'
+  config-guardian accept /etc/nginx/nginx.conf --dry-run   # what would change
+  config-guardian accept --from-report reviewed.json
+  config-guardian accept /etc/nginx/nginx.conf --profile nginx --publish http://collector:8080
+
+--publish then sends the snapshot to the collector, signed with
+GUARDIAN_BASELINE_KEY, as the next version of the profile's baseline (the
+default profile's without --profile); monitors started with
+--baseline-subscribe take it in. A version another host published first is
+a conflict, refused by the collector: accept again to publish after it.
+
+--from-report takes a drift report as --on-drift hooks and the collector get
+it, reviewed offline. With entries marked \"approved\": true or false, only
+the approved ones are accepted; with none marked, all of them are. An
+approved file that no longer hashes as the report recorded changed after the
+review, and is refused as stale. The summary is recorded in the history.
+
+Exit status:
+  0  The files were accepted, or already matched the snapshot.
+  1  --dry-run was given and nothing would change, or --from-report refused a
+     file as stale.
+  7  --publish was given and the baseline could not be published.";
+
+pub const EXPORT_HELP: &str = "\
+Examples:
+  config-guardian export --format sha256sums > SHA256SUMS
+  config-guardian export /opt/app > SHA256SUMS && (cd /opt/app && sha256sum -c ../SHA256SUMS)
+
+Exit status:
+  0  The manifest was written.
+  2  The snapshot's hashes aren't SHA-256.
+  3  There is no snapshot yet; run snapshot first.";
+
+pub const IMPORT_HELP: &str = "\
+Each entry of the snapshot made holds only the hash, so comparisons read
+every file. What the snapshot held for directories the manifest doesn't
+name is kept, and the snapshot replaced is archived as snapshot does.
+
+Examples:
+  config-guardian import SHA256SUMS --directory /opt/app
+  config-guardian import SHA256SUMS --directory /opt/app --tag vendor_release=1.4.2
+  config-guardian compare /opt/app   # exactly what the vendor published?
+
+Exit status:
+  0  The manifest was imported.
+  1  --dry-run was given and the manifest names no file.
+  2  The manifest has a line that isn't a SHA256SUMS line, or hash isn't
+     sha256.
+  5  The manifest could not be read, or the snapshot written.";
+
+pub const LIST_HELP: &str = "\
+The current snapshot comes first, then the archived ones that
+compare --against can pick. Each line shows when the snapshot was
+taken, its file, how many files it holds and its tags.
+
+Examples:
+  config-guardian list
+  config-guardian list --tag release=2.7.1 --format json";
+
+pub const REPORT_HELP: &str = "\
+Reports read the snapshot only; nothing is scanned. fleet reads a
+collector's data directory instead.
+
+inventory lists, for each directory, the files tracked and their total
+size, the oldest and newest modification times, what the last scan left
+out (excluded, special and unreadable entries) and the largest files.
+
+duplicates lists sets of files with the same content, such as config
+copied where a symlink was meant, with the bytes each set wastes. Hard
+links to one file aren't counted as waste. Empty files are left out;
+--min-size leaves out smaller files too.
+
+fleet rolls up, for each host that uploads to a collector, its latest
+drift report of each directory: drift counted as changed, new, deleted,
+metadata or unreadable, the worst severity, the baseline's age and when
+it last reported. A host with no report within --stale-after is flagged
+missing. Report files that can't be parsed are passed over, with a
+warning, for the one before.
+
+Examples:
+  config-guardian report inventory
+  config-guardian report inventory --format csv > inventory.csv
+  config-guardian report inventory --format json --top 10
+  config-guardian report duplicates --min-size 1K
+  config-guardian report fleet --data-dir /var/lib/guardian --format html > fleet.html";
+
+pub const STATS_HELP: &str = "\
+Prints the number and total size of the files the snapshot tracks, how old
+the snapshot is, when the last comparison against it finished and how long
+it took, how many drift incidents the history recorded in the last 7 and 30
+days, and the 5 paths that drifted most often. A drift found again by later
+comparisons is one incident.
+
+Examples:
+  config-guardian stats
+  config-guardian stats --format json
+
+Exit status:
+  0  The stats were printed.
+  3  There is no snapshot yet; run snapshot first.";
+
+pub const VERIFY_HELP: &str = "\
+Each file is reported as OK, CHANGED (expected ..., got ...), MISSING or
+NOT IN BASELINE.
+
+Examples:
+  config-guardian verify /etc/ssh/sshd_config
+  config-guardian verify nginx.conf sites-enabled/default --format json
+
+Exit status:
+  0  Every file matches the snapshot.
+  1  A file changed, is missing, or isn't in the snapshot.
+  3  There is no snapshot yet; run snapshot first.
+  5  A file could not be read.";
+
+pub const VERIFY_INSTALL_HELP: &str = "\
+Hashes the running executable with SHA-256 and compares it with --expected,
+or else the digest embedded when it was built with GUARDIAN_EXPECTED_DIGEST
+set. The embedded digest's own bytes are hashed as zeros, so a release is
+built once without it, hashed with sha256sum, and built again with that
+digest, both stripped and linked without a build ID (see the README); a
+build with none embedded hashes as sha256sum hashes it.
+monitor --verify-install repeats the check while it runs.
+
+Examples:
+  config-guardian verify-install
+  config-guardian verify-install --expected \"$(cut -d' ' -f1 config-guardian.sha256)\"
+
+Exit status:
+  0  The executable hashes to the expected digest.
+  1  It doesn't.
+  2  There is no digest to compare with: pass --expected.
+  5  The executable could not be read.";
+
+pub const EXCEPTION_HELP: &str = "\
+While an exception is in force, compare and monitor report the drift it
+matches under Excepted: it isn't alerted on, doesn't fail --fail-on, and
+isn't recorded as drift. Once it expires, the drift is reported again, with
+a warning. Adding, removing and expiring an exception are recorded in the
+history with its reason.
+
+Examples:
+  config-guardian exception add --path /etc/app/flag.conf --until 2024-07-01 --reason \"CHG-1234 canary\"
+  config-guardian exception add --path '/etc/app/conf.d/*' --until 2w --reason \"CHG-1240 migration\"
+  config-guardian exception list
+  config-guardian exception remove --path /etc/app/flag.conf
+
+Files:
+  exceptions.json  The exceptions, in the working directory.";
+
+pub const ANNOTATE_HELP: &str = "\
+Drift to an annotated file is reported with its annotation, as in
+Changed: /etc/db.conf — owner: db-team — note: rotated monthly, and alerts
+carry it for routing. Annotations are kept apart from the snapshot, so
+accept and a new snapshot of the same files leave them as they are; show
+--annotations flags those whose file the snapshot no longer has.
+
+Examples:
+  config-guardian annotate /etc/db.conf --owner db-team --note \"rotated monthly\"
+  config-guardian annotate /etc/db.conf --note \"changes in maintenance windows\"
+  config-guardian annotate /etc/db.conf --clear
+  config-guardian show --annotations
+
+Files:
+  annotations.json  The annotations, in the working directory.";
+
+pub const CONFIG_HELP: &str = "\
+Examples:
+  config-guardian config validate
+  GUARDIAN_POLL=30 config-guardian --config /etc/guardian.toml config show
+
+Exit status:
+  0  The settings are valid (validate), or were printed (show).
+  1  The file or environment has problems, which are listed.";
+
+pub const DOCTOR_HELP: &str = "\
+Checks, each reported as PASS, WARN or FAIL with a hint:
+  state directory  The snapshot, log, history and digest can be written.
+  snapshot         The snapshot parses and holds well-formed hashes of the
+                   configured algorithm.
+  watcher          The native watch backend can watch a directory.
+  alerts           (--network) Each configured channel's server accepts a
+                   connection. Nothing is sent.
+  hashing          Hashing with the configured algorithm is not unusually slow.
+
+Exit status:
+  0  No check failed (warnings allowed).
+  1  At least one check failed.";
+
+pub const RECOVER_HELP: &str = "\
+Problems found:
+  corrupt snapshot  The snapshot doesn't parse. --apply moves it aside as
+                    SNAPSHOT.corrupt and restores the newest archived
+                    snapshot that does.
+  orphaned temp     A .partial or .tmp file left, untouched for a minute, by
+                    a write that never finished. --apply removes it.
+  stale lock        The PID file names a monitor that is no longer running.
+                    --apply removes it, with its .info file and socket.
+  torn history      The drift history's last line is a partial record.
+                    --apply cuts it off.
+
+Exit status:
+  0  No problems, or --apply fixed them all.
+  1  Problems were found and not fixed.";
+
+pub const SERVE_HELP: &str = "\
+Endpoints, each requiring Authorization: Bearer TOKEN:
+  GET  /hosts                      Each host, whether it has a snapshot, its report count.
+  PUT  /hosts/NAME/snapshot        Store the host's snapshot, replacing the last.
+  GET  /hosts/NAME/snapshot
+  POST /hosts/NAME/drift           Store a drift report; answers with its ID.
+  GET  /hosts/NAME/drift           The IDs of the host's reports, oldest first.
+  GET  /hosts/NAME/drift/ID
+  PUT  /baselines/PROFILE          Store the next version of a published baseline.
+  GET  /baselines/PROFILE          The latest version.
+
+Examples:
+  GUARDIAN_COLLECTOR_TOKEN=... config-guardian serve --listen 0.0.0.0:8080 --data-dir /var/lib/guardian
+  config-guardian snapshot /etc/nginx --upload http://collector:8080 --host-name web-01
+  config-guardian compare /etc/nginx --report-upload http://collector:8080 --host-name web-01
+
+Files:
+  DATA_DIR/NAME/snapshot.json   The host's latest snapshot.
+  DATA_DIR/NAME/drift/ID.json   Each uploaded drift report.
+  DATA_DIR/.baselines/PROFILE.json   The profile's latest published baseline.";
+
+pub const COMPLETIONS_HELP: &str = "\
+Examples:
+  config-guardian completions bash > /etc/bash_completion.d/config-guardian
+  config-guardian completions zsh > \"${fpath[1]}/_config-guardian\"
+  config-guardian completions fish > ~/.config/fish/completions/config-guardian.fish";
+
+/// Write `config-guardian.1` and a `config-guardian-<subcommand>.1` for
+/// each visible subcommand into `out_dir`, returning the number written.
+pub fn generate(out_dir: &Path) -> io::Result<usize> {
+    std::fs::create_dir_all(out_dir)?;
+    let mut cmd = Cli::command()
+        .name(BIN_NAME)
+        .bin_name(BIN_NAME)
+        .disable_help_subcommand(true);
+    cmd.build();
+
+    let subcommands: Vec<clap::Command> = cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(|sub| {
+            let name = format!("{}-{}", BIN_NAME, sub.get_name());
+            sub.clone().name(name.clone()).display_name(name)
+        })
+        .collect();
+    let pages: Vec<String> = subcommands
+        .iter()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+
+    write_page(&cmd, out_dir, &pages)?;
+    for sub in &subcommands {
+        write_page(sub, out_dir, &[BIN_NAME.to_string()])?;
+    }
+    Ok(subcommands.len() + 1)
+}
+
+fn write_page(cmd: &clap::Command, out_dir: &Path, see_also: &[String]) -> io::Result<()> {
+    let man = clap_mangen::Man::new(cmd.clone()).source(format!(
+        "{} {}",
+        BIN_NAME,
+        env!("CARGO_PKG_VERSION")
+    ));
+    let mut out = File::create(out_dir.join(man.get_filename()))?;
+    man.render_title(&mut out)?;
+    man.render_name_section(&mut out)?;
+    man.render_synopsis_section(&mut out)?;
+    man.render_description_section(&mut out)?;
+    if cmd.get_arguments().any(|arg| !arg.is_hide_set()) {
+        man.render_options_section(&mut out)?;
+    }
+    if cmd.get_subcommands().any(|sub| !sub.is_hide_set()) {
+        man.render_subcommands_section(&mut out)?;
+    }
+
+    let mut roff = Roff::default();
+    if let Some(text) = cmd.get_after_long_help() {
+        render_sections(&mut roff, &text.to_string());
+    }
+    roff.control("SH", ["SEE ALSO"]);
+    let references: Vec<String> = see_also.iter().map(|page| format!("{}(1)", page)).collect();
+    roff.text([roman(references.join(", "))]);
+    roff.to_writer(&mut out)?;
+    out.flush()
+}
+
+/// Each `Heading:` becomes a man section, with its lines kept as written.
+fn render_sections(roff: &mut Roff, text: &str) {
+    let mut in_section = false;
+    // Blank lines are only kept between lines of the same section.
+    let mut blank_lines = 0;
+    for line in text.lines() {
+        if let Some(heading) = line.strip_suffix(':')
+            && !line.starts_with(' ')
+        {
+            if in_section {
+                roff.control("fi", []);
+            }
+            roff.control("SH", [heading.to_uppercase().as_str()]);
+            roff.control("nf", []);
+            in_section = true;
+            blank_lines = 0;
+        } else if line.trim().is_empty() {
+            blank_lines += 1;
+        } else if in_section {
+            for _ in 0..blank_lines {
+                roff.text([roman("")]);
+            }
+            blank_lines = 0;
+            roff.text([roman(line.strip_prefix("  ").unwrap_or(line))]);
+        }
+    }
+    if in_section {
+        roff.control("fi", []);
+    }
+}
@@ -0,0 +1,158 @@
+//! Prometheus metrics for monitor mode.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::listener::Response;
+use crate::{Drift, DriftKind, DriftReport};
+
+#[derive(Default)]
+pub struct Metrics {
+    files_tracked: usize,
+    /// Score of the last comparison's drift.
+    drift_score: u64,
+    changed_total: u64,
+    truncated_total: u64,
+    populated_total: u64,
+    new_total: u64,
+    new_executable_total: u64,
+    new_setuid_total: u64,
+    deleted_total: u64,
+    unreadable_total: u64,
+    locked_total: u64,
+    mount_changed_total: u64,
+    attributes_changed_total: u64,
+    acl_changed_total: u64,
+    inode_replaced_total: u64,
+    link_count_changed_total: u64,
+    permissions_changed_total: u64,
+    type_changed_total: u64,
+    symlink_retargeted_total: u64,
+    symlink_escaped_total: u64,
+    baseline_modified_total: u64,
+    /// Unix time of the last comparison, in seconds.
+    last_comparison: Option<f64>,
+    comparison_duration: Duration,
+    watch_errors_total: u64,
+}
+
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+impl Metrics {
+    pub fn record_comparison(&mut self, report: &DriftReport, duration: Duration) {
+        self.files_tracked = report.files_tracked;
+        self.drift_score = report.score;
+        self.record_drifts(&report.drifts);
+        self.record_duration(duration);
+    }
+
+    /// Count drift found outside of a comparison.
+    pub fn record_drifts(&mut self, drifts: &[Drift]) {
+        for drift in drifts {
+            match drift.kind {
+                DriftKind::Changed => self.changed_total += 1,
+                DriftKind::Truncated => self.truncated_total += 1,
+                DriftKind::Populated => self.populated_total += 1,
+                DriftKind::New => self.new_total += 1,
+                DriftKind::NewExecutable => self.new_executable_total += 1,
+                DriftKind::NewSetuid => self.new_setuid_total += 1,
+                DriftKind::Deleted => self.deleted_total += 1,
+                DriftKind::Unreadable => self.unreadable_total += 1,
+                DriftKind::Locked => self.locked_total += 1,
+                DriftKind::MountChanged => self.mount_changed_total += 1,
+                DriftKind::AttributesChanged => self.attributes_changed_total += 1,
+                DriftKind::AclChanged => self.acl_changed_total += 1,
+                DriftKind::InodeReplaced => self.inode_replaced_total += 1,
+                DriftKind::LinkCountChanged => self.link_count_changed_total += 1,
+                DriftKind::PermissionsChanged => self.permissions_changed_total += 1,
+                DriftKind::TypeChanged => self.type_changed_total += 1,
+                DriftKind::SymlinkRetargeted => self.symlink_retargeted_total += 1,
+                DriftKind::SymlinkEscaped => self.symlink_escaped_total += 1,
+                DriftKind::BaselineModified => self.baseline_modified_total += 1,
+            }
+        }
+    }
+
+    /// Record a comparison that failed before producing a report.
+    pub fn record_duration(&mut self, duration: Duration) {
+        self.last_comparison = Some(chrono::Utc::now().timestamp_millis() as f64 / 1000.0);
+        self.comparison_duration = duration;
+    }
+
+    pub fn record_watch_error(&mut self) {
+        self.watch_errors_total += 1;
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP guardian_files_tracked Files found by the last comparison.\n");
+        out.push_str("# TYPE guardian_files_tracked gauge\n");
+        out.push_str(&format!("guardian_files_tracked {}\n", self.files_tracked));
+        out.push_str("# HELP guardian_drift_score Severity-weighted score of the drift the last comparison found.\n");
+        out.push_str("# TYPE guardian_drift_score gauge\n");
+        out.push_str(&format!("guardian_drift_score {}\n", self.drift_score));
+        out.push_str("# HELP guardian_drift_total Drift entries detected, by type.\n");
+        out.push_str("# TYPE guardian_drift_total counter\n");
+        for (kind, value) in [
+            ("changed", self.changed_total),
+            ("truncated", self.truncated_total),
+            ("populated", self.populated_total),
+            ("new", self.new_total),
+            ("new_executable", self.new_executable_total),
+            ("new_setuid", self.new_setuid_total),
+            ("deleted", self.deleted_total),
+            ("unreadable", self.unreadable_total),
+            ("locked", self.locked_total),
+            ("mount_changed", self.mount_changed_total),
+            ("attributes_changed", self.attributes_changed_total),
+            ("acl_changed", self.acl_changed_total),
+            ("inode_replaced", self.inode_replaced_total),
+            ("link_count_changed", self.link_count_changed_total),
+            ("permissions_changed", self.permissions_changed_total),
+            ("type_changed", self.type_changed_total),
+            ("symlink_retargeted", self.symlink_retargeted_total),
+            ("symlink_escaped", self.symlink_escaped_total),
+            ("baseline_modified", self.baseline_modified_total),
+        ] {
+            out.push_str(&format!(
+                "guardian_drift_total{{type=\"{}\"}} {}\n",
+                kind, value
+            ));
+        }
+        out.push_str(
+            "# HELP guardian_last_comparison_timestamp_seconds Unix time of the last comparison.\n",
+        );
+        out.push_str("# TYPE guardian_last_comparison_timestamp_seconds gauge\n");
+        out.push_str(&format!(
+            "guardian_last_comparison_timestamp_seconds {}\n",
+            self.last_comparison.unwrap_or(0.0)
+        ));
+        out.push_str(
+            "# HELP guardian_comparison_duration_seconds Duration of the last comparison.\n",
+        );
+        out.push_str("# TYPE guardian_comparison_duration_seconds gauge\n");
+        out.push_str(&format!(
+            "guardian_comparison_duration_seconds {}\n",
+            self.comparison_duration.as_secs_f64()
+        ));
+        out.push_str("# HELP guardian_watch_errors_total Times the filesystem watch was lost.\n");
+        out.push_str("# TYPE guardian_watch_errors_total counter\n");
+        out.push_str(&format!(
+            "guardian_watch_errors_total {}\n",
+            self.watch_errors_total
+        ));
+        out
+    }
+}
+
+/// Answer `GET /metrics`; anything else is a 404.
+pub fn handle(metrics: &SharedMetrics, path: &str) -> Response {
+    match path {
+        "/metrics" => Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: metrics.lock().unwrap_or_else(|e| e.into_inner()).render(),
+        },
+        _ => Response::not_found(),
+    }
+}
@@ -0,0 +1,1865 @@
+use chrono::{DateTime, Utc};
+use notify::EventKind;
+use notify::event::{MetadataKind, ModifyKind};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, mpsc};
+use tokio::time::MissedTickBehavior;
+use tokio_util::task::TaskTracker;
+
+pub use crate::watch::{WATCH_LIMIT_HINT, WatchBackend};
+pub use tokio_util::sync::CancellationToken;
+
+use crate::alert::{self, Alert, AlertChannels, Cooldown, Severity};
+use crate::api::{self, ApiState, EventHub, SharedApiState};
+use crate::config;
+use crate::control::{Control, Trigger};
+use crate::digest::{self, DigestTimer};
+use crate::drift::{self, DriftTracker, Resolved, Seen};
+use crate::heartbeat::{Heartbeat, HeartbeatStatus};
+use crate::history;
+use crate::install::InstallCheck;
+use crate::listener::HttpListener;
+use crate::metrics::{self, SharedMetrics};
+use crate::publish::{self, Baselines};
+use crate::redact;
+use crate::reload::{ConfigReload, ConfigWatch, Reloaded};
+use crate::seal::BaselineGuard;
+use crate::systemd::SystemdNotifier;
+use crate::utils::{self, directory_identity, is_valid_directory, random_u64};
+use crate::watch::{EventReceiver, Overflow, Watch};
+use crate::{
+    Drift, DriftKind, DriftReport, GuardianError, SnapshotMetadata, compare_against, snapshot_at,
+};
+
+/// A burst of events is compared once, this long after its first event,
+/// unless [`MonitorOptions::debounce`] says otherwise.
+const DEBOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+/// Filesystem events waiting to be handled at most, unless
+/// [`MonitorOptions::event_queue_size`] says otherwise. Past it, events are
+/// dropped and the session is in a storm until they stop.
+const EVENT_QUEUE_SIZE: usize = 4096;
+/// A storm is over once no event was dropped for this long.
+const STORM_QUIET: Duration = Duration::from_secs(5);
+/// How often schedules, cooldowns, heartbeats and the watchdog are serviced.
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+/// Alert deliveries in flight at once; further ones wait for a slot.
+const MAX_DELIVERIES: usize = 4;
+/// How long shutdown waits for alert deliveries still in flight.
+const DELIVERY_GRACE: Duration = Duration::from_secs(10);
+
+/// Settings for a monitor session.
+pub struct MonitorOptions {
+    /// Alert channels, or `None` when alerting is off.
+    pub alerts: Option<AlertChannels>,
+    pub rewatch_timeout: Duration,
+    /// Compare on a fixed schedule instead of using filesystem events.
+    pub poll: Option<Duration>,
+    /// Where filesystem events come from when not polling.
+    pub watch_backend: WatchBackend,
+    /// Window during which repeat alerts for the same paths are held back.
+    pub alert_cooldown: Option<Duration>,
+    /// Run a full comparison on this schedule regardless of events.
+    pub compare_every: Option<Duration>,
+    /// Upper bound of the random delay added to the schedule's start.
+    pub jitter: Duration,
+    /// Only run scheduled comparisons; don't watch for filesystem events.
+    pub schedule_only: bool,
+    pub heartbeat: Option<Duration>,
+    pub heartbeat_url: Option<String>,
+    /// Hash the executable at startup and on this interval, alerting when
+    /// it changed.
+    pub verify_install: Option<Duration>,
+    /// Serve Prometheus metrics on this address.
+    pub metrics_listen: Option<SocketAddr>,
+    /// Serve the JSON status API on this address.
+    pub api_listen: Option<SocketAddr>,
+    /// Answer `status` on this control socket (a named pipe on Windows).
+    pub control_socket: Option<PathBuf>,
+    /// Stop after handling this many filesystem events, or polls when
+    /// polling.
+    pub max_events: Option<u64>,
+    /// Stop once the session has run this long.
+    pub duration: Option<Duration>,
+    /// Stop at the first drift, once it was reported.
+    pub once: bool,
+    /// Only events on paths matching one of these, by full path or file
+    /// name, start a comparison; others are counted and passed over. Empty
+    /// lets every event through.
+    pub watch_only: Vec<glob::Pattern>,
+    /// Report only the drift that changed since the last comparison, and
+    /// the files that came back to the baseline, rather than all of it
+    /// every time.
+    pub dedup: bool,
+    /// Accept drift into the snapshot once it was reported, so that the
+    /// monitor records each change once rather than guarding against it.
+    pub auto_accept: bool,
+    /// Print what `auto_accept` would accept rather than accepting it.
+    pub dry_run: bool,
+    /// Compare against the snapshot saved here rather than the configured
+    /// snapshot file. Drift from it isn't recorded in the history.
+    pub baseline: Option<PathBuf>,
+    /// How long after the first event of a burst the burst is compared.
+    pub debounce: Duration,
+    /// Filesystem events waiting to be handled at most; further ones are
+    /// dropped rather than held in memory.
+    pub event_queue_size: usize,
+    /// Take in each newer version of the baseline published here as the
+    /// baseline, and compare against it right away.
+    pub subscribe: Option<Baselines>,
+    /// How often `subscribe` asks for a newer version.
+    pub subscribe_every: Duration,
+    /// Take up changes to this config file as it's edited.
+    pub reload: Option<ConfigReload>,
+}
+
+impl MonitorOptions {
+    /// The snapshot file comparisons are made against.
+    fn baseline_file(&self) -> PathBuf {
+        self.baseline
+            .clone()
+            .unwrap_or_else(|| config::settings().snapshot_file.clone())
+    }
+
+    /// Refuse a poll or comparison interval of zero, which would never let
+    /// the next poll or comparison come round.
+    fn check_intervals(&self) -> Result<(), GuardianError> {
+        for (name, interval) in [("poll", self.poll), ("compare_every", self.compare_every)] {
+            if interval.is_some_and(|interval| interval.is_zero()) {
+                return Err(GuardianError::Config(format!(
+                    "The {} interval must be greater than zero.",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        MonitorOptions {
+            alerts: None,
+            rewatch_timeout: Duration::from_secs(300),
+            poll: None,
+            watch_backend: WatchBackend::default(),
+            alert_cooldown: None,
+            compare_every: None,
+            jitter: Duration::ZERO,
+            schedule_only: false,
+            heartbeat: None,
+            heartbeat_url: None,
+            verify_install: None,
+            metrics_listen: None,
+            api_listen: None,
+            control_socket: None,
+            max_events: None,
+            duration: None,
+            once: false,
+            watch_only: Vec::new(),
+            dedup: true,
+            auto_accept: false,
+            dry_run: false,
+            baseline: None,
+            debounce: DEBOUNCE_INTERVAL,
+            event_queue_size: EVENT_QUEUE_SIZE,
+            subscribe: None,
+            subscribe_every: publish::SUBSCRIBE_INTERVAL,
+            reload: None,
+        }
+    }
+}
+
+/// Called with each comparison that found drift.
+type DriftCallback<'a> = Box<dyn FnMut(&DriftReport) + Send + 'a>;
+
+/// Watches a directory and compares it against the saved snapshot whenever
+/// it changes, alerting through the configured channels.
+///
+/// ```no_run
+/// use config_guardian::Monitor;
+/// use std::time::Duration;
+///
+/// Monitor::new("/etc/nginx")
+///     .poll(Duration::from_secs(30))
+///     .on_drift(|report| println!("{} files drifted", report.drifts.len()))
+///     .run()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// A program with work of its own to do can [`start`](Monitor::start) it
+/// on a thread of its own instead, and stop it when it's done:
+///
+/// ```no_run
+/// use config_guardian::Monitor;
+/// use std::time::Duration;
+///
+/// let monitor = Monitor::new("/etc/nginx")
+///     .baseline("/var/lib/guardian/nginx.json")
+///     .debounce(Duration::from_millis(500))
+///     .on_drift(|report| println!("{} files drifted", report.drifts.len()))
+///     .start()?;
+/// // ...
+/// let summary = monitor.stop()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Monitor {
+    dir: String,
+    options: MonitorOptions,
+    on_drift: Option<DriftCallback<'static>>,
+}
+
+impl Monitor {
+    pub fn new(dir: impl Into<String>) -> Self {
+        Monitor {
+            dir: dir.into(),
+            options: MonitorOptions::default(),
+            on_drift: None,
+        }
+    }
+
+    /// Replace every setting at once.
+    pub fn options(mut self, options: MonitorOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn alerts(mut self, alerts: AlertChannels) -> Self {
+        self.options.alerts = Some(alerts);
+        self
+    }
+
+    /// Compare on this schedule instead of using filesystem events. An
+    /// `interval` of zero fails the session with [`GuardianError::Config`].
+    pub fn poll(mut self, interval: Duration) -> Self {
+        self.options.poll = Some(interval);
+        self
+    }
+
+    /// Stop after handling `max` filesystem events, or polls when polling.
+    pub fn max_events(mut self, max: u64) -> Self {
+        self.options.max_events = Some(max);
+        self
+    }
+
+    /// Stop once the session has run for `duration`.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.options.duration = Some(duration);
+        self
+    }
+
+    /// Stop at the first drift, once it was reported, as a script waiting
+    /// for a change would.
+    pub fn once(mut self) -> Self {
+        self.options.once = true;
+        self
+    }
+
+    /// Compare against the snapshot saved at `path` rather than the
+    /// configured snapshot file.
+    pub fn baseline(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.baseline = Some(path.into());
+        self
+    }
+
+    /// Compare a burst of events this long after its first event, rather
+    /// than 2s after.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.options.debounce = debounce;
+        self
+    }
+
+    /// Hold at most `size` filesystem events waiting to be handled, rather
+    /// than 4096.
+    pub fn event_queue_size(mut self, size: usize) -> Self {
+        self.options.event_queue_size = size;
+        self
+    }
+
+    /// Call `callback` with the report of each comparison that finds drift,
+    /// after alerts were sent. It's called on the monitor's thread.
+    pub fn on_drift(mut self, callback: impl FnMut(&DriftReport) + Send + 'static) -> Self {
+        self.on_drift = Some(Box::new(callback));
+        self
+    }
+
+    /// Monitor on a thread of its own until the returned handle is stopped
+    /// or dropped, or the session ends by itself. Signals are left to the
+    /// caller.
+    pub fn start(self) -> Result<MonitorHandle, GuardianError> {
+        if !is_valid_directory(&self.dir) {
+            return Err(GuardianError::NotADirectory {
+                path: self.dir.clone().into(),
+            });
+        }
+        self.options.check_intervals()?;
+        let shutdown = CancellationToken::new();
+        let stop = shutdown.clone();
+        let thread = std::thread::Builder::new()
+            .name(format!("monitor {}", self.dir))
+            .spawn(move || self.block_on(stop, false))
+            .map_err(|e| GuardianError::Other(format!("Could not start the monitor: {}", e)))?;
+        Ok(MonitorHandle {
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// Monitor until Ctrl+C or, on Unix, SIGTERM or SIGHUP.
+    pub fn run(self) -> Result<SessionSummary, GuardianError> {
+        self.block_on(CancellationToken::new(), true)
+    }
+
+    /// Monitor until `shutdown` is cancelled by the caller's shutdown
+    /// mechanism.
+    pub fn run_until(self, shutdown: CancellationToken) -> Result<SessionSummary, GuardianError> {
+        self.block_on(shutdown, false)
+    }
+
+    /// The session runs on a runtime of its own, so callers don't need one.
+    fn block_on(
+        self,
+        shutdown: CancellationToken,
+        signals: bool,
+    ) -> Result<SessionSummary, GuardianError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| GuardianError::Other(format!("Could not start the monitor: {}", e)))?;
+        runtime.block_on(async {
+            if signals {
+                tokio::spawn(cancel_on_signal(shutdown.clone()));
+            }
+            monitor_until(&self.dir, &self.options, shutdown, self.on_drift).await
+        })
+    }
+}
+
+impl fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Monitor")
+            .field("dir", &self.dir)
+            .field("poll", &self.options.poll)
+            .field("on_drift", &self.on_drift.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A monitor running on a thread of its own, from [`Monitor::start`].
+/// Dropping it stops the monitor and waits for it, as [`stop`](Self::stop)
+/// does, so a monitor never outlives its handle.
+pub struct MonitorHandle {
+    shutdown: CancellationToken,
+    thread: Option<std::thread::JoinHandle<Result<SessionSummary, GuardianError>>>,
+}
+
+impl MonitorHandle {
+    /// Stop the monitor, giving alerts in flight the shutdown grace period,
+    /// and return the session's summary, or the error that ended it.
+    pub fn stop(mut self) -> Result<SessionSummary, GuardianError> {
+        self.shutdown.cancel();
+        self.join()
+    }
+
+    /// Wait for the session to end by itself, as with
+    /// [`once`](Monitor::once) or [`duration`](Monitor::duration).
+    pub fn wait(mut self) -> Result<SessionSummary, GuardianError> {
+        self.join()
+    }
+
+    /// Whether the session has ended, by itself or with an error.
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    fn join(&mut self) -> Result<SessionSummary, GuardianError> {
+        match self.thread.take().map(|thread| thread.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(GuardianError::Other("The monitor thread panicked".into())),
+            None => Err(GuardianError::Other(
+                "The monitor was already stopped".into(),
+            )),
+        }
+    }
+}
+
+impl Drop for MonitorHandle {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for MonitorHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MonitorHandle")
+            .field("finished", &self.is_finished())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Fixed-rate schedule for periodic comparisons. Ticks missed while a
+/// comparison was still running are skipped rather than run back to back.
+struct Scheduler {
+    interval: Duration,
+    next_run: Instant,
+}
+
+impl Scheduler {
+    fn new(interval: Duration, jitter: Duration) -> Self {
+        let offset = if jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(random_u64() % jitter.as_millis().max(1) as u64)
+        };
+        log::info!(
+            "Scheduled comparisons every {:?}, first in {:?}",
+            interval,
+            interval + offset
+        );
+        Scheduler {
+            interval,
+            next_run: Instant::now() + interval + offset,
+        }
+    }
+
+    fn due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_run {
+            return false;
+        }
+        self.next_run += self.interval;
+        let mut skipped = 0;
+        while self.next_run <= now {
+            self.next_run += self.interval;
+            skipped += 1;
+        }
+        if skipped > 0 {
+            log::warn!(
+                "Skipped {} scheduled comparison(s) while the previous one was still running",
+                skipped
+            );
+        }
+        true
+    }
+}
+
+/// A monitor's subscription to the baselines published for its profile.
+struct Subscription {
+    baselines: Baselines,
+    next_check: Instant,
+    /// The version the baseline is at, if it is one.
+    applied: Option<u64>,
+    /// What kept the latest version out at the last check, which is only
+    /// alerted on again once it changes.
+    failing: Option<String>,
+}
+
+/// Statistics collected over the lifetime of a monitor session.
+#[derive(Debug)]
+pub struct SessionSummary {
+    started: Instant,
+    events: u64,
+    /// Events passed over for `watch_only`.
+    filtered_events: u64,
+    /// Events dropped for want of room in the queue.
+    dropped_events: u64,
+    pub drift_incidents: u64,
+    paths: BTreeSet<String>,
+    /// Paths drifting as of the last comparison, the longest drifting
+    /// first.
+    drifting: Vec<(String, Seen)>,
+    /// Paths that drifted and came back to the baseline during the session.
+    resolved: Vec<Resolved>,
+}
+
+impl SessionSummary {
+    fn new() -> Self {
+        SessionSummary {
+            started: Instant::now(),
+            events: 0,
+            filtered_events: 0,
+            dropped_events: 0,
+            drift_incidents: 0,
+            paths: BTreeSet::new(),
+            drifting: Vec::new(),
+            resolved: Vec::new(),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Session summary:");
+        println!("  Uptime: {}s", self.started.elapsed().as_secs());
+        println!("  Events seen: {}", self.events);
+        if self.filtered_events > 0 {
+            println!("  Events filtered out: {}", self.filtered_events);
+        }
+        if self.dropped_events > 0 {
+            println!("  Events dropped: {}", self.dropped_events);
+        }
+        println!("  Drift incidents: {}", self.drift_incidents);
+        if !self.paths.is_empty() {
+            println!("  Paths involved:");
+            for path in &self.paths {
+                println!("    {}", redact::shown(path));
+            }
+        }
+        if !self.drifting.is_empty() {
+            println!("  Still drifting:");
+            for (path, seen) in &self.drifting {
+                println!(
+                    "    {} (first seen {}, last seen {})",
+                    redact::shown(path),
+                    utils::format_time(seen.first_seen),
+                    utils::format_time(seen.last_seen)
+                );
+            }
+        }
+        if !self.resolved.is_empty() {
+            println!("  Resolved:");
+            for resolved in &self.resolved {
+                println!(
+                    "    {} (drifted {} to {})",
+                    redact::shown(&resolved.path),
+                    utils::format_time(resolved.first_seen),
+                    utils::format_time(resolved.resolved_at)
+                );
+            }
+        }
+        log::info!(
+            "Monitor stopped after {}s: {} events ({} dropped), {} drift incidents",
+            self.started.elapsed().as_secs(),
+            self.events,
+            self.dropped_events,
+            self.drift_incidents
+        );
+    }
+}
+
+/// Cancel `shutdown` on Ctrl+C and, on Unix, SIGTERM or SIGHUP so systemd
+/// stops are clean.
+pub async fn cancel_on_signal(shutdown: CancellationToken) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let (Ok(mut term), Ok(mut hup)) = (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::hangup()),
+        ) else {
+            log::warn!("Could not listen for SIGTERM and SIGHUP; only Ctrl+C stops the monitor");
+            let _ = tokio::signal::ctrl_c().await;
+            shutdown.cancel();
+            return;
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+            _ = hup.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+    shutdown.cancel();
+}
+
+/// Alert deliveries, run on the blocking pool a few at a time so a slow or
+/// retrying channel can't hold up the session.
+struct Deliveries {
+    channels: Arc<AlertChannels>,
+    slots: Arc<Semaphore>,
+    tasks: TaskTracker,
+}
+
+impl Deliveries {
+    fn new(channels: &AlertChannels) -> Self {
+        Deliveries {
+            channels: Arc::new(channels.clone()),
+            slots: Arc::new(Semaphore::new(MAX_DELIVERIES)),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    fn spawn(&self, deliver: impl FnOnce(&AlertChannels) + Send + 'static) {
+        let (channels, slots) = (self.channels.clone(), self.slots.clone());
+        self.tasks.spawn(async move {
+            let Ok(_slot) = slots.acquire_owned().await else {
+                return;
+            };
+            if let Err(e) = tokio::task::spawn_blocking(move || deliver(&channels)).await {
+                log::error!("Alert delivery failed: {}", e);
+            }
+        });
+    }
+
+    /// Deliver through `channels` from now on. Deliveries in flight keep
+    /// the channels they started with.
+    fn retarget(&mut self, channels: &AlertChannels) {
+        self.channels = Arc::new(channels.clone());
+    }
+
+    fn send(&self, alert: Alert) {
+        self.spawn(move |channels| alert::send(&alert, channels));
+    }
+
+    fn resolve(&self, dir: &str) {
+        let dir = dir.to_string();
+        self.spawn(move |channels| alert::resolve(&dir, channels));
+    }
+
+    /// Wait for the deliveries in flight, up to `DELIVERY_GRACE`.
+    async fn finish(&self) {
+        self.tasks.close();
+        if tokio::time::timeout(DELIVERY_GRACE, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            log::warn!(
+                "Stopped waiting for {} alert deliveries after {}s",
+                self.tasks.len(),
+                DELIVERY_GRACE.as_secs()
+            );
+        }
+    }
+}
+
+/// State shared by the event and polling loops for one monitor session.
+struct Session<'a> {
+    dir: &'a str,
+    options: &'a MonitorOptions,
+    shutdown: CancellationToken,
+    summary: SessionSummary,
+    systemd: SystemdNotifier,
+    scheduler: Option<Scheduler>,
+    cooldown: Option<Cooldown>,
+    /// How long after the first event of a burst the burst is compared.
+    debounce: Duration,
+    config: Option<ConfigWatch<'a>>,
+    heartbeat: Option<Heartbeat>,
+    install: Option<InstallCheck>,
+    digest: Option<DigestTimer>,
+    deliveries: Option<Deliveries>,
+    /// Drift entries seen since the last heartbeat.
+    drift_since_heartbeat: u64,
+    files_tracked: usize,
+    last_comparison: Option<DateTime<Utc>>,
+    metrics: Option<SharedMetrics>,
+    api: Option<SharedApiState>,
+    events: Option<Arc<EventHub>>,
+    on_drift: Option<DriftCallback<'a>>,
+    baseline: BaselineGuard,
+    subscription: Option<Subscription>,
+    tracker: DriftTracker,
+    /// Comparisons asked for over the control socket.
+    triggers: Option<mpsc::UnboundedReceiver<Trigger>>,
+}
+
+impl Session<'_> {
+    fn update_api(&self, update: impl FnOnce(&mut ApiState)) {
+        if let Some(api) = &self.api {
+            update(&mut api.write().unwrap_or_else(|e| e.into_inner()));
+        }
+    }
+
+    /// The paths of an event that may start a comparison under
+    /// `watch_only`.
+    fn watched_paths(&self, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        let patterns = &self.options.watch_only;
+        if patterns.is_empty() {
+            return paths;
+        }
+        paths
+            .into_iter()
+            .filter(|path| {
+                let full = utils::path_text(path);
+                let name = path.file_name().map(|n| utils::path_text(Path::new(n)));
+                patterns.iter().any(|pattern| {
+                    pattern.matches(&full) || name.as_deref().is_some_and(|n| pattern.matches(n))
+                })
+            })
+            .collect()
+    }
+
+    /// Whether the session handled its `max_events`, which ends it; says so
+    /// when it did.
+    fn out_of_events(&self) -> bool {
+        let out = self
+            .options
+            .max_events
+            .is_some_and(|max| self.summary.events >= max);
+        if out {
+            let events = self.summary.events;
+            println!(
+                "Stopping after {} event{}.",
+                events,
+                if events == 1 { "" } else { "s" }
+            );
+            log::info!(
+                "Monitor reached its limit of {} events",
+                self.summary.events
+            );
+        }
+        out
+    }
+
+    /// End the session after drift was reported, if it runs `once`. Alerts
+    /// already sent still get the shutdown grace period.
+    fn stop_if_once(&self) {
+        if self.options.once {
+            println!("Drift detected; stopping.");
+            log::info!("Monitor stopping at its first drift");
+            self.shutdown.cancel();
+        }
+    }
+
+    /// Housekeeping done on every tick, event or not.
+    async fn tick(&mut self) {
+        self.systemd.watchdog_tick();
+        self.update_api(ApiState::tick);
+        if let Some(drift) = self.baseline.check() {
+            self.baseline_modified(drift);
+        }
+        self.check_subscription().await;
+        if let Some(config) = &mut self.config
+            && config.due()
+            && let Some(result) = config.check()
+        {
+            match result {
+                Ok((reloaded, checksum)) => self.reload_config(reloaded, checksum).await,
+                Err(problem) => {
+                    eprintln!(
+                        "Warning: the config file was not reloaded: {}; the config in effect is kept.",
+                        problem
+                    );
+                    log::error!(
+                        event = "config_reload_failed",
+                        dir = self.dir,
+                        problem = problem.as_str();
+                        "Config file not reloaded: {}", problem
+                    );
+                }
+            }
+        }
+        if let Some(scheduler) = &mut self.scheduler
+            && scheduler.due()
+        {
+            println!("Running scheduled comparison.");
+            log::info!("Scheduled comparison of {}", self.dir);
+            self.run_comparison().await;
+        }
+        if let Some(cooldown) = &mut self.cooldown
+            && let Some(summary) = cooldown.expire(self.dir)
+        {
+            log::info!("Cooldown window closed: {}", summary.summary);
+            if let Some(deliveries) = &self.deliveries {
+                deliveries.send(summary);
+            }
+        }
+        if let Some(heartbeat) = &mut self.heartbeat
+            && heartbeat.due()
+        {
+            heartbeat.beat(&HeartbeatStatus {
+                uptime: self.summary.started.elapsed(),
+                drift_in_interval: self.drift_since_heartbeat,
+                files_tracked: self.files_tracked,
+                last_comparison: self.last_comparison,
+            });
+            self.drift_since_heartbeat = 0;
+        }
+        if let Some(install) = &mut self.install
+            && install.due()
+            && let Some(problem) = install.check()
+        {
+            self.install_changed(problem);
+        }
+        if let Some(timer) = &mut self.digest
+            && timer.due()
+            && let Some(deliveries) = &self.deliveries
+        {
+            deliveries.spawn(|channels| {
+                let Some(email) = &channels.email else {
+                    return;
+                };
+                match digest::send_pending(email) {
+                    Ok(0) => log::info!("No pending alerts for the email digest"),
+                    Ok(n) => println!("Email digest sent with {} alerts.", n),
+                    Err(e) => {
+                        // The pending store is kept, so the next digest retries.
+                        eprintln!("Warning: email digest failed: {}", e);
+                        log::error!("email digest failed: {}", e);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Run the comparison asked for by `first` over the control socket, and
+    /// answer with its summary. Other requests waiting by then are answered
+    /// with the same comparison, rather than each starting one.
+    async fn compare_now(&mut self, first: Trigger) {
+        let mut waiting = vec![first];
+        if let Some(triggers) = &mut self.triggers {
+            while let Ok(trigger) = triggers.try_recv() {
+                waiting.push(trigger);
+            }
+        }
+        println!("Running the comparison asked for on the control socket.");
+        log::info!(
+            "Comparison of {} asked for on the control socket ({} requests)",
+            self.dir,
+            waiting.len()
+        );
+        let summary = self.run_comparison().await;
+        for trigger in waiting {
+            let _ = trigger.send(summary.clone());
+        }
+    }
+
+    /// Run a comparison and fold the result into the session. Returns a
+    /// summary of it, as `compare-now` answers.
+    async fn run_comparison(&mut self) -> serde_json::Value {
+        // Alerts are dispatched here rather than by the comparison so the
+        // cooldown can see them.
+        let started = Instant::now();
+        let dir = self.dir.to_string();
+        let dedup = self.options.dedup;
+        let baseline = self.options.baseline_file();
+        let result = tokio::task::spawn_blocking(move || {
+            if dedup {
+                drift::compare_quietly(&dir, &baseline, history::Source::Monitor)
+            } else {
+                compare_against(&dir, &baseline, None, history::Source::Monitor)
+            }
+        })
+        .await
+        .unwrap_or_else(|e| Err(GuardianError::Other(format!("Comparison failed: {}", e))));
+        let compared_at = Utc::now();
+        self.last_comparison = Some(compared_at);
+        if let Some(metrics) = &self.metrics {
+            let mut metrics = metrics.lock().unwrap_or_else(|e| e.into_inner());
+            match &result {
+                Ok(report) => metrics.record_comparison(report, started.elapsed()),
+                Err(_) => metrics.record_duration(started.elapsed()),
+            }
+        }
+        // The tracker takes the comparison in first, so that the events and
+        // the status API can say since when each file drifts.
+        let delta = match &result {
+            Ok(report) => {
+                let delta = self.tracker.update(report, compared_at);
+                self.summary.resolved.extend(delta.resolved.iter().cloned());
+                Some(delta)
+            }
+            Err(_) => None,
+        };
+        let observed = match &result {
+            Ok(report) => Ok(self.tracker.observed(&report.drifts)),
+            Err(e) => Err(e.to_string()),
+        };
+        if let (Some(events), Ok(observed)) = (&self.events, &observed) {
+            events.publish(self.dir, observed);
+        }
+        self.update_api(|api| api.record(observed));
+        let summary = match &result {
+            Ok(report) => serde_json::json!({
+                "directory": self.dir,
+                "compared_at": self.last_comparison.map(|t| t.to_rfc3339()),
+                "files_tracked": report.files_tracked,
+                "drift": redact::drifts(&report.drifts),
+            }),
+            Err(e) => serde_json::json!({ "directory": self.dir, "error": e.to_string() }),
+        };
+        match (result, delta) {
+            (Ok(report), Some(delta)) => {
+                self.files_tracked = report.files_tracked;
+                for warning in &report.warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                let drifting = report.drifts.len();
+                let fresh = if dedup {
+                    self.announce_delta(report, delta, true)
+                } else {
+                    Some(report).filter(|report| !report.drifts.is_empty())
+                };
+                if drifting == 0 {
+                    self.systemd.status("Last comparison: no drift");
+                    if let Some(deliveries) = &self.deliveries {
+                        deliveries.resolve(self.dir);
+                    }
+                    return summary;
+                }
+                self.systemd
+                    .status(&format!("Last comparison: {} drifted files", drifting));
+                if let Some(report) = fresh {
+                    self.report_drift(report);
+                }
+            }
+            (Err(e), _) => self
+                .systemd
+                .status(&format!("Last comparison failed: {}", e)),
+            (Ok(_), None) => unreachable!("a comparison's drift is tracked"),
+        }
+        summary
+    }
+
+    /// Compare the metadata of the files a metadata event named, right
+    /// away: a chmod or chown leaves the content, and so a full
+    /// comparison, unchanged.
+    async fn compare_metadata(&mut self, paths: Vec<PathBuf>) {
+        let dir = self.dir.to_string();
+        let dedup = self.options.dedup;
+        let baseline = self.options.baseline_file();
+        let result = tokio::task::spawn_blocking(move || {
+            let (report, checked) =
+                drift::check_metadata(&dir, &baseline, &paths, history::Source::Monitor)?;
+            if dedup {
+                Ok((report, checked))
+            } else {
+                if !report.drifts.is_empty() {
+                    drift::announce(&dir, &report);
+                }
+                Ok((report, Vec::new()))
+            }
+        })
+        .await
+        .unwrap_or_else(|e| Err(GuardianError::Other(format!("Comparison failed: {}", e))));
+        let report = match result {
+            Ok((report, checked)) if dedup => {
+                let delta = self.tracker.update_metadata(&report, &checked, Utc::now());
+                self.summary.resolved.extend(delta.resolved.iter().cloned());
+                self.announce_delta(report, delta, false)
+            }
+            Ok((report, _)) => Some(report).filter(|report| !report.drifts.is_empty()),
+            Err(e) => {
+                log::warn!("Could not compare metadata in {}: {}", self.dir, e);
+                None
+            }
+        };
+        if let Some(report) = report {
+            if let Some(events) = &self.events {
+                events.publish(self.dir, &self.tracker.observed(&report.drifts));
+            }
+            self.report_drift(report);
+        }
+    }
+
+    /// Print and log what changed in the drift since the last comparison,
+    /// `full` or of metadata: the files back to the baseline, and a report
+    /// of the drift that's new, which is returned to alert on. Drift
+    /// reported before isn't repeated.
+    fn announce_delta(
+        &mut self,
+        report: DriftReport,
+        delta: drift::DriftDelta,
+        full: bool,
+    ) -> Option<DriftReport> {
+        if !delta.resolved.is_empty() {
+            println!("Resolved:");
+            for resolved in &delta.resolved {
+                println!(
+                    "  {} (drifting since {})",
+                    redact::shown(&resolved.path),
+                    utils::format_time(resolved.first_seen)
+                );
+            }
+            let resolved: Vec<String> = delta
+                .resolved
+                .iter()
+                .map(|r| redact::path(&r.path).into_owned())
+                .collect();
+            log::info!(
+                event = "drift_resolved",
+                dir = self.dir,
+                paths:serde = resolved;
+                "Drift resolved in {}: {}", self.dir, resolved.join(", ")
+            );
+        }
+        let fresh = DriftReport {
+            score: alert::score(&delta.drifts, config::settings()),
+            drifts: delta.drifts,
+            ..report
+        };
+        let drifting = self.tracker.drifting().len();
+        if !fresh.drifts.is_empty() {
+            drift::announce(self.dir, &fresh);
+        } else if full && drifting == 0 {
+            println!("No drift detected.");
+        } else if full && delta.resolved.is_empty() {
+            println!(
+                "No new drift; {} file{} still drifting.",
+                drifting,
+                if drifting == 1 { "" } else { "s" }
+            );
+        }
+        drift::announce_set_asides(self.dir, &fresh);
+        Some(fresh).filter(|report| !report.drifts.is_empty())
+    }
+
+    /// Alert on a change to the snapshot file that config-guardian didn't
+    /// make. The cooldown doesn't hold it back: whoever edits the baseline
+    /// may be about to edit the files it vouches for.
+    fn baseline_modified(&mut self, drift: Drift) {
+        eprintln!(
+            "Warning: {}: {} ({})",
+            drift.kind,
+            drift.path,
+            drift.reason.as_deref().unwrap_or_default()
+        );
+        let drifts = [drift];
+        if let Some(metrics) = &self.metrics {
+            let mut metrics = metrics.lock().unwrap_or_else(|e| e.into_inner());
+            metrics.record_drifts(&drifts);
+        }
+        if let Some(events) = &self.events {
+            events.publish(self.dir, &self.tracker.observed(&drifts));
+        }
+        if let Some(deliveries) = &self.deliveries {
+            deliveries.send(Alert {
+                summary: DriftKind::BaselineModified.to_string(),
+                ..Alert::drift(self.dir, &drifts)
+            });
+        }
+        self.summary.drift_incidents += 1;
+        self.summary
+            .paths
+            .extend(drifts.into_iter().map(|d| d.path));
+        self.stop_if_once();
+    }
+
+    /// Ask for a newer published baseline, if the subscription is due, and
+    /// take it in: saved and sealed as the baseline, as `accept` would, and
+    /// compared against right away. A version that can't be taken in, as
+    /// one older than the baseline's or one the key didn't sign, is alerted
+    /// on instead, and the baseline is left as it is.
+    async fn check_subscription(&mut self) {
+        let Some(subscription) = &mut self.subscription else {
+            return;
+        };
+        if Instant::now() < subscription.next_check {
+            return;
+        }
+        subscription.next_check = Instant::now() + self.options.subscribe_every;
+        let baselines = subscription.baselines.clone();
+        let latest = tokio::task::spawn_blocking(move || baselines.latest())
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+        let latest = match latest {
+            Ok(Some(latest)) => latest,
+            Ok(None) => return,
+            Err(e) => {
+                // The collector being away is no reason to doubt the baseline.
+                log::warn!(
+                    "Could not ask {} for a newer {} baseline: {}",
+                    subscription.baselines.url,
+                    subscription.baselines.profile,
+                    e
+                );
+                return;
+            }
+        };
+        let baselines = &subscription.baselines;
+        let taken = publish::judge(
+            &latest,
+            &baselines.profile,
+            subscription.applied,
+            &baselines.key,
+        );
+        let saved = match taken {
+            Ok(None) => return,
+            Ok(Some(mut snapshot)) => {
+                snapshot.metadata.published = Some(latest.version);
+                snapshot
+                    .save(&self.options.baseline_file())
+                    .map_err(|e| format!("version {} could not be saved: {}", latest.version, e))
+            }
+            Err(problem) => Err(problem),
+        };
+        if let Err(problem) = saved {
+            self.subscription_failed(problem);
+            return;
+        }
+        subscription.applied = Some(latest.version);
+        subscription.failing = None;
+        println!(
+            "Baseline updated to version {} of the {} baseline, published from {} at {}; comparing against it.",
+            latest.version,
+            latest.profile,
+            latest.host,
+            utils::format_time(latest.published)
+        );
+        log::info!(
+            event = "baseline_subscribed",
+            dir = self.dir,
+            profile = latest.profile.as_str(),
+            version = latest.version,
+            host = latest.host.as_str();
+            "Took version {} of the {} baseline, published from {}, as the baseline of {}",
+            latest.version, latest.profile, latest.host, self.dir
+        );
+        self.run_comparison().await;
+    }
+
+    /// Take up the settings, alert channels, cooldown and debounce of a
+    /// config file that changed. A cooldown window already open runs its
+    /// course, unless the cooldown is off now, and alerts in flight go where
+    /// they were going.
+    async fn reload_config(&mut self, reloaded: Reloaded, checksum: String) {
+        config::reload(reloaded.settings.carry_over(config::settings()));
+        match (&mut self.deliveries, &reloaded.alerts) {
+            (Some(deliveries), Some(channels)) => deliveries.retarget(channels),
+            (None, Some(channels)) => self.deliveries = Some(Deliveries::new(channels)),
+            (Some(_), None) => {
+                if let Some(deliveries) = self.deliveries.take() {
+                    deliveries.finish().await;
+                }
+            }
+            (None, None) => {}
+        }
+        match (&mut self.cooldown, reloaded.alert_cooldown) {
+            (Some(cooldown), Some(window)) => cooldown.set_window(window),
+            (None, Some(window)) => self.cooldown = Some(Cooldown::new(window)),
+            (_, None) => self.cooldown = None,
+        }
+        self.debounce = reloaded.debounce;
+        if let Some(config) = &self.options.reload {
+            let path = config.path().to_path_buf();
+            println!("Reloaded the config file {}.", utils::path_text(&path));
+            log::info!(
+                event = "config_reloaded",
+                dir = self.dir,
+                checksum = checksum.as_str();
+                "Took up the changed config file {}", utils::path_text(&path)
+            );
+            self.update_api(|api| api.config_loaded(path, checksum));
+        }
+    }
+
+    /// Alert, critically, that the latest published baseline can't be
+    /// taken in: the fleet's baselines no longer agree.
+    fn subscription_failed(&mut self, problem: String) {
+        let Some(subscription) = &mut self.subscription else {
+            return;
+        };
+        let profile = &subscription.baselines.profile;
+        log::error!(
+            event = "baseline_subscribe_failed",
+            dir = self.dir,
+            profile = profile.as_str(),
+            problem = problem.as_str();
+            "The published {} baseline was not taken in: {}", profile, problem
+        );
+        if subscription.failing.as_ref() == Some(&problem) {
+            return;
+        }
+        eprintln!(
+            "Warning: the published {} baseline was not taken in: {}.",
+            profile, problem
+        );
+        if let Some(deliveries) = &self.deliveries {
+            deliveries.send(Alert {
+                summary: format!("Published {} baseline refused: {}", profile, problem),
+                severity: Some(Severity::Critical),
+                ..Alert::drift(self.dir, &[])
+            });
+        }
+        subscription.failing = Some(problem);
+    }
+
+    /// Alert that the executable no longer hashes as it should.
+    fn install_changed(&self, problem: String) {
+        eprintln!("Warning: {}.", problem);
+        log::error!(event = "install_changed", dir = self.dir; "{}", problem);
+        if let Some(deliveries) = &self.deliveries {
+            deliveries.send(Alert {
+                summary: problem,
+                severity: Some(Severity::Critical),
+                ..Alert::drift(self.dir, &[])
+            });
+        }
+    }
+
+    /// Start a storm for the events the queue dropped: alert on the flood
+    /// itself, since whatever changed in it can't be told from the events.
+    fn storm_started(&mut self, overflow: &Overflow) {
+        eprintln!(
+            "Warning: more filesystem events in {} than the queue holds ({}); dropping them, and comparing once they stop.",
+            self.dir, self.options.event_queue_size
+        );
+        log::warn!(
+            event = "event_storm",
+            dir = self.dir,
+            dropped = overflow.dropped;
+            "Event queue for {} overflowed; events are dropped until the storm subsides",
+            self.dir
+        );
+        if let Some(deliveries) = &self.deliveries {
+            deliveries.send(Alert {
+                summary: format!(
+                    "Event flood: over {} filesystem events queued, the rest dropped until it subsides",
+                    self.options.event_queue_size
+                ),
+                severity: Some(Severity::Critical),
+                ..Alert::drift(self.dir, &[])
+            });
+        }
+    }
+
+    /// End a storm that dropped the events of `storm`, and compare what it
+    /// left behind.
+    async fn storm_ended(&mut self, storm: Overflow) {
+        println!(
+            "Event storm over: {} event{} dropped; running a full comparison.",
+            storm.dropped,
+            if storm.dropped == 1 { "" } else { "s" }
+        );
+        for (dir, dropped) in &storm.dirs {
+            println!("  {}: {} dropped", utils::path_text(dir), dropped);
+        }
+        log::warn!(
+            event = "event_storm_over",
+            dir = self.dir,
+            dropped = storm.dropped,
+            directories = storm.dirs.len();
+            "Event storm in {} over after {} dropped events in {} directories",
+            self.dir,
+            storm.dropped,
+            storm.dirs.len()
+        );
+        self.run_comparison().await;
+    }
+
+    /// Alert on, and hand to the callback and hook, a report with drift.
+    fn report_drift(&mut self, report: DriftReport) {
+        self.summary.drift_incidents += 1;
+        if let Some(deliveries) = &self.deliveries {
+            let alert = match &mut self.cooldown {
+                Some(cooldown) => cooldown.filter(self.dir, &report.drifts),
+                None => Some(Alert::drift(self.dir, &report.drifts)),
+            };
+            if let Some(alert) = alert {
+                deliveries.send(
+                    alert
+                        .tagged(&report.tags)
+                        .aged(report.baseline_age_days)
+                        .annotated(&report.annotations)
+                        .reported(&report),
+                );
+            }
+        }
+        if let Some(callback) = &mut self.on_drift {
+            callback(&report);
+        }
+        self.drift_since_heartbeat += report.drifts.len() as u64;
+        if self.options.auto_accept {
+            self.accept(&report.drifts);
+        }
+        self.summary
+            .paths
+            .extend(report.drifts.into_iter().map(|d| d.path));
+        self.stop_if_once();
+    }
+
+    /// Take the files of `drifts` into the snapshot as `accept` does, which
+    /// records it in the history. Files that couldn't be read are left to
+    /// drift.
+    fn accept(&mut self, drifts: &[Drift]) {
+        let paths: Vec<String> = drifts
+            .iter()
+            .filter(|d| !matches!(d.kind, DriftKind::Unreadable | DriftKind::Locked))
+            .map(|d| d.path.clone())
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
+        match crate::accept(&paths, &[], None, self.options.dry_run) {
+            Ok(_) if self.options.dry_run => {}
+            // Back to the baseline now, which isn't news.
+            Ok(_) => self.tracker.forget(&paths),
+            Err(e) => {
+                eprintln!("Warning: could not accept the drift: {}", e);
+                log::error!("Could not accept drift in {}: {}", self.dir, e);
+            }
+        }
+    }
+}
+
+/// The next comparison asked for over the control socket; never, without
+/// one.
+async fn triggered(triggers: &mut Option<mpsc::UnboundedReceiver<Trigger>>) -> Trigger {
+    match triggers {
+        Some(triggers) => match triggers.recv().await {
+            Some(trigger) => trigger,
+            None => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// Sleep until `deadline`, or forever if there is none.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn ticks() -> tokio::time::Interval {
+    let mut ticks = tokio::time::interval(TICK_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticks
+}
+
+async fn monitor_until<'a>(
+    dir: &'a str,
+    options: &'a MonitorOptions,
+    shutdown: CancellationToken,
+    on_drift: Option<DriftCallback<'a>>,
+) -> Result<SessionSummary, GuardianError> {
+    options.check_intervals()?;
+    if !options.watch_backend.is_available() {
+        return Err(GuardianError::Config(format!(
+            "The {} watch backend is not available on this platform.",
+            options.watch_backend
+        )));
+    }
+    // A child, so that running out of time doesn't cancel the caller's token.
+    let shutdown = shutdown.child_token();
+    if let Some(duration) = options.duration {
+        let stop = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            println!("Stopping after {:?}.", duration);
+            log::info!("Monitor reached its time limit of {:?}", duration);
+            stop.cancel();
+        });
+    }
+    let metrics = options.metrics_listen.map(|_| SharedMetrics::default());
+    let _metrics_listener = match (options.metrics_listen, &metrics) {
+        (Some(addr), Some(metrics)) => {
+            let metrics = metrics.clone();
+            let listener =
+                HttpListener::start(addr, move |path| metrics::handle(&metrics, path).into())
+                    .map_err(|e| {
+                        GuardianError::Other(format!(
+                            "Could not listen for metrics on {}: {}",
+                            addr, e
+                        ))
+                    })?;
+            println!("Serving metrics on http://{}/metrics", addr);
+            log::info!("Metrics listener bound to {}", addr);
+            Some(listener)
+        }
+        _ => None,
+    };
+    let baseline = options.baseline_file();
+    if options.auto_accept && baseline != config::settings().snapshot_file {
+        return Err(GuardianError::Config(format!(
+            "auto_accept accepts drift into {}, not the baseline {} the monitor compares against",
+            config::settings().snapshot_file.display(),
+            baseline.display()
+        )));
+    }
+    let baseline_files = snapshot_at(&baseline, dir)
+        .map(|s| s.entries.len())
+        .unwrap_or(0);
+    let api = (options.api_listen.is_some() || options.control_socket.is_some())
+        .then(|| Arc::new(RwLock::new(ApiState::new(dir, baseline_files))));
+    let events = options.api_listen.map(|_| Arc::new(EventHub::default()));
+    let _api_listener = match (options.api_listen, &api, &events) {
+        (Some(addr), Some(api), Some(events)) => {
+            let (api, events) = (api.clone(), events.clone());
+            let listener = HttpListener::start(addr, move |path| api::handle(&api, &events, path))
+                .map_err(|e| {
+                    GuardianError::Other(format!(
+                        "Could not listen for the status API on {}: {}",
+                        addr, e
+                    ))
+                })?;
+            println!("Serving status API on http://{}/status", addr);
+            log::info!("Status API listener bound to {}", addr);
+            Some(listener)
+        }
+        _ => None,
+    };
+    // Stopped when the session ends for any reason, so the socket is removed.
+    let stop_control = shutdown.child_token();
+    let (trigger_tx, triggers) = mpsc::unbounded_channel();
+    let control = match (&options.control_socket, &api) {
+        (Some(path), Some(api)) => {
+            let control = Control::bind(path).map_err(|e| {
+                GuardianError::Other(format!(
+                    "Could not serve the control socket {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            log::info!("Control socket bound to {}", control.path().display());
+            Some(tokio::spawn(control.serve(
+                api.clone(),
+                trigger_tx,
+                stop_control.clone(),
+            )))
+        }
+        _ => None,
+    };
+
+    let mut session = Session {
+        dir,
+        options,
+        shutdown,
+        summary: SessionSummary::new(),
+        systemd: SystemdNotifier::from_env(),
+        scheduler: options
+            .compare_every
+            .map(|interval| Scheduler::new(interval, options.jitter)),
+        cooldown: options.alert_cooldown.map(Cooldown::new),
+        debounce: options.debounce,
+        config: options.reload.as_ref().map(ConfigWatch::new),
+        heartbeat: options
+            .heartbeat
+            .map(|interval| Heartbeat::new(interval, options.heartbeat_url.clone())),
+        install: options.verify_install.and_then(InstallCheck::new),
+        digest: options
+            .alerts
+            .as_ref()
+            .and_then(|a| a.email.as_ref())
+            .and_then(|email| email.digest)
+            .map(DigestTimer::new),
+        deliveries: options.alerts.as_ref().map(Deliveries::new),
+        drift_since_heartbeat: 0,
+        files_tracked: baseline_files,
+        last_comparison: None,
+        metrics,
+        api,
+        events,
+        on_drift,
+        baseline: BaselineGuard::new(&baseline),
+        subscription: options.subscribe.clone().map(|baselines| Subscription {
+            baselines,
+            next_check: Instant::now(),
+            applied: SnapshotMetadata::load(&baseline)
+                .ok()
+                .and_then(|metadata| metadata.published),
+            failing: None,
+        }),
+        tracker: DriftTracker::default(),
+        triggers: control.is_some().then_some(triggers),
+    };
+    if options.auto_accept && options.dry_run {
+        eprintln!(
+            "Dry run: drift in {} is printed as it would be accepted; the snapshot is left as it is.",
+            dir
+        );
+    } else if options.auto_accept {
+        eprintln!(
+            "Warning: drift in {} is accepted into the snapshot as it's reported; the snapshot no longer guards it.",
+            dir
+        );
+        log::warn!("Monitoring {} with drift accepted as it's reported", dir);
+    }
+    if let Some(reload) = &options.reload {
+        session.update_api(|api| {
+            api.config_loaded(reload.path().to_path_buf(), reload.checksum().to_string())
+        });
+    }
+    if let Some(drift) = session.baseline.tampered_at_start() {
+        session.baseline_modified(drift);
+    }
+    // Before any comparison, so that none is made against a baseline the
+    // fleet has moved on from.
+    session.check_subscription().await;
+
+    let result = if options.schedule_only {
+        run_schedule_only(&mut session).await;
+        Ok(())
+    } else if let Some(interval) = options.poll {
+        poll_directory(&mut session, interval).await;
+        Ok(())
+    } else {
+        match Watch::start(dir, options.watch_backend, options.event_queue_size) {
+            Ok((watch, rx)) => watch_events(&mut session, watch, rx).await,
+            Err(e) => Err(e),
+        }
+    };
+
+    session.systemd.stopping();
+    stop_control.cancel();
+    if let Some(control) = control {
+        let _ = control.await;
+    }
+    if let Some(deliveries) = &session.deliveries {
+        deliveries.finish().await;
+    }
+    result?;
+    session.summary.drifting = session
+        .tracker
+        .drifting()
+        .into_iter()
+        .map(|(path, seen)| (path.to_string(), seen))
+        .collect();
+    println!("Shutting down monitor.");
+    Ok(session.summary)
+}
+
+async fn watch_events(
+    session: &mut Session<'_>,
+    mut watch: Watch,
+    mut rx: EventReceiver,
+) -> Result<(), GuardianError> {
+    let dir = session.dir;
+    let mut root_identity = directory_identity(dir);
+
+    println!("Monitoring {} for changes... (Press Ctrl+C to stop)", dir);
+    session.systemd.ready();
+    session.update_api(|api| api.set_watching(true));
+    session.systemd.status(&format!("Watching {}", dir));
+
+    let mut ticks = ticks();
+    // Set by the first event of a burst; the burst is compared when it passes.
+    let mut compare_at = None;
+    // The paths the burst created, wrote, removed or renamed, on either side
+    // of the rename: a file replaced by writing a temporary one and renaming
+    // it over the original. The full comparison reports what became of them.
+    let mut burst: BTreeSet<PathBuf> = BTreeSet::new();
+    // While the queue overflows, the events dropped so far, and when the
+    // storm is over if no more are.
+    let mut storm: Option<(Overflow, tokio::time::Instant)> = None;
+
+    loop {
+        // The watch follows the original inode, so a root that was removed or
+        // had another directory renamed over it stops delivering events.
+        let mut watch_lost = false;
+        tokio::select! {
+            _ = session.shutdown.cancelled() => break,
+            _ = ticks.tick() => session.tick().await,
+            trigger = triggered(&mut session.triggers) => {
+                compare_at = None;
+                burst.clear();
+                session.compare_now(trigger).await;
+            }
+            _ = sleep_until(compare_at) => {
+                compare_at = None;
+                burst.clear();
+                session.run_comparison().await;
+            }
+            _ = sleep_until(storm.as_ref().map(|(_, quiet_at)| *quiet_at)) => {
+                // The events still queued are covered by the comparison.
+                while let Ok(event) = rx.try_recv() {
+                    if event.is_ok() {
+                        session.summary.events += 1;
+                    }
+                }
+                if let Some((dropped, _)) = storm.take() {
+                    session.storm_ended(dropped).await;
+                }
+            }
+            event = rx.recv() => match event {
+                // The log file is written as events are handled, so an event
+                // for it would set off another comparison, and so on.
+                Some(Ok(event)) if only_own_files(&event.paths) => {}
+                // Compared as a whole once the storm is over.
+                Some(Ok(_)) if storm.is_some() => session.summary.events += 1,
+                Some(Ok(mut event)) => {
+                    session.summary.events += 1;
+                    // An event naming no path, such as a rescan, may concern any file.
+                    let named = !event.paths.is_empty();
+                    event.paths = session.watched_paths(event.paths);
+                    if named && event.paths.is_empty() {
+                        session.summary.filtered_events += 1;
+                    } else if is_metadata(&event.kind) {
+                        // A chmod of a file the burst replaced, or of the
+                        // temporary file it was replaced with, is part of
+                        // the replacement, and reported with it.
+                        event.paths.retain(|path| !burst.contains(path));
+                        if !event.paths.is_empty() {
+                            println!("Metadata change detected: {:?}", event);
+                            // Only the paths left in are compared.
+                            session.compare_metadata(event.paths).await;
+                        }
+                    } else {
+                        if compare_at.is_none() {
+                            println!("Change detected: {:?}", event);
+                            compare_at = Some(tokio::time::Instant::now() + session.debounce);
+                        }
+                        burst.extend(event.paths);
+                    }
+                }
+                Some(Err(e)) => {
+                    println!("Watch error: {:?}", e);
+                    log::warn!("Watch error on {}: {:?}", dir, e);
+                    watch_lost = true;
+                }
+                None => {
+                    println!("Channel error: watcher disconnected");
+                    break;
+                }
+            },
+        }
+
+        if let Some(overflow) = watch.overflow() {
+            session.summary.dropped_events += overflow.dropped;
+            let quiet_at = tokio::time::Instant::now() + STORM_QUIET;
+            match &mut storm {
+                Some((dropped, until)) => {
+                    dropped.dropped += overflow.dropped;
+                    for (dir, count) in overflow.dirs {
+                        *dropped.dirs.entry(dir).or_default() += count;
+                    }
+                    *until = quiet_at;
+                }
+                None => {
+                    session.storm_started(&overflow);
+                    compare_at = None;
+                    burst.clear();
+                    storm = Some((overflow, quiet_at));
+                }
+            }
+        }
+
+        // The last event's burst, or the storm, is compared before stopping.
+        if session.out_of_events() {
+            if compare_at.take().is_some() | storm.take().is_some() {
+                burst.clear();
+                session.run_comparison().await;
+            }
+            break;
+        }
+
+        if watch_lost || directory_identity(dir) != root_identity {
+            println!("Watch on {} was invalidated; re-establishing.", dir);
+            log::warn!("Watch on {} invalidated, attempting to re-watch", dir);
+            if let Some(metrics) = &session.metrics {
+                metrics
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record_watch_error();
+            }
+            session.systemd.status(&format!("Re-watching {}", dir));
+            session.update_api(|api| api.set_watching(false));
+            if !rewatch(&mut watch, session).await? {
+                break;
+            }
+            root_identity = directory_identity(dir);
+            session.update_api(|api| api.set_watching(true));
+            compare_at = None;
+            burst.clear();
+            session.run_comparison().await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `kind` is a change to metadata alone (chmod, chown, extended
+/// attributes), which leaves the content as it was. A new modification
+/// time, which is all the poll backend reports, may mean new content.
+fn is_metadata(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Modify(ModifyKind::Metadata(metadata)) if *metadata != MetadataKind::WriteTime
+    )
+}
+
+/// Whether an event names only files config-guardian writes itself.
+fn only_own_files(paths: &[PathBuf]) -> bool {
+    let settings = config::settings();
+    !paths.is_empty() && paths.iter().all(|path| settings.is_own_file(path))
+}
+
+/// Idle loop for `--schedule-only`: comparisons happen from `tick`.
+async fn run_schedule_only(session: &mut Session<'_>) {
+    println!(
+        "Running scheduled comparisons of {}... (Press Ctrl+C to stop)",
+        session.dir
+    );
+    session.systemd.ready();
+    session.update_api(|api| api.set_watching(true));
+    let mut ticks = ticks();
+    loop {
+        tokio::select! {
+            _ = session.shutdown.cancelled() => break,
+            _ = ticks.tick() => session.tick().await,
+            trigger = triggered(&mut session.triggers) => session.compare_now(trigger).await,
+        }
+    }
+}
+
+/// Compare on a fixed schedule, for filesystems where the native watcher
+/// never delivers events (NFS, FUSE).
+async fn poll_directory(session: &mut Session<'_>, interval: Duration) {
+    println!(
+        "Polling {} every {} for changes... (Press Ctrl+C to stop)",
+        session.dir,
+        utils::format_duration(interval)
+    );
+    log::info!("Polling {} every {:?}", session.dir, interval);
+    session.systemd.ready();
+    session.update_api(|api| api.set_watching(true));
+
+    let mut ticks = ticks();
+    let mut next_check = tokio::time::Instant::now();
+    loop {
+        tokio::select! {
+            _ = session.shutdown.cancelled() => break,
+            _ = ticks.tick() => session.tick().await,
+            trigger = triggered(&mut session.triggers) => session.compare_now(trigger).await,
+            _ = tokio::time::sleep_until(next_check) => {
+                session.summary.events += 1;
+                session.run_comparison().await;
+                next_check = tokio::time::Instant::now() + interval;
+            }
+        }
+        if session.out_of_events() {
+            break;
+        }
+    }
+}
+
+/// Re-register the watch on `dir` with exponential backoff.
+///
+/// Returns `Ok(false)` if shutdown was requested while waiting, and an error
+/// once `timeout` has elapsed without the directory becoming watchable again.
+async fn rewatch(watch: &mut Watch, session: &mut Session<'_>) -> Result<bool, GuardianError> {
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+    let dir = session.dir;
+    let timeout = session.options.rewatch_timeout;
+    let started = Instant::now();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        session.systemd.watchdog_tick();
+        match watch.rewatch(dir) {
+            Ok(()) if is_valid_directory(dir) => {
+                println!("Watch on {} re-established.", dir);
+                log::info!("Re-watch of {} succeeded on attempt {}", dir, attempt);
+                return Ok(true);
+            }
+            Ok(()) => log::warn!("Re-watch attempt {} for {}: not a directory", attempt, dir),
+            Err(e) => log::warn!("Re-watch attempt {} for {} failed: {}", attempt, dir, e),
+        }
+
+        if started.elapsed() >= timeout {
+            return Err(GuardianError::WatchLost {
+                path: dir.into(),
+                timeout,
+            });
+        }
+        let remaining = timeout.saturating_sub(started.elapsed());
+        tokio::select! {
+            _ = session.shutdown.cancelled() => return Ok(false),
+            _ = tokio::time::sleep(backoff.min(remaining)) => {}
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_directory;
+    use std::sync::mpsc;
+
+    /// A directory holding app.conf, and a baseline of it saved beside it.
+    fn watched(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("guardian-{}-{}", name, std::process::id()));
+        let dir = root.join("watched");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.conf"), "listen 80").unwrap();
+        let baseline = root.join("baseline.json");
+        let scan = scan_directory(dir.to_str().unwrap()).unwrap();
+        scan.snapshot.save(&baseline).unwrap();
+        (root, dir, baseline)
+    }
+
+    #[test]
+    fn a_started_monitor_reports_drift_until_it_is_stopped() {
+        let (root, dir, baseline) = watched("started");
+        std::fs::write(dir.join("app.conf"), "listen 8080").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let monitor = Monitor::new(dir.to_str().unwrap())
+            .baseline(&baseline)
+            .poll(Duration::from_millis(100))
+            .on_drift(move |report| {
+                let _ = tx.send(report.drifts.clone());
+            })
+            .start()
+            .unwrap();
+        let drifts = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(drifts.len(), 1);
+        assert!(drifts[0].path.ends_with("app.conf"));
+        assert!(!monitor.is_finished());
+
+        let summary = monitor.stop().unwrap();
+        assert_eq!(summary.drift_incidents, 1);
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert!(matches!(
+            Monitor::new(root.join("gone").to_str().unwrap()).start(),
+            Err(GuardianError::NotADirectory { .. })
+        ));
+    }
+
+    #[test]
+    fn intervals_of_zero_are_refused_rather_than_panicking() {
+        let (root, dir, baseline) = watched("zero");
+        let monitor = || Monitor::new(dir.to_str().unwrap()).baseline(&baseline);
+        let refused = |result: Result<_, GuardianError>, name: &str| match result {
+            Err(GuardianError::Config(message)) => assert_eq!(
+                message,
+                format!("The {} interval must be greater than zero.", name)
+            ),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("a {} interval of zero was taken", name),
+        };
+        refused(monitor().poll(Duration::ZERO).start().map(|_| ()), "poll");
+        refused(
+            monitor()
+                .poll(Duration::ZERO)
+                .run_until(CancellationToken::new())
+                .map(|_| ()),
+            "poll",
+        );
+        let options = MonitorOptions {
+            compare_every: Some(Duration::ZERO),
+            ..MonitorOptions::default()
+        };
+        refused(
+            Monitor::new(dir.to_str().unwrap())
+                .options(options)
+                .start()
+                .map(|_| ()),
+            "compare_every",
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn dropping_the_handle_stops_the_monitor() {
+        let (root, dir, baseline) = watched("dropped");
+        let (tx, rx) = mpsc::channel::<()>();
+        let monitor = Monitor::new(dir.to_str().unwrap())
+            .baseline(&baseline)
+            .poll(Duration::from_millis(100))
+            .on_drift(move |_| {
+                let _ = tx.send(());
+            })
+            .start()
+            .unwrap();
+        drop(monitor);
+        // The callback, and the sender it holds, went with the monitor.
+        assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
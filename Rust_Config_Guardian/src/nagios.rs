@@ -0,0 +1,119 @@
+//! `compare --nagios`: the comparison as a Nagios (or Icinga) check plugin
+//! reports it, one line with the service state and the drift counted by
+//! category as performance data, and the state's exit status. The state is
+//! the worst of the drift's, each drift's coming from the `[nagios]`
+//! config section by its kind or severity.
+
+use std::collections::BTreeMap;
+
+use crate::config::{self, NagiosState};
+use crate::report::{CATEGORIES, category};
+use config_guardian::DriftReport;
+
+/// What the first word of each line names.
+const SERVICE: &str = "GUARDIAN";
+/// The exit status of a check that couldn't tell the state.
+pub const UNKNOWN: i32 = 3;
+
+/// The state of the comparisons in `reports` and the line reporting it,
+/// such as `GUARDIAN CRITICAL - 4 drift (1 critical, 3 warning) |
+/// changed=2 new=1 deleted=1 metadata=0 unreadable=0 score=23`.
+pub fn check(reports: &[(String, DriftReport)]) -> (NagiosState, String) {
+    let settings = config::settings();
+    let mut states = BTreeMap::new();
+    let mut categories: BTreeMap<&str, usize> = CATEGORIES.iter().map(|c| (*c, 0)).collect();
+    let mut drifts = 0;
+    let mut score = 0;
+    for (_, report) in reports {
+        for drift in &report.drifts {
+            *states.entry(settings.nagios_state(drift)).or_insert(0) += 1;
+            *categories.entry(category(drift.kind)).or_insert(0) += 1;
+        }
+        drifts += report.drifts.len();
+        score += report.score;
+    }
+    let state = states.keys().max().copied().unwrap_or(NagiosState::Ok);
+    let counted: Vec<String> = states
+        .iter()
+        .rev()
+        .filter(|(state, _)| **state != NagiosState::Ok)
+        .map(|(state, count)| format!("{} {}", count, state.to_string().to_lowercase()))
+        .collect();
+    let summary = match counted.is_empty() {
+        true => format!("{} drift", drifts),
+        false => format!("{} drift ({})", drifts, counted.join(", ")),
+    };
+    let perfdata: Vec<String> = CATEGORIES
+        .iter()
+        .map(|c| format!("{}={}", c, categories[c]))
+        .chain([format!("score={}", score)])
+        .collect();
+    let line = format!(
+        "{} {} - {} | {}",
+        SERVICE,
+        state,
+        summary,
+        perfdata.join(" ")
+    );
+    (state, line)
+}
+
+/// The line of a check that failed with `error`, which Nagios shows as
+/// UNKNOWN: its first line only, as a plugin's first line is its status.
+pub fn unknown(error: &str) -> String {
+    let first = error.lines().next().unwrap_or_default();
+    format!("{} UNKNOWN - {}", SERVICE, first.replace('|', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Drift, DriftKind, Snapshot};
+    use config_guardian::compare;
+
+    fn report(kinds: &[DriftKind]) -> (String, DriftReport) {
+        let mut report = compare(&Snapshot::default(), &Snapshot::default());
+        report.drifts = kinds
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| Drift {
+                kind: *kind,
+                path: format!("etc/{}.conf", i),
+                reason: None,
+            })
+            .collect();
+        report.score = 5 * kinds.len() as u64;
+        ("etc".to_string(), report)
+    }
+
+    #[test]
+    fn the_line_reports_the_worst_state_and_counts_by_category() {
+        assert_eq!(
+            check(&[report(&[])]),
+            (
+                NagiosState::Ok,
+                "GUARDIAN OK - 0 drift | changed=0 new=0 deleted=0 metadata=0 unreadable=0 score=0"
+                    .to_string()
+            )
+        );
+        let drifted = [
+            report(&[DriftKind::Changed, DriftKind::New]),
+            report(&[DriftKind::Deleted, DriftKind::PermissionsChanged]),
+        ];
+        let (state, line) = check(&drifted);
+        assert_eq!(state, NagiosState::Critical);
+        assert_eq!(
+            line,
+            "GUARDIAN CRITICAL - 4 drift (1 critical, 3 warning) | changed=1 new=1 deleted=1 metadata=1 unreadable=0 score=20"
+        );
+        assert_eq!(state.code(), 2);
+    }
+
+    #[test]
+    fn unknown_takes_the_first_line_of_the_error() {
+        assert_eq!(
+            unknown("No snapshot found | run snapshot\nfirst"),
+            "GUARDIAN UNKNOWN - No snapshot found / run snapshot"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Content normalizers: commands the `normalize` table in the config file
+//! pipes the files matching a glob through before they are hashed, so that
+//! a format one's vendor tool rewrites without changing what it says, such
+//! as by reordering keys or stamping the time it was saved, hashes the same
+//! from one save to the next. The file's content goes to the command's
+//! stdin and what it prints is hashed in its place. A command that fails,
+//! or runs past `normalize_timeout`, leaves the file hashed as it is, with
+//! a warning. Files hashed by a sample (see `sample_hash`) aren't
+//! normalized.
+//!
+//! Snapshots record each normalizer by a hash of its command, so that a
+//! comparison run with other normalizers than the baseline's says so
+//! rather than report every file they match as changed.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use crate::{config, redact, utils};
+
+/// How long a normalizer may take over one file (default).
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command the files matching `pattern` are hashed through.
+#[derive(Clone, Debug)]
+pub struct Normalizer {
+    pub pattern: glob::Pattern,
+    pub command: String,
+    args: Vec<String>,
+}
+
+impl Normalizer {
+    pub fn new(pattern: &str, command: &str) -> Result<Normalizer, String> {
+        let pattern = glob::Pattern::new(pattern)
+            .map_err(|e| format!("normalize: invalid glob '{}': {}", pattern, e))?;
+        let args = shlex::split(command)
+            .filter(|args| !args.is_empty())
+            .ok_or_else(|| format!("normalize: could not parse command '{}'", command))?;
+        Ok(Normalizer {
+            pattern,
+            command: command.to_string(),
+            args,
+        })
+    }
+
+    /// Whether `path`, or its file name, matches the pattern.
+    pub fn matches(&self, path: &str) -> bool {
+        let name = Path::new(path)
+            .file_name()
+            .map(|n| utils::path_text(Path::new(n)));
+        self.pattern.matches(path) || name.as_deref().is_some_and(|n| self.pattern.matches(n))
+    }
+
+    /// Pipe `content` through the command, giving what it printed.
+    fn run(&self, content: &[u8], timeout: Duration) -> Result<Vec<u8>, String> {
+        let mut child = Command::new(&self.args[0])
+            .args(&self.args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not run it: {}", e))?;
+        // Write and read from threads, so that neither a command that
+        // doesn't read all of its input nor one printing more than a pipe
+        // holds can block it past the timeout.
+        let (Some(mut stdin), Some(mut stdout), Some(mut stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            let _ = child.kill();
+            return Err("its pipes are unavailable".to_string());
+        };
+        let content = content.to_vec();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&content);
+        });
+        let output = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+        let errors = std::thread::spawn(move || {
+            let mut errors = String::new();
+            let _ = stderr.read_to_string(&mut errors);
+            errors
+        });
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("killed after {}", utils::format_duration(timeout)));
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        if !status.success() {
+            let errors = errors.join().unwrap_or_default();
+            return Err(match errors.lines().find(|line| !line.trim().is_empty()) {
+                Some(line) => format!("exited with {}: {}", status, line.trim()),
+                None => format!("exited with {}", status),
+            });
+        }
+        output
+            .join()
+            .map_err(|_| "reading its output failed".to_string())?
+            .map_err(|e| format!("could not read its output: {}", e))
+    }
+}
+
+/// What a snapshot records a normalizer by: the start of a SHA-256 of its
+/// command, as the command may hold what shouldn't be saved.
+pub fn identity(command: &str) -> String {
+    let digest = format!("{:x}", Sha256::digest(command.as_bytes()));
+    digest[..16].to_string()
+}
+
+/// The identities of the normalizers scans run with now, by their globs.
+pub fn identities() -> BTreeMap<String, String> {
+    config::settings()
+        .normalize
+        .iter()
+        .map(|n| (n.pattern.as_str().to_string(), identity(&n.command)))
+        .collect()
+}
+
+/// `content`, read from `file`, through the first normalizer `file`
+/// matches, or as it is when none does or the normalizer fails.
+pub(crate) fn apply(file: &Path, content: Vec<u8>) -> Vec<u8> {
+    let settings = config::settings();
+    if settings.normalize.is_empty() {
+        return content;
+    }
+    let path = utils::path_text(file);
+    let Some(normalizer) = settings.normalize.iter().find(|n| n.matches(&path)) else {
+        return content;
+    };
+    match normalizer.run(&content, settings.normalize_timeout) {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            eprintln!(
+                "Warning: normalizer '{}' failed on {} ({}); hashing it as it is.",
+                normalizer.command,
+                redact::shown(&path),
+                e
+            );
+            log::warn!(
+                event = "normalize_failed",
+                path = redact::path(&path).as_ref(),
+                pattern = normalizer.pattern.as_str();
+                "Normalizer for {} failed on {}, hashed as it is: {}",
+                normalizer.pattern, redact::path(&path), e
+            );
+            content
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizers_match_by_name_or_path_and_are_known_by_their_command() {
+        let normalizer = Normalizer::new("*.vconf", "vendor-canonicalize --stdin").unwrap();
+        assert!(normalizer.matches("/etc/app/router.vconf"));
+        assert!(normalizer.matches("router.vconf"));
+        assert!(!normalizer.matches("/etc/app/router.conf"));
+        assert!(Normalizer::new("*.vconf", "").is_err());
+        assert!(Normalizer::new("*.vconf", "tool 'unclosed").is_err());
+        assert!(Normalizer::new("[", "tool").is_err());
+
+        assert_eq!(identity("sort"), identity("sort"));
+        assert_ne!(identity("sort"), identity("sort -r"));
+        assert_eq!(identity("sort").len(), 16);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_failing_normalizer_gives_its_error() {
+        let sort = Normalizer::new("*", "sort").unwrap();
+        assert_eq!(sort.run(b"b\na\n", DEFAULT_TIMEOUT).unwrap(), b"a\nb\n");
+        let failing = Normalizer::new("*", "sh -c 'echo bad input >&2; exit 3'").unwrap();
+        let error = failing.run(b"", DEFAULT_TIMEOUT).unwrap_err();
+        assert!(error.contains("bad input"), "{}", error);
+        let slow = Normalizer::new("*", "sleep 5").unwrap();
+        let error = slow.run(b"", Duration::from_millis(50)).unwrap_err();
+        assert!(error.starts_with("killed after"), "{}", error);
+    }
+}
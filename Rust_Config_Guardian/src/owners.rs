@@ -0,0 +1,89 @@
+//! The names of the users and groups that own files. A directory's files
+//! often share a handful of owners, so each ID is looked up once per scan.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    User,
+    Group,
+}
+
+static NAMES: Mutex<BTreeMap<(Kind, u32), Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// Forget the names looked up so far, as a scan starts, so that an account
+/// renamed since the last one is seen.
+pub fn forget() {
+    NAMES.lock().unwrap().clear();
+}
+
+/// The name of the user `uid`, if it has one here.
+pub fn user_name(uid: u32) -> Option<String> {
+    cached(Kind::User, uid)
+}
+
+/// The name of the group `gid`, if it has one here.
+pub fn group_name(gid: u32) -> Option<String> {
+    cached(Kind::Group, gid)
+}
+
+fn cached(kind: Kind, id: u32) -> Option<String> {
+    if let Some(name) = NAMES.lock().unwrap().get(&(kind, id)) {
+        return name.clone();
+    }
+    let name = look_up(kind, id);
+    NAMES.lock().unwrap().insert((kind, id), name.clone());
+    name
+}
+
+fn look_up(kind: Kind, id: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: the entries point into `buf`, which outlives them, and is
+        // as long as it is said to be.
+        let (rc, name) = unsafe {
+            match kind {
+                Kind::User => {
+                    let mut entry: libc::passwd = std::mem::zeroed();
+                    let mut found = std::ptr::null_mut();
+                    let rc =
+                        libc::getpwuid_r(id, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found);
+                    (rc, (!found.is_null()).then_some(entry.pw_name))
+                }
+                Kind::Group => {
+                    let mut entry: libc::group = std::mem::zeroed();
+                    let mut found = std::ptr::null_mut();
+                    let rc =
+                        libc::getgrgid_r(id, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found);
+                    (rc, (!found.is_null()).then_some(entry.gr_name))
+                }
+            }
+        };
+        // A long list of group members needs a bigger buffer.
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 4, 0);
+            continue;
+        }
+        let name = name.filter(|_| rc == 0)?;
+        // SAFETY: a found entry's name is a C string in `buf`.
+        return Some(
+            unsafe { CStr::from_ptr(name) }
+                .to_string_lossy()
+                .into_owned(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_has_a_name() {
+        assert_eq!(user_name(0).as_deref(), Some("root"));
+        assert!(group_name(0).is_some());
+        assert_eq!(user_name(u32::MAX - 1), None);
+    }
+}
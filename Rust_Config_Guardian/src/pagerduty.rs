@@ -0,0 +1,218 @@
+//! PagerDuty Events API v2 alert channel.
+//!
+//! Each trigger's dedup key is remembered per directory in
+//! `pagerduty-incidents.json`, so a later comparison that finds the
+//! directory clean — in this process or a later `compare` run — can
+//! resolve the incidents it opened.
+
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::alert::{Alert, Severity};
+use crate::utils::hostname;
+use crate::{http, identity};
+
+pub(crate) const EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+pub const INCIDENTS_FILE: &str = "pagerduty-incidents.json";
+
+#[derive(Clone)]
+pub struct PagerDutyConfig {
+    pub routing_key: String,
+    pub timeout: Duration,
+}
+
+/// Open dedup keys by directory.
+type Incidents = BTreeMap<String, Vec<String>>;
+
+/// Stable for a given host, directory and set of drifted paths, whatever
+/// order the paths were found in.
+pub fn dedup_key(host: &str, dir: &str, alert: &Alert) -> String {
+    let mut paths: Vec<&str> = alert.drifts.iter().map(|d| d.path.as_str()).collect();
+    paths.sort_unstable();
+    paths.dedup();
+    let mut hasher = Sha256::new();
+    hasher.update(host.as_bytes());
+    hasher.update([0]);
+    hasher.update(dir.as_bytes());
+    for path in paths {
+        hasher.update([0]);
+        hasher.update(path.as_bytes());
+    }
+    format!("config-guardian-{:x}", hasher.finalize())
+}
+
+fn severity(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "info",
+        Severity::Warning => "warning",
+        Severity::Critical => "critical",
+    }
+}
+
+pub fn trigger_payload(routing_key: &str, host: &str, alert: &Alert) -> Value {
+    json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        "dedup_key": dedup_key(host, &alert.dir, alert),
+        "payload": {
+            "summary": format!("Config drift on {}: {} in {}", host, alert.summary, alert.dir),
+            "source": host,
+            "severity": severity(alert.severity()),
+            "component": alert.dir,
+            "class": "config-drift",
+            "custom_details": {
+                "score": alert.score(),
+                "entries": alert.drifts,
+                "tags": alert.tags,
+                "baseline_age_days": alert.baseline_age_days,
+                "annotations": alert.annotations,
+                "instance_id": identity::current(),
+            },
+        },
+    })
+}
+
+pub fn resolve_payload(routing_key: &str, dedup_key: &str) -> Value {
+    json!({
+        "routing_key": routing_key,
+        "event_action": "resolve",
+        "dedup_key": dedup_key,
+    })
+}
+
+fn load_incidents() -> Incidents {
+    std::fs::read_to_string(INCIDENTS_FILE)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_incidents(incidents: &Incidents) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(incidents).map_err(|e| e.to_string())?;
+    std::fs::write(INCIDENTS_FILE, data)
+        .map_err(|e| format!("could not write {}: {}", INCIDENTS_FILE, e))
+}
+
+pub fn send(config: &PagerDutyConfig, alert: &Alert) -> Result<(), String> {
+    // Summary-only alerts (cooldown digests) carry nothing to page on.
+    if alert.drifts.is_empty() {
+        return Ok(());
+    }
+    let host = hostname();
+    let body = trigger_payload(&config.routing_key, &host, alert);
+    http::post_json(EVENTS_URL, &[], config.timeout, &body)?;
+
+    let key = body["dedup_key"].as_str().unwrap_or_default().to_string();
+    let mut incidents = load_incidents();
+    let open = incidents.entry(alert.dir.clone()).or_default();
+    if !open.contains(&key) {
+        open.push(key);
+    }
+    save_incidents(&incidents)
+}
+
+/// Resolve every incident opened for `dir`. Keys that fail to resolve stay
+/// recorded so the next clean comparison tries again.
+pub fn resolve(config: &PagerDutyConfig, dir: &str) -> Result<(), String> {
+    let mut incidents = load_incidents();
+    let Some(open) = incidents.remove(dir) else {
+        return Ok(());
+    };
+    let mut failed = Vec::new();
+    let mut error = None;
+    for key in open {
+        let body = resolve_payload(&config.routing_key, &key);
+        match http::post_json(EVENTS_URL, &[], config.timeout, &body) {
+            Ok(()) => log::info!("Resolved PagerDuty incident {} for {}", key, dir),
+            Err(e) => {
+                failed.push(key);
+                error = Some(e);
+            }
+        }
+    }
+    if !failed.is_empty() {
+        incidents.insert(dir.to_string(), failed);
+    }
+    save_incidents(&incidents)?;
+    error.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Drift, DriftKind};
+
+    fn drift(kind: DriftKind, path: &str) -> Drift {
+        Drift {
+            kind,
+            path: path.to_string(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn trigger_payload_carries_entries_and_severity() {
+        let alert = Alert::drift(
+            "/etc/nginx",
+            &[
+                drift(DriftKind::Changed, "/etc/nginx/nginx.conf"),
+                drift(DriftKind::Deleted, "/etc/nginx/old.conf"),
+            ],
+        );
+        let value = trigger_payload("KEY", "web1", &alert);
+
+        assert_eq!(value["routing_key"], "KEY");
+        assert_eq!(value["event_action"], "trigger");
+        assert_eq!(value["dedup_key"], dedup_key("web1", "/etc/nginx", &alert));
+        let payload = &value["payload"];
+        assert_eq!(
+            payload["summary"],
+            "Config drift on web1: 2 drifted files in /etc/nginx"
+        );
+        assert_eq!(payload["source"], "web1");
+        assert_eq!(payload["severity"], "critical");
+        assert_eq!(payload["component"], "/etc/nginx");
+        assert_eq!(payload["custom_details"]["score"], 55);
+        assert_eq!(
+            payload["custom_details"]["entries"],
+            json!([
+                { "kind": "changed", "path": "/etc/nginx/nginx.conf" },
+                { "kind": "deleted", "path": "/etc/nginx/old.conf" },
+            ])
+        );
+    }
+
+    #[test]
+    fn dedup_key_ignores_order_but_not_paths() {
+        let a = Alert::drift(
+            "d",
+            &[drift(DriftKind::New, "d/a"), drift(DriftKind::New, "d/b")],
+        );
+        let b = Alert::drift(
+            "d",
+            &[
+                drift(DriftKind::Changed, "d/b"),
+                drift(DriftKind::New, "d/a"),
+            ],
+        );
+        let c = Alert::drift("d", &[drift(DriftKind::New, "d/a")]);
+
+        assert_eq!(dedup_key("h", "d", &a), dedup_key("h", "d", &b));
+        assert_ne!(dedup_key("h", "d", &a), dedup_key("h", "d", &c));
+        assert_ne!(dedup_key("h", "d", &a), dedup_key("other", "d", &a));
+    }
+
+    #[test]
+    fn resolve_payload_references_the_key() {
+        assert_eq!(
+            resolve_payload("KEY", "config-guardian-abc"),
+            json!({
+                "routing_key": "KEY",
+                "event_action": "resolve",
+                "dedup_key": "config-guardian-abc",
+            })
+        );
+    }
+}
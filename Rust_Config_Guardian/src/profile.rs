@@ -0,0 +1,236 @@
+//! Profiles: a directory with the excludes and severity rules that suit it,
+//! selected with `--profile NAME`. The built-in ones are TOML files under
+//! `src/profiles/`; the config file adds its own as `[profile.NAME]`
+//! sections, which take the place of a built-in one of the same name.
+
+use serde::Deserialize;
+
+use crate::config::{Config, SeverityRule};
+
+const PROFILES: &[(&str, &str)] = &[
+    ("etc-linux", include_str!("profiles/etc-linux.toml")),
+    ("nginx", include_str!("profiles/nginx.toml")),
+    ("sshd", include_str!("profiles/sshd.toml")),
+];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip)]
+    pub name: &'static str,
+    pub description: String,
+    pub directories: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub severity: Vec<SeverityRule>,
+}
+
+impl Profile {
+    /// Add the profile to `config`. Its directories replace the config
+    /// file's; its excludes are added to the file's, and its severity rules
+    /// come after the file's, so the file's win where both match.
+    pub fn apply(&self, config: &mut Config) {
+        config.directories = self.directories.clone();
+        config.exclude.extend(self.exclude.iter().cloned());
+        config.severity.extend(self.severity.iter().cloned());
+    }
+}
+
+fn parse(name: &'static str, data: &str) -> Profile {
+    let mut profile: Profile = toml::from_str(data)
+        .unwrap_or_else(|e| panic!("built-in profile {} is invalid: {}", name, e));
+    profile.name = name;
+    profile
+}
+
+/// Every built-in profile, by name.
+pub fn all() -> Vec<Profile> {
+    PROFILES
+        .iter()
+        .map(|(name, data)| parse(name, data))
+        .collect()
+}
+
+pub fn find(name: &str) -> Result<Profile, String> {
+    PROFILES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(name, data)| parse(name, data))
+        .ok_or_else(|| {
+            format!(
+                "Unknown profile '{}'; run 'profiles list' to see them.",
+                name
+            )
+        })
+}
+
+/// Apply the profile called `name` to `config`: the config file's
+/// `[profile.NAME]` section if it has one, otherwise the built-in profile.
+pub fn select(config: &mut Config, name: &str) -> Result<(), String> {
+    match config.profiles.get(name).cloned() {
+        Some(section) => section
+            .apply(config)
+            .map_err(|e| format!("profile '{}': {}", name, e))?,
+        None => find(name)?.apply(config),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn every_profile_is_valid() {
+        for profile in all() {
+            let mut config = Config::default();
+            profile.apply(&mut config);
+            assert_eq!(config.directories.len(), 1, "{}", profile.name);
+            assert_eq!(config.problems(), Vec::<String>::new(), "{}", profile.name);
+        }
+    }
+
+    #[test]
+    fn etc_linux_leaves_out_files_the_system_rewrites() {
+        let mut config = Config::default();
+        find("etc-linux").unwrap().apply(&mut config);
+        let settings = config.settings().unwrap();
+        for path in [
+            "/etc/mtab",
+            "/etc/adjtime",
+            "/etc/ld.so.cache",
+            "/etc/shadow-",
+        ] {
+            assert!(settings.is_excluded(Path::new(path)), "{}", path);
+        }
+        assert!(!settings.is_excluded(Path::new("/etc/shadow")));
+    }
+
+    #[test]
+    fn profile_rules_come_after_the_config_files() {
+        let mut config: Config = toml::from_str(
+            "exclude = [\"*.orig\"]\n\
+             [[severity]]\npath = \"/etc/ssh/sshd_config\"\nseverity = \"info\"\n",
+        )
+        .unwrap();
+        find("sshd").unwrap().apply(&mut config);
+        assert_eq!(config.directories, ["/etc/ssh"]);
+        assert_eq!(config.exclude[0], "*.orig");
+        assert_eq!(config.severity[0].path, "/etc/ssh/sshd_config");
+        assert!(config.severity.len() > 1);
+        assert!(find("apache").is_err());
+    }
+
+    #[test]
+    fn the_config_files_profiles_come_first() {
+        let file = r#"
+            directories = ["/etc"]
+            exclude = ["*.orig"]
+            [[severity]]
+            path = "*.conf"
+            severity = "info"
+            [profile.nginx]
+            directories = ["/srv/nginx"]
+            exclude = ["*.tmp"]
+            snapshot = "nginx.json"
+            [[profile.nginx.severity]]
+            path = "/srv/nginx/nginx.conf"
+            severity = "critical"
+            [profile.nginx.alerts]
+            slack_webhook = "https://hooks.slack.com/services/T0/B0/nginx"
+            [profile.app]
+            exclude = ["*.log"]
+        "#;
+        let mut config: Config = toml::from_str(file).unwrap();
+        select(&mut config, "nginx").unwrap();
+        assert_eq!(config.directories, ["/srv/nginx"]);
+        assert_eq!(config.exclude, ["*.orig", "*.tmp"]);
+        assert_eq!(config.severity[0].path, "/srv/nginx/nginx.conf");
+        assert_eq!(config.severity[1].path, "*.conf");
+        assert_eq!(config.snapshot.as_deref(), Some(Path::new("nginx.json")));
+        assert!(config.alerts.slack_webhook.is_some());
+
+        // Without directories of its own, a profile keeps the file's.
+        let mut config: Config = toml::from_str(file).unwrap();
+        select(&mut config, "app").unwrap();
+        assert_eq!(config.directories, ["/etc"]);
+        assert_eq!(config.snapshot, None);
+
+        let mut config: Config = toml::from_str(file).unwrap();
+        select(&mut config, "sshd").unwrap();
+        assert_eq!(config.directories, ["/etc/ssh"]);
+        assert!(select(&mut config, "apache").is_err());
+    }
+
+    #[test]
+    fn profiles_send_alerts_to_the_channels_they_name() {
+        let file = r#"
+            [alerts]
+            slack_webhook = "https://hooks.slack.com/services/T0/B0/ops"
+            [channel.web-slack]
+            slack_webhook = "https://hooks.slack.com/services/T0/B0/web"
+            [channel.db-pagerduty]
+            pagerduty_routing_key = "R0UT1NG"
+            [profile.nginx]
+            channels = ["web-slack"]
+            [profile.postgres]
+            channels = ["db-pagerduty", "web-slack"]
+            [profile.postgres.alerts]
+            webhook_url = "https://example.com/hook"
+            [profile.app]
+            exclude = ["*.log"]
+        "#;
+        let config: Config = toml::from_str(file).unwrap();
+        assert_eq!(config.problems(), Vec::<String>::new());
+
+        let mut nginx = config.clone();
+        select(&mut nginx, "nginx").unwrap();
+        assert!(nginx.alerts.slack_webhook.unwrap().ends_with("/web"));
+        assert_eq!(nginx.alerts.pagerduty_routing_key, None);
+
+        let mut postgres = config.clone();
+        select(&mut postgres, "postgres").unwrap();
+        assert!(postgres.alerts.slack_webhook.unwrap().ends_with("/web"));
+        assert_eq!(
+            postgres.alerts.pagerduty_routing_key.as_deref(),
+            Some("R0UT1NG")
+        );
+        assert!(postgres.alerts.webhook_url.is_some());
+
+        // A profile naming no channel keeps the file's alerts.
+        let mut app = config.clone();
+        select(&mut app, "app").unwrap();
+        assert!(app.alerts.slack_webhook.unwrap().ends_with("/ops"));
+        let masked = config.masked();
+        assert_ne!(
+            masked.channels["db-pagerduty"].pagerduty_routing_key,
+            config.channels["db-pagerduty"].pagerduty_routing_key
+        );
+
+        let broken = r#"
+            [channel.web-slack]
+            slack_webhook = "https://hooks.slack.com/services/T0/B0/web"
+            [channel.other-slack]
+            slack_webhook = "https://hooks.slack.com/services/T0/B0/other"
+            [profile.nginx]
+            channels = ["web-slak"]
+            [profile.both]
+            channels = ["web-slack", "other-slack"]
+        "#;
+        let mut config: Config = toml::from_str(broken).unwrap();
+        assert_eq!(
+            config.problems(),
+            [
+                "profile.both.channels: channel 'other-slack' sets slack_webhook again",
+                "profile.nginx.channels: channel 'web-slak' is not defined; add a [channel.web-slak] section",
+            ]
+        );
+        assert!(
+            select(&mut config, "nginx")
+                .unwrap_err()
+                .contains("web-slak")
+        );
+    }
+}
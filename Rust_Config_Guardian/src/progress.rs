@@ -0,0 +1,226 @@
+//! Progress of the scan in flight, for the monitor's status API and control
+//! socket, which answer while a comparison of a large tree is still reading
+//! it. A scan goes through two phases: listing and stat'ing the files, and
+//! taking those whose size and modification time match the baseline as
+//! unchanged, then reading and hashing the rest. Reads publish their counts
+//! at most every [`PUBLISH_INTERVAL`], so that the status is never what the
+//! threads reading files wait on. Once the scan is done, its figures stay
+//! as the last scan's of its directory.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How often reads in progress publish how far they are, at most.
+pub const PUBLISH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Listing the files and checking their metadata against the baseline.
+    Metadata,
+    /// Reading and hashing the files that need it.
+    Hashing,
+}
+
+/// How far a scan is, or got.
+#[derive(Clone, Debug, Serialize)]
+pub struct Progress {
+    pub dir: String,
+    pub phase: Phase,
+    /// Files whose hash is known, read or taken from the baseline.
+    pub files_scanned: usize,
+    /// The baseline's entries for the directory, for a comparison.
+    pub files_expected: Option<usize>,
+    pub bytes_hashed: u64,
+    pub elapsed_ms: u64,
+    /// When the scan ended, for one that has.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// The scans in flight, by when they began, and the last of each
+/// directory to end: `monitor --all-profiles` scans several at once.
+static CURRENT: Mutex<BTreeMap<u64, Progress>> = Mutex::new(BTreeMap::new());
+static LAST: Mutex<BTreeMap<String, Progress>> = Mutex::new(BTreeMap::new());
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+fn lock<T>(progress: &'static Mutex<T>) -> MutexGuard<'static, T> {
+    progress.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The scan of `dir` in flight, if any; the latest to begin if there are
+/// several.
+pub fn current(dir: &str) -> Option<Progress> {
+    lock(&CURRENT)
+        .values()
+        .rfind(|progress| progress.dir == dir)
+        .cloned()
+        .map(|mut progress| {
+            progress.elapsed_ms = progress.started.elapsed().as_millis() as u64;
+            progress
+        })
+}
+
+/// The last scan of `dir` to end.
+pub fn last(dir: &str) -> Option<Progress> {
+    lock(&LAST).get(dir).cloned()
+}
+
+/// Where a scan in flight publishes how far it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Slot(u64);
+
+/// A scan in flight, which ends with it.
+pub(crate) struct Scanning(Slot);
+
+/// Start tracking a scan of `dir`, whose baseline has `expected` entries.
+pub(crate) fn begin(dir: &str, expected: Option<usize>) -> Scanning {
+    let id = NEXT.fetch_add(1, Ordering::Relaxed);
+    lock(&CURRENT).insert(
+        id,
+        Progress {
+            dir: dir.to_string(),
+            phase: Phase::Metadata,
+            files_scanned: 0,
+            files_expected: expected,
+            bytes_hashed: 0,
+            elapsed_ms: 0,
+            finished: None,
+            started: Instant::now(),
+        },
+    );
+    Scanning(Slot(id))
+}
+
+impl Scanning {
+    pub(crate) fn slot(&self) -> Slot {
+        self.0
+    }
+}
+
+impl Drop for Scanning {
+    fn drop(&mut self) {
+        let Some(mut progress) = lock(&CURRENT).remove(&self.0.0) else {
+            return;
+        };
+        progress.elapsed_ms = progress.started.elapsed().as_millis() as u64;
+        progress.finished = Some(Utc::now());
+        lock(&LAST).insert(progress.dir.clone(), progress);
+    }
+}
+
+impl Slot {
+    fn update(self, f: impl FnOnce(&mut Progress)) {
+        if let Some(progress) = lock(&CURRENT).get_mut(&self.0) {
+            f(progress);
+        }
+    }
+
+    /// The scan has started reading files.
+    pub(crate) fn hashing(self) {
+        self.update(|progress| progress.phase = Phase::Hashing);
+    }
+
+    /// `files` were settled without being read.
+    pub(crate) fn settle(self, files: usize) {
+        self.update(|progress| progress.files_scanned += files);
+    }
+}
+
+/// Counts of files read, kept by the thread collecting the reads and
+/// published to the scan's slot, if it has one, every [`PUBLISH_INTERVAL`]
+/// and when dropped.
+pub(crate) struct Counter {
+    slot: Option<Slot>,
+    files: usize,
+    bytes: u64,
+    published: Instant,
+}
+
+impl Counter {
+    pub(crate) fn new(slot: Option<Slot>) -> Counter {
+        Counter {
+            slot,
+            files: 0,
+            bytes: 0,
+            published: Instant::now(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+        if self.published.elapsed() >= PUBLISH_INTERVAL {
+            self.publish();
+        }
+    }
+
+    fn publish(&mut self) {
+        let (files, bytes) = (
+            std::mem::take(&mut self.files),
+            std::mem::take(&mut self.bytes),
+        );
+        if let Some(slot) = self.slot {
+            slot.update(|progress| {
+                progress.files_scanned += files;
+                progress.bytes_hashed += bytes;
+            });
+        }
+        self.published = Instant::now();
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        self.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_of_different_directories_keep_their_own_figures() {
+        let etc = begin("/progress/etc", Some(10));
+        let srv = begin("/progress/srv", None);
+        etc.slot().settle(4);
+        etc.slot().hashing();
+        {
+            let mut counter = Counter::new(Some(etc.slot()));
+            counter.add(100);
+            counter.add(50);
+        }
+        srv.slot().settle(1);
+
+        let progress = current("/progress/etc").unwrap();
+        assert_eq!(progress.phase, Phase::Hashing);
+        assert_eq!(progress.files_scanned, 6);
+        assert_eq!(progress.files_expected, Some(10));
+        assert_eq!(progress.bytes_hashed, 150);
+        assert_eq!(current("/progress/srv").unwrap().files_scanned, 1);
+        assert_eq!(current("/progress/srv").unwrap().phase, Phase::Metadata);
+        assert!(last("/progress/etc").is_none());
+
+        drop(etc);
+        assert!(current("/progress/etc").is_none());
+        let last_scan = last("/progress/etc").unwrap();
+        assert_eq!(last_scan.files_scanned, 6);
+        assert!(last_scan.finished.is_some());
+        assert!(current("/progress/srv").is_some(), "still scanning");
+
+        // A scan ending after another began leaves it in flight.
+        let again = begin("/progress/srv", None);
+        drop(srv);
+        assert_eq!(current("/progress/srv").unwrap().files_scanned, 0);
+        assert_eq!(last("/progress/srv").unwrap().files_scanned, 1);
+        drop(again);
+        assert!(current("/progress/srv").is_none());
+    }
+}
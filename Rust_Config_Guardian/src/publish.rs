@@ -0,0 +1,265 @@
+//! Baselines published through the collector, for a change meant for a
+//! whole fleet: `accept --publish` on one host sends its snapshot to the
+//! collector as the next version of its profile's baseline, and monitors
+//! started with `--baseline-subscribe` on the other hosts take in each
+//! newer version as their baseline and compare against it. Each version is
+//! signed with HMAC-SHA256 under a key the publisher and the subscribers
+//! share, which the collector doesn't need: it keeps the versions in order,
+//! but can't make one up or change one a subscriber would take.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+use crate::seal::{checksum, hex};
+use crate::{Snapshot, http};
+
+/// The profile a host without `--profile` publishes and subscribes for.
+pub const DEFAULT_PROFILE: &str = "default";
+/// How often a subscribed monitor asks for a newer baseline, unless
+/// `--baseline-poll` says otherwise.
+pub const SUBSCRIBE_INTERVAL: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A version of a profile's baseline, as the collector keeps it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Published {
+    pub profile: String,
+    /// One more than the version before it.
+    pub version: u64,
+    pub published: DateTime<Utc>,
+    /// The host it was published from.
+    pub host: String,
+    /// The snapshot, as JSON, so that it is signed as it travels.
+    pub snapshot: serde_json::Value,
+    /// HMAC-SHA256 of the rest, in hex.
+    pub signature: String,
+}
+
+impl Published {
+    /// Version `version` of `profile`'s baseline, `snapshot` as published
+    /// from `host` now and signed with `key`.
+    pub fn new(profile: &str, version: u64, host: &str, snapshot: &Snapshot, key: &str) -> Self {
+        let mut published = Published {
+            profile: profile.to_string(),
+            version,
+            published: Utc::now(),
+            host: host.to_string(),
+            snapshot: serde_json::to_value(snapshot).expect("snapshots serialize to JSON"),
+            signature: String::new(),
+        };
+        published.signature = published.sign(key);
+        published
+    }
+
+    /// What the signature covers: the snapshot by checksum, and everything
+    /// said about it. Its JSON comes out the same however it was parsed, as
+    /// object keys are written in order.
+    fn signed(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.profile,
+            self.version,
+            self.published.to_rfc3339(),
+            self.host,
+            checksum(self.snapshot.to_string().as_bytes())
+        )
+    }
+
+    fn sign(&self, key: &str) -> String {
+        hex(&hmac(key.as_bytes(), self.signed().as_bytes()))
+    }
+
+    /// The snapshot, if the signature is `key`'s.
+    pub fn verify(&self, key: &str) -> Result<Snapshot, String> {
+        if !same(self.sign(key).as_bytes(), self.signature.as_bytes()) {
+            return Err(format!(
+                "the signature of version {} doesn't match the baseline key",
+                self.version
+            ));
+        }
+        serde_json::from_value(self.snapshot.clone())
+            .map_err(|e| format!("version {} is not a snapshot: {}", self.version, e))
+    }
+}
+
+/// HMAC-SHA256 of `message` under `key` (RFC 2104).
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+/// Whether `a` and `b` are the same, compared in constant time so that
+/// response times don't tell how much of a guess was right.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// A profile's baselines at a collector.
+#[derive(Clone)]
+pub struct Baselines {
+    /// The collector's base URL, e.g. `http://collector:8080`.
+    pub url: String,
+    pub token: String,
+    pub profile: String,
+    /// What versions are signed with.
+    pub key: String,
+}
+
+impl Baselines {
+    fn url(&self) -> String {
+        format!(
+            "{}/baselines/{}",
+            self.url.trim_end_matches('/'),
+            self.profile
+        )
+    }
+
+    fn request(&self, method: &str) -> Result<ureq::Request, String> {
+        Ok(http::agent(TIMEOUT)?
+            .request(method, &self.url())
+            .set("Authorization", &format!("Bearer {}", self.token)))
+    }
+
+    /// The latest version published, if any.
+    pub fn latest(&self) -> Result<Option<Published>, String> {
+        let target = http::origin(&self.url).to_string();
+        match self.request("GET")?.call() {
+            Ok(response) => serde_json::from_reader(response.into_reader())
+                .map(Some)
+                .map_err(|e| format!("{} answered with no baseline: {}", target, e)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(format!("{}: {}", target, http::describe(&e))),
+        }
+    }
+
+    /// Publish `snapshot` from `host` as the version after the latest,
+    /// returning it. Another host publishing first is a conflict, which
+    /// the collector refuses rather than have one version overwrite the
+    /// other.
+    pub fn publish(&self, host: &str, snapshot: &Snapshot) -> Result<Published, String> {
+        let version = self.latest()?.map_or(0, |latest| latest.version) + 1;
+        let published = Published::new(&self.profile, version, host, snapshot, &self.key);
+        let body = serde_json::to_string(&published).expect("baselines serialize to JSON");
+        let target = http::origin(&self.url);
+        match self
+            .request("PUT")?
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+        {
+            Ok(_) => Ok(published),
+            Err(ureq::Error::Status(409, response)) => Err(format!(
+                "{} refused version {} as a conflict: {}",
+                target,
+                version,
+                response.into_string().unwrap_or_default().trim()
+            )),
+            Err(e) => Err(format!("{}: {}", target, http::describe(&e))),
+        }
+    }
+}
+
+/// What a subscriber makes of the latest version published, `applied`
+/// being the version it has already: the snapshot to take in, if it is a
+/// newer version signed with `key`, or why it can't be taken in. A version
+/// older than the one applied is refused rather than rolled back to, and
+/// one signed with another key, or for another profile, is refused too.
+pub fn judge(
+    published: &Published,
+    profile: &str,
+    applied: Option<u64>,
+    key: &str,
+) -> Result<Option<Snapshot>, String> {
+    if published.profile != profile {
+        return Err(format!(
+            "the collector answered with the baseline of profile {}, not {}",
+            published.profile, profile
+        ));
+    }
+    match applied {
+        Some(applied) if published.version == applied => return Ok(None),
+        Some(applied) if published.version < applied => {
+            return Err(format!(
+                "the collector's latest baseline for {} is version {}, older than version {} here; it is not rolled back to",
+                profile, published.version, applied
+            ));
+        }
+        _ => {}
+    }
+    published.verify(key).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileHash;
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(
+            hex(&hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // Keys longer than a block are hashed first.
+        assert_eq!(
+            hex(&hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn only_newer_versions_signed_with_the_key_are_taken() {
+        let snapshot = Snapshot {
+            entries: vec![FileHash {
+                path: "etc/app.conf".into(),
+                hash: "1".into(),
+                ..FileHash::default()
+            }],
+            ..Snapshot::default()
+        };
+        let published = Published::new("nginx", 3, "web-01", &snapshot, "k3y");
+        // As it arrives, parsed back from the collector.
+        let published: Published =
+            serde_json::from_str(&serde_json::to_string(&published).unwrap()).unwrap();
+        let taken = judge(&published, "nginx", Some(2), "k3y").unwrap().unwrap();
+        assert_eq!(taken.entries, snapshot.entries);
+        assert!(judge(&published, "nginx", None, "k3y").unwrap().is_some());
+        assert!(
+            judge(&published, "nginx", Some(3), "k3y")
+                .unwrap()
+                .is_none()
+        );
+
+        let older = judge(&published, "nginx", Some(4), "k3y").unwrap_err();
+        assert!(older.contains("older than version 4"), "{}", older);
+        let forged = judge(&published, "nginx", Some(2), "other").unwrap_err();
+        assert!(forged.contains("signature"), "{}", forged);
+        assert!(judge(&published, "sshd", Some(2), "k3y").is_err());
+
+        let mut tampered = published.clone();
+        tampered.snapshot["entries"][0]["hash"] = "2".into();
+        assert!(tampered.verify("k3y").is_err());
+        let mut renumbered = published;
+        renumbered.version = 9;
+        assert!(renumbered.verify("k3y").is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! `recover`: find what a crash or a full disk leaves in the state
+//! directory, which makes later commands fail with errors that don't point
+//! at it, and with `--apply` put it right. Nothing is changed without
+//! `--apply`, and every fix is printed and logged.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::daemon::{self, control_path, info_path};
+use config_guardian::archive::Archive;
+use config_guardian::history::{HISTORY_FILE, HistoryEntry, RUNS_FILE};
+use config_guardian::{
+    GuardianError, Snapshot, annotation, cache, config, exception, identity, partial_path,
+};
+
+/// A temporary file untouched for this long was left by a write that never
+/// finished, rather than being written now.
+const ORPHAN_AGE: Duration = Duration::from_secs(60);
+
+/// Something wrong in the state directory.
+enum Problem {
+    /// The snapshot doesn't load; `restore` is the newest archived
+    /// snapshot that does.
+    CorruptSnapshot {
+        path: PathBuf,
+        error: String,
+        restore: Option<PathBuf>,
+    },
+    /// A file written aside to be renamed into place by a run that stopped
+    /// before it was.
+    OrphanedTemp(PathBuf),
+    /// The PID file of a monitor that exited without removing it.
+    StaleLock { pid_file: PathBuf, pid: u32 },
+    /// The history ends with a record cut short, on `line`, after the
+    /// first `keep` bytes.
+    TornHistory {
+        path: PathBuf,
+        keep: u64,
+        line: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::CorruptSnapshot {
+                error,
+                restore: Some(restore),
+                ..
+            } => write!(f, "{}; {} loads", error, restore.display()),
+            Problem::CorruptSnapshot {
+                error,
+                restore: None,
+                ..
+            } => write!(f, "{}, and no archived snapshot loads", error),
+            Problem::OrphanedTemp(path) => {
+                write!(
+                    f,
+                    "{} is left from a write that never finished",
+                    path.display()
+                )
+            }
+            Problem::StaleLock { pid_file, pid } => write!(
+                f,
+                "{} names PID {}, which is no longer running",
+                pid_file.display(),
+                pid
+            ),
+            Problem::TornHistory { path, line, .. } => write!(
+                f,
+                "{} ends with a partial record on line {}",
+                path.display(),
+                line
+            ),
+        }
+    }
+}
+
+impl Problem {
+    fn kind(&self) -> &'static str {
+        match self {
+            Problem::CorruptSnapshot { .. } => "corrupt_snapshot",
+            Problem::OrphanedTemp(_) => "orphaned_temp",
+            Problem::StaleLock { .. } => "stale_lock",
+            Problem::TornHistory { .. } => "torn_history",
+        }
+    }
+
+    /// Put the problem right; says what was done.
+    fn fix(&self) -> Result<String, String> {
+        match self {
+            Problem::CorruptSnapshot { restore: None, .. } => Err(
+                "there is nothing to restore it from; move it aside and take a new snapshot".into(),
+            ),
+            Problem::CorruptSnapshot {
+                path,
+                restore: Some(restore),
+                ..
+            } => {
+                let snapshot = Snapshot::load(restore).map_err(|e| e.to_string())?;
+                // Kept, in case there is more in it than in the archive.
+                let mut aside = path.as_os_str().to_owned();
+                aside.push(".corrupt");
+                let aside = PathBuf::from(aside);
+                fs::rename(path, &aside)
+                    .map_err(|e| format!("could not move {} aside: {}", path.display(), e))?;
+                snapshot.save(path).map_err(|e| e.to_string())?;
+                Ok(format!(
+                    "moved {} aside as {} and restored it from {}",
+                    path.display(),
+                    aside.display(),
+                    restore.display()
+                ))
+            }
+            Problem::OrphanedTemp(path) => fs::remove_file(path)
+                .map(|()| format!("removed {}", path.display()))
+                .map_err(|e| format!("could not remove {}: {}", path.display(), e)),
+            Problem::StaleLock { pid_file, .. } => {
+                fs::remove_file(pid_file)
+                    .map_err(|e| format!("could not remove {}: {}", pid_file.display(), e))?;
+                // The monitor's details and socket went with it.
+                let mut removed = vec![pid_file.display().to_string()];
+                for leftover in [info_path(pid_file), control_path(pid_file)] {
+                    if fs::remove_file(&leftover).is_ok() {
+                        removed.push(leftover.display().to_string());
+                    }
+                }
+                Ok(format!("removed {}", removed.join(", ")))
+            }
+            Problem::TornHistory { path, keep, line } => fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(*keep))
+                .map(|()| format!("cut {} back to before line {}", path.display(), line))
+                .map_err(|e| format!("could not truncate {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Look for problems, printing and logging each, and fix them with
+/// `apply`. Returns the exit code: 0 if there were none or all were fixed,
+/// 1 if any are left.
+pub fn run(pid_file: &Path, apply: bool) -> i32 {
+    let problems = find(pid_file);
+    if problems.is_empty() {
+        println!("No problems found in the state directory.");
+        return 0;
+    }
+    let mut left = 0;
+    for problem in &problems {
+        println!("Found: {}", problem);
+        log::warn!(event = "recover_found", kind = problem.kind(); "{}", problem);
+        if !apply {
+            left += 1;
+            continue;
+        }
+        match problem.fix() {
+            Ok(done) => {
+                println!("  Fixed: {}", done);
+                log::info!(
+                    event = "recover_fixed",
+                    kind = problem.kind();
+                    "Recovered: {}", done
+                );
+            }
+            Err(e) => {
+                left += 1;
+                println!("  Not fixed: {}", e);
+                log::error!(
+                    event = "recover_failed",
+                    kind = problem.kind();
+                    "Could not recover: {}", e
+                );
+            }
+        }
+    }
+    match (apply, left) {
+        (_, 0) => 0,
+        (false, _) => {
+            println!("\nRun `config-guardian recover --apply` to fix them.");
+            1
+        }
+        (true, _) => 1,
+    }
+}
+
+/// Every problem in the state directory, in the order they are fixed:
+/// restoring the snapshot writes through the temporary file it may have
+/// left, which is removed first.
+fn find(pid_file: &Path) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = orphaned_temps()
+        .into_iter()
+        .map(Problem::OrphanedTemp)
+        .collect();
+    problems.extend(corrupt_snapshot());
+    if let Some(pid) = daemon::stale_pid(pid_file) {
+        problems.push(Problem::StaleLock {
+            pid_file: pid_file.to_path_buf(),
+            pid,
+        });
+    }
+    problems.extend(torn_history());
+    problems
+}
+
+fn corrupt_snapshot() -> Option<Problem> {
+    let path = &config::settings().snapshot_file;
+    let error = match Snapshot::load(path) {
+        Err(e @ GuardianError::SnapshotCorrupt { .. }) => e.to_string(),
+        _ => return None,
+    };
+    let restore = Archive::of(path)
+        .list()
+        .into_iter()
+        .find(|copy| Snapshot::load(copy).is_ok());
+    Some(Problem::CorruptSnapshot {
+        path: path.clone(),
+        error,
+        restore,
+    })
+}
+
+/// The files written aside to be renamed into place that haven't been
+/// touched for [`ORPHAN_AGE`].
+fn orphaned_temps() -> Vec<PathBuf> {
+    let mut temps = vec![partial_path(&config::settings().snapshot_file)];
+    temps.extend(
+        [
+            HISTORY_FILE,
+            RUNS_FILE,
+            annotation::ANNOTATIONS_FILE,
+            cache::CACHE_FILE,
+            exception::EXCEPTIONS_FILE,
+        ]
+        .map(|file| PathBuf::from(format!("{}.tmp", file))),
+    );
+    // Each run writes its own, named by its PID.
+    let prefix = format!("{}.", identity::INSTANCE_ID_FILE);
+    temps.extend(
+        fs::read_dir(".")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(&prefix) && name.ends_with(".tmp"))
+            .map(PathBuf::from),
+    );
+    let cutoff = SystemTime::now() - ORPHAN_AGE;
+    temps
+        .into_iter()
+        .filter(|temp| {
+            fs::metadata(temp)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified < cutoff)
+        })
+        .collect()
+}
+
+/// The history, if its last line isn't a whole record: a write cut short.
+fn torn_history() -> Option<Problem> {
+    let data = fs::read(HISTORY_FILE).ok()?;
+    let body = data.strip_suffix(b"\n").unwrap_or(&data);
+    let start = body.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let last = &body[start..];
+    if last.iter().all(u8::is_ascii_whitespace)
+        || serde_json::from_slice::<HistoryEntry>(last).is_ok()
+    {
+        return None;
+    }
+    Some(Problem::TornHistory {
+        path: PathBuf::from(HISTORY_FILE),
+        keep: start as u64,
+        line: body[..start].iter().filter(|&&b| b == b'\n').count() + 1,
+    })
+}
@@ -0,0 +1,106 @@
+//! Redaction of the files `redact` lists. Their content is compared as any
+//! other file's, but what leaves the host, alerts, hooks, uploads, logs,
+//! the status API and the history, names them by a placeholder in place of
+//! the file name: `/etc/app/secrets/[redacted:3f2a9c1e]`. The placeholder
+//! is derived from the path, so the same file has the same one from report
+//! to report. What is printed to the terminal is redacted too, unless
+//! `--no-redact` was given there.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::annotation::Annotation;
+use crate::{Drift, DriftReport, config, utils};
+
+/// `path`, or its placeholder if it's redacted.
+pub fn path(path: &str) -> Cow<'_, str> {
+    if !config::settings().is_redacted(path) {
+        return Cow::Borrowed(path);
+    }
+    let digest = format!("{:x}", Sha256::digest(path.as_bytes()));
+    let placeholder = format!("[redacted:{}]", &digest[..8]);
+    match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            Cow::Owned(utils::path_text(&parent.join(placeholder)))
+        }
+        _ => Cow::Owned(placeholder),
+    }
+}
+
+/// [`path`] for the terminal: the path itself with `--no-redact`.
+pub fn shown(path: &str) -> Cow<'_, str> {
+    if config::settings().reveal_redacted {
+        Cow::Borrowed(path)
+    } else {
+        self::path(path)
+    }
+}
+
+/// `drifts` with the redacted paths replaced.
+pub fn drifts(drifts: &[Drift]) -> Vec<Drift> {
+    drifts.iter().map(|d| redacted(d, path)).collect()
+}
+
+/// [`drifts`] for the terminal.
+pub fn shown_drifts(drifts: &[Drift]) -> Vec<Drift> {
+    drifts.iter().map(|d| redacted(d, shown)).collect()
+}
+
+/// `annotations`, by path, with the redacted paths replaced.
+pub fn annotations(annotations: &BTreeMap<String, Annotation>) -> BTreeMap<String, Annotation> {
+    annotations
+        .iter()
+        .map(|(p, annotation)| (path(p).into_owned(), annotation.clone()))
+        .collect()
+}
+
+fn redacted(drift: &Drift, how: fn(&str) -> Cow<'_, str>) -> Drift {
+    Drift {
+        path: how(&drift.path).into_owned(),
+        ..drift.clone()
+    }
+}
+
+/// `report` with the redacted paths replaced, wherever it names them.
+pub fn report(report: &DriftReport) -> DriftReport {
+    DriftReport {
+        drifts: drifts(&report.drifts),
+        warnings: report
+            .warnings
+            .iter()
+            .map(|w| crate::ScanWarning {
+                path: path(&w.path).into_owned(),
+                ..w.clone()
+            })
+            .collect(),
+        volatile: report
+            .volatile
+            .iter()
+            .map(|p| path(p).into_owned())
+            .collect(),
+        unreadable: drifts(&report.unreadable),
+        excepted: report
+            .excepted
+            .iter()
+            .map(|e| crate::exception::Excepted {
+                drift: redacted(&e.drift, path),
+                ..e.clone()
+            })
+            .collect(),
+        hashes: report
+            .hashes
+            .iter()
+            .map(|(p, hashes)| (path(p).into_owned(), hashes.clone()))
+            .collect(),
+        chunks: report
+            .chunks
+            .iter()
+            .map(|(p, diff)| (path(p).into_owned(), diff.clone()))
+            .collect(),
+        annotations: annotations(&report.annotations),
+        ..report.clone()
+    }
+}
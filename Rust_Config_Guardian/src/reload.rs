@@ -0,0 +1,191 @@
+//! `monitor`'s live reload of its config file. The file is read again every
+//! couple of seconds, and when its content changed it is parsed and checked
+//! as at startup, then taken up: excludes, severity rules and the other
+//! scan settings, alert channels, the alert cooldown and the debounce. A
+//! change to what only a restart can change, such as the directories
+//! watched or the snapshot file ([`Config::restart_only`]), is refused as a
+//! whole, as is a file that doesn't parse or check out; either way the
+//! config in effect stays as it was, and the monitor says why.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::GuardianError;
+use crate::alert::AlertChannels;
+use crate::config::{self, Config, Settings};
+use crate::utils::path_text;
+
+/// How often the config file is read to see whether it changed.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a monitor takes up from its config file, with what the command
+/// line set on top.
+pub struct Reloaded {
+    pub settings: Settings,
+    pub alerts: Option<AlertChannels>,
+    pub alert_cooldown: Option<Duration>,
+    pub debounce: Duration,
+}
+
+/// Builds what a monitor takes up from a config file as read, as its
+/// startup did from the file then.
+pub type Prepare = Box<dyn Fn(Config) -> Result<Reloaded, String> + Send + Sync>;
+
+/// The config file a monitor was started with, to take up changes to.
+pub struct ConfigReload {
+    path: PathBuf,
+    /// The config in effect at startup, as the file and the environment had
+    /// it.
+    config: Config,
+    checksum: String,
+    prepare: Prepare,
+}
+
+impl ConfigReload {
+    /// Watch the file at `path`, which `config` was loaded from, building
+    /// each new version with `prepare`.
+    pub fn new(path: &Path, config: Config, prepare: Prepare) -> Result<Self, GuardianError> {
+        Ok(ConfigReload {
+            path: path.to_path_buf(),
+            checksum: checksum(path)?,
+            config,
+            prepare,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The SHA-256 of the file as it was loaded at startup.
+    pub fn checksum(&self) -> &str {
+        &self.checksum
+    }
+}
+
+impl fmt::Debug for ConfigReload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigReload")
+            .field("path", &self.path)
+            .field("checksum", &self.checksum)
+            .finish_non_exhaustive()
+    }
+}
+
+fn checksum(path: &Path) -> Result<String, GuardianError> {
+    let content = std::fs::read(path).map_err(GuardianError::io(path))?;
+    Ok(format!("{:x}", Sha256::digest(&content)))
+}
+
+/// Monitor's repeated look at its config file.
+pub(crate) struct ConfigWatch<'a> {
+    reload: &'a ConfigReload,
+    /// The config in effect.
+    config: Config,
+    /// The checksum of the file when last read, taken up or not, so that
+    /// each version is looked at once; or why it couldn't be read.
+    seen: Result<String, String>,
+    next: Instant,
+}
+
+impl<'a> ConfigWatch<'a> {
+    pub(crate) fn new(reload: &'a ConfigReload) -> Self {
+        ConfigWatch {
+            reload,
+            config: reload.config.clone(),
+            seen: Ok(reload.checksum.clone()),
+            next: Instant::now() + CHECK_INTERVAL,
+        }
+    }
+
+    pub(crate) fn due(&self) -> bool {
+        Instant::now() >= self.next
+    }
+
+    /// Read the file again, giving what to take up and its checksum when it
+    /// changed since the last look, or why the change can't be taken up.
+    pub(crate) fn check(&mut self) -> Option<Result<(Reloaded, String), String>> {
+        self.next = Instant::now() + CHECK_INTERVAL;
+        let path = &self.reload.path;
+        let found = checksum(path).map_err(|e| e.to_string());
+        if found == self.seen {
+            return None;
+        }
+        self.seen = found.clone();
+        let checksum = match found {
+            Ok(checksum) => checksum,
+            Err(e) => return Some(Err(e)),
+        };
+        let (_, config) = match config::load(Some(path)) {
+            Ok(loaded) => loaded,
+            Err(e) => return Some(Err(e)),
+        };
+        let restart = self.config.restart_only(&config);
+        if !restart.is_empty() {
+            return Some(Err(format!(
+                "{} changed in {}, which only a restart takes up",
+                restart.join(", "),
+                path_text(path)
+            )));
+        }
+        match (self.reload.prepare)(config.clone()) {
+            Ok(reloaded) => {
+                self.config = config;
+                Some(Ok((reloaded, checksum)))
+            }
+            Err(e) => Some(Err(format!("Invalid config {}: {}", path_text(path), e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_taken_up_once_and_restart_only_ones_refused() {
+        let path =
+            std::env::temp_dir().join(format!("guardian-reload-{}.toml", std::process::id()));
+        std::fs::write(&path, "exclude = [\"*.bak\"]\n").unwrap();
+        let (_, config) = config::load(Some(&path)).unwrap();
+        let reload = ConfigReload::new(
+            &path,
+            config,
+            Box::new(|config| {
+                Ok(Reloaded {
+                    settings: config.settings()?,
+                    alerts: None,
+                    alert_cooldown: None,
+                    debounce: config.monitor.debounce.unwrap_or_default(),
+                })
+            }),
+        )
+        .unwrap();
+        let mut watch = ConfigWatch::new(&reload);
+        assert!(watch.check().is_none());
+
+        std::fs::write(
+            &path,
+            "exclude = [\"*.tmp\"]\n[monitor]\ndebounce = \"1s\"\n",
+        )
+        .unwrap();
+        let (reloaded, checksum) = watch.check().unwrap().unwrap();
+        assert_eq!(reloaded.settings.exclude[0].as_str(), "*.tmp");
+        assert_eq!(reloaded.debounce, Duration::from_secs(1));
+        assert_ne!(checksum, reload.checksum());
+        assert!(watch.check().is_none());
+
+        std::fs::write(&path, "exclude = [\"[\"]\n").unwrap();
+        assert!(watch.check().unwrap().is_err());
+        assert!(watch.check().is_none());
+
+        std::fs::write(&path, "directories = [\"/srv\"]\n").unwrap();
+        let Some(Err(refused)) = watch.check() else {
+            panic!("a change of directories was taken up");
+        };
+        assert!(refused.starts_with("directories changed"), "{}", refused);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,477 @@
+//! Remote targets, `sftp://[USER@]HOST[:PORT]/PATH`: directories on machines
+//! that can't run config-guardian but serve SFTP, as some appliances do.
+//! Their files are listed and read over the connection and recorded under
+//! the target, like a local directory's, so `compare` reads them the same
+//! way again. Connecting needs a build with the `sftp` feature.
+
+use crate::GuardianError;
+
+const SCHEME: &str = "sftp://";
+const DEFAULT_PORT: u16 = 22;
+
+/// Whether `target` names a remote directory rather than a local one.
+pub fn is_remote(target: &str) -> bool {
+    target.starts_with(SCHEME)
+}
+
+/// Where a remote target is, and who logs in to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    /// The directory on the host, always absolute.
+    pub path: String,
+}
+
+impl Target {
+    /// Parse `sftp://[USER@]HOST[:PORT]/PATH`, also written as scp does,
+    /// `sftp://USER@HOST:/PATH`. The user defaults to the local one.
+    pub fn parse(target: &str) -> Result<Target, String> {
+        let invalid = |why: &str| {
+            format!(
+                "invalid remote target '{}': {} (expected sftp://[USER@]HOST[:PORT]/PATH)",
+                target, why
+            )
+        };
+        let rest = target
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| invalid("not an sftp:// URL"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => return Err(invalid("no directory")),
+        };
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) if !user.is_empty() => (user.to_string(), host),
+            Some(_) => return Err(invalid("empty user name")),
+            None => (
+                local_user().ok_or_else(|| invalid("no user name"))?,
+                authority,
+            ),
+        };
+        let (host, port) = match host.strip_prefix('[') {
+            // IPv6 literals need brackets, as in `sftp://[::1]:2222/etc`.
+            Some(bracketed) => {
+                let (host, port) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unclosed '['"))?;
+                (host, port.strip_prefix(':').unwrap_or(port))
+            }
+            None => host.split_once(':').unwrap_or((host, "")),
+        };
+        if host.is_empty() {
+            return Err(invalid("no host"));
+        }
+        let port = match port {
+            "" => DEFAULT_PORT,
+            port => port.parse().map_err(|_| invalid("invalid port"))?,
+        };
+        let path = match path.trim_end_matches('/') {
+            "" => "/",
+            trimmed => trimmed,
+        };
+        Ok(Target {
+            user,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+fn local_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+}
+
+/// Check that `target` is a remote target that can be scanned, without
+/// connecting to it.
+pub fn check(target: &str) -> Result<(), GuardianError> {
+    Target::parse(target).map_err(GuardianError::Config)?;
+    #[cfg(not(feature = "sftp"))]
+    return Err(unsupported(target));
+    #[cfg(feature = "sftp")]
+    Ok(())
+}
+
+#[cfg(not(feature = "sftp"))]
+fn unsupported(target: &str) -> GuardianError {
+    GuardianError::Config(format!(
+        "{} is a remote target, but this build of config-guardian can't connect to one; build it with --features sftp",
+        target
+    ))
+}
+
+#[cfg(not(feature = "sftp"))]
+pub(crate) fn scan(
+    target: &str,
+    _baseline: Option<&crate::Snapshot>,
+) -> Result<crate::Scan, GuardianError> {
+    Err(unsupported(target))
+}
+
+#[cfg(feature = "sftp")]
+pub(crate) use sftp::scan;
+
+#[cfg(feature = "sftp")]
+mod sftp {
+    use std::collections::{BTreeMap, HashSet};
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    use base64::Engine;
+    use chrono::{DateTime, Utc};
+    use ssh2::{CheckResult, HashType, KnownHostFileKind, Session, Sftp};
+
+    use super::Target;
+    use crate::config;
+    use crate::{
+        EntryType, FileHash, GuardianError, Permissions, Scan, ScanOptions, ScanStats, ScanWarning,
+        Snapshot, SnapshotMetadata, WarningKind,
+    };
+    use crate::{identity, invoker};
+
+    /// How long to wait for the host to answer, and for each reply after.
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn failed(target: &Target, doing: &str, e: impl std::fmt::Display) -> GuardianError {
+        GuardianError::Other(format!("{} {}:{}: {}", doing, target.host, target.port, e))
+    }
+
+    fn known_hosts_file() -> Option<PathBuf> {
+        if let Some(path) = &config::settings().sftp.known_hosts {
+            return Some(path.clone());
+        }
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+        Some(Path::new(&home).join(".ssh").join("known_hosts"))
+    }
+
+    /// Refuse a host whose key isn't the one `known_hosts` holds for it, or
+    /// that it holds none for.
+    fn check_host_key(session: &Session, target: &Target) -> Result<(), GuardianError> {
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| failed(target, "No host key from", "the handshake gave none"))?;
+        let fingerprint = session
+            .host_key_hash(HashType::Sha256)
+            .map(|hash| {
+                format!(
+                    "SHA256:{}",
+                    base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash)
+                )
+            })
+            .unwrap_or_default();
+        let file = known_hosts_file().ok_or_else(|| {
+            GuardianError::Config(
+                "No known_hosts file to check host keys against; set --known-hosts".into(),
+            )
+        })?;
+        let mut known = session
+            .known_hosts()
+            .map_err(|e| failed(target, "Could not check the host key of", e))?;
+        // A file that doesn't exist yet knows no hosts.
+        if file.exists() {
+            known
+                .read_file(&file, KnownHostFileKind::OpenSSH)
+                .map_err(|e| {
+                    GuardianError::Config(format!("Could not read {}: {}", file.display(), e))
+                })?;
+        }
+        match known.check_port(&target.host, target.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => Err(GuardianError::Config(format!(
+                "The host key of {} ({}) is not in {}. Check the fingerprint with its administrator, then add it, e.g. with: ssh-keyscan -p {} {} >> {}",
+                target.host,
+                fingerprint,
+                file.display(),
+                target.port,
+                target.host,
+                file.display()
+            ))),
+            CheckResult::Mismatch => Err(GuardianError::Config(format!(
+                "The host key of {} ({}) does not match the one in {}: the host was reinstalled, or the connection is being intercepted. Not connecting.",
+                target.host,
+                fingerprint,
+                file.display()
+            ))),
+            CheckResult::Failure => Err(failed(
+                target,
+                "Could not check the host key of",
+                "known_hosts lookup failed",
+            )),
+        }
+    }
+
+    fn connect(target: &Target) -> Result<(Session, Sftp), GuardianError> {
+        let address = (target.host.as_str(), target.port)
+            .to_socket_addrs()
+            .map_err(|e| failed(target, "Could not resolve", e))?
+            .next()
+            .ok_or_else(|| failed(target, "Could not resolve", "no addresses"))?;
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+            .map_err(|e| failed(target, "Could not connect to", e))?;
+        let mut session =
+            Session::new().map_err(|e| failed(target, "Could not start SSH to", e))?;
+        let timeout = config::settings().read_timeout.unwrap_or(CONNECT_TIMEOUT);
+        session.set_timeout(timeout.as_millis().min(u32::MAX as u128) as u32);
+        session.set_tcp_stream(stream);
+        session
+            .handshake()
+            .map_err(|e| failed(target, "SSH handshake failed with", e))?;
+        check_host_key(&session, target)?;
+
+        let identity = &config::settings().sftp.identity;
+        let login = match identity {
+            Some(key) => session.userauth_pubkey_file(&target.user, None, key, None),
+            None => session.userauth_agent(&target.user),
+        };
+        let refused = match login {
+            Ok(()) if session.authenticated() => None,
+            Ok(()) => Some("the server did not accept it".to_string()),
+            Err(e) => Some(e.message().to_string()),
+        };
+        if let Some(why) = refused {
+            let with = match identity {
+                Some(key) => format!("the key {}", key.display()),
+                None => "the SSH agent's keys (give one with --identity)".to_string(),
+            };
+            return Err(GuardianError::Config(format!(
+                "Could not log in to {} as {} with {}: {}",
+                target.host, target.user, with, why
+            )));
+        }
+        let sftp = session
+            .sftp()
+            .map_err(|e| failed(target, "Could not start SFTP on", e))?;
+        Ok((session, sftp))
+    }
+
+    /// Whether the connection is still up, after a failed read: a file that
+    /// can't be read is a warning, but a dropped connection ends the scan.
+    fn still_connected(sftp: &Sftp, target: &Target) -> bool {
+        sftp.stat(Path::new(&target.path)).is_ok()
+    }
+
+    fn lost(target: &Target, path: &str, e: impl std::fmt::Display) -> GuardianError {
+        GuardianError::Other(format!(
+            "Lost the connection to {} while reading {}: {}; nothing was saved.",
+            target.host, path, e
+        ))
+    }
+
+    /// The hash of the remote file at `path`, streamed, and its length.
+    fn read(sftp: &Sftp, path: &str) -> std::io::Result<(String, u64)> {
+        let mut file = sftp.open(Path::new(path)).map_err(std::io::Error::from)?;
+        config::settings().hash.digest_reader(&mut file)
+    }
+
+    fn modified(stat: &ssh2::FileStat) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(stat.mtime? as i64, 0)
+    }
+
+    fn permissions(stat: &ssh2::FileStat) -> Option<Permissions> {
+        Some(Permissions {
+            mode: stat.perm? & 0o7777,
+            uid: stat.uid?,
+            gid: stat.gid?,
+            // SFTP gives the remote host's IDs but not its names for them,
+            // so they are compared by ID.
+            ..Permissions::default()
+        })
+    }
+
+    /// Scan the remote target `dir` as [`crate::scan_directory`] scans a
+    /// local directory: the files directly in it, hashed in full, with their
+    /// size, modification time, permissions and type.
+    pub(crate) fn scan(dir: &str, baseline: Option<&Snapshot>) -> Result<Scan, GuardianError> {
+        let started = Instant::now();
+        let target = Target::parse(dir).map_err(GuardianError::Config)?;
+        let (_session, sftp) = connect(&target)?;
+        let listed = sftp
+            .readdir(Path::new(&target.path))
+            .map_err(|e| failed(&target, &format!("Could not list {} on", target.path), e))?;
+        let settings = config::settings();
+        let root = dir.trim_end_matches('/');
+        let remote_root = target.path.trim_end_matches('/');
+        let mut stats = ScanStats::default();
+        let mut warnings = Vec::new();
+        let mut entries = Vec::new();
+        let mut subdirectories = HashSet::new();
+        let mut listed: Vec<(String, ssh2::FileStat)> = listed
+            .into_iter()
+            .filter_map(|(path, stat)| {
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                Some((name, stat))
+            })
+            .collect();
+        listed.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, stat) in listed {
+            let recorded = format!("{}/{}", root, name);
+            let remote = format!("{}/{}", remote_root, name);
+            if let Some(pattern) = settings.excluded_by(Path::new(&recorded)) {
+                log::debug!(event = "scan_decision", path = recorded.as_str(); "{}: excluded by glob {}", recorded, pattern);
+                stats.excluded += 1;
+                continue;
+            }
+            let (entry_type, link_target, stat) = if stat.file_type().is_symlink() {
+                let link_target = sftp
+                    .readlink(Path::new(&remote))
+                    .ok()
+                    .map(|t| t.to_string_lossy().into_owned());
+                // Followed, like a local scan follows it.
+                match sftp.stat(Path::new(&remote)) {
+                    Ok(stat) => (EntryType::Symlink, link_target, stat),
+                    Err(_) => {
+                        stats.special += 1;
+                        warnings.push(ScanWarning {
+                            kind: WarningKind::Special,
+                            path: recorded,
+                            error: None,
+                        });
+                        continue;
+                    }
+                }
+            } else {
+                (EntryType::File, None, stat)
+            };
+            if stat.is_dir() {
+                stats.dirs_seen += 1;
+                subdirectories.insert(recorded.clone());
+                continue;
+            }
+            if !stat.is_file() {
+                stats.special += 1;
+                warnings.push(ScanWarning {
+                    kind: WarningKind::Special,
+                    path: recorded,
+                    error: None,
+                });
+                continue;
+            }
+            match read(&sftp, &remote) {
+                Ok((hash, bytes)) => {
+                    stats.files_hashed += 1;
+                    stats.bytes_read += bytes;
+                    entries.push(FileHash {
+                        path: recorded,
+                        hash,
+                        size: stat.size,
+                        modified: modified(&stat),
+                        permissions: permissions(&stat),
+                        entry_type: Some(entry_type),
+                        link_target,
+                        ..FileHash::default()
+                    });
+                }
+                Err(e) if !still_connected(&sftp, &target) => {
+                    return Err(lost(&target, &remote, e));
+                }
+                Err(e) => {
+                    stats.unreadable += 1;
+                    warnings.push(ScanWarning {
+                        kind: WarningKind::Unreadable,
+                        path: recorded,
+                        error: Some(e.to_string()),
+                    });
+                }
+            }
+        }
+        // A recorded file that is now a directory says so, as in a local
+        // scan.
+        for entry in baseline.map(|b| b.entries.as_slice()).unwrap_or_default() {
+            if subdirectories.contains(&entry.path) {
+                entries.push(FileHash {
+                    path: entry.path.clone(),
+                    entry_type: Some(EntryType::Directory),
+                    ..FileHash::default()
+                });
+            }
+        }
+        if entries.len() + stats.excluded + stats.special + stats.unreadable == 0 {
+            warnings.push(ScanWarning {
+                kind: WarningKind::EmptyDirectory,
+                path: dir.to_string(),
+                error: None,
+            });
+        }
+        for warning in &warnings {
+            log::warn!(
+                event = "scan_warning",
+                kind:serde = warning.kind,
+                path = warning.path.as_str();
+                "{}", warning
+            );
+        }
+        stats.duration_ms = started.elapsed().as_millis() as u64;
+        Ok(Scan {
+            snapshot: Snapshot {
+                metadata: SnapshotMetadata {
+                    created: Some(Utc::now()),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    hash: settings.hash,
+                    directories: vec![dir.to_string()],
+                    stats: BTreeMap::from([(dir.to_string(), stats.clone())]),
+                    acls: false,
+                    tags: BTreeMap::new(),
+                    taken: BTreeMap::new(),
+                    options: BTreeMap::from([(
+                        dir.to_string(),
+                        // Normalizers only run over local files.
+                        ScanOptions {
+                            normalize: BTreeMap::new(),
+                            ..ScanOptions::current()
+                        },
+                    )]),
+                    invoker: Some(invoker::current().clone()),
+                    instance_id: Some(identity::current().to_string()),
+                    published: None,
+                },
+                entries,
+            },
+            warnings,
+            stats,
+            sampled: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_parse_as_urls_or_the_scp_way() {
+        let target = |user: &str, host: &str, port, path: &str| Target {
+            user: user.into(),
+            host: host.into(),
+            port,
+            path: path.into(),
+        };
+        assert_eq!(
+            Target::parse("sftp://admin@firewall:/config"),
+            Ok(target("admin", "firewall", 22, "/config"))
+        );
+        assert_eq!(
+            Target::parse("sftp://admin@10.0.0.1:2222/etc/app/"),
+            Ok(target("admin", "10.0.0.1", 2222, "/etc/app"))
+        );
+        assert_eq!(
+            Target::parse("sftp://root@[fe80::1]:2200/"),
+            Ok(target("root", "fe80::1", 2200, "/"))
+        );
+        for (bad, why) in [
+            ("sftp://admin@firewall", "no directory"),
+            ("sftp://admin@:22/config", "no host"),
+            ("sftp://admin@firewall:ssh/config", "invalid port"),
+            ("sftp://@firewall/config", "empty user name"),
+        ] {
+            let error = Target::parse(bad).unwrap_err();
+            assert!(error.contains(why), "{}: {}", bad, error);
+        }
+        assert!(is_remote("sftp://firewall/config"));
+        assert!(!is_remote("/etc/sftp://"));
+    }
+}
@@ -0,0 +1,541 @@
+//! `report`: summaries of what the snapshot tracks, read from the snapshot
+//! alone, and of a fleet's drift, read from a collector's data directory.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::collector::{self, HostSummary};
+use crate::{DriftKind, FileHash, GuardianError, Snapshot, config, redact, utils};
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+#[derive(Serialize)]
+struct Sized<'a> {
+    path: Cow<'a, str>,
+    bytes: u64,
+}
+
+/// What the snapshot holds for one directory.
+#[derive(Default, Serialize)]
+struct Inventory<'a> {
+    directory: String,
+    files: usize,
+    /// Total size of the files whose size is recorded.
+    bytes: u64,
+    /// Files recorded by versions that didn't store sizes.
+    #[serde(skip_serializing_if = "is_zero")]
+    size_unknown: usize,
+    /// Files with a sampled hash, of their ends and length only.
+    #[serde(skip_serializing_if = "is_zero")]
+    sampled: usize,
+    largest: Vec<Sized<'a>>,
+    oldest_modified: Option<DateTime<Utc>>,
+    newest_modified: Option<DateTime<Utc>>,
+    /// Left out by the last scan; unknown for snapshots without stats.
+    excluded: Option<usize>,
+    special: Option<usize>,
+    unreadable: Option<usize>,
+    subdirectories: Option<usize>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl<'a> Inventory<'a> {
+    fn add(&mut self, file: &'a FileHash) {
+        self.files += 1;
+        match file.size {
+            Some(size) => self.bytes += size,
+            None => self.size_unknown += 1,
+        }
+        if file.sampled.is_some() {
+            self.sampled += 1;
+        }
+        if let Some(modified) = file.modified {
+            self.oldest_modified = Some(self.oldest_modified.map_or(modified, |t| t.min(modified)));
+            self.newest_modified = Some(self.newest_modified.map_or(modified, |t| t.max(modified)));
+        }
+    }
+
+    /// Keep the `top` largest of `files`, biggest first.
+    fn keep_largest(&mut self, mut files: Vec<&'a FileHash>, top: usize) {
+        files.retain(|f| f.size.is_some());
+        files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        self.largest = files
+            .into_iter()
+            .take(top)
+            .map(|f| Sized {
+                path: redact::shown(&f.path),
+                bytes: f.size.unwrap_or(0),
+            })
+            .collect();
+    }
+}
+
+/// Roll up the snapshot per directory, with the `top` largest files of each.
+fn inventory(snapshot: &Snapshot, top: usize) -> Vec<Inventory<'_>> {
+    let mut by_dir: BTreeMap<String, Vec<&FileHash>> = snapshot
+        .metadata
+        .directories
+        .iter()
+        .filter(|dir| !crate::is_pattern(dir))
+        .map(|dir| (dir.clone(), Vec::new()))
+        .collect();
+    for file in &snapshot.entries {
+        let dir = Path::new(&file.path)
+            .parent()
+            .map_or_else(String::new, |p| p.to_string_lossy().into_owned());
+        by_dir.entry(dir).or_default().push(file);
+    }
+    by_dir
+        .into_iter()
+        .map(|(directory, files)| {
+            let stats = snapshot.metadata.stats.get(&directory);
+            let mut inventory = Inventory {
+                excluded: stats.map(|s| s.excluded),
+                special: stats.map(|s| s.special),
+                unreadable: stats.map(|s| s.unreadable),
+                subdirectories: stats.map(|s| s.dirs_seen),
+                directory,
+                ..Inventory::default()
+            };
+            for file in &files {
+                inventory.add(file);
+            }
+            inventory.keep_largest(files, top);
+            inventory
+        })
+        .collect()
+}
+
+/// Quote a CSV field if it needs it.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn count(n: Option<usize>) -> String {
+    n.map_or_else(|| "-".to_string(), |n| utils::human_count(n as u64))
+}
+
+/// Print `rows` as columns, left-aligning those `left` picks and
+/// right-aligning the others.
+fn print_aligned(rows: &[Vec<String>], left: fn(usize) -> bool) {
+    let mut widths = vec![0; rows.first().map_or(0, Vec::len)];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(i, (cell, &width))| match left(i) {
+                true => format!("{:<width$}", cell),
+                false => format!("{:>width$}", cell),
+            })
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+fn print_text(inventories: &[Inventory]) {
+    let time = |t: Option<DateTime<Utc>>| t.map_or_else(|| "-".to_string(), utils::format_time);
+    let header = [
+        "DIRECTORY",
+        "FILES",
+        "SIZE",
+        "EXCLUDED",
+        "SPECIAL",
+        "UNREADABLE",
+        "SUBDIRS",
+        "OLDEST MODIFIED",
+        "NEWEST MODIFIED",
+    ]
+    .map(String::from);
+    let rows: Vec<Vec<String>> = inventories
+        .iter()
+        .map(|i| {
+            vec![
+                i.directory.clone(),
+                count(Some(i.files)),
+                utils::human_size(i.bytes),
+                count(i.excluded),
+                count(i.special),
+                count(i.unreadable),
+                count(i.subdirectories),
+                time(i.oldest_modified),
+                time(i.newest_modified),
+            ]
+        })
+        .collect();
+    // The directory and times are left-aligned, the counts and sizes
+    // right-aligned.
+    print_aligned(
+        &std::iter::once(header.to_vec())
+            .chain(rows)
+            .collect::<Vec<_>>(),
+        |i| matches!(i, 0 | 7 | 8),
+    );
+    for inventory in inventories.iter().filter(|i| !i.largest.is_empty()) {
+        println!();
+        println!("Largest in {}:", inventory.directory);
+        for file in &inventory.largest {
+            println!("  {:>7}  {}", utils::human_size(file.bytes), file.path);
+        }
+    }
+    let size_unknown: usize = inventories.iter().map(|i| i.size_unknown).sum();
+    if size_unknown > 0 {
+        println!();
+        println!(
+            "{} files were recorded without a size; take a new snapshot to include them.",
+            utils::human_count(size_unknown as u64)
+        );
+    }
+    let sampled: usize = inventories.iter().map(|i| i.sampled).sum();
+    if sampled > 0 {
+        println!();
+        println!(
+            "{} files have sampled hashes, of their first and last {} and their length only.",
+            utils::human_count(sampled as u64),
+            utils::human_size(config::SAMPLE_HASH_SPAN)
+        );
+    }
+}
+
+fn print_csv(inventories: &[Inventory]) {
+    let time = |t: Option<DateTime<Utc>>| t.map_or_else(String::new, |t| t.to_rfc3339());
+    let count = |n: Option<usize>| n.map_or_else(String::new, |n| n.to_string());
+    println!(
+        "directory,files,bytes,largest_file,largest_bytes,oldest_modified,newest_modified,excluded,special,unreadable,subdirectories"
+    );
+    for i in inventories {
+        let largest = i.largest.first();
+        println!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&i.directory),
+            i.files,
+            i.bytes,
+            largest.map_or_else(String::new, |f| csv_field(&f.path)),
+            largest.map_or_else(String::new, |f| f.bytes.to_string()),
+            time(i.oldest_modified),
+            time(i.newest_modified),
+            count(i.excluded),
+            count(i.special),
+            count(i.unreadable),
+            count(i.subdirectories),
+        );
+    }
+}
+
+/// Print the inventory of the saved snapshot.
+pub fn run_inventory(format: ReportFormat, top: usize) -> Result<(), GuardianError> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let inventories = inventory(&snapshot, top);
+    match format {
+        ReportFormat::Text => print_text(&inventories),
+        ReportFormat::Csv => print_csv(&inventories),
+        ReportFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&inventories).expect("inventories serialize to JSON")
+        ),
+    }
+    Ok(())
+}
+
+/// Paths in the snapshot with the same content.
+#[derive(Serialize)]
+struct Duplicates<'a> {
+    hash: &'a str,
+    /// Size of each copy.
+    bytes: u64,
+    /// Bytes that one copy and links to it would save; hard links to the
+    /// same file don't count.
+    wasted: u64,
+    /// The hash is a sampled one, so the copies may differ between their
+    /// ends.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    sampled: bool,
+    paths: Vec<Cow<'a, str>>,
+}
+
+/// Group the snapshot's entries of at least `min_size` bytes by hash,
+/// keeping groups of more than one file (rather than links to one file),
+/// most wasted bytes first. Entries without a recorded size are left out.
+fn duplicates(snapshot: &Snapshot, min_size: u64) -> Vec<Duplicates<'_>> {
+    let mut by_hash: HashMap<&str, Vec<&FileHash>> = HashMap::new();
+    for file in &snapshot.entries {
+        if file.size.is_some_and(|size| size >= min_size) {
+            by_hash.entry(&file.hash).or_default().push(file);
+        }
+    }
+    let mut sets: Vec<Duplicates> = by_hash
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .filter_map(|(hash, files)| {
+            let bytes = files[0].size.unwrap_or(0);
+            // Entries without an inode are counted as separate files.
+            let mut inodes = HashSet::new();
+            let copies = files
+                .iter()
+                .filter(|f| f.inode.is_none_or(|i| inodes.insert((i.dev, i.ino))))
+                .count() as u64;
+            if copies < 2 {
+                return None;
+            }
+            let mut paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+            paths.sort_unstable();
+            Some(Duplicates {
+                hash,
+                bytes,
+                wasted: bytes * (copies - 1),
+                sampled: files.iter().any(|f| f.sampled.is_some()),
+                paths: paths.into_iter().map(redact::shown).collect(),
+            })
+        })
+        .collect();
+    sets.sort_by(|a, b| b.wasted.cmp(&a.wasted).then_with(|| a.paths.cmp(&b.paths)));
+    sets
+}
+
+/// Print the sets of files in the saved snapshot with the same content.
+pub fn run_duplicates(format: ReportFormat, min_size: u64) -> Result<(), GuardianError> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let sets = duplicates(&snapshot, min_size);
+    match format {
+        ReportFormat::Text if sets.is_empty() => println!("No duplicate files found."),
+        ReportFormat::Text => {
+            let display = config::settings().hash_display;
+            for set in &sets {
+                let hash = display
+                    .show(Some(set.hash))
+                    .map_or_else(String::new, |hash| format!(", {}", hash));
+                let sampled = if set.sampled { ", sampled hashes" } else { "" };
+                println!(
+                    "{} copies of {} ({} wasted{}{}):",
+                    utils::human_count(set.paths.len() as u64),
+                    utils::human_size(set.bytes),
+                    utils::human_size(set.wasted),
+                    hash,
+                    sampled
+                );
+                for path in &set.paths {
+                    println!("  {}", path);
+                }
+            }
+            let wasted: u64 = sets.iter().map(|s| s.wasted).sum();
+            println!(
+                "{} set{} of duplicates, {} wasted",
+                utils::human_count(sets.len() as u64),
+                if sets.len() == 1 { "" } else { "s" },
+                utils::human_size(wasted)
+            );
+        }
+        ReportFormat::Csv => {
+            println!("set,hash,bytes,path");
+            for (i, set) in sets.iter().enumerate() {
+                for path in &set.paths {
+                    println!("{},{},{},{}", i + 1, set.hash, set.bytes, csv_field(path));
+                }
+            }
+        }
+        ReportFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&sets).expect("duplicates serialize to JSON")
+        ),
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FleetFormat {
+    Text,
+    Json,
+    Html,
+    Markdown,
+}
+
+/// The columns drift is counted in, each taking the kinds [`category`]
+/// puts there.
+pub(crate) const CATEGORIES: [&str; 5] = ["changed", "new", "deleted", "metadata", "unreadable"];
+
+pub(crate) fn category(kind: DriftKind) -> &'static str {
+    match kind {
+        DriftKind::New | DriftKind::NewExecutable | DriftKind::NewSetuid => "new",
+        DriftKind::Deleted => "deleted",
+        DriftKind::Unreadable | DriftKind::Locked => "unreadable",
+        kind if DriftKind::METADATA.contains(&kind) => "metadata",
+        _ => "changed",
+    }
+}
+
+/// One row of the fleet table per host, the header first.
+fn fleet_rows(hosts: &[HostSummary]) -> Vec<Vec<String>> {
+    let header = ["HOST", "STATE", "BASELINE AGE"]
+        .into_iter()
+        .chain(CATEGORIES)
+        .chain(["WORST", "LAST REPORT"])
+        .map(str::to_uppercase);
+    let mut rows = vec![header.collect::<Vec<_>>()];
+    for host in hosts {
+        let mut counts = BTreeMap::new();
+        for (kind, count) in &host.drifts {
+            let kind = DriftKind::ALL.iter().find(|k| k.as_str() == *kind);
+            *counts
+                .entry(kind.map_or("changed", |k| category(*k)))
+                .or_insert(0) += count;
+        }
+        let state = if host.missing {
+            "missing"
+        } else if host.drifts.is_empty() {
+            "ok"
+        } else {
+            "drifting"
+        };
+        let name = match &host.hostname {
+            Some(hostname) if *hostname != host.host => format!("{} ({})", host.host, hostname),
+            _ => host.host.clone(),
+        };
+        let mut row = vec![
+            name,
+            state.to_string(),
+            host.baseline_age_days
+                .map_or_else(|| "-".to_string(), |days| format!("{}d", days)),
+        ];
+        row.extend(CATEGORIES.map(|c| count(Some(counts.get(c).copied().unwrap_or(0)))));
+        row.push(
+            host.worst
+                .map_or_else(|| "-".to_string(), |s| s.to_string()),
+        );
+        row.push(
+            host.last_report
+                .map_or_else(|| "never".to_string(), utils::format_time),
+        );
+        rows.push(row);
+    }
+    rows
+}
+
+/// The text columns: host, state, worst severity and time. The others are
+/// numbers, right-aligned.
+fn is_text_column(i: usize) -> bool {
+    matches!(i, 0 | 1 | 8 | 9)
+}
+
+fn fleet_summary(hosts: &[HostSummary], stale_after: Duration) -> String {
+    let missing = hosts.iter().filter(|h| h.missing).count();
+    let drifting = hosts
+        .iter()
+        .filter(|h| !h.missing && !h.drifts.is_empty())
+        .count();
+    format!(
+        "{} hosts: {} drifting, {} missing (no report within {})",
+        utils::human_count(hosts.len() as u64),
+        utils::human_count(drifting as u64),
+        utils::human_count(missing as u64),
+        utils::format_duration(stale_after)
+    )
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn print_html(hosts: &[HostSummary], rows: &[Vec<String>], stale_after: Duration) {
+    println!("<!DOCTYPE html>");
+    println!("<html>");
+    println!("<head>");
+    println!("<meta charset=\"utf-8\">");
+    println!("<title>config-guardian fleet report</title>");
+    println!(
+        "<style>table {{ border-collapse: collapse; }} th, td {{ padding: 2px 8px; }} td.n {{ text-align: right; }} tr.missing {{ color: #a00; }} tr.drifting {{ font-weight: bold; }}</style>"
+    );
+    println!("</head>");
+    println!("<body>");
+    println!("<p>{}</p>", html_escape(&fleet_summary(hosts, stale_after)));
+    println!("<table>");
+    for (r, row) in rows.iter().enumerate() {
+        let cells: String = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| match (r, is_text_column(i)) {
+                (0, _) => format!("<th>{}</th>", html_escape(cell)),
+                (_, true) => format!("<td>{}</td>", html_escape(cell)),
+                (_, false) => format!("<td class=\"n\">{}</td>", html_escape(cell)),
+            })
+            .collect();
+        match r {
+            0 => println!("<tr>{}</tr>", cells),
+            _ => println!("<tr class=\"{}\">{}</tr>", row[1], cells),
+        }
+    }
+    println!("</table>");
+    println!("</body>");
+    println!("</html>");
+}
+
+/// Print the latest drift of each host that has reported to the collector
+/// keeping its files in `data_dir`.
+pub fn run_fleet(
+    data_dir: &Path,
+    stale_after: Duration,
+    format: FleetFormat,
+) -> Result<(), GuardianError> {
+    let hosts = collector::fleet(data_dir, stale_after)?;
+    for skipped in hosts.iter().flat_map(|h| &h.skipped) {
+        eprintln!("Warning: passed over {}", skipped);
+    }
+    let rows = fleet_rows(&hosts);
+    match format {
+        FleetFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&hosts).expect("host summaries serialize to JSON")
+        ),
+        FleetFormat::Text if hosts.is_empty() => {
+            println!("No hosts have reported to {}.", data_dir.display())
+        }
+        FleetFormat::Text => {
+            print_aligned(&rows, is_text_column);
+            println!();
+            println!("{}", fleet_summary(&hosts, stale_after));
+        }
+        FleetFormat::Markdown => {
+            for (r, row) in rows.iter().enumerate() {
+                let cells: Vec<String> = row.iter().map(|cell| markdown_cell(cell)).collect();
+                println!("| {} |", cells.join(" | "));
+                if r == 0 {
+                    let rule: Vec<&str> = (0..row.len())
+                        .map(|i| if is_text_column(i) { "---" } else { "---:" })
+                        .collect();
+                    println!("| {} |", rule.join(" | "));
+                }
+            }
+            println!();
+            println!("{}", fleet_summary(&hosts, stale_after));
+        }
+        FleetFormat::Html => print_html(&hosts, &rows, stale_after),
+    }
+    Ok(())
+}
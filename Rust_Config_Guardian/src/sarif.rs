@@ -0,0 +1,238 @@
+//! `compare --format sarif`: drift as a SARIF 2.1.0 log, the format GitHub
+//! code scanning and other code-scanning tools ingest.
+
+use serde_json::{Value, json};
+
+use crate::alert::{self, Severity};
+use crate::manual::BIN_NAME;
+use crate::{Drift, DriftKind};
+
+pub const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+pub const VERSION: &str = "2.1.0";
+
+/// What drift of each kind means, as the rule's description.
+fn description(kind: DriftKind) -> &'static str {
+    match kind {
+        DriftKind::Changed => "The file's content differs from the snapshot.",
+        DriftKind::Truncated => "The file was emptied.",
+        DriftKind::Populated => "A file that was empty in the snapshot has content.",
+        DriftKind::New => "The file is not in the snapshot.",
+        DriftKind::NewExecutable => "An executable file that is not in the snapshot.",
+        DriftKind::NewSetuid => "A setuid or setgid file that is not in the snapshot.",
+        DriftKind::Deleted => "A file in the snapshot is gone.",
+        DriftKind::Unreadable => "A file in the snapshot could not be read.",
+        DriftKind::Locked => "Another process has the file open exclusively.",
+        DriftKind::MountChanged => {
+            "The file's content changed and it is on another mount, as when something was mounted over it."
+        }
+        DriftKind::AttributesChanged => "The file's extended attributes changed.",
+        DriftKind::AclChanged => "The file's ACL changed.",
+        DriftKind::InodeReplaced => "The file was replaced by another file at the same path.",
+        DriftKind::LinkCountChanged => "The number of hard links to the file changed.",
+        DriftKind::PermissionsChanged => "The file's mode bits or owner changed.",
+        DriftKind::TypeChanged => {
+            "The path's type changed, as a file replaced by a symlink or a directory."
+        }
+        DriftKind::SymlinkRetargeted => "The symlink leads to another path than in the snapshot.",
+        DriftKind::SymlinkEscaped => {
+            "The symlink now leads out of the directory scanned, as a symlink attack's does."
+        }
+        DriftKind::BaselineModified => {
+            "The snapshot file was changed by something other than config-guardian."
+        }
+    }
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Critical => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "note",
+    }
+}
+
+/// `path` as a SARIF artifact URI: a `file://` URI for an absolute path, a
+/// relative reference otherwise, with `/` separators and the characters a
+/// URI can't hold percent-encoded.
+pub fn uri_of(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut uri = String::new();
+    if path.starts_with('/') {
+        uri.push_str("file://");
+    } else if path.as_bytes().get(1) == Some(&b':') {
+        // A Windows drive letter, as in C:/ProgramData.
+        uri.push_str("file:///");
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => uri.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'/' | b':' | b'@' | b'!' | b'$' | b'&' | b'\'' => {
+                uri.push(byte as char)
+            }
+            b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// The SARIF log of `drifts`: one result per drift, its rule the drift's
+/// kind and its level the drift's severity.
+pub fn log(drifts: &[Drift]) -> Value {
+    let rules: Vec<Value> = DriftKind::ALL
+        .iter()
+        .map(|&kind| {
+            json!({
+                "id": kind.as_str(),
+                "name": kind.to_string(),
+                "shortDescription": { "text": kind.to_string() },
+                "fullDescription": { "text": description(kind) },
+                "defaultConfiguration": { "level": level(Severity::of(kind)) },
+            })
+        })
+        .collect();
+    let results: Vec<Value> = drifts
+        .iter()
+        .map(|drift| {
+            let rule_index = DriftKind::ALL
+                .iter()
+                .position(|&kind| kind == drift.kind)
+                .expect("every kind has a rule");
+            json!({
+                "ruleId": drift.kind.as_str(),
+                "ruleIndex": rule_index,
+                "level": level(alert::severity_of(drift)),
+                "message": { "text": drift.to_string() },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri_of(&drift.path) },
+                    },
+                }],
+            })
+        })
+        .collect();
+    let mut driver = json!({
+        "name": BIN_NAME,
+        "version": env!("CARGO_PKG_VERSION"),
+        "rules": rules,
+    });
+    if !env!("CARGO_PKG_REPOSITORY").is_empty() {
+        driver["informationUri"] = env!("CARGO_PKG_REPOSITORY").into();
+    }
+    json!({
+        "$schema": SCHEMA,
+        "version": VERSION,
+        "runs": [{
+            "tool": { "driver": driver },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileHash, Snapshot};
+    use config_guardian::compare;
+
+    #[test]
+    fn uris_are_file_uris_or_relative_references() {
+        assert_eq!(
+            uri_of("/etc/nginx/nginx.conf"),
+            "file:///etc/nginx/nginx.conf"
+        );
+        assert_eq!(uri_of("conf.d/my site.conf"), "conf.d/my%20site.conf");
+        assert_eq!(
+            uri_of(r"C:\ProgramData\app#1.ini"),
+            "file:///C:/ProgramData/app%231.ini"
+        );
+        assert_eq!(uri_of("etc/caf\u{e9}"), "etc/caf%C3%A9");
+    }
+
+    /// Checks the properties the SARIF 2.1.0 schema requires, and the
+    /// values it allows, of everything `log` writes.
+    fn assert_sarif_shape(log: &Value) {
+        assert_eq!(log["version"], "2.1.0");
+        assert_eq!(log["$schema"], SCHEMA);
+        let runs = log["runs"].as_array().expect("runs is an array");
+        assert_eq!(runs.len(), 1);
+        let driver = &runs[0]["tool"]["driver"];
+        assert!(driver["name"].as_str().is_some_and(|n| !n.is_empty()));
+        let rules = driver["rules"].as_array().expect("rules is an array");
+        let ids: Vec<&str> = rules.iter().map(|r| r["id"].as_str().unwrap()).collect();
+        for rule in rules {
+            assert!(rule["shortDescription"]["text"].is_string());
+            assert!(rule["fullDescription"]["text"].is_string());
+        }
+        let levels = ["none", "note", "warning", "error"];
+        let results = runs[0]["results"].as_array().expect("results is an array");
+        for result in results {
+            assert!(result["message"]["text"].is_string(), "{}", result);
+            let rule = result["ruleId"].as_str().unwrap();
+            let index = result["ruleIndex"].as_u64().unwrap() as usize;
+            assert_eq!(ids[index], rule, "ruleIndex points at ruleId");
+            assert!(levels.contains(&result["level"].as_str().unwrap()));
+            for rule in rules {
+                assert!(levels.contains(&rule["defaultConfiguration"]["level"].as_str().unwrap()));
+            }
+            let locations = result["locations"].as_array().unwrap();
+            let uri = locations[0]["physicalLocation"]["artifactLocation"]["uri"]
+                .as_str()
+                .unwrap();
+            assert!(
+                !uri.is_empty() && !uri.contains([' ', '\\', '#']),
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn drift_becomes_one_result_per_entry() {
+        let entry = |path: &str, hash: &str| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            ..FileHash::default()
+        };
+        let baseline = Snapshot {
+            entries: vec![
+                entry("/etc/app/app.conf", "1"),
+                entry("/etc/app/old.conf", "2"),
+            ],
+            ..Snapshot::default()
+        };
+        let current = Snapshot {
+            entries: vec![
+                entry("/etc/app/app.conf", "3"),
+                entry("/etc/app/new site.conf", "4"),
+            ],
+            ..Snapshot::default()
+        };
+        let log = log(&compare(&baseline, &current).drifts);
+        assert_sarif_shape(&log);
+
+        let results = log["runs"][0]["results"].as_array().unwrap();
+        let summary: Vec<(&str, &str, &str)> = results
+            .iter()
+            .map(|r| {
+                (
+                    r["ruleId"].as_str().unwrap(),
+                    r["level"].as_str().unwrap(),
+                    r["locations"][0]["physicalLocation"]["artifactLocation"]["uri"]
+                        .as_str()
+                        .unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("changed", "warning", "file:///etc/app/app.conf"),
+                ("new", "note", "file:///etc/app/new%20site.conf"),
+                ("deleted", "error", "file:///etc/app/old.conf"),
+            ]
+        );
+        assert_eq!(results[0]["message"]["text"], "Changed: /etc/app/app.conf");
+        assert_sarif_shape(&super::log(&[]));
+    }
+}
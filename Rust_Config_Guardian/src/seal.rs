@@ -0,0 +1,235 @@
+//! The seal on the snapshot file: a record, kept next to it, of the
+//! checksum of the last snapshot config-guardian wrote itself. A snapshot
+//! whose content doesn't match its seal was changed by something else.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::utils::random_u64;
+use crate::{Drift, DriftKind, GuardianError, utils};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Seal {
+    /// SHA-256 of the snapshot file's bytes, whatever hash it records.
+    pub sha256: String,
+    /// Changes with every write, so rewriting the same content still
+    /// counts as a new write.
+    pub nonce: String,
+    pub sealed: DateTime<Utc>,
+}
+
+/// `snapshot.json` is sealed in `snapshot.json.seal`.
+pub fn path_of(snapshot_file: &Path) -> PathBuf {
+    let mut name = snapshot_file.as_os_str().to_owned();
+    name.push(".seal");
+    name.into()
+}
+
+pub fn checksum(content: &[u8]) -> String {
+    hex(&Sha256::digest(content))
+}
+
+pub(crate) fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A writer that works out the checksum of what goes through it, so a
+/// snapshot streamed to disk can be sealed without holding it in memory.
+pub struct Checksummed<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Checksummed<W> {
+    pub fn new(inner: W) -> Self {
+        Checksummed {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The writer, and the checksum of everything written to it.
+    pub fn finish(self) -> (W, String) {
+        (self.inner, hex(&self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Seal content with this checksum as what is about to replace
+/// `snapshot_file`. The seal is written first, so a monitor never sees our
+/// snapshot without it.
+pub fn write(snapshot_file: &Path, checksum: String) -> Result<(), GuardianError> {
+    let seal = Seal {
+        sha256: checksum,
+        nonce: format!("{:016x}", random_u64()),
+        sealed: Utc::now(),
+    };
+    let path = path_of(snapshot_file);
+    let json = serde_json::to_string_pretty(&seal).expect("seals serialize to JSON");
+    fs::write(&path, json).map_err(GuardianError::io(path))
+}
+
+/// The seal of `snapshot_file`, if it has a readable one.
+pub fn read(snapshot_file: &Path) -> Option<Seal> {
+    let data = fs::read_to_string(path_of(snapshot_file)).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+/// Whether `snapshot_file` matches its seal as it is now. A snapshot
+/// without a seal, written by an older version, matches.
+pub fn verify(snapshot_file: &Path) -> io::Result<bool> {
+    let Some(seal) = read(snapshot_file) else {
+        return Ok(true);
+    };
+    let mut out = Checksummed::new(io::sink());
+    io::copy(&mut fs::File::open(snapshot_file)?, &mut out)?;
+    Ok(out.finish().1 == seal.sha256)
+}
+
+/// Watches the snapshot file for changes config-guardian didn't make, for
+/// `monitor`. It is checked on every tick, so it works wherever the file
+/// lives, inside the monitored directory or not.
+pub struct BaselineGuard {
+    path: PathBuf,
+    /// Size and modification time at the last look.
+    seen: Option<(u64, SystemTime)>,
+    /// Those of the content last judged.
+    judged: Option<(u64, SystemTime)>,
+    checksum: Option<String>,
+}
+
+impl BaselineGuard {
+    pub fn new(path: &Path) -> Self {
+        let mut guard = BaselineGuard {
+            path: path.to_path_buf(),
+            seen: None,
+            judged: None,
+            checksum: None,
+        };
+        guard.seen = guard.stat();
+        guard.judged = guard.seen;
+        guard.checksum = fs::read(path).ok().map(|content| checksum(&content));
+        guard
+    }
+
+    fn stat(&self) -> Option<(u64, SystemTime)> {
+        let metadata = fs::metadata(&self.path).ok()?;
+        Some((metadata.len(), metadata.modified().ok()?))
+    }
+
+    /// Whether the snapshot doesn't match its seal, as it is now. A
+    /// snapshot without a seal, written by an older version, matches.
+    pub fn tampered_at_start(&self) -> Option<Drift> {
+        let seal = read(&self.path)?;
+        let checksum = self.checksum.as_ref()?;
+        (*checksum != seal.sha256).then(|| self.modified("it doesn't match its seal"))
+    }
+
+    /// Look at the snapshot file again: drift if it changed since the last
+    /// look and the change isn't one config-guardian sealed. A change is
+    /// judged once the file has stopped changing for a tick, so a write in
+    /// progress isn't mistaken for tampering.
+    pub fn check(&mut self) -> Option<Drift> {
+        let now = self.stat();
+        let settled = now == self.seen;
+        self.seen = now;
+        if !settled || now == self.judged {
+            return None;
+        }
+        self.judged = now;
+        let Some(content) = now.and_then(|_| fs::read(&self.path).ok()) else {
+            self.checksum = None;
+            return Some(self.modified("it was removed"));
+        };
+        let checksum = checksum(&content);
+        if self.checksum.as_ref() == Some(&checksum) {
+            return None;
+        }
+        self.checksum = Some(checksum.clone());
+        match read(&self.path) {
+            Some(seal) if seal.sha256 == checksum => {
+                log::info!(
+                    event = "baseline_updated",
+                    path = utils::path_text(&self.path).as_str();
+                    "Snapshot {} was updated by config-guardian", self.path.display()
+                );
+                None
+            }
+            Some(_) => Some(self.modified("it doesn't match its seal")),
+            None => Some(self.modified("it has no seal")),
+        }
+    }
+
+    fn modified(&self, reason: &str) -> Drift {
+        let path = utils::path_text(&self.path);
+        log::error!(
+            event = "baseline_modified",
+            path = path.as_str(),
+            reason = reason;
+            "Snapshot {} was modified outside of config-guardian: {}", path, reason
+        );
+        Drift {
+            kind: DriftKind::BaselineModified,
+            path,
+            reason: Some(reason.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_sealed_writes_of_the_snapshot_pass() {
+        let dir = std::env::temp_dir().join(format!("guardian-seal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("snapshot.json");
+        // Modification times can be as coarse as a clock tick.
+        let settle = || std::thread::sleep(std::time::Duration::from_millis(20));
+        let ours = |content: &str| {
+            settle();
+            write(&file, checksum(content.as_bytes())).unwrap();
+            fs::write(&file, content).unwrap();
+        };
+        ours(r#"{"entries": []}"#);
+        let mut guard = BaselineGuard::new(&file);
+        assert_eq!(guard.tampered_at_start(), None);
+        assert!(verify(&file).unwrap());
+        // Each change is judged on the look after it settles.
+        let look = |guard: &mut BaselineGuard| guard.check().or_else(|| guard.check());
+
+        ours(r#"{"entries": [{"path": "a", "hash": "1"}]}"#);
+        assert_eq!(look(&mut guard), None);
+
+        settle();
+        fs::write(&file, r#"{"entries": [{"path": "a", "hash": "2"}]}"#).unwrap();
+        let drift = look(&mut guard).expect("an unsealed write is reported");
+        assert_eq!(drift.kind, DriftKind::BaselineModified);
+        assert_eq!(drift.reason.as_deref(), Some("it doesn't match its seal"));
+        assert_eq!(look(&mut guard), None, "reported once");
+        assert!(BaselineGuard::new(&file).tampered_at_start().is_some());
+        assert!(!verify(&file).unwrap());
+
+        fs::remove_file(&file).unwrap();
+        let drift = look(&mut guard).expect("removal is reported");
+        assert_eq!(drift.reason.as_deref(), Some("it was removed"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+//! Hosting the monitor loop as a Windows service.
+//!
+//! Services have no argv of their own and start in System32, so everything
+//! except the config path comes from the config file named at install time.
+
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use crate::alert::AlertChannels;
+use crate::config::EmailSettings;
+use crate::control;
+use crate::monitor::{CancellationToken, Monitor, MonitorOptions};
+use crate::pagerduty::PagerDutyConfig;
+use crate::slack::SlackConfig;
+use crate::teams::TeamsConfig;
+use crate::telegram::TelegramConfig;
+use crate::utils::is_valid_directory;
+use crate::webhook::WebhookConfig;
+
+const SERVICE_NAME: &str = "ConfigGuardian";
+const SERVICE_DISPLAY_NAME: &str = "Config Guardian";
+
+/// Settings read from the service config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceConfig {
+    /// Directory to monitor.
+    directory: String,
+    /// Working directory holding snapshot.json and drift.log.
+    state_dir: PathBuf,
+    #[serde(default)]
+    alert: bool,
+    /// POST drift alerts to this URL; implies `alert`.
+    #[serde(default)]
+    webhook_url: Option<String>,
+    /// Slack incoming-webhook URL; implies `alert`.
+    #[serde(default)]
+    slack_webhook: Option<String>,
+    /// Microsoft Teams incoming-webhook URL; implies `alert`.
+    #[serde(default)]
+    teams_webhook: Option<String>,
+    /// Telegram bot token (or GUARDIAN_TELEGRAM_TOKEN) and chat; both are
+    /// needed, and imply `alert`.
+    telegram_token: Option<String>,
+    telegram_chat_id: Option<String>,
+    /// PagerDuty routing key (or GUARDIAN_PAGERDUTY_ROUTING_KEY); implies `alert`.
+    pagerduty_routing_key: Option<String>,
+    /// SMTP settings for email alerts; implies `alert`.
+    #[serde(default)]
+    email: Option<EmailSettings>,
+    /// Poll every N seconds instead of using filesystem events.
+    #[serde(default)]
+    poll: Option<u64>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum ServiceAction {
+    /// Register the service with the Service Control Manager.
+    Install {
+        /// Service config file (TOML) with the directory and state_dir to use.
+        #[arg(long, value_name = "PATH")]
+        config: PathBuf,
+    },
+    /// Remove the service registration.
+    Uninstall,
+    /// Entry point used by the Service Control Manager; not for interactive use.
+    Run {
+        #[arg(long, value_name = "PATH")]
+        config: PathBuf,
+    },
+}
+
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn handle(action: &ServiceAction) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        ServiceAction::Install { config } => install(config),
+        ServiceAction::Uninstall => uninstall(),
+        ServiceAction::Run { config } => {
+            let _ = CONFIG_PATH.set(config.clone());
+            service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+            Ok(())
+        }
+    }
+}
+
+fn load_config(path: &Path) -> Result<ServiceConfig, Box<dyn std::error::Error>> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read service config {}: {}", path.display(), e))?;
+    let config: ServiceConfig = toml::from_str(&data)
+        .map_err(|e| format!("Invalid service config {}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+fn install(config: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    // Validate up front so a typo is reported now, not at the next boot.
+    let config = std::fs::canonicalize(config)?;
+    alert_channels(&load_config(&config)?)?;
+
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![
+            OsString::from("service"),
+            OsString::from("run"),
+            OsString::from("--config"),
+            config.clone().into_os_string(),
+        ],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Detects configuration drift in monitored directories.")?;
+    println!(
+        "Installed service {} using config {}",
+        SERVICE_NAME,
+        config.display()
+    );
+    Ok(())
+}
+
+fn uninstall() -> Result<(), Box<dyn std::error::Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    println!("Uninstalled service {}", SERVICE_NAME);
+    Ok(())
+}
+
+fn alert_channels(config: &ServiceConfig) -> Result<Option<AlertChannels>, String> {
+    let channels = AlertChannels {
+        webhook: config.webhook_url.as_ref().map(|url| WebhookConfig {
+            url: url.clone(),
+            headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }),
+        slack: config.slack_webhook.as_ref().map(|url| SlackConfig {
+            url: url.clone(),
+            timeout: Duration::from_secs(10),
+        }),
+        teams: config.teams_webhook.as_ref().map(|url| TeamsConfig {
+            url: url.clone(),
+            timeout: Duration::from_secs(10),
+        }),
+        email: config
+            .email
+            .as_ref()
+            .map(EmailSettings::email_config)
+            .transpose()?,
+        telegram: match (
+            config
+                .telegram_token
+                .clone()
+                .or_else(|| std::env::var("GUARDIAN_TELEGRAM_TOKEN").ok()),
+            &config.telegram_chat_id,
+        ) {
+            (Some(token), Some(chat_id)) => Some(TelegramConfig {
+                token,
+                chat_id: chat_id.clone(),
+                timeout: Duration::from_secs(10),
+            }),
+            (None, None) => None,
+            _ => return Err("telegram_token and telegram_chat_id must be set together".into()),
+        },
+        pagerduty: config
+            .pagerduty_routing_key
+            .clone()
+            .or_else(|| std::env::var("GUARDIAN_PAGERDUTY_ROUTING_KEY").ok())
+            .map(|routing_key| PagerDutyConfig {
+                routing_key,
+                timeout: Duration::from_secs(10),
+            }),
+        desktop: false,
+        on_drift: None,
+        limits: Default::default(),
+    };
+    Ok((config.alert || !channels.is_empty()).then_some(channels))
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        log::error!("Service failed: {}", e);
+    }
+}
+
+fn run_service() -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = CONFIG_PATH
+        .get()
+        .ok_or("Service started without --config")?;
+    let config = load_config(config_path)?;
+    let alerts = alert_channels(&config)?;
+    std::env::set_current_dir(&config.state_dir)?;
+    crate::init_logging(&crate::LogArgs::default())?;
+
+    // Stop and shutdown requests take the same graceful path as Ctrl+C.
+    let shutdown = CancellationToken::new();
+    let handler_shutdown = shutdown.clone();
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                handler_shutdown.cancel();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+    let set_state = |state: ServiceState, exit_code: u32| {
+        let controls = if state == ServiceState::Running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        };
+        status_handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: controls,
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::from_secs(10),
+            process_id: None,
+        })
+    };
+
+    set_state(ServiceState::StartPending, 0)?;
+    if !is_valid_directory(&config.directory) {
+        set_state(ServiceState::Stopped, 1)?;
+        return Err(format!("{} is not a valid directory.", config.directory).into());
+    }
+
+    log::info!("Service monitoring directory: {}", config.directory);
+    let options = MonitorOptions {
+        alerts,
+        poll: config.poll.map(Duration::from_secs),
+        control_socket: Some(control::DEFAULT_PIPE.into()),
+        ..MonitorOptions::default()
+    };
+    set_state(ServiceState::Running, 0)?;
+    let result = Monitor::new(config.directory.as_str())
+        .options(options)
+        .run_until(shutdown);
+    set_state(ServiceState::StopPending, 0)?;
+
+    let exit_code = match result {
+        Ok(summary) => {
+            summary.print();
+            0
+        }
+        Err(e) => {
+            log::error!("Monitor stopped with an error: {}", e);
+            1
+        }
+    };
+    log::logger().flush();
+    set_state(ServiceState::Stopped, exit_code)?;
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! Slack incoming-webhook alert channel, formatted with Block Kit.
+
+use serde_json::{Value, json};
+use std::time::Duration;
+
+use crate::DriftKind;
+use crate::alert::{Alert, Listing};
+use crate::http;
+use crate::identity;
+
+#[derive(Clone)]
+pub struct SlackConfig {
+    pub url: String,
+    pub timeout: Duration,
+}
+
+/// Slack's mrkdwn treats these three characters as control sequences.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn payload(alert: &Alert, host: &str, listing: &Listing) -> Value {
+    let severity = alert.severity();
+    let title = format!(
+        "Config drift on {} ({}, score {})",
+        host,
+        severity,
+        alert.score()
+    );
+
+    // The rarer kinds only get a field when there are any.
+    let fields: Vec<Value> = [
+        DriftKind::Changed,
+        DriftKind::Truncated,
+        DriftKind::Populated,
+        DriftKind::New,
+        DriftKind::NewExecutable,
+        DriftKind::NewSetuid,
+        DriftKind::Deleted,
+        DriftKind::Unreadable,
+        DriftKind::Locked,
+        DriftKind::MountChanged,
+        DriftKind::AttributesChanged,
+        DriftKind::AclChanged,
+        DriftKind::InodeReplaced,
+        DriftKind::LinkCountChanged,
+        DriftKind::PermissionsChanged,
+        DriftKind::TypeChanged,
+        DriftKind::SymlinkRetargeted,
+        DriftKind::SymlinkEscaped,
+        DriftKind::BaselineModified,
+    ]
+    .iter()
+    .filter(|&&kind| {
+        matches!(
+            kind,
+            DriftKind::Changed | DriftKind::New | DriftKind::Deleted
+        ) || alert.count(kind) > 0
+    })
+    .map(|&kind| {
+        json!({
+            "type": "mrkdwn",
+            "text": format!("*{}*\n{}", kind, alert.count(kind)),
+        })
+    })
+    .collect();
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": title },
+        }),
+        json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("*{}* in `{}`", escape(&alert.summary), escape(&alert.dir)),
+            },
+            "fields": fields,
+        }),
+    ];
+
+    let (listed, omitted) = alert.listed(listing);
+    if !listed.is_empty() {
+        let lines: Vec<String> = listed
+            .iter()
+            .map(|d| match alert.annotations.get(&d.path) {
+                Some(annotation) => format!(
+                    "• {}: `{}` — {}",
+                    d.kind,
+                    escape(&d.path),
+                    escape(&annotation.to_string())
+                ),
+                None => format!("• {}: `{}`", d.kind, escape(&d.path)),
+            })
+            .collect();
+        blocks.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": lines.join("\n") },
+        }));
+    }
+    if !alert.tags.is_empty() {
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{
+                "type": "mrkdwn",
+                "text": format!("Snapshot tags: {}", escape(&crate::format_tags(&alert.tags))),
+            }],
+        }));
+    }
+    if omitted > 0 {
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{
+                "type": "mrkdwn",
+                "text": escape(&listing.trailer(omitted, false)),
+            }],
+        }));
+    }
+
+    // Block Kit has no colour of its own; an attachment supplies the bar.
+    json!({
+        "text": format!("{}: {}", title, alert.summary),
+        "attachments": [{ "color": severity.color(), "blocks": blocks }],
+    })
+}
+
+pub fn send(config: &SlackConfig, alert: &Alert, listing: &Listing) -> Result<(), String> {
+    http::post_json(
+        &config.url,
+        &[],
+        config.timeout,
+        &payload(alert, &identity::label(), listing),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Drift;
+
+    fn drift(kind: DriftKind, path: &str) -> Drift {
+        Drift {
+            kind,
+            path: path.to_string(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn payload_matches_block_kit_structure() {
+        let drifts = vec![
+            drift(DriftKind::Changed, "etc/nginx.conf"),
+            drift(DriftKind::New, "etc/a<b>.conf"),
+        ];
+        let alert = Alert::drift("etc", &drifts);
+
+        let expected = json!({
+            "text": "Config drift on web1 (warning, score 6): 2 drifted files",
+            "attachments": [{
+                "color": "#ecb22e",
+                "blocks": [
+                    {
+                        "type": "header",
+                        "text": { "type": "plain_text", "text": "Config drift on web1 (warning, score 6)" },
+                    },
+                    {
+                        "type": "section",
+                        "text": { "type": "mrkdwn", "text": "*2 drifted files* in `etc`" },
+                        "fields": [
+                            { "type": "mrkdwn", "text": "*Changed*\n1" },
+                            { "type": "mrkdwn", "text": "*New*\n1" },
+                            { "type": "mrkdwn", "text": "*Deleted*\n0" },
+                        ],
+                    },
+                    {
+                        "type": "section",
+                        "text": {
+                            "type": "mrkdwn",
+                            "text": "• Changed: `etc/nginx.conf`\n• New: `etc/a&lt;b&gt;.conf`",
+                        },
+                    },
+                ],
+            }],
+        });
+        assert_eq!(payload(&alert, "web1", &Listing::default()), expected);
+    }
+
+    #[test]
+    fn payload_truncates_long_lists() {
+        let drifts: Vec<Drift> = (0..25)
+            .map(|i| drift(DriftKind::Deleted, &format!("f{}", i)))
+            .collect();
+        let listing = Listing {
+            max_entries: 20,
+            report: Some("artifacts/drift.sarif".into()),
+        };
+        let value = payload(&Alert::drift("d", &drifts), "web1", &listing);
+
+        let attachment = &value["attachments"][0];
+        assert_eq!(attachment["color"], "#e01e5a");
+        let blocks = attachment["blocks"].as_array().unwrap();
+        let listed = blocks[2]["text"]["text"].as_str().unwrap();
+        assert_eq!(listed.lines().count(), 20);
+        assert_eq!(
+            blocks[3]["elements"][0]["text"],
+            "…and 5 more (see artifacts/drift.sarif)"
+        );
+    }
+}
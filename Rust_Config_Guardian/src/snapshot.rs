@@ -0,0 +1,3139 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
+
+use crate::GuardianError;
+use crate::archive::Archive;
+use crate::cache::{HashCache, Identity};
+use crate::chunk::{self, Chunks};
+use crate::config::{self, HashAlgorithm, Ownership};
+use crate::content;
+use crate::identity;
+use crate::invoker::{self, Invoker};
+use crate::normalize;
+#[cfg(unix)]
+use crate::owners;
+use crate::progress;
+use crate::redact;
+use crate::remote;
+use crate::seal::{self, Checksummed};
+use crate::throttle;
+use crate::utils::{self, acl_of, attributes_of, format_size, long_path, mount_of};
+
+/// Default snapshot file, relative to the working directory.
+pub const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// The hash of one file's content.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHash {
+    /// The path as text; see [`utils::path_text`] for names that aren't
+    /// valid Unicode.
+    pub path: String,
+    /// The path's raw form, base64-encoded, when it isn't valid Unicode,
+    /// so the file can be found again by its real name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_base64: Option<String>,
+    pub hash: String,
+    /// The mount the file was on, recorded with `detect_mounts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<u64>,
+    /// The file's immutable (`i`) and append-only (`a`) attributes, as
+    /// `lsattr` letters, recorded with `attrs` where the filesystem has them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<String>,
+    /// The file's POSIX ACL as `getfacl` would print it on one line, with
+    /// numeric IDs; recorded with `acls`, and only when it holds more than
+    /// the mode bits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acl: Option<String>,
+    /// The inode holding the file's content, recorded on Unix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<Inode>,
+    /// The file's mode bits and owner, recorded on Unix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Permissions>,
+    /// The file grew while it was hashed, or is named like a log; changes
+    /// to it aren't drift unless `include_volatile` is set. Tagged with
+    /// `skip_growing`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub volatile: bool,
+    /// Another process had the file open exclusively when it was scanned,
+    /// as Windows services do with their configs. The hash is the last one
+    /// read, or empty if it never was.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub locked: bool,
+    /// The hash is a sampled one, of the first and last this many bytes of
+    /// the file and its length, not a digest of all of it; set for files
+    /// larger than `sample_hash`. Comparisons hash the file the same way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampled: Option<u64>,
+    /// The file's content-defined chunks, recorded for the files
+    /// `chunked_hash` names, to say where drift to them is; comparisons go
+    /// by `hash` all the same.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Chunks>,
+    /// What the file held, as text, for one of the `virtual_files`: a file
+    /// of `/proc` or `/sys`, whose size and modification time say nothing,
+    /// so it is read on every scan, and whose drift shows both values.
+    #[serde(default, rename = "virtual", skip_serializing_if = "Option::is_none")]
+    pub virtual_value: Option<String>,
+    /// The file's size and modification time when it was hashed, for
+    /// reports; changes to them alone aren't drift.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    /// What the path was when it was scanned; unknown for entries recorded
+    /// by versions that didn't look.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub entry_type: Option<EntryType>,
+    /// Where the path pointed, when it was a symlink. The hash is of the
+    /// file it led to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    /// The symlink's target resolved against the link's directory, without
+    /// following other links: relative to the directory scanned when it
+    /// leads to a path in it, otherwise absolute. Targets naming the same
+    /// path, such as `../sites-available/app.conf` and its absolute path,
+    /// resolve alike. Unknown for entries recorded by versions that didn't
+    /// resolve targets, and for remote targets and images.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_resolved: Option<String>,
+    /// The symlink leads out of the directory scanned.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub link_escapes: bool,
+}
+
+/// Names of files that grow on their own, checked with `skip_growing`.
+const GROWING_SUFFIXES: [&str; 2] = [".log", ".journal"];
+
+/// Where a file's content lives on disk, and how many paths share it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inode {
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
+}
+
+impl Inode {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Option<Inode> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Inode {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            nlink: metadata.nlink(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn of(_metadata: &fs::Metadata) -> Option<Inode> {
+        None
+    }
+
+    /// Whether `other` is a different inode, rather than this one with
+    /// another link count.
+    pub fn replaced_by(&self, other: &Inode) -> bool {
+        (self.dev, self.ino) != (other.dev, other.ino)
+    }
+}
+
+/// What a path of a snapshot was: a file, a symlink to one, or, where a
+/// comparison found one in place of a recorded file, a directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    File,
+    Symlink,
+    Directory,
+}
+
+impl EntryType {
+    /// The path's type and, for a symlink, its target, without following
+    /// it.
+    fn of(file: &Path) -> Option<(EntryType, Option<String>)> {
+        let metadata = fs::symlink_metadata(file).ok()?;
+        Some(if metadata.is_symlink() {
+            let target = fs::read_link(file).ok().map(|t| utils::path_text(&t));
+            (EntryType::Symlink, target)
+        } else if metadata.is_dir() {
+            (EntryType::Directory, None)
+        } else {
+            (EntryType::File, None)
+        })
+    }
+}
+
+impl fmt::Display for EntryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryType::File => write!(f, "file"),
+            EntryType::Symlink => write!(f, "symlink"),
+            EntryType::Directory => write!(f, "directory"),
+        }
+    }
+}
+
+/// Who owns a file and what its mode bits allow.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permissions {
+    /// The permission bits, including setuid, setgid and sticky.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The names of the owner and group, where their IDs have one on the
+    /// host scanned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl Permissions {
+    #[cfg(unix)]
+    fn of(metadata: &fs::Metadata) -> Option<Permissions> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Permissions {
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            user: owners::user_name(metadata.uid()),
+            group: owners::group_name(metadata.gid()),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn of(_metadata: &fs::Metadata) -> Option<Permissions> {
+        None
+    }
+
+    /// Whether `other` has the same mode, owner and group, the owner and
+    /// group compared `by` name or ID. An ID without a name on either side
+    /// is compared as an ID.
+    pub fn same_as(&self, other: &Permissions, by: Ownership) -> bool {
+        self.mode == other.mode && self.same_owner(other, by)
+    }
+
+    fn same_owner(&self, other: &Permissions, by: Ownership) -> bool {
+        let same = |a: &Option<String>, b: &Option<String>, a_id: u32, b_id: u32| match (by, a, b) {
+            (Ownership::Name, Some(a), Some(b)) => a == b,
+            _ => a_id == b_id,
+        };
+        same(&self.user, &other.user, self.uid, other.uid)
+            && same(&self.group, &other.group, self.gid, other.gid)
+    }
+
+    /// The owner and group as `user:group`, by name where they have one
+    /// and names are compared.
+    fn owner(&self, by: Ownership) -> String {
+        match by {
+            Ownership::Name => format!(
+                "{}:{}",
+                self.user.clone().unwrap_or_else(|| self.uid.to_string()),
+                self.group.clone().unwrap_or_else(|| self.gid.to_string())
+            ),
+            Ownership::Id => format!("{}:{}", self.uid, self.gid),
+        }
+    }
+
+    /// What changed from these permissions to `other`, such as
+    /// `mode 0644 -> 0666` or `owner root:root -> deploy:root`, noting an
+    /// ID compared as one because it has no name.
+    pub fn describe_change(&self, other: &Permissions, by: Ownership) -> String {
+        let mut changes = Vec::new();
+        if self.mode != other.mode {
+            changes.push(format!("mode {:04o} -> {:04o}", self.mode, other.mode));
+        }
+        if !self.same_owner(other, by) {
+            let mut change = format!("owner {} -> {}", self.owner(by), other.owner(by));
+            if by == Ownership::Name {
+                // Where neither side has a name, as in a snapshot from before
+                // names were recorded, the IDs are all there is to go by.
+                let mut unnamed: Vec<String> = Vec::new();
+                for (what, a, b, ids) in [
+                    ("uid", &self.user, &other.user, [self.uid, other.uid]),
+                    ("gid", &self.group, &other.group, [self.gid, other.gid]),
+                ] {
+                    match (a, b) {
+                        (None, Some(_)) => unnamed.push(format!("{} {}", what, ids[0])),
+                        (Some(_), None) => unnamed.push(format!("{} {}", what, ids[1])),
+                        _ => {}
+                    }
+                }
+                if !unnamed.is_empty() {
+                    change.push_str(&format!(
+                        ", compared by ID as {} {} no name",
+                        unnamed.join(" and "),
+                        if unnamed.len() == 1 { "has" } else { "have" }
+                    ));
+                }
+            }
+            changes.push(change);
+        }
+        changes.join(", ")
+    }
+}
+
+impl FileHash {
+    /// The path of the file the entry was recorded for.
+    pub fn file_path(&self) -> PathBuf {
+        self.path_base64
+            .as_deref()
+            .and_then(utils::path_from_raw)
+            .unwrap_or_else(|| PathBuf::from(&self.path))
+    }
+
+    /// The entry for the file at `file`, recorded as `path`, with the
+    /// details the settings opt into.
+    pub fn new(path: String, hash: String, file: &Path) -> Self {
+        Self::with_metadata(path, hash, file, fs::metadata(file).ok().as_ref())
+    }
+
+    fn with_metadata(
+        path: String,
+        hash: String,
+        file: &Path,
+        metadata: Option<&fs::Metadata>,
+    ) -> Self {
+        let settings = config::settings();
+        let volatile = settings.skip_growing && GROWING_SUFFIXES.iter().any(|s| path.ends_with(s));
+        let (entry_type, link_target) = match EntryType::of(file) {
+            Some((entry_type, target)) => (Some(entry_type), target),
+            None => (None, None),
+        };
+        FileHash {
+            path,
+            path_base64: None,
+            hash,
+            inode: metadata.and_then(Inode::of),
+            permissions: metadata.and_then(Permissions::of),
+            volatile,
+            locked: false,
+            sampled: None,
+            chunks: None,
+            virtual_value: None,
+            size: metadata.map(fs::Metadata::len),
+            modified: metadata.and_then(|m| m.modified().ok()).map(Into::into),
+            mount: settings.detect_mounts.then(|| mount_of(file)).flatten(),
+            attributes: settings.attrs.then(|| attributes_of(file)).flatten(),
+            acl: settings.acls.then(|| acl_of(file)).flatten(),
+            entry_type,
+            link_target,
+            link_resolved: None,
+            link_escapes: false,
+        }
+    }
+
+    /// What the entry was, as `file`, or `symlink -> TARGET`. An entry of
+    /// a version that didn't record types held a file's content.
+    pub fn describe_type(&self) -> String {
+        match (self.entry_type, &self.link_target) {
+            (Some(entry_type), Some(target)) => format!("{} -> {}", entry_type, target),
+            (Some(entry_type), None) => entry_type.to_string(),
+            (None, _) => EntryType::File.to_string(),
+        }
+    }
+}
+
+/// Where and how a snapshot was taken.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotMetadata {
+    /// When the snapshot was last taken; unknown for snapshots written by
+    /// versions that saved a bare list of entries.
+    pub created: Option<DateTime<Utc>>,
+    /// Version of config-guardian that wrote the snapshot.
+    pub version: String,
+    pub hash: HashAlgorithm,
+    /// Directories whose files the entries cover, and glob patterns whose
+    /// matches they cover.
+    pub directories: Vec<String>,
+    /// What the latest scan of each directory saw.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub stats: BTreeMap<String, ScanStats>,
+    /// When each directory was last taken into the snapshot, which may be
+    /// long before `created` when other directories were taken since.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub taken: BTreeMap<String, DateTime<Utc>>,
+    /// Whether ACLs were recorded, which tells a file without one apart
+    /// from one that wasn't looked at.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub acls: bool,
+    /// Labels given with `snapshot --tag` or `accept --tag`, such as the
+    /// release that was deployed.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// The options each directory was last scanned with, which comparisons
+    /// reuse; snapshots written before they were recorded have none.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, ScanOptions>,
+    /// Who last saved it, and how.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoker: Option<Invoker>,
+    /// The identity of the machine it was last saved on; see
+    /// [`identity`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// The version of its profile's baseline at the collector it was last
+    /// brought up to: by `accept --publish` on the host that published it,
+    /// or by a monitor subscribed to it on the others.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<u64>,
+}
+
+impl SnapshotMetadata {
+    /// When `dir`'s files were taken into the snapshot, as far as is known:
+    /// snapshots written before directories' times were recorded only know
+    /// when the snapshot was last saved.
+    pub fn taken_at(&self, dir: &str) -> Option<DateTime<Utc>> {
+        self.taken.get(dir).copied().or(self.created)
+    }
+
+    /// The metadata of the snapshot at `path`, without keeping its entries.
+    pub fn load(path: &Path) -> Result<SnapshotMetadata, GuardianError> {
+        #[derive(Deserialize)]
+        struct Head {
+            #[serde(default)]
+            metadata: SnapshotMetadata,
+        }
+        let mut reader = match fs::File::open(path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(GuardianError::SnapshotMissing { path: path.into() });
+            }
+            Err(e) => return Err(GuardianError::io(path)(e)),
+        };
+        if first_byte(&mut reader).map_err(GuardianError::io(path))? == Some(b'[') {
+            return Ok(SnapshotMetadata::default());
+        }
+        serde_json::from_reader::<_, Head>(reader)
+            .map(|head| head.metadata)
+            .map_err(|source| GuardianError::SnapshotCorrupt {
+                path: path.into(),
+                source,
+            })
+    }
+}
+
+/// The options that decide which files a scan takes in and how it hashes
+/// them. A comparison made with others than the baseline's reports files as
+/// new, deleted or changed that aren't. Those left at their defaults aren't
+/// saved.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanOptions {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(skip_serializing_if = "is_default")]
+    pub hash: HashAlgorithm,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_hash: Option<u64>,
+    /// The identities of the normalizers' commands, by their globs.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub normalize: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub detect_mounts: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub attrs: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub acls: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub skip_growing: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub track_self: bool,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl ScanOptions {
+    /// The options scans run with now.
+    pub fn current() -> ScanOptions {
+        let settings = config::settings();
+        ScanOptions {
+            exclude: settings
+                .exclude
+                .iter()
+                .map(|p| p.as_str().to_string())
+                .collect(),
+            hash: settings.hash,
+            sample_hash: settings.sample_hash,
+            normalize: normalize::identities(),
+            detect_mounts: settings.detect_mounts,
+            attrs: settings.attrs,
+            acls: settings.acls,
+            skip_growing: settings.skip_growing,
+            track_self: settings.track_self,
+        }
+    }
+}
+
+/// The options not left at their defaults, as they are set in the config
+/// file: `exclude = ["*.bak"], hash = "sha512"`, or `defaults`.
+impl fmt::Display for ScanOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut set = Vec::new();
+        if !self.exclude.is_empty() {
+            set.push(format!("exclude = {:?}", self.exclude));
+        }
+        if !is_default(&self.hash) {
+            set.push(format!("hash = \"{}\"", self.hash));
+        }
+        if let Some(size) = self.sample_hash {
+            set.push(format!("sample_hash = \"{}\"", format_size(size)));
+        }
+        if !self.normalize.is_empty() {
+            set.push(format!("normalize = {:?}", self.normalize));
+        }
+        for (name, on) in [
+            ("detect_mounts", self.detect_mounts),
+            ("attrs", self.attrs),
+            ("acls", self.acls),
+            ("skip_growing", self.skip_growing),
+            ("track_self", self.track_self),
+        ] {
+            if on {
+                set.push(format!("{} = true", name));
+            }
+        }
+        if set.is_empty() {
+            write!(f, "defaults")
+        } else {
+            write!(f, "{}", set.join(", "))
+        }
+    }
+}
+
+/// Parse a `KEY=VALUE` tag. Both parts are free-form, but the key can't be
+/// empty and neither may hold control characters.
+pub fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = tag.split_once('=') else {
+        return Err(format!("invalid tag '{}' (expected KEY=VALUE)", tag));
+    };
+    if key.trim().is_empty() {
+        return Err(format!("invalid tag '{}' (the key is empty)", tag));
+    }
+    if tag.chars().any(char::is_control) {
+        return Err(format!(
+            "invalid tag {:?} (control characters aren't allowed)",
+            tag
+        ));
+    }
+    Ok((key.trim().to_string(), value.to_string()))
+}
+
+/// Tags as `key=value` pairs, separated by commas.
+pub fn format_tags(tags: &BTreeMap<String, String>) -> String {
+    let pairs: Vec<String> = tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    pairs.join(", ")
+}
+
+/// What a scan of a directory saw and how long it took.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanStats {
+    pub files_hashed: usize,
+    /// Subdirectories met; scans don't descend into them.
+    pub dirs_seen: usize,
+    /// Bytes of file content hashed.
+    pub bytes_read: u64,
+    /// Files left out by an exclude pattern.
+    pub excluded: usize,
+    /// Sockets, FIFOs, devices and broken symlinks, which are skipped.
+    pub special: usize,
+    /// Entries that couldn't be read.
+    pub unreadable: usize,
+    /// Files another process had open exclusively, recorded as locked.
+    pub locked: usize,
+    /// Files a comparison took as unchanged, without reading them, as their
+    /// size and modification time matched the baseline.
+    pub unchanged: usize,
+    /// Files read again after a transient error, whether or not a retry
+    /// got through.
+    pub retried: usize,
+    /// Files whose hash the hash cache had, counted among those hashed.
+    pub cache_hits: usize,
+    /// Files looked up in the hash cache and read, as it didn't have them.
+    pub cache_misses: usize,
+    pub duration_ms: u64,
+}
+
+impl ScanStats {
+    /// Both scans' counts together, as for one scan of several directories.
+    pub fn add(&mut self, other: &ScanStats) {
+        self.files_hashed += other.files_hashed;
+        self.dirs_seen += other.dirs_seen;
+        self.bytes_read += other.bytes_read;
+        self.excluded += other.excluded;
+        self.special += other.special;
+        self.unreadable += other.unreadable;
+        self.locked += other.locked;
+        self.unchanged += other.unchanged;
+        self.retried += other.retried;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.duration_ms += other.duration_ms;
+    }
+}
+
+impl fmt::Display for ScanStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Hashed {} files ({}) in {}ms",
+            utils::human_count(self.files_hashed as u64),
+            utils::human_size(self.bytes_read),
+            self.duration_ms
+        )?;
+        if self.unchanged > 0 {
+            write!(
+                f,
+                "; {} unchanged by size and modification time",
+                utils::human_count(self.unchanged as u64)
+            )?;
+        }
+        let lookups = self.cache_hits + self.cache_misses;
+        if lookups > 0 {
+            write!(
+                f,
+                "; hash cache hits {} of {} ({:.0}%)",
+                utils::human_count(self.cache_hits as u64),
+                utils::human_count(lookups as u64),
+                self.cache_hits as f64 * 100.0 / lookups as f64
+            )?;
+        }
+        if let Some(skipped) = self.skipped() {
+            write!(f, "; skipped {}", skipped)?;
+        }
+        match self.retried {
+            0 => {}
+            1 => write!(f, "; 1 file needed retries")?,
+            n => write!(f, "; {} files needed retries", n)?,
+        }
+        Ok(())
+    }
+}
+
+impl ScanStats {
+    /// The entries left out, e.g. `2 subdirectories, 1 special file`.
+    pub fn skipped(&self) -> Option<String> {
+        let skipped: Vec<String> = [
+            (self.dirs_seen, "subdirectory", "subdirectories"),
+            (self.excluded, "excluded file", "excluded files"),
+            (self.special, "special file", "special files"),
+            (self.unreadable, "unreadable entry", "unreadable entries"),
+            (self.locked, "locked file", "locked files"),
+        ]
+        .iter()
+        .filter(|(count, ..)| *count > 0)
+        .map(|(count, one, many)| format!("{} {}", count, if *count == 1 { one } else { many }))
+        .collect();
+        (!skipped.is_empty()).then(|| skipped.join(", "))
+    }
+}
+
+/// File hashes recorded at one point in time, compared against the files on
+/// disk to find drift.
+///
+/// ```
+/// use config_guardian::{FileHash, Snapshot};
+///
+/// let path = std::env::temp_dir().join(format!("guardian-doc-{}.json", std::process::id()));
+/// let snapshot = Snapshot {
+///     entries: vec![FileHash { path: "app.conf".into(), hash: "abc".into(), ..FileHash::default() }],
+///     ..Snapshot::default()
+/// };
+/// snapshot.save(&path)?;
+///
+/// let loaded = Snapshot::load(&path)?;
+/// assert_eq!(loaded.get("app.conf").map(|f| f.hash.as_str()), Some("abc"));
+/// # std::fs::remove_file(&path)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub metadata: SnapshotMetadata,
+    pub entries: Vec<FileHash>,
+}
+
+impl Snapshot {
+    /// Read a snapshot saved by [`Snapshot::save`], or a bare list of entries
+    /// as saved by earlier versions.
+    ///
+    /// The file is parsed as it is read, so a snapshot of a large tree
+    /// doesn't sit in memory as text beside the entries parsed from it.
+    ///
+    /// An entry that doesn't parse, one for a path that another entry has
+    /// too, and one whose path leads out of its directory are dropped with
+    /// a warning, the newest of the duplicates being kept; with
+    /// `--strict-load` they fail the load instead.
+    pub fn load(path: &Path) -> Result<Snapshot, GuardianError> {
+        Snapshot::read(path, config::settings().strict_load)
+    }
+
+    fn read(path: &Path, strict: bool) -> Result<Snapshot, GuardianError> {
+        #[derive(Deserialize)]
+        struct Saved {
+            metadata: SnapshotMetadata,
+            entries: Entries,
+        }
+        let mut reader = match fs::File::open(path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(GuardianError::SnapshotMissing { path: path.into() });
+            }
+            Err(e) => return Err(GuardianError::io(path)(e)),
+        };
+        let corrupt = |source| GuardianError::SnapshotCorrupt {
+            path: path.into(),
+            source,
+        };
+        let (metadata, Entries(read)) =
+            if first_byte(&mut reader).map_err(GuardianError::io(path))? == Some(b'[') {
+                let entries = serde_json::from_reader(reader).map_err(corrupt)?;
+                (SnapshotMetadata::default(), entries)
+            } else {
+                let saved: Saved = serde_json::from_reader(reader).map_err(corrupt)?;
+                (saved.metadata, saved.entries)
+            };
+        let total = read.len();
+        let (entries, dropped) = check_entries(read);
+        if !dropped.is_empty() {
+            if strict {
+                return Err(GuardianError::SnapshotEntries {
+                    path: path.into(),
+                    problems: dropped.iter().map(|d| d.describe(redact::shown)).collect(),
+                });
+            }
+            warn_dropped(path, total, &dropped);
+        }
+        if !seal::verify(path).map_err(GuardianError::io(path))? {
+            warn_unsealed(path);
+        }
+        Ok(Snapshot { metadata, entries })
+    }
+
+    /// Write the snapshot to `path`, entry by entry rather than through a
+    /// copy of it all as text. It goes to `<path>.partial` first and is
+    /// sealed and renamed over `path` once complete, so a run that fails
+    /// halfway leaves the old snapshot in place.
+    pub fn save(&self, path: &Path) -> Result<(), GuardianError> {
+        let partial = partial_path(path);
+        let written = fs::File::create(&partial).and_then(|file| {
+            let mut out = Checksummed::new(BufWriter::new(file));
+            serde_json::to_writer_pretty(&mut out, self)?;
+            let (out, checksum) = out.finish();
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            Ok(checksum)
+        });
+        let checksum = match written {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                return Err(GuardianError::io(partial)(e));
+            }
+        };
+        seal::write(path, checksum)?;
+        fs::rename(&partial, path).map_err(GuardianError::io(path))
+    }
+
+    /// The entry recorded for `path`, if any.
+    pub fn get(&self, path: &str) -> Option<&FileHash> {
+        self.entries.iter().find(|f| f.path == path)
+    }
+
+    /// The entries by path, for looking up many of them: [`Snapshot::get`]
+    /// searches the whole list each time. As with `get`, the first entry
+    /// for a path wins.
+    pub fn index(&self) -> HashMap<&str, &FileHash> {
+        index_of(&self.entries)
+    }
+
+    /// The entry for the file at `path`, which may be given as recorded,
+    /// relative to one of the snapshot's directories, or as any other path
+    /// to the same file (absolute instead of relative, through a symlinked
+    /// directory, ...).
+    pub fn resolve(&self, path: &str) -> Option<&FileHash> {
+        if let Some(entry) = self.get(path) {
+            return Some(entry);
+        }
+        let dirs = &self.metadata.directories;
+        if let Some(entry) = dirs
+            .iter()
+            .find_map(|dir| self.get(&utils::path_text(&Path::new(dir).join(path))))
+        {
+            return Some(entry);
+        }
+        // The file itself may be gone, so it's the directories that are
+        // compared.
+        let path = Path::new(path);
+        let name = path.file_name()?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let parent = fs::canonicalize(parent).ok()?;
+        let recorded: BTreeSet<&Path> = self
+            .entries
+            .iter()
+            .filter_map(|f| Path::new(&f.path).parent())
+            .collect();
+        recorded
+            .into_iter()
+            .filter(|dir| fs::canonicalize(dir).is_ok_and(|dir| dir == parent))
+            .find_map(|dir| self.get(&utils::path_text(&dir.join(name))))
+    }
+}
+
+/// What went wrong with one entry while scanning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// The directory holds no files; subdirectories don't count.
+    EmptyDirectory,
+    /// A file, or a directory entry, that couldn't be read.
+    Unreadable,
+    /// A file another process has open exclusively. It is recorded as
+    /// locked rather than left out.
+    Locked,
+    /// Not a regular file or directory: a socket, FIFO, device or broken
+    /// symlink. Skipped.
+    Special,
+    /// A file a pattern matched through a symlinked directory leading out
+    /// of the pattern's base directory, to where `error` says. Skipped.
+    Outside,
+}
+
+/// A problem met while scanning that didn't stop the scan.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanWarning {
+    pub kind: WarningKind,
+    pub path: String,
+    /// The underlying error, for unreadable entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl fmt::Display for ScanWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            WarningKind::EmptyDirectory => write!(f, "Directory {} has no files.", self.path),
+            WarningKind::Unreadable => write!(
+                f,
+                "Could not read {}: {}",
+                self.path,
+                self.error.as_deref().unwrap_or("unknown error")
+            ),
+            WarningKind::Locked => write!(
+                f,
+                "Could not read {}: another process has it open exclusively",
+                self.path
+            ),
+            WarningKind::Special => write!(f, "Skipped {}: not a regular file", self.path),
+            WarningKind::Outside => write!(
+                f,
+                "Skipped {}: it resolves to {}, outside the tree scanned",
+                self.path,
+                self.error.as_deref().unwrap_or("elsewhere")
+            ),
+        }
+    }
+}
+
+/// A directory's snapshot and the problems met while taking it.
+#[derive(Clone, Debug, Default)]
+pub struct Scan {
+    pub snapshot: Snapshot,
+    pub warnings: Vec<ScanWarning>,
+    pub stats: ScanStats,
+    /// For a two-phase scan (see [`scan_against`]), the files read although
+    /// they looked unchanged; `None` when every file was read.
+    pub sampled: Option<HashSet<String>>,
+}
+
+/// Replace the snapshot's entries for `dir`, keeping other directories',
+/// and its tags with `tags`. Returns the scan of `dir`.
+pub fn take_snapshot(dir: &str, tags: &BTreeMap<String, String>) -> Result<Scan, GuardianError> {
+    let scan = scan_directory(dir)?;
+    let path = &config::settings().snapshot_file;
+    let mut snapshot = Snapshot::load(path).unwrap_or_default();
+    merge(&mut snapshot, dir, &scan, tags);
+    save_complete(&snapshot, path, &[&scan])?;
+    Ok(scan)
+}
+
+/// [`take_snapshot`] for several directories at once: all of them are
+/// scanned before the snapshot file is touched, so a scan that fails, is
+/// interrupted or runs out of time leaves it as it was. The snapshot
+/// replaced is archived.
+pub fn take_snapshots(
+    dirs: &[String],
+    tags: &BTreeMap<String, String>,
+) -> Result<Vec<Scan>, GuardianError> {
+    let mut scans = Vec::new();
+    for dir in dirs {
+        log::info!("Taking snapshot of directory: {}", dir);
+        scans.push(scan_directory(dir)?);
+    }
+    save_scans(dirs, &scans, tags)?;
+    Ok(scans)
+}
+
+/// Save `scans` of `dirs` in place of what the snapshot had for them,
+/// archiving the snapshot replaced.
+pub(crate) fn save_scans(
+    dirs: &[String],
+    scans: &[Scan],
+    tags: &BTreeMap<String, String>,
+) -> Result<(), GuardianError> {
+    let path = &config::settings().snapshot_file;
+    let mut snapshot = Snapshot::load(path).unwrap_or_default();
+    for (dir, scan) in dirs.iter().zip(scans) {
+        merge(&mut snapshot, dir, scan, tags);
+    }
+    if !cancelled()
+        && let Some(copy) = Archive::of(path).add(path)?
+    {
+        log::info!("Archived the previous snapshot as {}", copy.display());
+    }
+    save_complete(&snapshot, path, &scans.iter().collect::<Vec<_>>())
+}
+
+/// Put `scan` of `dir` into `snapshot` in place of what it had for `dir`.
+fn merge(snapshot: &mut Snapshot, dir: &str, scan: &Scan, tags: &BTreeMap<String, String>) {
+    // A file that couldn't be read keeps its old entry rather than dropping
+    // out of the snapshot, so it isn't later reported as new.
+    let unreadable: HashSet<&str> = scan
+        .warnings
+        .iter()
+        .filter(|w| w.kind == WarningKind::Unreadable)
+        .map(|w| w.path.as_str())
+        .collect();
+    // A locked file keeps the hash last read from it.
+    let last_read: HashMap<String, String> = {
+        let before = snapshot.index();
+        scan.warnings
+            .iter()
+            .filter(|w| w.kind == WarningKind::Locked)
+            .filter_map(|w| before.get(w.path.as_str()))
+            .filter(|f| !f.hash.is_empty())
+            .map(|f| (f.path.clone(), f.hash.clone()))
+            .collect()
+    };
+    let covered = covered_by(dir);
+    snapshot
+        .entries
+        .retain(|f| !covered(f) || unreadable.contains(f.path.as_str()));
+    snapshot
+        .entries
+        .extend(scan.snapshot.entries.iter().map(|entry| {
+            let mut entry = entry.clone();
+            if let Some(hash) = last_read.get(&entry.path).filter(|_| entry.locked) {
+                entry.hash = hash.clone();
+            }
+            entry
+        }));
+    if !snapshot.metadata.directories.iter().any(|d| d == dir) {
+        snapshot.metadata.directories.push(dir.to_string());
+    }
+    let taken = &scan.snapshot.metadata;
+    if let Some(created) = taken.created {
+        snapshot.metadata.taken.insert(dir.to_string(), created);
+    }
+    snapshot.metadata.created = taken.created;
+    snapshot.metadata.version = taken.version.clone();
+    snapshot.metadata.hash = taken.hash;
+    snapshot.metadata.acls = taken.acls;
+    snapshot.metadata.tags = tags.clone();
+    snapshot.metadata.invoker = taken.invoker.clone();
+    snapshot.metadata.instance_id = taken.instance_id.clone();
+    if let Some(options) = taken.options.get(dir) {
+        snapshot
+            .metadata
+            .options
+            .insert(dir.to_string(), options.clone());
+    }
+    snapshot
+        .metadata
+        .stats
+        .insert(dir.to_string(), scan.stats.clone());
+}
+
+/// Save `snapshot` unless the run was interrupted after the last of the
+/// scans of `dirs` was read: even then, the baseline isn't replaced.
+fn save_complete(snapshot: &Snapshot, path: &Path, dirs: &[&Scan]) -> Result<(), GuardianError> {
+    if cancelled() {
+        let read = dirs
+            .iter()
+            .map(|scan| scan.stats.files_hashed + scan.stats.unreadable)
+            .sum();
+        return Err(GuardianError::Interrupted {
+            dir: snapshot.metadata.directories.join(", ").into(),
+            done: read,
+            total: read,
+        });
+    }
+    snapshot.save(path)?;
+    for scan in dirs {
+        content::keep(&scan.snapshot.entries);
+    }
+    Ok(())
+}
+
+/// The entry for `recorded`'s file as it is now, taking its content to be
+/// unchanged: only the metadata is read. `None` if the file is gone or no
+/// longer a regular file.
+pub(crate) fn restat(recorded: &FileHash) -> Option<FileHash> {
+    let path = recorded.file_path();
+    let file = long_path(&path);
+    let metadata = fs::metadata(&file).ok().filter(fs::Metadata::is_file)?;
+    Some(FileHash {
+        path_base64: recorded.path_base64.clone(),
+        ..FileHash::with_metadata(
+            recorded.path.clone(),
+            recorded.hash.clone(),
+            &file,
+            Some(&metadata),
+        )
+    })
+}
+
+/// Hash the content of `path` with the configured algorithm.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    hash_file_as(path, None)
+}
+
+/// [`hash_file`], as a sampled hash over `sampled` bytes at each end when
+/// that is set: the way an entry with [`FileHash::sampled`] was hashed.
+/// One of the `virtual_files` is read as scans read it.
+pub fn hash_file_as(path: &Path, sampled: Option<u64>) -> std::io::Result<String> {
+    let is_virtual = config::settings().is_virtual(&utils::path_text(path));
+    let path = long_path(path);
+    let content = match sampled {
+        _ if is_virtual => utils::read_virtual(&path)?,
+        Some(span) => utils::read_sampled(&path, span)?,
+        None => normalize::apply(&path, utils::read_shared(&path)?),
+    };
+    Ok(config::settings().hash.digest(&content))
+}
+
+/// The chunks an entry for `path`, hashed as [`hash_file_as`] does with
+/// `sampled`, records: see [`FileHash::chunks`].
+pub fn chunks_of(path: &Path, sampled: Option<u64>) -> std::io::Result<Option<Chunks>> {
+    if sampled.is_some() || !config::settings().is_chunked(path) {
+        return Ok(None);
+    }
+    let content = utils::read_shared(&long_path(path))?;
+    Ok(Some(chunk::split(&content)))
+}
+
+/// Whether `target` names files by a glob pattern, such as
+/// `/etc/**/*.conf`, rather than a directory. A directory whose name holds
+/// `*`, `?` or `[` is still a directory.
+pub fn is_pattern(target: &str) -> bool {
+    target.contains(['*', '?', '['])
+        && !remote::is_remote(target)
+        && !utils::is_valid_directory(target)
+}
+
+/// The directory `target` names, or for a pattern, the one its leading
+/// components without a wildcard name: what `compare --scope` paths are
+/// relative to.
+pub fn base_of(target: &str) -> PathBuf {
+    if !is_pattern(target) {
+        return PathBuf::from(target);
+    }
+    Path::new(target)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect()
+}
+
+/// `scope` as a path under `base`, or `None` when it leads out of it.
+fn scope_path(base: &Path, scope: &str) -> Option<PathBuf> {
+    let scope = Path::new(scope);
+    let relative = match scope.is_absolute() {
+        true => scope.strip_prefix(base).ok()?,
+        false => scope,
+    };
+    let mut path = base.to_path_buf();
+    for component in relative.components() {
+        match component {
+            std::path::Component::Normal(part) => path.push(part),
+            std::path::Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Whether `path`, a file of `target`, lies in one of the subtrees of
+/// [`config::Settings::scope`]; with none, every file does.
+pub(crate) fn in_scope(target: &str, path: &Path) -> bool {
+    let scope = &config::settings().scope;
+    if scope.is_empty() {
+        return true;
+    }
+    let base = base_of(target);
+    scope
+        .iter()
+        .filter_map(|scope| scope_path(&base, scope))
+        .any(|subtree| path.starts_with(subtree))
+}
+
+/// Check each `compare --scope` path against the baseline saved at `path`:
+/// it must lie under `target`'s base directory, and hold some of the files
+/// the baseline has for `target`.
+pub fn check_scope(path: &Path, target: &str) -> Result<(), GuardianError> {
+    let settings = config::settings();
+    if settings.scope.is_empty() {
+        return Ok(());
+    }
+    let mut snapshot = Snapshot::load(path)?;
+    if let Some(root) = settings.baseline_roots.get(target) {
+        rebase(&mut snapshot, root, target);
+    }
+    let covered = covered_by(target);
+    let base = base_of(target);
+    for scope in &settings.scope {
+        let Some(subtree) = scope_path(&base, scope) else {
+            return Err(GuardianError::Config(format!(
+                "--scope {} isn't under {}",
+                scope,
+                base.display()
+            )));
+        };
+        if !snapshot
+            .entries
+            .iter()
+            .any(|f| covered(f) && Path::new(&f.path).starts_with(&subtree))
+        {
+            let hint = match !is_pattern(target) && subtree.is_dir() {
+                true => "; scans of a directory don't descend into its subdirectories",
+                false => "",
+            };
+            return Err(GuardianError::Config(format!(
+                "--scope {} holds none of the files the baseline has for {}{}",
+                scope, target, hint
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The patterns to expand for `pattern`: itself, or for one reaching into
+/// every directory below its base, such as `/etc/**/*.conf`, the same for
+/// each [`config::Settings::scope`] subtree alone, so what is out of scope
+/// isn't walked at all.
+fn scoped_patterns(pattern: &str) -> Vec<String> {
+    let scope = &config::settings().scope;
+    let base = base_of(pattern);
+    let rest = pattern
+        .strip_prefix(utils::path_text(&base).as_str())
+        .map(|rest| rest.trim_start_matches(std::path::is_separator));
+    let Some(rest) = rest.filter(|rest| *rest == "**" || rest.starts_with("**/")) else {
+        return vec![pattern.to_string()];
+    };
+    let mut subtrees: Vec<PathBuf> = scope
+        .iter()
+        .filter_map(|scope| scope_path(&base, scope))
+        .collect();
+    if scope.is_empty() || subtrees.len() < scope.len() || !subtrees.iter().all(|s| s.is_dir()) {
+        return vec![pattern.to_string()];
+    }
+    // A subtree within another is walked with it.
+    subtrees.sort();
+    subtrees.dedup_by(|later, earlier| later.starts_with(earlier));
+    subtrees
+        .iter()
+        .map(|subtree| {
+            let subtree = glob::Pattern::escape(&utils::path_text(subtree));
+            format!("{}/{}", subtree, rest)
+        })
+        .collect()
+}
+
+/// How patterns match recorded paths: like `glob` expands them, with `*`
+/// staying within one path component.
+const PATTERN_MATCH: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+fn expand(pattern: &str) -> Result<glob::Paths, GuardianError> {
+    glob::glob_with(pattern, PATTERN_MATCH)
+        .map_err(|e| GuardianError::Config(format!("Invalid pattern {}: {}", pattern, e)))
+}
+
+/// Refuse to scan `target` when its root, the directory or a pattern's base
+/// directory, is itself a symlink, unless `follow_root_symlink` is set: a
+/// link swapped in for the directory, say to `/`, would have the scan take
+/// in wherever it leads, under the directory's name.
+pub(crate) fn check_root(target: &str) -> Result<(), GuardianError> {
+    if config::settings().follow_root_symlink {
+        return Ok(());
+    }
+    let base = base_of(target);
+    // `link/` would be the directory it leads to.
+    let root = utils::path_text(&base);
+    let root = Path::new(root.trim_end_matches(std::path::is_separator));
+    if root.as_os_str().is_empty() || !fs::symlink_metadata(root).is_ok_and(|m| m.is_symlink()) {
+        return Ok(());
+    }
+    let leads_to =
+        fs::read_link(root).map_or_else(|_| "elsewhere".to_string(), |t| utils::path_text(&t));
+    Err(GuardianError::Config(format!(
+        "{} is a symlink to {}; scan the directory it leads to, or pass --follow-root-symlink to scan through it",
+        root.display(),
+        leads_to
+    )))
+}
+
+/// Where the file matched as `recorded` resolves to, if that is outside
+/// `root`, the real path of the pattern's base directory: a directory on
+/// the way there was a symlink leading out of it. A file that is itself a
+/// symlink is recorded as one, wherever it leads.
+fn outside(root: &Path, recorded: &Path) -> Option<PathBuf> {
+    let parent = match recorded.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let resolved = fs::canonicalize(long_path(parent)).ok()?;
+    (!resolved.starts_with(root)).then(|| resolved.join(recorded.file_name().unwrap_or_default()))
+}
+
+/// Check that `target` has something to snapshot: it's a directory, or a
+/// pattern matching at least one path.
+pub fn check_target(target: &str) -> Result<(), GuardianError> {
+    if remote::is_remote(target) {
+        return remote::check(target);
+    }
+    if config::settings().is_virtual(target) {
+        return fs::metadata(target)
+            .map(drop)
+            .map_err(GuardianError::io(target));
+    }
+    check_root(target)?;
+    if !is_pattern(target) {
+        return match utils::is_valid_directory(target) {
+            true => Ok(()),
+            false => Err(GuardianError::NotADirectory {
+                path: target.into(),
+            }),
+        };
+    }
+    match expand(target)?.next() {
+        Some(_) => Ok(()),
+        None => Err(GuardianError::NoMatches {
+            pattern: target.to_string(),
+        }),
+    }
+}
+
+/// Whether the glob `pattern` matches the recorded `path`.
+pub fn matches_pattern(pattern: &str, path: &str) -> bool {
+    glob::Pattern::new(pattern).is_ok_and(|p| p.matches_with(path, PATTERN_MATCH))
+}
+
+/// Which entries a scan of `target` covers: the files directly in a
+/// directory, those a pattern matches, or one of the `virtual_files`.
+fn covered_by(target: &str) -> impl Fn(&FileHash) -> bool + '_ {
+    let pattern = is_pattern(target)
+        .then(|| glob::Pattern::new(target).ok())
+        .flatten();
+    let is_virtual = config::settings().is_virtual(target);
+    move |file| match &pattern {
+        _ if is_virtual => file.path == target,
+        Some(pattern) => pattern.matches_with(&file.path, PATTERN_MATCH),
+        None => Path::new(&file.path).parent() == Some(Path::new(target)),
+    }
+}
+
+/// What a scan did with one directory entry, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Left out by this exclude pattern.
+    Excluded(String),
+    /// A file config-guardian writes itself, left out without `--track-self`.
+    Own,
+    Subdirectory,
+    /// A socket, FIFO, device or broken symlink.
+    Special,
+    /// Another path to the same inode was already read.
+    Linked,
+    /// Reached through a symlinked directory, it resolves to this path,
+    /// outside the pattern's base directory.
+    Outside(PathBuf),
+    Hashed(Duration),
+    /// Its size and modification time match the baseline's, so it wasn't
+    /// read.
+    Unchanged,
+    /// The hash cache has its hash, so it wasn't read.
+    Cached,
+    Unreadable(String),
+    /// A baseline entry left out as it was loaded, by this exclude pattern,
+    /// or with `baseline` by this `baseline_exclude` pattern.
+    LeftOut {
+        pattern: String,
+        baseline: bool,
+    },
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Excluded(pattern) => write!(f, "excluded by glob {}", pattern),
+            Decision::Own => write!(f, "skipped: written by config-guardian (see --track-self)"),
+            Decision::Subdirectory => write!(f, "skipped: subdirectory (scans don't descend)"),
+            Decision::Special => write!(f, "skipped: special file"),
+            Decision::Linked => write!(f, "not read again: hard link to a file already hashed"),
+            Decision::Outside(resolved) => write!(
+                f,
+                "skipped: resolves to {}, outside the tree scanned",
+                resolved.display()
+            ),
+            Decision::Hashed(took) => write!(f, "hashed in {:.1?}", took),
+            Decision::Unchanged => {
+                write!(f, "not read: size and modification time match the snapshot")
+            }
+            Decision::Cached => {
+                write!(
+                    f,
+                    "not read: the hash cache has it, inode, size and ctime unchanged"
+                )
+            }
+            Decision::Unreadable(error) => write!(f, "skipped: unreadable ({})", error),
+            Decision::LeftOut {
+                pattern,
+                baseline: false,
+            } => write!(f, "left out of the baseline: excluded by glob {}", pattern),
+            Decision::LeftOut {
+                pattern,
+                baseline: true,
+            } => write!(
+                f,
+                "left out of the baseline: --baseline-exclude {}",
+                pattern
+            ),
+        }
+    }
+}
+
+/// Log what a scan did with `path`, and print it with `trace`.
+fn trace(path: &str, decision: &Decision) {
+    log::debug!(event = "scan_decision", path = path; "{}: {}", path, decision);
+    if config::settings().trace {
+        eprintln!("  {}: {}", path, decision);
+    }
+}
+
+/// Whether a scan of its directory would hash the entry recorded as `path`
+/// and read through `file`: its metadata if so, or why not.
+pub fn classify(path: &Path, file: &Path) -> Result<fs::Metadata, Decision> {
+    if config::settings().is_own_file(path) {
+        return Err(Decision::Own);
+    }
+    if let Some(pattern) = config::settings().excluded_by(path) {
+        return Err(Decision::Excluded(pattern.as_str().to_string()));
+    }
+    // Follows symlinks, like the read; a broken one is special.
+    match fs::metadata(file) {
+        Ok(metadata) if metadata.is_dir() => Err(Decision::Subdirectory),
+        Ok(metadata) if metadata.is_file() => Ok(metadata),
+        _ => Err(Decision::Special),
+    }
+}
+
+/// A file a scan of its directory would hash.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    /// The path as it will be recorded.
+    pub path: String,
+    /// Its raw form, for names that aren't valid Unicode.
+    raw: Option<String>,
+    /// The path it is read through.
+    file: PathBuf,
+    pub metadata: fs::Metadata,
+}
+
+/// What a walk of a directory found, before anything was read.
+#[derive(Clone, Debug, Default)]
+pub struct Walk {
+    /// The files to hash, in directory order.
+    pub files: Vec<Candidate>,
+    /// Problems met, not yet logged.
+    pub warnings: Vec<ScanWarning>,
+    /// The entries left out; `files_hashed` and `bytes_read` are zero.
+    pub stats: ScanStats,
+}
+
+/// List the files in `dir` that a scan would hash, applying the exclude
+/// patterns and leaving out subdirectories and special files, without
+/// reading any of them. `dir` may also be a glob pattern (see
+/// [`is_pattern`]), whose matches are the files considered.
+pub fn walk_directory(dir: &str) -> Result<Walk, GuardianError> {
+    if remote::is_remote(dir) {
+        return Err(GuardianError::Config(format!(
+            "{} is a remote target, whose files are only listed as they are read",
+            dir
+        )));
+    }
+    if config::settings().is_virtual(dir) {
+        let mut walk = Walk::default();
+        let file = long_path(Path::new(dir)).into_owned();
+        walk.visit(dir, 0, PathBuf::from(dir), file)?;
+        return Ok(walk);
+    }
+    check_root(dir)?;
+    if is_pattern(dir) {
+        return walk_pattern(dir);
+    }
+    let mut walk = Walk::default();
+    // Entries are read through the long form of `dir` but recorded under
+    // `dir` itself.
+    let entries = fs::read_dir(long_path(Path::new(dir))).map_err(GuardianError::io(dir))?;
+    for (seen, entry) in entries.enumerate() {
+        match entry {
+            Ok(entry) => walk.visit(
+                dir,
+                seen,
+                Path::new(dir).join(entry.file_name()),
+                entry.path(),
+            )?,
+            Err(e) => walk.unreadable(dir.to_string(), e.to_string()),
+        }
+    }
+    let stats = &walk.stats;
+    if walk.files.len() + stats.excluded + stats.special + stats.unreadable == 0 {
+        walk.warnings.push(ScanWarning {
+            kind: WarningKind::EmptyDirectory,
+            path: dir.to_string(),
+            error: None,
+        });
+    }
+    Ok(walk)
+}
+
+/// [`walk_directory`] for a glob pattern: the paths it matches, in order.
+fn walk_pattern(pattern: &str) -> Result<Walk, GuardianError> {
+    let mut walk = Walk::default();
+    let mut matches = Vec::new();
+    for scoped in scoped_patterns(pattern) {
+        matches.push(expand(&scoped)?);
+    }
+    let base = base_of(pattern);
+    let root = match base.as_os_str().is_empty() {
+        true => fs::canonicalize("."),
+        false => fs::canonicalize(long_path(&base)),
+    };
+    for (seen, matched) in matches.into_iter().flatten().enumerate() {
+        match matched {
+            Ok(recorded) => {
+                if let Ok(root) = &root
+                    && let Some(resolved) = outside(root, &recorded)
+                {
+                    walk.outside(recorded, resolved);
+                    continue;
+                }
+                let file = long_path(&recorded).into_owned();
+                walk.visit(pattern, seen, recorded, file)?;
+            }
+            Err(e) => walk.unreadable(utils::path_text(e.path()), e.error().to_string()),
+        }
+    }
+    Ok(walk)
+}
+
+impl Walk {
+    /// Sort the entry recorded as `recorded`, the `seen`th met in `dir`,
+    /// into a file to hash or a skipped entry.
+    fn visit(
+        &mut self,
+        dir: &str,
+        seen: usize,
+        recorded: PathBuf,
+        file: PathBuf,
+    ) -> Result<(), GuardianError> {
+        if !in_scope(dir, &recorded) {
+            return Ok(());
+        }
+        let decision = classify(&recorded, &file);
+        let path = utils::path_text(&recorded);
+        match decision {
+            Ok(metadata) => {
+                if let Some(limit) = config::settings().max_files
+                    && self.files.len() == limit
+                {
+                    log::error!(
+                        event = "scan_limit",
+                        dir = dir,
+                        limit = limit;
+                        "Scan of {} stopped at the limit of {} files", dir, limit
+                    );
+                    return Err(GuardianError::TooManyFiles {
+                        dir: dir.into(),
+                        limit,
+                        seen: seen + 1,
+                    });
+                }
+                self.files.push(Candidate {
+                    path,
+                    raw: utils::raw_path(&recorded),
+                    file,
+                    metadata,
+                });
+            }
+            Err(decision) => {
+                trace(&path, &decision);
+                match decision {
+                    Decision::Excluded(_) | Decision::Own => self.stats.excluded += 1,
+                    Decision::Subdirectory => self.stats.dirs_seen += 1,
+                    _ => {
+                        self.stats.special += 1;
+                        self.warnings.push(ScanWarning {
+                            kind: WarningKind::Special,
+                            path,
+                            error: None,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn outside(&mut self, recorded: PathBuf, resolved: PathBuf) {
+        let path = utils::path_text(&recorded);
+        trace(&path, &Decision::Outside(resolved.clone()));
+        self.stats.special += 1;
+        self.warnings.push(ScanWarning {
+            kind: WarningKind::Outside,
+            path,
+            error: Some(utils::path_text(&resolved)),
+        });
+    }
+
+    fn unreadable(&mut self, path: String, error: String) {
+        self.stats.unreadable += 1;
+        self.warnings.push(ScanWarning {
+            kind: WarningKind::Unreadable,
+            path,
+            error: Some(error),
+        });
+    }
+}
+
+/// Hash every file in `dir` that isn't excluded, without touching the saved
+/// snapshot, in one pass over the directory. Files that can't be read are
+/// left out and reported as warnings, which are also logged.
+///
+/// ```
+/// use config_guardian::scan_directory;
+///
+/// let dir = std::env::temp_dir().join(format!("guardian-doc-scan-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir)?;
+/// std::fs::write(dir.join("app.conf"), "listen 80")?;
+///
+/// let scan = scan_directory(dir.to_str().unwrap())?;
+/// assert_eq!(scan.snapshot.entries.len(), 1);
+/// assert!(scan.snapshot.metadata.created.is_some());
+/// assert!(scan.warnings.is_empty());
+/// assert_eq!(scan.stats.bytes_read, 9);
+/// # std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn scan_directory(dir: &str) -> Result<Scan, GuardianError> {
+    scan(dir, None)
+}
+
+/// [`scan_directory`] for a comparison with `baseline`, in two phases unless
+/// `paranoid` is set. First every file is listed and stat'ed: a file whose
+/// size and modification time are those `baseline` recorded is taken as
+/// unchanged and not read, but for a random `verify_sample` of them, which
+/// keeps a change made with the modification time put back from going
+/// unseen forever. Then the rest, and the sample, are read and hashed.
+///
+/// Files the baseline has a sampled hash of are hashed the same way again.
+pub fn scan_against(dir: &str, baseline: &Snapshot) -> Result<Scan, GuardianError> {
+    scan(dir, Some(baseline))
+}
+
+/// How long before a snapshot's scans started a file must have last been
+/// modified for its size and modification time to be trusted. A write in
+/// the same clock tick as the read that hashed it leaves them as they were,
+/// and some filesystems keep times to the second or two.
+const TRUST_MARGIN: chrono::TimeDelta = chrono::TimeDelta::seconds(2);
+
+/// The entries of `baseline` whose files may be taken as unchanged while
+/// their size and modification time match: those hashed the way scans hash
+/// now, with both recorded, and last modified well before the scans that
+/// recorded them. Virtual files are read whatever their size and time say.
+fn trustworthy(baseline: &Snapshot) -> HashMap<&str, &FileHash> {
+    let metadata = &baseline.metadata;
+    let Some(created) = metadata.created else {
+        return HashMap::new();
+    };
+    if config::settings().paranoid || metadata.hash != config::settings().hash {
+        return HashMap::new();
+    }
+    let took: u64 = metadata.stats.values().map(|stats| stats.duration_ms).sum();
+    let started = created - chrono::TimeDelta::milliseconds(took as i64) - TRUST_MARGIN;
+    baseline
+        .index()
+        .into_iter()
+        .filter(|(_, f)| {
+            !f.locked
+                && f.virtual_value.is_none()
+                && f.size.is_some()
+                && f.modified.is_some_and(|m| m < started)
+        })
+        .collect()
+}
+
+fn modified(candidate: &Candidate) -> Option<DateTime<Utc>> {
+    candidate
+        .metadata
+        .modified()
+        .ok()
+        .map(DateTime::<Utc>::from)
+}
+
+/// The latest modification time that isn't skewed: `clock_skew` from now.
+fn skew_horizon() -> DateTime<Utc> {
+    let skew = chrono::TimeDelta::from_std(config::settings().clock_skew)
+        .unwrap_or(chrono::TimeDelta::MAX);
+    Utc::now()
+        .checked_add_signed(skew)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+/// Log the files of `dir` modified after `horizon`, as a host with a
+/// broken clock leaves them.
+fn warn_of_skew(dir: &str, files: &[Candidate], horizon: DateTime<Utc>) {
+    let skewed: Vec<String> = files
+        .iter()
+        .filter(|candidate| modified(candidate).is_some_and(|m| m > horizon))
+        .map(|candidate| crate::redact::path(&candidate.path).into_owned())
+        .collect();
+    if skewed.is_empty() {
+        return;
+    }
+    log::warn!(
+        event = "clock_skew",
+        dir = dir,
+        paths:serde = skewed;
+        "Files in {} modified more than {} in the future, so read in full: {}",
+        dir,
+        utils::format_duration(config::settings().clock_skew),
+        skewed.join(", ")
+    );
+}
+
+/// `count` of the indexes below `n`, picked at random (Floyd's algorithm).
+fn sample(n: usize, count: usize) -> HashSet<usize> {
+    let mut picked = HashSet::new();
+    for j in n - count.min(n)..n {
+        let t = (utils::random_u64() % (j as u64 + 1)) as usize;
+        if !picked.insert(t) {
+            picked.insert(j);
+        }
+    }
+    picked
+}
+
+fn scan(dir: &str, baseline: Option<&Snapshot>) -> Result<Scan, GuardianError> {
+    if remote::is_remote(dir) {
+        return remote::scan(dir, baseline);
+    }
+    let started = Instant::now();
+    let scanning = progress::begin(dir, baseline.map(|b| b.entries.len()));
+    #[cfg(unix)]
+    owners::forget();
+    let Walk {
+        files,
+        mut warnings,
+        mut stats,
+    } = walk_directory(dir)?;
+    // Only the first path to a file with more than one link is read; the
+    // others take its hash.
+    let mut first_link: HashMap<(u64, u64), usize> = HashMap::new();
+    let linked_to: Vec<Option<usize>> = files
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| {
+            let inode = Inode::of(&candidate.metadata).filter(|inode| inode.nlink > 1)?;
+            let first = *first_link.entry((inode.dev, inode.ino)).or_insert(i);
+            (first != i).then_some(first)
+        })
+        .collect();
+
+    let horizon = skew_horizon();
+    warn_of_skew(dir, &files, horizon);
+
+    // Phase one: files whose size and modification time match the
+    // baseline take its hash, but for the sample. A modification time in
+    // the future says nothing about whether the file changed since.
+    let recorded = baseline.map(Snapshot::index).unwrap_or_default();
+    let trusted = baseline.map(trustworthy).unwrap_or_default();
+    let mut unchanged: Vec<Option<&FileHash>> = files
+        .iter()
+        .zip(&linked_to)
+        .map(|(candidate, link)| {
+            let recorded = *trusted.get(candidate.path.as_str())?;
+            let modified = modified(candidate);
+            (link.is_none()
+                && modified.is_none_or(|m| m <= horizon)
+                && recorded.size == Some(candidate.metadata.len())
+                && recorded.modified == modified)
+                .then_some(recorded)
+        })
+        .collect();
+    let sampled: Option<HashSet<String>> = (!trusted.is_empty()).then(|| {
+        let looks_unchanged: Vec<usize> = (0..files.len())
+            .filter(|&i| unchanged[i].is_some())
+            .collect();
+        let share = config::settings().verify_sample / 100.0;
+        let count = (looks_unchanged.len() as f64 * share).ceil() as usize;
+        sample(looks_unchanged.len(), count)
+            .into_iter()
+            .map(|j| {
+                let i = looks_unchanged[j];
+                unchanged[i] = None;
+                files[i].path.clone()
+            })
+            .collect()
+    });
+
+    // Each file is read the way the baseline hashed it.
+    let settings = config::settings();
+    let spans: Vec<Option<u64>> = files
+        .iter()
+        .map(|candidate| match recorded.get(candidate.path.as_str()) {
+            Some(entry) => entry.sampled,
+            None => settings.sample_span(candidate.metadata.len()),
+        })
+        .collect();
+    let limits = ReadLimits {
+        per_file: settings.read_timeout,
+        deadline: settings.deadline,
+        progress: Some(scanning.slot()),
+    };
+    let stopped = |total| {
+        move |stopped| match stopped {
+            Stopped::Interrupted { done } => GuardianError::Interrupted {
+                dir: dir.into(),
+                done,
+                total,
+            },
+            Stopped::TimedOut { done, timeout } => GuardianError::TimedOut {
+                dir: dir.into(),
+                timeout,
+                done,
+                total,
+            },
+        }
+    };
+
+    // The files left to read take the hash the cache has for them, but for
+    // a sample, read first to check the cache: one that hashes differently
+    // than the cache says clears it, and every file is read after all.
+    scanning.slot().hashing();
+    let mut cache = HashCache::open();
+    let identities: Vec<Option<Identity>> = files
+        .iter()
+        .map(|candidate| Identity::of(&candidate.metadata))
+        .collect();
+    let mut cached: Vec<Option<String>> = vec![None; files.len()];
+    let mut verified: HashMap<usize, Outcome> = HashMap::new();
+    if let Some(cache) = &mut cache {
+        for i in 0..files.len() {
+            if linked_to[i].is_some() || unchanged[i].is_some() {
+                continue;
+            }
+            let Some(identity) = &identities[i] else {
+                continue;
+            };
+            // The cache has no chunks to give, and a virtual file's inode
+            // and ctime don't change with what it holds.
+            if settings.is_chunked(&files[i].file) || settings.is_virtual(&files[i].path) {
+                continue;
+            }
+            match cache.get(identity, spans[i]) {
+                Some(hash) => cached[i] = Some(hash.to_string()),
+                None => stats.cache_misses += 1,
+            }
+        }
+        let hits: Vec<usize> = (0..files.len()).filter(|&i| cached[i].is_some()).collect();
+        let count = (hits.len() as f64 * settings.verify_sample / 100.0).ceil() as usize;
+        let checked: Vec<usize> = sample(hits.len(), count)
+            .into_iter()
+            .map(|j| hits[j])
+            .collect();
+        let to_check: Vec<(&Candidate, Option<u64>)> =
+            checked.iter().map(|&i| (&files[i], spans[i])).collect();
+        let reads = read_all(&to_check, settings.jobs, limits).map_err(stopped(to_check.len()))?;
+        let wrong = checked
+            .iter()
+            .zip(&reads)
+            .find(|(i, outcome)| {
+                matches!(&outcome.read, Ok(read) if cached[**i].as_deref() != Some(read.hash.as_str()))
+            })
+            .map(|(i, _)| *i);
+        stats.cache_hits = hits.len();
+        if let Some(i) = wrong {
+            eprintln!(
+                "Warning: the hash cache had a wrong hash for {}; it was cleared, and {} is read in full.",
+                crate::redact::shown(&files[i].path),
+                dir
+            );
+            log::warn!(
+                event = "hash_cache_poisoned",
+                dir = dir,
+                path = crate::redact::path(&files[i].path).as_ref();
+                "The hash cache had a wrong hash for {}; cleared it",
+                crate::redact::path(&files[i].path)
+            );
+            cache.clear();
+            cached = vec![None; files.len()];
+            stats.cache_misses += stats.cache_hits;
+            stats.cache_hits = 0;
+        }
+        for (i, outcome) in checked.into_iter().zip(reads) {
+            cached[i] = None;
+            verified.insert(i, outcome);
+        }
+    }
+
+    // Phase two reads the rest.
+    let to_read: Vec<(&Candidate, Option<u64>)> = (0..files.len())
+        .filter(|&i| {
+            linked_to[i].is_none()
+                && unchanged[i].is_none()
+                && cached[i].is_none()
+                && !verified.contains_key(&i)
+        })
+        .map(|i| (&files[i], spans[i]))
+        .collect();
+    let total = to_read.len();
+    scanning.slot().settle(files.len() - total - verified.len());
+    let mut reads = read_all(&to_read, settings.jobs, limits)
+        .map_err(stopped(total))?
+        .into_iter();
+
+    // Results are taken in walk order, so the entries and warnings come out
+    // the same however the reads were spread over threads.
+    let mut hashes = Vec::new();
+    let mut hash_at: Vec<Option<String>> = vec![None; files.len()];
+    for (i, candidate) in files.into_iter().enumerate() {
+        if let Some(recorded) = unchanged[i] {
+            trace(&candidate.path, &Decision::Unchanged);
+            stats.unchanged += 1;
+            hash_at[i] = Some(recorded.hash.clone());
+            let mut entry = FileHash {
+                path_base64: candidate.raw,
+                ..FileHash::with_metadata(
+                    candidate.path,
+                    recorded.hash.clone(),
+                    &candidate.file,
+                    Some(&candidate.metadata),
+                )
+            };
+            entry.volatile |= recorded.volatile;
+            entry.sampled = recorded.sampled;
+            entry.chunks = recorded.chunks.clone();
+            hashes.push(entry);
+            continue;
+        }
+        if let Some(hash) = cached[i].take() {
+            trace(&candidate.path, &Decision::Cached);
+            stats.files_hashed += 1;
+            hash_at[i] = Some(hash.clone());
+            hashes.push(FileHash {
+                path_base64: candidate.raw,
+                sampled: spans[i],
+                ..FileHash::with_metadata(
+                    candidate.path,
+                    hash,
+                    &candidate.file,
+                    Some(&candidate.metadata),
+                )
+            });
+            continue;
+        }
+        let Outcome {
+            took,
+            retries,
+            read,
+        } = match linked_to[i] {
+            Some(first) => match &hash_at[first] {
+                Some(hash) => {
+                    trace(&candidate.path, &Decision::Linked);
+                    stats.files_hashed += 1;
+                    hashes.push(FileHash {
+                        path_base64: candidate.raw,
+                        sampled: spans[first],
+                        ..FileHash::with_metadata(
+                            candidate.path,
+                            hash.clone(),
+                            &candidate.file,
+                            Some(&candidate.metadata),
+                        )
+                    });
+                    continue;
+                }
+                // The first path couldn't be read; this one may be.
+                None => read_all(&[(&candidate, spans[i])], 1, limits)
+                    .map_err(stopped(total))?
+                    .pop()
+                    .expect("one read for one file"),
+            },
+            None => match verified.remove(&i) {
+                Some(outcome) => outcome,
+                None => reads.next().expect("a read for every file not linked"),
+            },
+        };
+        let Candidate {
+            path,
+            raw,
+            file,
+            metadata,
+        } = candidate;
+        if retries > 0 {
+            stats.retried += 1;
+        }
+        match read {
+            Ok(read) => {
+                stats.files_hashed += 1;
+                stats.bytes_read += read.bytes;
+                trace(&path, &Decision::Hashed(took));
+                hash_at[i] = Some(read.hash.clone());
+                if let (Some(cache), Some(identity)) = (&mut cache, identities[i]) {
+                    cache.put(dir, identity, &read.hash, spans[i]);
+                }
+                let mut entry = FileHash {
+                    path_base64: raw,
+                    sampled: spans[i],
+                    chunks: read.chunks,
+                    virtual_value: read.value,
+                    ..FileHash::with_metadata(path, read.hash, &file, Some(&metadata))
+                };
+                entry.volatile |= read.grew;
+                hashes.push(entry);
+            }
+            Err(e) if utils::is_locked(&e) => {
+                stats.locked += 1;
+                trace(&path, &Decision::Unreadable(e.to_string()));
+                hashes.push(FileHash {
+                    path_base64: raw,
+                    locked: true,
+                    ..FileHash::with_metadata(path.clone(), String::new(), &file, Some(&metadata))
+                });
+                warnings.push(ScanWarning {
+                    kind: WarningKind::Locked,
+                    path,
+                    error: Some(e.to_string()),
+                });
+            }
+            Err(e) => {
+                stats.unreadable += 1;
+                trace(&path, &Decision::Unreadable(e.to_string()));
+                warnings.push(ScanWarning {
+                    kind: WarningKind::Unreadable,
+                    path,
+                    error: Some(e.to_string()),
+                });
+            }
+        }
+    }
+    // A recorded file that is now a directory, or a symlink to one or to
+    // nothing, isn't among the files hashed; an entry without a hash says
+    // what it became.
+    if let Some(baseline) = baseline {
+        let found: HashSet<String> = hashes
+            .iter()
+            .map(|f| f.path.clone())
+            .chain(warnings.iter().map(|w| w.path.clone()))
+            .collect();
+        for entry in &baseline.entries {
+            if found.contains(&entry.path) {
+                continue;
+            }
+            let file = entry.file_path();
+            if let Some((entry_type @ (EntryType::Directory | EntryType::Symlink), link_target)) =
+                EntryType::of(&long_path(&file))
+            {
+                hashes.push(FileHash {
+                    path: entry.path.clone(),
+                    path_base64: entry.path_base64.clone(),
+                    entry_type: Some(entry_type),
+                    link_target,
+                    ..FileHash::default()
+                });
+            }
+        }
+    }
+    if let Some(cache) = &mut cache {
+        // A narrowed scan doesn't see the files out of its scope.
+        if config::settings().scope.is_empty() {
+            let seen: HashSet<(u64, u64)> =
+                identities.iter().flatten().map(Identity::inode).collect();
+            cache.retain_seen(dir, &seen);
+        }
+        cache.save();
+    }
+    for warning in &warnings {
+        log::warn!(
+            event = "scan_warning",
+            kind:serde = warning.kind,
+            path = warning.path.as_str();
+            "{}", warning
+        );
+    }
+    stats.duration_ms = started.elapsed().as_millis() as u64;
+    resolve_links(&mut hashes, dir);
+
+    Ok(Scan {
+        snapshot: Snapshot {
+            metadata: SnapshotMetadata {
+                created: Some(Utc::now()),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                hash: config::settings().hash,
+                directories: vec![dir.to_string()],
+                stats: BTreeMap::from([(dir.to_string(), stats.clone())]),
+                acls: config::settings().acls,
+                tags: BTreeMap::new(),
+                taken: BTreeMap::new(),
+                options: BTreeMap::from([(dir.to_string(), ScanOptions::current())]),
+                invoker: Some(invoker::current().clone()),
+                instance_id: Some(identity::current().to_string()),
+                published: None,
+            },
+            entries: hashes,
+        },
+        warnings,
+        stats,
+        sampled,
+    })
+}
+
+/// Resolve the targets of the symlinks among `entries`, found by a scan
+/// of `dir`, and tell which lead out of it.
+fn resolve_links(entries: &mut [FileHash], dir: &str) {
+    let Ok(root) = std::path::absolute(base_of(dir)).map(|root| lexical(&root)) else {
+        return;
+    };
+    for entry in entries {
+        let Some(target) = &entry.link_target else {
+            continue;
+        };
+        let link = entry.file_path();
+        let Ok(resolved) = std::path::absolute(link.with_file_name(target)) else {
+            continue;
+        };
+        let resolved = lexical(&resolved);
+        match resolved.strip_prefix(&root) {
+            Ok(inside) => entry.link_resolved = Some(utils::path_text(inside)),
+            Err(_) => {
+                entry.link_resolved = Some(utils::path_text(&resolved));
+                entry.link_escapes = true;
+            }
+        }
+    }
+}
+
+/// `path` with its `.` and `..` components taken out, as far as they can
+/// be without looking at the filesystem.
+fn lexical(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+/// A file read and hashed by a scan.
+#[derive(Debug)]
+struct Read {
+    hash: String,
+    bytes: u64,
+    /// Cut from the content read, for a file `chunked_hash` names.
+    chunks: Option<Chunks>,
+    /// The content read, as text, for one of the `virtual_files`.
+    value: Option<String>,
+    /// Grew while it was read, for `skip_growing`.
+    grew: bool,
+}
+
+/// What came of reading one file.
+#[derive(Debug)]
+struct Outcome {
+    took: Duration,
+    /// Reads after the first, for transient errors.
+    retries: u32,
+    read: std::io::Result<Read>,
+}
+
+/// Read and hash `file`, which was `before` bytes long when listed, over
+/// `span` bytes at each end if it gets a sampled hash. A virtual file is
+/// hashed on what one bounded read gives, as it is.
+fn read_file(file: &Path, before: u64, span: Option<u64>, is_virtual: bool) -> Outcome {
+    let reading = Instant::now();
+    let (retries, content) = retrying(file, config::settings(), || match span {
+        _ if is_virtual => utils::read_virtual(file),
+        Some(span) => utils::read_sampled(file, span),
+        None => utils::read_shared(file),
+    });
+    if is_virtual {
+        let read = content.map(|content| Read {
+            hash: config::settings().hash.digest(&content),
+            bytes: content.len() as u64,
+            chunks: None,
+            value: Some(String::from_utf8_lossy(&content).into_owned()),
+            grew: false,
+        });
+        return Outcome {
+            took: reading.elapsed(),
+            retries,
+            read,
+        };
+    }
+    let read = content.map(|content| {
+        // A sampled read ends with the length, which wasn't read.
+        let bytes = content.len() as u64 - span.map_or(0, |_| 8);
+        // Of the bytes in the file, so that the ranges are where it changed.
+        let chunks =
+            (span.is_none() && config::settings().is_chunked(file)).then(|| chunk::split(&content));
+        let content = match span {
+            Some(_) => content,
+            None => normalize::apply(file, content),
+        };
+        Read {
+            hash: config::settings().hash.digest(&content),
+            bytes,
+            chunks,
+            value: None,
+            grew: config::settings().skip_growing
+                && ((span.is_none() && bytes > before)
+                    || fs::metadata(file).is_ok_and(|m| m.len() > before)),
+        }
+    });
+    Outcome {
+        took: reading.elapsed(),
+        retries,
+        read,
+    }
+}
+
+/// Run `read` of `file`, and again while it fails with one of the
+/// settings' transient errors, after a wait that doubles each time, until
+/// the retries run out. Gives the number of retries with the result.
+fn retrying<T>(
+    file: &Path,
+    settings: &config::Settings,
+    mut read: impl FnMut() -> std::io::Result<T>,
+) -> (u32, std::io::Result<T>) {
+    let mut retries = 0;
+    loop {
+        match read() {
+            Err(e) if retries < settings.retries && settings.is_transient(&e) && !cancelled() => {
+                let delay = settings.retry_delay.saturating_mul(1 << retries.min(16));
+                retries += 1;
+                log::debug!(
+                    event = "read_retry",
+                    path = utils::path_text(file).as_str(),
+                    attempt = retries;
+                    "Reading {} again in {:?} (retry {} of {}): {}",
+                    file.display(), delay, retries, settings.retries, e
+                );
+                std::thread::sleep(delay);
+            }
+            Err(e) if retries > 0 => {
+                let error =
+                    std::io::Error::new(e.kind(), format!("{} (after {} retries)", e, retries));
+                return (retries, Err(error));
+            }
+            read => return (retries, read),
+        }
+    }
+}
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Stop the scans in progress, and any started later, between files: they
+/// fail with [`GuardianError::Interrupted`], so nothing is saved. `snapshot`
+/// and `compare` call this on Ctrl+C.
+pub fn cancel_scans() {
+    CANCELLED.store(true, Ordering::Relaxed);
+}
+
+fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// How often reads in progress are checked on, for a cancellation, the
+/// deadline or a read taking too long.
+const READ_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits on reading the files of one scan.
+#[derive(Clone, Copy, Debug, Default)]
+struct ReadLimits {
+    /// A file taking longer is given up as unreadable.
+    per_file: Option<Duration>,
+    /// When to give up on the whole run, and the `--timeout` that set it.
+    deadline: Option<(Instant, Duration)>,
+    /// Where to publish the files read, for the scan in flight.
+    progress: Option<progress::Slot>,
+}
+
+/// Why [`read_all`] stopped before reading every file, and how many it had.
+#[derive(Debug, PartialEq, Eq)]
+enum Stopped {
+    Interrupted { done: usize },
+    TimedOut { done: usize, timeout: Duration },
+}
+
+enum Progress {
+    Started(usize),
+    Done(usize, Outcome),
+}
+
+/// A file [`read_all`] is to read, as its threads take it.
+struct Queued {
+    file: PathBuf,
+    len: u64,
+    span: Option<u64>,
+    /// One of the `virtual_files`.
+    is_virtual: bool,
+}
+
+/// Read `files` on up to `jobs` threads, each taking the next file not yet
+/// taken, and give the results in the order of `files`. Snapshots and
+/// comparisons both scan through here. A file with a span gets a sampled
+/// hash.
+///
+/// The threads are never waited on: a read stuck on a hung network mount
+/// is abandoned, with its thread, once it takes longer than the per-file
+/// limit (a thread takes its place) or when the scan is cancelled or runs
+/// past its deadline.
+fn read_all(
+    files: &[(&Candidate, Option<u64>)],
+    jobs: usize,
+    limits: ReadLimits,
+) -> Result<Vec<Outcome>, Stopped> {
+    let queue: Arc<Vec<Queued>> = Arc::new(
+        files
+            .iter()
+            .map(|(c, span)| Queued {
+                file: c.file.clone(),
+                len: c.metadata.len(),
+                span: *span,
+                is_virtual: config::settings().is_virtual(&c.path),
+            })
+            .collect(),
+    );
+    let next = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+    let spawn = || {
+        let (queue, next, stop, tx) = (queue.clone(), next.clone(), stop.clone(), tx.clone());
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(Queued {
+                    file,
+                    len,
+                    span,
+                    is_virtual,
+                }) = queue.get(i)
+                else {
+                    return;
+                };
+                throttle::before_read(span.map_or(*len, |span| (2 * span).min(*len)));
+                if tx.send(Progress::Started(i)).is_err() {
+                    return;
+                }
+                let outcome = read_file(file, *len, *span, *is_virtual);
+                let took = outcome.took;
+                if tx.send(Progress::Done(i, outcome)).is_err() {
+                    return;
+                }
+                throttle::after_read(took);
+            }
+        });
+    };
+    for _ in 0..jobs.clamp(1, files.len().max(1)) {
+        spawn();
+    }
+
+    let mut reads: Vec<Option<_>> = files.iter().map(|_| None).collect();
+    let mut reading: HashMap<usize, Instant> = HashMap::new();
+    let mut counter = progress::Counter::new(limits.progress);
+    let mut left = files.len();
+    let stopped = loop {
+        if left == 0 {
+            break None;
+        }
+        let done = files.len() - left;
+        if cancelled() {
+            break Some(Stopped::Interrupted { done });
+        }
+        if let Some((deadline, timeout)) = limits.deadline
+            && Instant::now() >= deadline
+        {
+            break Some(Stopped::TimedOut { done, timeout });
+        }
+        match rx.recv_timeout(READ_CHECK_INTERVAL) {
+            Ok(Progress::Started(i)) => {
+                reading.insert(i, Instant::now());
+            }
+            // A read given up on may still finish; it stays given up.
+            Ok(Progress::Done(i, outcome)) => {
+                reading.remove(&i);
+                if reads[i].is_none() {
+                    counter.add(outcome.read.as_ref().map_or(0, |read| read.bytes));
+                    reads[i] = Some(outcome);
+                    left -= 1;
+                }
+            }
+            // The channel can't disconnect while `tx` is held here.
+            Err(_) => {}
+        }
+        if let Some(limit) = limits.per_file {
+            let stuck: Vec<usize> = reading
+                .iter()
+                .filter(|(_, started)| started.elapsed() >= limit)
+                .map(|(i, _)| *i)
+                .collect();
+            for i in stuck {
+                reading.remove(&i);
+                let error = std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("read timed out after {:?}", limit),
+                );
+                reads[i] = Some(Outcome {
+                    took: limit,
+                    retries: 0,
+                    read: Err(error),
+                });
+                counter.add(0);
+                left -= 1;
+                spawn();
+            }
+        }
+    };
+    stop.store(true, Ordering::Relaxed);
+    match stopped {
+        Some(stopped) => Err(stopped),
+        None => Ok(reads
+            .into_iter()
+            .map(|read| read.expect("every file is read"))
+            .collect()),
+    }
+}
+
+/// Where [`Snapshot::save`] writes a snapshot before moving it into place.
+pub fn partial_path(snapshot_file: &Path) -> PathBuf {
+    let mut name = snapshot_file.as_os_str().to_owned();
+    name.push(".partial");
+    name.into()
+}
+
+/// The first byte of `reader` that isn't whitespace, left unread.
+/// A snapshot's entries as read, each parsed on its own, so that one that
+/// doesn't parse is dropped alone rather than failing the whole snapshot.
+struct Entries(Vec<Result<FileHash, Dropped>>);
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Entries, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Entries;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a list of entries")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Entries, A::Error> {
+                let mut entries = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(value) = seq.next_element::<serde_json::Value>()? {
+                    let entry = entries.len() + 1;
+                    entries.push(FileHash::deserialize(&value).map_err(|e| Dropped {
+                        broken: Broken::Malformed,
+                        entry,
+                        path: value.get("path").and_then(|p| p.as_str()).map(String::from),
+                        why: e.to_string(),
+                    }));
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_seq(Visitor)
+    }
+}
+
+/// Why loading dropped an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Broken {
+    /// It didn't parse, for want of a field or with one of the wrong type.
+    Malformed,
+    /// Another entry has its path and is newer.
+    Duplicate,
+    /// Its path has a `..` in it, so it names a file outside the directory.
+    Escaping,
+}
+
+/// An entry loading dropped: the `entry`th of the file.
+#[derive(Debug)]
+struct Dropped {
+    broken: Broken,
+    entry: usize,
+    path: Option<String>,
+    why: String,
+}
+
+impl Dropped {
+    /// What was dropped and why, its path as `how` says to show it.
+    fn describe(&self, how: fn(&str) -> std::borrow::Cow<'_, str>) -> String {
+        match &self.path {
+            Some(path) => format!("entry {} ({}): {}", self.entry, how(path), self.why),
+            None => format!("entry {}: {}", self.entry, self.why),
+        }
+    }
+}
+
+/// The entries fit to compare against, in the order read, and those
+/// dropped.
+fn check_entries(read: Vec<Result<FileHash, Dropped>>) -> (Vec<FileHash>, Vec<Dropped>) {
+    let mut entries: Vec<(usize, FileHash)> = Vec::with_capacity(read.len());
+    let mut dropped = Vec::new();
+    let mut seen: HashMap<(String, Option<String>), usize> = HashMap::new();
+    for (i, entry) in read.into_iter().enumerate() {
+        let n = i + 1;
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(malformed) => {
+                dropped.push(malformed);
+                continue;
+            }
+        };
+        if entry.path.split(['/', '\\']).any(|part| part == "..") {
+            dropped.push(Dropped {
+                broken: Broken::Escaping,
+                entry: n,
+                path: Some(entry.path),
+                why: "the path leads out of its directory".to_string(),
+            });
+            continue;
+        }
+        let key = (entry.path.clone(), entry.path_base64.clone());
+        let Some(&at) = seen.get(&key) else {
+            seen.insert(key, entries.len());
+            entries.push((n, entry));
+            continue;
+        };
+        let earlier = entries[at].0;
+        // Of two equally new, the later was written last.
+        let (gone, kept) = if entry.modified >= entries[at].1.modified {
+            entries[at] = (n, entry);
+            (earlier, n)
+        } else {
+            (n, earlier)
+        };
+        dropped.push(Dropped {
+            broken: Broken::Duplicate,
+            entry: gone,
+            path: Some(key.0),
+            why: format!("a duplicate of entry {}, which is newer", kept),
+        });
+    }
+    (
+        entries.into_iter().map(|(_, entry)| entry).collect(),
+        dropped,
+    )
+}
+
+/// Warn of each entry dropped from the snapshot at `path`, and of how many.
+/// A snapshot that doesn't match its seal was changed by something other
+/// than config-guardian, perhaps to make a comparison agree with changes
+/// it should report.
+fn warn_unsealed(path: &Path) {
+    eprintln!(
+        "Warning: {} doesn't match its seal: it was modified outside of config-guardian.",
+        path.display()
+    );
+    log::error!(
+        event = "baseline_modified",
+        path = utils::path_text(path).as_str(),
+        reason = "it doesn't match its seal";
+        "Snapshot {} was modified outside of config-guardian: it doesn't match its seal",
+        path.display()
+    );
+}
+
+fn warn_dropped(path: &Path, total: usize, dropped: &[Dropped]) {
+    for entry in dropped {
+        eprintln!(
+            "Warning: {}: dropped {}",
+            path.display(),
+            entry.describe(redact::shown)
+        );
+    }
+    let count = |broken| dropped.iter().filter(|d| d.broken == broken).count();
+    let kinds: Vec<String> = [
+        (Broken::Malformed, "malformed"),
+        (Broken::Duplicate, "duplicated"),
+        (Broken::Escaping, "leading out of its directory"),
+    ]
+    .into_iter()
+    .filter(|(broken, _)| count(*broken) > 0)
+    .map(|(broken, name)| format!("{} {}", count(broken), name))
+    .collect();
+    eprintln!(
+        "Warning: dropped {} of the {} entries of {} ({}); --strict-load refuses such a snapshot.",
+        dropped.len(),
+        total,
+        path.display(),
+        kinds.join(", ")
+    );
+    let problems: Vec<String> = dropped.iter().map(|d| d.describe(redact::path)).collect();
+    log::warn!(
+        event = "snapshot_entries_dropped",
+        path = path.display().to_string().as_str(),
+        dropped = dropped.len(),
+        total = total;
+        "Dropped {} of the {} entries of {}: {}",
+        dropped.len(),
+        total,
+        path.display(),
+        problems.join("; ")
+    );
+}
+
+fn first_byte(reader: &mut impl BufRead) -> std::io::Result<Option<u8>> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let byte = buf[i];
+                reader.consume(i);
+                return Ok(Some(byte));
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// `files` by path, the first entry for a path winning.
+pub(crate) fn index_of(files: &[FileHash]) -> HashMap<&str, &FileHash> {
+    files.iter().rev().map(|f| (f.path.as_str(), f)).collect()
+}
+
+pub(crate) fn hash_of(index: &HashMap<&str, &FileHash>, path: &str) -> Option<String> {
+    index.get(path).map(|f| f.hash.clone())
+}
+
+/// The saved snapshot's entries for the files in `dir` that aren't excluded.
+pub fn snapshot_of(dir: &str) -> Result<Snapshot, GuardianError> {
+    snapshot_at(&config::settings().snapshot_file, dir)
+}
+
+/// [`snapshot_of`] for the snapshot saved at `path`, such as an archived one.
+///
+/// Where the command found `dir`'s entries recorded under another
+/// directory (see [`config::Settings::baseline_roots`]), they are taken
+/// from there, as if recorded under `dir`.
+pub fn snapshot_at(path: &Path, dir: &str) -> Result<Snapshot, GuardianError> {
+    baseline_at(path, dir).map(|(snapshot, _)| snapshot)
+}
+
+/// [`snapshot_at`], also returning the paths of the entries
+/// `baseline_exclude` left out, which a comparison mustn't report on.
+pub fn baseline_at(path: &Path, dir: &str) -> Result<(Snapshot, Vec<String>), GuardianError> {
+    let mut snapshot = Snapshot::load(path)?;
+    let settings = config::settings();
+    if let Some(root) = settings.baseline_roots.get(dir) {
+        rebase(&mut snapshot, root, dir);
+    }
+    let covered = covered_by(dir);
+    let base = base_of(dir);
+    let mut left_out = Vec::new();
+    // Snapshots taken before own files were left out may still hold them.
+    snapshot.entries.retain(|f| {
+        let path = Path::new(&f.path);
+        if !covered(f) || !in_scope(dir, path) || settings.is_own_file(path) {
+            return false;
+        }
+        let (pattern, baseline) = match settings.excluded_by(path) {
+            Some(pattern) => (pattern, false),
+            None => match settings.baseline_excluded_by(path, &base) {
+                Some(pattern) => (pattern, true),
+                None => return true,
+            },
+        };
+        let pattern = pattern.to_string();
+        trace(&f.path, &Decision::LeftOut { pattern, baseline });
+        if baseline {
+            left_out.push(f.path.clone());
+        }
+        false
+    });
+    Ok((snapshot, left_out))
+}
+
+/// Move `snapshot`'s entries for `root`, and what it records of `root`, to
+/// `dir`, dropping those it had for `dir`.
+fn rebase(snapshot: &mut Snapshot, root: &str, dir: &str) {
+    let covered = covered_by(root);
+    let mut entries = Vec::new();
+    for mut entry in std::mem::take(&mut snapshot.entries) {
+        if covered(&entry) {
+            let name = Path::new(&entry.path).file_name().unwrap_or_default();
+            entry.path = utils::path_text(&Path::new(dir).join(name));
+            entry.path_base64 = None;
+            entries.push(entry);
+        } else if Path::new(&entry.path).parent() != Some(Path::new(dir)) {
+            entries.push(entry);
+        }
+    }
+    snapshot.entries = entries;
+    let metadata = &mut snapshot.metadata;
+    metadata.directories.retain(|d| d != dir);
+    for d in &mut metadata.directories {
+        if d == root {
+            *d = dir.to_string();
+        }
+    }
+    if let Some(stats) = metadata.stats.remove(root) {
+        metadata.stats.insert(dir.to_string(), stats);
+    }
+    if let Some(taken) = metadata.taken.remove(root) {
+        metadata.taken.insert(dir.to_string(), taken);
+    }
+    if let Some(options) = metadata.options.remove(root) {
+        metadata.options.insert(dir.to_string(), options);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_key_value_pairs_without_control_characters() {
+        assert_eq!(
+            parse_tag("change_ticket=CHG-4432"),
+            Ok(("change_ticket".into(), "CHG-4432".into()))
+        );
+        assert_eq!(parse_tag("note=a=b c"), Ok(("note".into(), "a=b c".into())));
+        assert_eq!(parse_tag("empty="), Ok(("empty".into(), String::new())));
+        assert!(parse_tag("release").is_err());
+        assert!(parse_tag(" =2.7.1").is_err());
+        assert!(parse_tag("release=2.7.1\n").is_err());
+        let tags = BTreeMap::from([("b".into(), "2".into()), ("a".into(), "1".into())]);
+        assert_eq!(format_tags(&tags), "a=1, b=2");
+    }
+
+    #[test]
+    fn loads_snapshots_saved_as_a_bare_list() {
+        let path =
+            std::env::temp_dir().join(format!("guardian-legacy-{}.json", std::process::id()));
+        fs::write(&path, r#"[{"path": "/etc/app.conf", "hash": "abc"}]"#).unwrap();
+        let snapshot = Snapshot::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(snapshot.metadata.created.is_none());
+        assert_eq!(
+            snapshot.get("/etc/app.conf").map(|f| f.hash.as_str()),
+            Some("abc")
+        );
+    }
+
+    #[test]
+    fn saving_streams_a_sealed_snapshot_into_place() {
+        let dir = std::env::temp_dir().join(format!("guardian-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot.json");
+        let snapshot = Snapshot {
+            entries: (0..1000)
+                .map(|i| FileHash {
+                    path: format!("/etc/{}.conf", i),
+                    hash: format!("{:x}", i),
+                    ..FileHash::default()
+                })
+                .collect(),
+            ..Snapshot::default()
+        };
+        snapshot.save(&path).unwrap();
+        assert!(!partial_path(&path).exists());
+        let content = fs::read(&path).unwrap();
+        assert_eq!(
+            seal::read(&path).map(|seal| seal.sha256),
+            Some(seal::checksum(&content))
+        );
+        let loaded = Snapshot::load(&path).unwrap();
+        assert_eq!(loaded.entries, snapshot.entries);
+
+        // Leading whitespace doesn't hide a bare list.
+        fs::write(
+            &path,
+            "\n  [{\"path\": \"/etc/app.conf\", \"hash\": \"abc\"}]",
+        )
+        .unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap().entries.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn subdirectories_are_counted_but_dont_make_a_directory_non_empty() {
+        let dir = std::env::temp_dir().join(format!("guardian-stats-{}", std::process::id()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let scan = scan_directory(dir.to_str().unwrap()).unwrap();
+        assert_eq!(scan.warnings[0].kind, WarningKind::EmptyDirectory);
+        assert_eq!(scan.stats.dirs_seen, 1);
+
+        fs::write(dir.join("app.conf"), "listen 80").unwrap();
+        let scan = scan_directory(dir.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(scan.warnings.is_empty());
+        assert_eq!((scan.stats.files_hashed, scan.stats.bytes_read), (1, 9));
+        assert_eq!(
+            scan.snapshot.metadata.stats[dir.to_str().unwrap()],
+            scan.stats
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_are_read_once() {
+        let dir = std::env::temp_dir().join(format!("guardian-links-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.conf"), "listen 80").unwrap();
+        fs::hard_link(dir.join("app.conf"), dir.join("app.conf.orig")).unwrap();
+        let scan = scan_directory(dir.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((scan.stats.files_hashed, scan.stats.bytes_read), (2, 9));
+        let [a, b] = &scan.snapshot.entries[..] else {
+            panic!("expected two entries")
+        };
+        assert_eq!((&a.hash, a.inode), (&b.hash, b.inode));
+        assert_eq!(a.inode.map(|i| i.nlink), Some(2));
+    }
+
+    #[test]
+    fn reads_spread_over_threads_come_back_in_walk_order() {
+        let dir = std::env::temp_dir().join(format!("guardian-jobs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for i in 0..40 {
+            fs::write(dir.join(format!("{:02}.conf", i)), "x".repeat(i * 1000)).unwrap();
+        }
+        let walk = walk_directory(dir.to_str().unwrap()).unwrap();
+        // Gone between the walk and the read.
+        fs::remove_file(dir.join("07.conf")).unwrap();
+        let files: Vec<(&Candidate, Option<u64>)> = walk.files.iter().map(|c| (c, None)).collect();
+        let results = |jobs| -> Vec<Result<String, std::io::ErrorKind>> {
+            read_all(&files, jobs, ReadLimits::default())
+                .unwrap()
+                .into_iter()
+                .map(|outcome| outcome.read.map(|r| r.hash).map_err(|e| e.kind()))
+                .collect()
+        };
+        let alone = results(1);
+        let gone = files.iter().position(|(c, _)| c.path.ends_with("07.conf"));
+        assert_eq!(alone.iter().position(Result::is_err), gone, "{:?}", alone);
+        assert_eq!(results(4), alone);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn comparisons_read_only_files_that_look_changed_and_a_sample() {
+        let dir = std::env::temp_dir().join(format!("guardian-phases-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let an_hour_ago = std::time::SystemTime::now() - Duration::from_secs(3600);
+        let write = |name: &str, content: &str, modified| {
+            fs::write(dir.join(name), content).unwrap();
+            if let Some(modified) = modified {
+                let file = fs::File::options()
+                    .write(true)
+                    .open(dir.join(name))
+                    .unwrap();
+                file.set_modified(modified).unwrap();
+            }
+        };
+        for name in ["a.conf", "b.conf", "c.conf"] {
+            write(name, "old", Some(an_hour_ago));
+        }
+        // Written just before the snapshot: its time can't be trusted.
+        write("d.conf", "new", None);
+        let dir_text = dir.to_str().unwrap();
+        let baseline = scan_directory(dir_text).unwrap().snapshot;
+
+        write("a.conf", "edited", None);
+        // Same size, with the time put back.
+        write("b.conf", "bad", Some(an_hour_ago));
+        let scan = scan_against(dir_text, &baseline).unwrap();
+        let sampled = scan.sampled.clone().expect("a two-phase scan");
+        // One of b.conf and c.conf, which look unchanged, is sampled.
+        assert_eq!(sampled.len(), 1);
+        assert_eq!(scan.stats.unchanged, 1);
+        assert_eq!(scan.stats.files_hashed, 3);
+
+        let path = |name: &str| utils::path_text(&dir.join(name));
+        let report = crate::compare(&baseline, &scan.snapshot);
+        let mut drifted: Vec<&str> = report.drifts.iter().map(|d| d.path.as_str()).collect();
+        drifted.sort();
+        let caught = sampled.contains(&path("b.conf"));
+        let expected = [Some(path("a.conf")), caught.then(|| path("b.conf"))];
+        assert_eq!(drifted, expected.iter().flatten().collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_modified_in_the_future_are_always_read() {
+        let dir = std::env::temp_dir().join(format!("guardian-skew-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = std::time::SystemTime::now();
+        let day = Duration::from_secs(86400);
+        for (name, modified) in [
+            ("a.conf", now - day),
+            ("b.conf", now - day),
+            ("ahead.conf", now + day),
+        ] {
+            fs::write(dir.join(name), "same").unwrap();
+            let file = fs::File::options()
+                .write(true)
+                .open(dir.join(name))
+                .unwrap();
+            file.set_modified(modified).unwrap();
+        }
+        let dir_text = dir.to_str().unwrap();
+        let mut baseline = scan_directory(dir_text).unwrap().snapshot;
+        // As a snapshot taken while the clock was as far off would be.
+        baseline.metadata.created = Some(Utc::now() + chrono::TimeDelta::days(2));
+
+        let scan = scan_against(dir_text, &baseline).unwrap();
+        let sampled = scan.sampled.expect("a two-phase scan");
+        // Of the two that look unchanged, one is the sample.
+        assert_eq!(scan.stats.unchanged, 1);
+        assert_eq!(scan.stats.files_hashed, 2);
+        assert!(!sampled.contains(&utils::path_text(&dir.join("ahead.conf"))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sampled_hashes_cover_the_ends_and_the_length() {
+        let dir = std::env::temp_dir().join(format!("guardian-sampled-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("disk.img");
+        let hash = |content: &str| {
+            fs::write(&file, content).unwrap();
+            let outcome = read_file(&file, content.len() as u64, Some(4), false);
+            let read = outcome.read.unwrap();
+            assert_eq!(read.hash, hash_file_as(&file, Some(4)).unwrap());
+            (read.hash, read.bytes)
+        };
+        let (original, bytes) = hash("head-middle-tail");
+        assert_eq!(bytes, 8, "only the ends are read");
+        assert_eq!(hash("head-MIDDLE-tail").0, original);
+        assert_ne!(hash("HEAD-middle-tail").0, original);
+        assert_ne!(hash("head-middle-TAIL").0, original);
+        assert_ne!(hash("head-middle--tail").0, original, "the length counts");
+        // Short files are read whole.
+        assert_eq!(hash("headtail").1, 8);
+        assert_ne!(hash("headtail").0, hash_file(&file).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transient_errors_are_retried_until_the_retries_run_out() {
+        let code = utils::TRANSIENT_ERRORS.first().map_or(5, |(_, code)| *code);
+        let mut settings = config::Settings::default();
+        settings.retries = 2;
+        settings.retry_delay = Duration::from_millis(1);
+        settings.retry_on = vec![code];
+        let file = Path::new("flaky.conf");
+        // Fails `times` times with OS error `code`, then reads.
+        let failing = |times: u32, code: i32| {
+            let mut calls = 0;
+            move || {
+                calls += 1;
+                match calls <= times {
+                    true => Err(std::io::Error::from_raw_os_error(code)),
+                    false => Ok(calls),
+                }
+            }
+        };
+
+        let (retries, read) = retrying(file, &settings, failing(2, code));
+        assert_eq!((retries, read.unwrap()), (2, 3));
+
+        let (retries, read) = retrying(file, &settings, failing(3, code));
+        assert_eq!(retries, 2);
+        assert!(read.unwrap_err().to_string().ends_with("(after 2 retries)"));
+
+        // ENOENT, or ERROR_FILE_NOT_FOUND on Windows.
+        let (retries, read) = retrying(file, &settings, failing(1, 2));
+        assert_eq!(retries, 0, "only the configured errors are retried");
+        assert_eq!(read.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stuck_reads_time_out_and_deadlines_stop_the_run() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = std::env::temp_dir().join(format!("guardian-stuck-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.conf"), "a").unwrap();
+        let fifo = dir.join("b.conf");
+        let name = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+        // Opening a FIFO to read blocks until something opens it to write,
+        // like a read from a hung network mount.
+        let walk = walk_directory(dir.to_str().unwrap()).unwrap();
+        let a = walk.files.into_iter().next().unwrap();
+        let stuck = Candidate {
+            path: utils::path_text(&fifo),
+            raw: None,
+            file: fifo.clone(),
+            metadata: a.metadata.clone(),
+        };
+        let files = [(&a, None), (&stuck, None)];
+
+        let limits = ReadLimits {
+            per_file: Some(Duration::from_millis(200)),
+            ..ReadLimits::default()
+        };
+        let results = read_all(&files, 2, limits).unwrap();
+        assert!(results[0].read.is_ok());
+        let error = results[1].read.as_ref().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+
+        let timeout = Duration::from_millis(200);
+        let limits = ReadLimits {
+            deadline: Some((Instant::now() + timeout, timeout)),
+            ..ReadLimits::default()
+        };
+        let stopped = read_all(&files, 2, limits).unwrap_err();
+        assert_eq!(stopped, Stopped::TimedOut { done: 1, timeout });
+
+        // Let the stuck readers go; one writer opening wakes them all.
+        use std::os::unix::fs::OpenOptionsExt;
+        let writer = fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&fifo);
+        drop(writer);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_arent_unicode_are_kept_apart_and_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = std::env::temp_dir().join(format!("guardian-bytes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Both would be "app\u{FFFD}.conf" if decoded lossily.
+        let latin1 = dir.join(std::ffi::OsStr::from_bytes(b"app\xE9.conf"));
+        let cp1252 = dir.join(std::ffi::OsStr::from_bytes(b"app\x82.conf"));
+        fs::write(&latin1, "listen 80").unwrap();
+        fs::write(&cp1252, "listen 8080").unwrap();
+        let dir_text = dir.to_str().unwrap();
+        let baseline = scan_directory(dir_text).unwrap().snapshot;
+
+        let entry = baseline
+            .get(&format!("{}/app\\xE9.conf", dir_text))
+            .expect("entry recorded with the byte spelled out");
+        assert_eq!(entry.file_path(), latin1);
+        assert!(
+            baseline
+                .get(&format!("{}/app\\x82.conf", dir_text))
+                .is_some()
+        );
+        assert_eq!(baseline.entries.len(), 2);
+
+        let json = serde_json::to_string(&baseline).unwrap();
+        let loaded: Snapshot = serde_json::from_str(&json).unwrap();
+        let paths: BTreeSet<PathBuf> = loaded.entries.iter().map(FileHash::file_path).collect();
+        assert_eq!(paths, BTreeSet::from([latin1.clone(), cp1252.clone()]));
+
+        fs::write(&cp1252, "listen 443").unwrap();
+        let current = scan_directory(dir_text).unwrap().snapshot;
+        fs::remove_dir_all(&dir).unwrap();
+        let report = crate::compare(&loaded, &current);
+        assert_eq!(report.drifts.len(), 1);
+        assert_eq!(report.drifts[0].path, format!("{}/app\\x82.conf", dir_text));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn device_names_trailing_dots_and_long_paths_are_scanned() {
+        let root = std::env::temp_dir().join(format!("guardian-win-{}", std::process::id()));
+        // Well past MAX_PATH once the file name is added.
+        let dir = root.join("d".repeat(120)).join("e".repeat(120));
+        let dir = dir.to_str().unwrap();
+        fs::create_dir_all(long_path(Path::new(dir))).unwrap();
+        let names = ["CON", "aux.conf", "trailing.", "space ", "ordinary.conf"];
+        for name in names {
+            fs::write(long_path(&Path::new(dir).join(name)), name).unwrap();
+        }
+
+        let scan = scan_directory(dir).unwrap();
+        fs::remove_dir_all(long_path(&root)).unwrap();
+        assert!(scan.warnings.is_empty(), "{:?}", scan.warnings);
+        let mut recorded: Vec<String> = scan.snapshot.entries.into_iter().map(|f| f.path).collect();
+        recorded.sort();
+        let mut expected: Vec<String> = names
+            .iter()
+            .map(|name| Path::new(dir).join(name).to_string_lossy().into_owned())
+            .collect();
+        expected.sort();
+        assert_eq!(recorded, expected);
+    }
+
+    #[test]
+    fn missing_and_corrupt_snapshots_are_told_apart() {
+        let path =
+            std::env::temp_dir().join(format!("guardian-corrupt-{}.json", std::process::id()));
+        assert!(matches!(
+            Snapshot::load(&path),
+            Err(GuardianError::SnapshotMissing { .. })
+        ));
+        fs::write(&path, "{\"entries\": [").unwrap();
+        let loaded = Snapshot::load(&path);
+        fs::remove_file(&path).unwrap();
+        match loaded {
+            Err(e @ GuardianError::SnapshotCorrupt { .. }) => {
+                assert!(e.to_string().starts_with(&path.display().to_string()))
+            }
+            other => panic!("expected a corrupt snapshot, got {:?}", other),
+        }
+    }
+
+    /// Load `entries` as a snapshot's, saved beside metadata as `save`
+    /// writes it, leniently and then strictly.
+    fn load_entries(name: &str, entries: &str) -> (Snapshot, Result<Snapshot, GuardianError>) {
+        let path = std::env::temp_dir().join(format!(
+            "guardian-broken-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let metadata = serde_json::to_string(&SnapshotMetadata::default()).unwrap();
+        fs::write(
+            &path,
+            format!("{{\"metadata\": {}, \"entries\": {}}}", metadata, entries),
+        )
+        .unwrap();
+        let lenient = Snapshot::read(&path, false).unwrap();
+        let strict = Snapshot::read(&path, true);
+        fs::remove_file(&path).unwrap();
+        (lenient, strict)
+    }
+
+    fn paths(snapshot: &Snapshot) -> Vec<&str> {
+        snapshot.entries.iter().map(|f| f.path.as_str()).collect()
+    }
+
+    #[test]
+    fn malformed_entries_are_dropped_alone() {
+        let (lenient, strict) = load_entries(
+            "malformed",
+            r#"[{"path": "d/a.conf", "hash": "aa"}, {"path": "d/b.conf"},
+                {"path": "d/c.conf", "hash": 7}, "d/d.conf", {"path": "d/e.conf", "hash": "ee"}]"#,
+        );
+        assert_eq!(paths(&lenient), ["d/a.conf", "d/e.conf"]);
+        let Err(GuardianError::SnapshotEntries { problems, .. }) = strict else {
+            panic!("--strict-load refuses malformed entries");
+        };
+        assert_eq!(problems.len(), 3);
+        assert!(
+            problems[0].starts_with("entry 2 (d/b.conf): missing field `hash`"),
+            "{:?}",
+            problems
+        );
+        assert!(
+            problems[1].starts_with("entry 3 (d/c.conf): invalid type"),
+            "{:?}",
+            problems
+        );
+        assert!(
+            problems[2].starts_with("entry 4: invalid type"),
+            "{:?}",
+            problems
+        );
+    }
+
+    #[test]
+    fn of_duplicated_paths_the_newest_entry_is_kept() {
+        let (lenient, strict) = load_entries(
+            "duplicate",
+            r#"[{"path": "d/a.conf", "hash": "old", "modified": "2024-01-01T00:00:00Z"},
+                {"path": "d/b.conf", "hash": "bb"},
+                {"path": "d/a.conf", "hash": "new", "modified": "2024-06-01T00:00:00Z"},
+                {"path": "d/a.conf", "hash": "older", "modified": "2023-01-01T00:00:00Z"},
+                {"path": "d/b.conf", "hash": "later"}]"#,
+        );
+        assert_eq!(paths(&lenient), ["d/a.conf", "d/b.conf"]);
+        assert_eq!(lenient.entries[0].hash, "new");
+        // Neither is known to be newer: the one written last wins.
+        assert_eq!(lenient.entries[1].hash, "later");
+        let Err(GuardianError::SnapshotEntries { problems, .. }) = strict else {
+            panic!("--strict-load refuses duplicates");
+        };
+        assert_eq!(
+            problems,
+            [
+                "entry 1 (d/a.conf): a duplicate of entry 3, which is newer",
+                "entry 4 (d/a.conf): a duplicate of entry 3, which is newer",
+                "entry 2 (d/b.conf): a duplicate of entry 5, which is newer",
+            ]
+        );
+    }
+
+    #[test]
+    fn entries_leading_out_of_their_directory_are_rejected() {
+        let (lenient, strict) = load_entries(
+            "escaping",
+            r#"[{"path": "d/a.conf", "hash": "aa"}, {"path": "d/../../etc/shadow", "hash": "bb"},
+                {"path": "d\\..\\..\\boot.ini", "hash": "cc"}, {"path": "d/..conf", "hash": "dd"}]"#,
+        );
+        assert_eq!(paths(&lenient), ["d/a.conf", "d/..conf"]);
+        let Err(GuardianError::SnapshotEntries { problems, .. }) = strict else {
+            panic!("--strict-load refuses entries leading out of their directory");
+        };
+        assert_eq!(
+            problems,
+            [
+                "entry 2 (d/../../etc/shadow): the path leads out of its directory",
+                "entry 3 (d\\..\\..\\boot.ini): the path leads out of its directory",
+            ]
+        );
+
+        let (sound, strict) = load_entries("sound", r#"[{"path": "d/a.conf", "hash": "aa"}]"#);
+        assert_eq!(paths(&sound), ["d/a.conf"]);
+        assert!(strict.is_ok());
+    }
+}
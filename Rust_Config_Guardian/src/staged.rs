@@ -0,0 +1,126 @@
+//! Staged changes: a directory laid over the one compared, for `compare
+//! --staged`, to tell what drift a deployment would bring before it is
+//! applied. Each file in the staging directory stands for the file at the
+//! same path under the directory compared, replacing it or added to it, and
+//! an empty `NAME.whiteout` stands for NAME's deletion. Only content comes
+//! from the staging directory: a file it replaces keeps the live file's
+//! mode, owner and other metadata, which files staged for a deployment
+//! rarely share.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config;
+use crate::snapshot::{self, FileHash, Snapshot};
+use crate::utils;
+
+/// What an empty file standing for a deletion ends with.
+pub const WHITEOUT: &str = ".whiteout";
+
+/// Lay `staged`, the scan of `staged_dir`, over `current`, the scan of
+/// `dir`.
+pub(crate) fn overlay(current: &mut Snapshot, dir: &str, staged_dir: &str, staged: Snapshot) {
+    let settings = config::settings();
+    let mut entries: BTreeMap<String, FileHash> = current
+        .entries
+        .drain(..)
+        .map(|f| (f.path.clone(), f))
+        .collect();
+    for mut entry in staged.entries {
+        let Ok(relative) = Path::new(&entry.path).strip_prefix(staged_dir) else {
+            continue;
+        };
+        let path = utils::path_text(&Path::new(dir).join(relative));
+        if entry.size == Some(0)
+            && let Some(target) = path.strip_suffix(WHITEOUT)
+        {
+            // A directory's whiteout takes everything under it too.
+            let target = Path::new(target);
+            entries.retain(|path, _| !Path::new(path).starts_with(target));
+            continue;
+        }
+        if settings.is_excluded(Path::new(&path)) || !snapshot::in_scope(dir, Path::new(&path)) {
+            continue;
+        }
+        match entries.get_mut(&path) {
+            Some(live) => {
+                live.hash = entry.hash;
+                live.sampled = entry.sampled;
+                live.size = entry.size;
+                live.modified = entry.modified;
+                live.entry_type = entry.entry_type;
+                live.link_target = entry.link_target;
+                live.link_resolved = entry.link_resolved;
+                live.link_escapes = entry.link_escapes;
+                live.locked = entry.locked;
+            }
+            None => {
+                entry.path = path.clone();
+                entry.path_base64 = None;
+                entries.insert(path, entry);
+            }
+        }
+    }
+    current.entries = entries.into_values().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Permissions;
+
+    #[test]
+    fn staged_files_replace_add_and_white_out_live_ones() {
+        let entry = |path: &str, hash: &str, size: u64| FileHash {
+            path: path.into(),
+            hash: hash.into(),
+            size: Some(size),
+            permissions: Some(Permissions {
+                mode: if path.starts_with("stage") {
+                    0o600
+                } else {
+                    0o644
+                },
+                ..Permissions::default()
+            }),
+            ..FileHash::default()
+        };
+        let mut current = Snapshot {
+            entries: vec![
+                entry("etc/app.conf", "1", 10),
+                entry("etc/conf.d/a.conf", "2", 10),
+                entry("etc/conf.d/b.conf", "3", 10),
+                entry("etc/kept.conf", "4", 10),
+            ],
+            ..Snapshot::default()
+        };
+        let staged = Snapshot {
+            entries: vec![
+                entry("stage/app.conf", "5", 12),
+                entry("stage/conf.d.whiteout", "e", 0),
+                entry("stage/new.conf", "6", 3),
+                entry("stage/kept.conf.whiteout", "7", 2),
+            ],
+            ..Snapshot::default()
+        };
+        overlay(&mut current, "etc", "stage", staged);
+        let entries: Vec<(&str, &str, u32)> = current
+            .entries
+            .iter()
+            .map(|f| {
+                let mode = f.permissions.as_ref().unwrap().mode;
+                (f.path.as_str(), f.hash.as_str(), mode)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("etc/app.conf", "5", 0o644),
+                ("etc/kept.conf", "4", 0o644),
+                // Not empty, it's a file like any other.
+                ("etc/kept.conf.whiteout", "7", 0o600),
+                ("etc/new.conf", "6", 0o600),
+            ]
+        );
+    }
+}
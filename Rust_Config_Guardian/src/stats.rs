@@ -0,0 +1,147 @@
+//! `stats`: a quick health read of the snapshot, the last comparisons and
+//! the drift history, for a dashboard without Prometheus.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::history::{self, HistoryEntry, OutputFormat, Run};
+use crate::{Snapshot, config, utils};
+
+/// How many of the most often drifting paths are listed.
+const TOP: usize = 5;
+
+#[derive(Serialize)]
+struct LastCompare {
+    directory: String,
+    #[serde(flatten)]
+    run: Run,
+}
+
+#[derive(Serialize)]
+struct Drifting {
+    path: String,
+    incidents: usize,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    tracked_files: usize,
+    /// Total size of the files whose size is recorded.
+    tracked_bytes: u64,
+    baseline_created: Option<DateTime<Utc>>,
+    baseline_age_seconds: Option<i64>,
+    last_compare: Option<LastCompare>,
+    drift_last_7_days: usize,
+    drift_last_30_days: usize,
+    most_drifted: Vec<Drifting>,
+}
+
+fn stats(snapshot: &Snapshot, runs: BTreeMap<String, Run>, history: &[HistoryEntry]) -> Stats {
+    let now = Utc::now();
+    let drift: Vec<&HistoryEntry> = history.iter().filter(|e| e.category.is_drift()).collect();
+    let since = |days| {
+        let cutoff = now - Duration::days(days);
+        drift.iter().filter(|e| e.timestamp >= cutoff).count()
+    };
+    let mut by_path: HashMap<&str, usize> = HashMap::new();
+    for entry in &drift {
+        *by_path.entry(&entry.path).or_default() += 1;
+    }
+    let mut most_drifted: Vec<(&str, usize)> = by_path.into_iter().collect();
+    most_drifted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    let created = snapshot.metadata.created;
+    Stats {
+        tracked_files: snapshot.entries.len(),
+        tracked_bytes: snapshot.entries.iter().filter_map(|f| f.size).sum(),
+        baseline_created: created,
+        baseline_age_seconds: created.map(|t| (now - t).num_seconds()),
+        last_compare: runs
+            .into_iter()
+            .max_by_key(|(_, run)| run.finished)
+            .map(|(directory, run)| LastCompare { directory, run }),
+        drift_last_7_days: since(7),
+        drift_last_30_days: since(30),
+        most_drifted: most_drifted
+            .into_iter()
+            .take(TOP)
+            .map(|(path, incidents)| Drifting {
+                path: path.to_string(),
+                incidents,
+            })
+            .collect(),
+    }
+}
+
+/// An age as `3d 4h`, `5h 12m`, `7m` or `42s`.
+fn format_age(seconds: i64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", seconds.max(0))
+    }
+}
+
+fn print_text(stats: &Stats) {
+    println!(
+        "Tracked files:    {} ({})",
+        utils::human_count(stats.tracked_files as u64),
+        utils::human_size(stats.tracked_bytes)
+    );
+    match (stats.baseline_created, stats.baseline_age_seconds) {
+        (Some(created), Some(age)) => println!(
+            "Baseline age:     {} (taken {})",
+            format_age(age),
+            utils::format_time(created)
+        ),
+        _ => println!("Baseline age:     unknown"),
+    }
+    match &stats.last_compare {
+        Some(LastCompare { directory, run }) => println!(
+            "Last compare:     {} ({}, by {}, took {}, {} drifted)",
+            utils::format_time(run.finished),
+            directory,
+            run.source.as_str(),
+            match run.duration_ms {
+                ms @ 0..1000 => format!("{}ms", ms),
+                ms => format!("{:.1}s", ms as f64 / 1000.0),
+            },
+            utils::human_count(run.drifts as u64)
+        ),
+        None => println!("Last compare:     never"),
+    }
+    println!(
+        "Drift incidents:  {} in the last 7 days, {} in the last 30 days",
+        utils::human_count(stats.drift_last_7_days as u64),
+        utils::human_count(stats.drift_last_30_days as u64)
+    );
+    if !stats.most_drifted.is_empty() {
+        println!("Most drifted:");
+        for drifting in &stats.most_drifted {
+            println!(
+                "  {:>5}  {}",
+                utils::human_count(drifting.incidents as u64),
+                drifting.path
+            );
+        }
+    }
+}
+
+/// Print the stats of the saved snapshot and the history kept with it.
+pub fn run(format: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let stats = stats(&snapshot, history::load_runs()?, &history::load()?);
+    match format {
+        OutputFormat::Text => print_text(&stats),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).expect("stats serialize to JSON")
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Logging to a syslog daemon as RFC 5424 messages, over the local socket or
+//! UDP. A record's key-values travel in its structured-data element.
+
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use std::net::UdpSocket;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+use crate::utils::hostname;
+
+const APP_NAME: &str = "config-guardian";
+/// Facility `daemon`.
+const FACILITY: u8 = 3;
+/// SD-ID for our structured data; 32473 is the enterprise number RFC 5612
+/// reserves for documentation and private use.
+const SD_ID: &str = "guardian@32473";
+const DEFAULT_UDP_PORT: u16 = 514;
+
+/// Where syslog messages are sent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyslogAddr {
+    Udp(String),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl SyslogAddr {
+    /// The local socket on Unix, localhost UDP elsewhere.
+    pub fn local() -> Self {
+        #[cfg(unix)]
+        return SyslogAddr::Unix("/dev/log".into());
+        #[cfg(not(unix))]
+        return SyslogAddr::Udp(format!("127.0.0.1:{}", DEFAULT_UDP_PORT));
+    }
+
+    /// Parse `udp://host[:port]` or `unix:///path`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if let Some(host) = value.strip_prefix("udp://") {
+            if host.is_empty() {
+                return Err(format!("missing host in syslog address '{}'", value));
+            }
+            // IPv6 literals need brackets, as in `udp://[::1]:514`.
+            let has_port = match host.strip_prefix('[') {
+                Some(rest) => rest.contains("]:"),
+                None => host.contains(':'),
+            };
+            return Ok(SyslogAddr::Udp(if has_port {
+                host.to_string()
+            } else {
+                format!("{}:{}", host, DEFAULT_UDP_PORT)
+            }));
+        }
+        if let Some(path) = value.strip_prefix("unix://") {
+            #[cfg(unix)]
+            return Ok(SyslogAddr::Unix(path.into()));
+            #[cfg(not(unix))]
+            return Err(format!(
+                "unix syslog sockets are not available on this platform ({})",
+                path
+            ));
+        }
+        Err(format!(
+            "invalid syslog address '{}' (expected udp://host:514 or unix:///dev/log)",
+            value
+        ))
+    }
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+pub struct SyslogLogger {
+    transport: Transport,
+    hostname: String,
+}
+
+impl SyslogLogger {
+    pub fn connect(addr: &SyslogAddr) -> Result<Self, String> {
+        let transport = match addr {
+            SyslogAddr::Udp(target) => {
+                let socket = UdpSocket::bind(if target.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                })
+                .map_err(|e| e.to_string())?;
+                socket
+                    .connect(target)
+                    .map_err(|e| format!("could not reach syslog at {}: {}", target, e))?;
+                Transport::Udp(socket)
+            }
+            #[cfg(unix)]
+            SyslogAddr::Unix(path) => {
+                let socket = UnixDatagram::unbound().map_err(|e| e.to_string())?;
+                socket.connect(path).map_err(|e| {
+                    format!(
+                        "syslog is not available ({}: {}); is rsyslog running? Use --log-target file instead",
+                        path.display(),
+                        e
+                    )
+                })?;
+                Transport::Unix(socket)
+            }
+        };
+        Ok(SyslogLogger {
+            transport,
+            hostname: hostname(),
+        })
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Escape an SD-PARAM value: `"`, `\` and `]` must be backslash-escaped.
+fn escape_param(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// SD-NAMEs are printable ASCII without `=`, space, `]` or `"`, at most 32
+/// characters.
+fn param_name(key: &str) -> String {
+    key.chars()
+        .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+        .take(32)
+        .collect()
+}
+
+/// Collects a record's key-values as SD-PARAMs.
+#[derive(Default)]
+struct Params {
+    params: Vec<String>,
+    event: Option<String>,
+}
+
+impl<'kvs> VisitSource<'kvs> for Params {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = value.to_string();
+        if key.as_str() == "event" {
+            self.event = Some(value.clone());
+        }
+        self.params.push(format!(
+            "{}=\"{}\"",
+            param_name(key.as_str()),
+            escape_param(&value)
+        ));
+        Ok(())
+    }
+}
+
+fn format_message(hostname: &str, record: &Record) -> String {
+    let mut params = Params::default();
+    let _ = record.key_values().visit(&mut params);
+    let structured = if params.params.is_empty() {
+        "-".to_string()
+    } else {
+        format!("[{} {}]", SD_ID, params.params.join(" "))
+    };
+    format!(
+        "<{}>1 {} {} {} {} {} {} {}",
+        FACILITY * 8 + severity(record.level()),
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+        hostname,
+        APP_NAME,
+        std::process::id(),
+        params
+            .event
+            .as_deref()
+            .map(param_name)
+            .as_deref()
+            .unwrap_or("-"),
+        structured,
+        record.args()
+    )
+}
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = format_message(&self.hostname, record);
+        // Nowhere left to report a failure to log.
+        let _ = match &self.transport {
+            Transport::Udp(socket) => socket.send(message.as_bytes()),
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(message.as_bytes()),
+        };
+    }
+
+    fn flush(&self) {}
+}
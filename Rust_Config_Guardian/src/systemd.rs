@@ -0,0 +1,104 @@
+//! Minimal sd_notify(3) client for running monitor as a `Type=notify` unit.
+//!
+//! Everything here is a no-op unless systemd passed us `NOTIFY_SOCKET`.
+
+#[cfg(unix)]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+type NotifySocket = (UnixDatagram, SocketAddr);
+
+pub struct SystemdNotifier {
+    #[cfg(unix)]
+    socket: Option<NotifySocket>,
+    watchdog_interval: Option<Duration>,
+    last_ping: Instant,
+}
+
+impl SystemdNotifier {
+    pub fn from_env() -> Self {
+        SystemdNotifier {
+            #[cfg(unix)]
+            socket: connect_notify_socket(),
+            watchdog_interval: watchdog_interval(),
+            last_ping: Instant::now(),
+        }
+    }
+
+    pub fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}", status.replace('\n', " ")));
+    }
+
+    /// Ping the watchdog if half of `WATCHDOG_USEC` has passed since the last
+    /// ping. Safe to call on every loop iteration.
+    pub fn watchdog_tick(&mut self) {
+        if let Some(interval) = self.watchdog_interval
+            && self.last_ping.elapsed() >= interval / 2
+        {
+            self.send("WATCHDOG=1");
+            self.last_ping = Instant::now();
+        }
+    }
+
+    #[cfg(unix)]
+    fn send(&self, message: &str) {
+        if let Some((socket, addr)) = &self.socket
+            && let Err(e) = socket.send_to_addr(message.as_bytes(), addr)
+        {
+            log::warn!("Could not notify systemd ({}): {}", message, e);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _message: &str) {}
+}
+
+#[cfg(unix)]
+fn connect_notify_socket() -> Option<NotifySocket> {
+    let path = std::env::var_os("NOTIFY_SOCKET")?;
+    let path = path.to_string_lossy();
+    let addr = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes())
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return None,
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let addr = match addr {
+        Ok(addr) => addr,
+        Err(e) => {
+            log::warn!("Ignoring invalid NOTIFY_SOCKET {}: {}", path, e);
+            return None;
+        }
+    };
+    match UnixDatagram::unbound() {
+        Ok(socket) => Some((socket, addr)),
+        Err(e) => {
+            log::warn!("Could not create systemd notify socket: {}", e);
+            None
+        }
+    }
+}
+
+fn watchdog_interval() -> Option<Duration> {
+    // WATCHDOG_PID, when present, says which process the watchdog is meant for.
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+        && pid.parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
@@ -0,0 +1,126 @@
+//! Microsoft Teams incoming-webhook alert channel, sent as a MessageCard.
+
+use serde_json::{Value, json};
+use std::time::Duration;
+
+use crate::alert::{Alert, Listing};
+use crate::http;
+use crate::identity;
+
+#[derive(Clone)]
+pub struct TeamsConfig {
+    pub url: String,
+    pub timeout: Duration,
+}
+
+pub fn payload(alert: &Alert, host: &str, listing: &Listing) -> Value {
+    let severity = alert.severity();
+    let title = format!(
+        "Config drift on {} ({}, score {})",
+        host,
+        severity,
+        alert.score()
+    );
+
+    let (listed, omitted) = alert.listed(listing);
+    let facts: Vec<Value> = listed
+        .iter()
+        .map(|d| json!({ "name": d.kind.to_string(), "value": d.path }))
+        .collect();
+    let mut text = format!("{} in {}", alert.summary, alert.dir);
+    if omitted > 0 {
+        text.push_str(&format!("\n\n{}", listing.trailer(omitted, false)));
+    }
+
+    json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "summary": title,
+        "themeColor": severity.color().trim_start_matches('#'),
+        "title": title,
+        "sections": [{ "text": text, "facts": facts }],
+    })
+}
+
+pub fn send(config: &TeamsConfig, alert: &Alert, listing: &Listing) -> Result<(), String> {
+    http::post_json(
+        &config.url,
+        &[],
+        config.timeout,
+        &payload(alert, &identity::label(), listing),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Drift, DriftKind};
+
+    fn drift(kind: DriftKind, path: &str) -> Drift {
+        Drift {
+            kind,
+            path: path.to_string(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn payload_is_a_message_card() {
+        let drifts = vec![
+            drift(DriftKind::Deleted, "etc/sshd_config"),
+            drift(DriftKind::New, "etc/extra.conf"),
+        ];
+        let expected = json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": "Config drift on web1 (critical, score 51)",
+            "themeColor": "e01e5a",
+            "title": "Config drift on web1 (critical, score 51)",
+            "sections": [{
+                "text": "2 drifted files in etc",
+                "facts": [
+                    { "name": "Deleted", "value": "etc/sshd_config" },
+                    { "name": "New", "value": "etc/extra.conf" },
+                ],
+            }],
+        });
+        assert_eq!(
+            payload(&Alert::drift("etc", &drifts), "web1", &Listing::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn payload_limits_facts() {
+        let drifts: Vec<Drift> = (0..15)
+            .map(|i| drift(DriftKind::New, &format!("f{}", i)))
+            .collect();
+        let listing = Listing {
+            max_entries: 10,
+            report: None,
+        };
+        let value = payload(&Alert::drift("d", &drifts), "web1", &listing);
+
+        assert_eq!(value["themeColor"], "2eb67d");
+        let section = &value["sections"][0];
+        assert_eq!(section["facts"].as_array().unwrap().len(), 10);
+        assert_eq!(section["text"], "15 drifted files in d\n\n…and 5 more");
+    }
+
+    #[test]
+    fn cooldown_summary_has_no_facts() {
+        let alert = Alert {
+            dir: "d".to_string(),
+            summary: "3 further changes to 2 files suppressed during cooldown".to_string(),
+            drifts: Vec::new(),
+            tags: Default::default(),
+            baseline_age_days: None,
+            annotations: Default::default(),
+            severity: None,
+            report: None,
+        };
+        let value = payload(&alert, "web1", &Listing::default());
+        assert_eq!(value["themeColor"], "2eb67d");
+        assert!(value["sections"][0]["facts"].as_array().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! Telegram Bot API alert channel.
+
+use serde_json::json;
+use std::time::Duration;
+
+use crate::alert::{Alert, Listing};
+use crate::http;
+use crate::identity;
+
+/// Telegram rejects messages longer than this many characters.
+const MAX_MESSAGE_LEN: usize = 4096;
+pub(crate) const API_ORIGIN: &str = "https://api.telegram.org";
+
+#[derive(Clone)]
+pub struct TelegramConfig {
+    pub token: String,
+    pub chat_id: String,
+    pub timeout: Duration,
+}
+
+/// Escape text for MarkdownV2, where every one of these characters is
+/// markup — paths like `nginx_site.conf` break otherwise.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Join lines into messages that fit the length limit, breaking between
+/// lines and, for an overlong line, never right after an escaping backslash.
+fn split_messages(lines: &[String]) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        let mut line: &str = line;
+        loop {
+            let needed = line.chars().count() + usize::from(!current.is_empty());
+            if current.chars().count() + needed <= MAX_MESSAGE_LEN {
+                if !current.is_empty() {
+                    current.push('\n');
+                }
+                current.push_str(line);
+                break;
+            }
+            if !current.is_empty() {
+                messages.push(std::mem::take(&mut current));
+                continue;
+            }
+            let mut cut = line
+                .char_indices()
+                .nth(MAX_MESSAGE_LEN)
+                .map_or(line.len(), |(i, _)| i);
+            let backslashes = line[..cut].chars().rev().take_while(|&c| c == '\\').count();
+            if backslashes % 2 == 1 {
+                cut -= 1;
+            }
+            messages.push(line[..cut].to_string());
+            line = &line[cut..];
+        }
+    }
+    if !current.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+fn messages(alert: &Alert, host: &str, listing: &Listing) -> Vec<String> {
+    let mut lines = vec![format!(
+        "*{}* on {} \\({}\\)",
+        escape("Config drift"),
+        escape(host),
+        escape(&format!("{}, score {}", alert.severity(), alert.score()))
+    )];
+    lines.push(escape(&format!("{} in {}", alert.summary, alert.dir)));
+    let (listed, omitted) = alert.listed(listing);
+    for drift in listed {
+        lines.push(format!(
+            "{}: `{}`",
+            escape(&drift.kind.to_string()),
+            drift.path.replace('\\', "\\\\").replace('`', "\\`")
+        ));
+    }
+    if omitted > 0 {
+        lines.push(escape(&listing.trailer(omitted, false)));
+    }
+    split_messages(&lines)
+}
+
+pub fn send(config: &TelegramConfig, alert: &Alert, listing: &Listing) -> Result<(), String> {
+    let url = format!("{}/bot{}/sendMessage", API_ORIGIN, config.token);
+    for text in messages(alert, &identity::label(), listing) {
+        let body = json!({
+            "chat_id": config.chat_id,
+            "text": text,
+            "parse_mode": "MarkdownV2",
+        });
+        http::post_json(&url, &[], config.timeout, &body)?;
+    }
+    Ok(())
+}
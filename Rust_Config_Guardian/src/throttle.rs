@@ -0,0 +1,189 @@
+//! Throttled scans, for a latency-sensitive host whose service they would
+//! otherwise compete with for disk and CPU: `--io-throttle` caps the
+//! bandwidth a scan's reads take together, `--cpu-limit` the share of a
+//! CPU they spend reading and hashing, and `--nice` sets both, reads one
+//! file at a time and lowers the process's priority. Reads are paced
+//! between files, by booking time on a timeline all of a scan's threads
+//! share, so that however many threads read, their reads add up to the
+//! limits: before a read, the time its bytes take at the bandwidth allowed,
+//! which it waits its turn for, and after it, the time it took at the share
+//! of a CPU allowed, which the thread waits out before its next read.
+
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{config, utils};
+
+/// Files `--nice` reads at once, unless `--jobs` says otherwise.
+pub const NICE_JOBS: usize = 1;
+/// The bandwidth, in MiB/s, `--nice` caps reads at.
+pub const NICE_IO_THROTTLE: f64 = 20.0;
+/// The share of a CPU `--nice` lets reads take.
+pub const NICE_CPU_LIMIT: f64 = 50.0;
+/// The niceness `--nice` gives the process, on Unix.
+pub const NICENESS: i32 = 10;
+
+/// The limits scans run under.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Throttle {
+    /// Bytes per second all reads together may take.
+    pub io: Option<u64>,
+    /// The percentage of a CPU reads may take.
+    pub cpu: Option<f64>,
+    /// Whether the process's priority is lowered.
+    pub nice: bool,
+}
+
+impl Throttle {
+    pub fn is_none(&self) -> bool {
+        *self == Throttle::default()
+    }
+}
+
+impl fmt::Display for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut limits = Vec::new();
+        if let Some(io) = self.io {
+            limits.push(format!("reads of at most {}/s", utils::format_size(io)));
+        }
+        if let Some(cpu) = self.cpu {
+            limits.push(format!("{}% of a CPU", cpu));
+        }
+        if self.nice {
+            limits.push("lowered priority".to_string());
+        }
+        match limits.is_empty() {
+            true => f.write_str("none"),
+            false => f.write_str(&limits.join(", ")),
+        }
+    }
+}
+
+static IO_BOOKED: Mutex<Option<Instant>> = Mutex::new(None);
+static CPU_BOOKED: Mutex<Option<Instant>> = Mutex::new(None);
+
+fn lock(booked: &'static Mutex<Option<Instant>>) -> MutexGuard<'static, Option<Instant>> {
+    booked.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Book `cost` from `from`, or after what is booked already, giving when
+/// the booking starts and ends.
+fn book(
+    booked: &'static Mutex<Option<Instant>>,
+    from: Instant,
+    cost: Duration,
+) -> (Instant, Instant) {
+    let mut booked = lock(booked);
+    let start = booked.map_or(from, |end| end.max(from));
+    *booked = Some(start + cost);
+    (start, start + cost)
+}
+
+/// How long a read of `bytes` waits for its turn at `rate` bytes per
+/// second, booked `now`.
+fn io_wait(rate: u64, bytes: u64, now: Instant) -> Duration {
+    let cost = Duration::from_secs_f64(bytes as f64 / rate.max(1) as f64);
+    book(&IO_BOOKED, now, cost).0 - now
+}
+
+/// How long a read that took `took`, until `now`, waits before the next at
+/// `share` percent of a CPU.
+fn cpu_wait(share: f64, took: Duration, now: Instant) -> Duration {
+    let started = now.checked_sub(took).unwrap_or(now);
+    let cost = took.mul_f64(100.0 / share);
+    book(&CPU_BOOKED, started, cost)
+        .1
+        .saturating_duration_since(now)
+}
+
+fn sleep(wait: Duration) {
+    if !wait.is_zero() {
+        std::thread::sleep(wait);
+    }
+}
+
+/// Wait for the turn of a read of `bytes` at the settings' bandwidth.
+pub(crate) fn before_read(bytes: u64) {
+    if let Some(rate) = config::settings().throttle.io {
+        sleep(io_wait(rate, bytes, Instant::now()));
+    }
+}
+
+/// Wait out a read that took `took` at the settings' share of a CPU.
+pub(crate) fn after_read(took: Duration) {
+    if let Some(share) = config::settings().throttle.cpu {
+        sleep(cpu_wait(share, took, Instant::now()));
+    }
+}
+
+/// Lower the process's priority for `--nice`: its niceness to
+/// [`NICENESS`], unless it is nicer already, and on Linux its I/O to the
+/// lowest best-effort priority. Threads started after inherit both, so
+/// this is done before the scans start theirs.
+#[cfg(unix)]
+pub fn lower_priority() -> std::io::Result<()> {
+    // SAFETY: getpriority and setpriority take no pointers. A failed
+    // getpriority gives -1, and the niceness is set anyway.
+    let set = unsafe {
+        libc::getpriority(libc::PRIO_PROCESS, 0) >= NICENESS
+            || libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS) == 0
+    };
+    if !set {
+        return Err(std::io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_BE: libc::c_long = 2;
+        const IOPRIO_LOWEST: libc::c_long = 7;
+        // SAFETY: ioprio_set takes no pointers.
+        let set = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_BE << 13 | IOPRIO_LOWEST,
+            )
+        };
+        if set != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Priorities are left as they are on Windows; the limits still hold.
+#[cfg(not(unix))]
+pub fn lower_priority() -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_wait_their_turn_on_the_time_they_book() {
+        let now = Instant::now();
+        // 5 MiB at 10 MiB/s books half a second, which the next read waits
+        // for, and the one after it the second's half second too.
+        assert_eq!(io_wait(10 << 20, 5 << 20, now), Duration::ZERO);
+        assert_eq!(io_wait(10 << 20, 5 << 20, now), Duration::from_millis(500));
+        assert_eq!(io_wait(10 << 20, 0, now), Duration::from_secs(1));
+
+        // A quarter of a CPU: a second's read waits three more, and a
+        // second read, done at the same time, four more after that.
+        let took = Duration::from_secs(1);
+        assert_eq!(cpu_wait(25.0, took, now), Duration::from_secs(3));
+        assert_eq!(cpu_wait(25.0, took, now), Duration::from_secs(7));
+
+        let io = Throttle {
+            io: Some(10 << 20),
+            nice: true,
+            ..Throttle::default()
+        };
+        assert_eq!(io.to_string(), "reads of at most 10.0M/s, lowered priority");
+        assert_eq!(Throttle::default().to_string(), "none");
+    }
+}
@@ -1,5 +1,925 @@
-use std::path::Path;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 
 pub fn is_valid_directory(path: &str) -> bool {
-    Path::new(path).is_dir()
+    long_path(Path::new(path)).is_dir()
+}
+
+/// `path` in the form file operations should use. On Windows that is an
+/// extended-length `\\?\` path, which isn't limited to 260 characters and
+/// reaches files named like devices (`CON`, `aux.conf`) or ending in a dot
+/// or space. Elsewhere `path` is returned as is.
+///
+/// Paths shown and stored keep their original form; only the operation
+/// uses this one.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::path::{Component, PathBuf, Prefix};
+    let joined;
+    let absolute = if path.is_absolute() {
+        path
+    } else {
+        match std::env::current_dir() {
+            Ok(cwd) => {
+                joined = cwd.join(path);
+                joined.as_path()
+            }
+            Err(_) => return Cow::Borrowed(path),
+        }
+    };
+    // Extended paths aren't normalized by Windows, so `.`, `..` and `/` are
+    // resolved here, without the name mangling GetFullPathNameW would do.
+    let mut long = String::new();
+    let mut parts = Vec::new();
+    for component in absolute.components() {
+        match component {
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(drive) => long = format!(r"\\?\{}:\", drive as char),
+                Prefix::UNC(server, share) => {
+                    long = format!(
+                        r"\\?\UNC\{}\{}\",
+                        server.to_string_lossy(),
+                        share.to_string_lossy()
+                    )
+                }
+                // Already extended, or a device path.
+                _ => return Cow::Borrowed(path),
+            },
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::Normal(part) => parts.push(part),
+        }
+    }
+    if long.is_empty() {
+        return Cow::Borrowed(path);
+    }
+    let mut long = PathBuf::from(long);
+    for part in parts {
+        long.push(part);
+    }
+    Cow::Owned(long)
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// `path` as text, as recorded in the snapshot: unchanged when it's valid
+/// Unicode, and otherwise with each byte that isn't (each unpaired surrogate
+/// on Windows) spelled out as `\xNN` (`\u{NNNN}`). Unlike
+/// `to_string_lossy`, names that differ only in such bytes stay apart.
+pub fn path_text(path: &Path) -> String {
+    if let Some(text) = path.to_str() {
+        return text.to_string();
+    }
+    escape_invalid(path)
+}
+
+#[cfg(unix)]
+fn escape_invalid(path: &Path) -> String {
+    use std::fmt::Write;
+    use std::os::unix::ffi::OsStrExt;
+    let mut text = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            let _ = write!(text, "\\x{:02X}", byte);
+        }
+    }
+    text
+}
+
+#[cfg(windows)]
+fn escape_invalid(path: &Path) -> String {
+    use std::os::windows::ffi::OsStrExt;
+    char::decode_utf16(path.as_os_str().encode_wide())
+        .map(|c| match c {
+            Ok(c) => c.to_string(),
+            Err(e) => format!("\\u{{{:04X}}}", e.unpaired_surrogate()),
+        })
+        .collect()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn escape_invalid(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// The raw form of a path that isn't valid Unicode, base64-encoded so it
+/// can be stored alongside its [`path_text`]: the bytes on Unix, the
+/// UTF-16 code units (little-endian) on Windows. `None` for valid paths,
+/// whose text is the path.
+pub fn raw_path(path: &Path) -> Option<String> {
+    if path.to_str().is_some() {
+        return None;
+    }
+    raw_bytes(path).map(|bytes| BASE64.encode(bytes))
+}
+
+/// The path [`raw_path`] encoded, or `None` if `raw` isn't one.
+pub fn path_from_raw(raw: &str) -> Option<PathBuf> {
+    let bytes = BASE64.decode(raw).ok()?;
+    from_raw_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn raw_bytes(path: &Path) -> Option<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(unix)]
+fn from_raw_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(std::ffi::OsString::from_vec(bytes).into())
+}
+
+#[cfg(windows)]
+fn raw_bytes(path: &Path) -> Option<Vec<u8>> {
+    use std::os::windows::ffi::OsStrExt;
+    Some(
+        path.as_os_str()
+            .encode_wide()
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+    )
+}
+
+#[cfg(windows)]
+fn from_raw_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::windows::ffi::OsStringExt;
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let wide: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    Some(std::ffi::OsString::from_wide(&wide).into())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn raw_bytes(_path: &Path) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(not(any(unix, windows)))]
+fn from_raw_bytes(_bytes: Vec<u8>) -> Option<PathBuf> {
+    None
+}
+
+/// Identifies the directory currently at `path`, so a directory that was
+/// removed and recreated (or renamed into place) can be told apart from the
+/// one originally watched. Returns `None` when no directory exists there.
+#[cfg(unix)]
+pub fn directory_identity(path: &str) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = std::fs::metadata(path).ok()?;
+    meta.is_dir().then(|| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn directory_identity(path: &str) -> Option<(u64, u64)> {
+    is_valid_directory(path).then_some((0, 0))
+}
+
+/// The mount `path` is on: its mount ID, or its device number where the
+/// kernel predates `statx` reporting mount IDs. Follows symlinks. Only
+/// available on Linux.
+#[cfg(target_os = "linux")]
+pub fn mount_of(path: &std::path::Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    #[cfg(any(target_env = "gnu", target_env = "musl"))]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        // SAFETY: statx only writes into `buf`, which is sized for it.
+        let mut buf: libc::statx = unsafe { std::mem::zeroed() };
+        let rc = unsafe {
+            libc::statx(
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                0,
+                libc::STATX_MNT_ID,
+                &mut buf,
+            )
+        };
+        if rc == 0 && buf.stx_mask & libc::STATX_MNT_ID != 0 {
+            return Some(buf.stx_mnt_id);
+        }
+    }
+    std::fs::metadata(path).ok().map(|meta| meta.dev())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn mount_of(_path: &std::path::Path) -> Option<u64> {
+    None
+}
+
+/// The immutable (`i`) and append-only (`a`) attributes of the file at
+/// `path`, as `lsattr` shows them. `None` where the filesystem has no such
+/// attributes, which isn't worth a warning. Only available on Linux.
+#[cfg(target_os = "linux")]
+pub fn attributes_of(path: &Path) -> Option<String> {
+    use std::os::fd::AsRawFd;
+    // From linux/fs.h; libc doesn't carry the flag values.
+    const FS_IMMUTABLE_FL: libc::c_long = 0x10;
+    const FS_APPEND_FL: libc::c_long = 0x20;
+    let file = std::fs::File::open(path).ok()?;
+    let mut flags: libc::c_long = 0;
+    // SAFETY: FS_IOC_GETFLAGS writes at most a long into `flags`.
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return None;
+    }
+    let mut letters = String::new();
+    if flags & FS_IMMUTABLE_FL != 0 {
+        letters.push('i');
+    }
+    if flags & FS_APPEND_FL != 0 {
+        letters.push('a');
+    }
+    Some(letters)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn attributes_of(_path: &Path) -> Option<String> {
+    None
+}
+
+/// The access ACL of the file at `path` in `getfacl`'s short form with
+/// numeric IDs, e.g. `user::rw-,user:1000:r--,group::r--,mask::r--,other::---`.
+/// `None` when the file has only the ACL its mode bits imply, or the
+/// filesystem has no ACLs. Only available on Linux.
+#[cfg(target_os = "linux")]
+pub fn acl_of(path: &Path) -> Option<String> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let name = c"system.posix_acl_access";
+    // SAFETY: a null buffer of size 0 only asks for the value's size.
+    let size = unsafe { libc::getxattr(c_path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size <= 0 {
+        return None;
+    }
+    let mut value = vec![0u8; size as usize];
+    // SAFETY: getxattr writes at most `value.len()` bytes into `value`.
+    let read = unsafe {
+        libc::getxattr(
+            c_path.as_ptr(),
+            name.as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+    if read <= 0 {
+        return None;
+    }
+    value.truncate(read as usize);
+    acl_text(&value)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn acl_of(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Decode the kernel's `posix_acl_xattr` format: a version header, then one
+/// tag, permission and ID per entry, all little-endian and already sorted.
+#[cfg(any(target_os = "linux", test))]
+fn acl_text(value: &[u8]) -> Option<String> {
+    // From linux/posix_acl_xattr.h and linux/posix_acl.h.
+    const VERSION: u32 = 2;
+    const USER_OBJ: u16 = 0x01;
+    const USER: u16 = 0x02;
+    const GROUP_OBJ: u16 = 0x04;
+    const GROUP: u16 = 0x08;
+    const MASK: u16 = 0x10;
+    const OTHER: u16 = 0x20;
+    let (header, entries) = value.split_at_checked(4)?;
+    if u32::from_le_bytes(header.try_into().ok()?) != VERSION || entries.len() % 8 != 0 {
+        return None;
+    }
+    let mut extended = false;
+    let mut text = Vec::new();
+    for entry in entries.chunks_exact(8) {
+        let tag = u16::from_le_bytes([entry[0], entry[1]]);
+        let perm = u16::from_le_bytes([entry[2], entry[3]]);
+        let id = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+        let qualifier = match tag {
+            USER_OBJ => "user::".to_string(),
+            USER => format!("user:{}:", id),
+            GROUP_OBJ => "group::".to_string(),
+            GROUP => format!("group:{}:", id),
+            MASK => "mask::".to_string(),
+            OTHER => "other::".to_string(),
+            _ => return None,
+        };
+        extended |= matches!(tag, USER | GROUP | MASK);
+        let bit = |mask: u16, letter: char| if perm & mask != 0 { letter } else { '-' };
+        text.push(format!(
+            "{}{}{}{}",
+            qualifier,
+            bit(4, 'r'),
+            bit(2, 'w'),
+            bit(1, 'x')
+        ));
+    }
+    extended.then(|| text.join(","))
+}
+
+/// Open the file at `path` to read. On Windows it is opened sharing read,
+/// write and delete access, so a file another process has open for writing
+/// can still be read, and with backup semantics, so an administrator with
+/// the backup privilege can read files their ACLs would keep from them.
+#[cfg(windows)]
+pub fn open_shared(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_FLAG_BACKUP_SEMANTICS, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE,
+    };
+    std::fs::OpenOptions::new()
+        .read(true)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+}
+
+#[cfg(not(windows))]
+pub fn open_shared(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::File::open(path)
+}
+
+/// Read the whole file at `path`, opened with [`open_shared`].
+pub fn read_shared(path: &Path) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+    let mut content = Vec::new();
+    open_shared(path)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// What a sampled hash of the file at `path` covers: its first and last
+/// `span` bytes (all of it, if it is no longer than twice that), followed
+/// by its length as 8 little-endian bytes.
+pub fn read_sampled(path: &Path, span: u64) -> std::io::Result<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = open_shared(path)?;
+    let len = file.metadata()?.len();
+    let mut content = Vec::new();
+    if len <= span * 2 {
+        file.read_to_end(&mut content)?;
+    } else {
+        (&mut file).take(span).read_to_end(&mut content)?;
+        file.seek(SeekFrom::Start(len - span))?;
+        file.take(span).read_to_end(&mut content)?;
+    }
+    content.extend_from_slice(&len.to_le_bytes());
+    Ok(content)
+}
+
+/// The most [`read_virtual`] takes from a file.
+pub const VIRTUAL_READ_LIMIT: usize = 64 * 1024;
+
+/// The content of the virtual file at `path`, such as a kernel tunable
+/// under `/proc/sys`: what one read of at most [`VIRTUAL_READ_LIMIT`] bytes
+/// gives. Such files report a size of 0 and are generated on the read, so
+/// their size says nothing of what they hold. The file is opened without
+/// blocking, so that a FIFO in its place can't hang the scan.
+pub fn read_virtual(path: &Path) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NONBLOCK);
+    }
+    let mut content = vec![0; VIRTUAL_READ_LIMIT];
+    let read = options.open(path)?.read(&mut content)?;
+    content.truncate(read);
+    Ok(content)
+}
+
+/// Whether `error` means another process has the file open exclusively or
+/// locked: a sharing or lock violation. Only Windows has these.
+#[cfg(windows)]
+pub fn is_locked(error: &std::io::Error) -> bool {
+    use windows_sys::Win32::Foundation::{ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+    error.raw_os_error().is_some_and(|code| {
+        [ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION].contains(&(code as u32))
+    })
+}
+
+#[cfg(not(windows))]
+pub fn is_locked(_error: &std::io::Error) -> bool {
+    false
+}
+
+/// OS errors by the names `retry_on` knows them by: the ones a network
+/// filesystem gives for a passing hiccup, which scans retry by default.
+#[cfg(unix)]
+pub const TRANSIENT_ERRORS: &[(&str, i32)] = &[
+    ("EIO", libc::EIO),
+    ("ESTALE", libc::ESTALE),
+    ("EAGAIN", libc::EAGAIN),
+    ("EINTR", libc::EINTR),
+    ("EBUSY", libc::EBUSY),
+    ("ETIMEDOUT", libc::ETIMEDOUT),
+];
+
+#[cfg(windows)]
+pub const TRANSIENT_ERRORS: &[(&str, i32)] = {
+    use windows_sys::Win32::Foundation::*;
+    &[
+        ("ERROR_SHARING_VIOLATION", ERROR_SHARING_VIOLATION as i32),
+        ("ERROR_LOCK_VIOLATION", ERROR_LOCK_VIOLATION as i32),
+        ("ERROR_UNEXP_NET_ERR", ERROR_UNEXP_NET_ERR as i32),
+        ("ERROR_NETNAME_DELETED", ERROR_NETNAME_DELETED as i32),
+        ("ERROR_SEM_TIMEOUT", ERROR_SEM_TIMEOUT as i32),
+    ]
+};
+
+#[cfg(not(any(unix, windows)))]
+pub const TRANSIENT_ERRORS: &[(&str, i32)] = &[];
+
+/// The OS error code `name` stands for: one of [`TRANSIENT_ERRORS`],
+/// ignoring case, or the code itself.
+pub fn os_error_code(name: &str) -> Result<i32, String> {
+    let name = name.trim();
+    TRANSIENT_ERRORS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code)
+        .or_else(|| name.parse().ok())
+        .ok_or_else(|| {
+            let known: Vec<&str> = TRANSIENT_ERRORS.iter().map(|(known, _)| *known).collect();
+            format!(
+                "unknown error '{}' (expected an error number or one of {})",
+                name,
+                known.join(", ")
+            )
+        })
+}
+
+/// What [`parse_duration`] accepts, for its errors.
+const DURATION_FORMS: &str =
+    "a number and a unit, ms, s, m, h, d or w, as in 500ms, 30s, 1.5h or 2w";
+
+/// What [`parse_size`] accepts, for its errors.
+const SIZE_FORMS: &str =
+    "a number of bytes, or a number and a unit, K, M, G or T, as in 512K, 10M or 1.5G";
+
+/// Split `value` into its number, digits with at most one decimal point,
+/// and what follows.
+fn split_number(value: &str) -> (&str, &str) {
+    let mut point = false;
+    let end = value
+        .char_indices()
+        .find(|&(_, c)| match c {
+            '.' if !point => {
+                point = true;
+                false
+            }
+            c => !c.is_ascii_digit(),
+        })
+        .map_or(value.len(), |(i, _)| i);
+    let (number, unit) = value.split_at(end);
+    (number, unit.trim_start())
+}
+
+/// `number` times `unit`, exactly for a whole number and as near as a
+/// float gets for a fraction.
+fn scale(number: &str, unit: u64) -> Option<u128> {
+    if !number.contains('.') {
+        return number
+            .parse::<u128>()
+            .ok()
+            .and_then(|n| n.checked_mul(unit as u128));
+    }
+    let n: f64 = number.parse().ok()?;
+    let scaled = (n * unit as f64).round();
+    (scaled.is_finite() && scaled < u128::MAX as f64).then_some(scaled as u128)
+}
+
+/// Parse a duration such as `500ms`, `30s`, `15m`, `1.5h`, `1d` or `2w`,
+/// as every flag taking one does. A bare number is refused: `--retry-delay
+/// 100` could mean milliseconds as well as seconds.
+pub fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    let invalid = |why: String| {
+        format!(
+            "invalid duration '{}': {} (expected {})",
+            value, why, DURATION_FORMS
+        )
+    };
+    let (number, unit) = split_number(value);
+    if number.is_empty() || number == "." {
+        return Err(invalid("it doesn't start with a number".into()));
+    }
+    // In milliseconds, so that 1.5s and 0.5m are exact.
+    let millis: u64 = match unit {
+        "" => {
+            return Err(invalid(format!(
+                "it has no unit; for seconds, write {}s",
+                number
+            )));
+        }
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        "w" => 604_800_000,
+        _ => return Err(invalid(format!("'{}' is not a unit", unit))),
+    };
+    scale(number, millis)
+        .and_then(|millis| u64::try_from(millis).ok())
+        .map(std::time::Duration::from_millis)
+        .ok_or_else(|| invalid("it is too long".into()))
+}
+
+/// [`parse_duration`], also taking a bare number as seconds, as config
+/// files and `GUARDIAN_*` variables have always had them.
+pub fn parse_duration_or_secs(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Ok(std::time::Duration::from_secs(secs)),
+        Err(_) => parse_duration(value),
+    }
+}
+
+/// [`parse_duration`] for how often something repeats, which must be
+/// longer than zero.
+pub fn parse_interval(value: &str) -> Result<std::time::Duration, String> {
+    positive(value, parse_duration(value)?)
+}
+
+fn positive(value: &str, interval: std::time::Duration) -> Result<std::time::Duration, String> {
+    if interval.is_zero() {
+        return Err(format!(
+            "invalid interval '{}': it must be longer than zero",
+            value.trim()
+        ));
+    }
+    Ok(interval)
+}
+
+/// `duration` as [`parse_duration`] reads it, in the largest unit that's
+/// exact, e.g. `15m`.
+pub fn format_duration(duration: std::time::Duration) -> String {
+    if duration.subsec_millis() != 0 {
+        return format!("{}ms", duration.as_millis());
+    }
+    let secs = duration.as_secs();
+    let (number, unit) = [(86400, "d"), (3600, "h"), (60, "m")]
+        .into_iter()
+        .find(|(size, _)| secs != 0 && secs.is_multiple_of(*size))
+        .map_or((secs, "s"), |(size, unit)| (secs / size, unit));
+    format!("{}{}", number, unit)
+}
+
+/// Parse a byte size such as `512K`, `10M`, `1.5G` or `2T` (binary
+/// multiples, with or without a trailing `B` or `iB`). A bare number is
+/// bytes, and must be whole.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let invalid = |why: String| {
+        format!(
+            "invalid size '{}': {} (expected {})",
+            value, why, SIZE_FORMS
+        )
+    };
+    let (number, unit) = split_number(value);
+    if number.is_empty() || number == "." {
+        return Err(invalid("it doesn't start with a number".into()));
+    }
+    let upper = unit.to_ascii_uppercase();
+    let multiplier: u64 = match upper
+        .strip_suffix("IB")
+        .or(upper.strip_suffix('B'))
+        .unwrap_or(&upper)
+    {
+        "" if number.contains('.') => {
+            return Err(invalid("a number of bytes must be whole".into()));
+        }
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(invalid(format!("'{}' is not a unit", unit))),
+    };
+    scale(number, multiplier)
+        .and_then(|bytes| u64::try_from(bytes).ok())
+        .ok_or_else(|| invalid("it is too large".into()))
+}
+
+/// Parse a percentage such as `1%`, `0.5%` or `100`, between 0 and 100.
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    value
+        .strip_suffix('%')
+        .unwrap_or(value)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|percent| (0.0..=100.0).contains(percent))
+        .ok_or_else(|| format!("invalid percentage '{}' (expected e.g. 1% or 0.5%)", value))
+}
+
+/// A byte size as `parse_size` would read it, to one decimal place:
+/// `512B`, `1.5K`, `10.0M`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+    match UNITS.iter().find(|(size, _)| bytes >= *size) {
+        Some((size, unit)) => format!("{:.1}{}", bytes as f64 / *size as f64, unit),
+        None => format!("{}B", bytes),
+    }
+}
+
+/// A count as text output shows it, its digits grouped as `--locale` says:
+/// `4182`, `4,182` or `4.182`.
+pub fn human_count(n: u64) -> String {
+    count_in(n, crate::config::settings().locale.as_ref())
+}
+
+/// A byte size as text output shows it, to one decimal place in the units
+/// `--size-units` picks, with `--locale`'s decimal mark: `512 B`,
+/// `1.5 KiB`, `10,0 MB`.
+pub fn human_size(bytes: u64) -> String {
+    let settings = crate::config::settings();
+    size_in(bytes, settings.size_units, settings.locale.as_ref())
+}
+
+fn count_in(n: u64, locale: Option<&crate::config::Locale>) -> String {
+    let digits = n.to_string();
+    let Some(group) = locale.and_then(|l| l.group) else {
+        return digits;
+    };
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(group);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+fn size_in(
+    bytes: u64,
+    units: crate::config::SizeUnits,
+    locale: Option<&crate::config::Locale>,
+) -> String {
+    let (base, names): (u64, [&str; 4]) = match units {
+        crate::config::SizeUnits::Binary => (1024, ["KiB", "MiB", "GiB", "TiB"]),
+        crate::config::SizeUnits::Si => (1000, ["kB", "MB", "GB", "TB"]),
+    };
+    let mut scale = 1;
+    let mut unit = None;
+    for name in names {
+        if bytes < scale * base {
+            break;
+        }
+        scale *= base;
+        unit = Some(name);
+    }
+    let Some(unit) = unit else {
+        return format!("{} B", count_in(bytes, locale));
+    };
+    let value = format!("{:.1}", bytes as f64 / scale as f64);
+    let decimal = locale.map_or('.', |l| l.decimal);
+    format!("{} {}", value.replace('.', &decimal.to_string()), unit)
+}
+
+/// A time as human-readable output shows it: in the local time zone, or in
+/// UTC with `--utc`. Times are stored, logged and served as UTC RFC 3339.
+pub fn format_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+    if crate::config::settings().utc {
+        time.format(FORMAT).to_string()
+    } else {
+        time.with_timezone(&chrono::Local)
+            .format(FORMAT)
+            .to_string()
+    }
+}
+
+/// A random value for jitter and similar non-cryptographic uses.
+pub fn random_u64() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+/// Name of this machine, for alert payloads.
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if rc == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+    }
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut value = 2u32.to_le_bytes().to_vec();
+        for (tag, perm, id) in entries {
+            value.extend(tag.to_le_bytes());
+            value.extend(perm.to_le_bytes());
+            value.extend(id.to_le_bytes());
+        }
+        value
+    }
+
+    #[test]
+    fn durations_need_a_unit_and_read_back_as_formatted() {
+        use std::time::Duration;
+        let units = [
+            ("ms", 1),
+            ("s", 1000),
+            ("m", 60_000),
+            ("h", 3_600_000),
+            ("d", 86_400_000),
+            ("w", 604_800_000),
+        ];
+        for n in [0u64, 1, 7, 59, 60, 1000, 86_401] {
+            for (unit, millis) in units {
+                let parsed = parse_duration(&format!("{}{}", n, unit));
+                assert_eq!(
+                    parsed,
+                    Ok(Duration::from_millis(n * millis)),
+                    "{}{}",
+                    n,
+                    unit
+                );
+                assert_eq!(parse_duration(&format!(" {} {} ", n, unit)), parsed);
+                let duration = parsed.unwrap();
+                assert_eq!(parse_duration(&format_duration(duration)), Ok(duration));
+            }
+        }
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("0.5s"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration(".25m"), Ok(Duration::from_secs(15)));
+
+        for value in [
+            "",
+            "s",
+            ".",
+            ".s",
+            "1.2.3s",
+            "-5s",
+            "5M",
+            "5sec",
+            "1e3s",
+            "99999999999999w",
+        ] {
+            let error = parse_duration(value).unwrap_err();
+            assert!(error.contains("as in 500ms, 30s, 1.5h or 2w"), "{}", error);
+        }
+        // A bare number could be seconds as well as milliseconds, so only
+        // the config file and the flags that always took seconds read it.
+        assert!(
+            parse_duration("100")
+                .unwrap_err()
+                .contains("for seconds, write 100s")
+        );
+        assert_eq!(parse_duration_or_secs("100"), Ok(Duration::from_secs(100)));
+        assert_eq!(
+            parse_duration_or_secs("100ms"),
+            Ok(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn text_output_writes_numbers_as_the_locale_does() {
+        use crate::config::{Locale, SizeUnits};
+        let (de, ch, fr) = (
+            Locale::parse("de-DE").unwrap(),
+            Locale::parse("de_CH.UTF-8").unwrap(),
+            Locale::parse("fr").unwrap(),
+        );
+        assert_eq!(count_in(1234567, None), "1234567");
+        assert_eq!(
+            count_in(1234567, Some(&Locale::parse("en-US").unwrap())),
+            "1,234,567"
+        );
+        assert_eq!(count_in(1234567, Some(&de)), "1.234.567");
+        assert_eq!(count_in(1234567, Some(&ch)), "1\u{2019}234\u{2019}567");
+        assert_eq!(count_in(123, Some(&de)), "123");
+        assert_eq!(count_in(1234, Some(&Locale::parse("C").unwrap())), "1234");
+        assert!(Locale::parse("xx").is_err());
+
+        assert_eq!(size_in(1023, SizeUnits::Binary, None), "1023 B");
+        assert_eq!(size_in(1536, SizeUnits::Binary, None), "1.5 KiB");
+        assert_eq!(size_in(1536, SizeUnits::Si, None), "1.5 kB");
+        assert_eq!(size_in(10 << 20, SizeUnits::Si, Some(&de)), "10,5 MB");
+        assert_eq!(size_in(3 << 30, SizeUnits::Binary, Some(&fr)), "3,0 GiB");
+        assert_eq!(size_in(1 << 40, SizeUnits::Binary, None), "1.0 TiB");
+    }
+
+    #[test]
+    fn sizes_take_binary_units_and_read_back_as_formatted() {
+        let units = [
+            ("", 1u64),
+            ("K", 1 << 10),
+            ("M", 1 << 20),
+            ("G", 1 << 30),
+            ("T", 1 << 40),
+        ];
+        for n in [0u64, 1, 10, 512, 1023, 4096] {
+            for (unit, multiplier) in units {
+                for spelling in [unit.to_string(), unit.to_lowercase(), format!("{}B", unit)] {
+                    assert_eq!(
+                        parse_size(&format!("{}{}", n, spelling)),
+                        Ok(n * multiplier),
+                        "{}{}",
+                        n,
+                        spelling
+                    );
+                }
+            }
+        }
+        assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+        assert_eq!(parse_size("8MiB"), Ok(8 << 20));
+        for bytes in [0, 1, 1000, 1536, 10 << 20, (1 << 30) + 1] {
+            let shown = parse_size(&format_size(bytes)).unwrap();
+            assert!(
+                shown.abs_diff(bytes) <= bytes / 20,
+                "{} read back as {}",
+                bytes,
+                shown
+            );
+        }
+
+        for value in [
+            "",
+            "K",
+            "1.5",
+            "-1K",
+            "10Q",
+            "1.2.3M",
+            "20000000T",
+            "18446744073709551616",
+        ] {
+            let error = parse_size(value).unwrap_err();
+            assert!(error.contains("as in 512K, 10M or 1.5G"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn acls_are_rendered_in_short_form_and_trivial_ones_dropped() {
+        const UNDEFINED: u32 = u32::MAX;
+        let trivial = [
+            (0x01, 6, UNDEFINED),
+            (0x04, 4, UNDEFINED),
+            (0x20, 4, UNDEFINED),
+        ];
+        assert_eq!(acl_text(&acl(&trivial)), None);
+        let extended = [
+            (0x01, 6, UNDEFINED),
+            (0x02, 7, 1000),
+            (0x04, 4, UNDEFINED),
+            (0x08, 5, 50),
+            (0x10, 7, UNDEFINED),
+            (0x20, 0, UNDEFINED),
+        ];
+        assert_eq!(
+            acl_text(&acl(&extended)).as_deref(),
+            Some("user::rw-,user:1000:rwx,group::r--,group:50:r-x,mask::rwx,other::---")
+        );
+        assert_eq!(acl_text(&[1, 0, 0, 0]), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_paths_are_extended_and_resolved_without_renaming() {
+        assert_eq!(
+            long_path(Path::new(r"C:\conf\..\etc/./CON")),
+            Path::new(r"\\?\C:\etc\CON")
+        );
+        assert_eq!(
+            long_path(Path::new(r"\\server\share\app.conf.")),
+            Path::new(r"\\?\UNC\server\share\app.conf.")
+        );
+        assert_eq!(
+            long_path(Path::new(r"\\?\C:\already")),
+            Path::new(r"\\?\C:\already")
+        );
+    }
 }
@@ -0,0 +1,127 @@
+//! `verify`: check individual files against the snapshot without scanning
+//! their directories.
+
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::{FileHash, GuardianError, Snapshot, config, hash_file_as, history::OutputFormat};
+
+/// How one file compares with the snapshot.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Ok,
+    Changed,
+    /// Recorded in the snapshot, but no longer there.
+    Missing,
+    NotInBaseline,
+    Unreadable,
+}
+
+#[derive(Serialize)]
+struct Verdict<'a> {
+    /// The path as given on the command line.
+    path: &'a str,
+    status: Status,
+    /// The path as recorded in the snapshot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline_path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actual: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The hashes are sampled ones, as the snapshot recorded.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    sampled: bool,
+}
+
+impl Verdict<'_> {
+    fn print(&self) {
+        let display = config::settings().hash_display;
+        let hashes = |text: &str| match (
+            display.show(self.expected),
+            display.show(self.actual.as_deref()),
+        ) {
+            (Some(expected), Some(actual)) => {
+                format!("{} (expected {}, got {})", text, expected, actual)
+            }
+            _ => text.to_string(),
+        };
+        let status = match self.status {
+            Status::Ok => "OK".to_string(),
+            Status::Changed => hashes("CHANGED"),
+            Status::Missing => match display.show(self.expected) {
+                Some(expected) => format!("MISSING (expected {})", expected),
+                None => "MISSING".to_string(),
+            },
+            Status::NotInBaseline => "NOT IN BASELINE".to_string(),
+            Status::Unreadable => format!(
+                "UNREADABLE ({})",
+                self.error.as_deref().unwrap_or("unknown error")
+            ),
+        };
+        let sampled = if self.sampled { " (sampled hash)" } else { "" };
+        println!("{}: {}{}", self.path, status, sampled);
+    }
+}
+
+/// Compare each of `paths` with its snapshot entry and report the result.
+/// Returns the exit code: 0 if every file matches, 1 if any differs or isn't
+/// in the snapshot, 5 if any couldn't be read.
+pub fn verify(paths: &[String], format: OutputFormat) -> Result<i32, GuardianError> {
+    let snapshot = Snapshot::load(&config::settings().snapshot_file)?;
+    let verdicts: Vec<Verdict> = paths.iter().map(|path| verdict(&snapshot, path)).collect();
+    match format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&verdicts).expect("verdicts serialize to JSON")
+        ),
+        OutputFormat::Text => verdicts.iter().for_each(Verdict::print),
+    }
+    let code = |verdict: &Verdict| match verdict.status {
+        Status::Ok => 0,
+        Status::Changed | Status::Missing | Status::NotInBaseline => 1,
+        Status::Unreadable => 5,
+    };
+    Ok(verdicts.iter().map(code).max().unwrap_or(0))
+}
+
+fn verdict<'a>(snapshot: &'a Snapshot, path: &'a str) -> Verdict<'a> {
+    let entry = snapshot.resolve(path);
+    // Hash the file that compare would, so a relative path given against
+    // a snapshot directory reads that directory's file.
+    let on_disk = entry.map_or_else(|| PathBuf::from(path), FileHash::file_path);
+    let mut verdict = Verdict {
+        path,
+        status: Status::Ok,
+        baseline_path: entry.map(|e| e.path.as_str()),
+        expected: entry.map(|e| e.hash.as_str()),
+        actual: None,
+        error: None,
+        sampled: entry.is_some_and(|e| e.sampled.is_some()),
+    };
+    match hash_file_as(&on_disk, entry.and_then(|e| e.sampled)) {
+        Ok(hash) => {
+            verdict.status = match entry {
+                None => Status::NotInBaseline,
+                Some(e) if e.hash == hash => Status::Ok,
+                Some(_) => Status::Changed,
+            };
+            verdict.actual = Some(hash);
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            verdict.status = match entry {
+                Some(_) => Status::Missing,
+                None => Status::NotInBaseline,
+            };
+        }
+        Err(e) => {
+            verdict.status = Status::Unreadable;
+            verdict.error = Some(e.to_string());
+        }
+    }
+    verdict
+}
@@ -0,0 +1,286 @@
+//! The filesystem watch behind `monitor`, on whichever of notify's backends
+//! was asked for, falling back to polling when the native one can't be used.
+
+use notify::{Config, PollWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::GuardianError;
+use crate::utils::is_valid_directory;
+
+/// How often the polling backend scans the directory for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Which of notify's watcher implementations to use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WatchBackend {
+    /// The platform's native backend.
+    #[default]
+    Auto,
+    /// Linux inotify.
+    Inotify,
+    /// BSD kqueue.
+    Kqueue,
+    /// macOS FSEvents.
+    Fsevents,
+    /// Scan the directory every ten seconds; works on any filesystem.
+    Poll,
+}
+
+impl fmt::Display for WatchBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WatchBackend::Auto => "native",
+            WatchBackend::Inotify => "inotify",
+            WatchBackend::Kqueue => "kqueue",
+            WatchBackend::Fsevents => "fsevents",
+            WatchBackend::Poll => "polling",
+        };
+        f.write_str(name)
+    }
+}
+
+impl WatchBackend {
+    /// Whether this platform has the backend at all.
+    pub fn is_available(self) -> bool {
+        match self {
+            WatchBackend::Auto | WatchBackend::Poll => true,
+            WatchBackend::Inotify => cfg!(any(target_os = "linux", target_os = "android")),
+            WatchBackend::Kqueue => cfg!(any(
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd"
+            )),
+            WatchBackend::Fsevents => cfg!(target_os = "macos"),
+        }
+    }
+
+    /// Register a watch on `dir` with this backend alone and drop it again,
+    /// to find out whether `monitor` could use it here.
+    pub fn probe(self, dir: &str) -> notify::Result<()> {
+        let (queue, _rx) = Queue::new(1);
+        open(dir, self, queue).map(drop)
+    }
+}
+
+pub type EventReceiver = Receiver<notify::Result<notify::Event>>;
+
+/// Events the queue had no room for, since [`Watch::overflow`] last
+/// took them.
+#[derive(Debug, Default)]
+pub struct Overflow {
+    pub dropped: u64,
+    /// The events dropped, by the directory of the paths they named.
+    pub dirs: BTreeMap<PathBuf, u64>,
+}
+
+/// The sending side of the event queue, which drops what it has no room
+/// for rather than growing, and counts it.
+#[derive(Clone)]
+struct Queue {
+    events: Sender<notify::Result<notify::Event>>,
+    overflow: Arc<Mutex<Overflow>>,
+}
+
+impl Queue {
+    fn new(capacity: usize) -> (Queue, EventReceiver) {
+        let (events, rx) = mpsc::channel(capacity.max(1));
+        let queue = Queue {
+            events,
+            overflow: Arc::default(),
+        };
+        (queue, rx)
+    }
+
+    fn send(&self, event: notify::Result<notify::Event>) {
+        let Err(mpsc::error::TrySendError::Full(event)) = self.events.try_send(event) else {
+            return;
+        };
+        let mut overflow = self.overflow.lock().unwrap_or_else(|e| e.into_inner());
+        overflow.dropped += 1;
+        let paths = event.map(|event| event.paths).unwrap_or_default();
+        for dir in paths.iter().filter_map(|path| path.parent()) {
+            *overflow.dirs.entry(dir.to_path_buf()).or_default() += 1;
+        }
+    }
+}
+
+/// A watch on one directory, whose events arrive on the receiver returned by
+/// [`Watch::start`].
+pub struct Watch {
+    watcher: Box<dyn Watcher + Send>,
+    backend: WatchBackend,
+    queue: Queue,
+}
+
+impl Watch {
+    /// Watch `dir` with `backend`, or with the polling backend if it can't be
+    /// set up. Fails only when neither works. At most `capacity` events wait
+    /// on the receiver; those that arrive while it's full are dropped, and
+    /// counted for [`Watch::overflow`].
+    pub fn start(
+        dir: &str,
+        backend: WatchBackend,
+        capacity: usize,
+    ) -> Result<(Watch, EventReceiver), GuardianError> {
+        let (queue, rx) = Queue::new(capacity);
+        let (watcher, backend) = match open(dir, backend, queue.clone()) {
+            Ok(watcher) => (watcher, backend),
+            Err(e) if backend == WatchBackend::Poll => {
+                return Err(watch_failed(dir, e.to_string()));
+            }
+            Err(e) => {
+                warn_fallback(dir, backend, &e);
+                let watcher = open(dir, WatchBackend::Poll, queue.clone()).map_err(|poll| {
+                    watch_failed(
+                        dir,
+                        format!("{} backend: {}; polling backend: {}", backend, e, poll),
+                    )
+                })?;
+                (watcher, WatchBackend::Poll)
+            }
+        };
+        log::info!("Watching {} with the {} backend", dir, backend);
+        let watch = Watch {
+            watcher,
+            backend,
+            queue,
+        };
+        Ok((watch, rx))
+    }
+
+    /// The events dropped since the last call, if any were.
+    pub fn overflow(&self) -> Option<Overflow> {
+        let mut overflow = self
+            .queue
+            .overflow
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        (overflow.dropped > 0).then(|| std::mem::take(&mut *overflow))
+    }
+
+    /// Register `dir` again after the watch was lost. When the native backend
+    /// has run out of watches, switches to the polling backend instead of
+    /// failing.
+    pub fn rewatch(&mut self, dir: &str) -> notify::Result<()> {
+        let _ = self.watcher.unwatch(Path::new(dir));
+        match self
+            .watcher
+            .watch(Path::new(dir), RecursiveMode::NonRecursive)
+        {
+            Err(e)
+                if matches!(e.kind, notify::ErrorKind::MaxFilesWatch)
+                    && self.backend != WatchBackend::Poll =>
+            {
+                warn_fallback(dir, self.backend, &e);
+                self.watcher = open(dir, WatchBackend::Poll, self.queue.clone())?;
+                self.backend = WatchBackend::Poll;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// A watcher on `backend` with `dir` registered.
+fn open(dir: &str, backend: WatchBackend, queue: Queue) -> notify::Result<Box<dyn Watcher + Send>> {
+    let handler = move |event| queue.send(event);
+    let config = Config::default();
+    let mut watcher: Box<dyn Watcher + Send> = match backend {
+        WatchBackend::Auto => Box::new(notify::RecommendedWatcher::new(handler, config)?),
+        WatchBackend::Poll => Box::new(PollWatcher::new(
+            handler,
+            config.with_poll_interval(POLL_INTERVAL),
+        )?),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        WatchBackend::Inotify => Box::new(notify::INotifyWatcher::new(handler, config)?),
+        #[cfg(any(target_os = "freebsd", target_os = "openbsd", target_os = "netbsd"))]
+        WatchBackend::Kqueue => Box::new(notify::KqueueWatcher::new(handler, config)?),
+        #[cfg(target_os = "macos")]
+        WatchBackend::Fsevents => Box::new(notify::FsEventWatcher::new(handler, config)?),
+        unavailable => {
+            return Err(notify::Error::generic(&format!(
+                "the {} backend is not available on this platform",
+                unavailable
+            )));
+        }
+    };
+    watcher.watch(Path::new(dir), RecursiveMode::NonRecursive)?;
+    // The polling backend accepts a path that isn't there and watches nothing.
+    if backend == WatchBackend::Poll && !is_valid_directory(dir) {
+        return Err(notify::Error::path_not_found().add_path(dir.into()));
+    }
+    Ok(watcher)
+}
+
+fn watch_failed(dir: &str, reason: String) -> GuardianError {
+    GuardianError::WatchFailed {
+        path: dir.into(),
+        reason,
+    }
+}
+
+/// Shown when inotify runs out of watches.
+pub const WATCH_LIMIT_HINT: &str = "Raise the limit with `sysctl fs.inotify.max_user_watches=524288` \
+     and persist it in /etc/sysctl.d/.";
+
+fn warn_fallback(dir: &str, backend: WatchBackend, e: &notify::Error) {
+    println!(
+        "Warning: Could not watch {} with the {} backend ({}); falling back to polling every {}s.",
+        dir,
+        backend,
+        e,
+        POLL_INTERVAL.as_secs()
+    );
+    if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) {
+        println!("{}", WATCH_LIMIT_HINT);
+        log::warn!(
+            "inotify watch limit reached while watching {}; polling instead. {}",
+            dir,
+            WATCH_LIMIT_HINT
+        );
+    } else {
+        log::warn!("Watcher setup failed for {}: {}; polling instead", dir, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_directory_no_backend_can_watch_is_fatal() {
+        let dir = std::env::temp_dir().join(format!("guardian-unwatchable-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        match Watch::start(dir, WatchBackend::Auto, 16) {
+            Err(e @ GuardianError::WatchFailed { .. }) => {
+                let message = e.to_string();
+                assert!(message.starts_with(&format!("Could not watch {} with any backend", dir)));
+                assert!(message.contains("polling backend"));
+            }
+            Err(e) => panic!("expected a watch failure, got {:?}", e),
+            Ok(_) => panic!("watched a directory that doesn't exist"),
+        }
+    }
+
+    #[test]
+    fn events_a_full_queue_has_no_room_for_are_counted() {
+        let (queue, mut rx) = Queue::new(2);
+        for name in ["a.conf", "b.conf", "c.conf", "d.conf"] {
+            let event = notify::Event::default().add_path(PathBuf::from("/etc/app").join(name));
+            queue.send(Ok(event));
+        }
+        let overflow = std::mem::take(&mut *queue.overflow.lock().unwrap());
+        assert_eq!(overflow.dropped, 2);
+        assert_eq!(
+            overflow.dirs,
+            BTreeMap::from([(PathBuf::from("/etc/app"), 2)])
+        );
+        assert!(rx.try_recv().is_ok() && rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+    }
+}
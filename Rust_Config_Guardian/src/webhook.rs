@@ -0,0 +1,58 @@
+//! Generic JSON webhook alert channel.
+
+use serde_json::json;
+use std::time::Duration;
+
+use crate::alert::{Alert, Listing};
+use crate::utils::hostname;
+use crate::{http, identity};
+
+#[derive(Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Extra request headers, e.g. for authentication.
+    pub headers: Vec<(String, String)>,
+    pub timeout: Duration,
+}
+
+/// The alert as JSON, `omitted` counting the entries `listing` left out
+/// and `report` saying where they are.
+pub fn payload(alert: &Alert, listing: &Listing) -> serde_json::Value {
+    let (listed, omitted) = alert.listed(listing);
+    json!({
+        "summary": alert.summary,
+        "score": alert.score(),
+        "directory": alert.dir,
+        "hostname": hostname(),
+        "instance_id": identity::current(),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "entries": listed,
+        "omitted": omitted,
+        "report": listing.report,
+        "tags": alert.tags,
+        "baseline_age_days": alert.baseline_age_days,
+        "annotations": alert.annotations,
+    })
+}
+
+pub fn send(config: &WebhookConfig, alert: &Alert, listing: &Listing) -> Result<(), String> {
+    http::post_json(
+        &config.url,
+        &config.headers,
+        config.timeout,
+        &payload(alert, listing),
+    )
+}
+
+/// Parse a `Name: Value` header given on the command line.
+pub fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!(
+            "invalid header '{}' (expected 'Name: Value')",
+            value
+        )),
+    }
+}
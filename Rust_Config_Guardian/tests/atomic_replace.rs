@@ -0,0 +1,64 @@
+//! Replaces a watched file the way editors and config management do, by
+//! writing a temporary file and renaming it over the original, and checks
+//! that a real monitor reports the replacement once, for the real file.
+#![cfg(unix)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const BIN: &str = env!("CARGO_BIN_EXE_Rust_Config_Guardian");
+
+fn run(state: &Path, args: &[&str]) {
+    let status = Command::new(BIN)
+        .args(args)
+        .current_dir(state)
+        .env_remove("GUARDIAN_SNAPSHOT_FILE")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "{:?} failed", args);
+}
+
+#[test]
+fn an_atomic_replace_is_reported_once_for_the_real_file() {
+    let state = std::env::temp_dir().join(format!("guardian-replace-{}", std::process::id()));
+    let watched = state.join("watched");
+    std::fs::create_dir_all(&watched).unwrap();
+    std::fs::write(watched.join("app.conf"), "listen 80\n").unwrap();
+    run(&state, &["snapshot", "watched"]);
+
+    let monitor = Command::new(BIN)
+        .args(["monitor", "watched", "--duration", "6s"])
+        .current_dir(&state)
+        .env_remove("GUARDIAN_SNAPSHOT_FILE")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+
+    // The new file's mode is set both before and after the rename, as
+    // tools that copy the original's mode over do.
+    let temp = watched.join(".app.conf.tmp");
+    let conf = watched.join("app.conf");
+    std::fs::write(&temp, "listen 8080\n").unwrap();
+    std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o600)).unwrap();
+    std::fs::rename(&temp, &conf).unwrap();
+    std::fs::set_permissions(&conf, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+    let output = monitor.wait_with_output().unwrap();
+    let _ = std::fs::remove_dir_all(&state);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let reports: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("  ") && line.contains(": watched/"))
+        .collect();
+    assert_eq!(
+        reports,
+        ["  Inode replaced: watched/app.conf"],
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout.matches("Drift detected").count(), 1, "{}", stdout);
+}
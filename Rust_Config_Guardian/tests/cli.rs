@@ -0,0 +1,2186 @@
+//! Runs the binary against scratch directories to pin down the CLI's output
+//! and exit codes.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_Rust_Config_Guardian");
+
+/// A state directory holding a `watched` directory, removed on drop.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("guardian-cli-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("watched")).unwrap();
+        Scratch(dir)
+    }
+
+    fn write(&self, name: &str, content: &str) {
+        std::fs::write(self.0.join(name), content).unwrap();
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    /// The binary run with `args` in the state directory, outside of any
+    /// GitHub Actions job the tests themselves run in.
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(BIN);
+        command
+            .args(args)
+            .current_dir(&self.0)
+            .env_remove("GUARDIAN_SNAPSHOT_FILE")
+            .env_remove("GITHUB_ACTIONS")
+            .env_remove("GITHUB_STEP_SUMMARY");
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn watched(name: &str) -> String {
+    Path::new("watched")
+        .join(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn compare_reports_each_kind_of_drift() {
+    let state = Scratch::new("drift");
+    state.write("watched/app.conf", "a");
+    state.write("watched/old.conf", "b");
+    let output = state.run(&["snapshot", "watched"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Snapshot taken and saved to snapshot.json (2 files)"));
+
+    let output = state.run(&["compare", "watched"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("No drift detected."));
+
+    state.write("watched/app.conf", "changed");
+    state.write("watched/new.conf", "c");
+    std::fs::remove_file(state.path("watched/old.conf")).unwrap();
+    let output = state.run(&["compare", "watched"]);
+    assert_eq!(output.status.code(), Some(1), "compare exits 1 on drift");
+    let out = stdout(&output);
+    assert!(out.contains("Drift detected (score 56):"));
+    assert!(out.contains(&format!("Changed: {}", watched("app.conf"))));
+    assert!(out.contains(&format!("New: {}", watched("new.conf"))));
+    assert!(out.contains(&format!("Deleted: {}", watched("old.conf"))));
+}
+
+#[test]
+fn compare_without_a_snapshot_fails() {
+    let state = Scratch::new("missing");
+    let output = state.run(&["compare", "watched"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No snapshot.json found"));
+}
+
+#[test]
+fn errors_name_the_path_and_exit_with_their_own_code() {
+    let state = Scratch::new("errors");
+    state.write("snapshot.json", "{\"entries\": [");
+    let output = state.run(&["compare", "watched"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("snapshot.json is not a valid snapshot")
+    );
+
+    let output = state.run(&["snapshot", "missing"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing is not a valid directory."));
+}
+
+#[test]
+fn invalid_usage_exits_2() {
+    let state = Scratch::new("usage");
+    assert_eq!(
+        state.run(&["compare", "--no-such-flag"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn accept_takes_drift_into_the_snapshot() {
+    let state = Scratch::new("accept");
+    state.write("watched/app.conf", "a");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    state.write("watched/app.conf", "changed");
+    let conf = watched("app.conf");
+    let output = state.run(&["accept", &conf]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(&format!("Accepted {}", conf)));
+    assert!(stdout(&state.run(&["compare", "watched"])).contains("No drift detected."));
+}
+
+#[test]
+fn accept_records_what_changed_and_why_in_the_history() {
+    let state = Scratch::new("accept-summary");
+    let keeping = |args: &[&str]| {
+        state
+            .command(args)
+            .env("GUARDIAN_KEEP_CONTENT", "true")
+            .output()
+            .unwrap()
+    };
+    state.write("watched/app.conf", "listen 80\nworkers 4\n");
+    assert!(keeping(&["snapshot", "watched"]).status.success());
+
+    state.write("watched/app.conf", "listen 8080\nworkers 4\nlog on\n");
+    let conf = watched("app.conf");
+    let output = keeping(&["accept", &conf, "--reason", "CHG-4432"]);
+    assert!(output.status.success());
+    assert!(
+        stdout(&output).contains("+2 -1 lines"),
+        "{}",
+        stdout(&output)
+    );
+
+    // Without keep_content, what changed isn't known.
+    state.write("watched/app.conf", "listen 443\n");
+    assert!(state.run(&["accept", &conf]).status.success());
+
+    let out = stdout(&state.run(&["history", "--path", &conf]));
+    let accepted: Vec<&str> = out.lines().filter(|l| l.contains("accepted")).collect();
+    assert_eq!(accepted.len(), 2, "{}", out);
+    assert!(
+        accepted[0].contains(&format!("{} [+2 -1 lines] (CHG-4432)", conf)),
+        "{}",
+        out
+    );
+    assert!(!accepted[1].contains(" lines]"), "{}", out);
+}
+
+#[test]
+fn snapshots_saved_as_a_bare_list_still_compare() {
+    let state = Scratch::new("legacy");
+    state.write("watched/app.conf", "a");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(state.path("snapshot.json")).unwrap())
+            .unwrap();
+    assert_eq!(saved["metadata"]["directories"][0], "watched");
+    std::fs::write(
+        state.path("snapshot.json"),
+        serde_json::to_string(&saved["entries"]).unwrap(),
+    )
+    .unwrap();
+
+    let output = state.run(&["compare", "watched"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("No drift detected."));
+}
+
+#[cfg(unix)]
+#[test]
+fn skipped_files_are_summed_up_and_fail_under_strict() {
+    let state = Scratch::new("warnings");
+    state.write("watched/app.conf", "a");
+    let fifo = state.path("watched/pipe");
+    assert!(
+        Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    let output = state.run(&["snapshot", "watched"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("(1 files)"));
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(stderr.contains("Completed with 1 warning (run with --verbose for details)"));
+    assert!(!stderr.contains("not a regular file"));
+
+    let output = state.run(&["compare", "watched", "--verbose", "--strict"]);
+    assert_eq!(output.status.code(), Some(8));
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(stderr.contains(&format!("Skipped {}: not a regular file", watched("pipe"))));
+    assert!(stderr.contains("Completed with 1 warning."));
+}
+
+#[test]
+fn doctor_fails_on_a_damaged_snapshot() {
+    let state = Scratch::new("doctor");
+    state.write("watched/app.conf", "a");
+    let output = state.run(&["doctor"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("PASS  state directory: . is writable"));
+    assert!(out.contains("WARN  snapshot: snapshot.json does not exist yet"));
+
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    assert!(
+        stdout(&state.run(&["doctor"])).contains("PASS  snapshot: snapshot.json holds 1 files")
+    );
+
+    let mut saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(state.path("snapshot.json")).unwrap())
+            .unwrap();
+    saved["entries"][0]["hash"] = "not-a-hash".into();
+    std::fs::write(state.path("snapshot.json"), saved.to_string()).unwrap();
+    // Unsealed, as an older version left it, so it comes to the hashes.
+    std::fs::remove_file(state.path("snapshot.json.seal")).unwrap();
+    let output = state.run(&["doctor"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains(&format!(
+        "FAIL  snapshot: 1 of 1 entries in snapshot.json are not sha256 hashes (first: {})",
+        watched("app.conf")
+    )));
+}
+
+#[test]
+fn a_snapshot_edited_by_hand_is_caught_by_its_seal() {
+    use sha2::{Digest, Sha256};
+    let state = Scratch::new("unsealed");
+    state.write("watched/app.conf", "a");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let output = state.run(&["compare", "watched"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("seal"));
+
+    // Made to agree with a change it should report.
+    state.write("watched/app.conf", "b");
+    let mut saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(state.path("snapshot.json")).unwrap())
+            .unwrap();
+    saved["entries"][0]["hash"] = format!("{:x}", Sha256::digest(b"b")).into();
+    state.write("snapshot.json", &saved.to_string());
+
+    let output = state.run(&["compare", "watched"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Warning: snapshot.json doesn't match its seal: it was modified outside of \
+             config-guardian."
+    ));
+    let output = state.run(&["doctor"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("FAIL  snapshot: snapshot.json doesn't match its seal"));
+}
+
+#[test]
+fn verify_checks_only_the_named_files() {
+    let state = Scratch::new("verify");
+    state.write("watched/app.conf", "a");
+    state.write("watched/db.conf", "b");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["verify", &watched("app.conf"), "db.conf"]);
+    assert_eq!(output.status.code(), Some(0));
+    let out = stdout(&output);
+    assert!(out.contains(&format!("{}: OK", watched("app.conf"))));
+    assert!(out.contains("db.conf: OK"));
+
+    state.write("watched/db.conf", "changed");
+    state.write("watched/new.conf", "c");
+    let absolute = state.path("watched/db.conf");
+    let absolute = absolute.to_str().unwrap();
+    let output = state.run(&["verify", absolute, "new.conf"]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = stdout(&output);
+    assert!(out.contains(&format!("{}: CHANGED (expected ", absolute)));
+    assert!(out.contains("new.conf: NOT IN BASELINE"));
+
+    let output = state.run(&["verify", "db.conf", "--format", "json"]);
+    let verdicts: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(verdicts[0]["status"], "changed");
+    assert_eq!(verdicts[0]["baseline_path"], watched("db.conf"));
+}
+
+#[test]
+fn inventory_rolls_up_the_snapshot_per_directory() {
+    let state = Scratch::new("inventory");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/big.conf", &"x".repeat(2048));
+    std::fs::create_dir(state.path("watched/conf.d")).unwrap();
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["report", "inventory", "--format", "json", "--top", "1"]);
+    assert!(output.status.success(), "{:?}", output);
+    let inventory: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(inventory[0]["directory"], "watched");
+    assert_eq!(inventory[0]["files"], 2);
+    assert_eq!(inventory[0]["bytes"], 2057);
+    assert_eq!(inventory[0]["subdirectories"], 1);
+    assert_eq!(inventory[0]["largest"][0]["path"], watched("big.conf"));
+    assert_eq!(inventory[0]["largest"].as_array().unwrap().len(), 1);
+
+    let output = state.run(&[
+        "report",
+        "inventory",
+        "--format",
+        "csv",
+        "--locale",
+        "de-DE",
+    ]);
+    let csv = stdout(&output);
+    let mut lines = csv.lines();
+    assert!(lines.next().unwrap().starts_with("directory,files,bytes,"));
+    assert!(lines.next().unwrap().starts_with("watched,2,2057,"));
+
+    let text = stdout(&state.run(&["report", "inventory"]));
+    assert!(text.contains("2.0 KiB"), "{}", text);
+    let output = state.run(&[
+        "report",
+        "inventory",
+        "--size-units",
+        "si",
+        "--locale",
+        "de-DE",
+    ]);
+    let text = stdout(&output);
+    assert!(text.contains("2,1 kB"), "{}", text);
+}
+
+#[test]
+fn duplicates_are_grouped_by_content() {
+    let state = Scratch::new("duplicates");
+    state.write("watched/a.conf", "same");
+    state.write("watched/b.conf", "same");
+    state.write("watched/c.conf", "other");
+    state.write("watched/empty1", "");
+    state.write("watched/empty2", "");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["report", "duplicates", "--format", "json"]);
+    assert!(output.status.success(), "{:?}", output);
+    let sets: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(sets.as_array().unwrap().len(), 1);
+    assert_eq!(sets[0]["wasted"], 4);
+    assert_eq!(
+        sets[0]["paths"],
+        serde_json::json!([watched("a.conf"), watched("b.conf")])
+    );
+
+    let output = state.run(&["report", "duplicates"]);
+    assert!(stdout(&output).contains("1 set of duplicates, 4 B wasted"));
+
+    let output = state.run(&["report", "duplicates", "--min-size", "5"]);
+    assert_eq!(stdout(&output), "No duplicate files found.\n");
+}
+
+#[test]
+fn stats_sum_up_the_snapshot_and_the_drift_history() {
+    let state = Scratch::new("stats");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/other.conf", "x");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let stats = |state: &Scratch| -> serde_json::Value {
+        let output = state.run(&["stats", "--format", "json"]);
+        assert!(output.status.success(), "{:?}", output);
+        serde_json::from_slice(&output.stdout).unwrap()
+    };
+    let before = stats(&state);
+    assert_eq!(
+        (
+            before["tracked_files"].as_u64(),
+            before["tracked_bytes"].as_u64()
+        ),
+        (Some(2), Some(10))
+    );
+    assert!(before["last_compare"].is_null());
+
+    state.write("watched/app.conf", "listen 8080");
+    assert_eq!(state.run(&["compare", "watched"]).status.code(), Some(1));
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/other.conf", "y");
+    assert_eq!(state.run(&["compare", "watched"]).status.code(), Some(1));
+    state.write("watched/app.conf", "listen 443");
+    assert_eq!(state.run(&["compare", "watched"]).status.code(), Some(1));
+
+    let after = stats(&state);
+    assert_eq!(after["last_compare"]["directory"], "watched");
+    assert_eq!(after["last_compare"]["drifts"], 2);
+    assert_eq!(after["drift_last_7_days"], 3);
+    assert_eq!(after["most_drifted"][0]["path"], watched("app.conf"));
+    assert_eq!(after["most_drifted"][0]["incidents"], 2);
+    assert_eq!(after["most_drifted"][1]["path"], watched("other.conf"));
+}
+
+#[test]
+fn compare_writes_only_the_sarif_log_to_stdout() {
+    let state = Scratch::new("sarif");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "listen 8080");
+
+    let output = state.run(&["compare", "watched", "--format", "sarif"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let log: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(log["version"], "2.1.0");
+    let results = log["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["ruleId"], "changed");
+    assert_eq!(results[0]["level"], "warning");
+    assert_eq!(
+        results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+        "watched/app.conf"
+    );
+}
+
+#[test]
+fn nagios_checks_print_one_line_and_exit_with_the_state() {
+    let state = Scratch::new("nagios");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/db.conf", "host=db1");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["compare", "watched", "--nagios"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert_eq!(
+        stdout(&output),
+        "GUARDIAN OK - 0 drift | changed=0 new=0 deleted=0 metadata=0 unreadable=0 score=0\n"
+    );
+
+    state.write("watched/app.conf", "listen 8080");
+    std::fs::remove_file(state.path("watched/db.conf")).unwrap();
+    let output = state.run(&["compare", "watched", "--nagios"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let line = stdout(&output);
+    assert_eq!(line.lines().count(), 1, "{}", line);
+    assert!(
+        line.starts_with(
+            "GUARDIAN CRITICAL - 2 drift (1 critical, 1 warning) | changed=1 new=0 deleted=1 "
+        ),
+        "{}",
+        line
+    );
+
+    // Deletions taken down to a warning.
+    state.write(
+        "guardian.toml",
+        "[nagios]\nkinds = { deleted = \"warning\" }\n",
+    );
+    let output = state.run(&["compare", "watched", "--nagios"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(stdout(&output).starts_with("GUARDIAN WARNING - 2 drift (2 warning) |"));
+
+    let output = state.run(&["compare", "missing", "--nagios"]);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(stdout(&output).starts_with("GUARDIAN UNKNOWN - "));
+    let output = state.run(&["compare", "--nagios", "--format", "sarif"]);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+}
+
+#[test]
+fn report_out_writes_junit_beside_the_text_report() {
+    let state = Scratch::new("junit");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "listen 8080");
+
+    let out = state.path("drift.xml");
+    let output = state.run(&[
+        "compare",
+        "watched",
+        "--format",
+        "junit",
+        "--report-out",
+        out.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Drift detected"));
+    let xml = std::fs::read_to_string(&out).unwrap();
+    assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites "));
+    assert!(xml.contains("tests=\"1\" failures=\"1\""), "{}", xml);
+    assert!(xml.contains("<failure type=\"changed\""), "{}", xml);
+
+    let output = state.run(&["compare", "watched", "--report-out", "x.xml"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+}
+
+#[test]
+fn github_actions_get_annotations_and_a_step_summary() {
+    let state = Scratch::new("gha");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/old.conf", "x");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "listen 8080");
+    std::fs::remove_file(state.path("watched/old.conf")).unwrap();
+
+    let output = state
+        .command(&["compare", "watched"])
+        .env("GITHUB_ACTIONS", "true")
+        .env("GITHUB_STEP_SUMMARY", state.path("summary.md"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let text = stdout(&output);
+    assert!(text.contains("Drift detected"), "{}", text);
+    assert!(
+        text.contains("::warning file=watched/app.conf,title=Config drift::Changed (hash "),
+        "{}",
+        text
+    );
+    assert!(text.contains("::error file=watched/old.conf,title=Config drift::Deleted\n"));
+    let summary = std::fs::read_to_string(state.path("summary.md")).unwrap();
+    assert!(summary.contains("2 drifted files."), "{}", summary);
+
+    let output = state
+        .command(&["compare", "watched", "--format", "text"])
+        .env("GITHUB_ACTIONS", "true")
+        .output()
+        .unwrap();
+    assert!(!stdout(&output).contains("::"), "{:?}", output);
+}
+
+#[test]
+fn bounded_monitors_stop_on_their_own_and_fail_on_drift() {
+    let state = Scratch::new("bounded-monitor");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["monitor", "watched", "--duration", "1s"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = stdout(&output);
+    assert!(text.contains("Stopping after 1s."), "{}", text);
+    assert!(text.contains("Session summary:"), "{}", text);
+
+    state.write("watched/app.conf", "listen 8080");
+    // The time limit is only a backstop; the second poll ends the session.
+    let output = state.run(&[
+        "monitor",
+        "watched",
+        "--poll",
+        "1s",
+        "--max-events",
+        "2",
+        "--duration",
+        "1m",
+    ]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let text = stdout(&output);
+    assert!(text.contains("Stopping after 2 events."), "{}", text);
+    assert!(text.contains("Events seen: 2"), "{}", text);
+}
+
+#[test]
+fn sub_second_poll_intervals_are_shown_as_given() {
+    let state = Scratch::new("poll-ms");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["monitor", "watched", "--poll", "500ms", "--duration", "1s"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = stdout(&output);
+    assert!(
+        text.contains("Polling watched every 500ms for changes"),
+        "{}",
+        text
+    );
+
+    // A bare number could be seconds or milliseconds, so it takes a unit.
+    for flag in ["--poll", "--rewatch-timeout"] {
+        let output = state.run(&["monitor", "watched", flag, "5", "--duration", "1s"]);
+        assert_eq!(output.status.code(), Some(2), "{}", flag);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("it has no unit; for seconds, write 5s"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn once_waits_for_the_first_drift() {
+    let state = Scratch::new("once");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["monitor", "watched", "--once", "--duration", "1s"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+
+    state.write("watched/app.conf", "listen 8080");
+    let output = state.run(&[
+        "monitor",
+        "watched",
+        "--once",
+        "--poll",
+        "1s",
+        "--duration",
+        "1m",
+    ]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let text = stdout(&output);
+    assert!(text.contains("Changed: watched"), "{}", text);
+    assert!(text.contains("Drift detected; stopping."), "{}", text);
+    assert!(text.contains("Events seen: 1"), "{}", text);
+}
+
+#[test]
+fn sessions_and_history_say_since_when_files_drift() {
+    let state = Scratch::new("first-seen");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    state.write("watched/app.conf", "listen 8080");
+    let output = state.run(&["monitor", "watched", "--poll", "1s", "--max-events", "2"]);
+    let text = stdout(&output);
+    let still = &text[text.find("Still drifting:").expect("a summary")..];
+    assert!(
+        still.contains(&format!("    {} (first seen ", watched("app.conf"))),
+        "{}",
+        text
+    );
+    assert!(still.contains(", last seen "), "{}", text);
+
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["compare", "watched"]).status.success());
+    let history = stdout(&state.run(&["history"]));
+    let restored = history
+        .lines()
+        .find(|l| l.contains("restored"))
+        .expect("a restored record");
+    assert!(restored.contains("(drifting since "), "{}", history);
+}
+
+#[test]
+fn auto_accept_takes_reported_drift_into_the_snapshot() {
+    let state = Scratch::new("auto-accept");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    state.write("watched/app.conf", "listen 8080");
+    let output = state.run(&[
+        "monitor",
+        "watched",
+        "--auto-accept",
+        "--poll",
+        "1s",
+        "--max-events",
+        "2",
+    ]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no longer guards it"));
+    let text = stdout(&output);
+    assert!(text.contains("Accepted watched"), "{}", text);
+    // The second poll measures from the accepted state.
+    let after = &text[text.find("Accepted watched").unwrap()..];
+    assert!(after.contains("No drift detected."), "{}", text);
+    assert!(!after.contains("Resolved:"), "{}", text);
+
+    assert!(state.run(&["compare", "watched"]).status.success());
+    let history = stdout(&state.run(&["history"]));
+    assert!(history.contains("monitor"), "{}", history);
+    assert!(history.contains("accepted"), "{}", history);
+}
+
+#[test]
+fn watch_only_passes_over_events_on_other_files() {
+    let state = Scratch::new("watch-only");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/notes.txt", "todo");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let monitor = state
+        .command(&[
+            "monitor",
+            "watched",
+            "--watch-only",
+            "*.conf",
+            "--once",
+            "--duration",
+            "4s",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    state.write("watched/notes.txt", "done");
+    let output = monitor.wait_with_output().unwrap();
+    // The edit drifted a tracked file, but no comparison ran to find it.
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = stdout(&output);
+    assert!(!text.contains("Change detected"), "{}", text);
+    assert!(text.contains("Events filtered out: "), "{}", text);
+}
+
+#[test]
+fn all_profiles_runs_each_config_file_profile() {
+    let state = Scratch::new("all-profiles");
+    std::fs::create_dir_all(state.path("other")).unwrap();
+    state.write("watched/app.conf", "listen 80");
+    state.write("other/sshd_config", "Port 22");
+    state.write("other/sshd_config.tmp", "draft");
+    state.write(
+        "guardian.toml",
+        "[profile.app]\ndirectories = [\"watched\"]\nsnapshot = \"app.json\"\n\
+         [profile.ssh]\ndirectories = [\"other\"]\nexclude = [\"*.tmp\"]\n",
+    );
+
+    let output = state.run(&["snapshot", "--all-profiles"]);
+    assert!(output.status.success(), "{:?}", output);
+    let text = stdout(&output);
+    assert!(text.contains("==> Profile app\n"), "{}", text);
+    assert!(text.contains("saved to app.json (1 files)"), "{}", text);
+    assert!(
+        text.contains("saved to snapshot.json (1 files)"),
+        "{}",
+        text
+    );
+    assert!(state.path("app.json").is_file());
+
+    state.write("watched/app.conf", "listen 8080");
+    let output = state.run(&["compare", "--all-profiles", "--fail-on"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let text = stdout(&output);
+    assert!(
+        text.contains("Profiles:\n  app  exit 1\n  ssh  ok\n"),
+        "{}",
+        text
+    );
+
+    let output = state.run(&["compare", "--profile", "ssh"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("No drift detected."));
+    assert!(
+        !state
+            .run(&["compare", "--profile", "nope"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn sha256sums_manifests_import_and_export() {
+    let state = Scratch::new("sha256sums");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/extra.conf", "debug on");
+    // The SHA-256 of "listen 80" and of "gone".
+    state.write(
+        "SHA256SUMS",
+        "a18fb33c7e428aa456b05fabadca7e6beac5f26553f530ab4293d988359cdc1d  app.conf\n\
+         283bb9deef02e6843abfb538efa1eca70801bd8a701c3f98191e123496339247  gone.conf\n",
+    );
+    let output = state.run(&["import", "SHA256SUMS", "--directory", "watched"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("Imported 2 files"));
+
+    let text = stdout(&state.run(&["compare", "watched"]));
+    assert!(
+        text.contains(&format!("New: {}", watched("extra.conf"))),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains(&format!("Deleted: {}", watched("gone.conf"))),
+        "{}",
+        text
+    );
+    assert!(!text.contains("Changed"), "{}", text);
+
+    let output = state.run(&["export", "watched", "--format", "sha256sums"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        stdout(&output),
+        "a18fb33c7e428aa456b05fabadca7e6beac5f26553f530ab4293d988359cdc1d  app.conf\n\
+         283bb9deef02e6843abfb538efa1eca70801bd8a701c3f98191e123496339247  gone.conf\n"
+    );
+
+    state.write("SHA256SUMS", "not a manifest\n");
+    let output = state.run(&["import", "SHA256SUMS"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+}
+
+#[cfg(unix)]
+#[test]
+fn files_turned_into_symlinks_or_directories_are_type_changes() {
+    let state = Scratch::new("types");
+    state.write("watched/resolv.conf", "nameserver 10.0.0.1");
+    state.write("watched/app.conf", "a");
+    std::fs::create_dir(state.path("run")).unwrap();
+    state.write("run/stub-resolv.conf", "nameserver 127.0.0.53");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let saved = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+    assert!(saved.contains(r#""type": "file""#), "{}", saved);
+
+    std::fs::remove_file(state.path("watched/resolv.conf")).unwrap();
+    std::os::unix::fs::symlink("../run/stub-resolv.conf", state.path("watched/resolv.conf"))
+        .unwrap();
+    std::fs::remove_file(state.path("watched/app.conf")).unwrap();
+    std::fs::create_dir(state.path("watched/app.conf")).unwrap();
+    let out = stdout(&state.run(&["compare", "watched"]));
+    assert!(
+        out.contains(&format!(
+            "Type changed: {} (file → symlink -> ../run/stub-resolv.conf)",
+            watched("resolv.conf")
+        )),
+        "{}",
+        out
+    );
+    assert!(out.contains(&format!(
+        "Type changed: {} (file → directory)",
+        watched("app.conf")
+    )));
+    assert!(!out.contains("Deleted"));
+
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let saved = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+    assert!(saved.contains(r#""link_target": "../run/stub-resolv.conf""#));
+    assert!(stdout(&state.run(&["compare", "watched"])).contains("No drift detected."));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_retargeted_out_of_the_tree_are_told_apart() {
+    use std::os::unix::fs::symlink;
+    let state = Scratch::new("retarget");
+    state.write("watched/app.conf", "a");
+    state.write("watched/other.conf", "a");
+    state.write("evil.conf", "a");
+    symlink("app.conf", state.path("watched/enabled.conf")).unwrap();
+    symlink("app.conf", state.path("watched/spelled.conf")).unwrap();
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let saved = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+    assert!(
+        saved.contains(r#""link_resolved": "app.conf""#),
+        "{}",
+        saved
+    );
+
+    let relink = |name: &str, target: &Path| {
+        std::fs::remove_file(state.path(name)).unwrap();
+        symlink(target, state.path(name)).unwrap();
+    };
+    relink("watched/enabled.conf", &state.path("evil.conf"));
+    // Absolute now, but leading to the same file.
+    relink("watched/spelled.conf", &state.path("watched/app.conf"));
+    let output = state.run(&["compare", "watched", "--fail-on", "symlink_escaped"]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = stdout(&output);
+    assert!(
+        out.contains(&format!(
+            "Symlink retargeted out of the tree: {} (app.conf → {})",
+            watched("enabled.conf"),
+            state.path("evil.conf").display()
+        )),
+        "{}",
+        out
+    );
+    assert!(!out.contains("spelled.conf"), "{}", out);
+
+    relink("watched/enabled.conf", Path::new("other.conf"));
+    let out = stdout(&state.run(&["compare", "watched"]));
+    assert!(
+        out.contains(&format!(
+            "Symlink retargeted: {} (app.conf → other.conf)",
+            watched("enabled.conf")
+        )),
+        "{}",
+        out
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn ignored_fields_are_left_out_of_drift_and_named_in_the_header() {
+    use std::os::unix::fs::PermissionsExt;
+    let state = Scratch::new("ignore-fields");
+    state.write("watched/app.conf", "a");
+    state.write("watched/shadow", "s");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    for name in ["watched/app.conf", "watched/shadow"] {
+        std::fs::set_permissions(state.path(name), std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    let output = state.run(&[
+        "compare",
+        "watched",
+        "--ignore-fields",
+        "mode,uid",
+        "--verbose",
+    ]);
+    assert!(stdout(&output).contains("No drift detected."));
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(
+        stderr.contains("Ignoring metadata fields: mode, uid"),
+        "{}",
+        stderr
+    );
+
+    let shadow = watched("shadow");
+    state.write(
+        "guardian.toml",
+        &format!("ignore_fields = [\"mode\"]\n[ignore]\n{:?} = []\n", shadow),
+    );
+    let out = stdout(&state.run(&["compare", "watched"]));
+    assert!(
+        out.contains(&format!(
+            "Drift detected (score 5; ignoring mode; nothing for {}):",
+            shadow
+        )),
+        "{}",
+        out
+    );
+    assert!(out.contains(&format!(
+        "Permissions changed: {} (mode 0644 -> 0600)",
+        shadow
+    )));
+    assert!(!out.contains("app.conf"));
+}
+
+#[cfg(not(feature = "sftp"))]
+#[test]
+fn remote_targets_need_the_sftp_feature() {
+    let scratch = Scratch::new("sftp");
+    let out = scratch.run(&["snapshot", "sftp://admin@firewall:/config"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("--features sftp"), "{}", stderr);
+    assert!(!scratch.path("snapshot.json").exists());
+
+    let out = scratch.run(&["monitor", "sftp://admin@firewall:/config"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("run 'compare' on a schedule"), "{}", stderr);
+}
+
+#[test]
+fn dry_run_prints_what_accept_would_change_without_saving() {
+    let state = Scratch::new("dry-run-accept");
+    state.write("app.conf", "listen 80");
+    let conf = state.path("app.conf");
+    let conf = conf.to_str().unwrap();
+    let dir = state.path("");
+    assert!(
+        state
+            .run(&["snapshot", dir.to_str().unwrap()])
+            .status
+            .success()
+    );
+    let saved = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+
+    state.write("app.conf", "listen 8080");
+    let output = state.run(&["--dry-run", "accept", conf, "--tag", "reason=CHG-1"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(
+        out.contains(&format!("Would accept {} into the snapshot", conf)),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("Would tag the snapshot reason=CHG-1"),
+        "{}",
+        out
+    );
+    assert_eq!(
+        std::fs::read_to_string(state.path("snapshot.json")).unwrap(),
+        saved
+    );
+
+    // Neither recorded nor there: nothing to accept.
+    let missing = state.path("missing.conf");
+    let output = state.run(&["accept", missing.to_str().unwrap(), "--dry-run"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = state.run(&["compare", "--dry-run"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--dry-run only previews"));
+}
+
+#[test]
+fn old_baselines_warn_or_fail() {
+    let state = Scratch::new("stale-baseline");
+    state.write("app.conf", "listen 80");
+    let dir = state.path("");
+    let dir = dir.to_str().unwrap();
+    assert!(state.run(&["snapshot", dir]).status.success());
+
+    // Taken long ago, as far as the snapshot says.
+    let file = state.path("snapshot.json");
+    let mut saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    saved["metadata"]["created"] = "2020-01-01T00:00:00Z".into();
+    saved["metadata"].as_object_mut().unwrap().remove("taken");
+    std::fs::write(&file, saved.to_string()).unwrap();
+
+    let output = state.run(&["compare", dir, "--max-baseline-age", "30d"]);
+    assert!(output.status.success());
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(err.contains("days old (taken 2020-01-01"), "{}", err);
+
+    let output = state.run(&[
+        "compare",
+        dir,
+        "--max-baseline-age",
+        "30d",
+        "--require-fresh-baseline",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = state.run(&["list", "--max-baseline-age", "30d"]);
+    assert!(stdout(&output).contains("  stale: "), "{}", stdout(&output));
+    let output = state.run(&["list", "--max-baseline-age", "100000d"]);
+    assert!(
+        !stdout(&output).contains("  stale: "),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn redacted_files_are_named_by_a_placeholder() {
+    let state = Scratch::new("redact");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/tls.key", "secret");
+    state.write("guardian.toml", "redact = [\"*.key\"]\n");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "listen 8080");
+    state.write("watched/tls.key", "rotated");
+
+    let output = state.run(&["compare", "watched"]);
+    let out = stdout(&output);
+    assert!(
+        out.contains(&format!("Changed: {}", watched("app.conf"))),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("Changed: watched") && out.contains("[redacted:"),
+        "{}",
+        out
+    );
+    assert!(!out.contains("tls.key"), "{}", out);
+    let history = std::fs::read_to_string(state.path("drift-history.jsonl")).unwrap();
+    assert!(history.contains("[redacted:") && !history.contains("tls.key"));
+
+    // Only a terminal is shown the names.
+    let output = state.run(&["compare", "watched", "--no-redact"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn a_baseline_is_taken_from_an_image_layer() {
+    let state = Scratch::new("image");
+    state.write("watched/app.conf", "listen 8080\n");
+    state.write("watched/hard.conf", "listen 80\n");
+    let layer = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/images/layer-gnu.tar"
+    );
+    let output = state.run(&[
+        "snapshot",
+        "watched",
+        "--from-tar",
+        layer,
+        "--strip-prefix",
+        "etc/app",
+    ]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("(6 files)"), "{}", stdout(&output));
+
+    let output = state.run(&["compare", "watched"]);
+    let out = stdout(&output);
+    assert!(
+        out.contains(&format!("Changed: {}", watched("app.conf"))),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains(&format!("Deleted: {}", watched("shared.conf"))),
+        "{}",
+        out
+    );
+    assert!(!out.contains("hard.conf"), "{}", out);
+
+    let output = state.run(&["snapshot", "watched", "--strip-prefix", "etc/app"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn new_executables_are_reported_first_unless_expected() {
+    use std::os::unix::fs::PermissionsExt;
+    let state = Scratch::new("executables");
+    state.write("watched/app.conf", "a");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "b");
+    state.write("watched/zz-run.sh", "#!/bin/sh");
+    let mode = std::fs::Permissions::from_mode(0o755);
+    std::fs::set_permissions(state.path("watched/zz-run.sh"), mode).unwrap();
+
+    let out = stdout(&state.run(&["compare", "watched"]));
+    let executable = out.find(&format!("New executable: {}", watched("zz-run.sh")));
+    let changed = out.find(&format!("Changed: {}", watched("app.conf")));
+    assert!(executable.is_some() && executable < changed, "{}", out);
+
+    state.write("guardian.toml", "expect_executables = [\"*watched\"]\n");
+    let out = stdout(&state.run(&["compare", "watched"]));
+    assert!(
+        out.contains(&format!("New: {}", watched("zz-run.sh"))),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn excepted_drift_is_not_drift_until_the_exception_expires() {
+    let state = Scratch::new("exceptions");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/flag.conf", "off");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/flag.conf", "on");
+    let flag = watched("flag.conf");
+    let output = state.run(&[
+        "exception",
+        "add",
+        "--path",
+        &flag,
+        "--until",
+        "1d",
+        "--reason",
+        "CHG-1234 canary",
+    ]);
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    let output = state.run(&["compare", "watched", "--fail-on"]);
+    let out = stdout(&output);
+    assert_eq!(output.status.code(), Some(0), "{}", out);
+    assert!(out.contains("Excepted (not drift):"), "{}", out);
+    assert!(
+        out.contains(&format!("  Changed: {} (CHG-1234 canary, until ", flag)),
+        "{}",
+        out
+    );
+
+    // Lapsed, with the drift still there.
+    let exceptions = std::fs::read_to_string(state.path("exceptions.json")).unwrap();
+    let mut exceptions: serde_json::Value = serde_json::from_str(&exceptions).unwrap();
+    exceptions[0]["until"] = "2020-01-01T00:00:00Z".into();
+    state.write("exceptions.json", &exceptions.to_string());
+    let output = state.run(&["compare", "watched", "--fail-on"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(stderr.contains("expired"), "{}", stderr);
+    assert!(stdout(&state.run(&["exception", "list"])).contains("No exceptions."));
+
+    let history = std::fs::read_to_string(state.path("drift-history.jsonl")).unwrap();
+    assert!(history.contains("\"exception_added\"") && history.contains("\"exception_expired\""));
+    assert!(history.contains("CHG-1234 canary"));
+}
+
+#[test]
+fn annotations_follow_drift_and_outlive_new_snapshots() {
+    let state = Scratch::new("annotations");
+    state.write("watched/db.conf", "pool 10");
+    state.write("watched/old.conf", "legacy");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let db = watched("db.conf");
+    let old = watched("old.conf");
+    for (path, owner) in [(&db, "db-team"), (&old, "ops")] {
+        let output = state.run(&["annotate", path, "--owner", owner]);
+        assert!(output.status.success(), "{}", stdout(&output));
+    }
+    let output = state.run(&["annotate", &db, "--note", "rotated monthly"]);
+    assert!(
+        stdout(&output).contains("owner: db-team — note: rotated monthly"),
+        "{}",
+        stdout(&output)
+    );
+    assert_eq!(
+        state
+            .run(&["annotate", "watched/none.conf", "--owner", "x"])
+            .status
+            .code(),
+        Some(2)
+    );
+
+    state.write("watched/db.conf", "pool 20");
+    let out = stdout(&state.run(&["compare", "watched"]));
+    assert!(
+        out.contains(&format!(
+            "  Changed: {} — owner: db-team — note: rotated monthly",
+            db
+        )),
+        "{}",
+        out
+    );
+
+    // A new snapshot without old.conf keeps both, the one for it stale.
+    std::fs::remove_file(state.path("watched/old.conf")).unwrap();
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let output = state.run(&["show", "--annotations", "--format", "json"]);
+    let listed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+    let stale: Vec<&serde_json::Value> = listed
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|annotation| annotation["stale"] == true)
+        .collect();
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0]["owner"], "ops");
+}
+
+#[test]
+fn chunked_hashes_say_where_a_large_file_changed() {
+    let state = Scratch::new("chunked-hash");
+    let mut seed = 7u64;
+    let mut bundle: Vec<u8> = (0..(2 << 20))
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as u8
+        })
+        .collect();
+    std::fs::create_dir_all(state.path("watched")).unwrap();
+    std::fs::write(state.path("watched/all.bundle"), &bundle).unwrap();
+    state.write("watched/app.conf", "listen 80");
+    let output = state.run(&["snapshot", "watched", "--chunked-hash", "*.bundle"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let snapshot = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+    let snapshot: serde_json::Value = serde_json::from_str(&snapshot).unwrap();
+    let entries = snapshot["entries"].as_array().unwrap();
+    let chunked = |name: &str| {
+        let entry = entries
+            .iter()
+            .find(|e| e["path"].as_str().unwrap().ends_with(name))
+            .unwrap();
+        entry["chunks"]["list"].as_array().map(Vec::len)
+    };
+    assert!(chunked("all.bundle").unwrap() > 4);
+    assert_eq!(chunked("app.conf"), None);
+
+    bundle[1 << 20] ^= 0xff;
+    std::fs::write(state.path("watched/all.bundle"), &bundle).unwrap();
+    let out = stdout(&state.run(&["compare", "watched", "--chunked-hash", "*.bundle"]));
+    let line = out
+        .lines()
+        .find(|line| line.contains("Changed:"))
+        .unwrap_or_else(|| panic!("{}", out));
+    assert!(line.contains("all.bundle (1 of "), "{}", out);
+    assert!(line.contains(" chunks differs, at "), "{}", out);
+}
+
+#[test]
+fn virtual_files_are_read_on_every_comparison_and_show_their_values() {
+    let state = Scratch::new("virtual-file");
+    state.write("watched/app.conf", "listen 80");
+    // Like a tunable under /proc/sys, it keeps its size and time as its
+    // value changes.
+    let set_back = || {
+        std::fs::File::options()
+            .write(true)
+            .open(state.path("ip_forward"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(86400))
+            .unwrap();
+    };
+    state.write("ip_forward", "0\n");
+    set_back();
+    let output = state.run(&["snapshot", "watched", "--virtual-file", "ip_forward"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let snapshot = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+    let snapshot: serde_json::Value = serde_json::from_str(&snapshot).unwrap();
+    let entry = snapshot["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|e| e["path"] == "ip_forward")
+        .unwrap();
+    assert_eq!(entry["virtual"], "0\n");
+
+    state.write("ip_forward", "1\n");
+    set_back();
+    let out = stdout(&state.run(&["compare", "watched", "--virtual-file", "ip_forward"]));
+    assert!(out.contains("ip_forward (\"0\" → \"1\")"), "{}", out);
+    assert!(!out.contains("app.conf"), "{}", out);
+}
+
+#[test]
+fn monitor_refuses_intervals_of_zero() {
+    let state = Scratch::new("zero-interval");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    for args in [
+        &["--compare-every", "0s", "--schedule-only"][..],
+        &["--poll", "0s"],
+        &["--poll", "0ms"],
+    ] {
+        let output = state.run(&[&["monitor", "watched"], args].concat());
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("it must be longer than zero"), "{}", stderr);
+    }
+
+    state.write("guardian.toml", "[monitor]\ncompare_every = \"0s\"\n");
+    let output = state.run(&[
+        "--config",
+        "guardian.toml",
+        "monitor",
+        "watched",
+        "--schedule-only",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("monitor: compare_every must be longer than zero"),
+        "{}",
+        stderr
+    );
+
+    let output = state.run(&["--config", "guardian.toml", "config", "validate"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("has 1 problem:\n  monitor: compare_every"));
+}
+
+#[test]
+fn baseline_exclude_leaves_entries_out_of_the_loaded_baseline() {
+    let state = Scratch::new("baseline-exclude");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/README.md", "vendor notes");
+    state.write("watched/notes.txt", "v1");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    std::fs::remove_file(state.path("watched/README.md")).unwrap();
+    state.write("watched/notes.txt", "v2");
+
+    let out = stdout(&state.run(&["compare", "watched"]));
+    assert!(out.contains("README.md"), "{}", out);
+
+    let output = state.run(&[
+        "compare",
+        "watched",
+        "--verbose",
+        "--baseline-exclude",
+        "README.md",
+        "--baseline-exclude",
+        "*.txt",
+    ]);
+    let out = stdout(&output);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}{}", out, err);
+    assert!(out.contains("No drift detected"), "{}", out);
+    assert!(
+        out.contains("Left out of the baseline by --baseline-exclude: 2 entries"),
+        "{}",
+        out
+    );
+    assert!(
+        err.contains("left out of the baseline: --baseline-exclude *.txt"),
+        "{}",
+        err
+    );
+
+    // Snapshots don't load a baseline, so they have nothing to leave out.
+    let output = state.run(&["snapshot", "watched", "--baseline-exclude", "*.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn the_identity_is_stored_on_the_first_run_unless_given() {
+    let state = Scratch::new("identity");
+    state.write("watched/app.conf", "listen 80");
+    let recorded = || {
+        let snapshot = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+        let snapshot: serde_json::Value = serde_json::from_str(&snapshot).unwrap();
+        snapshot["metadata"]["instance_id"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    let stored = std::fs::read_to_string(state.path("instance-id")).unwrap();
+    let stored = stored.trim().to_string();
+    assert_eq!(stored.len(), 32);
+    assert_eq!(recorded(), stored);
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    assert_eq!(recorded(), stored);
+
+    let output = state.run(&["--instance-id", "web-7", "snapshot", "watched"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert_eq!(recorded(), "web-7");
+    let output = state.run(&["--instance-id", "../web-7", "snapshot", "watched"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn files_read_before_are_hashed_from_the_cache_until_cleared() {
+    let state = Scratch::new("hash-cache");
+    state.write("watched/app.conf", "listen 80");
+    // Only files older than the scan by a couple of seconds are cached.
+    std::thread::sleep(std::time::Duration::from_millis(2500));
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    assert!(state.path("hash-cache.json").exists());
+
+    let output = state.run(&["compare", "watched", "--verbose"]);
+    let out = stdout(&output);
+    assert!(out.contains("hash cache hits 1 of 1 (100%)"), "{}", out);
+    let out = stdout(&state.run(&["compare", "watched", "--verbose", "--no-cache"]));
+    assert!(!out.contains("hash cache"), "{}", out);
+
+    assert!(stdout(&state.run(&["cache", "clear"])).contains("Hash cache cleared (1 entries)."));
+    assert!(!state.path("hash-cache.json").exists());
+    assert!(stdout(&state.run(&["cache", "clear"])).contains("No hash cache to clear."));
+}
+
+#[test]
+fn comparisons_scan_with_the_options_the_baseline_was_taken_with() {
+    let state = Scratch::new("baseline-options");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/app.conf.bak", "listen 8080");
+    state.write("guardian.toml", "exclude = [\"*.bak\"]\n");
+    let dir = state.path("watched");
+    let dir = dir.to_str().unwrap();
+    assert!(state.run(&["snapshot", dir]).status.success());
+    std::fs::remove_file(state.path("guardian.toml")).unwrap();
+
+    let output = state.run(&["list"]);
+    assert!(
+        stdout(&output).contains("exclude = [\"*.bak\"]"),
+        "{}",
+        stdout(&output)
+    );
+
+    // Without the exclude, the backup would be new.
+    let output = state.run(&["compare", dir, "--fail-on"]);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}{}", stdout(&output), err);
+    assert!(
+        err.contains("Using the baseline's scan options: exclude = [\"*.bak\"]"),
+        "{}",
+        err
+    );
+
+    state.write("guardian.toml", "exclude = [\"*.tmp\"]\n");
+    let output = state.run(&["compare", dir]);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "the backup is new");
+    assert!(
+        err.contains("Warning: the scan options differ from the baseline's: exclude = [\"*.tmp\"]"),
+        "{}",
+        err
+    );
+    let output = state.run(&["compare", dir, "--strict-options"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn a_directory_the_baseline_was_not_taken_of_is_refused() {
+    let state = Scratch::new("baseline-root");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/motd", "hello");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    // The same directory by another path is compared with its entries.
+    let watched = state.path("watched");
+    let output = state.run(&["compare", watched.to_str().unwrap(), "--fail-on"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("No drift detected."));
+
+    std::fs::create_dir(state.path("other")).unwrap();
+    state.write("other/app.conf", "listen 80");
+    let output = state.run(&["compare", "other"]);
+    assert_eq!(output.status.code(), Some(2));
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        err.contains("the baseline was taken of watched, not other"),
+        "{}",
+        err
+    );
+
+    let output = state.run(&["compare", "other", "--allow-root-mismatch"]);
+    let out = stdout(&output);
+    assert!(out.contains("Deleted: other/motd"), "{}", out);
+    assert!(!out.contains("app.conf"), "{}", out);
+}
+
+#[test]
+fn duration_flags_need_a_unit_but_poll_takes_seconds() {
+    let state = Scratch::new("duration-flags");
+    state.write("watched/app.conf", "listen 80");
+    let output = state.run(&["compare", "watched", "--retry-delay", "100"]);
+    assert_eq!(output.status.code(), Some(2));
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        err.contains("it has no unit; for seconds, write 100s"),
+        "{}",
+        err
+    );
+    let output = state.run(&["snapshot", "watched", "--sample-hash", "1.5"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be whole"));
+
+    assert!(
+        state
+            .run(&[
+                "snapshot",
+                "watched",
+                "--sample-hash",
+                "1.5G",
+                "--retry-delay",
+                "0.1s"
+            ])
+            .status
+            .success()
+    );
+    let output = state.run(&["monitor", "watched", "--poll", "1s", "--max-events", "1"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn history_records_who_ran_each_command_without_its_secrets() {
+    let state = Scratch::new("invoker");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    state.write("watched/app.conf", "listen 8080");
+    let output = state
+        .command(&[
+            "compare",
+            "watched",
+            "--webhook-url",
+            "http://127.0.0.1:9/hooks/s3cr3t-path",
+            "--webhook-header=Authorization: Bearer s3cr3t-header",
+        ])
+        .env("SUDO_USER", "alice")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+
+    let history = stdout(&state.run(&["history", "--user", "alice"]));
+    assert!(history.contains("(sudo from alice)"), "{}", history);
+    let others = stdout(&state.run(&["history", "--user", "no-such-user"]));
+    assert!(others.contains("No matching drift history."), "{}", others);
+
+    let recorded = std::fs::read_to_string(state.path("drift-history.jsonl")).unwrap();
+    assert!(
+        recorded.contains("http://127.0.0.1:9/********"),
+        "{}",
+        recorded
+    );
+    assert!(recorded.contains("Authorization: ********"), "{}", recorded);
+    assert!(!recorded.contains("s3cr3t"), "{}", recorded);
+}
+
+#[cfg(unix)]
+#[test]
+fn normalizers_hash_what_their_command_prints() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let state = Scratch::new("normalize");
+    state.write("watched/router.vconf", "a = 1\nb = 2\n");
+    state.write("watched/app.conf", "listen 80\n");
+    state.write("guardian.toml", "[normalize]\n\"*.vconf\" = \"sort\"\n");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    // Reordered, it sorts the same; a broken normalizer hashes it raw. The
+    // time is set back so that it's read rather than passed as unchanged.
+    state.write("watched/router.vconf", "b = 2\na = 1\n");
+    std::fs::File::options()
+        .write(true)
+        .open(state.path("watched/router.vconf"))
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600))
+        .unwrap();
+    let output = state.run(&["compare", "watched", "--fail-on"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    state.write(
+        "guardian.toml",
+        "[normalize]\n\"*.vconf\" = \"sh -c 'exit 7'\"\n",
+    );
+    let output = state.run(&["compare", "watched"]);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stdout(&output).contains("router.vconf"),
+        "{}",
+        stdout(&output)
+    );
+    assert!(
+        err.contains("failed on") && err.contains("hashing it as it is"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains("Warning: the scan options differ from the baseline's: normalize = "),
+        "{}",
+        err
+    );
+
+    let config = state.path("guardian.toml");
+    std::fs::set_permissions(&config, std::fs::Permissions::from_mode(0o666)).unwrap();
+    let output = state.run(&["compare", "watched"]);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(err.contains("Refusing the normalizers"), "{}", err);
+}
+
+#[test]
+fn verify_install_checks_the_executable_against_its_digest() {
+    use sha2::{Digest, Sha256};
+
+    let state = Scratch::new("verify-install");
+    let digest = format!("{:x}", Sha256::digest(std::fs::read(BIN).unwrap()));
+    let output = state.run(&["verify-install", "--expected", &digest]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert!(stdout(&output).contains(&format!("OK (sha256 {})", digest)));
+
+    let other = "0".repeat(64);
+    let output = state.run(&["verify-install", "--expected", &other, "--format", "json"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let verified: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(verified["actual"], digest.as_str());
+    assert_eq!(verified["matches"], false);
+
+    let output = state.run(&["verify-install", "--expected", "abc"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn accept_applies_what_a_reviewed_report_approved() {
+    let state = Scratch::new("reviewed");
+    for name in ["approved.conf", "rejected.conf", "edited.conf"] {
+        state.write(&format!("watched/{}", name), "listen 80");
+    }
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    for name in ["approved.conf", "rejected.conf", "edited.conf"] {
+        state.write(&format!("watched/{}", name), "listen 8080");
+    }
+    let output = state.run(&["compare", "watched", "--on-drift", "tee report.json"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+
+    // The reviewer approves two, one of which is edited again after.
+    let report = std::fs::read_to_string(state.path("report.json")).unwrap();
+    let mut report: serde_json::Value = serde_json::from_str(&report).unwrap();
+    for drift in report["drifts"].as_array_mut().unwrap() {
+        let approved = !drift["path"].as_str().unwrap().ends_with("rejected.conf");
+        drift["approved"] = approved.into();
+    }
+    state.write("reviewed.json", &report.to_string());
+    state.write("watched/edited.conf", "listen 9090");
+
+    let output = state.run(&["accept", "--from-report", "reviewed.json"]);
+    let out = stdout(&output);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}{}", out, err);
+    assert!(out.contains("1 applied, 1 skipped, 1 stale"), "{}", out);
+    assert!(
+        err.contains("edited.conf: changed since the review"),
+        "{}",
+        err
+    );
+
+    let output = state.run(&["compare", "watched"]);
+    let out = stdout(&output);
+    assert!(!out.contains("approved.conf"), "{}", out);
+    assert!(
+        out.contains("rejected.conf") && out.contains("edited.conf"),
+        "{}",
+        out
+    );
+    let history = stdout(&state.run(&["history"]));
+    assert!(history.contains("reviewed"), "{}", history);
+}
+
+#[cfg(unix)]
+#[test]
+fn scans_do_not_follow_symlinks_out_of_the_tree() {
+    use std::os::unix::fs::symlink;
+    let state = Scratch::new("root-symlink");
+    state.write("watched/app.conf", "listen 80");
+    std::fs::create_dir_all(state.path("outside")).unwrap();
+    state.write("outside/secret.conf", "password=1");
+    symlink("watched", state.path("linked")).unwrap();
+    symlink("../outside", state.path("watched/escape")).unwrap();
+
+    let output = state.run(&["snapshot", "linked"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(err.contains("linked is a symlink to watched"), "{}", err);
+    let output = state.run(&["snapshot", "linked/", "--follow-root-symlink"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    // A pattern's ** descends into symlinked directories, but what they
+    // lead to outside its base directory isn't recorded.
+    let output = state.run(&["snapshot", "watched/**/*", "--verbose"]);
+    assert!(output.status.success(), "{:?}", output);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        err.contains("Skipped watched/escape/secret.conf: it resolves to")
+            && err.contains("outside/secret.conf, outside the tree scanned"),
+        "{}",
+        err
+    );
+    let saved = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+    assert!(!saved.contains("secret.conf"), "{}", saved);
+}
+
+#[cfg(unix)]
+#[test]
+fn accept_refuses_paths_that_lead_out_of_the_tree() {
+    let state = Scratch::new("hostile");
+    state.write("watched/app.conf", "listen 80");
+    std::fs::create_dir_all(state.path("outside")).unwrap();
+    state.write("outside/secret.conf", "password=1");
+    std::os::unix::fs::symlink("../outside", state.path("watched/escape")).unwrap();
+    assert!(state.run(&["snapshot", "watched/**/*"]).status.success());
+
+    // A hostile snapshot naming files outside the tree, by `..` and by a
+    // path of its own.
+    let mut saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(state.path("snapshot.json")).unwrap())
+            .unwrap();
+    let entry = saved["entries"][0].clone();
+    for path in ["watched/../outside/secret.conf", "/etc/passwd"] {
+        let mut hostile = entry.clone();
+        hostile["path"] = path.into();
+        saved["entries"].as_array_mut().unwrap().push(hostile);
+    }
+    state.write("snapshot.json", &saved.to_string());
+    let output = state.run(&["compare", "watched/**/*"]);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        err.contains("(watched/../outside/secret.conf): the path leads out of its directory"),
+        "{}",
+        err
+    );
+
+    for (path, why) in [
+        (
+            "watched/../outside/secret.conf",
+            "the path leads out of its directory",
+        ),
+        ("/etc/passwd", "it isn't in watched/**/*"),
+        (
+            "watched/escape/secret.conf",
+            "outside/secret.conf, outside watched/**/*",
+        ),
+    ] {
+        let output = state.run(&["accept", path]);
+        assert_eq!(output.status.code(), Some(2), "{:?}", output);
+        let err = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            err.contains(&format!("refusing to accept {}: ", path)),
+            "{}",
+            err
+        );
+        assert!(err.contains(why), "{}", err);
+    }
+
+    let drifts = [
+        "watched/../outside/secret.conf",
+        "/etc/passwd",
+        "watched/escape/secret.conf",
+    ]
+    .map(|path| serde_json::json!({"kind": "changed", "path": path}));
+    state.write(
+        "reviewed.json",
+        &serde_json::json!({ "drifts": drifts }).to_string(),
+    );
+    let output = state.run(&["accept", "--from-report", "reviewed.json"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(
+        stdout(&output).contains("0 applied, 0 skipped, 3 stale"),
+        "{:?}",
+        output
+    );
+    let saved = std::fs::read_to_string(state.path("snapshot.json")).unwrap();
+    assert!(!saved.contains("escape/secret.conf"), "{}", saved);
+}
+
+#[cfg(unix)]
+#[test]
+fn emptied_files_are_reported_as_truncated() {
+    let state = Scratch::new("truncated");
+    state.write("watched/app.conf", &"listen 80\n".repeat(500));
+    state.write("watched/placeholder.conf", "");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "");
+    state.write("watched/placeholder.conf", "listen 80");
+
+    let out = stdout(&state.run(&["compare", "watched", "--on-drift", "tee report.json"]));
+    assert!(
+        out.contains("Truncated: ") && out.contains("app.conf (was 4.9 KiB)"),
+        "{}",
+        out
+    );
+    assert!(out.contains("placeholder.conf (now 9 B)"), "{}", out);
+    let report = std::fs::read_to_string(state.path("report.json")).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+    let kinds: Vec<&str> = report["drifts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["truncated", "populated"]);
+}
+
+#[test]
+fn staged_changes_are_compared_as_hypothetical_drift() {
+    let state = Scratch::new("staged");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/old.conf", "legacy");
+    state.write("watched/same.conf", "unchanged");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    std::fs::create_dir(state.path("stage")).unwrap();
+    state.write("stage/app.conf", "listen 8080");
+    state.write("stage/old.conf.whiteout", "");
+    state.write("stage/new.conf", "feature = on");
+
+    let output = state.run(&["compare", "watched", "--staged", "stage", "--fail-on"]);
+    let out = stdout(&output);
+    assert_eq!(output.status.code(), Some(1), "{}", out);
+    assert!(
+        out.contains("Hypothetical drift with stage applied"),
+        "{}",
+        out
+    );
+    for line in ["Changed: ", "New: ", "Deleted: "] {
+        assert!(out.contains(line), "{}", out);
+    }
+    assert!(!out.contains("same.conf"), "{}", out);
+
+    // The live tree and its history are as they were.
+    let output = state.run(&["compare", "watched", "--fail-on"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let history = stdout(&state.run(&["history"]));
+    assert!(!history.contains("app.conf"), "{}", history);
+    let output = state.run(&["compare", "watched", "--staged", "missing"]);
+    assert_eq!(output.status.code(), Some(5));
+}
+
+#[test]
+fn scoped_comparisons_leave_the_rest_of_the_baseline_out() {
+    let state = Scratch::new("scope");
+    std::fs::create_dir(state.path("watched/ssh")).unwrap();
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/ssh/sshd.conf", "PermitRootLogin no");
+    state.write("watched/ssh/old.conf", "Protocol 2");
+    assert!(
+        state
+            .run(&["snapshot", "watched/**/*.conf"])
+            .status
+            .success()
+    );
+
+    state.write("watched/app.conf", "listen 8080");
+    let output = state.run(&["compare", "watched/**/*.conf", "--scope", "ssh/"]);
+    let out = stdout(&output);
+    assert!(out.contains("No drift detected in scope ssh/."), "{}", out);
+
+    std::fs::remove_file(state.path("watched/ssh/old.conf")).unwrap();
+    let output = state.run(&["compare", "watched/**/*.conf", "--scope", "ssh"]);
+    let out = stdout(&output);
+    assert!(
+        out.contains("Drift detected (score 50; scope ssh):"),
+        "{}",
+        out
+    );
+    assert!(out.contains("Deleted: watched/ssh/old.conf"), "{}", out);
+    assert!(!out.contains("app.conf"), "{}", out);
+
+    for scope in ["../other", "nginx"] {
+        let output = state.run(&["compare", "watched/**/*.conf", "--scope", scope]);
+        assert_eq!(output.status.code(), Some(2), "{}", scope);
+    }
+    let err = String::from_utf8_lossy(&state.run(&["compare", "watched", "--scope", "ssh"]).stderr)
+        .into_owned();
+    assert!(
+        err.contains("scans of a directory don't descend into its subdirectories"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn throttled_scans_are_paced_and_log_their_limits() {
+    let state = Scratch::new("throttle");
+    let content = "x".repeat(64 << 10);
+    state.write(&watched("a.conf"), &content);
+    state.write(&watched("b.conf"), &content);
+    state.write(&watched("c.conf"), &content);
+
+    // At 256 KiB/s, each 64 KiB read waits a quarter of a second after the
+    // one before it, however many run at once.
+    let started = std::time::Instant::now();
+    let output = state.run(&["snapshot", "watched", "--io-throttle", "0.25"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(450));
+    let log = std::fs::read_to_string(state.path("drift.log")).unwrap();
+    assert!(log.contains("Scans throttled: "), "{}", log);
+    assert!(log.contains("reads of at most 256.0K/s"), "{}", log);
+
+    let output = state.run(&["compare", "watched", "--nice"]);
+    assert!(output.status.success(), "{:?}", output);
+    let log = std::fs::read_to_string(state.path("drift.log")).unwrap();
+    assert!(
+        log.contains("Scans throttled: 1 file(s) read at once, reads of at most 20.0M/s, 50% of a CPU, lowered priority"),
+        "{}",
+        log
+    );
+
+    let output = state.run(&["compare", "watched", "--io-throttle", "0"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn dry_run_lists_what_would_be_hashed_without_saving() {
+    let state = Scratch::new("dry-run");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/app.conf.bak", "listen 81");
+    state.write("guardian.toml", "exclude = [\"*.bak\"]\n");
+
+    let output = state.run(&["--verbose", "snapshot", "watched", "--dry-run"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let out = stdout(&output);
+    assert!(out.contains(&format!("  {} (9 B)", watched("app.conf"))));
+    assert!(!out.contains("app.conf.bak ("));
+    assert!(out.contains("Would hash 1 files (9 B); would skip 1 excluded file"));
+    assert!(!state.path("snapshot.json").exists());
+
+    state.write("guardian.toml", "exclude = [\"*.conf*\"]\n");
+    let output = state.run(&["snapshot", "watched", "--dry-run"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn explain_names_the_rule_that_drops_a_file() {
+    let state = Scratch::new("explain");
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/app.conf.bak", "listen 81");
+    state.write("guardian.toml", "exclude = [\"*.tmp\", \"*.bak\"]\n");
+
+    let output = state.run(&["snapshot", "watched", "--explain", &watched("app.conf.bak")]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = stdout(&output);
+    assert!(out.contains("excluded by glob *.bak"), "{}", out);
+    assert!(out.contains("no snapshot saved yet"));
+
+    let output = state.run(&["snapshot", "watched", "--explain", &watched("app.conf")]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("  hashed in "));
+    assert!(!state.path("snapshot.json").exists());
+
+    let output = state.run(&["--verbose", "snapshot", "watched"]);
+    let trace = String::from_utf8_lossy(&output.stderr);
+    assert!(trace.contains(&format!(
+        "  {}: excluded by glob *.bak",
+        watched("app.conf.bak")
+    )));
+    assert!(trace.contains(&format!("  {}: hashed in ", watched("app.conf"))));
+}
+
+#[test]
+fn scans_stop_at_the_file_limit() {
+    let state = Scratch::new("max-files");
+    for name in ["a.conf", "b.conf", "c.conf"] {
+        state.write(&format!("watched/{}", name), name);
+    }
+    let output = state.run(&["snapshot", "watched", "--max-files", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        error.contains("Stopped scanning watched after finding 2 files"),
+        "{}",
+        error
+    );
+    assert!(error.contains("--max-files"));
+    assert!(!state.path("snapshot.json").exists());
+
+    assert!(
+        state
+            .run(&["snapshot", "watched", "--max-files", "0"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn a_glob_pattern_is_expanded_in_place_of_a_directory() {
+    let state = Scratch::new("pattern");
+    std::fs::create_dir_all(state.path("watched/conf.d")).unwrap();
+    state.write("watched/app.conf", "listen 80");
+    state.write("watched/conf.d/tls.conf", "tls on");
+    state.write("watched/notes.txt", "not tracked");
+    assert!(
+        state
+            .run(&["snapshot", "watched/**/*.conf"])
+            .status
+            .success()
+    );
+
+    state.write("watched/conf.d/tls.conf", "tls off");
+    state.write("watched/notes.txt", "still not tracked");
+    // Without a DIRECTORY, compare expands the pattern the snapshot recorded.
+    let output = stdout(&state.run(&["compare"]));
+    assert!(
+        output.contains("Changed: watched/conf.d/tls.conf"),
+        "{}",
+        output
+    );
+    assert!(!output.contains("notes.txt"), "{}", output);
+    assert!(!output.contains("app.conf"), "{}", output);
+
+    let output = state.run(&["snapshot", "watched/**/*.yaml"]);
+    assert_eq!(output.status.code(), Some(5));
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        error.contains("No files match watched/**/*.yaml"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn fail_on_picks_the_kinds_of_drift_that_fail_compare() {
+    let state = Scratch::new("fail-on");
+    state.write("watched/app.conf", "listen 80");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/extra.conf", "new");
+
+    let compare = |args: &[&str]| {
+        let output = state.run(&[&["compare", "watched"], args].concat());
+        // The drift is printed whatever decides the exit code.
+        assert!(stdout(&output).contains("New: watched/extra.conf"));
+        output.status.code()
+    };
+    assert_eq!(compare(&[]), Some(1), "all kinds fail by default");
+    assert_eq!(compare(&["--fail-on", "changed,deleted"]), Some(0));
+    assert_eq!(compare(&["--fail-on", "new"]), Some(1));
+    assert_eq!(compare(&["--fail-on"]), Some(1));
+    assert_eq!(compare(&["--fail-on", "none"]), Some(0));
+
+    let output = state.run(&["compare", "watched", "--fail-on", "renamed"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn alerting_with_no_channel_warns_that_alerts_only_go_to_the_log() {
+    let state = Scratch::new("alert-log-only");
+    state.write("watched/app.conf", "a");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    let output = state.run(&["compare", "watched", "--alert"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(
+        stderr.contains("no alert channel is configured"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("--webhook-url"), "{}", stderr);
+
+    let output = state.run(&["compare", "watched"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("no alert channel"));
+}
+
+#[test]
+fn the_drift_hook_is_an_alert_channel_whose_failure_is_isolated() {
+    let state = Scratch::new("hook-channel");
+    state.write("watched/app.conf", "a");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "b");
+
+    let output = state.run(&["compare", "watched", "--alert", "--on-drift", "false"]);
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(!stderr.contains("no alert channel"), "{}", stderr);
+    assert!(
+        stderr.contains("Warning: on-drift alert failed: exited with"),
+        "{}",
+        stderr
+    );
+    // The drift is still reported, and the comparison finishes, failing
+    // for the drift rather than the hook.
+    assert!(stdout(&output).contains("Changed: "), "{}", stdout(&output));
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+
+    let output = state.run(&[
+        "compare",
+        "watched",
+        "--on-drift",
+        "sleep 5",
+        "--on-drift-timeout",
+        "300ms",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(
+        stderr.contains("killed after exceeding 300ms timeout"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn recover_reports_what_a_crash_left_and_fixes_it_with_apply() {
+    let state = Scratch::new("recover");
+    state.write("watched/app.conf", "a");
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+    state.write("watched/app.conf", "b");
+    assert_eq!(state.run(&["compare", "watched"]).status.code(), Some(1));
+    assert!(state.run(&["snapshot", "watched"]).status.success());
+
+    // A full disk cut the snapshot and the history short, and a monitor
+    // died leaving its PID file.
+    let snapshot = std::fs::read(state.path("snapshot.json")).unwrap();
+    std::fs::write(state.path("snapshot.json"), &snapshot[..snapshot.len() / 2]).unwrap();
+    let mut history = std::fs::read_to_string(state.path("drift-history.jsonl")).unwrap();
+    let whole = history.len();
+    history.push_str("{\"timestamp\":\"2026-");
+    state.write("drift-history.jsonl", &history);
+    state.write("snapshot.json.partial", "{");
+    std::fs::File::options()
+        .write(true)
+        .open(state.path("snapshot.json.partial"))
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(3600))
+        .unwrap();
+    let mut child = Command::new("true").spawn().unwrap();
+    let dead = child.id();
+    child.wait().unwrap();
+    state.write("monitor.pid", &format!("{}\n", dead));
+
+    let output = state.run(&["recover", "--pid-file", "monitor.pid"]);
+    let out = stdout(&output);
+    assert_eq!(output.status.code(), Some(1), "{}", out);
+    assert_eq!(out.matches("Found: ").count(), 4, "{}", out);
+    assert!(out.contains("loads"), "{}", out);
+    assert!(out.contains(&format!("names PID {}", dead)), "{}", out);
+    assert!(out.contains("partial record on line"), "{}", out);
+    assert!(state.path("monitor.pid").exists());
+    assert!(state.path("snapshot.json.partial").exists());
+
+    let output = state.run(&["recover", "--pid-file", "monitor.pid", "--apply"]);
+    let out = stdout(&output);
+    assert!(output.status.success(), "{}", out);
+    assert_eq!(out.matches("Fixed: ").count(), 4, "{}", out);
+    assert!(state.path("snapshot.json.corrupt").exists());
+    assert!(!state.path("monitor.pid").exists());
+    assert!(!state.path("snapshot.json.partial").exists());
+    let history = std::fs::read_to_string(state.path("drift-history.jsonl")).unwrap();
+    assert_eq!(history.len(), whole);
+
+    // The restored snapshot loads, and the file drifted from it.
+    let output = state.run(&["compare", "watched"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    let output = state.run(&["recover", "--pid-file", "monitor.pid"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("No problems found"));
+}
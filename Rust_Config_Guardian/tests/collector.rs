@@ -0,0 +1,184 @@
+//! Uploads a snapshot and a drift report to a real `serve` process, and
+//! publishes a baseline through it to a subscribed monitor.
+
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+const BIN: &str = env!("CARGO_BIN_EXE_Rust_Config_Guardian");
+
+/// Kills the collector even if an assertion fails.
+struct Collector(Child);
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn run(state: &Path, token: &str, args: &[&str]) -> Output {
+    Command::new(BIN)
+        .args(args)
+        .current_dir(state)
+        .env_remove("GUARDIAN_SNAPSHOT_FILE")
+        .env("GUARDIAN_COLLECTOR_TOKEN", token)
+        .output()
+        .unwrap()
+}
+
+/// Start a collector storing under `state/data`, and wait for it to
+/// listen; gives its URL.
+fn serve(state: &Path) -> (Collector, String) {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let collector = Collector(
+        Command::new(BIN)
+            .args(["serve", "--listen", &addr.to_string(), "--data-dir", "data"])
+            .current_dir(state)
+            .env("GUARDIAN_COLLECTOR_TOKEN", "s3cret")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(addr).is_err() {
+        assert!(Instant::now() < deadline, "the collector never listened");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    (collector, format!("http://{}", addr))
+}
+
+/// A scratch state directory with `watched/app.conf` holding `content`.
+fn state(name: &str, content: &str) -> PathBuf {
+    let state = std::env::temp_dir().join(format!("guardian-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&state);
+    std::fs::create_dir_all(state.join("watched")).unwrap();
+    std::fs::write(state.join("watched/app.conf"), content).unwrap();
+    state
+}
+
+#[test]
+fn hosts_upload_snapshots_and_reports_with_the_token() {
+    let state = state("collector", "a");
+    let (collector, url) = serve(&state);
+    let upload = ["--upload", &url, "--host-name", "web-01"];
+
+    let output = run(
+        &state,
+        "wrong",
+        &[&["snapshot", "watched"][..], &upload].concat(),
+    );
+    assert_eq!(output.status.code(), Some(7));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("HTTP 401"));
+
+    let output = run(
+        &state,
+        "s3cret",
+        &[&["snapshot", "watched"][..], &upload].concat(),
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let stored = std::fs::read_to_string(state.join("data/web-01/snapshot.json")).unwrap();
+    assert!(stored.contains("app.conf"));
+
+    std::fs::write(state.join("watched/app.conf"), "b").unwrap();
+    let report = ["--report-upload", &url, "--host-name", "web-01"];
+    let output = run(
+        &state,
+        "s3cret",
+        &[&["compare", "watched"][..], &report].concat(),
+    );
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let reports: Vec<_> = std::fs::read_dir(state.join("data/web-01/drift"))
+        .unwrap()
+        .collect();
+    assert_eq!(reports.len(), 1);
+
+    drop(collector);
+    let _ = std::fs::remove_dir_all(&state);
+}
+
+#[test]
+fn a_published_baseline_is_taken_in_by_subscribed_monitors() {
+    let blessed = state("blessed", "a");
+    let fleet = state("fleet", "a");
+    let (collector, url) = serve(&blessed);
+    for state in [&blessed, &fleet] {
+        assert!(
+            run(state, "s3cret", &["snapshot", "watched"])
+                .status
+                .success()
+        );
+    }
+    // The change rolled out everywhere, and accepted on the blessed host.
+    for state in [&blessed, &fleet] {
+        std::fs::write(state.join("watched/app.conf"), "b").unwrap();
+    }
+    let publish = [
+        "accept",
+        "watched/app.conf",
+        "--profile",
+        "nginx",
+        "--publish",
+        &url,
+        "--baseline-key",
+        "k3y",
+    ];
+    let output = run(&blessed, "s3cret", &publish);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("as version 1 of the nginx baseline"));
+
+    let monitor = |key: &str| {
+        run(
+            &fleet,
+            "s3cret",
+            &[
+                "monitor",
+                "watched",
+                "--profile",
+                "nginx",
+                "--poll",
+                "1s",
+                "--max-events",
+                "1",
+                "--baseline-subscribe",
+                &url,
+                "--baseline-key",
+                key,
+            ],
+        )
+    };
+    // Another key didn't sign it: refused, and the baseline left as it is.
+    let output = monitor("other");
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("signature of version 1"), "{}", stderr);
+
+    let output = monitor("k3y");
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Baseline updated to version 1 of the nginx baseline"),
+        "{}",
+        stdout
+    );
+    let snapshot = std::fs::read_to_string(fleet.join("snapshot.json")).unwrap();
+    assert!(snapshot.contains("\"published\": 1"), "{}", snapshot);
+
+    // The next change accepted is the next version.
+    std::fs::write(blessed.join("watched/app.conf"), "c").unwrap();
+    assert!(run(&blessed, "s3cret", &publish).status.success());
+    let stored = blessed.join("data/.baselines/nginx.json");
+    assert!(
+        std::fs::read_to_string(&stored)
+            .unwrap()
+            .contains("\"version\":2")
+    );
+
+    drop(collector);
+    let _ = std::fs::remove_dir_all(&blessed);
+    let _ = std::fs::remove_dir_all(&fleet);
+}
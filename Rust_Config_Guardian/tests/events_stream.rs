@@ -0,0 +1,110 @@
+//! Drives a real monitor process and checks that `/events` streams drift.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const BIN: &str = env!("CARGO_BIN_EXE_Rust_Config_Guardian");
+
+/// Kills the monitor even if an assertion fails.
+struct Monitor(Child);
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn connect(port: u16) -> TcpStream {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => return stream,
+            Err(e) if Instant::now() > deadline => panic!("monitor never listened: {}", e),
+            Err(_) => std::thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+fn run(state: &Path, args: &[&str]) {
+    let status = Command::new(BIN)
+        .args(args)
+        .current_dir(state)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "{:?} failed", args);
+}
+
+#[test]
+fn drift_arrives_as_an_event() {
+    let state = std::env::temp_dir().join(format!("guardian-events-{}", std::process::id()));
+    let watched = state.join("watched");
+    std::fs::create_dir_all(&watched).unwrap();
+    std::fs::write(watched.join("app.conf"), "a").unwrap();
+    run(&state, &["snapshot", "watched"]);
+
+    let port = free_port();
+    let addr = format!("127.0.0.1:{}", port);
+    let _monitor = Monitor(
+        Command::new(BIN)
+            .args(["monitor", "watched", "--poll", "1s", "--api-listen", &addr])
+            .current_dir(&state)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let mut stream = connect(port);
+    stream
+        .set_read_timeout(Some(Duration::from_secs(15)))
+        .unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert!(
+        line.starts_with("HTTP/1.1 200"),
+        "unexpected status: {}",
+        line
+    );
+
+    std::fs::write(watched.join("extra.conf"), "b").unwrap();
+
+    let mut event = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        if let Some(data) = line.strip_prefix("data: ") {
+            let value: serde_json::Value = serde_json::from_str(data.trim()).unwrap();
+            if value["path"]
+                .as_str()
+                .is_some_and(|p| p.ends_with("extra.conf"))
+            {
+                event = Some(value);
+                break;
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&state);
+
+    let event = event.expect("no drift event received");
+    assert_eq!(event["kind"], "new");
+    assert_eq!(event["directory"], "watched");
+    assert!(event["timestamp"].is_string());
+}
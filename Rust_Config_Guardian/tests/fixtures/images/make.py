@@ -0,0 +1,112 @@
+#!/usr/bin/env python3
+"""Writes the image fixtures the tests read: run it from this directory."""
+
+import gzip
+import hashlib
+import io
+import json
+import os
+import shutil
+import tarfile
+
+MTIME = 1_700_000_000
+LONG = "etc/app/" + "long-" * 24 + "name.conf"
+
+
+def add(tar, name, content=None, kind=tarfile.REGTYPE, link="", mtime=MTIME):
+    info = tarfile.TarInfo(name)
+    info.type = kind
+    info.linkname = link
+    info.mtime = mtime
+    info.mode = 0o755 if kind == tarfile.DIRTYPE else 0o644
+    info.uname = info.gname = "root"
+    data = None
+    if content is not None:
+        data = content.encode()
+        info.size = len(data)
+    tar.addfile(info, io.BytesIO(data) if data is not None else None)
+
+
+def archive(entries, format):
+    buffer = io.BytesIO()
+    with tarfile.open(fileobj=buffer, mode="w", format=format) as tar:
+        for entry in entries:
+            add(tar, *entry[:2], **entry[2] if len(entry) > 2 else {})
+    return buffer.getvalue()
+
+
+def gzipped(data):
+    buffer = io.BytesIO()
+    with gzip.GzipFile(fileobj=buffer, mode="wb", mtime=0) as out:
+        out.write(data)
+    return buffer.getvalue()
+
+
+# A GNU tar layer: a long name, hard links, and symlinks that resolve
+# inside the prefix, outside it, and nowhere.
+with open("layer-gnu.tar", "wb") as out:
+    out.write(archive([
+        ("etc/app/", None, {"kind": tarfile.DIRTYPE}),
+        ("usr/share/app/defaults.conf", "defaults\n"),
+        ("etc/app/app.conf", "listen 80\n"),
+        (LONG, "long\n"),
+        ("etc/app/hard.conf", None, {"kind": tarfile.LNKTYPE, "link": "etc/app/app.conf"}),
+        ("etc/app/linked.conf", None, {"kind": tarfile.LNKTYPE, "link": "usr/share/app/defaults.conf"}),
+        ("etc/app/current.conf", None, {"kind": tarfile.SYMTYPE, "link": "app.conf"}),
+        ("etc/app/shared.conf", None, {"kind": tarfile.SYMTYPE, "link": "/usr/share/app/defaults.conf"}),
+        ("etc/app/dangling.conf", None, {"kind": tarfile.SYMTYPE, "link": "missing.conf"}),
+        ("etc/app/conf.d/extra.conf", "extra\n"),
+    ], tarfile.GNU_FORMAT))
+
+# A gzipped PAX layer: names only PAX records can hold, and a time with a
+# fraction of a second.
+with open("layer-pax.tar.gz", "wb") as out:
+    out.write(gzipped(archive([
+        ("./etc/app/café.conf", "accent\n", {"mtime": MTIME + 0.5}),
+        (LONG, "long\n"),
+    ], tarfile.PAX_FORMAT)))
+
+# An OCI layout of three layers: the second changes a.conf and whites out
+# b.conf, the third makes etc/opaque opaque but for the new.conf it adds.
+# The first also has a directory whose name only the ustar prefix holds.
+shutil.rmtree("oci", ignore_errors=True)
+os.makedirs("oci/blobs/sha256")
+
+
+def blob(data):
+    digest = hashlib.sha256(data).hexdigest()
+    with open(f"oci/blobs/sha256/{digest}", "wb") as out:
+        out.write(data)
+    return {"digest": f"sha256:{digest}", "size": len(data)}
+
+
+DEEP = "etc/" + "deep-" * 24 + "dir"
+layers = [
+    archive([
+        ("etc/app/a.conf", "a1\n"),
+        ("etc/app/b.conf", "b\n"),
+        ("etc/app/c.conf", "c\n"),
+        ("etc/opaque/old.conf", "old\n"),
+        (DEEP + "/x.conf", "x\n"),
+    ], tarfile.USTAR_FORMAT),
+    archive([("etc/app/a.conf", "a2\n"), ("etc/app/.wh.b.conf", "")], tarfile.USTAR_FORMAT),
+    archive([("etc/opaque/.wh..wh..opq", ""), ("etc/opaque/new.conf", "new\n")], tarfile.USTAR_FORMAT),
+]
+config = blob(json.dumps({"architecture": "amd64", "os": "linux"}).encode())
+manifest = json.dumps({
+    "schemaVersion": 2,
+    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+    "config": {"mediaType": "application/vnd.oci.image.config.v1+json", **config},
+    "layers": [
+        {"mediaType": "application/vnd.oci.image.layer.v1.tar+gzip", **blob(gzipped(layer))}
+        for layer in layers
+    ],
+}).encode()
+index = {
+    "schemaVersion": 2,
+    "manifests": [{"mediaType": "application/vnd.oci.image.manifest.v1+json", **blob(manifest)}],
+}
+with open("oci/index.json", "w") as out:
+    json.dump(index, out)
+with open("oci/oci-layout", "w") as out:
+    json.dump({"imageLayoutVersion": "1.0.0"}, out)
@@ -0,0 +1,306 @@
+//! Runs the core snapshot, compare and monitor flows end to end against
+//! fixture trees, comparing what they print, and the JSON report a hook
+//! gets, with the golden files in `tests/fixtures/golden`. Times, durations,
+//! the host and its identity, the user and the scratch paths are masked
+//! first, so the golden files only change when the output does. Run with
+//! `GUARDIAN_BLESS=1` to write the golden files from the output instead,
+//! and review the diff.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use serde_json::Value;
+
+const BIN: &str = env!("CARGO_BIN_EXE_Rust_Config_Guardian");
+
+/// Stands in for each run's scratch directory.
+const STATE: &str = "$STATE";
+/// What masked text and JSON values read as.
+const MASKED: &str = "<masked>";
+
+/// Fields of the JSON report that differ from one run, or host, to the
+/// next.
+const VOLATILE_FIELDS: [&str; 8] = [
+    "started",
+    "taken",
+    "host",
+    "instance_id",
+    "user",
+    "baseline_age_days",
+    "elapsed_ms",
+    "finished",
+];
+
+/// A scratch state directory, removed on drop, to build a fixture tree in
+/// and run the binary from, away from the environment and config files of
+/// whoever runs the tests.
+struct Fixture(PathBuf);
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("guardian-golden-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Fixture(dir)
+    }
+
+    /// Write `files`, relative to the state directory, creating the
+    /// directories they are in.
+    fn tree(&self, files: &[(&str, &str)]) -> &Self {
+        for (name, content) in files {
+            let path = self.path(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        self
+    }
+
+    fn remove(&self, name: &str) {
+        std::fs::remove_file(self.path(name)).unwrap();
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        let mut command = Command::new(BIN);
+        command.args(args).current_dir(&self.0);
+        for (name, _) in std::env::vars_os() {
+            let name = name.to_string_lossy();
+            if name.starts_with("GUARDIAN_") || name.starts_with("GITHUB_") {
+                command.env_remove(&*name);
+            }
+        }
+        command
+            .env("HOME", &self.0)
+            .env("XDG_CONFIG_HOME", &self.0)
+            .env("APPDATA", &self.0)
+            .env("NO_COLOR", "1");
+        command.output().unwrap()
+    }
+
+    /// `text` with the scratch directory and the binary's path in it
+    /// replaced by placeholders, its times and durations masked, and its
+    /// paths separated by `/` on Windows too.
+    fn mask(&self, text: &str) -> String {
+        let text = text
+            .replace(BIN, "$BIN")
+            .replace(&*self.0.to_string_lossy(), STATE);
+        mask_text(&unixy(&text))
+    }
+
+    /// The JSON in `text`, pretty-printed, with its volatile fields masked
+    /// and the rest as [`Fixture::mask`] masks text.
+    fn mask_json(&self, text: &str) -> String {
+        let mut value: Value = serde_json::from_str(text).unwrap();
+        mask_value(&mut value);
+        let text = serde_json::to_string_pretty(&value).unwrap();
+        mask_text(&text) + "\n"
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn mask_value(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                match VOLATILE_FIELDS.contains(&name.as_str()) && !field.is_null() {
+                    true => *field = Value::String(MASKED.to_string()),
+                    false => mask_value(field),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(mask_value),
+        Value::String(text) => *text = unixy(&text.replace(BIN, "$BIN")),
+        _ => {}
+    }
+}
+
+fn unixy(text: &str) -> String {
+    match cfg!(windows) {
+        true => text.replace('\\', "/"),
+        false => text.to_string(),
+    }
+}
+
+/// Mask the times (`2026-01-02 03:04:05 +0000`, RFC 3339) and durations
+/// (`0ms`, `1.5s`) in `text`.
+fn mask_text(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut masked = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let starts_word = i == 0 || !chars[i - 1].is_alphanumeric();
+        let taken = match starts_word {
+            true => time_at(&chars[i..]).or_else(|| duration_at(&chars[i..])),
+            false => None,
+        };
+        match taken {
+            Some(len) => {
+                masked.push_str(MASKED);
+                i += len;
+            }
+            None => {
+                masked.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    masked
+}
+
+fn digits(chars: &[char]) -> usize {
+    chars.iter().take_while(|c| c.is_ascii_digit()).count()
+}
+
+/// The length of the time `chars` start with: a date, a time of day after
+/// a space or `T`, its fraction of a second and its offset.
+fn time_at(chars: &[char]) -> Option<usize> {
+    let pattern = "dddd-dd-dd_dd:dd:dd";
+    if chars.len() < pattern.len() {
+        return None;
+    }
+    let matches = pattern.chars().zip(chars).all(|(p, c)| match p {
+        'd' => c.is_ascii_digit(),
+        '_' => *c == ' ' || *c == 'T',
+        p => p == *c,
+    });
+    if !matches {
+        return None;
+    }
+    let mut len = pattern.len();
+    if chars.get(len) == Some(&'.') {
+        len += 1 + digits(&chars[len + 1..]);
+    }
+    match chars.get(len) {
+        Some('Z') => len += 1,
+        Some(' ') if matches!(chars.get(len + 1), Some('+' | '-')) => {
+            len += 2 + digits(&chars[len + 2..]);
+        }
+        _ => {}
+    }
+    Some(len)
+}
+
+/// The length of the duration `chars` start with, a number and a unit
+/// ending the word.
+fn duration_at(chars: &[char]) -> Option<usize> {
+    let mut len = digits(chars);
+    if len == 0 {
+        return None;
+    }
+    if chars.get(len) == Some(&'.') && digits(&chars[len + 1..]) > 0 {
+        len += 1 + digits(&chars[len + 1..]);
+    }
+    let unit = ["ms", "µs", "ns", "s", "m", "h"]
+        .into_iter()
+        .find(|unit| unit.chars().zip(&chars[len..]).all(|(u, c)| u == *c))?;
+    len += unit.chars().count();
+    match chars.get(len) {
+        Some(c) if c.is_alphanumeric() => None,
+        _ => Some(len),
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/golden")
+        .join(name)
+}
+
+/// Compare `actual` with the golden file `name`, or write it there with
+/// `GUARDIAN_BLESS` set.
+fn assert_golden(name: &str, actual: &str) {
+    let path = golden_path(name);
+    if std::env::var_os("GUARDIAN_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (GUARDIAN_BLESS=1 writes it)", path.display(), e));
+    assert!(
+        expected.replace("\r\n", "\n") == actual,
+        "{} differs from the output (GUARDIAN_BLESS=1 rewrites it):\n--- expected\n{}\n--- actual\n{}",
+        name,
+        expected,
+        actual
+    );
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// A tree of three files and its snapshot, with one of the files then
+/// changed, one deleted and one added.
+fn drifted(name: &str) -> Fixture {
+    let fixture = Fixture::new(name);
+    fixture.tree(&[
+        ("watched/app.conf", "listen 80\n"),
+        ("watched/db.conf", "host=db1\n"),
+        ("watched/keep.conf", "kept\n"),
+    ]);
+    let output = fixture.run(&["snapshot", "watched"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_golden("snapshot.txt", &fixture.mask(&stdout(&output)));
+    fixture.tree(&[
+        ("watched/app.conf", "listen 8080\n"),
+        ("watched/new.conf", "new\n"),
+    ]);
+    fixture.remove("watched/db.conf");
+    fixture
+}
+
+#[test]
+fn mask_takes_out_times_and_durations_only() {
+    assert_eq!(
+        mask_text("Hashed 3 files (25 B) in 0ms at 2026-01-02 03:04:05 +0000; 1.5s"),
+        "Hashed 3 files (25 B) in <masked> at <masked>; <masked>"
+    );
+    assert_eq!(mask_text("2026-01-02T03:04:05.123Z"), "<masked>");
+    assert_eq!(mask_text("score 56, 3s2 v10s"), "score 56, 3s2 v10s");
+}
+
+#[test]
+fn compare_reports_drift_as_in_the_golden_file() {
+    let fixture = drifted("compare");
+    let output = fixture.run(&["compare", "watched"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert_golden("compare.txt", &fixture.mask(&stdout(&output)));
+
+    let output = fixture.run(&["compare", "watched", "--fail-on", "deleted"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = fixture.run(&["compare", "watched", "--fail-on", "none"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn the_hook_gets_the_report_in_the_golden_file() {
+    let fixture = drifted("hook");
+    let hook = match cfg!(windows) {
+        true => "more > report.json",
+        false => "cat > report.json",
+    };
+    let output = fixture.run(&["compare", "watched", "--on-drift", hook, "--on-drift-shell"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let report = std::fs::read_to_string(fixture.path("report.json")).unwrap();
+    let report = fixture.mask_json(&report).replace(hook, "$HOOK");
+    assert_golden("drift-report.json", &report);
+}
+
+#[test]
+fn monitor_reports_drift_until_its_last_event() {
+    let fixture = drifted("monitor");
+    let output = fixture.run(&["monitor", "watched", "--poll", "1s", "--max-events", "2"]);
+    // Drift during the session, so it exits 1 as with --exit-drift.
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert_golden("monitor.txt", &fixture.mask(&stdout(&output)));
+}
@@ -0,0 +1,133 @@
+//! Asks a real monitor process for its status over the control socket.
+#![cfg(unix)]
+
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+const BIN: &str = env!("CARGO_BIN_EXE_Rust_Config_Guardian");
+
+/// Kills the monitor even if an assertion fails.
+struct Monitor(Child);
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn run(state: &Path, args: &[&str]) -> Output {
+    Command::new(BIN)
+        .args(args)
+        .current_dir(state)
+        .env_remove("GUARDIAN_SNAPSHOT_FILE")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn status_reports_what_a_running_monitor_sees() {
+    let state = std::env::temp_dir().join(format!("guardian-status-{}", std::process::id()));
+    let watched = state.join("watched");
+    std::fs::create_dir_all(&watched).unwrap();
+    std::fs::write(watched.join("app.conf"), "a").unwrap();
+    assert!(run(&state, &["snapshot", "watched"]).status.success());
+
+    let output = run(&state, &["status", "--control-socket", "monitor.sock"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No monitor running"));
+
+    let monitor = Monitor(
+        Command::new(BIN)
+            .args(["monitor", "watched", "--poll", "1s"])
+            .args(["--control-socket", "monitor.sock"])
+            .current_dir(&state)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    std::fs::write(watched.join("app.conf"), "changed").unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(15);
+    let stdout = loop {
+        let output = run(&state, &["status", "--control-socket", "monitor.sock"]);
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        if output.status.success() && stdout.contains("1 drifted files") {
+            break stdout;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "monitor never reported drift: {}",
+            stdout
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    };
+    drop(monitor);
+    let _ = std::fs::remove_dir_all(&state);
+
+    assert!(stdout.contains("  Watching: watched"));
+    assert!(stdout.contains("(1 files, taken"));
+    assert!(stdout.contains("  Pending drift: 1"));
+    assert!(
+        stdout.contains("  Last scan: 1 of 1 files, 7 B hashed in "),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn trigger_has_the_monitor_compare_at_once() {
+    let state = std::env::temp_dir().join(format!("guardian-trigger-{}", std::process::id()));
+    let watched = state.join("watched");
+    std::fs::create_dir_all(&watched).unwrap();
+    std::fs::write(watched.join("app.conf"), "a").unwrap();
+    assert!(run(&state, &["snapshot", "watched"]).status.success());
+
+    let output = run(&state, &["trigger", "--control-socket", "monitor.sock"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    // Left to its schedule, it wouldn't compare for an hour.
+    let monitor = Monitor(
+        Command::new(BIN)
+            .args([
+                "monitor",
+                "watched",
+                "--schedule-only",
+                "--compare-every",
+                "1h",
+            ])
+            .args(["--control-socket", "monitor.sock"])
+            .current_dir(&state)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    std::fs::write(watched.join("app.conf"), "changed").unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(15);
+    let output = loop {
+        let output = run(&state, &["trigger", "--control-socket", "monitor.sock"]);
+        if output.status.code() != Some(3) {
+            break output;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "the monitor never served its socket"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    };
+    let status = run(&state, &["status", "--control-socket", "monitor.sock"]);
+    drop(monitor);
+    let _ = std::fs::remove_dir_all(&state);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("Compared watched: 1 drifted files."),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Changed: "), "{}", stdout);
+    assert!(String::from_utf8_lossy(&status.stdout).contains("1 drifted files"));
+}